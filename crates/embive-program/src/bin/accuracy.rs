@@ -0,0 +1,91 @@
+//! Guest side of the lp-glsl-vm accuracy tests
+//!
+//! The host sets the input of the VM to an IR module lowered to a numeric
+//! format, as text, followed by the arguments of each sample. The program
+//! calls `@f` of the module on each sample with the IR interpreter, so the
+//! runtime routines and the arithmetic run compiled for the guest, and
+//! writes back one result word per sample.
+//!
+//! Input words:
+//! - length of the text in bytes, then the text packed little-endian into
+//!   words, the last one padded with zeros
+//! - number of arguments of `@f`
+//! - number of samples, then the arguments of each sample
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+#[macro_use]
+extern crate embive_runtime;
+
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+
+use embive_runtime::{ebreak, syscall, SYSCALL_ARGS};
+use lp_glsl_vm::ir::{
+    interpret::{Bits, Exit, Interpreter},
+    parse,
+};
+
+/// Stack of the interpreter, which a single operation barely uses
+const STACK_SIZE: u32 = 4096;
+const STEP_LIMIT: u64 = 1_000_000;
+
+/// Report the panic to the host and exit the interpreter (ebreak)
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    println!("[guest] {}", info);
+    ebreak()
+}
+
+/// Interrupt handler
+#[no_mangle]
+fn interrupt_handler(_value: i32) {}
+
+/// Word `index` of the input (syscall 3)
+fn input(index: usize) -> u32 {
+    let mut args = [0i32; SYSCALL_ARGS];
+    args[0] = index as i32;
+    syscall(3, &args).expect("reading the input failed") as u32
+}
+
+/// Append a word to the output (syscall 4)
+fn output(word: u32) {
+    let mut args = [0i32; SYSCALL_ARGS];
+    args[0] = word as i32;
+    syscall(4, &args).expect("writing the output failed");
+}
+
+#[no_mangle]
+pub extern "Rust" fn main() {
+    let mut next = 0;
+    let mut read = || {
+        next += 1;
+        input(next - 1)
+    };
+
+    let len = read() as usize;
+    let bytes: Vec<u8> = (0..len.div_ceil(4))
+        .flat_map(|_| read().to_le_bytes())
+        .collect();
+    let text = core::str::from_utf8(&bytes[..len]).expect("IR text is not UTF-8");
+    let module = match parse(text) {
+        Ok(module) => module,
+        Err(e) => panic!("IR text does not parse: {}", e),
+    };
+    let func = module.find_function("f").expect("no function @f");
+
+    let arity = read() as usize;
+    let samples = read();
+    let mut interpreter = Interpreter::with_limits(&module, STACK_SIZE, STEP_LIMIT);
+    for _ in 0..samples {
+        let args: Vec<Bits> = (0..arity).map(|_| [read(), 0, 0, 0]).collect();
+        match interpreter.call(func, &args) {
+            Ok(Exit::Return(results)) => output(results[0][0]),
+            result => panic!("@f of {:?}: {:?}", args, result),
+        }
+    }
+
+    ebreak()
+}
@@ -0,0 +1,221 @@
+//! Call graph of the user functions of a module
+//!
+//! GLSL forbids recursion, so the graph of a valid shader is acyclic and the
+//! deepest chain of calls from the entry point bounds its stack usage.
+//! Builtin calls are not part of the graph: they are leaf routines whose
+//! stack use is fixed by the runtime.
+
+use alloc::{collections::BTreeSet, vec, vec::Vec};
+
+use crate::{
+    layout,
+    sema::hir::{walk_expr, Expr, ExprKind, FunctionId, Module, Visitor},
+};
+
+/// Calls between the functions of a module
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallGraph {
+    /// Functions called by each function, indexed by [`FunctionId`]
+    callees: Vec<BTreeSet<FunctionId>>,
+    /// Worst-case stack frame size of each function, in bytes
+    frame_sizes: Vec<u32>,
+}
+
+impl CallGraph {
+    pub fn new(module: &Module) -> CallGraph {
+        let callees = module
+            .functions
+            .iter()
+            .map(|f| {
+                let mut calls = Calls(BTreeSet::new());
+                if let Some(body) = &f.body {
+                    calls.visit_block(body);
+                }
+                calls.0
+            })
+            .collect();
+        let frame_sizes = module.functions.iter().map(layout::frame_size).collect();
+        CallGraph {
+            callees,
+            frame_sizes,
+        }
+    }
+
+    /// Functions called directly by `function`
+    pub fn callees(&self, function: FunctionId) -> &BTreeSet<FunctionId> {
+        &self.callees[function.0 as usize]
+    }
+
+    /// Worst-case size of the stack frame of `function`, in bytes, as
+    /// bounded by [`layout::frame_size`]
+    pub fn frame_size(&self, function: FunctionId) -> u32 {
+        self.frame_sizes[function.0 as usize]
+    }
+
+    /// One call cycle through each group of mutually recursive functions
+    ///
+    /// A cycle lists each function once, starting from the lowest id; the
+    /// last function calls the first.
+    pub fn cycles(&self) -> Vec<Vec<FunctionId>> {
+        let mut cycles = Vec::new();
+        for component in self.components() {
+            let start = component[0];
+            if component.len() == 1 && !self.callees(start).contains(&start) {
+                continue;
+            }
+            cycles.push(self.cycle_through(start, &component));
+        }
+        cycles.sort();
+        cycles
+    }
+
+    /// Number of frames on the deepest chain of calls starting at `root`,
+    /// itself included, or `None` if the chain can recurse
+    pub fn max_depth(&self, root: FunctionId) -> Option<u32> {
+        self.longest_path(root, &|_| 1)
+    }
+
+    /// Bytes of stack used by the deepest chain of frames starting at
+    /// `root`, or `None` if the chain can recurse
+    pub fn max_stack_size(&self, root: FunctionId) -> Option<u32> {
+        self.longest_path(root, &|f| self.frame_size(f))
+    }
+
+    /// Heaviest path from `root` with the given node weights
+    fn longest_path(&self, root: FunctionId, weight: &dyn Fn(FunctionId) -> u32) -> Option<u32> {
+        /// Visit state of each function during the search
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            New,
+            Active,
+            Done(u32),
+        }
+
+        fn visit(
+            graph: &CallGraph,
+            f: FunctionId,
+            weight: &dyn Fn(FunctionId) -> u32,
+            states: &mut [State],
+        ) -> Option<u32> {
+            match states[f.0 as usize] {
+                State::Done(total) => return Some(total),
+                State::Active => return None,
+                State::New => {}
+            }
+            states[f.0 as usize] = State::Active;
+            let mut deepest = 0;
+            for &callee in graph.callees(f) {
+                deepest = deepest.max(visit(graph, callee, weight, states)?);
+            }
+            let total = weight(f) + deepest;
+            states[f.0 as usize] = State::Done(total);
+            Some(total)
+        }
+
+        let mut states = vec![State::New; self.callees.len()];
+        visit(self, root, weight, &mut states)
+    }
+
+    /// Strongly connected components, each sorted by id
+    fn components(&self) -> Vec<Vec<FunctionId>> {
+        struct Tarjan<'a> {
+            graph: &'a CallGraph,
+            index: Vec<Option<u32>>,
+            low: Vec<u32>,
+            on_stack: Vec<bool>,
+            stack: Vec<FunctionId>,
+            next: u32,
+            components: Vec<Vec<FunctionId>>,
+        }
+
+        impl Tarjan<'_> {
+            fn visit(&mut self, f: FunctionId) {
+                let i = f.0 as usize;
+                self.index[i] = Some(self.next);
+                self.low[i] = self.next;
+                self.next += 1;
+                self.stack.push(f);
+                self.on_stack[i] = true;
+                for &callee in self.graph.callees(f) {
+                    let c = callee.0 as usize;
+                    match self.index[c] {
+                        None => {
+                            self.visit(callee);
+                            self.low[i] = self.low[i].min(self.low[c]);
+                        }
+                        Some(index) if self.on_stack[c] => self.low[i] = self.low[i].min(index),
+                        Some(_) => {}
+                    }
+                }
+                if Some(self.low[i]) == self.index[i] {
+                    let mut component = Vec::new();
+                    loop {
+                        let member = self.stack.pop().expect("component on the stack");
+                        self.on_stack[member.0 as usize] = false;
+                        component.push(member);
+                        if member == f {
+                            break;
+                        }
+                    }
+                    component.sort();
+                    self.components.push(component);
+                }
+            }
+        }
+
+        let n = self.callees.len();
+        let mut tarjan = Tarjan {
+            graph: self,
+            index: vec![None; n],
+            low: vec![0; n],
+            on_stack: vec![false; n],
+            stack: Vec::new(),
+            next: 0,
+            components: Vec::new(),
+        };
+        for i in 0..n {
+            if tarjan.index[i].is_none() {
+                tarjan.visit(FunctionId(i as u32));
+            }
+        }
+        tarjan.components
+    }
+
+    /// Shortest cycle from `start` back to itself within `component`
+    fn cycle_through(&self, start: FunctionId, component: &[FunctionId]) -> Vec<FunctionId> {
+        // Breadth-first search, remembering how each function was reached
+        let mut reached_from = vec![None; self.callees.len()];
+        let mut queue = alloc::collections::VecDeque::from([start]);
+        while let Some(f) = queue.pop_front() {
+            for &callee in self.callees(f) {
+                if callee == start {
+                    let mut cycle = vec![f];
+                    let mut at = f;
+                    while let Some(previous) = reached_from[at.0 as usize] {
+                        cycle.push(previous);
+                        at = previous;
+                    }
+                    cycle.reverse();
+                    return cycle;
+                }
+                if component.contains(&callee) && reached_from[callee.0 as usize].is_none() {
+                    reached_from[callee.0 as usize] = Some(f);
+                    queue.push_back(callee);
+                }
+            }
+        }
+        unreachable!("no cycle through a recursive component")
+    }
+}
+
+/// Collects the user functions called in the statements it visits
+struct Calls(BTreeSet<FunctionId>);
+
+impl Visitor for Calls {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let ExprKind::Call(id, _) = expr.kind {
+            self.0.insert(id);
+        }
+        walk_expr(self, expr);
+    }
+}
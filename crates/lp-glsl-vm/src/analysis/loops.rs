@@ -0,0 +1,313 @@
+//! Loop termination analysis
+//!
+//! Following GLSL ES 1.00 Appendix A, a loop is bounded when it is a `for`
+//! loop over an `int` or `float` index that is initialized to a constant,
+//! compared with a constant, stepped by a constant and not otherwise
+//! modified. The trip count of such a loop is computed exactly, for floats
+//! by stepping the index as the shader would. Every other loop is
+//! unbounded, apart from loops whose condition is constant `false`.
+//!
+//! Loops are numbered per function in the order their statements appear in
+//! the source, which is the pre-order of the HIR.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use crate::sema::{
+    const_eval::{ConstEval, Value},
+    hir::{
+        walk_expr, walk_stmt, BinaryOp, Block, Expr, ExprKind, FunctionId, Literal, LocalId,
+        Module, ParamQualifier, Stmt, UnaryOp, VarRef, Visitor,
+    },
+};
+
+/// Most iterations of a `float` loop that are simulated before giving up
+const MAX_SIMULATED_ITERATIONS: u32 = 1 << 16;
+
+/// Whether a loop is known to terminate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    /// The loop runs its body at most this many times
+    Bounded(u32),
+    Unbounded,
+}
+
+/// Termination of one loop
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoopInfo {
+    pub function: FunctionId,
+    /// Position of the loop among the loops of its function, in source order
+    pub ordinal: usize,
+    pub termination: Termination,
+}
+
+/// Classify every loop of every defined function
+pub fn classify(module: &Module) -> Vec<LoopInfo> {
+    let mut loops = Vec::new();
+    for (i, function) in module.functions.iter().enumerate() {
+        let Some(body) = &function.body else {
+            continue;
+        };
+        let mut walker = Walker {
+            module,
+            const_locals: BTreeMap::new(),
+            terminations: Vec::new(),
+            is_const: function.locals.iter().map(|l| l.is_const).collect(),
+        };
+        walker.visit_block(body);
+        loops.extend(
+            walker
+                .terminations
+                .into_iter()
+                .enumerate()
+                .map(|(ordinal, termination)| LoopInfo {
+                    function: FunctionId(i as u32),
+                    ordinal,
+                    termination,
+                }),
+        );
+    }
+    loops
+}
+
+struct Walker<'a> {
+    module: &'a Module,
+    /// Values of the `const` locals declared so far
+    const_locals: BTreeMap<LocalId, Value>,
+    is_const: Vec<bool>,
+    terminations: Vec<Termination>,
+}
+
+impl Visitor for Walker<'_> {
+    fn visit_block(&mut self, block: &Block) {
+        for (i, stmt) in block.iter().enumerate() {
+            match stmt {
+                Stmt::Decl(id, Some(init)) if self.is_const[id.0 as usize] => {
+                    if let Some(value) = self.constant(init) {
+                        self.const_locals.insert(*id, value);
+                    }
+                }
+                Stmt::Loop {
+                    cond,
+                    step,
+                    body,
+                    test_first,
+                } => {
+                    // A `for` loop index is declared just before the loop
+                    let init = match i.checked_sub(1).map(|j| &block[j]) {
+                        Some(Stmt::Decl(index, Some(init))) => Some((*index, init)),
+                        _ => None,
+                    };
+                    let termination =
+                        self.termination(init, cond.as_ref(), step.as_ref(), body, *test_first);
+                    self.terminations.push(termination);
+                }
+                _ => {}
+            }
+            walk_stmt(self, stmt);
+        }
+    }
+
+    /// Loops are statements, so expressions hold none
+    fn visit_expr(&mut self, _: &Expr) {}
+}
+
+impl Walker<'_> {
+    fn constant(&self, expr: &Expr) -> Option<Value> {
+        ConstEval::with_locals(self.module, &self.const_locals).eval(expr)
+    }
+
+    fn constant_scalar(&self, expr: &Expr) -> Option<Literal> {
+        self.constant(expr)?.as_scalar().cloned()
+    }
+
+    fn termination(
+        &self,
+        init: Option<(LocalId, &Expr)>,
+        cond: Option<&Expr>,
+        step: Option<&Expr>,
+        body: &Block,
+        test_first: bool,
+    ) -> Termination {
+        let Some(cond) = cond else {
+            return Termination::Unbounded;
+        };
+        if self.constant_scalar(cond) == Some(Literal::Bool(false)) {
+            return Termination::Bounded(if test_first { 0 } else { 1 });
+        }
+        let Some((index, init)) = init else {
+            return Termination::Unbounded;
+        };
+        let is_index =
+            |expr: &Expr| matches!(expr.kind, ExprKind::Var(VarRef::Local(id)) if id == index);
+        let ExprKind::Binary(op, lhs, rhs) = &cond.kind else {
+            return Termination::Unbounded;
+        };
+        if !op.is_comparison() || !is_index(lhs) || block_modifies(self.module, body, index) {
+            return Termination::Unbounded;
+        }
+        let (Some(start), Some(limit)) = (self.constant_scalar(init), self.constant_scalar(rhs))
+        else {
+            return Termination::Unbounded;
+        };
+        let increment = match step.map(|s| &s.kind) {
+            Some(ExprKind::Unary(UnaryOp::PreInc | UnaryOp::PostInc, operand))
+                if is_index(operand) =>
+            {
+                Increment::One(1)
+            }
+            Some(ExprKind::Unary(UnaryOp::PreDec | UnaryOp::PostDec, operand))
+                if is_index(operand) =>
+            {
+                Increment::One(-1)
+            }
+            Some(ExprKind::Assign(Some(op @ (BinaryOp::Add | BinaryOp::Sub)), lhs, rhs))
+                if is_index(lhs) =>
+            {
+                match self.constant_scalar(rhs) {
+                    Some(amount) => Increment::By(*op, amount),
+                    None => return Termination::Unbounded,
+                }
+            }
+            _ => return Termination::Unbounded,
+        };
+        match (start, limit) {
+            (Literal::Int(start), Literal::Int(limit)) => {
+                let step = match increment {
+                    Increment::One(step) => step as i64,
+                    Increment::By(BinaryOp::Add, Literal::Int(v)) => v as i64,
+                    Increment::By(_, Literal::Int(v)) => -(v as i64),
+                    Increment::By(..) => return Termination::Unbounded,
+                };
+                int_trip_count(*op, start as i64, limit as i64, step)
+            }
+            (Literal::Float(start), Literal::Float(limit)) => {
+                let step = match increment {
+                    Increment::One(step) => step as f32,
+                    Increment::By(BinaryOp::Add, Literal::Float(v)) => v,
+                    Increment::By(_, Literal::Float(v)) => -v,
+                    Increment::By(..) => return Termination::Unbounded,
+                };
+                float_trip_count(*op, start, limit, step)
+            }
+            _ => Termination::Unbounded,
+        }
+    }
+}
+
+/// How the step expression of a loop changes its index
+enum Increment {
+    /// `++` or `--`
+    One(i32),
+    /// `+=` or `-=` by a constant
+    By(BinaryOp, Literal),
+}
+
+fn compare(op: BinaryOp, ordering: core::cmp::Ordering) -> bool {
+    use core::cmp::Ordering::*;
+    match op {
+        BinaryOp::Lt => ordering == Less,
+        BinaryOp::Le => ordering != Greater,
+        BinaryOp::Gt => ordering == Greater,
+        BinaryOp::Ge => ordering != Less,
+        BinaryOp::Eq => ordering == Equal,
+        _ => ordering != Equal,
+    }
+}
+
+/// Trip count of `for (i = start; i op limit; i += step)` over integers
+///
+/// Loops that only end by overflowing the index are unbounded.
+fn int_trip_count(op: BinaryOp, start: i64, limit: i64, step: i64) -> Termination {
+    if !compare(op, start.cmp(&limit)) {
+        return Termination::Bounded(0);
+    }
+    let div_ceil = |distance: i64, step: i64| (distance + step - 1) / step;
+    let count = match op {
+        BinaryOp::Lt if step > 0 => div_ceil(limit - start, step),
+        BinaryOp::Le if step > 0 => div_ceil(limit - start + 1, step),
+        BinaryOp::Gt if step < 0 => div_ceil(start - limit, -step),
+        BinaryOp::Ge if step < 0 => div_ceil(start - limit + 1, -step),
+        BinaryOp::Eq if step != 0 => 1,
+        BinaryOp::Ne if step != 0 && (limit - start) % step == 0 && (limit - start) / step > 0 => {
+            (limit - start) / step
+        }
+        _ => return Termination::Unbounded,
+    };
+    // The index must still be in range when the condition fails
+    let last = start + count * step;
+    if last < i32::MIN as i64 || last > i32::MAX as i64 {
+        return Termination::Unbounded;
+    }
+    bounded(count)
+}
+
+fn bounded(count: i64) -> Termination {
+    Termination::Bounded(count.clamp(0, u32::MAX as i64) as u32)
+}
+
+/// Trip count of a `float` loop, found by stepping the index
+fn float_trip_count(op: BinaryOp, start: f32, limit: f32, step: f32) -> Termination {
+    let mut index = start;
+    for count in 0..MAX_SIMULATED_ITERATIONS {
+        match index.partial_cmp(&limit) {
+            Some(ordering) if compare(op, ordering) => {}
+            // NaN compares false with everything but `!=`
+            None if op == BinaryOp::Ne => {}
+            _ => return Termination::Bounded(count),
+        }
+        index += step;
+    }
+    Termination::Unbounded
+}
+
+/// Whether any statement of `block` may assign to the local `local`
+pub fn block_modifies(module: &Module, block: &[Stmt], local: LocalId) -> bool {
+    let mut modifies = Modifies {
+        module,
+        local,
+        found: false,
+    };
+    for stmt in block {
+        modifies.visit_stmt(stmt);
+    }
+    modifies.found
+}
+
+/// Whether evaluating `expr` may assign to the local `local`
+pub fn expr_modifies(module: &Module, expr: &Expr, local: LocalId) -> bool {
+    let mut modifies = Modifies {
+        module,
+        local,
+        found: false,
+    };
+    modifies.visit_expr(expr);
+    modifies.found
+}
+
+/// Looks for an assignment to `local` in the expressions it visits
+struct Modifies<'a> {
+    module: &'a Module,
+    local: LocalId,
+    found: bool,
+}
+
+impl Visitor for Modifies<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        let is_local =
+            |expr: &Expr| matches!(expr.kind, ExprKind::Var(VarRef::Local(id)) if id == self.local);
+        self.found |= match &expr.kind {
+            ExprKind::Assign(_, lhs, _) => is_local(lhs),
+            ExprKind::Unary(op, operand) => op.is_inc_dec() && is_local(operand),
+            ExprKind::Call(id, args) => {
+                let params = &self.module.function(*id).params;
+                args.iter()
+                    .zip(params)
+                    .any(|(arg, param)| param.qualifier != ParamQualifier::In && is_local(arg))
+            }
+            _ => false,
+        };
+        if !self.found {
+            walk_expr(self, expr);
+        }
+    }
+}
@@ -0,0 +1,8 @@
+//! Static analyses of analyzed HIR
+//!
+//! Analyses only inspect a [`Module`](crate::sema::hir::Module); passes that
+//! act on their results, such as capping loops, live with the lowering
+//! passes.
+
+pub mod call_graph;
+pub mod loops;
@@ -0,0 +1,245 @@
+//! Shader compilation pipeline
+//!
+//! [`compile`] preprocesses and parses GLSL source, runs semantic analysis
+//! and lowers the result, reporting every problem as a [`Diagnostic`] that
+//! points into the original source files. The uniforms of the compiled
+//! module are described by [`Compiled::reflection`].
+
+use alloc::{format, string::String, vec, vec::Vec};
+use core::fmt;
+
+use glsl::{
+    parser::{Parse, ParseError},
+    syntax::TranslationUnit,
+};
+
+use crate::{
+    analysis::call_graph::CallGraph,
+    diagnostic::{render_all, Diagnostic, SourceMap},
+    ir, lower,
+    numeric::{self, Numeric, Precisions},
+    preprocessor::{IncludeResolver, NoIncludes, Preprocessed, Preprocessor},
+    reflect::Reflection,
+    sema::{
+        self,
+        hir::{self, Module},
+        Version,
+    },
+};
+
+/// Settings for [`compile_with`]
+#[derive(Clone, Debug, Default)]
+pub struct CompileOptions {
+    /// Macros defined before the shader, as `(name, value)` pairs
+    pub defines: Vec<(String, String)>,
+    /// Version targeted by shaders without a `#version` directive
+    ///
+    /// When `None`, such shaders may use every feature the compiler
+    /// implements.
+    pub version: Option<Version>,
+    /// Iteration cap for loops that cannot be shown to terminate
+    ///
+    /// Each capped loop is reported with a warning. When `None`, loops are
+    /// left as written.
+    pub loop_cap: Option<u32>,
+    /// Format `float` is computed in by the IR from [`Compiled::ir`], for
+    /// `highp` values and those without a precision qualifier
+    pub numeric: Numeric,
+    /// Format of `mediump` values, the `highp` one when `None`
+    pub mediump: Option<Numeric>,
+    /// Format of `lowp` values, the `mediump` one when `None`
+    pub lowp: Option<Numeric>,
+}
+
+impl CompileOptions {
+    /// Format of `float` at each precision
+    pub fn precisions(&self) -> Precisions {
+        let medium = self.mediump.unwrap_or(self.numeric);
+        Precisions {
+            low: self.lowp.unwrap_or(medium),
+            medium,
+            high: self.numeric,
+        }
+    }
+}
+
+/// A compiled shader with the warnings found while compiling it
+#[derive(Clone, Debug)]
+pub struct Compiled {
+    pub module: Module,
+    /// Sources the warnings refer to
+    pub sources: SourceMap,
+    pub warnings: Vec<Diagnostic>,
+    /// Worst-case bytes of stack taken by the frames of `main` and the
+    /// functions it calls, saved and spilled registers included, `None` for
+    /// a module without `main`
+    pub stack_size: Option<u32>,
+    /// Format `float` is computed in at each precision, from the options
+    pub precisions: Precisions,
+}
+
+impl Compiled {
+    /// Translate the module to IR, lowered to the numeric formats
+    pub fn ir(&self) -> ir::Module {
+        let mut ir = ir::translate(&self.module);
+        numeric::lower(&mut ir, self.precisions);
+        ir
+    }
+
+    /// Uniforms of the module, with floats encoded in the `highp` format
+    /// the IR from [`ir`](Self::ir) keeps in memory
+    pub fn reflection(&self) -> Reflection {
+        Reflection::of(&self.module).with_numeric(self.precisions.high)
+    }
+
+    /// Render every warning with source snippets
+    pub fn render_warnings(&self) -> String {
+        render_all(&self.warnings, &self.sources)
+    }
+}
+
+/// Compilation failure with the diagnostics that caused it
+#[derive(Clone, Debug)]
+pub struct CompileError {
+    /// Sources the diagnostics refer to
+    pub sources: SourceMap,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl CompileError {
+    /// Render every diagnostic with source snippets
+    pub fn render(&self) -> String {
+        render_all(&self.diagnostics, &self.sources)
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render())
+    }
+}
+
+/// Preprocess, parse, analyze and lower a shader
+///
+/// `name` is used as the file name in diagnostics. `#include` is rejected;
+/// use [`compile_with`] to provide a resolver. The default options produce
+/// no warnings.
+pub fn compile(name: &str, source: &str) -> Result<Module, CompileError> {
+    compile_with(name, source, &CompileOptions::default(), &NoIncludes).map(|c| c.module)
+}
+
+/// Compile with options, resolving `#include` through `includes`
+pub fn compile_with(
+    name: &str,
+    source: &str,
+    options: &CompileOptions,
+    includes: &dyn IncludeResolver,
+) -> Result<Compiled, CompileError> {
+    let mut sources = SourceMap::new();
+    let id = sources.add(name, source);
+
+    let mut preprocessor = Preprocessor::new(includes);
+    if let Some(version) = options.version {
+        preprocessor.default_version(version.number.into(), version.es);
+    }
+    for (name, value) in &options.defines {
+        preprocessor.define(name, value);
+    }
+    let preprocessed = match preprocessor.run(&mut sources, id) {
+        Ok(preprocessed) => preprocessed,
+        Err(diagnostics) => {
+            return Err(CompileError {
+                sources,
+                diagnostics,
+            })
+        }
+    };
+
+    let tu = match TranslationUnit::parse(preprocessed.text.as_str()) {
+        Ok(tu) => tu,
+        Err(e) => {
+            let diagnostic = parse_error_diagnostic(&e, &preprocessed, &sources);
+            return Err(CompileError {
+                sources,
+                diagnostics: vec![diagnostic],
+            });
+        }
+    };
+
+    let text = &preprocessed.text;
+    let mut module = match sema::analyze_with_version(&tu, options.version) {
+        Ok(module) => module,
+        Err(errors) => {
+            let diagnostics = errors
+                .iter()
+                .map(|e| {
+                    let span = sema::locate(e, text)
+                        .and_then(|r| preprocessed.line_map.map_range(text, &sources, r));
+                    e.to_diagnostic(span)
+                })
+                .collect();
+            return Err(CompileError {
+                sources,
+                diagnostics,
+            });
+        }
+    };
+    lower::lower(&mut module);
+
+    let mut warnings = Vec::new();
+    if let Some(cap) = options.loop_cap {
+        for capped in lower::cap_loops(&mut module, cap) {
+            let function = &module.function(capped.function).name;
+            let span = sema::locate_loop(text, function, capped.ordinal)
+                .and_then(|r| preprocessed.line_map.map_range(text, &sources, r));
+            let mut warning = Diagnostic::warning("loop may not terminate");
+            if let Some(span) = span {
+                warning = warning.with_primary(span, format!("capped at {} iterations", cap));
+            }
+            warnings.push(warning.with_note(
+                "bound the loop with a constant to avoid the cap, as in GLSL ES 1.00 Appendix A",
+            ));
+        }
+    }
+    // Recursion was rejected during analysis, so the graph is acyclic
+    let stack_size = module
+        .find_function(hir::MAIN)
+        .and_then(|main| CallGraph::new(&module).max_stack_size(main));
+    Ok(Compiled {
+        module,
+        sources,
+        warnings,
+        stack_size,
+        precisions: options.precisions(),
+    })
+}
+
+/// Convert a parser error, pointing at the reported line when there is one
+fn parse_error_diagnostic(
+    error: &ParseError,
+    preprocessed: &Preprocessed,
+    sources: &SourceMap,
+) -> Diagnostic {
+    let info = error.info.trim();
+    let mut diagnostic = Diagnostic::error("syntax error");
+
+    // The parser reports positions in the preprocessed text as "at line N"
+    let span = info
+        .split("at line ")
+        .nth(1)
+        .and_then(|rest| {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            rest[..digits].parse::<usize>().ok()
+        })
+        .and_then(|line| preprocessed.line_map.origin(line))
+        .and_then(|origin| sources.line_span(origin.source, origin.line));
+    if let Some(span) = span {
+        diagnostic = diagnostic.with_primary(span, "could not parse this line");
+    }
+    if let Some(first) = info.lines().find(|l| !l.trim().is_empty()) {
+        diagnostic = diagnostic.with_note(first.trim());
+    }
+    diagnostic
+}
@@ -0,0 +1,290 @@
+//! Compiler diagnostics with source locations
+//!
+//! A [`Diagnostic`] carries a severity, a message, an optional primary
+//! [`Label`] pointing into a source file, secondary labels and free-form
+//! notes. [`Diagnostic::render`] prints it with the offending source lines
+//! and carets underneath, similar to rustc:
+//!
+//! ```text
+//! error: use of undeclared variable `colr`
+//!  --> shader.glsl:3:25
+//!   |
+//! 3 |     gl_FragColor = vec4(colr, 1.0);
+//!   |                         ^^^^ not found in this scope
+//! ```
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::{self, Write};
+
+/// Identifies a file in a [`SourceMap`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceId(pub u32);
+
+/// Byte range in one source file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub source: SourceId,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(source: SourceId, start: usize, end: usize) -> Self {
+        Self { source, start, end }
+    }
+}
+
+/// A named source text with a line index
+#[derive(Clone, Debug)]
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: impl Into<String>, text: impl Into<String>) -> Self {
+        let text = text.into();
+        let line_starts = core::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            name: name.into(),
+            text,
+            line_starts,
+        }
+    }
+
+    /// 1-based line and column of a byte offset
+    ///
+    /// Columns count characters, not bytes.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let column = self.text[self.line_starts[line]..offset].chars().count();
+        (line + 1, column + 1)
+    }
+
+    /// Text of a 1-based line, without its line terminator
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .copied()
+            .unwrap_or(self.text.len());
+        self.text[start..end].trim_end_matches(['\n', '\r'])
+    }
+
+    /// Byte offset where a 1-based line starts
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        self.line_starts.get(line.checked_sub(1)?).copied()
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+}
+
+/// Collection of source files that spans refer to
+#[derive(Clone, Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, text: impl Into<String>) -> SourceId {
+        let id = SourceId(self.files.len() as u32);
+        self.files.push(SourceFile::new(name, text));
+        id
+    }
+
+    pub fn get(&self, id: SourceId) -> &SourceFile {
+        &self.files[id.0 as usize]
+    }
+
+    /// Span covering a 1-based line without its surrounding whitespace
+    pub fn line_span(&self, id: SourceId, line: usize) -> Option<Span> {
+        let file = self.get(id);
+        if line == 0 || line > file.line_count() {
+            return None;
+        }
+        let text = file.line(line);
+        let start = file.line_start(line)? + text.len() - text.trim_start().len();
+        Some(Span::new(id, start, start + text.trim().len()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A message attached to a span
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub primary: Option<Label>,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            primary: None,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    /// Set the span the diagnostic is about
+    pub fn with_primary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.primary = Some(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Add a related span, such as a previous declaration
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Render the diagnostic with source snippets
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = String::new();
+        // Writing to a String cannot fail
+        let _ = self.render_to(&mut out, sources);
+        out
+    }
+
+    fn render_to(&self, out: &mut String, sources: &SourceMap) -> fmt::Result {
+        writeln!(out, "{}: {}", self.severity, self.message)?;
+
+        let mut labels: Vec<(&Label, bool)> = Vec::new();
+        labels.extend(self.primary.iter().map(|l| (l, true)));
+        labels.extend(self.secondary.iter().map(|l| (l, false)));
+
+        // Width of the line number gutter
+        let width = labels
+            .iter()
+            .map(|(l, _)| sources.get(l.span.source).line_col(l.span.start).0)
+            .max()
+            .map_or(0, |line| format!("{}", line).len());
+        let pad = " ".repeat(width);
+
+        let mut current_file = None;
+        for (label, is_primary) in &labels {
+            let file = sources.get(label.span.source);
+            let (line, column) = file.line_col(label.span.start);
+            if current_file != Some(label.span.source) {
+                let arrow = if current_file.is_none() { "-->" } else { ":::" };
+                writeln!(out, "{}{} {}:{}:{}", pad, arrow, file.name, line, column)?;
+                writeln!(out, "{} |", pad)?;
+                current_file = Some(label.span.source);
+            }
+
+            let text = file.line(line);
+            // Underline up to the end of the span or the end of its first line
+            let line_start = file.line_start(line).unwrap_or(0);
+            let start = label.span.start - line_start;
+            let end = (label.span.end.max(label.span.start) - line_start).min(text.len());
+            let prefix = expand_tabs(&text[..start.min(text.len())]);
+            let underlined =
+                expand_tabs(&text[start.min(text.len())..end.max(start).min(text.len())]);
+            let marker = if *is_primary { "^" } else { "-" };
+            let marks = marker.repeat(underlined.chars().count().max(1));
+
+            writeln!(
+                out,
+                "{:>width$} | {}",
+                line,
+                expand_tabs(text),
+                width = width
+            )?;
+            write!(
+                out,
+                "{} | {}{}",
+                pad,
+                " ".repeat(prefix.chars().count()),
+                marks
+            )?;
+            if label.message.is_empty() {
+                writeln!(out)?;
+            } else {
+                writeln!(out, " {}", label.message)?;
+            }
+        }
+
+        if !self.notes.is_empty() {
+            if !labels.is_empty() {
+                writeln!(out, "{} |", pad)?;
+            }
+            for note in &self.notes {
+                writeln!(out, "{} = note: {}", pad, note)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn expand_tabs(text: &str) -> String {
+    text.replace('\t', "    ")
+}
+
+/// Render a list of diagnostics separated by blank lines
+pub fn render_all(diagnostics: &[Diagnostic], sources: &SourceMap) -> String {
+    diagnostics
+        .iter()
+        .map(|d| d.render(sources))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
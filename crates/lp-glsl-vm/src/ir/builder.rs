@@ -0,0 +1,493 @@
+//! Function construction with on-the-fly SSA for variables
+//!
+//! [`FunctionBuilder`] appends instructions to the current block. Mutable
+//! variables are declared with [`FunctionBuilder::declare_var`], and the
+//! value a variable holds is looked up when it is read, adding a block
+//! parameter where definitions from several predecessors meet. This is the
+//! algorithm of Braun et al., "Simple and Efficient Construction of Static
+//! Single Assignment Form", with block parameters in place of phi nodes.
+//!
+//! A block must be sealed once every branch to it has been added. Until
+//! then, reading a variable in it adds a parameter whose arguments are
+//! filled in when it is sealed. A parameter that receives the same value
+//! from every predecessor is removed again.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec,
+    vec::Vec,
+};
+
+use super::{
+    BinaryOp, Block, BlockCall, Builtin, CompareOp, Constant, ConvertOp, FuncRef, Function,
+    GlobalRef, Inst, InstKind, ScalarType, Slot, Type, UnaryOp, Value, ValueDef,
+};
+
+/// Mutable variable whose SSA values are managed by the builder
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Variable(pub u32);
+
+/// Appends blocks and instructions to a function
+///
+/// Parameters added with [`append_block_param`](Self::append_block_param)
+/// must be added before any branch to the block, and before any variable
+/// is read in it.
+pub struct FunctionBuilder<'a> {
+    func: &'a mut Function,
+    current: Option<Block>,
+    /// Type of each variable
+    variables: Vec<Type>,
+    /// Value of each variable at the end of a block, as far as it is known
+    defs: BTreeMap<(Block, Variable), Value>,
+    /// Branches to each block
+    predecessors: BTreeMap<Block, Vec<Inst>>,
+    /// Block containing each instruction
+    inst_blocks: BTreeMap<Inst, Block>,
+    sealed: BTreeSet<Block>,
+    /// Parameters added to unsealed blocks, given arguments when sealed
+    incomplete: BTreeMap<Block, Vec<(Variable, Value)>>,
+    /// Removed parameters and the value replacing each
+    aliases: BTreeMap<Value, Value>,
+}
+
+impl<'a> FunctionBuilder<'a> {
+    /// Build the body of `func`, which must not have one yet
+    pub fn new(func: &'a mut Function) -> FunctionBuilder<'a> {
+        assert!(func.is_declaration(), "function already has a body");
+        FunctionBuilder {
+            func,
+            current: None,
+            variables: Vec::new(),
+            defs: BTreeMap::new(),
+            predecessors: BTreeMap::new(),
+            inst_blocks: BTreeMap::new(),
+            sealed: BTreeSet::new(),
+            incomplete: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+        }
+    }
+
+    /// The function being built
+    pub fn func(&self) -> &Function {
+        self.func
+    }
+
+    pub fn value_type(&self, value: Value) -> Type {
+        self.func.value_type(value)
+    }
+
+    pub fn create_block(&mut self) -> Block {
+        self.func.new_block()
+    }
+
+    /// Create a block with one parameter per parameter of the signature
+    ///
+    /// It is the entry block if it is the first block switched to.
+    pub fn create_entry_block(&mut self) -> Block {
+        let block = self.create_block();
+        for ty in self.func.signature.params.clone() {
+            self.func.append_block_param(block, ty);
+        }
+        block
+    }
+
+    pub fn append_block_param(&mut self, block: Block, ty: Type) -> Value {
+        self.func.append_block_param(block, ty)
+    }
+
+    pub fn block_params(&self, block: Block) -> &[Value] {
+        &self.func.block(block).params
+    }
+
+    /// Append instructions to `block` from now on
+    ///
+    /// Blocks are laid out in the order they are first switched to.
+    pub fn switch_to_block(&mut self, block: Block) {
+        if !self.func.layout.contains(&block) {
+            self.func.layout.push(block);
+        }
+        self.current = Some(block);
+    }
+
+    pub fn current_block(&self) -> Option<Block> {
+        self.current
+    }
+
+    /// Whether the current block already ends with a terminator
+    pub fn is_terminated(&self) -> bool {
+        self.current
+            .is_some_and(|block| self.func.terminator(block).is_some())
+    }
+
+    /// Declare that every branch to `block` has been added
+    pub fn seal_block(&mut self, block: Block) {
+        if !self.sealed.insert(block) {
+            return;
+        }
+        for (var, param) in self.incomplete.remove(&block).unwrap_or_default() {
+            self.add_param_args(block, var, param);
+        }
+    }
+
+    pub fn seal_all_blocks(&mut self) {
+        for block in self.func.layout.clone() {
+            self.seal_block(block);
+        }
+    }
+
+    pub fn create_slot(&mut self, size: u32, align: u32) -> Slot {
+        self.func.new_slot(size, align)
+    }
+
+    pub fn declare_var(&mut self, ty: Type) -> Variable {
+        self.variables.push(ty);
+        Variable(self.variables.len() as u32 - 1)
+    }
+
+    /// Assign `value` to `var` in the current block
+    pub fn def_var(&mut self, var: Variable, value: Value) {
+        assert_eq!(
+            self.value_type(value),
+            self.variables[var.0 as usize],
+            "value of the wrong type for {:?}",
+            var
+        );
+        let block = self.current.expect("no current block");
+        self.defs.insert((block, var), value);
+    }
+
+    /// Value of `var` in the current block
+    ///
+    /// A variable read before any assignment reaches it is zero.
+    pub fn use_var(&mut self, var: Variable) -> Value {
+        let block = self.current.expect("no current block");
+        self.read_var(var, block)
+    }
+
+    /// Remove blocks that cannot be reached and replace uses of removed
+    /// block parameters, sealing any block still unsealed
+    pub fn finish(mut self) {
+        self.seal_all_blocks();
+
+        let Some(entry) = self.func.entry() else {
+            return;
+        };
+        let mut reachable = BTreeSet::from([entry]);
+        let mut stack = vec![entry];
+        while let Some(block) = stack.pop() {
+            for successor in self.func.successors(block) {
+                if reachable.insert(successor) {
+                    stack.push(successor);
+                }
+            }
+        }
+        self.func.layout.retain(|block| reachable.contains(block));
+
+        let aliases = &self.aliases;
+        let resolve = |mut value: Value| {
+            while let Some(&alias) = aliases.get(&value) {
+                value = alias;
+            }
+            value
+        };
+        for inst in &mut self.func.insts {
+            inst.kind.map_operands(resolve);
+        }
+    }
+
+    pub fn iconst(&mut self, value: i32) -> Value {
+        self.constant(Constant::I32(value))
+    }
+
+    pub fn fconst(&mut self, value: f32) -> Value {
+        self.constant(Constant::F32(value))
+    }
+
+    pub fn bconst(&mut self, value: bool) -> Value {
+        self.constant(Constant::Bool(value))
+    }
+
+    pub fn constant(&mut self, constant: Constant) -> Value {
+        self.ins1(InstKind::Const(constant), constant.ty())
+    }
+
+    /// Zero, `false` or `0.0` in every lane of a value of type `ty`
+    pub fn zero(&mut self, ty: Type) -> Value {
+        let zero = self.constant(zero_constant(ty.scalar));
+        if ty.is_vector() {
+            self.splat(zero, ty.lanes)
+        } else {
+            zero
+        }
+    }
+
+    pub fn unary(&mut self, op: UnaryOp, value: Value) -> Value {
+        let ty = self.value_type(value);
+        self.ins1(InstKind::Unary(op, value), ty)
+    }
+
+    pub fn binary(&mut self, op: BinaryOp, lhs: Value, rhs: Value) -> Value {
+        let ty = self.value_type(lhs);
+        self.ins1(InstKind::Binary(op, lhs, rhs), ty)
+    }
+
+    pub fn compare(&mut self, op: CompareOp, lhs: Value, rhs: Value) -> Value {
+        let ty = self.value_type(lhs).with_scalar(ScalarType::Bool);
+        self.ins1(InstKind::Compare(op, lhs, rhs), ty)
+    }
+
+    pub fn convert(&mut self, op: ConvertOp, value: Value) -> Value {
+        let ty = self.value_type(value).with_scalar(op.types().1);
+        self.ins1(InstKind::Convert(op, value), ty)
+    }
+
+    pub fn select(&mut self, cond: Value, if_true: Value, if_false: Value) -> Value {
+        let ty = self.value_type(if_true);
+        self.ins1(InstKind::Select(cond, if_true, if_false), ty)
+    }
+
+    pub fn splat(&mut self, value: Value, lanes: u8) -> Value {
+        let ty = Type::vector(self.value_type(value).scalar, lanes);
+        self.ins1(InstKind::Splat(value), ty)
+    }
+
+    pub fn vector(&mut self, lanes: &[Value]) -> Value {
+        let ty = Type::vector(self.value_type(lanes[0]).scalar, lanes.len() as u8);
+        self.ins1(InstKind::Vector(lanes.to_vec()), ty)
+    }
+
+    pub fn extract(&mut self, vector: Value, lane: u8) -> Value {
+        let ty = self.value_type(vector).lane_type();
+        self.ins1(InstKind::Extract(vector, lane), ty)
+    }
+
+    pub fn insert(&mut self, vector: Value, lane: u8, value: Value) -> Value {
+        let ty = self.value_type(vector);
+        self.ins1(InstKind::Insert(vector, lane, value), ty)
+    }
+
+    pub fn builtin(&mut self, builtin: Builtin, args: &[Value], ty: Type) -> Value {
+        self.ins1(InstKind::Builtin(builtin, args.to_vec()), ty)
+    }
+
+    /// Call `func`, whose signature returns `returns`
+    pub fn call(&mut self, func: FuncRef, args: &[Value], returns: &[Type]) -> Vec<Value> {
+        let inst = self.ins(InstKind::Call(func, args.to_vec()), returns);
+        self.func.inst(inst).results.clone()
+    }
+
+    pub fn stack_addr(&mut self, slot: Slot) -> Value {
+        self.ins1(InstKind::StackAddr(slot), Type::I32)
+    }
+
+    pub fn global_addr(&mut self, global: GlobalRef) -> Value {
+        self.ins1(InstKind::GlobalAddr(global), Type::I32)
+    }
+
+    pub fn load(&mut self, ty: Type, addr: Value, offset: i32) -> Value {
+        self.ins1(InstKind::Load(addr, offset), ty)
+    }
+
+    pub fn store(&mut self, value: Value, addr: Value, offset: i32) {
+        self.ins(InstKind::Store(value, addr, offset), &[]);
+    }
+
+    pub fn jump(&mut self, block: Block, args: &[Value]) {
+        self.ins(InstKind::Jump(block_call(block, args)), &[]);
+    }
+
+    pub fn brif(
+        &mut self,
+        cond: Value,
+        then_block: Block,
+        then_args: &[Value],
+        else_block: Block,
+        else_args: &[Value],
+    ) {
+        self.ins(
+            InstKind::Branch(
+                cond,
+                block_call(then_block, then_args),
+                block_call(else_block, else_args),
+            ),
+            &[],
+        );
+    }
+
+    pub fn ret(&mut self, values: &[Value]) {
+        self.ins(InstKind::Return(values.to_vec()), &[]);
+    }
+
+    pub fn discard(&mut self) {
+        self.ins(InstKind::Discard, &[]);
+    }
+
+    fn ins1(&mut self, kind: InstKind, ty: Type) -> Value {
+        let inst = self.ins(kind, &[ty]);
+        self.func.inst(inst).results[0]
+    }
+
+    /// Append an instruction to the current block
+    fn ins(&mut self, kind: InstKind, result_types: &[Type]) -> Inst {
+        let block = self.current.expect("no current block");
+        assert!(
+            !self.is_terminated(),
+            "block{} is already terminated",
+            block.0
+        );
+        for successor in kind.successors() {
+            assert!(
+                !self.sealed.contains(&successor),
+                "branch to sealed block{}",
+                successor.0
+            );
+        }
+        let successors = kind.successors();
+        let inst = self.func.new_inst(kind, result_types);
+        self.func.block_mut(block).insts.push(inst);
+        self.inst_blocks.insert(inst, block);
+        for successor in successors {
+            let predecessors = self.predecessors.entry(successor).or_default();
+            // Both targets of a branch may be the same block
+            if predecessors.last() != Some(&inst) {
+                predecessors.push(inst);
+            }
+        }
+        inst
+    }
+
+    fn resolve(&self, mut value: Value) -> Value {
+        while let Some(&alias) = self.aliases.get(&value) {
+            value = alias;
+        }
+        value
+    }
+
+    fn read_var(&mut self, var: Variable, block: Block) -> Value {
+        if let Some(&value) = self.defs.get(&(block, var)) {
+            return self.resolve(value);
+        }
+        let ty = self.variables[var.0 as usize];
+        let predecessors = self.predecessors.get(&block).cloned().unwrap_or_default();
+        let value = if !self.sealed.contains(&block) {
+            let param = self.func.append_block_param(block, ty);
+            self.incomplete.entry(block).or_default().push((var, param));
+            param
+        } else if predecessors.is_empty() {
+            self.zero_at_start(block, ty)
+        } else if let [single] = predecessors[..] {
+            let predecessor = self.inst_blocks[&single];
+            if predecessor == block {
+                // A block that only loops to itself is unreachable
+                self.zero_at_start(block, ty)
+            } else {
+                self.read_var(var, predecessor)
+            }
+        } else {
+            // Record the parameter first so that loops back to this block
+            // find it instead of recursing
+            let param = self.func.append_block_param(block, ty);
+            self.defs.insert((block, var), param);
+            self.add_param_args(block, var, param)
+        };
+        self.defs.insert((block, var), value);
+        value
+    }
+
+    /// Pass the value of `var` in each predecessor as the argument for
+    /// `param`, then remove `param` if it only ever receives one value
+    fn add_param_args(&mut self, block: Block, var: Variable, param: Value) -> Value {
+        let predecessors = self.predecessors.get(&block).cloned().unwrap_or_default();
+        for inst in predecessors {
+            let arg = self.read_var(var, self.inst_blocks[&inst]);
+            for call in self.func.inst_mut(inst).kind.block_calls_mut() {
+                if call.block == block {
+                    call.args.push(arg);
+                }
+            }
+        }
+        self.remove_trivial_param(block, param)
+    }
+
+    fn remove_trivial_param(&mut self, block: Block, param: Value) -> Value {
+        let index = self
+            .func
+            .block(block)
+            .params
+            .iter()
+            .position(|&p| p == param)
+            .expect("parameter of another block");
+        let predecessors = self.predecessors.get(&block).cloned().unwrap_or_default();
+        let mut same = None;
+        for &inst in &predecessors {
+            for call in self.func.inst(inst).kind.block_calls() {
+                if call.block != block {
+                    continue;
+                }
+                let arg = self.resolve(call.args[index]);
+                if arg == param || Some(arg) == same {
+                    continue;
+                }
+                if same.is_some() {
+                    return param;
+                }
+                same = Some(arg);
+            }
+        }
+        let Some(same) = same else {
+            return param;
+        };
+
+        let params = &mut self.func.block_mut(block).params;
+        params.remove(index);
+        let shifted = params[index..].to_vec();
+        for (i, value) in shifted.into_iter().enumerate() {
+            self.func.values[value.0 as usize].def = ValueDef::Param(block, (index + i) as u32);
+        }
+        self.func.values[param.0 as usize].def = ValueDef::Detached;
+        for inst in predecessors {
+            for call in self.func.inst_mut(inst).kind.block_calls_mut() {
+                if call.block == block {
+                    call.args.remove(index);
+                }
+            }
+        }
+        self.aliases.insert(param, same);
+        same
+    }
+
+    /// Zero value for a variable read where no assignment reaches, placed
+    /// at the start of `block` so it dominates every use there
+    fn zero_at_start(&mut self, block: Block, ty: Type) -> Value {
+        let constant = self
+            .func
+            .new_inst(InstKind::Const(zero_constant(ty.scalar)), &[ty.lane_type()]);
+        let mut insts = vec![constant];
+        let mut value = self.func.inst(constant).results[0];
+        if ty.is_vector() {
+            let splat = self.func.new_inst(InstKind::Splat(value), &[ty]);
+            insts.push(splat);
+            value = self.func.inst(splat).results[0];
+        }
+        for inst in &insts {
+            self.inst_blocks.insert(*inst, block);
+        }
+        self.func.block_mut(block).insts.splice(0..0, insts);
+        value
+    }
+}
+
+fn zero_constant(scalar: ScalarType) -> Constant {
+    match scalar {
+        ScalarType::Bool => Constant::Bool(false),
+        ScalarType::I32 => Constant::I32(0),
+        ScalarType::F32 => Constant::F32(0.0),
+    }
+}
+
+fn block_call(block: Block, args: &[Value]) -> BlockCall {
+    BlockCall {
+        block,
+        args: args.to_vec(),
+    }
+}
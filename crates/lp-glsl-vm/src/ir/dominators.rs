@@ -0,0 +1,134 @@
+//! Dominator tree of a function body
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec,
+    vec::Vec,
+};
+
+use super::{Block, Function};
+
+/// Dominator tree of the blocks reachable from the entry block
+pub struct Dominators {
+    /// Immediate dominator of each reachable block; the entry block is its own
+    idom: BTreeMap<Block, Block>,
+    /// Reachable blocks in reverse postorder
+    rpo: Vec<Block>,
+    /// Position of each reachable block in `rpo`
+    rpo_index: BTreeMap<Block, usize>,
+}
+
+impl Dominators {
+    /// Cooper, Harvey and Kennedy, "A Simple, Fast Dominance Algorithm"
+    pub fn new(func: &Function) -> Dominators {
+        let entry = func.entry().expect("function with a body");
+        // Reverse postorder by an iterative depth-first search
+        let mut postorder = Vec::new();
+        let mut visited = BTreeSet::from([entry]);
+        let mut stack = vec![(entry, func.successors(entry), 0)];
+        while let Some((block, successors, next)) = stack.last_mut() {
+            if let Some(&successor) = successors.get(*next) {
+                *next += 1;
+                if visited.insert(successor) {
+                    let successors = func.successors(successor);
+                    stack.push((successor, successors, 0));
+                }
+            } else {
+                postorder.push(*block);
+                stack.pop();
+            }
+        }
+        let rpo: Vec<Block> = postorder.into_iter().rev().collect();
+        let rpo_index: BTreeMap<Block, usize> =
+            rpo.iter().enumerate().map(|(i, &b)| (b, i)).collect();
+
+        let mut predecessors: BTreeMap<Block, Vec<Block>> = BTreeMap::new();
+        for &block in &rpo {
+            for successor in func.successors(block) {
+                predecessors.entry(successor).or_default().push(block);
+            }
+        }
+
+        let mut idom = BTreeMap::from([(entry, entry)]);
+        let intersect = |idom: &BTreeMap<Block, Block>, mut a: Block, mut b: Block| {
+            while a != b {
+                while rpo_index[&a] > rpo_index[&b] {
+                    a = idom[&a];
+                }
+                while rpo_index[&b] > rpo_index[&a] {
+                    b = idom[&b];
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &rpo[1..] {
+                let mut new_idom = None;
+                for &predecessor in predecessors.get(&block).into_iter().flatten() {
+                    if !idom.contains_key(&predecessor) {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => predecessor,
+                        Some(current) => intersect(&idom, predecessor, current),
+                    });
+                }
+                let new_idom = new_idom.expect("reachable block without a processed predecessor");
+                if idom.get(&block) != Some(&new_idom) {
+                    idom.insert(block, new_idom);
+                    changed = true;
+                }
+            }
+        }
+        Dominators {
+            idom,
+            rpo,
+            rpo_index,
+        }
+    }
+
+    pub fn is_reachable(&self, block: Block) -> bool {
+        self.rpo_index.contains_key(&block)
+    }
+
+    /// Whether every path from the entry block to `b` goes through `a`
+    pub fn dominates(&self, a: Block, mut b: Block) -> bool {
+        loop {
+            if a == b {
+                return true;
+            }
+            let Some(&idom) = self.idom.get(&b) else {
+                return false;
+            };
+            if idom == b {
+                return false;
+            }
+            b = idom;
+        }
+    }
+
+    /// Reachable blocks in reverse postorder, so each block comes after its
+    /// dominators
+    pub fn rpo(&self) -> &[Block] {
+        &self.rpo
+    }
+
+    /// Immediate dominator of a reachable block other than the entry block
+    pub fn idom(&self, block: Block) -> Option<Block> {
+        self.idom.get(&block).copied().filter(|&idom| idom != block)
+    }
+
+    /// Blocks immediately dominated by each reachable block, in reverse
+    /// postorder
+    pub fn children(&self) -> BTreeMap<Block, Vec<Block>> {
+        let mut children: BTreeMap<Block, Vec<Block>> = BTreeMap::new();
+        for &block in &self.rpo {
+            if let Some(idom) = self.idom(block) {
+                children.entry(idom).or_default().push(block);
+            }
+        }
+        children
+    }
+}
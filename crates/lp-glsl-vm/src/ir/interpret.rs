@@ -0,0 +1,494 @@
+//! Reference interpreter for the IR
+//!
+//! [`Interpreter`] runs functions of a module on the host with native `f32`
+//! arithmetic, giving the results compiled code is checked against. Values
+//! are handled as the raw bits of their lanes: `bool` as 0 or 1, `i32` in
+//! two's complement and `f32` in IEEE 754 single precision. Integer
+//! operations behave as on RISC-V: arithmetic wraps, shift amounts are
+//! taken modulo 32, and division by zero gives all ones (the dividend for a
+//! remainder) instead of trapping. Builtins are evaluated with the same
+//! routines as constant folding, and calls to functions without a body run
+//! the [runtime](crate::runtime) routine of the same name, if there is one.
+//!
+//! Globals and stack slots live in one zero-initialized memory. Address 0
+//! and the words after it are never allocated, so null pointers trap.
+
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+
+use super::{
+    BinaryOp, Block, BlockCall, CompareOp, Constant, ConvertOp, FuncRef, Function, InstKind,
+    Module, ScalarType, Type, UnaryOp, Value,
+};
+use crate::{
+    layout::align_to,
+    runtime,
+    sema::{self, const_eval::builtin_call, hir::Literal},
+};
+
+/// Lanes of a value as raw bits, unused lanes being 0
+pub type Bits = [u32; 4];
+
+/// Stack size of a new interpreter, in bytes
+pub const DEFAULT_STACK_SIZE: u32 = 64 * 1024;
+
+/// Instructions a new interpreter executes before giving up
+pub const DEFAULT_STEP_LIMIT: u64 = 100_000_000;
+
+/// Deepest nesting of calls
+const MAX_CALL_DEPTH: u32 = 256;
+
+/// First address that can be allocated
+const FIRST_ADDRESS: u32 = 16;
+
+/// Why execution stopped before finishing
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Trap {
+    /// Access to memory outside every global and the stack
+    OutOfBounds {
+        addr: u32,
+        size: u32,
+    },
+    StackOverflow,
+    /// The step limit was reached, such as in an endless loop
+    StepLimit,
+    /// Call to a function that has no body and is no runtime routine, or
+    /// that is not in the module
+    Undefined(String),
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trap::OutOfBounds { addr, size } => {
+                write!(
+                    f,
+                    "access to {} bytes at {:#x} is out of bounds",
+                    size, addr
+                )
+            }
+            Trap::StackOverflow => f.write_str("stack overflow"),
+            Trap::StepLimit => f.write_str("step limit reached"),
+            Trap::Undefined(name) => write!(f, "call to undefined function `@{}`", name),
+        }
+    }
+}
+
+/// How a call ended
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Exit {
+    Return(Vec<Bits>),
+    /// A `discard` ended the invocation
+    Discard,
+}
+
+pub struct Interpreter<'m> {
+    module: &'m Module,
+    memory: Vec<u8>,
+    /// Address of each global
+    globals: Vec<u32>,
+    /// Next free stack address
+    stack_pointer: u32,
+    depth: u32,
+    steps_left: u64,
+}
+
+impl<'m> Interpreter<'m> {
+    pub fn new(module: &'m Module) -> Interpreter<'m> {
+        Interpreter::with_limits(module, DEFAULT_STACK_SIZE, DEFAULT_STEP_LIMIT)
+    }
+
+    /// Interpreter with `stack_size` bytes of stack that executes at most
+    /// `step_limit` instructions
+    pub fn with_limits(module: &'m Module, stack_size: u32, step_limit: u64) -> Interpreter<'m> {
+        let mut end = FIRST_ADDRESS;
+        let globals = module
+            .globals
+            .iter()
+            .map(|global| {
+                let addr = align_to(end, global.align.max(1));
+                end = addr + global.size;
+                addr
+            })
+            .collect();
+        let stack_pointer = align_to(end, 16);
+        Interpreter {
+            module,
+            memory: vec![0; (stack_pointer + stack_size) as usize],
+            globals,
+            stack_pointer,
+            depth: 0,
+            steps_left: step_limit,
+        }
+    }
+
+    /// Address of the global called `name`
+    pub fn global_address(&self, name: &str) -> Option<u32> {
+        Some(self.globals[self.module.find_global(name)?.0 as usize])
+    }
+
+    pub fn read(&self, addr: u32, size: u32) -> Result<&[u8], Trap> {
+        let range = self.range(addr, size)?;
+        Ok(&self.memory[range])
+    }
+
+    pub fn write(&mut self, addr: u32, bytes: &[u8]) -> Result<(), Trap> {
+        let range = self.range(addr, bytes.len() as u32)?;
+        self.memory[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn range(&self, addr: u32, size: u32) -> Result<core::ops::Range<usize>, Trap> {
+        let end = addr as u64 + size as u64;
+        if addr < FIRST_ADDRESS || end > self.memory.len() as u64 {
+            return Err(Trap::OutOfBounds { addr, size });
+        }
+        Ok(addr as usize..end as usize)
+    }
+
+    /// Call `func` with the bits of its arguments
+    pub fn call(&mut self, func: FuncRef, args: &[Bits]) -> Result<Exit, Trap> {
+        let func = self.module.function(func);
+        if func.is_declaration() {
+            let args: Vec<u32> = args.iter().map(|arg| arg[0]).collect();
+            return match runtime::call_symbol(&func.name, &args) {
+                Some(result) => Ok(Exit::Return(vec![scalar(result)])),
+                None => Err(Trap::Undefined(func.name.clone())),
+            };
+        }
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(Trap::StackOverflow);
+        }
+
+        // Allocate the slots of the frame
+        let saved_stack_pointer = self.stack_pointer;
+        let mut slots = Vec::with_capacity(func.slots.len());
+        for slot in &func.slots {
+            let addr = align_to(self.stack_pointer, slot.align.max(1));
+            if addr as u64 + slot.size as u64 > self.memory.len() as u64 {
+                self.stack_pointer = saved_stack_pointer;
+                return Err(Trap::StackOverflow);
+            }
+            self.memory[addr as usize..(addr + slot.size) as usize].fill(0);
+            slots.push(addr);
+            self.stack_pointer = addr + slot.size;
+        }
+
+        self.depth += 1;
+        let mut frame = Frame {
+            func,
+            values: vec![[0; 4]; func.values.len()],
+            slots,
+        };
+        let entry = func.entry().expect("function with a body");
+        for (&param, &arg) in func.block(entry).params.iter().zip(args) {
+            frame.values[param.0 as usize] = arg;
+        }
+        let exit = self.run(&mut frame, entry);
+        self.depth -= 1;
+        self.stack_pointer = saved_stack_pointer;
+        exit
+    }
+
+    fn run(&mut self, frame: &mut Frame, mut block: Block) -> Result<Exit, Trap> {
+        'blocks: loop {
+            for &inst in &frame.func.block(block).insts {
+                if self.steps_left == 0 {
+                    return Err(Trap::StepLimit);
+                }
+                self.steps_left -= 1;
+
+                let data = frame.func.inst(inst);
+                let result = match &data.kind {
+                    InstKind::Jump(call) => {
+                        block = frame.enter(call);
+                        continue 'blocks;
+                    }
+                    InstKind::Branch(cond, then, otherwise) => {
+                        block = match frame.get(*cond)[0] {
+                            0 => frame.enter(otherwise),
+                            _ => frame.enter(then),
+                        };
+                        continue 'blocks;
+                    }
+                    InstKind::Return(values) => {
+                        return Ok(Exit::Return(values.iter().map(|&v| frame.get(v)).collect()));
+                    }
+                    InstKind::Discard => return Ok(Exit::Discard),
+                    InstKind::Call(callee, args) => {
+                        let args: Vec<Bits> = args.iter().map(|&v| frame.get(v)).collect();
+                        match self.call(*callee, &args)? {
+                            Exit::Return(results) => {
+                                for (&result, bits) in data.results.iter().zip(results) {
+                                    frame.values[result.0 as usize] = bits;
+                                }
+                                continue;
+                            }
+                            Exit::Discard => return Ok(Exit::Discard),
+                        }
+                    }
+                    InstKind::Store(value, addr, offset) => {
+                        let ty = frame.func.value_type(*value);
+                        let bytes: Vec<u8> = frame.get(*value)[..ty.lanes as usize]
+                            .iter()
+                            .flat_map(|lane| lane.to_le_bytes())
+                            .collect();
+                        let addr = frame.get(*addr)[0].wrapping_add(*offset as u32);
+                        self.write(addr, &bytes)?;
+                        continue;
+                    }
+                    InstKind::Load(addr, offset) => {
+                        let ty = frame.func.value_type(data.results[0]);
+                        let addr = frame.get(*addr)[0].wrapping_add(*offset as u32);
+                        let bytes = self.read(addr, 4 * ty.lanes as u32)?;
+                        let mut bits = [0; 4];
+                        for (lane, word) in bits.iter_mut().zip(bytes.chunks(4)) {
+                            *lane = u32::from_le_bytes(word.try_into().unwrap());
+                            if ty.scalar == ScalarType::Bool {
+                                *lane = (*lane != 0) as u32;
+                            }
+                        }
+                        bits
+                    }
+                    InstKind::StackAddr(slot) => scalar(frame.slots[slot.0 as usize]),
+                    InstKind::GlobalAddr(global) => scalar(self.globals[global.0 as usize]),
+                    kind => {
+                        let ty = frame.func.value_type(data.results[0]);
+                        eval(frame, kind, ty)
+                            .unwrap_or_else(|| panic!("{:?} cannot be evaluated", kind))
+                    }
+                };
+                frame.values[data.results[0].0 as usize] = result;
+            }
+            unreachable!("{} has no terminator", block);
+        }
+    }
+}
+
+/// State of one call
+struct Frame<'m> {
+    func: &'m Function,
+    values: Vec<Bits>,
+    /// Address of each stack slot
+    slots: Vec<u32>,
+}
+
+impl Frame<'_> {
+    fn get(&self, value: Value) -> Bits {
+        self.values[value.0 as usize]
+    }
+
+    /// Pass the arguments of a branch to the parameters of its target
+    fn enter(&mut self, call: &BlockCall) -> Block {
+        let args: Vec<Bits> = call.args.iter().map(|&v| self.get(v)).collect();
+        for (&param, bits) in self.func.block(call.block).params.iter().zip(args) {
+            self.values[param.0 as usize] = bits;
+        }
+        call.block
+    }
+}
+
+/// Types and bits of the values an instruction operates on
+pub(crate) trait Operands {
+    fn ty(&self, value: Value) -> Type;
+    fn bits(&self, value: Value) -> Bits;
+}
+
+impl Operands for Frame<'_> {
+    fn ty(&self, value: Value) -> Type {
+        self.func.value_type(value)
+    }
+
+    fn bits(&self, value: Value) -> Bits {
+        self.get(value)
+    }
+}
+
+/// Result of an instruction without effects on memory or control flow, or
+/// `None` for a builtin that cannot be evaluated on its arguments
+pub(crate) fn eval(operands: &impl Operands, kind: &InstKind, result_ty: Type) -> Option<Bits> {
+    let lanes = |f: &dyn Fn(usize) -> u32| {
+        let mut bits = [0; 4];
+        for (i, lane) in bits.iter_mut().enumerate().take(result_ty.lanes as usize) {
+            *lane = f(i);
+        }
+        bits
+    };
+    Some(match kind {
+        InstKind::Const(constant) => scalar(match *constant {
+            Constant::Bool(b) => b as u32,
+            Constant::I32(i) => i as u32,
+            Constant::F32(f) => f.to_bits(),
+        }),
+        InstKind::Unary(op, a) => {
+            let (a, scalar) = (operands.bits(*a), operands.ty(*a).scalar);
+            lanes(&|i| unary(*op, scalar, a[i]))
+        }
+        InstKind::Binary(op, a, b) => {
+            let scalar = operands.ty(*a).scalar;
+            let (a, b) = (operands.bits(*a), operands.bits(*b));
+            lanes(&|i| binary(*op, scalar, a[i], b[i]))
+        }
+        InstKind::Compare(op, a, b) => {
+            let scalar = operands.ty(*a).scalar;
+            let (a, b) = (operands.bits(*a), operands.bits(*b));
+            lanes(&|i| compare(*op, scalar, a[i], b[i]) as u32)
+        }
+        InstKind::Convert(op, a) => {
+            let a = operands.bits(*a);
+            lanes(&|i| convert(*op, a[i]))
+        }
+        InstKind::Select(cond, a, b) => {
+            let vector_cond = operands.ty(*cond).is_vector();
+            let (cond, a, b) = (operands.bits(*cond), operands.bits(*a), operands.bits(*b));
+            lanes(&|i| match cond[if vector_cond { i } else { 0 }] {
+                0 => b[i],
+                _ => a[i],
+            })
+        }
+        InstKind::Splat(a) => {
+            let a = operands.bits(*a)[0];
+            lanes(&|_| a)
+        }
+        InstKind::Vector(values) => lanes(&|i| operands.bits(values[i])[0]),
+        InstKind::Extract(v, lane) => scalar(operands.bits(*v)[*lane as usize]),
+        InstKind::Insert(v, lane, x) => {
+            let mut bits = operands.bits(*v);
+            bits[*lane as usize] = operands.bits(*x)[0];
+            bits
+        }
+        InstKind::Builtin(builtin, args) => {
+            let args: Vec<Vec<Literal>> = args
+                .iter()
+                .map(|&arg| {
+                    let ty = operands.ty(arg);
+                    let bits = operands.bits(arg);
+                    (0..ty.lanes as usize)
+                        .map(|i| literal(ty.scalar, bits[i]))
+                        .collect()
+                })
+                .collect();
+            let ty = sema_type(result_ty);
+            let result = builtin_call(*builtin, &args, &ty)?;
+            lanes(&|i| literal_bits(&result[i]))
+        }
+        _ => unreachable!("instruction with effects"),
+    })
+}
+
+fn scalar(bits: u32) -> Bits {
+    [bits, 0, 0, 0]
+}
+
+fn unary(op: UnaryOp, scalar: ScalarType, a: u32) -> u32 {
+    match (op, scalar) {
+        (UnaryOp::Neg, ScalarType::F32) => (-f32::from_bits(a)).to_bits(),
+        (UnaryOp::Neg, _) => (a as i32).wrapping_neg() as u32,
+        (UnaryOp::Not, ScalarType::Bool) => a ^ 1,
+        (UnaryOp::Not, _) => !a,
+        (UnaryOp::Lowp | UnaryOp::Mediump | UnaryOp::Highp, _) => a,
+    }
+}
+
+fn binary(op: BinaryOp, scalar: ScalarType, a: u32, b: u32) -> u32 {
+    use BinaryOp::*;
+
+    if scalar == ScalarType::F32 {
+        let (x, y) = (f32::from_bits(a), f32::from_bits(b));
+        let result = match op {
+            Add => x + y,
+            Sub => x - y,
+            Mul => x * y,
+            Div => x / y,
+            _ => unreachable!("`{}` of f32", op.name()),
+        };
+        return result.to_bits();
+    }
+    let (x, y) = (a as i32, b as i32);
+    match op {
+        Add => x.wrapping_add(y) as u32,
+        Sub => x.wrapping_sub(y) as u32,
+        Mul => x.wrapping_mul(y) as u32,
+        MulH => ((x as i64 * y as i64) >> 32) as u32,
+        Div if y == 0 => u32::MAX,
+        Div => x.wrapping_div(y) as u32,
+        UDiv => a.checked_div(b).unwrap_or(u32::MAX),
+        Rem if y == 0 => a,
+        Rem => x.wrapping_rem(y) as u32,
+        URem => a.checked_rem(b).unwrap_or(a),
+        And => a & b,
+        Or => a | b,
+        Xor => a ^ b,
+        Shl => a << (b & 31),
+        Shr => (x >> (b & 31)) as u32,
+        UShr => a >> (b & 31),
+    }
+}
+
+fn compare(op: CompareOp, scalar: ScalarType, a: u32, b: u32) -> bool {
+    use CompareOp::*;
+
+    match (op, scalar) {
+        (Eq | Ne | Lt | Le | Gt | Ge, ScalarType::F32) => {
+            let (x, y) = (f32::from_bits(a), f32::from_bits(b));
+            match op {
+                Eq => x == y,
+                Ne => x != y,
+                Lt => x < y,
+                Le => x <= y,
+                Gt => x > y,
+                _ => x >= y,
+            }
+        }
+        (Eq, _) => a == b,
+        (Ne, _) => a != b,
+        (Lt, _) => (a as i32) < (b as i32),
+        (Le, _) => (a as i32) <= (b as i32),
+        (Gt, _) => (a as i32) > (b as i32),
+        (Ge, _) => (a as i32) >= (b as i32),
+        (ULt, _) => a < b,
+        (ULe, _) => a <= b,
+        (UGt, _) => a > b,
+        (UGe, _) => a >= b,
+    }
+}
+
+fn convert(op: ConvertOp, a: u32) -> u32 {
+    // Float to integer casts saturate and turn NaN into 0
+    match op {
+        ConvertOp::SIntToFloat => (a as i32 as f32).to_bits(),
+        ConvertOp::UIntToFloat => (a as f32).to_bits(),
+        ConvertOp::FloatToSInt => f32::from_bits(a) as i32 as u32,
+        ConvertOp::FloatToUInt => f32::from_bits(a) as u32,
+    }
+}
+
+fn literal(scalar: ScalarType, bits: u32) -> Literal {
+    match scalar {
+        ScalarType::Bool => Literal::Bool(bits != 0),
+        ScalarType::I32 => Literal::Int(bits as i32),
+        ScalarType::F32 => Literal::Float(f32::from_bits(bits)),
+    }
+}
+
+fn literal_bits(literal: &Literal) -> u32 {
+    match *literal {
+        Literal::Bool(b) => b as u32,
+        Literal::Int(i) => i as u32,
+        Literal::UInt(u) => u,
+        Literal::Float(f) => f.to_bits(),
+    }
+}
+
+/// GLSL type of values of type `ty`, taking `i32` as `int`
+fn sema_type(ty: Type) -> sema::Type {
+    let scalar = match ty.scalar {
+        ScalarType::Bool => sema::ScalarType::Bool,
+        ScalarType::I32 => sema::ScalarType::Int,
+        ScalarType::F32 => sema::ScalarType::Float,
+    };
+    match ty.lanes {
+        1 => sema::Type::Scalar(scalar),
+        n => sema::Type::Vector(scalar, n),
+    }
+}
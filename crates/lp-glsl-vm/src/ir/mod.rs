@@ -0,0 +1,697 @@
+//! SSA intermediate representation
+//!
+//! A function is a list of basic blocks of instructions over typed SSA
+//! values. Instead of phi nodes, blocks take parameters and every branch
+//! passes arguments for them. Scalars and vectors are values; structs,
+//! arrays and matrices live in memory, in stack slots or globals, and are
+//! accessed through `i32` addresses with `load` and `store`.
+//!
+//! [`translate`] produces a module from lowered HIR and [`FunctionBuilder`]
+//! builds functions by hand. [`verify`] checks that a module is well formed,
+//! and the [`Display`](fmt::Display) implementation of [`Module`] prints it
+//! in a stable textual form, which [`parse`] reads back.
+//! [`interpret::Interpreter`] runs functions on the host as a reference.
+
+mod builder;
+mod dominators;
+pub mod interpret;
+mod parse;
+mod print;
+mod translate;
+mod verify;
+
+use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+use core::fmt;
+
+pub use builder::{FunctionBuilder, Variable};
+pub use dominators::Dominators;
+pub use parse::{parse, ParseError};
+pub use translate::{translate, UNIFORMS};
+pub use verify::{verify, VerifyError};
+
+pub use crate::sema::builtins::Builtin;
+use crate::sema::hir::Precision;
+
+/// SSA value, printed `v0`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub u32);
+
+/// Basic block, printed `block0`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Block(pub u32);
+
+/// Index into [`Function::insts`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Inst(pub u32);
+
+/// Stack slot of a function, printed `ss0`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Slot(pub u32);
+
+/// Index into [`Module::globals`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GlobalRef(pub u32);
+
+/// Index into [`Module::functions`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FuncRef(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ScalarType {
+    Bool,
+    /// 32-bit integer, signed or unsigned depending on the operation
+    I32,
+    F32,
+}
+
+/// Type of a value: a scalar, or a vector of 2 to 4 lanes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Type {
+    pub scalar: ScalarType,
+    pub lanes: u8,
+}
+
+impl Type {
+    pub const BOOL: Type = Type::scalar(ScalarType::Bool);
+    pub const F32: Type = Type::scalar(ScalarType::F32);
+    pub const I32: Type = Type::scalar(ScalarType::I32);
+
+    pub const fn scalar(scalar: ScalarType) -> Type {
+        Type { scalar, lanes: 1 }
+    }
+
+    pub const fn vector(scalar: ScalarType, lanes: u8) -> Type {
+        Type { scalar, lanes }
+    }
+
+    pub fn is_vector(self) -> bool {
+        self.lanes > 1
+    }
+
+    /// Type of one lane
+    pub fn lane_type(self) -> Type {
+        Type::scalar(self.scalar)
+    }
+
+    /// The type with the same lane count and scalar type `scalar`
+    pub fn with_scalar(self, scalar: ScalarType) -> Type {
+        Type { scalar, ..self }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Constant {
+    Bool(bool),
+    I32(i32),
+    F32(f32),
+}
+
+impl Constant {
+    pub fn ty(self) -> Type {
+        match self {
+            Constant::Bool(_) => Type::BOOL,
+            Constant::I32(_) => Type::I32,
+            Constant::F32(_) => Type::F32,
+        }
+    }
+}
+
+macro_rules! ops {
+    (
+        $(#[$meta:meta])*
+        $op:ident { $($(#[$variant_meta:meta])* $variant:ident => $name:literal,)* }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum $op {
+            $($(#[$variant_meta])* $variant,)*
+        }
+
+        impl $op {
+            pub const ALL: &'static [$op] = &[$($op::$variant,)*];
+
+            /// Name of the instruction in the textual form
+            pub fn name(self) -> &'static str {
+                match self {
+                    $($op::$variant => $name,)*
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<$op> {
+                match name {
+                    $($name => Some($op::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+ops! {
+    /// Operation on one value, lane-wise on vectors
+    ///
+    /// `lowp`, `mediump` and `highp` mark an `f32` as held at that GLSL
+    /// precision, where it is read from or written to a variable declared
+    /// with it. They are the identity in `f32`; [`numeric`](crate::numeric)
+    /// lowering converts to the format of the precision.
+    UnaryOp {
+        Neg => "neg",
+        /// Logical not of `bool`, bitwise not of `i32`
+        Not => "not",
+        Lowp => "lowp",
+        Mediump => "mediump",
+        Highp => "highp",
+    }
+}
+
+impl UnaryOp {
+    /// Marker of values held at `precision`
+    pub fn marker(precision: Precision) -> UnaryOp {
+        match precision {
+            Precision::Low => UnaryOp::Lowp,
+            Precision::Medium => UnaryOp::Mediump,
+            Precision::High => UnaryOp::Highp,
+        }
+    }
+
+    /// Precision the operation marks, if it is a marker
+    pub fn precision(self) -> Option<Precision> {
+        match self {
+            UnaryOp::Lowp => Some(Precision::Low),
+            UnaryOp::Mediump => Some(Precision::Medium),
+            UnaryOp::Highp => Some(Precision::High),
+            UnaryOp::Neg | UnaryOp::Not => None,
+        }
+    }
+}
+
+ops! {
+    /// Operation on two values of the same type, lane-wise on vectors
+    ///
+    /// Division and remainder are signed unless prefixed with `u`, `shr` is
+    /// an arithmetic shift, and `mulh` gives the high word of the signed
+    /// 64-bit product. `and`, `or` and `xor` apply to `bool`
+    /// and `i32`; the others to `i32` or `f32` as their name suggests.
+    BinaryOp {
+        Add => "add",
+        Sub => "sub",
+        Mul => "mul",
+        MulH => "mulh",
+        Div => "div",
+        UDiv => "udiv",
+        Rem => "rem",
+        URem => "urem",
+        And => "and",
+        Or => "or",
+        Xor => "xor",
+        Shl => "shl",
+        Shr => "shr",
+        UShr => "ushr",
+    }
+}
+
+ops! {
+    /// Comparison of two values of the same type, giving a `bool` per lane
+    CompareOp {
+        Eq => "eq",
+        Ne => "ne",
+        Lt => "lt",
+        Le => "le",
+        Gt => "gt",
+        Ge => "ge",
+        ULt => "ult",
+        ULe => "ule",
+        UGt => "ugt",
+        UGe => "uge",
+    }
+}
+
+ops! {
+    /// Conversion between integers and floats, lane-wise on vectors
+    ///
+    /// Float to integer conversions truncate toward zero and saturate.
+    ConvertOp {
+        SIntToFloat => "sitofp",
+        UIntToFloat => "uitofp",
+        FloatToSInt => "fptosi",
+        FloatToUInt => "fptoui",
+    }
+}
+
+impl BinaryOp {
+    /// Whether the operation applies to values of `scalar` type
+    pub fn accepts(self, scalar: ScalarType) -> bool {
+        use BinaryOp::*;
+        match self {
+            Add | Sub | Mul | Div => scalar != ScalarType::Bool,
+            MulH | UDiv | Rem | URem | Shl | Shr | UShr => scalar == ScalarType::I32,
+            And | Or | Xor => scalar != ScalarType::F32,
+        }
+    }
+}
+
+impl CompareOp {
+    /// Whether the comparison applies to values of `scalar` type
+    pub fn accepts(self, scalar: ScalarType) -> bool {
+        use CompareOp::*;
+        match self {
+            Eq | Ne => true,
+            Lt | Le | Gt | Ge => scalar != ScalarType::Bool,
+            ULt | ULe | UGt | UGe => scalar == ScalarType::I32,
+        }
+    }
+}
+
+impl ConvertOp {
+    /// Scalar types of the operand and the result
+    pub fn types(self) -> (ScalarType, ScalarType) {
+        match self {
+            ConvertOp::SIntToFloat | ConvertOp::UIntToFloat => (ScalarType::I32, ScalarType::F32),
+            ConvertOp::FloatToSInt | ConvertOp::FloatToUInt => (ScalarType::F32, ScalarType::I32),
+        }
+    }
+}
+
+/// Branch target with the arguments for its parameters
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockCall {
+    pub block: Block,
+    pub args: Vec<Value>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InstKind {
+    Const(Constant),
+    Unary(UnaryOp, Value),
+    Binary(BinaryOp, Value, Value),
+    Compare(CompareOp, Value, Value),
+    Convert(ConvertOp, Value),
+    /// `cond ? a : b`, lane-wise when `cond` is a vector
+    Select(Value, Value, Value),
+    /// Vector with every lane set to a scalar
+    Splat(Value),
+    /// Vector built from one scalar per lane
+    Vector(Vec<Value>),
+    /// One lane of a vector
+    Extract(Value, u8),
+    /// Vector with one lane replaced
+    Insert(Value, u8, Value),
+    Builtin(Builtin, Vec<Value>),
+    Call(FuncRef, Vec<Value>),
+    /// Address of a stack slot
+    StackAddr(Slot),
+    /// Address of a global
+    GlobalAddr(GlobalRef),
+    /// Load from an address plus a byte offset
+    Load(Value, i32),
+    /// Store a value to an address plus a byte offset
+    Store(Value, Value, i32),
+    Jump(BlockCall),
+    /// Branch to the first target if the condition is true
+    Branch(Value, BlockCall, BlockCall),
+    Return(Vec<Value>),
+    /// End the shader invocation without output
+    Discard,
+}
+
+impl InstKind {
+    pub fn is_terminator(&self) -> bool {
+        matches!(
+            self,
+            InstKind::Jump(_) | InstKind::Branch(..) | InstKind::Return(_) | InstKind::Discard
+        )
+    }
+
+    /// Whether the instruction does more than compute its results, so it
+    /// must be kept even if they are unused
+    pub fn has_side_effects(&self) -> bool {
+        matches!(self, InstKind::Call(..) | InstKind::Store(..)) || self.is_terminator()
+    }
+
+    /// Whether the results depend on the operands alone, so the instruction
+    /// can be removed, merged with an identical one or moved
+    pub fn is_pure(&self) -> bool {
+        !self.has_side_effects() && !matches!(self, InstKind::Load(..))
+    }
+
+    /// Branch targets of a terminator
+    pub fn block_calls(&self) -> Vec<&BlockCall> {
+        match self {
+            InstKind::Jump(call) => vec![call],
+            InstKind::Branch(_, then, otherwise) => vec![then, otherwise],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn block_calls_mut(&mut self) -> Vec<&mut BlockCall> {
+        match self {
+            InstKind::Jump(call) => vec![call],
+            InstKind::Branch(_, then, otherwise) => vec![then, otherwise],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Blocks the instruction may branch to
+    pub fn successors(&self) -> Vec<Block> {
+        match self {
+            InstKind::Jump(call) => vec![call.block],
+            InstKind::Branch(_, then, otherwise) => vec![then.block, otherwise.block],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Every value the instruction reads, including branch arguments
+    pub fn operands(&self) -> Vec<Value> {
+        let mut operands = Vec::new();
+        self.visit_operands(&mut |v| operands.push(v));
+        operands
+    }
+
+    fn visit_operands(&self, f: &mut impl FnMut(Value)) {
+        match self {
+            InstKind::Const(_)
+            | InstKind::StackAddr(_)
+            | InstKind::GlobalAddr(_)
+            | InstKind::Discard => {}
+            InstKind::Unary(_, a)
+            | InstKind::Convert(_, a)
+            | InstKind::Splat(a)
+            | InstKind::Extract(a, _)
+            | InstKind::Load(a, _) => f(*a),
+            InstKind::Binary(_, a, b)
+            | InstKind::Compare(_, a, b)
+            | InstKind::Insert(a, _, b)
+            | InstKind::Store(a, b, _) => {
+                f(*a);
+                f(*b);
+            }
+            InstKind::Select(a, b, c) => {
+                f(*a);
+                f(*b);
+                f(*c);
+            }
+            InstKind::Vector(args)
+            | InstKind::Builtin(_, args)
+            | InstKind::Call(_, args)
+            | InstKind::Return(args) => args.iter().copied().for_each(f),
+            InstKind::Jump(call) => call.args.iter().copied().for_each(f),
+            InstKind::Branch(cond, then, otherwise) => {
+                f(*cond);
+                then.args.iter().chain(&otherwise.args).copied().for_each(f);
+            }
+        }
+    }
+
+    /// Apply `f` to every value the instruction reads
+    pub fn map_operands(&mut self, mut f: impl FnMut(Value) -> Value) {
+        let mut map = |v: &mut Value| *v = f(*v);
+        match self {
+            InstKind::Const(_)
+            | InstKind::StackAddr(_)
+            | InstKind::GlobalAddr(_)
+            | InstKind::Discard => {}
+            InstKind::Unary(_, a)
+            | InstKind::Convert(_, a)
+            | InstKind::Splat(a)
+            | InstKind::Extract(a, _)
+            | InstKind::Load(a, _) => map(a),
+            InstKind::Binary(_, a, b)
+            | InstKind::Compare(_, a, b)
+            | InstKind::Insert(a, _, b)
+            | InstKind::Store(a, b, _) => {
+                map(a);
+                map(b);
+            }
+            InstKind::Select(a, b, c) => {
+                map(a);
+                map(b);
+                map(c);
+            }
+            InstKind::Vector(args)
+            | InstKind::Builtin(_, args)
+            | InstKind::Call(_, args)
+            | InstKind::Return(args) => args.iter_mut().for_each(map),
+            InstKind::Jump(call) => call.args.iter_mut().for_each(map),
+            InstKind::Branch(cond, then, otherwise) => {
+                map(cond);
+                then.args
+                    .iter_mut()
+                    .chain(&mut otherwise.args)
+                    .for_each(map);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstData {
+    pub kind: InstKind,
+    pub results: Vec<Value>,
+}
+
+/// Where a value comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueDef {
+    /// Parameter of a block, by position
+    Param(Block, u32),
+    /// Result of an instruction, by position
+    Result(Inst, u32),
+    /// No longer defined, such as a removed block parameter
+    Detached,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValueData {
+    pub ty: Type,
+    pub def: ValueDef,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockData {
+    pub params: Vec<Value>,
+    pub insts: Vec<Inst>,
+}
+
+/// Stack memory reserved for the duration of a call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlotData {
+    pub size: u32,
+    pub align: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    pub returns: Vec<Type>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub signature: Signature,
+    pub slots: Vec<SlotData>,
+    /// Every block created, indexed by [`Block`]
+    pub blocks: Vec<BlockData>,
+    /// Blocks of the body in order, starting with the entry block
+    ///
+    /// Empty for a declaration without a body.
+    pub layout: Vec<Block>,
+    pub insts: Vec<InstData>,
+    pub values: Vec<ValueData>,
+}
+
+impl Function {
+    pub fn new(name: impl Into<String>, signature: Signature) -> Function {
+        Function {
+            name: name.into(),
+            signature,
+            slots: Vec::new(),
+            blocks: Vec::new(),
+            layout: Vec::new(),
+            insts: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    pub fn is_declaration(&self) -> bool {
+        self.layout.is_empty()
+    }
+
+    pub fn entry(&self) -> Option<Block> {
+        self.layout.first().copied()
+    }
+
+    pub fn block(&self, block: Block) -> &BlockData {
+        &self.blocks[block.0 as usize]
+    }
+
+    pub fn block_mut(&mut self, block: Block) -> &mut BlockData {
+        &mut self.blocks[block.0 as usize]
+    }
+
+    pub fn inst(&self, inst: Inst) -> &InstData {
+        &self.insts[inst.0 as usize]
+    }
+
+    pub fn inst_mut(&mut self, inst: Inst) -> &mut InstData {
+        &mut self.insts[inst.0 as usize]
+    }
+
+    pub fn value(&self, value: Value) -> &ValueData {
+        &self.values[value.0 as usize]
+    }
+
+    pub fn value_type(&self, value: Value) -> Type {
+        self.value(value).ty
+    }
+
+    /// Number of instructions in the body
+    pub fn inst_count(&self) -> usize {
+        self.layout.iter().map(|&b| self.block(b).insts.len()).sum()
+    }
+
+    /// Replace every use in the body of a key of `map` by the value it maps
+    /// to, following chains of replacements
+    pub fn replace_uses(&mut self, map: &BTreeMap<Value, Value>) {
+        if map.is_empty() {
+            return;
+        }
+        let resolve = |mut value| {
+            while let Some(&to) = map.get(&value) {
+                value = to;
+            }
+            value
+        };
+        for &block in &self.layout {
+            for &inst in &self.blocks[block.0 as usize].insts {
+                self.insts[inst.0 as usize].kind.map_operands(resolve);
+            }
+        }
+    }
+
+    /// Mark the results of an instruction removed from the body as no
+    /// longer defined
+    pub fn detach_results(&mut self, inst: Inst) {
+        for &result in &self.insts[inst.0 as usize].results {
+            self.values[result.0 as usize].def = ValueDef::Detached;
+        }
+    }
+
+    /// Last instruction of `block`, if it is a terminator
+    pub fn terminator(&self, block: Block) -> Option<Inst> {
+        let last = *self.block(block).insts.last()?;
+        self.inst(last).kind.is_terminator().then_some(last)
+    }
+
+    pub fn successors(&self, block: Block) -> Vec<Block> {
+        self.terminator(block)
+            .map(|inst| self.inst(inst).kind.successors())
+            .unwrap_or_default()
+    }
+
+    pub fn new_block(&mut self) -> Block {
+        self.blocks.push(BlockData::default());
+        Block(self.blocks.len() as u32 - 1)
+    }
+
+    pub fn new_value(&mut self, ty: Type, def: ValueDef) -> Value {
+        self.values.push(ValueData { ty, def });
+        Value(self.values.len() as u32 - 1)
+    }
+
+    pub fn new_slot(&mut self, size: u32, align: u32) -> Slot {
+        self.slots.push(SlotData { size, align });
+        Slot(self.slots.len() as u32 - 1)
+    }
+
+    pub fn append_block_param(&mut self, block: Block, ty: Type) -> Value {
+        let index = self.block(block).params.len() as u32;
+        let value = self.new_value(ty, ValueDef::Param(block, index));
+        self.block_mut(block).params.push(value);
+        value
+    }
+
+    /// Create an instruction with results of the given types, outside any block
+    pub fn new_inst(&mut self, kind: InstKind, result_types: &[Type]) -> Inst {
+        let inst = Inst(self.insts.len() as u32);
+        let results = result_types
+            .iter()
+            .enumerate()
+            .map(|(i, &ty)| self.new_value(ty, ValueDef::Result(inst, i as u32)))
+            .collect();
+        self.insts.push(InstData { kind, results });
+        inst
+    }
+}
+
+/// Memory shared by every function of a module
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GlobalData {
+    pub name: String,
+    pub size: u32,
+    pub align: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Module {
+    pub globals: Vec<GlobalData>,
+    pub functions: Vec<Function>,
+}
+
+impl Module {
+    pub fn function(&self, func: FuncRef) -> &Function {
+        &self.functions[func.0 as usize]
+    }
+
+    pub fn function_mut(&mut self, func: FuncRef) -> &mut Function {
+        &mut self.functions[func.0 as usize]
+    }
+
+    pub fn global(&self, global: GlobalRef) -> &GlobalData {
+        &self.globals[global.0 as usize]
+    }
+
+    pub fn find_function(&self, name: &str) -> Option<FuncRef> {
+        let i = self.functions.iter().position(|f| f.name == name)?;
+        Some(FuncRef(i as u32))
+    }
+
+    pub fn find_global(&self, name: &str) -> Option<GlobalRef> {
+        let i = self.globals.iter().position(|g| g.name == name)?;
+        Some(GlobalRef(i as u32))
+    }
+
+    pub fn add_function(&mut self, function: Function) -> FuncRef {
+        self.functions.push(function);
+        FuncRef(self.functions.len() as u32 - 1)
+    }
+
+    pub fn add_global(&mut self, name: impl Into<String>, size: u32, align: u32) -> GlobalRef {
+        self.globals.push(GlobalData {
+            name: name.into(),
+            size,
+            align,
+        });
+        GlobalRef(self.globals.len() as u32 - 1)
+    }
+}
+
+impl fmt::Display for ScalarType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScalarType::Bool => "bool",
+            ScalarType::I32 => "i32",
+            ScalarType::F32 => "f32",
+        })
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_vector() {
+            write!(f, "{}x{}", self.scalar, self.lanes)
+        } else {
+            write!(f, "{}", self.scalar)
+        }
+    }
+}
@@ -0,0 +1,599 @@
+//! Parser for the textual form of the IR
+//!
+//! [`parse`] reads what the printer writes: printing a parsed module gives
+//! back the text it was parsed from. Values, blocks and slots keep the
+//! numbers written in the text, and functions and globals may be referred
+//! to before the line declaring them. Blank lines and comments from `;` to
+//! the end of a line are ignored.
+
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use core::{fmt, str::FromStr};
+
+use super::{
+    BinaryOp, Block, BlockCall, Builtin, CompareOp, Constant, ConvertOp, FuncRef, Function,
+    GlobalRef, Inst, InstData, InstKind, Module, ScalarType, Signature, Slot, SlotData, Type,
+    UnaryOp, Value, ValueData, ValueDef,
+};
+
+/// A line that could not be parsed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// Line number, starting at 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+type Result<T> = core::result::Result<T, ParseError>;
+
+/// Parse a module from its textual form
+pub fn parse(text: &str) -> Result<Module> {
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split(';').next().unwrap().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+
+    let mut parser = Parser {
+        functions: BTreeMap::new(),
+        globals: BTreeMap::new(),
+    };
+    parser.declare(&lines)?;
+
+    let mut module = Module::default();
+    let mut lines = lines.into_iter();
+    while let Some((number, line)) = lines.next() {
+        let mut cursor = Cursor::new(line, number);
+        if cursor.eat("global") {
+            let name = cursor.name()?;
+            cursor.expect(":")?;
+            cursor.expect("size")?;
+            let size = cursor.number()?;
+            cursor.expect(",")?;
+            cursor.expect("align")?;
+            let align = cursor.number()?;
+            cursor.end()?;
+            module.add_global(name, size, align);
+        } else if cursor.eat("function") {
+            let name = cursor.name()?;
+            let signature = cursor.signature()?;
+            let mut func = Function::new(name, signature);
+            if cursor.eat("{") {
+                cursor.end()?;
+                FunctionParser::new(&parser, &mut func).body(&mut lines, number)?;
+            } else {
+                cursor.end()?;
+            }
+            module.add_function(func);
+        } else {
+            return Err(cursor.error("expected `global` or `function`"));
+        }
+    }
+    Ok(module)
+}
+
+impl FromStr for Module {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Module> {
+        parse(text)
+    }
+}
+
+/// Names of the functions and globals of the module being parsed
+struct Parser<'t> {
+    functions: BTreeMap<&'t str, FuncRef>,
+    globals: BTreeMap<&'t str, GlobalRef>,
+}
+
+impl<'t> Parser<'t> {
+    /// Number the functions and globals in order before parsing bodies, so
+    /// they can be referred to before their declaration
+    fn declare(&mut self, lines: &[(usize, &'t str)]) -> Result<()> {
+        for &(number, line) in lines {
+            let mut cursor = Cursor::new(line, number);
+            if cursor.eat("global") {
+                let name = cursor.name()?;
+                let id = GlobalRef(self.globals.len() as u32);
+                if self.globals.insert(name, id).is_some() {
+                    return Err(cursor.error(format!("global `@{}` is declared twice", name)));
+                }
+            } else if cursor.eat("function") {
+                let name = cursor.name()?;
+                let id = FuncRef(self.functions.len() as u32);
+                if self.functions.insert(name, id).is_some() {
+                    return Err(cursor.error(format!("function `@{}` is declared twice", name)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds one function from the lines of its body
+struct FunctionParser<'p, 't, 'f> {
+    parser: &'p Parser<'t>,
+    func: &'f mut Function,
+    /// Values by number, as they are defined
+    values: Vec<Option<ValueData>>,
+    /// Line of each instruction, to report undefined operands
+    inst_lines: Vec<usize>,
+}
+
+impl<'p, 't, 'f> FunctionParser<'p, 't, 'f> {
+    fn new(parser: &'p Parser<'t>, func: &'f mut Function) -> FunctionParser<'p, 't, 'f> {
+        FunctionParser {
+            parser,
+            func,
+            values: Vec::new(),
+            inst_lines: Vec::new(),
+        }
+    }
+
+    /// Parse lines up to the closing `}`
+    fn body(
+        mut self,
+        lines: &mut impl Iterator<Item = (usize, &'t str)>,
+        header: usize,
+    ) -> Result<()> {
+        let mut current = None;
+        for (number, line) in lines {
+            let mut cursor = Cursor::new(line, number);
+            if cursor.eat("}") {
+                cursor.end()?;
+                if self.func.layout.is_empty() {
+                    return Err(cursor.error("function body has no blocks"));
+                }
+                return self.finish();
+            } else if line.starts_with("ss") {
+                self.slot(cursor)?;
+            } else if line.starts_with("block") && line.ends_with(':') {
+                current = Some(self.block(cursor)?);
+            } else {
+                let Some(block) = current else {
+                    return Err(cursor.error("instruction outside a block"));
+                };
+                let inst = self.inst(cursor)?;
+                self.func.block_mut(block).insts.push(inst);
+            }
+        }
+        Err(ParseError {
+            line: header,
+            message: "function body is not closed".into(),
+        })
+    }
+
+    fn slot(&mut self, mut cursor: Cursor) -> Result<()> {
+        cursor.expect("ss")?;
+        let n: u32 = cursor.number()?;
+        if n as usize != self.func.slots.len() {
+            return Err(cursor.error(format!("expected `ss{}`", self.func.slots.len())));
+        }
+        cursor.expect("=")?;
+        cursor.expect("slot")?;
+        let size = cursor.number()?;
+        cursor.expect(",")?;
+        cursor.expect("align")?;
+        let align = cursor.number()?;
+        cursor.end()?;
+        self.func.slots.push(SlotData { size, align });
+        Ok(())
+    }
+
+    fn block(&mut self, mut cursor: Cursor) -> Result<Block> {
+        let block = cursor.block()?;
+        while self.func.blocks.len() <= block.0 as usize {
+            self.func.new_block();
+        }
+        if self.func.layout.contains(&block) {
+            return Err(cursor.error(format!("{} is defined twice", block)));
+        }
+        self.func.layout.push(block);
+        if cursor.eat("(") {
+            loop {
+                let (value, ty) = cursor.typed_value()?;
+                let index = self.func.block(block).params.len() as u32;
+                self.define(&cursor, value, ty, ValueDef::Param(block, index))?;
+                self.func.block_mut(block).params.push(value);
+                if !cursor.eat(",") {
+                    break;
+                }
+            }
+            cursor.expect(")")?;
+        }
+        cursor.expect(":")?;
+        cursor.end()?;
+        Ok(block)
+    }
+
+    fn define(&mut self, cursor: &Cursor, value: Value, ty: Type, def: ValueDef) -> Result<()> {
+        let i = value.0 as usize;
+        if self.values.len() <= i {
+            self.values.resize(i + 1, None);
+        }
+        if self.values[i].is_some() {
+            return Err(cursor.error(format!("{} is defined twice", value)));
+        }
+        self.values[i] = Some(ValueData { ty, def });
+        Ok(())
+    }
+
+    fn inst(&mut self, mut cursor: Cursor) -> Result<Inst> {
+        let inst = Inst(self.func.insts.len() as u32);
+        let mut results = Vec::new();
+        let mut types = Vec::new();
+        if cursor.line[cursor.pos..].contains('=') {
+            loop {
+                let (value, ty) = cursor.typed_value()?;
+                self.define(
+                    &cursor,
+                    value,
+                    ty,
+                    ValueDef::Result(inst, results.len() as u32),
+                )?;
+                results.push(value);
+                types.push(ty);
+                if !cursor.eat(",") {
+                    break;
+                }
+            }
+            cursor.expect("=")?;
+        }
+
+        let op = cursor.word();
+        let kind =
+            if let Some(op) = UnaryOp::from_name(op) {
+                InstKind::Unary(op, cursor.value()?)
+            } else if let Some(op) = BinaryOp::from_name(op) {
+                let (a, b) = cursor.value_pair()?;
+                InstKind::Binary(op, a, b)
+            } else if let Some(op) = CompareOp::from_name(op) {
+                let (a, b) = cursor.value_pair()?;
+                InstKind::Compare(op, a, b)
+            } else if let Some(op) = ConvertOp::from_name(op) {
+                InstKind::Convert(op, cursor.value()?)
+            } else {
+                match op {
+                    "const" => InstKind::Const(cursor.constant(&types)?),
+                    "select" => {
+                        let cond = cursor.value()?;
+                        cursor.expect(",")?;
+                        let (a, b) = cursor.value_pair()?;
+                        InstKind::Select(cond, a, b)
+                    }
+                    "splat" => InstKind::Splat(cursor.value()?),
+                    "vector" => InstKind::Vector(cursor.values()?),
+                    "extract" => {
+                        let vector = cursor.value()?;
+                        cursor.expect(",")?;
+                        InstKind::Extract(vector, cursor.number()?)
+                    }
+                    "insert" => {
+                        let vector = cursor.value()?;
+                        cursor.expect(",")?;
+                        let lane = cursor.number()?;
+                        cursor.expect(",")?;
+                        InstKind::Insert(vector, lane, cursor.value()?)
+                    }
+                    "builtin" => {
+                        let name = cursor.word();
+                        let builtin = Builtin::from_name(name)
+                            .ok_or_else(|| cursor.error(format!("unknown builtin `{}`", name)))?;
+                        InstKind::Builtin(builtin, cursor.arguments()?)
+                    }
+                    "call" => {
+                        let name = cursor.name()?;
+                        let callee =
+                            *self.parser.functions.get(name).ok_or_else(|| {
+                                cursor.error(format!("unknown function `@{}`", name))
+                            })?;
+                        InstKind::Call(callee, cursor.arguments()?)
+                    }
+                    "stack_addr" => {
+                        cursor.expect("ss")?;
+                        let slot = Slot(cursor.number()?);
+                        if slot.0 as usize >= self.func.slots.len() {
+                            return Err(cursor.error(format!("unknown slot `{}`", slot)));
+                        }
+                        InstKind::StackAddr(slot)
+                    }
+                    "global_addr" => {
+                        let name = cursor.name()?;
+                        let global =
+                            *self.parser.globals.get(name).ok_or_else(|| {
+                                cursor.error(format!("unknown global `@{}`", name))
+                            })?;
+                        InstKind::GlobalAddr(global)
+                    }
+                    "load" => {
+                        let addr = cursor.value()?;
+                        InstKind::Load(addr, cursor.offset()?)
+                    }
+                    "store" => {
+                        let value = cursor.value()?;
+                        cursor.expect(",")?;
+                        let addr = cursor.value()?;
+                        InstKind::Store(value, addr, cursor.offset()?)
+                    }
+                    "jump" => InstKind::Jump(cursor.block_call()?),
+                    "brif" => {
+                        let cond = cursor.value()?;
+                        cursor.expect(",")?;
+                        let then = cursor.block_call()?;
+                        cursor.expect(",")?;
+                        InstKind::Branch(cond, then, cursor.block_call()?)
+                    }
+                    "return" if cursor.at_end() => InstKind::Return(Vec::new()),
+                    "return" => InstKind::Return(cursor.values()?),
+                    "discard" => InstKind::Discard,
+                    "" => return Err(cursor.error("expected an instruction")),
+                    _ => return Err(cursor.error(format!("unknown instruction `{}`", op))),
+                }
+            };
+        cursor.end()?;
+
+        for call in kind.block_calls() {
+            while self.func.blocks.len() <= call.block.0 as usize {
+                self.func.new_block();
+            }
+        }
+        self.func.insts.push(InstData { kind, results });
+        self.inst_lines.push(cursor.number);
+        Ok(inst)
+    }
+
+    /// Check that every operand is defined and fill in the values
+    fn finish(self) -> Result<()> {
+        for (inst, &line) in self.func.insts.iter().zip(&self.inst_lines) {
+            for value in inst.kind.operands() {
+                if self
+                    .values
+                    .get(value.0 as usize)
+                    .copied()
+                    .flatten()
+                    .is_none()
+                {
+                    return Err(ParseError {
+                        line,
+                        message: format!("{} is not defined", value),
+                    });
+                }
+            }
+        }
+        // Numbers missing from the text belong to values that are no
+        // longer defined
+        self.func.values = self
+            .values
+            .into_iter()
+            .map(|value| {
+                value.unwrap_or(ValueData {
+                    ty: Type::I32,
+                    def: ValueDef::Detached,
+                })
+            })
+            .collect();
+        Ok(())
+    }
+}
+
+/// Position in a line being parsed
+struct Cursor<'t> {
+    line: &'t str,
+    pos: usize,
+    number: usize,
+}
+
+impl<'t> Cursor<'t> {
+    fn new(line: &'t str, number: usize) -> Cursor<'t> {
+        Cursor {
+            line,
+            pos: 0,
+            number,
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            line: self.number,
+            message: message.into(),
+        }
+    }
+
+    fn rest(&self) -> &'t str {
+        &self.line[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_whitespace();
+        self.pos == self.line.len()
+    }
+
+    fn end(&mut self) -> Result<()> {
+        match self.at_end() {
+            true => Ok(()),
+            false => Err(self.error(format!("unexpected `{}`", self.rest()))),
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        match self.eat(token) {
+            true => Ok(()),
+            false => Err(self.error(format!("expected `{}`", token))),
+        }
+    }
+
+    /// Take characters while `f` holds
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'t str {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn word(&mut self) -> &'t str {
+        self.take_while(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    fn number<T: FromStr>(&mut self) -> Result<T> {
+        let digits = self.take_while(|c| c.is_ascii_digit());
+        digits
+            .parse()
+            .map_err(|_| self.error(format!("expected a number at `{}`", self.rest())))
+    }
+
+    /// Name of a function or global after `@`
+    fn name(&mut self) -> Result<&'t str> {
+        self.expect("@")?;
+        let name = self.take_while(|c| !c.is_whitespace() && !"(),:".contains(c));
+        match name.is_empty() {
+            true => Err(self.error("expected a name")),
+            false => Ok(name),
+        }
+    }
+
+    fn ty(&mut self) -> Result<Type> {
+        let word = self.word();
+        let (scalar, lanes) = word.split_once('x').unwrap_or((word, "1"));
+        let scalar = match scalar {
+            "bool" => ScalarType::Bool,
+            "i32" => ScalarType::I32,
+            "f32" => ScalarType::F32,
+            _ => return Err(self.error(format!("unknown type `{}`", word))),
+        };
+        match lanes.parse() {
+            Ok(lanes @ 1..=4) => Ok(Type { scalar, lanes }),
+            _ => Err(self.error(format!("unknown type `{}`", word))),
+        }
+    }
+
+    fn types(&mut self) -> Result<Vec<Type>> {
+        let mut types = Vec::new();
+        loop {
+            types.push(self.ty()?);
+            if !self.eat(",") {
+                return Ok(types);
+            }
+        }
+    }
+
+    /// `(params) -> returns`, the returns being optional
+    fn signature(&mut self) -> Result<Signature> {
+        self.expect("(")?;
+        let params = match self.eat(")") {
+            true => Vec::new(),
+            false => {
+                let params = self.types()?;
+                self.expect(")")?;
+                params
+            }
+        };
+        let returns = match self.eat("->") {
+            true => self.types()?,
+            false => Vec::new(),
+        };
+        Ok(Signature { params, returns })
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        self.expect("v")?;
+        Ok(Value(self.number()?))
+    }
+
+    fn value_pair(&mut self) -> Result<(Value, Value)> {
+        let a = self.value()?;
+        self.expect(",")?;
+        Ok((a, self.value()?))
+    }
+
+    fn values(&mut self) -> Result<Vec<Value>> {
+        let mut values = Vec::new();
+        loop {
+            values.push(self.value()?);
+            if !self.eat(",") {
+                return Ok(values);
+            }
+        }
+    }
+
+    /// `vN: type`
+    fn typed_value(&mut self) -> Result<(Value, Type)> {
+        let value = self.value()?;
+        self.expect(":")?;
+        Ok((value, self.ty()?))
+    }
+
+    /// Parenthesized list of values, possibly empty
+    fn arguments(&mut self) -> Result<Vec<Value>> {
+        self.expect("(")?;
+        if self.eat(")") {
+            return Ok(Vec::new());
+        }
+        let values = self.values()?;
+        self.expect(")")?;
+        Ok(values)
+    }
+
+    fn block(&mut self) -> Result<Block> {
+        self.expect("block")?;
+        Ok(Block(self.number()?))
+    }
+
+    /// Target of a branch with its arguments, if any
+    fn block_call(&mut self) -> Result<BlockCall> {
+        let block = self.block()?;
+        self.skip_whitespace();
+        let args = match self.rest().starts_with('(') {
+            true => self.arguments()?,
+            false => Vec::new(),
+        };
+        Ok(BlockCall { block, args })
+    }
+
+    /// Signed offset after an address, or 0 without one
+    fn offset(&mut self) -> Result<i32> {
+        if self.eat("+") {
+            self.number()
+        } else if self.eat("-") {
+            Ok(-self.number::<i32>()?)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Constant for an instruction with the given result types
+    fn constant(&mut self, types: &[Type]) -> Result<Constant> {
+        let [ty] = types else {
+            return Err(self.error("`const` has one result"));
+        };
+        let text = self.take_while(|c| !c.is_whitespace());
+        let constant = match ty.scalar {
+            _ if ty.is_vector() => None,
+            ScalarType::Bool => text.parse().ok().map(Constant::Bool),
+            ScalarType::I32 => text.parse().ok().map(Constant::I32),
+            ScalarType::F32 => text.parse().ok().map(Constant::F32),
+        };
+        constant.ok_or_else(|| self.error(format!("`{}` is not a constant of type {}", text, ty)))
+    }
+}
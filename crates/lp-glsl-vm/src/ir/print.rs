@@ -0,0 +1,202 @@
+//! Textual form of the IR
+//!
+//! ```text
+//! global @gl_FragColor: size 16, align 4
+//!
+//! function @scale(f32x2, f32) -> f32x2 {
+//! block0(v0: f32x2, v1: f32):
+//!     v2: f32x2 = splat v1
+//!     v3: f32x2 = mul v0, v2
+//!     return v3
+//! }
+//! ```
+//!
+//! Values, blocks and slots keep the numbers they have in the function, so
+//! the text refers to them the same way as the data structures do.
+
+use core::fmt::{self, Write};
+
+use super::{Block, BlockCall, Constant, Function, InstData, InstKind, Module, Slot, Value};
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block{}", self.0)
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ss{}", self.0)
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for global in &self.globals {
+            writeln!(
+                f,
+                "global @{}: size {}, align {}",
+                global.name, global.size, global.align
+            )?;
+        }
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 || !self.globals.is_empty() {
+                writeln!(f)?;
+            }
+            write_function(f, self, function)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_function(f: &mut fmt::Formatter<'_>, module: &Module, func: &Function) -> fmt::Result {
+    write!(f, "function @{}(", func.name)?;
+    write_list(f, &func.signature.params)?;
+    f.write_char(')')?;
+    if !func.signature.returns.is_empty() {
+        f.write_str(" -> ")?;
+        write_list(f, &func.signature.returns)?;
+    }
+    if func.is_declaration() {
+        return writeln!(f);
+    }
+    writeln!(f, " {{")?;
+    for (i, slot) in func.slots.iter().enumerate() {
+        writeln!(
+            f,
+            "    {} = slot {}, align {}",
+            Slot(i as u32),
+            slot.size,
+            slot.align
+        )?;
+    }
+    for &block in &func.layout {
+        write!(f, "{}", block)?;
+        let params = &func.block(block).params;
+        if !params.is_empty() {
+            f.write_char('(')?;
+            for (i, &param) in params.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}: {}", param, func.value_type(param))?;
+            }
+            f.write_char(')')?;
+        }
+        writeln!(f, ":")?;
+        for &inst in &func.block(block).insts {
+            f.write_str("    ")?;
+            write_inst(f, module, func, func.inst(inst))?;
+            writeln!(f)?;
+        }
+    }
+    writeln!(f, "}}")
+}
+
+fn write_inst(
+    f: &mut fmt::Formatter<'_>,
+    module: &Module,
+    func: &Function,
+    inst: &InstData,
+) -> fmt::Result {
+    for (i, &result) in inst.results.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}: {}", result, func.value_type(result))?;
+    }
+    if !inst.results.is_empty() {
+        f.write_str(" = ")?;
+    }
+    match &inst.kind {
+        InstKind::Const(constant) => match constant {
+            Constant::Bool(b) => write!(f, "const {}", b),
+            Constant::I32(i) => write!(f, "const {}", i),
+            // Debug formatting is the shortest text that parses back to
+            // the same value
+            Constant::F32(x) => write!(f, "const {:?}", x),
+        },
+        InstKind::Unary(op, a) => write!(f, "{} {}", op.name(), a),
+        InstKind::Binary(op, a, b) => write!(f, "{} {}, {}", op.name(), a, b),
+        InstKind::Compare(op, a, b) => write!(f, "{} {}, {}", op.name(), a, b),
+        InstKind::Convert(op, a) => write!(f, "{} {}", op.name(), a),
+        InstKind::Select(c, a, b) => write!(f, "select {}, {}, {}", c, a, b),
+        InstKind::Splat(a) => write!(f, "splat {}", a),
+        InstKind::Vector(lanes) => {
+            f.write_str("vector ")?;
+            write_list(f, lanes)
+        }
+        InstKind::Extract(v, lane) => write!(f, "extract {}, {}", v, lane),
+        InstKind::Insert(v, lane, x) => write!(f, "insert {}, {}, {}", v, lane, x),
+        InstKind::Builtin(builtin, args) => {
+            write!(f, "builtin {}(", builtin.name())?;
+            write_list(f, args)?;
+            f.write_char(')')
+        }
+        InstKind::Call(callee, args) => {
+            write!(f, "call @{}(", module.function(*callee).name)?;
+            write_list(f, args)?;
+            f.write_char(')')
+        }
+        InstKind::StackAddr(slot) => write!(f, "stack_addr {}", slot),
+        InstKind::GlobalAddr(global) => write!(f, "global_addr @{}", module.global(*global).name),
+        InstKind::Load(addr, offset) => {
+            write!(f, "load {}", addr)?;
+            write_offset(f, *offset)
+        }
+        InstKind::Store(value, addr, offset) => {
+            write!(f, "store {}, {}", value, addr)?;
+            write_offset(f, *offset)
+        }
+        InstKind::Jump(call) => {
+            f.write_str("jump ")?;
+            write_block_call(f, call)
+        }
+        InstKind::Branch(cond, then, otherwise) => {
+            write!(f, "brif {}, ", cond)?;
+            write_block_call(f, then)?;
+            f.write_str(", ")?;
+            write_block_call(f, otherwise)
+        }
+        InstKind::Return(values) if values.is_empty() => f.write_str("return"),
+        InstKind::Return(values) => {
+            f.write_str("return ")?;
+            write_list(f, values)
+        }
+        InstKind::Discard => f.write_str("discard"),
+    }
+}
+
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+fn write_offset(f: &mut fmt::Formatter<'_>, offset: i32) -> fmt::Result {
+    match offset {
+        0 => Ok(()),
+        _ if offset > 0 => write!(f, "+{}", offset),
+        _ => write!(f, "{}", offset),
+    }
+}
+
+fn write_block_call(f: &mut fmt::Formatter<'_>, call: &BlockCall) -> fmt::Result {
+    write!(f, "{}", call.block)?;
+    if !call.args.is_empty() {
+        f.write_char('(')?;
+        write_list(f, &call.args)?;
+        f.write_char(')')?;
+    }
+    Ok(())
+}
@@ -0,0 +1,1303 @@
+//! Translation of lowered HIR into the IR
+//!
+//! Scalar and vector locals become SSA variables. Structs, arrays and
+//! matrices live in memory and are handled through their address: locals
+//! of those types get a stack slot, and expressions of those types evaluate
+//! to the address of a slot or global holding the value. Every global is
+//! stored in memory, the uniforms together in the [`UNIFORMS`] global at
+//! the offsets given by [`Reflection`]. `main` assigns the initializers of
+//! the other globals before running its body.
+//!
+//! Scalar and vector arguments are passed as values, and the final values
+//! of `out` and `inout` parameters are returned after the return value.
+//! Aggregate arguments are passed as the address of a copy made by the
+//! caller, which copies `out` and `inout` ones back after the call. An
+//! aggregate return value is written to an address passed before the other
+//! arguments.
+
+use alloc::{boxed::Box, collections::BTreeMap, format, string::String, vec, vec::Vec};
+
+use super::{
+    BinaryOp, Block, Builtin, CompareOp, Constant, ConvertOp, FuncRef, Function, FunctionBuilder,
+    GlobalRef, Module, ScalarType, Signature, Type, UnaryOp, Value, Variable,
+};
+use crate::{
+    layout::{array_stride, Layout, StructLayout, WORD_SIZE},
+    reflect::Reflection,
+    sema::{
+        self,
+        hir::{
+            self, Expr, ExprKind, Literal, ParamQualifier, Precision, Stmt, Storage, VarRef, MAIN,
+        },
+    },
+};
+
+/// Name of the global holding every uniform
+pub const UNIFORMS: &str = "lp.uniforms";
+
+/// Translate every function of a lowered module
+///
+/// Overloaded functions after the first of their name get a `.1`, `.2`, ...
+/// suffix, so each function of the result has a unique name.
+pub fn translate(module: &hir::Module) -> Module {
+    let mut ir = Module::default();
+
+    let reflection = Reflection::of(module);
+    let uniforms = (!reflection.uniforms.is_empty())
+        .then(|| ir.add_global(UNIFORMS, reflection.size, reflection.align));
+    let globals = module
+        .globals
+        .iter()
+        .enumerate()
+        .map(|(i, global)| {
+            if global.storage == Storage::Uniform {
+                let uniform = reflection
+                    .uniforms
+                    .iter()
+                    .find(|u| u.global.0 as usize == i)
+                    .expect("uniform is reflected");
+                (uniforms.expect("uniform area"), uniform.offset as i32)
+            } else {
+                let layout = Layout::of(&global.ty);
+                (ir.add_global(&global.name, layout.size, layout.align), 0)
+            }
+        })
+        .collect();
+
+    let abis: Vec<Abi> = module.functions.iter().map(Abi::of).collect();
+    for (abi, name) in abis.iter().zip(function_names(module)) {
+        ir.add_function(Function::new(name, abi.signature.clone()));
+    }
+
+    let context = Context {
+        module,
+        abis,
+        globals,
+    };
+    for (i, function) in module.functions.iter().enumerate() {
+        if let Some(body) = &function.body {
+            Translator::new(&context, function, &mut ir.functions[i]).translate(body);
+        }
+    }
+    ir
+}
+
+/// Unique names for the functions of a module
+fn function_names(module: &hir::Module) -> Vec<String> {
+    let mut seen: BTreeMap<&str, u32> = BTreeMap::new();
+    module
+        .functions
+        .iter()
+        .map(|f| {
+            let count = seen.entry(&f.name).or_insert(0);
+            *count += 1;
+            match *count {
+                1 => f.name.clone(),
+                n => format!("{}.{}", f.name, n - 1),
+            }
+        })
+        .collect()
+}
+
+/// IR type of values of GLSL type `ty`, or `None` for types kept in memory
+fn value_type(ty: &sema::Type) -> Option<Type> {
+    match *ty {
+        sema::Type::Scalar(s) => Some(Type::scalar(scalar_type(s))),
+        sema::Type::Vector(s, n) => Some(Type::vector(scalar_type(s), n)),
+        _ => None,
+    }
+}
+
+fn scalar_type(scalar: sema::ScalarType) -> ScalarType {
+    match scalar {
+        sema::ScalarType::Bool => ScalarType::Bool,
+        sema::ScalarType::Int | sema::ScalarType::UInt => ScalarType::I32,
+        sema::ScalarType::Float => ScalarType::F32,
+    }
+}
+
+fn in_memory(ty: &sema::Type) -> bool {
+    matches!(
+        ty,
+        sema::Type::Struct(_) | sema::Type::Array(..) | sema::Type::Matrix(_)
+    )
+}
+
+fn constant(literal: &Literal) -> Constant {
+    match *literal {
+        Literal::Bool(b) => Constant::Bool(b),
+        Literal::Int(i) => Constant::I32(i),
+        Literal::UInt(u) => Constant::I32(u as i32),
+        Literal::Float(f) => Constant::F32(f),
+    }
+}
+
+/// How the parameters and results of a function are passed
+struct Abi {
+    signature: Signature,
+    /// Whether the return value is written to an address passed first
+    returns_in_memory: bool,
+}
+
+impl Abi {
+    fn of(function: &hir::Function) -> Abi {
+        let mut signature = Signature::default();
+        let returns_in_memory = in_memory(&function.return_type);
+        if returns_in_memory {
+            signature.params.push(Type::I32);
+        } else if let Some(ty) = value_type(&function.return_type) {
+            signature.returns.push(ty);
+        }
+        for param in &function.params {
+            match value_type(&param.ty) {
+                None => signature.params.push(Type::I32),
+                Some(ty) => {
+                    if param.qualifier != ParamQualifier::Out {
+                        signature.params.push(ty);
+                    }
+                    if param.qualifier != ParamQualifier::In {
+                        signature.returns.push(ty);
+                    }
+                }
+            }
+        }
+        Abi {
+            signature,
+            returns_in_memory,
+        }
+    }
+}
+
+/// What every function translation needs to know about the module
+struct Context<'a> {
+    module: &'a hir::Module,
+    abis: Vec<Abi>,
+    /// IR global holding each global and the offset within it
+    globals: Vec<(GlobalRef, i32)>,
+}
+
+/// Where a local lives
+#[derive(Clone, Copy)]
+enum LocalPlace {
+    Variable(Variable),
+    Slot(super::Slot),
+    /// Aggregate parameter, at the address it was passed
+    Address(Value),
+}
+
+/// Address of memory, as a value and a constant offset
+#[derive(Clone, Copy)]
+struct Address {
+    base: Value,
+    offset: i32,
+}
+
+impl Address {
+    fn offset_by(self, offset: u32) -> Address {
+        Address {
+            base: self.base,
+            offset: self.offset + offset as i32,
+        }
+    }
+}
+
+/// Assignable location of a scalar or vector
+enum Place {
+    Variable(Variable),
+    Memory(Address, Type),
+    /// One lane of a vector place
+    Lane(Box<Place>, Lane),
+    /// Place of a float variable declared with a precision, whose values are
+    /// marked with it
+    Marked(Box<Place>, Precision),
+}
+
+#[derive(Clone, Copy)]
+enum Lane {
+    Constant(u8),
+    Dynamic(Value),
+}
+
+/// Result of an expression
+#[derive(Clone, Copy)]
+enum Operand {
+    Value(Value),
+    /// Address of a value kept in memory
+    Memory(Address),
+    Void,
+}
+
+struct Translator<'a, 'f> {
+    context: &'a Context<'a>,
+    function: &'a hir::Function,
+    abi: &'a Abi,
+    b: FunctionBuilder<'f>,
+    locals: Vec<LocalPlace>,
+    /// Where the return value goes when it is kept in memory
+    return_address: Option<Value>,
+    /// Variables of the `out` and `inout` parameters passed as values
+    outputs: Vec<Variable>,
+    /// Targets of `break` in the enclosing loops and switches
+    break_targets: Vec<Block>,
+    /// Targets of `continue` in the enclosing loops
+    continue_targets: Vec<Block>,
+}
+
+impl<'a, 'f> Translator<'a, 'f> {
+    fn new(
+        context: &'a Context<'a>,
+        function: &'a hir::Function,
+        func: &'f mut Function,
+    ) -> Translator<'a, 'f> {
+        let id = context
+            .module
+            .functions
+            .iter()
+            .position(|f| core::ptr::eq(f, function))
+            .expect("function of the module");
+        Translator {
+            context,
+            function,
+            abi: &context.abis[id],
+            b: FunctionBuilder::new(func),
+            locals: Vec::new(),
+            return_address: None,
+            outputs: Vec::new(),
+            break_targets: Vec::new(),
+            continue_targets: Vec::new(),
+        }
+    }
+
+    fn translate(mut self, body: &hir::Block) {
+        let entry = self.b.create_entry_block();
+        self.b.switch_to_block(entry);
+        self.b.seal_block(entry);
+
+        let mut params = self.b.block_params(entry).to_vec().into_iter();
+        if self.abi.returns_in_memory {
+            self.return_address = params.next();
+        }
+        let mut locals = vec![None; self.function.locals.len()];
+        for param in &self.function.params {
+            let place = match value_type(&param.ty) {
+                None => LocalPlace::Address(params.next().expect("parameter")),
+                Some(ty) => {
+                    let var = self.b.declare_var(ty);
+                    let initial = match param.qualifier {
+                        ParamQualifier::Out => self.b.zero(ty),
+                        _ => params.next().expect("parameter"),
+                    };
+                    let initial = self.mark(initial, param.precision);
+                    self.b.def_var(var, initial);
+                    if param.qualifier != ParamQualifier::In {
+                        self.outputs.push(var);
+                    }
+                    LocalPlace::Variable(var)
+                }
+            };
+            locals[param.local.0 as usize] = Some(place);
+        }
+        self.locals = locals
+            .into_iter()
+            .zip(&self.function.locals)
+            .map(|(place, local)| {
+                place.unwrap_or_else(|| match value_type(&local.ty) {
+                    Some(ty) => LocalPlace::Variable(self.b.declare_var(ty)),
+                    None => {
+                        let layout = Layout::of(&local.ty);
+                        LocalPlace::Slot(self.b.create_slot(layout.size, layout.align))
+                    }
+                })
+            })
+            .collect();
+
+        if self.function.name == MAIN {
+            self.initialize_globals();
+        }
+        self.block(body);
+        if !self.b.is_terminated() {
+            self.ret(None);
+        }
+        self.b.finish();
+    }
+
+    fn initialize_globals(&mut self) {
+        let module = self.context.module;
+        for (i, global) in module.globals.iter().enumerate() {
+            let Some(init) = &global.init else {
+                continue;
+            };
+            // Uniform defaults are stored by the host
+            if matches!(global.storage, Storage::Uniform | Storage::Builtin) {
+                continue;
+            }
+            let address = self.global_address(i);
+            self.store_expr(init, &global.ty, address);
+        }
+    }
+
+    fn block(&mut self, block: &hir::Block) {
+        for stmt in block {
+            // The rest of the block cannot be reached
+            if self.b.is_terminated() {
+                return;
+            }
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expr(expr) => {
+                self.eval(expr);
+            }
+            Stmt::Decl(local, init) => match self.locals[local.0 as usize] {
+                LocalPlace::Variable(var) => {
+                    let local = self.function.local(*local);
+                    let value = match init {
+                        Some(init) => self.value(init),
+                        None => self.b.zero(value_type(&local.ty).expect("value type")),
+                    };
+                    let value = self.mark(value, local.precision);
+                    self.b.def_var(var, value);
+                }
+                LocalPlace::Slot(slot) => {
+                    if let Some(init) = init {
+                        let address = Address {
+                            base: self.b.stack_addr(slot),
+                            offset: 0,
+                        };
+                        self.store_expr(init, &init.ty, address);
+                    }
+                }
+                LocalPlace::Address(_) => unreachable!("declaration of a parameter"),
+            },
+            Stmt::Block(block) => self.block(block),
+            Stmt::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                let cond = self.value(cond);
+                let then_block = self.b.create_block();
+                let else_block = else_branch.as_ref().map(|_| self.b.create_block());
+                let merge = self.b.create_block();
+                self.b
+                    .brif(cond, then_block, &[], else_block.unwrap_or(merge), &[]);
+                self.b.seal_block(then_block);
+                self.b.switch_to_block(then_block);
+                self.block(then_branch);
+                self.jump_unless_terminated(merge);
+                if let (Some(else_block), Some(else_branch)) = (else_block, else_branch) {
+                    self.b.seal_block(else_block);
+                    self.b.switch_to_block(else_block);
+                    self.block(else_branch);
+                    self.jump_unless_terminated(merge);
+                }
+                self.b.seal_block(merge);
+                self.b.switch_to_block(merge);
+            }
+            Stmt::Loop {
+                cond,
+                step,
+                body,
+                test_first,
+            } => self.loop_stmt(cond.as_ref(), step.as_ref(), body, *test_first),
+            Stmt::Switch { selector, cases } => self.switch(selector, cases),
+            Stmt::Break => {
+                let target = *self.break_targets.last().expect("break outside a loop");
+                self.b.jump(target, &[]);
+            }
+            Stmt::Continue => {
+                let target = *self
+                    .continue_targets
+                    .last()
+                    .expect("continue outside a loop");
+                self.b.jump(target, &[]);
+            }
+            Stmt::Return(value) => self.ret(value.as_ref()),
+            Stmt::Discard => self.b.discard(),
+        }
+    }
+
+    fn jump_unless_terminated(&mut self, block: Block) {
+        if !self.b.is_terminated() {
+            self.b.jump(block, &[]);
+        }
+    }
+
+    /// A loop as blocks: the condition, the body, the step (which
+    /// `continue` jumps to) and the exit
+    fn loop_stmt(
+        &mut self,
+        cond: Option<&Expr>,
+        step: Option<&Expr>,
+        body: &hir::Block,
+        test_first: bool,
+    ) {
+        let header = self.b.create_block();
+        let body_block = self.b.create_block();
+        let latch = self.b.create_block();
+        let exit = self.b.create_block();
+
+        if test_first {
+            self.b.jump(header, &[]);
+            self.b.switch_to_block(header);
+            self.loop_test(cond, body_block, exit);
+            self.b.seal_block(body_block);
+        } else {
+            self.b.jump(body_block, &[]);
+        }
+
+        self.b.switch_to_block(body_block);
+        self.break_targets.push(exit);
+        self.continue_targets.push(latch);
+        self.block(body);
+        self.break_targets.pop();
+        self.continue_targets.pop();
+        self.jump_unless_terminated(latch);
+
+        self.b.seal_block(latch);
+        self.b.switch_to_block(latch);
+        if let Some(step) = step {
+            self.eval(step);
+        }
+        if test_first {
+            self.b.jump(header, &[]);
+            self.b.seal_block(header);
+        } else {
+            self.loop_test(cond, body_block, exit);
+            self.b.seal_block(body_block);
+        }
+        self.b.seal_block(exit);
+        self.b.switch_to_block(exit);
+    }
+
+    /// Continue with `body` while `cond` holds, or forever without one
+    fn loop_test(&mut self, cond: Option<&Expr>, body: Block, exit: Block) {
+        match cond {
+            Some(cond) => {
+                let cond = self.value(cond);
+                self.b.brif(cond, body, &[], exit, &[]);
+            }
+            None => self.b.jump(body, &[]),
+        }
+    }
+
+    /// A switch as a chain of comparisons followed by the case bodies in
+    /// order, each falling through to the next
+    fn switch(&mut self, selector: &Expr, cases: &[hir::SwitchCase]) {
+        let selector = self.value(selector);
+        let case_blocks: Vec<Block> = cases.iter().map(|_| self.b.create_block()).collect();
+        let exit = self.b.create_block();
+
+        let mut default = exit;
+        for (case, &block) in cases.iter().zip(&case_blocks) {
+            for label in &case.labels {
+                let Some(label) = label else {
+                    default = block;
+                    continue;
+                };
+                let label = self.b.iconst(*label as i32);
+                let matches = self.b.compare(CompareOp::Eq, selector, label);
+                let next = self.b.create_block();
+                self.b.brif(matches, block, &[], next, &[]);
+                self.b.seal_block(next);
+                self.b.switch_to_block(next);
+            }
+        }
+        self.b.jump(default, &[]);
+
+        self.break_targets.push(exit);
+        for (i, case) in cases.iter().enumerate() {
+            self.b.seal_block(case_blocks[i]);
+            self.b.switch_to_block(case_blocks[i]);
+            self.block(&case.body);
+            self.jump_unless_terminated(case_blocks.get(i + 1).copied().unwrap_or(exit));
+        }
+        self.break_targets.pop();
+        self.b.seal_block(exit);
+        self.b.switch_to_block(exit);
+    }
+
+    fn ret(&mut self, value: Option<&Expr>) {
+        let mut values = Vec::new();
+        match (value, self.return_address) {
+            (Some(value), Some(base)) => {
+                let src = self.address(value);
+                self.copy(Address { base, offset: 0 }, src, &value.ty);
+            }
+            (Some(value), None) => {
+                let value = self.value(value);
+                values.push(self.mark(value, self.function.return_precision));
+            }
+            // Falling off the end of a function with a return type
+            (None, None) => {
+                if let Some(ty) = value_type(&self.function.return_type) {
+                    values.push(self.b.zero(ty));
+                }
+            }
+            (None, Some(_)) => {}
+        }
+        for var in self.outputs.clone() {
+            values.push(self.b.use_var(var));
+        }
+        self.b.ret(&values);
+    }
+
+    /// Value of a scalar or vector expression
+    fn value(&mut self, expr: &Expr) -> Value {
+        match self.eval(expr) {
+            Operand::Value(value) => value,
+            _ => unreachable!("`{}` expression is not a value", expr.ty),
+        }
+    }
+
+    /// Address of a struct, array or matrix expression
+    fn address(&mut self, expr: &Expr) -> Address {
+        match &expr.kind {
+            ExprKind::Var(VarRef::Local(local)) => match self.locals[local.0 as usize] {
+                LocalPlace::Slot(slot) => Address {
+                    base: self.b.stack_addr(slot),
+                    offset: 0,
+                },
+                LocalPlace::Address(base) => Address { base, offset: 0 },
+                LocalPlace::Variable(_) => unreachable!("local in a variable has no address"),
+            },
+            ExprKind::Var(VarRef::Global(global)) => self.global_address(global.0 as usize),
+            ExprKind::Field(..) | ExprKind::Index(..) => self.element_address(expr),
+            _ => match self.eval(expr) {
+                Operand::Memory(address) => address,
+                _ => unreachable!("`{}` expression is not in memory", expr.ty),
+            },
+        }
+    }
+
+    /// Address of a field or element of a value kept in memory
+    fn element_address(&mut self, expr: &Expr) -> Address {
+        match &expr.kind {
+            ExprKind::Field(base, field) => {
+                let sema::Type::Struct(st) = &base.ty else {
+                    unreachable!("field of a `{}`", base.ty);
+                };
+                let offset = StructLayout::of(st).offsets[*field as usize];
+                self.address(base).offset_by(offset)
+            }
+            ExprKind::Index(base, index) => {
+                let stride = match &base.ty {
+                    sema::Type::Array(element, _) => array_stride(element),
+                    sema::Type::Matrix(n) => WORD_SIZE * *n as u32,
+                    ty => unreachable!("element of a `{}` in memory", ty),
+                };
+                let address = self.address(base);
+                if let ExprKind::Literal(Literal::Int(i)) = index.kind {
+                    return address.offset_by(i as u32 * stride);
+                }
+                let index = self.value(index);
+                let stride = self.b.iconst(stride as i32);
+                let offset = self.b.binary(BinaryOp::Mul, index, stride);
+                Address {
+                    base: self.b.binary(BinaryOp::Add, address.base, offset),
+                    offset: address.offset,
+                }
+            }
+            _ => unreachable!("not an element"),
+        }
+    }
+
+    fn global_address(&mut self, global: usize) -> Address {
+        let (global, offset) = self.context.globals[global];
+        Address {
+            base: self.b.global_addr(global),
+            offset,
+        }
+    }
+
+    /// The address as a single value
+    fn address_value(&mut self, address: Address) -> Value {
+        if address.offset == 0 {
+            return address.base;
+        }
+        let offset = self.b.iconst(address.offset);
+        self.b.binary(BinaryOp::Add, address.base, offset)
+    }
+
+    /// Memory for a temporary of type `ty`
+    fn temporary(&mut self, ty: &sema::Type) -> Address {
+        let layout = Layout::of(ty);
+        let slot = self.b.create_slot(layout.size, layout.align);
+        Address {
+            base: self.b.stack_addr(slot),
+            offset: 0,
+        }
+    }
+
+    /// Copy a value of type `ty` kept in memory, one word at a time
+    fn copy(&mut self, dst: Address, src: Address, ty: &sema::Type) {
+        for offset in (0..Layout::of(ty).size).step_by(WORD_SIZE as usize) {
+            let word = self.b.load(Type::I32, src.base, src.offset + offset as i32);
+            self.b.store(word, dst.base, dst.offset + offset as i32);
+        }
+    }
+
+    /// Evaluate `expr` of type `ty` into the memory at `dst`
+    fn store_expr(&mut self, expr: &Expr, ty: &sema::Type, dst: Address) {
+        if in_memory(ty) {
+            let src = self.address(expr);
+            self.copy(dst, src, ty);
+        } else {
+            let value = self.value(expr);
+            self.b.store(value, dst.base, dst.offset);
+        }
+    }
+
+    /// Precision of the variable `expr` reads part of, if it has one
+    fn precision(&self, expr: &Expr) -> Option<Precision> {
+        match &expr.kind {
+            ExprKind::Var(VarRef::Local(local)) => self.function.local(*local).precision,
+            ExprKind::Var(VarRef::Global(global)) => {
+                self.context.module.globals[global.0 as usize].precision
+            }
+            ExprKind::Field(base, _) | ExprKind::Index(base, _) | ExprKind::Swizzle(base, _) => {
+                self.precision(base)
+            }
+            _ => None,
+        }
+    }
+
+    /// `value` marked as held at `precision`, if it is a float
+    fn mark(&mut self, value: Value, precision: Option<Precision>) -> Value {
+        match precision {
+            Some(precision) if self.b.value_type(value).scalar == ScalarType::F32 => {
+                self.b.unary(UnaryOp::marker(precision), value)
+            }
+            _ => value,
+        }
+    }
+
+    /// Place of a whole variable or of an element in memory, marked with the
+    /// precision of the variable
+    fn marked(&self, place: Place, ty: Type, expr: &Expr) -> Place {
+        match self.precision(expr) {
+            Some(precision) if ty.scalar == ScalarType::F32 => {
+                Place::Marked(Box::new(place), precision)
+            }
+            _ => place,
+        }
+    }
+
+    /// Assignable location of a scalar or vector expression
+    fn place(&mut self, expr: &Expr) -> Place {
+        let ty = value_type(&expr.ty).expect("place of a value type");
+        match &expr.kind {
+            ExprKind::Var(VarRef::Local(local)) => match self.locals[local.0 as usize] {
+                LocalPlace::Variable(var) => self.marked(Place::Variable(var), ty, expr),
+                _ => unreachable!("value local in memory"),
+            },
+            ExprKind::Var(VarRef::Global(global)) => {
+                let address = self.global_address(global.0 as usize);
+                self.marked(Place::Memory(address, ty), ty, expr)
+            }
+            ExprKind::Index(base, index) if base.ty.is_vector() => {
+                let base = self.place(base);
+                let lane = self.lane(index);
+                Place::Lane(Box::new(base), lane)
+            }
+            ExprKind::Swizzle(base, lanes) if lanes.len() == 1 && base.ty.is_vector() => {
+                let base = self.place(base);
+                Place::Lane(Box::new(base), Lane::Constant(lanes[0]))
+            }
+            ExprKind::Field(..) | ExprKind::Index(..) => {
+                let address = self.element_address(expr);
+                self.marked(Place::Memory(address, ty), ty, expr)
+            }
+            _ => unreachable!("expression is not assignable"),
+        }
+    }
+
+    fn lane(&mut self, index: &Expr) -> Lane {
+        match index.kind {
+            ExprKind::Literal(Literal::Int(i)) => Lane::Constant(i as u8),
+            _ => Lane::Dynamic(self.value(index)),
+        }
+    }
+
+    fn read(&mut self, place: &Place) -> Value {
+        match place {
+            Place::Variable(var) => self.b.use_var(*var),
+            Place::Memory(address, ty) => self.b.load(*ty, address.base, address.offset),
+            Place::Lane(vector, lane) => {
+                let vector = self.read(vector);
+                self.extract(vector, *lane)
+            }
+            // Values written to variables are marked already
+            Place::Marked(place, _) if matches!(**place, Place::Variable(_)) => self.read(place),
+            Place::Marked(place, precision) => {
+                let value = self.read(place);
+                self.mark(value, Some(*precision))
+            }
+        }
+    }
+
+    fn write(&mut self, place: &Place, value: Value) {
+        match place {
+            Place::Variable(var) => self.b.def_var(*var, value),
+            Place::Memory(address, _) => self.b.store(value, address.base, address.offset),
+            Place::Lane(vector_place, lane) => {
+                let vector = self.read(vector_place);
+                let updated = match *lane {
+                    Lane::Constant(lane) => self.b.insert(vector, lane, value),
+                    // Replace the lane whose index matches
+                    Lane::Dynamic(index) => {
+                        let mut updated = vector;
+                        for lane in 0..self.b.value_type(vector).lanes {
+                            let inserted = self.b.insert(vector, lane, value);
+                            let lane = self.b.iconst(lane as i32);
+                            let selected = self.b.compare(CompareOp::Eq, index, lane);
+                            updated = self.b.select(selected, inserted, updated);
+                        }
+                        updated
+                    }
+                };
+                self.write(vector_place, updated);
+            }
+            Place::Marked(place, precision) => {
+                let value = self.mark(value, Some(*precision));
+                self.write(place, value);
+            }
+        }
+    }
+
+    fn extract(&mut self, vector: Value, lane: Lane) -> Value {
+        match lane {
+            Lane::Constant(lane) => self.b.extract(vector, lane),
+            // Select the lane whose index matches, or the first
+            Lane::Dynamic(index) => {
+                let mut selected = self.b.extract(vector, 0);
+                for lane in 1..self.b.value_type(vector).lanes {
+                    let value = self.b.extract(vector, lane);
+                    let lane = self.b.iconst(lane as i32);
+                    let matches = self.b.compare(CompareOp::Eq, index, lane);
+                    selected = self.b.select(matches, value, selected);
+                }
+                selected
+            }
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Operand {
+        let ty = &expr.ty;
+        match &expr.kind {
+            ExprKind::Literal(literal) => Operand::Value(self.b.constant(constant(literal))),
+            ExprKind::Var(_) | ExprKind::Field(..) | ExprKind::Index(..) if in_memory(ty) => {
+                Operand::Memory(self.address(expr))
+            }
+            ExprKind::Var(_) | ExprKind::Field(..) => {
+                let place = self.place(expr);
+                Operand::Value(self.read(&place))
+            }
+            ExprKind::Index(base, index) => {
+                if in_memory(&base.ty) {
+                    let address = self.element_address(expr);
+                    let ty = value_type(ty).expect("element of a value type");
+                    let value = self.b.load(ty, address.base, address.offset);
+                    return Operand::Value(self.mark(value, self.precision(expr)));
+                }
+                let vector = self.value(base);
+                let lane = self.lane(index);
+                Operand::Value(self.extract(vector, lane))
+            }
+            ExprKind::Swizzle(base, lanes) => {
+                let base_is_vector = base.ty.is_vector();
+                let base = self.value(base);
+                let lanes: Vec<Value> = lanes
+                    .iter()
+                    .map(|&lane| match base_is_vector {
+                        true => self.b.extract(base, lane),
+                        false => base,
+                    })
+                    .collect();
+                Operand::Value(match lanes[..] {
+                    [lane] => lane,
+                    _ => self.b.vector(&lanes),
+                })
+            }
+            ExprKind::Unary(op, operand) => Operand::Value(self.unary(*op, operand)),
+            ExprKind::Binary(op, lhs, rhs) => Operand::Value(self.binary(*op, lhs, rhs, ty)),
+            ExprKind::Assign(op, lhs, rhs) => self.assign(*op, lhs, rhs),
+            ExprKind::Ternary(cond, then, otherwise) => self.ternary(cond, then, otherwise),
+            ExprKind::Call(id, args) => self.call(*id, args),
+            ExprKind::Builtin(builtin, args) => Operand::Value(self.builtin(*builtin, args, ty)),
+            ExprKind::Construct(args) => self.construct(args, ty),
+            ExprKind::Comma(first, second) => {
+                self.eval(first);
+                self.eval(second)
+            }
+        }
+    }
+
+    /// Call a builtin, as comparisons for the relational ones and for
+    /// `min`, `max` and `clamp` of `uint`, since builtins of the IR treat
+    /// `i32` as signed
+    fn builtin(&mut self, builtin: Builtin, args: &[Expr], ty: &sema::Type) -> Value {
+        let unsigned = args[0].ty.scalar_type() == Some(sema::ScalarType::UInt);
+        let ty = value_type(ty).expect("builtin returning a value type");
+        let mut args: Vec<Value> = args.iter().map(|arg| self.value(arg)).collect();
+        let op = match (builtin, unsigned) {
+            (Builtin::Equal, _) => CompareOp::Eq,
+            (Builtin::NotEqual, _) => CompareOp::Ne,
+            (Builtin::LessThan, false) => CompareOp::Lt,
+            (Builtin::LessThanEqual, false) => CompareOp::Le,
+            (Builtin::GreaterThan, false) => CompareOp::Gt,
+            (Builtin::GreaterThanEqual, false) => CompareOp::Ge,
+            (Builtin::LessThan, true) => CompareOp::ULt,
+            (Builtin::LessThanEqual, true) => CompareOp::ULe,
+            (Builtin::GreaterThan, true) => CompareOp::UGt,
+            (Builtin::GreaterThanEqual, true) => CompareOp::UGe,
+            (Builtin::Min | Builtin::Max | Builtin::Clamp, true) => {
+                for arg in &mut args {
+                    if self.b.value_type(*arg).lanes < ty.lanes {
+                        *arg = self.b.splat(*arg, ty.lanes);
+                    }
+                }
+                let mut min_max = |op, a, b| {
+                    let replace = self.b.compare(op, b, a);
+                    self.b.select(replace, b, a)
+                };
+                return match builtin {
+                    Builtin::Min => min_max(CompareOp::ULt, args[0], args[1]),
+                    Builtin::Max => min_max(CompareOp::UGt, args[0], args[1]),
+                    _ => {
+                        let low = min_max(CompareOp::UGt, args[0], args[1]);
+                        min_max(CompareOp::ULt, low, args[2])
+                    }
+                };
+            }
+            _ => return self.b.builtin(builtin, &args, ty),
+        };
+        self.b.compare(op, args[0], args[1])
+    }
+
+    fn unary(&mut self, op: hir::UnaryOp, operand: &Expr) -> Value {
+        match op {
+            hir::UnaryOp::Neg => {
+                let value = self.value(operand);
+                self.b.unary(UnaryOp::Neg, value)
+            }
+            hir::UnaryOp::Not | hir::UnaryOp::BitNot => {
+                let value = self.value(operand);
+                self.b.unary(UnaryOp::Not, value)
+            }
+            hir::UnaryOp::PreInc
+            | hir::UnaryOp::PreDec
+            | hir::UnaryOp::PostInc
+            | hir::UnaryOp::PostDec => {
+                let place = self.place(operand);
+                let old = self.read(&place);
+                let ty = self.b.value_type(old);
+                let one = match ty.scalar {
+                    ScalarType::F32 => Constant::F32(1.0),
+                    _ => Constant::I32(1),
+                };
+                let one = self.splat_constant(one, ty.lanes);
+                let step = match op {
+                    hir::UnaryOp::PreInc | hir::UnaryOp::PostInc => BinaryOp::Add,
+                    _ => BinaryOp::Sub,
+                };
+                let new = self.b.binary(step, old, one);
+                self.write(&place, new);
+                match op {
+                    hir::UnaryOp::PreInc | hir::UnaryOp::PreDec => new,
+                    _ => old,
+                }
+            }
+        }
+    }
+
+    fn binary(&mut self, op: hir::BinaryOp, lhs: &Expr, rhs: &Expr, ty: &sema::Type) -> Value {
+        use hir::BinaryOp as B;
+
+        match op {
+            B::And | B::Or => self.short_circuit(op == B::And, lhs, rhs),
+            B::Xor => {
+                let lhs = self.value(lhs);
+                let rhs = self.value(rhs);
+                self.b.compare(CompareOp::Ne, lhs, rhs)
+            }
+            B::Eq | B::Ne if in_memory(&lhs.ty) => {
+                let a = self.address(lhs);
+                let b = self.address(rhs);
+                let equal = self.equal(a, b, &lhs.ty);
+                match op {
+                    B::Eq => equal,
+                    _ => self.b.unary(UnaryOp::Not, equal),
+                }
+            }
+            B::Eq | B::Ne => {
+                let a = self.value(lhs);
+                let b = self.value(rhs);
+                let (compare, reduce) = match op {
+                    B::Eq => (CompareOp::Eq, Builtin::All),
+                    _ => (CompareOp::Ne, Builtin::Any),
+                };
+                let lanes = self.b.compare(compare, a, b);
+                match lhs.ty.is_vector() {
+                    true => self.b.builtin(reduce, &[lanes], Type::BOOL),
+                    false => lanes,
+                }
+            }
+            B::Lt | B::Gt | B::Le | B::Ge => {
+                let unsigned = lhs.ty.scalar_type() == Some(sema::ScalarType::UInt);
+                let op = match (op, unsigned) {
+                    (B::Lt, false) => CompareOp::Lt,
+                    (B::Gt, false) => CompareOp::Gt,
+                    (B::Le, false) => CompareOp::Le,
+                    (B::Ge, false) => CompareOp::Ge,
+                    (B::Lt, true) => CompareOp::ULt,
+                    (B::Gt, true) => CompareOp::UGt,
+                    (B::Le, true) => CompareOp::ULe,
+                    (_, true) => CompareOp::UGe,
+                    _ => unreachable!(),
+                };
+                let a = self.value(lhs);
+                let b = self.value(rhs);
+                self.b.compare(op, a, b)
+            }
+            _ => {
+                let a = self.value(lhs);
+                let b = self.value(rhs);
+                self.arithmetic(op, a, b, &lhs.ty, ty)
+            }
+        }
+    }
+
+    /// `lhs op rhs` for an arithmetic or bitwise operator, widening a
+    /// scalar operand to the vector type of the result
+    fn arithmetic(
+        &mut self,
+        op: hir::BinaryOp,
+        lhs: Value,
+        rhs: Value,
+        lhs_ty: &sema::Type,
+        ty: &sema::Type,
+    ) -> Value {
+        use hir::BinaryOp as B;
+
+        let lanes = value_type(ty).expect("arithmetic on a value type").lanes;
+        let widen = |b: &mut FunctionBuilder, value: Value| {
+            if b.value_type(value).lanes < lanes {
+                b.splat(value, lanes)
+            } else {
+                value
+            }
+        };
+        let lhs = widen(&mut self.b, lhs);
+        let rhs = widen(&mut self.b, rhs);
+        let unsigned = lhs_ty.scalar_type() == Some(sema::ScalarType::UInt);
+        let op = match op {
+            B::Add => BinaryOp::Add,
+            B::Sub => BinaryOp::Sub,
+            B::Mul => BinaryOp::Mul,
+            B::Div if unsigned => BinaryOp::UDiv,
+            B::Div => BinaryOp::Div,
+            B::Mod if unsigned => BinaryOp::URem,
+            B::Mod => BinaryOp::Rem,
+            B::BitAnd => BinaryOp::And,
+            B::BitOr => BinaryOp::Or,
+            B::BitXor => BinaryOp::Xor,
+            B::Shl => BinaryOp::Shl,
+            B::Shr if unsigned => BinaryOp::UShr,
+            B::Shr => BinaryOp::Shr,
+            _ => unreachable!("`{:?}` is not arithmetic", op),
+        };
+        self.b.binary(op, lhs, rhs)
+    }
+
+    /// `&&` or `||`, evaluating `rhs` only when it decides the result
+    fn short_circuit(&mut self, and: bool, lhs: &Expr, rhs: &Expr) -> Value {
+        let lhs = self.value(lhs);
+        let rhs_block = self.b.create_block();
+        let merge = self.b.create_block();
+        let result = self.b.append_block_param(merge, Type::BOOL);
+        let decided = self.b.bconst(!and);
+        if and {
+            self.b.brif(lhs, rhs_block, &[], merge, &[decided]);
+        } else {
+            self.b.brif(lhs, merge, &[decided], rhs_block, &[]);
+        }
+        self.b.seal_block(rhs_block);
+        self.b.switch_to_block(rhs_block);
+        let rhs = self.value(rhs);
+        self.b.jump(merge, &[rhs]);
+        self.b.seal_block(merge);
+        self.b.switch_to_block(merge);
+        result
+    }
+
+    /// Whether the values of type `ty` at two addresses are equal
+    fn equal(&mut self, a: Address, b: Address, ty: &sema::Type) -> Value {
+        let parts: Vec<(u32, sema::Type)> = match ty {
+            sema::Type::Struct(st) => StructLayout::of(st)
+                .offsets
+                .into_iter()
+                .zip(st.fields.iter().map(|f| f.ty.clone()))
+                .collect(),
+            sema::Type::Array(element, n) => (0..*n)
+                .map(|i| (i * array_stride(element), (**element).clone()))
+                .collect(),
+            sema::Type::Matrix(n) => (0..*n as u32)
+                .map(|j| (j * WORD_SIZE * *n as u32, ty.column_type().unwrap()))
+                .collect(),
+            _ => {
+                let value_ty = value_type(ty).expect("comparison of a value type");
+                let x = self.b.load(value_ty, a.base, a.offset);
+                let y = self.b.load(value_ty, b.base, b.offset);
+                let lanes = self.b.compare(CompareOp::Eq, x, y);
+                return match value_ty.is_vector() {
+                    true => self.b.builtin(Builtin::All, &[lanes], Type::BOOL),
+                    false => lanes,
+                };
+            }
+        };
+        let mut all = self.b.bconst(true);
+        for (offset, part) in parts {
+            let equal = self.equal(a.offset_by(offset), b.offset_by(offset), &part);
+            all = self.b.binary(BinaryOp::And, all, equal);
+        }
+        all
+    }
+
+    fn assign(&mut self, op: Option<hir::BinaryOp>, lhs: &Expr, rhs: &Expr) -> Operand {
+        if in_memory(&lhs.ty) {
+            let dst = self.address(lhs);
+            let src = self.address(rhs);
+            self.copy(dst, src, &lhs.ty);
+            return Operand::Memory(dst);
+        }
+        let place = self.place(lhs);
+        let mut value = self.value(rhs);
+        if let Some(op) = op {
+            let old = self.read(&place);
+            value = self.arithmetic(op, old, value, &lhs.ty, &lhs.ty);
+        }
+        self.write(&place, value);
+        Operand::Value(value)
+    }
+
+    fn ternary(&mut self, cond: &Expr, then: &Expr, otherwise: &Expr) -> Operand {
+        let cond = self.value(cond);
+        let then_block = self.b.create_block();
+        let else_block = self.b.create_block();
+        let merge = self.b.create_block();
+        let result_ty = match &then.ty {
+            sema::Type::Void => None,
+            ty => Some(value_type(ty).unwrap_or(Type::I32)),
+        };
+        let result = result_ty.map(|ty| self.b.append_block_param(merge, ty));
+        self.b.brif(cond, then_block, &[], else_block, &[]);
+        for (block, expr) in [(then_block, then), (else_block, otherwise)] {
+            self.b.seal_block(block);
+            self.b.switch_to_block(block);
+            let args = match self.eval(expr) {
+                Operand::Value(value) => vec![value],
+                Operand::Memory(address) => vec![self.address_value(address)],
+                Operand::Void => Vec::new(),
+            };
+            self.b.jump(merge, &args);
+        }
+        self.b.seal_block(merge);
+        self.b.switch_to_block(merge);
+        match result {
+            None => Operand::Void,
+            Some(base) if in_memory(&then.ty) => Operand::Memory(Address { base, offset: 0 }),
+            Some(value) => Operand::Value(value),
+        }
+    }
+
+    fn call(&mut self, id: hir::FunctionId, args: &[Expr]) -> Operand {
+        let context = self.context;
+        let callee = context.module.function(id);
+        let abi = &context.abis[id.0 as usize];
+
+        let mut values = Vec::new();
+        let result_address = abi.returns_in_memory.then(|| {
+            let address = self.temporary(&callee.return_type);
+            values.push(address.base);
+            address
+        });
+        let mut outputs = Vec::new();
+        let mut copies = Vec::new();
+        for (param, arg) in callee.params.iter().zip(args) {
+            if in_memory(&param.ty) {
+                let copy = self.temporary(&param.ty);
+                let arg = self.address(arg);
+                if param.qualifier != ParamQualifier::Out {
+                    self.copy(copy, arg, &param.ty);
+                }
+                if param.qualifier != ParamQualifier::In {
+                    copies.push((arg, copy, &param.ty));
+                }
+                values.push(copy.base);
+            } else if param.qualifier == ParamQualifier::In {
+                values.push(self.value(arg));
+            } else {
+                let place = self.place(arg);
+                if param.qualifier == ParamQualifier::InOut {
+                    values.push(self.read(&place));
+                }
+                outputs.push(place);
+            }
+        }
+
+        let results = self.b.call(FuncRef(id.0), &values, &abi.signature.returns);
+        let mut results = results.into_iter();
+        let result = match result_address {
+            Some(address) => Operand::Memory(address),
+            None if value_type(&callee.return_type).is_some() => {
+                Operand::Value(results.next().expect("return value"))
+            }
+            None => Operand::Void,
+        };
+        for place in outputs {
+            let value = results.next().expect("output parameter");
+            self.write(&place, value);
+        }
+        for (dst, src, ty) in copies {
+            self.copy(dst, src, ty);
+        }
+        result
+    }
+
+    fn construct(&mut self, args: &[Expr], ty: &sema::Type) -> Operand {
+        match ty {
+            sema::Type::Struct(st) => {
+                let address = self.temporary(ty);
+                let offsets = StructLayout::of(st).offsets;
+                for ((arg, field), offset) in args.iter().zip(&st.fields).zip(offsets) {
+                    self.store_expr(arg, &field.ty, address.offset_by(offset));
+                }
+                Operand::Memory(address)
+            }
+            sema::Type::Array(element, _) => {
+                let address = self.temporary(ty);
+                let stride = array_stride(element);
+                for (i, arg) in args.iter().enumerate() {
+                    self.store_expr(arg, element, address.offset_by(i as u32 * stride));
+                }
+                Operand::Memory(address)
+            }
+            sema::Type::Matrix(_) => {
+                // Lowering leaves one argument per column, so the components
+                // of the arguments are the components of the matrix
+                let address = self.temporary(ty);
+                let components = self.components(args);
+                for (i, component) in components.into_iter().enumerate() {
+                    let component = self.convert_scalar(component, sema::ScalarType::Float);
+                    self.b
+                        .store(component.0, address.base, address.offset + 4 * i as i32);
+                }
+                Operand::Memory(address)
+            }
+            _ => Operand::Value(self.construct_value(args, ty)),
+        }
+    }
+
+    fn construct_value(&mut self, args: &[Expr], ty: &sema::Type) -> Value {
+        let target = value_type(ty).expect("constructor of a value type");
+        let scalar = ty.scalar_type().expect("constructor of a value type");
+        if let [arg] = args {
+            let from = arg.ty.scalar_type().expect("conversion from a value type");
+            if arg.ty.is_scalar() {
+                let value = self.value(arg);
+                let value = self.convert(value, from, scalar);
+                return match target.is_vector() {
+                    true => self.b.splat(value, target.lanes),
+                    false => value,
+                };
+            }
+            if value_type(&arg.ty).is_some_and(|t| t.lanes == target.lanes) {
+                let value = self.value(arg);
+                return self.convert(value, from, scalar);
+            }
+        }
+        let components = self.components(args);
+        let lanes: Vec<Value> = components
+            .into_iter()
+            .take(target.lanes as usize)
+            .map(|component| self.convert_scalar(component, scalar).0)
+            .collect();
+        match lanes[..] {
+            [lane] => lane,
+            _ => self.b.vector(&lanes),
+        }
+    }
+
+    /// Scalar components of the arguments in order, with their types
+    fn components(&mut self, args: &[Expr]) -> Vec<(Value, sema::ScalarType)> {
+        let mut components = Vec::new();
+        for arg in args {
+            let scalar = arg.ty.scalar_type().expect("components of a value");
+            match &arg.ty {
+                sema::Type::Matrix(n) => {
+                    let address = self.address(arg);
+                    for i in 0..*n as i32 * *n as i32 {
+                        let value = self.b.load(Type::F32, address.base, address.offset + 4 * i);
+                        components.push((value, scalar));
+                    }
+                }
+                sema::Type::Vector(_, n) => {
+                    let value = self.value(arg);
+                    for lane in 0..*n {
+                        components.push((self.b.extract(value, lane), scalar));
+                    }
+                }
+                _ => components.push((self.value(arg), scalar)),
+            }
+        }
+        components
+    }
+
+    fn convert_scalar(
+        &mut self,
+        (value, from): (Value, sema::ScalarType),
+        to: sema::ScalarType,
+    ) -> (Value, sema::ScalarType) {
+        (self.convert(value, from, to), to)
+    }
+
+    /// Convert a scalar or vector between GLSL scalar types, lane-wise
+    fn convert(&mut self, value: Value, from: sema::ScalarType, to: sema::ScalarType) -> Value {
+        use sema::ScalarType::*;
+
+        let lanes = self.b.value_type(value).lanes;
+        match (from, to) {
+            _ if from == to => value,
+            (Int | UInt, Int | UInt) | (Float, Float) => value,
+            (Int, Float) => self.b.convert(ConvertOp::SIntToFloat, value),
+            (UInt, Float) => self.b.convert(ConvertOp::UIntToFloat, value),
+            (Float, Int) => self.b.convert(ConvertOp::FloatToSInt, value),
+            (Float, UInt) => self.b.convert(ConvertOp::FloatToUInt, value),
+            (Bool, _) => {
+                let (one, zero) = match to {
+                    Float => (Constant::F32(1.0), Constant::F32(0.0)),
+                    _ => (Constant::I32(1), Constant::I32(0)),
+                };
+                let one = self.splat_constant(one, lanes);
+                let zero = self.splat_constant(zero, lanes);
+                self.b.select(value, one, zero)
+            }
+            (_, Bool) => {
+                let zero = self.b.zero(self.b.value_type(value));
+                self.b.compare(CompareOp::Ne, value, zero)
+            }
+        }
+    }
+
+    fn splat_constant(&mut self, constant: Constant, lanes: u8) -> Value {
+        let value = self.b.constant(constant);
+        match lanes {
+            1 => value,
+            _ => self.b.splat(value, lanes),
+        }
+    }
+}
@@ -0,0 +1,477 @@
+//! Well-formedness checks for IR modules
+//!
+//! [`verify`] checks the structure of every function body (each block ends
+//! with its only terminator, branches target blocks of the body with the
+//! right arguments), that every value is defined before it is used on every
+//! path, and that the operands and results of each instruction have the
+//! types it requires. Uses in blocks that cannot be reached from the entry
+//! block are only type checked.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::String,
+    vec::Vec,
+};
+use core::fmt;
+
+use super::{
+    Block, Builtin, Dominators, Function, Inst, InstKind, Module, ScalarType, Type, UnaryOp, Value,
+    ValueDef,
+};
+use crate::sema;
+
+/// A problem found in a function
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifyError {
+    pub function: String,
+    /// Block the problem is in, if it is in a block
+    pub block: Option<Block>,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@{}", self.function)?;
+        if let Some(block) = self.block {
+            write!(f, ", {}", block)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Check every function of `module`, returning all problems found
+pub fn verify(module: &Module) -> Result<(), Vec<VerifyError>> {
+    let mut errors = Vec::new();
+    for func in &module.functions {
+        Verifier {
+            module,
+            func,
+            errors: &mut errors,
+        }
+        .function();
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+struct Verifier<'a> {
+    module: &'a Module,
+    func: &'a Function,
+    errors: &'a mut Vec<VerifyError>,
+}
+
+/// Position of each instruction in the body
+#[derive(Clone, Copy)]
+struct Position {
+    block: Block,
+    index: usize,
+}
+
+impl Verifier<'_> {
+    fn error(&mut self, block: Option<Block>, message: String) {
+        self.errors.push(VerifyError {
+            function: self.func.name.clone(),
+            block,
+            message,
+        });
+    }
+
+    fn function(&mut self) {
+        let func = self.func;
+        let Some(entry) = func.entry() else {
+            return;
+        };
+
+        // Structure: blocks and instructions appear once, in a valid place
+        let mut positions: BTreeMap<Inst, Position> = BTreeMap::new();
+        let mut in_layout = BTreeSet::new();
+        for &block in &func.layout {
+            if block.0 as usize >= func.blocks.len() {
+                self.error(None, format!("{} does not exist", block));
+                return;
+            }
+            if !in_layout.insert(block) {
+                self.error(Some(block), "block appears twice in the layout".into());
+                return;
+            }
+            let data = func.block(block);
+            for (i, &param) in data.params.iter().enumerate() {
+                if !self.value_exists(param) {
+                    self.error(Some(block), format!("parameter {} does not exist", param));
+                    return;
+                }
+                if func.value(param).def != ValueDef::Param(block, i as u32) {
+                    self.error(
+                        Some(block),
+                        format!("parameter {} is recorded as defined elsewhere", param),
+                    );
+                }
+            }
+            if data.insts.is_empty() {
+                self.error(Some(block), "empty block".into());
+            }
+            for (index, &inst) in data.insts.iter().enumerate() {
+                if inst.0 as usize >= func.insts.len() {
+                    self.error(Some(block), "instruction does not exist".into());
+                    return;
+                }
+                if positions.insert(inst, Position { block, index }).is_some() {
+                    self.error(Some(block), "instruction appears twice".into());
+                    return;
+                }
+                let terminator = func.inst(inst).kind.is_terminator();
+                let last = index + 1 == data.insts.len();
+                if terminator && !last {
+                    self.error(Some(block), "terminator before the end of the block".into());
+                } else if !terminator && last {
+                    self.error(Some(block), "block does not end with a terminator".into());
+                }
+                for (i, &result) in func.inst(inst).results.iter().enumerate() {
+                    if !self.value_exists(result) {
+                        self.error(Some(block), format!("result {} does not exist", result));
+                        return;
+                    }
+                    if func.value(result).def != ValueDef::Result(inst, i as u32) {
+                        self.error(
+                            Some(block),
+                            format!("result {} is recorded as defined elsewhere", result),
+                        );
+                    }
+                }
+            }
+        }
+        for &block in &func.layout {
+            for successor in func.successors(block) {
+                if !in_layout.contains(&successor) {
+                    self.error(
+                        Some(block),
+                        format!("branch to {}, which is not in the body", successor),
+                    );
+                    return;
+                }
+                if successor == entry {
+                    self.error(Some(block), "branch to the entry block".into());
+                }
+            }
+        }
+        let entry_types: Vec<Type> = func
+            .block(entry)
+            .params
+            .iter()
+            .map(|&p| func.value_type(p))
+            .collect();
+        if entry_types != func.signature.params {
+            self.error(
+                Some(entry),
+                "entry block parameters do not match the signature".into(),
+            );
+        }
+
+        let dominators = Dominators::new(func);
+        for &block in &func.layout {
+            for &inst in &func.block(block).insts {
+                for operand in func.inst(inst).kind.operands() {
+                    if !self.value_exists(operand) {
+                        self.error(Some(block), format!("use of nonexistent value {}", operand));
+                        return;
+                    }
+                    if !dominators.is_reachable(block) {
+                        continue;
+                    }
+                    let defined = match func.value(operand).def {
+                        ValueDef::Param(def_block, _) => {
+                            in_layout.contains(&def_block) && dominators.dominates(def_block, block)
+                        }
+                        ValueDef::Result(def_inst, _) => match positions.get(&def_inst) {
+                            Some(def) if def.block == block => def.index < positions[&inst].index,
+                            Some(def) => dominators.dominates(def.block, block),
+                            None => false,
+                        },
+                        ValueDef::Detached => false,
+                    };
+                    if !defined {
+                        self.error(
+                            Some(block),
+                            format!("{} is used where it is not always defined", operand),
+                        );
+                    }
+                }
+                if let Err(message) = self.check_types(inst) {
+                    self.error(Some(block), message);
+                }
+            }
+        }
+    }
+
+    fn value_exists(&self, value: Value) -> bool {
+        (value.0 as usize) < self.func.values.len()
+    }
+
+    /// Check the operand and result types of `inst`
+    fn check_types(&self, inst: Inst) -> Result<(), String> {
+        let func = self.func;
+        let data = func.inst(inst);
+        let ty = |v: Value| func.value_type(v);
+        let results: Vec<Type> = data.results.iter().map(|&r| ty(r)).collect();
+        for t in data.results.iter().chain(&data.kind.operands()) {
+            let lanes = ty(*t).lanes;
+            if !(1..=4).contains(&lanes) {
+                return Err(format!("{} has {} lanes", t, lanes));
+            }
+        }
+        let name = inst_name(&data.kind);
+        let expect = |expected: &[Type]| {
+            if results == expected {
+                Ok(())
+            } else {
+                Err(format!(
+                    "`{}` gives {}, not {}",
+                    name,
+                    type_list(expected),
+                    type_list(&results)
+                ))
+            }
+        };
+        let same = |a: Value, b: Value| {
+            if ty(a) == ty(b) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "operands of `{}` have different types {} and {}",
+                    name,
+                    ty(a),
+                    ty(b)
+                ))
+            }
+        };
+        let accepts = |ok: bool, v: Value| {
+            if ok {
+                Ok(())
+            } else {
+                Err(format!("`{}` does not apply to {}", name, ty(v)))
+            }
+        };
+
+        match &data.kind {
+            InstKind::Const(constant) => expect(&[constant.ty()]),
+            InstKind::Unary(op, a) => {
+                let scalar = ty(*a).scalar;
+                let ok = match op {
+                    UnaryOp::Neg => scalar != ScalarType::Bool,
+                    UnaryOp::Not => scalar != ScalarType::F32,
+                    UnaryOp::Lowp | UnaryOp::Mediump | UnaryOp::Highp => scalar == ScalarType::F32,
+                };
+                accepts(ok, *a)?;
+                expect(&[ty(*a)])
+            }
+            InstKind::Binary(op, a, b) => {
+                same(*a, *b)?;
+                accepts(op.accepts(ty(*a).scalar), *a)?;
+                expect(&[ty(*a)])
+            }
+            InstKind::Compare(op, a, b) => {
+                same(*a, *b)?;
+                accepts(op.accepts(ty(*a).scalar), *a)?;
+                expect(&[ty(*a).with_scalar(ScalarType::Bool)])
+            }
+            InstKind::Convert(op, a) => {
+                let (from, to) = op.types();
+                accepts(ty(*a).scalar == from, *a)?;
+                expect(&[ty(*a).with_scalar(to)])
+            }
+            InstKind::Select(cond, a, b) => {
+                let c = ty(*cond);
+                if c.scalar != ScalarType::Bool || (c.is_vector() && c.lanes != ty(*a).lanes) {
+                    return Err(format!("`select` condition of type {}", c));
+                }
+                same(*a, *b)?;
+                expect(&[ty(*a)])
+            }
+            InstKind::Splat(a) => {
+                accepts(!ty(*a).is_vector(), *a)?;
+                match results[..] {
+                    [r] if r.is_vector() && r.scalar == ty(*a).scalar => Ok(()),
+                    _ => Err(format!(
+                        "`splat` of {} gives {}",
+                        ty(*a),
+                        type_list(&results)
+                    )),
+                }
+            }
+            InstKind::Vector(lanes) => {
+                let first = ty(lanes[0]);
+                if !(2..=4).contains(&lanes.len()) {
+                    return Err(format!("`vector` of {} lanes", lanes.len()));
+                }
+                for &lane in lanes {
+                    if ty(lane) != first || first.is_vector() {
+                        return Err(format!("`vector` lane of type {}", ty(lane)));
+                    }
+                }
+                expect(&[Type::vector(first.scalar, lanes.len() as u8)])
+            }
+            InstKind::Extract(v, lane) => {
+                accepts(ty(*v).is_vector() && *lane < ty(*v).lanes, *v)?;
+                expect(&[ty(*v).lane_type()])
+            }
+            InstKind::Insert(v, lane, x) => {
+                accepts(ty(*v).is_vector() && *lane < ty(*v).lanes, *v)?;
+                if ty(*x) != ty(*v).lane_type() {
+                    return Err(format!("`insert` of {} into {}", ty(*x), ty(*v)));
+                }
+                expect(&[ty(*v)])
+            }
+            InstKind::Builtin(builtin, args) => {
+                let args: Vec<Type> = args.iter().map(|&a| ty(a)).collect();
+                match results[..] {
+                    [result] if builtin_accepts(*builtin, &args, result) => Ok(()),
+                    _ => Err(format!(
+                        "no overload of `{}` takes ({}) and gives {}",
+                        builtin.name(),
+                        type_list(&args),
+                        type_list(&results)
+                    )),
+                }
+            }
+            InstKind::Call(callee, args) => {
+                let Some(callee) = self.module.functions.get(callee.0 as usize) else {
+                    return Err("call to a nonexistent function".into());
+                };
+                let args: Vec<Type> = args.iter().map(|&a| ty(a)).collect();
+                if args != callee.signature.params {
+                    return Err(format!(
+                        "call to @{} with arguments ({}), expected ({})",
+                        callee.name,
+                        type_list(&args),
+                        type_list(&callee.signature.params)
+                    ));
+                }
+                expect(&callee.signature.returns)
+            }
+            InstKind::StackAddr(slot) => {
+                if slot.0 as usize >= func.slots.len() {
+                    return Err(format!("{} does not exist", slot));
+                }
+                expect(&[Type::I32])
+            }
+            InstKind::GlobalAddr(global) => {
+                if global.0 as usize >= self.module.globals.len() {
+                    return Err("address of a nonexistent global".into());
+                }
+                expect(&[Type::I32])
+            }
+            InstKind::Load(addr, _) => {
+                accepts(ty(*addr) == Type::I32, *addr)?;
+                match results[..] {
+                    [_] => Ok(()),
+                    _ => Err("`load` must give one value".into()),
+                }
+            }
+            InstKind::Store(_, addr, _) => {
+                accepts(ty(*addr) == Type::I32, *addr)?;
+                expect(&[])
+            }
+            InstKind::Jump(_) | InstKind::Branch(..) => {
+                if let InstKind::Branch(cond, ..) = &data.kind {
+                    if ty(*cond) != Type::BOOL {
+                        return Err(format!("`brif` condition of type {}", ty(*cond)));
+                    }
+                }
+                for call in data.kind.block_calls() {
+                    let args: Vec<Type> = call.args.iter().map(|&a| ty(a)).collect();
+                    let params: Vec<Type> = func
+                        .block(call.block)
+                        .params
+                        .iter()
+                        .map(|&p| ty(p))
+                        .collect();
+                    if args != params {
+                        return Err(format!(
+                            "arguments ({}) for {}, which takes ({})",
+                            type_list(&args),
+                            call.block,
+                            type_list(&params)
+                        ));
+                    }
+                }
+                expect(&[])
+            }
+            InstKind::Return(values) => {
+                let types: Vec<Type> = values.iter().map(|&v| ty(v)).collect();
+                if types != func.signature.returns {
+                    return Err(format!(
+                        "`return` of ({}) from a function returning ({})",
+                        type_list(&types),
+                        type_list(&func.signature.returns)
+                    ));
+                }
+                expect(&[])
+            }
+            InstKind::Discard => expect(&[]),
+        }
+    }
+}
+
+/// Name of the instruction in the textual form
+fn inst_name(kind: &InstKind) -> &'static str {
+    match kind {
+        InstKind::Const(_) => "const",
+        InstKind::Unary(op, ..) => op.name(),
+        InstKind::Binary(op, ..) => op.name(),
+        InstKind::Compare(op, ..) => op.name(),
+        InstKind::Convert(op, ..) => op.name(),
+        InstKind::Select(..) => "select",
+        InstKind::Splat(_) => "splat",
+        InstKind::Vector(_) => "vector",
+        InstKind::Extract(..) => "extract",
+        InstKind::Insert(..) => "insert",
+        InstKind::Builtin(..) => "builtin",
+        InstKind::Call(..) => "call",
+        InstKind::StackAddr(_) => "stack_addr",
+        InstKind::GlobalAddr(_) => "global_addr",
+        InstKind::Load(..) => "load",
+        InstKind::Store(..) => "store",
+        InstKind::Jump(_) => "jump",
+        InstKind::Branch(..) => "brif",
+        InstKind::Return(_) => "return",
+        InstKind::Discard => "discard",
+    }
+}
+
+fn type_list(types: &[Type]) -> String {
+    let names: Vec<String> = types.iter().map(|t| format!("{}", t)).collect();
+    names.join(", ")
+}
+
+/// Whether an overload of `builtin` takes `args` and returns `result`
+///
+/// Both `int` and `uint` in a GLSL signature match `i32`.
+fn builtin_accepts(builtin: Builtin, args: &[Type], result: Type) -> bool {
+    fn matches(glsl: &sema::Type, ty: Type) -> bool {
+        let (scalar, lanes) = match *glsl {
+            sema::Type::Scalar(s) => (s, 1),
+            sema::Type::Vector(s, n) => (s, n),
+            _ => return false,
+        };
+        let scalar = match scalar {
+            sema::ScalarType::Bool => ScalarType::Bool,
+            sema::ScalarType::Int | sema::ScalarType::UInt => ScalarType::I32,
+            sema::ScalarType::Float => ScalarType::F32,
+        };
+        ty == Type::vector(scalar, lanes)
+    }
+
+    builtin.signatures().iter().any(|signature| {
+        signature.params.len() == args.len()
+            && matches(&signature.return_type, result)
+            && signature
+                .params
+                .iter()
+                .zip(args)
+                .all(|(p, &a)| matches(p, a))
+    })
+}
@@ -0,0 +1,164 @@
+//! Memory layout of shader types on the RV32 target
+//!
+//! Every scalar is a 32-bit word aligned to 4 bytes; `bool` is stored as a
+//! word holding 0 or 1. Vectors are tightly packed components, so a `vec3`
+//! is 12 bytes, and a matrix is its columns in order. Struct fields are
+//! placed in declaration order at the next offset aligned for their type,
+//! and the struct size is rounded up to its alignment. Array elements are
+//! spaced by their stride, the element size rounded up to the element
+//! alignment.
+//!
+//! Structs and arrays live in memory: [`FrameLayout`] assigns each local of
+//! aggregate type a slot in the function's stack frame. [`frame_size`]
+//! bounds the whole frame, registers saved and spilled included.
+
+use alloc::vec::Vec;
+
+use crate::sema::{
+    hir::{Function, LocalId},
+    StructType, Type,
+};
+
+/// Size of a machine word in bytes
+pub const WORD_SIZE: u32 = 4;
+
+/// Stack pointer alignment required by the RISC-V calling convention
+pub const STACK_ALIGN: u32 = 16;
+
+/// Bytes a frame saves for the return address `ra` and the callee-saved
+/// registers `s0`–`s11`
+pub const SAVE_AREA_SIZE: u32 = 13 * WORD_SIZE;
+
+/// Size and alignment of a type, in bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub size: u32,
+    pub align: u32,
+}
+
+impl Layout {
+    pub fn of(ty: &Type) -> Layout {
+        match ty {
+            Type::Void => Layout { size: 0, align: 1 },
+            Type::Scalar(_) => Layout {
+                size: WORD_SIZE,
+                align: WORD_SIZE,
+            },
+            Type::Vector(_, n) => Layout {
+                size: WORD_SIZE * *n as u32,
+                align: WORD_SIZE,
+            },
+            // Columns are packed one after another
+            Type::Matrix(n) => Layout {
+                size: WORD_SIZE * *n as u32 * *n as u32,
+                align: WORD_SIZE,
+            },
+            Type::Struct(st) => StructLayout::of(st).layout,
+            Type::Array(element, n) => Layout {
+                size: array_stride(element) * n,
+                align: Layout::of(element).align,
+            },
+        }
+    }
+}
+
+/// Layout of a struct and the offset of each field
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructLayout {
+    pub layout: Layout,
+    /// Byte offset of each field, in declaration order
+    pub offsets: Vec<u32>,
+}
+
+impl StructLayout {
+    pub fn of(st: &StructType) -> StructLayout {
+        let mut offsets = Vec::with_capacity(st.fields.len());
+        let mut size = 0;
+        let mut align = 1;
+        for field in &st.fields {
+            let layout = Layout::of(&field.ty);
+            size = align_to(size, layout.align);
+            offsets.push(size);
+            size += layout.size;
+            align = align.max(layout.align);
+        }
+        StructLayout {
+            layout: Layout {
+                size: align_to(size, align),
+                align,
+            },
+            offsets,
+        }
+    }
+}
+
+/// Distance in bytes between consecutive elements of an array of `element`
+pub fn array_stride(element: &Type) -> u32 {
+    let layout = Layout::of(element);
+    align_to(layout.size, layout.align)
+}
+
+/// Round `offset` up to a multiple of `align`, which must be a power of two
+pub fn align_to(offset: u32, align: u32) -> u32 {
+    (offset + align - 1) & !(align - 1)
+}
+
+/// Stack slots for the aggregate locals of a function
+///
+/// Parameters of aggregate type get a slot too: they are passed by address
+/// and copied into the callee's frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameLayout {
+    /// Offset from the stack pointer of each local's slot, `None` for
+    /// locals that are kept in registers
+    pub slots: Vec<Option<u32>>,
+    /// Frame size, a multiple of [`STACK_ALIGN`]
+    pub size: u32,
+}
+
+impl FrameLayout {
+    pub fn new(function: &Function) -> FrameLayout {
+        let mut size = 0;
+        let slots = function
+            .locals
+            .iter()
+            .map(|local| {
+                if !local.ty.is_aggregate() {
+                    return None;
+                }
+                let layout = Layout::of(&local.ty);
+                let offset = align_to(size, layout.align);
+                size = offset + layout.size;
+                Some(offset)
+            })
+            .collect();
+        FrameLayout {
+            slots,
+            size: align_to(size, STACK_ALIGN),
+        }
+    }
+
+    /// Stack offset of a local's slot, if it has one
+    pub fn slot(&self, local: LocalId) -> Option<u32> {
+        self.slots.get(local.0 as usize).copied().flatten()
+    }
+}
+
+/// Worst-case bytes of stack taken by a frame of `function`
+///
+/// Besides the slots of its [`FrameLayout`], the frame holds the save area
+/// and, in case every register gets spilled, a slot for each scalar, vector
+/// and matrix local, parameters included. Intermediate values of an
+/// expression are assumed to fit in the caller-saved registers.
+pub fn frame_size(function: &Function) -> u32 {
+    let spills: u32 = function
+        .locals
+        .iter()
+        .filter(|local| !local.ty.is_aggregate())
+        .map(|local| Layout::of(&local.ty).size)
+        .sum();
+    align_to(
+        FrameLayout::new(function).size + SAVE_AREA_SIZE + spills,
+        STACK_ALIGN,
+    )
+}
@@ -1,7 +1,20 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
-#[cfg(not(feature = "std"))]
 extern crate alloc;
 
+pub mod analysis;
+pub mod compiler;
+pub mod diagnostic;
+pub mod ir;
+pub mod layout;
+pub mod lower;
+pub mod numeric;
+pub mod opt;
+pub mod preprocessor;
+#[cfg(feature = "std")]
 pub mod r5vm;
-
+pub mod reference;
+pub mod reflect;
+pub mod runtime;
+pub mod rv32;
+pub mod sema;
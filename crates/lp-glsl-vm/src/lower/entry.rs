@@ -0,0 +1,51 @@
+//! Entry point normalization
+//!
+//! A Shadertoy shader defines `mainImage` instead of `main`. This pass adds
+//! the `main` that Shadertoy would call it from, passing `gl_FragColor` as
+//! the output and the pixel position `gl_FragCoord.xy`, so later stages only
+//! ever deal with `main`.
+
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+
+use crate::sema::{
+    hir::{Expr, ExprKind, Function, GlobalId, Module, Stmt, Storage, VarRef, MAIN, MAIN_IMAGE},
+    ScalarType, Type,
+};
+
+pub(super) fn lower(module: &mut Module) {
+    if module.find_function(MAIN).is_some() {
+        return;
+    }
+    let Some(main_image) = module.find_function(MAIN_IMAGE) else {
+        return;
+    };
+    let builtin = |name: &str| {
+        let (i, global) = module
+            .globals
+            .iter()
+            .enumerate()
+            .find(|(_, g)| g.storage == Storage::Builtin && g.name == name)
+            .expect("builtin global is declared");
+        Expr::new(
+            ExprKind::Var(VarRef::Global(GlobalId(i as u32))),
+            global.ty.clone(),
+        )
+    };
+    let frag_color = builtin("gl_FragColor");
+    let frag_coord = Expr::new(
+        ExprKind::Swizzle(Box::new(builtin("gl_FragCoord")), vec![0, 1]),
+        Type::Vector(ScalarType::Float, 2),
+    );
+    let call = Expr::new(
+        ExprKind::Call(main_image, vec![frag_color, frag_coord]),
+        Type::Void,
+    );
+    module.functions.push(Function {
+        name: String::from(MAIN),
+        return_type: Type::Void,
+        return_precision: None,
+        params: Vec::new(),
+        locals: Vec::new(),
+        body: Some(vec![Stmt::Expr(call)]),
+    });
+}
@@ -0,0 +1,124 @@
+//! Iteration caps for loops that may not terminate
+//!
+//! Each loop that [`loops::classify`] cannot show to be bounded gets a
+//! counter, checked at the start of every iteration: once the body has run
+//! `cap` times the loop is left with `break`, as if its condition had
+//! become false.
+
+use alloc::{boxed::Box, collections::BTreeSet, vec, vec::Vec};
+use core::mem;
+
+use super::Temps;
+use crate::{
+    analysis::loops::{self, LoopInfo, Termination},
+    sema::{
+        hir::{
+            walk_stmt_mut, BinaryOp, Expr, ExprKind, Function, Literal, Module, Stmt, VarRef,
+            VisitorMut,
+        },
+        Type,
+    },
+};
+
+/// Cap the unbounded loops of `module` at `cap` iterations, returning them
+pub fn cap_loops(module: &mut Module, cap: u32) -> Vec<LoopInfo> {
+    let capped: Vec<LoopInfo> = loops::classify(module)
+        .into_iter()
+        .filter(|l| l.termination == Termination::Unbounded)
+        .collect();
+    for (i, function) in module.functions.iter_mut().enumerate() {
+        let ordinals: BTreeSet<usize> = capped
+            .iter()
+            .filter(|l| l.function.0 as usize == i)
+            .map(|l| l.ordinal)
+            .collect();
+        if ordinals.is_empty() {
+            continue;
+        }
+        let Function { body, locals, .. } = function;
+        let mut capper = Capper {
+            temps: Temps { locals },
+            ordinals,
+            next: 0,
+            cap,
+        };
+        capper.visit_block_mut(body.as_mut().expect("capping a prototype"));
+    }
+    capped
+}
+
+struct Capper<'a> {
+    temps: Temps<'a>,
+    /// Ordinals of the loops to cap
+    ordinals: BTreeSet<usize>,
+    /// Ordinal of the next loop reached
+    next: usize,
+    cap: u32,
+}
+
+impl VisitorMut for Capper<'_> {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        if !matches!(stmt, Stmt::Loop { .. }) {
+            return walk_stmt_mut(self, stmt);
+        }
+        let ordinal = self.next;
+        self.next += 1;
+        walk_stmt_mut(self, stmt);
+        if self.ordinals.contains(&ordinal) {
+            *stmt = self.capped(mem::replace(stmt, Stmt::Break));
+        }
+    }
+
+    /// Loops are statements, so expressions hold none
+    fn visit_expr_mut(&mut self, _: &mut Expr) {}
+}
+
+impl Capper<'_> {
+    /// `{ uint n = 0u; loop { if (n >= cap) break; n += 1u; body } }`
+    fn capped(&mut self, stmt: Stmt) -> Stmt {
+        let Stmt::Loop {
+            cond,
+            step,
+            body,
+            test_first,
+        } = stmt
+        else {
+            unreachable!("capping a statement that is not a loop");
+        };
+        let counter = self.temps.new_local(&Type::UINT);
+        let ExprKind::Var(VarRef::Local(id)) = counter.kind else {
+            unreachable!("temporary is not a local");
+        };
+        let uint = |v: u32| Expr::new(ExprKind::Literal(Literal::UInt(v)), Type::UINT);
+        let exhausted = Expr::new(
+            ExprKind::Binary(
+                BinaryOp::Ge,
+                Box::new(counter.clone()),
+                Box::new(uint(self.cap)),
+            ),
+            Type::BOOL,
+        );
+        let count = Expr::new(
+            ExprKind::Assign(Some(BinaryOp::Add), Box::new(counter), Box::new(uint(1))),
+            Type::UINT,
+        );
+        let mut capped_body = vec![
+            Stmt::If {
+                cond: exhausted,
+                then_branch: vec![Stmt::Break],
+                else_branch: None,
+            },
+            Stmt::Expr(count),
+        ];
+        capped_body.extend(body);
+        Stmt::Block(vec![
+            Stmt::Decl(id, Some(uint(0))),
+            Stmt::Loop {
+                cond,
+                step,
+                body: capped_body,
+                test_first,
+            },
+        ])
+    }
+}
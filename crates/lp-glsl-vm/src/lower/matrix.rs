@@ -0,0 +1,361 @@
+//! Column-wise lowering of matrix operations
+//!
+//! Matrices stay first-class values that can be stored, passed and indexed,
+//! but every operation on them is rewritten in terms of their column
+//! vectors. `m * v` becomes a sum of scaled columns, `v * m` a vector of dot
+//! products, and component-wise operators, `matrixCompMult`, `transpose` and
+//! `outerProduct` build each column separately. Matrix constructors are
+//! canonicalized to one column vector per argument.
+
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use super::{for_each_expr, for_each_function, map_children, sequence, take, Temps};
+use crate::sema::{
+    builtins::Builtin,
+    hir::{BinaryOp, Expr, ExprKind, Function, Literal, Module, UnaryOp},
+    ScalarType, Type,
+};
+
+pub(super) fn lower(module: &mut Module) {
+    for_each_function(module, |function| {
+        let Function { body, locals, .. } = function;
+        let mut lowering = Lowering {
+            temps: Temps { locals },
+        };
+        for_each_expr(body.as_mut().expect("lowering a prototype"), &mut |expr| {
+            *expr = lowering.expr(take(expr));
+        });
+    });
+}
+
+struct Lowering<'a> {
+    temps: Temps<'a>,
+}
+
+impl Lowering<'_> {
+    /// Lower the subexpressions of `expr`, then `expr` itself
+    fn expr(&mut self, expr: Expr) -> Expr {
+        let expr = map_children(expr, &mut |e| self.expr(e));
+        let Expr { kind, ty } = expr;
+        match kind {
+            ExprKind::Binary(op, lhs, rhs) if lhs.ty.is_matrix() || rhs.ty.is_matrix() => {
+                self.binary(op, *lhs, *rhs, ty)
+            }
+            ExprKind::Assign(Some(op), lhs, rhs) if lhs.ty.is_matrix() || rhs.ty.is_matrix() => {
+                self.compound_assign(op, *lhs, *rhs, ty)
+            }
+            ExprKind::Unary(op, operand) if operand.ty.is_matrix() && op.is_inc_dec() => {
+                self.inc_dec(op, *operand, ty)
+            }
+            ExprKind::Unary(UnaryOp::Neg, operand) if operand.ty.is_matrix() => {
+                let mut seq = Vec::new();
+                let m = self.temps.reusable(*operand, &mut seq);
+                let column_ty = column_type(&ty);
+                seq.push(columns(&ty, |j| {
+                    Expr::new(
+                        ExprKind::Unary(UnaryOp::Neg, Box::new(column(&m, j))),
+                        column_ty.clone(),
+                    )
+                }));
+                sequence(seq)
+            }
+            ExprKind::Builtin(
+                builtin @ (Builtin::MatrixCompMult | Builtin::Transpose | Builtin::OuterProduct),
+                args,
+            ) => self.builtin(builtin, args, ty),
+            ExprKind::Construct(args) if ty.is_matrix() => self.construct(args, ty),
+            kind => Expr::new(kind, ty),
+        }
+    }
+
+    /// Lower a binary operator with at least one matrix operand
+    fn binary(&mut self, op: BinaryOp, lhs: Expr, rhs: Expr, ty: Type) -> Expr {
+        let mut seq = Vec::new();
+        let lhs = self.temps.reusable(lhs, &mut seq);
+        let rhs = self.temps.reusable(rhs, &mut seq);
+        let result =
+            match (op, &lhs.ty, &rhs.ty) {
+                // Sum of the columns of `lhs` scaled by the components of `rhs`
+                (BinaryOp::Mul, Type::Matrix(n), Type::Vector(..)) => sum((0..*n)
+                    .map(|k| binary(BinaryOp::Mul, column(&lhs, k), component(&rhs, k), &ty))),
+                // Dot products of `lhs` with each column of `rhs`
+                (BinaryOp::Mul, Type::Vector(..), Type::Matrix(_)) => {
+                    let args = (0..component_count(&ty))
+                        .map(|j| {
+                            Expr::new(
+                                ExprKind::Builtin(Builtin::Dot, vec![lhs.clone(), column(&rhs, j)]),
+                                Type::FLOAT,
+                            )
+                        })
+                        .collect();
+                    Expr::new(ExprKind::Construct(args), ty)
+                }
+                // Column `j` of the product is `lhs * rhs[j]`
+                (BinaryOp::Mul, Type::Matrix(n), Type::Matrix(_)) => {
+                    let column_ty = column_type(&ty);
+                    columns(&ty, |j| {
+                        let rhs_column = column(&rhs, j);
+                        sum((0..*n).map(|k| {
+                            binary(
+                                BinaryOp::Mul,
+                                column(&lhs, k),
+                                component(&rhs_column, k),
+                                &column_ty,
+                            )
+                        }))
+                    })
+                }
+                (BinaryOp::Eq | BinaryOp::Ne, Type::Matrix(n), _) => {
+                    let (compare, join) = match op {
+                        BinaryOp::Eq => (BinaryOp::Eq, BinaryOp::And),
+                        _ => (BinaryOp::Ne, BinaryOp::Or),
+                    };
+                    (0..*n)
+                        .map(|j| binary(compare, column(&lhs, j), column(&rhs, j), &Type::BOOL))
+                        .reduce(|a, b| binary(join, a, b, &Type::BOOL))
+                        .expect("matrix without columns")
+                }
+                // Component-wise, with a scalar operand applying to every column
+                _ => {
+                    let column_ty = column_type(&ty);
+                    columns(&ty, |j| {
+                        binary(
+                            op,
+                            column_or_scalar(&lhs, j),
+                            column_or_scalar(&rhs, j),
+                            &column_ty,
+                        )
+                    })
+                }
+            };
+        seq.push(result);
+        sequence(seq)
+    }
+
+    /// Lower `lhs op= rhs` to `lhs = lhs op rhs`, evaluating the place once
+    fn compound_assign(&mut self, op: BinaryOp, lhs: Expr, rhs: Expr, ty: Type) -> Expr {
+        let mut seq = Vec::new();
+        let place = self.place(lhs, &mut seq);
+        let value = self.binary(op, place.clone(), rhs, ty.clone());
+        seq.push(Expr::new(
+            ExprKind::Assign(None, Box::new(place), Box::new(value)),
+            ty,
+        ));
+        sequence(seq)
+    }
+
+    fn inc_dec(&mut self, op: UnaryOp, operand: Expr, ty: Type) -> Expr {
+        let mut seq = Vec::new();
+        let place = self.place(operand, &mut seq);
+        let old = match op {
+            UnaryOp::PostInc | UnaryOp::PostDec => {
+                let (store, old) = self.temps.store(place.clone());
+                seq.push(store);
+                Some(old)
+            }
+            _ => None,
+        };
+        let step = match op {
+            UnaryOp::PreInc | UnaryOp::PostInc => BinaryOp::Add,
+            _ => BinaryOp::Sub,
+        };
+        let value = self.binary(step, place.clone(), float(1.0), ty.clone());
+        seq.push(Expr::new(
+            ExprKind::Assign(None, Box::new(place), Box::new(value)),
+            ty,
+        ));
+        seq.extend(old);
+        sequence(seq)
+    }
+
+    fn builtin(&mut self, builtin: Builtin, args: Vec<Expr>, ty: Type) -> Expr {
+        let mut seq = Vec::new();
+        let args: Vec<Expr> = args
+            .into_iter()
+            .map(|arg| self.temps.reusable(arg, &mut seq))
+            .collect();
+        let column_ty = column_type(&ty);
+        seq.push(match builtin {
+            Builtin::MatrixCompMult => columns(&ty, |j| {
+                binary(
+                    BinaryOp::Mul,
+                    column(&args[0], j),
+                    column(&args[1], j),
+                    &column_ty,
+                )
+            }),
+            // Column `j` of the result is row `j` of the argument
+            Builtin::Transpose => columns(&ty, |j| {
+                let row = (0..component_count(&column_ty))
+                    .map(|i| component(&column(&args[0], i), j))
+                    .collect();
+                Expr::new(ExprKind::Construct(row), column_ty.clone())
+            }),
+            // Column `j` of the result is the first argument scaled by `r[j]`
+            _ => columns(&ty, |j| {
+                binary(
+                    BinaryOp::Mul,
+                    args[0].clone(),
+                    component(&args[1], j),
+                    &column_ty,
+                )
+            }),
+        });
+        sequence(seq)
+    }
+
+    /// Canonicalize a matrix constructor to one column vector per argument
+    fn construct(&mut self, args: Vec<Expr>, ty: Type) -> Expr {
+        let column_ty = column_type(&ty);
+        let n = component_count(&column_ty);
+        if args.len() == n as usize && args.iter().all(|arg| arg.ty == column_ty) {
+            return Expr::new(ExprKind::Construct(args), ty);
+        }
+        let mut seq = Vec::new();
+        let args: Vec<Expr> = args
+            .into_iter()
+            .map(|arg| self.temps.reusable(arg, &mut seq))
+            .collect();
+        let vector =
+            |components: Vec<Expr>| Expr::new(ExprKind::Construct(components), column_ty.clone());
+        seq.push(match args.as_slice() {
+            // A scalar fills the diagonal
+            [arg] if arg.ty.is_scalar() => columns(&ty, |j| {
+                vector(
+                    (0..n)
+                        .map(|i| if i == j { arg.clone() } else { float(0.0) })
+                        .collect(),
+                )
+            }),
+            // A matrix fills the upper left corner of the identity
+            [arg] if arg.ty.is_matrix() => {
+                let k = component_count(&column_type(&arg.ty));
+                columns(&ty, |j| match j < k {
+                    true if k == n => column(arg, j),
+                    true => vector(
+                        (0..n)
+                            .map(|i| match i < k {
+                                true => component(&column(arg, j), i),
+                                false => float(if i == j { 1.0 } else { 0.0 }),
+                            })
+                            .collect(),
+                    ),
+                    false => vector(
+                        (0..n)
+                            .map(|i| float(if i == j { 1.0 } else { 0.0 }))
+                            .collect(),
+                    ),
+                })
+            }
+            // Otherwise the components of the arguments fill the columns in order
+            _ => {
+                let mut components = Vec::new();
+                for arg in &args {
+                    match &arg.ty {
+                        Type::Scalar(_) => components.push(arg.clone()),
+                        Type::Vector(_, size) => {
+                            components.extend((0..*size).map(|i| component(arg, i)))
+                        }
+                        _ => {
+                            for j in 0..component_count(&column_type(&arg.ty)) {
+                                let arg_column = column(arg, j);
+                                components.extend(
+                                    (0..component_count(&arg_column.ty))
+                                        .map(|i| component(&arg_column, i)),
+                                );
+                            }
+                        }
+                    }
+                }
+                let mut components = components.into_iter();
+                columns(&ty, |_| {
+                    vector(components.by_ref().take(n as usize).collect())
+                })
+            }
+        });
+        sequence(seq)
+    }
+
+    /// Hoist non-pure indices out of an assigned place so it may be evaluated repeatedly
+    fn place(&mut self, expr: Expr, seq: &mut Vec<Expr>) -> Expr {
+        let Expr { kind, ty } = expr;
+        let kind = match kind {
+            ExprKind::Field(base, index) => {
+                ExprKind::Field(Box::new(self.place(*base, seq)), index)
+            }
+            ExprKind::Swizzle(base, mask) => {
+                ExprKind::Swizzle(Box::new(self.place(*base, seq)), mask)
+            }
+            ExprKind::Index(base, index) => {
+                let base = self.place(*base, seq);
+                let index = self.temps.reusable(*index, seq);
+                ExprKind::Index(Box::new(base), Box::new(index))
+            }
+            other => other,
+        };
+        Expr::new(kind, ty)
+    }
+}
+
+/// Column vector type of a matrix type
+fn column_type(ty: &Type) -> Type {
+    ty.column_type().expect("column of a non-matrix")
+}
+
+/// Number of components of a vector, or columns of a matrix
+fn component_count(ty: &Type) -> u8 {
+    match ty {
+        Type::Vector(_, n) | Type::Matrix(n) => *n,
+        _ => 1,
+    }
+}
+
+/// Matrix of type `ty` built from the columns `f(0)`, `f(1)`, ...
+fn columns(ty: &Type, f: impl FnMut(u8) -> Expr) -> Expr {
+    let args = (0..component_count(ty)).map(f).collect();
+    Expr::new(ExprKind::Construct(args), ty.clone())
+}
+
+/// Column `j` of a matrix
+fn column(m: &Expr, j: u8) -> Expr {
+    let index = Expr::new(ExprKind::Literal(Literal::Int(j as i32)), Type::INT);
+    Expr::new(
+        ExprKind::Index(Box::new(m.clone()), Box::new(index)),
+        column_type(&m.ty),
+    )
+}
+
+/// Column `j` of a matrix operand, or a scalar operand itself
+fn column_or_scalar(operand: &Expr, j: u8) -> Expr {
+    if operand.ty.is_matrix() {
+        column(operand, j)
+    } else {
+        operand.clone()
+    }
+}
+
+/// Component `i` of a vector
+fn component(v: &Expr, i: u8) -> Expr {
+    let ty = Type::Scalar(v.ty.scalar_type().unwrap_or(ScalarType::Float));
+    Expr::new(ExprKind::Swizzle(Box::new(v.clone()), vec![i]), ty)
+}
+
+fn binary(op: BinaryOp, lhs: Expr, rhs: Expr, ty: &Type) -> Expr {
+    Expr::new(
+        ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+        ty.clone(),
+    )
+}
+
+fn sum(terms: impl Iterator<Item = Expr>) -> Expr {
+    terms
+        .reduce(|a, b| {
+            let ty = a.ty.clone();
+            binary(BinaryOp::Add, a, b, &ty)
+        })
+        .expect("empty sum")
+}
+
+fn float(value: f32) -> Expr {
+    Expr::new(ExprKind::Literal(Literal::Float(value)), Type::FLOAT)
+}
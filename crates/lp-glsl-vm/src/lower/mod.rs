@@ -0,0 +1,151 @@
+//! Lowering of analyzed HIR toward code generation
+//!
+//! Passes rewrite a [`Module`] in place into a smaller subset of the HIR:
+//! after [`lower`], the entry point is always `main`, matrices are only
+//! built from and indexed as column vectors, and vector swizzles only ever
+//! read or write single components. Temporaries introduced by a pass become
+//! new locals of the function they are used in.
+
+mod entry;
+mod loop_cap;
+mod matrix;
+mod swizzle;
+
+use alloc::{boxed::Box, format, vec::Vec};
+use core::mem;
+
+pub use loop_cap::cap_loops;
+
+use crate::sema::{
+    hir::{Block, Expr, ExprKind, Function, Literal, Local, LocalId, Module, VarRef, VisitorMut},
+    Type,
+};
+
+/// Run every lowering pass over the function bodies of `module`
+pub fn lower(module: &mut Module) {
+    entry::lower(module);
+    matrix::lower(module);
+    swizzle::lower(module);
+}
+
+/// Apply `f` to every top-level expression of a block, including nested blocks
+fn for_each_expr(block: &mut Block, f: &mut impl FnMut(&mut Expr)) {
+    struct TopLevel<F>(F);
+
+    impl<F: FnMut(&mut Expr)> VisitorMut for TopLevel<F> {
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            (self.0)(expr);
+        }
+    }
+
+    TopLevel(f).visit_block_mut(block);
+}
+
+/// Take an expression out of its place, leaving a dummy behind
+fn take(expr: &mut Expr) -> Expr {
+    mem::replace(
+        expr,
+        Expr::new(ExprKind::Literal(Literal::Bool(false)), Type::BOOL),
+    )
+}
+
+/// Rebuild `expr` with `f` applied to each of its direct subexpressions
+fn map_children(expr: Expr, f: &mut impl FnMut(Expr) -> Expr) -> Expr {
+    let mut boxed = |expr: Box<Expr>| Box::new(f(*expr));
+    let Expr { kind, ty } = expr;
+    let kind = match kind {
+        ExprKind::Literal(_) | ExprKind::Var(_) => kind,
+        ExprKind::Unary(op, operand) => ExprKind::Unary(op, boxed(operand)),
+        ExprKind::Binary(op, lhs, rhs) => ExprKind::Binary(op, boxed(lhs), boxed(rhs)),
+        ExprKind::Assign(op, lhs, rhs) => ExprKind::Assign(op, boxed(lhs), boxed(rhs)),
+        ExprKind::Ternary(cond, then, otherwise) => {
+            ExprKind::Ternary(boxed(cond), boxed(then), boxed(otherwise))
+        }
+        ExprKind::Call(id, args) => ExprKind::Call(id, args.into_iter().map(&mut *f).collect()),
+        ExprKind::Builtin(builtin, args) => {
+            ExprKind::Builtin(builtin, args.into_iter().map(&mut *f).collect())
+        }
+        ExprKind::Construct(args) => ExprKind::Construct(args.into_iter().map(&mut *f).collect()),
+        ExprKind::Swizzle(base, mask) => ExprKind::Swizzle(boxed(base), mask),
+        ExprKind::Field(base, index) => ExprKind::Field(boxed(base), index),
+        ExprKind::Index(base, index) => ExprKind::Index(boxed(base), boxed(index)),
+        ExprKind::Comma(first, second) => ExprKind::Comma(boxed(first), boxed(second)),
+    };
+    Expr::new(kind, ty)
+}
+
+/// Whether evaluating `expr` has no side effects, so it may be repeated
+fn is_pure(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Var(_) => true,
+        ExprKind::Unary(op, operand) => !op.is_inc_dec() && is_pure(operand),
+        ExprKind::Binary(_, lhs, rhs) | ExprKind::Index(lhs, rhs) => is_pure(lhs) && is_pure(rhs),
+        ExprKind::Ternary(cond, then, otherwise) => {
+            is_pure(cond) && is_pure(then) && is_pure(otherwise)
+        }
+        ExprKind::Builtin(_, args) | ExprKind::Construct(args) => args.iter().all(is_pure),
+        ExprKind::Swizzle(base, _) | ExprKind::Field(base, _) => is_pure(base),
+        ExprKind::Call(..) | ExprKind::Assign(..) | ExprKind::Comma(..) => false,
+    }
+}
+
+/// Adds temporaries to the function being lowered
+struct Temps<'a> {
+    locals: &'a mut Vec<Local>,
+}
+
+impl Temps<'_> {
+    /// A new local of type `ty`
+    ///
+    /// The name contains a `.` so it cannot clash with a GLSL identifier.
+    fn new_local(&mut self, ty: &Type) -> Expr {
+        let id = LocalId(self.locals.len() as u32);
+        self.locals.push(Local {
+            name: format!("tmp.{}", id.0),
+            ty: ty.clone(),
+            is_const: false,
+            precision: None,
+        });
+        Expr::new(ExprKind::Var(VarRef::Local(id)), ty.clone())
+    }
+
+    /// Store `value` in a new temporary, returning the store and a read of the temporary
+    fn store(&mut self, value: Expr) -> (Expr, Expr) {
+        let tmp = self.new_local(&value.ty);
+        let ty = value.ty.clone();
+        let store = Expr::new(
+            ExprKind::Assign(None, Box::new(tmp.clone()), Box::new(value)),
+            ty,
+        );
+        (store, tmp)
+    }
+
+    /// `expr` itself if it may be evaluated repeatedly, else a temporary holding it
+    fn reusable(&mut self, expr: Expr, seq: &mut Vec<Expr>) -> Expr {
+        if is_pure(&expr) {
+            return expr;
+        }
+        let (store, tmp) = self.store(expr);
+        seq.push(store);
+        tmp
+    }
+}
+
+/// Chain expressions with the comma operator; the result is the last one's value
+fn sequence(exprs: Vec<Expr>) -> Expr {
+    let mut exprs = exprs.into_iter().rev();
+    let last = exprs.next().expect("empty sequence");
+    exprs.fold(last, |rest, expr| {
+        let ty = rest.ty.clone();
+        Expr::new(ExprKind::Comma(Box::new(expr), Box::new(rest)), ty)
+    })
+}
+
+/// Lower every defined function of a module with `f`
+fn for_each_function(module: &mut Module, mut f: impl FnMut(&mut Function)) {
+    for function in &mut module.functions {
+        if function.body.is_some() {
+            f(function);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! Component-wise lowering of swizzles
+//!
+//! A read of several components such as `col.bgr` becomes a constructor of
+//! single-component selections. A store through a write mask such as
+//! `col.rgb = ...`, `p.xy += t`, `v.xy++` or an `out` argument `f(v.zw)`
+//! evaluates the stored value once into a temporary, then assigns it to the
+//! masked components one at a time. Non-literal indices in the assigned
+//! place are evaluated once, before the value.
+
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use super::{for_each_expr, for_each_function, is_pure, sequence, take, Temps};
+use crate::sema::{
+    hir::{
+        BinaryOp, Expr, ExprKind, Function, FunctionId, Literal, Module, ParamQualifier, UnaryOp,
+    },
+    ScalarType, Type,
+};
+
+pub(super) fn lower(module: &mut Module) {
+    let qualifiers: Vec<Vec<ParamQualifier>> = module
+        .functions
+        .iter()
+        .map(|f| f.params.iter().map(|p| p.qualifier).collect())
+        .collect();
+    for_each_function(module, |function| {
+        let Function { body, locals, .. } = function;
+        let mut lowering = Lowering {
+            temps: Temps { locals },
+            qualifiers: &qualifiers,
+        };
+        for_each_expr(body.as_mut().expect("lowering a prototype"), &mut |expr| {
+            *expr = lowering.expr(take(expr));
+        });
+    });
+}
+
+struct Lowering<'a> {
+    temps: Temps<'a>,
+    /// Parameter qualifiers of every function in the module
+    qualifiers: &'a [Vec<ParamQualifier>],
+}
+
+impl Lowering<'_> {
+    fn expr(&mut self, expr: Expr) -> Expr {
+        let Expr { kind, ty } = expr;
+        let kind = match kind {
+            ExprKind::Assign(op, lhs, rhs) => return self.assign(op, *lhs, *rhs, ty),
+            ExprKind::Unary(op, operand) if op.is_inc_dec() => {
+                return self.inc_dec(op, *operand, ty)
+            }
+            ExprKind::Call(id, args) => return self.call(id, args, ty),
+            ExprKind::Swizzle(base, mask) => {
+                let base = self.expr(*base);
+                if mask.len() == 1 {
+                    ExprKind::Swizzle(Box::new(base), mask)
+                } else {
+                    let mut seq = Vec::new();
+                    let base = self.temps.reusable(base, &mut seq);
+                    seq.push(read(&base, &mask, &ty));
+                    return sequence(seq);
+                }
+            }
+            ExprKind::Literal(_) | ExprKind::Var(_) => kind,
+            ExprKind::Unary(op, operand) => ExprKind::Unary(op, self.boxed(*operand)),
+            ExprKind::Binary(op, lhs, rhs) => {
+                ExprKind::Binary(op, self.boxed(*lhs), self.boxed(*rhs))
+            }
+            ExprKind::Ternary(cond, then, otherwise) => {
+                ExprKind::Ternary(self.boxed(*cond), self.boxed(*then), self.boxed(*otherwise))
+            }
+            ExprKind::Builtin(builtin, args) => ExprKind::Builtin(builtin, self.all(args)),
+            ExprKind::Construct(args) => ExprKind::Construct(self.all(args)),
+            ExprKind::Field(base, index) => ExprKind::Field(self.boxed(*base), index),
+            ExprKind::Index(base, index) => ExprKind::Index(self.boxed(*base), self.boxed(*index)),
+            ExprKind::Comma(first, second) => {
+                ExprKind::Comma(self.boxed(*first), self.boxed(*second))
+            }
+        };
+        Expr::new(kind, ty)
+    }
+
+    fn boxed(&mut self, expr: Expr) -> Box<Expr> {
+        Box::new(self.expr(expr))
+    }
+
+    fn all(&mut self, exprs: Vec<Expr>) -> Vec<Expr> {
+        exprs.into_iter().map(|e| self.expr(e)).collect()
+    }
+
+    fn assign(&mut self, op: Option<BinaryOp>, lhs: Expr, rhs: Expr, ty: Type) -> Expr {
+        let mut seq = Vec::new();
+        let place = self.place(lhs, &mut seq);
+        let rhs = self.expr(rhs);
+        match place.kind {
+            ExprKind::Swizzle(base, mask) if mask.len() > 1 => {
+                let value = match op {
+                    None => rhs,
+                    Some(op) => Expr::new(
+                        ExprKind::Binary(op, Box::new(read(&base, &mask, &ty)), Box::new(rhs)),
+                        ty,
+                    ),
+                };
+                let (store, value) = self.temps.store(value);
+                seq.push(store);
+                write(&base, &mask, &value, &mut seq);
+                seq.push(value);
+            }
+            kind => {
+                let place = Expr::new(kind, place.ty);
+                seq.push(Expr::new(
+                    ExprKind::Assign(op, Box::new(place), Box::new(rhs)),
+                    ty,
+                ));
+            }
+        }
+        sequence(seq)
+    }
+
+    fn inc_dec(&mut self, op: UnaryOp, operand: Expr, ty: Type) -> Expr {
+        let mut seq = Vec::new();
+        let place = self.place(operand, &mut seq);
+        let (base, mask) = match place.kind {
+            ExprKind::Swizzle(base, mask) if mask.len() > 1 => (base, mask),
+            kind => {
+                let place = Expr::new(kind, place.ty);
+                seq.push(Expr::new(ExprKind::Unary(op, Box::new(place)), ty));
+                return sequence(seq);
+            }
+        };
+        let (store, old) = self.temps.store(read(&base, &mask, &ty));
+        seq.push(store);
+        let scalar = ty.scalar_type().expect("increment of a non-vector");
+        let one = match scalar {
+            ScalarType::Int => Literal::Int(1),
+            ScalarType::UInt => Literal::UInt(1),
+            _ => Literal::Float(1.0),
+        };
+        let step = match op {
+            UnaryOp::PreInc | UnaryOp::PostInc => BinaryOp::Add,
+            _ => BinaryOp::Sub,
+        };
+        let one = Expr::new(ExprKind::Literal(one), Type::Scalar(scalar));
+        let (store, new) = self.temps.store(Expr::new(
+            ExprKind::Binary(step, Box::new(old.clone()), Box::new(one)),
+            ty,
+        ));
+        seq.push(store);
+        write(&base, &mask, &new, &mut seq);
+        seq.push(match op {
+            UnaryOp::PreInc | UnaryOp::PreDec => new,
+            _ => old,
+        });
+        sequence(seq)
+    }
+
+    /// Lower a call, passing masked `out` and `inout` arguments through temporaries
+    fn call(&mut self, id: FunctionId, args: Vec<Expr>, ty: Type) -> Expr {
+        let qualifiers = self.qualifiers;
+        let mut seq = Vec::new();
+        let mut writes = Vec::new();
+        let mut lowered = Vec::with_capacity(args.len());
+        for (arg, qualifier) in args.into_iter().zip(&qualifiers[id.0 as usize]) {
+            if *qualifier == ParamQualifier::In {
+                lowered.push(self.expr(arg));
+                continue;
+            }
+            let place = self.place(arg, &mut seq);
+            match place.kind {
+                ExprKind::Swizzle(base, mask) if mask.len() > 1 => {
+                    let tmp = self.temps.new_local(&place.ty);
+                    if *qualifier == ParamQualifier::InOut {
+                        let value = read(&base, &mask, &place.ty);
+                        seq.push(Expr::new(
+                            ExprKind::Assign(None, Box::new(tmp.clone()), Box::new(value)),
+                            place.ty,
+                        ));
+                    }
+                    writes.push((base, mask, tmp.clone()));
+                    lowered.push(tmp);
+                }
+                kind => lowered.push(Expr::new(kind, place.ty)),
+            }
+        }
+        let call = Expr::new(ExprKind::Call(id, lowered), ty);
+        if writes.is_empty() {
+            seq.push(call);
+            return sequence(seq);
+        }
+        // The value of a call returning void is never used
+        let result = if call.ty == Type::Void {
+            seq.push(call);
+            None
+        } else {
+            let (store, result) = self.temps.store(call);
+            seq.push(store);
+            Some(result)
+        };
+        for (base, mask, tmp) in &writes {
+            write(base, mask, tmp, &mut seq);
+        }
+        seq.extend(result);
+        sequence(seq)
+    }
+
+    /// Lower an assigned place, hoisting what must only be evaluated once into `seq`
+    ///
+    /// Places stored through a write mask are read and written several
+    /// times, so their non-literal indices are moved into temporaries.
+    fn place(&mut self, expr: Expr, seq: &mut Vec<Expr>) -> Expr {
+        let masked = matches!(&expr.kind, ExprKind::Swizzle(_, mask) if mask.len() > 1);
+        self.place_inner(expr, masked, seq)
+    }
+
+    fn place_inner(&mut self, expr: Expr, hoist: bool, seq: &mut Vec<Expr>) -> Expr {
+        let Expr { kind, ty } = expr;
+        let kind = match kind {
+            ExprKind::Field(base, index) => {
+                ExprKind::Field(Box::new(self.place_inner(*base, hoist, seq)), index)
+            }
+            ExprKind::Swizzle(base, mask) => {
+                ExprKind::Swizzle(Box::new(self.place_inner(*base, hoist, seq)), mask)
+            }
+            ExprKind::Index(base, index) => {
+                let base = self.place_inner(*base, hoist, seq);
+                let index = self.expr(*index);
+                let index = match index.kind {
+                    ExprKind::Literal(_) => index,
+                    _ if hoist || !is_pure(&index) => {
+                        let (store, tmp) = self.temps.store(index);
+                        seq.push(store);
+                        tmp
+                    }
+                    _ => index,
+                };
+                ExprKind::Index(Box::new(base), Box::new(index))
+            }
+            other => other,
+        };
+        Expr::new(kind, ty)
+    }
+}
+
+/// Component `c` of a scalar or vector
+fn component(base: &Expr, c: u8) -> Expr {
+    let ty = Type::Scalar(base.ty.scalar_type().expect("swizzle of a non-vector"));
+    Expr::new(ExprKind::Swizzle(Box::new(base.clone()), vec![c]), ty)
+}
+
+/// Read the components `mask` of `base` as a value of type `ty`
+fn read(base: &Expr, mask: &[u8], ty: &Type) -> Expr {
+    let components = mask.iter().map(|&c| component(base, c)).collect();
+    Expr::new(ExprKind::Construct(components), ty.clone())
+}
+
+/// Assign each component of `value` to the matching masked component of `base`
+fn write(base: &Expr, mask: &[u8], value: &Expr, seq: &mut Vec<Expr>) {
+    for (i, &c) in mask.iter().enumerate() {
+        let target = component(base, c);
+        let ty = target.ty.clone();
+        seq.push(Expr::new(
+            ExprKind::Assign(None, Box::new(target), Box::new(component(value, i as u8))),
+            ty,
+        ));
+    }
+}
@@ -0,0 +1,531 @@
+//! Lowering of `float` to fixed point
+//!
+//! Once vectors are split into scalars, every `f32` value becomes an `i32`
+//! holding the scaled integer, and float instructions become integer ones.
+//! Comparisons, selects, loads and stores are unchanged. Products are
+//! assembled from the low and high words of the 64-bit product given by
+//! `mul` and `mulh`. Quotients in 32-bit formats call a division function
+//! added to the module, which divides magnitudes with `udiv` and `urem` and
+//! returns both the wrapped and the saturated quotient; narrow formats
+//! divide with a single `div`. Results that may overflow are checked as
+//! [`Overflow`] says. Builtins that take a few instructions are expanded
+//! inline, and the others call the routines of
+//! [`runtime::fixed`](crate::runtime::fixed), declared in the module under
+//! their symbol.
+
+use alloc::{collections::BTreeMap, format, string::String, vec, vec::Vec};
+
+use super::{precision::Formats, Callees, Fixed, Numeric, Overflow};
+use crate::{
+    ir::{
+        BinaryOp, Builtin, CompareOp, Constant, ConvertOp, Function, FunctionBuilder, Inst,
+        InstKind, ScalarType, Signature, Type, UnaryOp, Value,
+    },
+    runtime::fixed::{symbol, BUILTINS},
+};
+
+/// `π / 180` scaled by 2^32, for `radians` with `mulh`
+const RADIANS_PER_DEGREE: i32 = 74_961_321;
+
+pub(super) fn lower(func: &mut Function, format: Fixed, formats: &Formats, callees: &mut Callees) {
+    let mut lowering = Lowering {
+        func,
+        format,
+        formats,
+        callees,
+        out: Vec::new(),
+        aliases: BTreeMap::new(),
+    };
+    lowering.run();
+}
+
+/// Division in a 32-bit format, returning the wrapped and the saturated
+/// quotient, rounded toward zero
+///
+/// The integer part of the quotient of the magnitudes comes from `udiv`,
+/// and the fractional bits from dividing the remainder: with one more
+/// `udiv` when shifting it left by the fractional bits cannot overflow,
+/// else by long division a bit at a time.
+fn division(name: String, format: Fixed) -> Function {
+    let frac_bits = format.frac_bits as i32;
+    let signature = Signature {
+        params: vec![Type::I32; 2],
+        returns: vec![Type::I32; 2],
+    };
+    let mut func = Function::new(name, signature);
+    let mut b = FunctionBuilder::new(&mut func);
+    let entry = b.create_entry_block();
+    let by_zero = b.create_block();
+    let divide = b.create_block();
+    let short = b.create_block();
+    let long = b.create_block();
+    let step = b.create_block();
+    let done = b.create_block();
+    let i = b.append_block_param(long, Type::I32);
+    let frac = b.append_block_param(long, Type::I32);
+    let rem = b.append_block_param(long, Type::I32);
+    let frac_done = b.append_block_param(done, Type::I32);
+
+    b.switch_to_block(entry);
+    let (x, y) = (b.block_params(entry)[0], b.block_params(entry)[1]);
+    let zero = b.iconst(0);
+    let is_zero = b.compare(CompareOp::Eq, y, zero);
+    b.brif(is_zero, by_zero, &[], divide, &[]);
+
+    // The extreme with the sign of the dividend
+    b.switch_to_block(by_zero);
+    let sign = b.iconst(31);
+    let sign = b.binary(BinaryOp::Shr, x, sign);
+    let max = b.iconst(format.max());
+    let extreme = b.binary(BinaryOp::Xor, sign, max);
+    b.ret(&[extreme, extreme]);
+
+    b.switch_to_block(divide);
+    let magnitude = |b: &mut FunctionBuilder, v: Value| {
+        let negative = b.compare(CompareOp::Lt, v, zero);
+        let negated = b.unary(UnaryOp::Neg, v);
+        b.select(negative, negated, v)
+    };
+    let (ux, uy) = (magnitude(&mut b, x), magnitude(&mut b, y));
+    let int = b.binary(BinaryOp::UDiv, ux, uy);
+    let first_rem = b.binary(BinaryOp::URem, ux, uy);
+    let limit = b.iconst(1 << (32 - frac_bits));
+    let fits = b.compare(CompareOp::ULt, uy, limit);
+    b.brif(fits, short, &[], long, &[zero, zero, first_rem]);
+
+    b.switch_to_block(short);
+    let shift = b.iconst(frac_bits);
+    let scaled = b.binary(BinaryOp::Shl, first_rem, shift);
+    let short_frac = b.binary(BinaryOp::UDiv, scaled, uy);
+    b.jump(done, &[short_frac]);
+
+    // One bit of the fraction per iteration, the remainder staying below
+    // the divisor so that doubling it cannot overflow
+    b.switch_to_block(long);
+    let count = b.iconst(frac_bits);
+    let more = b.compare(CompareOp::Lt, i, count);
+    b.brif(more, step, &[], done, &[frac]);
+
+    b.switch_to_block(step);
+    let one = b.iconst(1);
+    let rem = b.binary(BinaryOp::Shl, rem, one);
+    let frac = b.binary(BinaryOp::Shl, frac, one);
+    let bit = b.compare(CompareOp::UGe, rem, uy);
+    let reduced = b.binary(BinaryOp::Sub, rem, uy);
+    let rem = b.select(bit, reduced, rem);
+    let set = b.binary(BinaryOp::Or, frac, one);
+    let frac = b.select(bit, set, frac);
+    let i = b.binary(BinaryOp::Add, i, one);
+    b.jump(long, &[i, frac, rem]);
+
+    b.switch_to_block(done);
+    let shift = b.iconst(frac_bits);
+    let high = b.binary(BinaryOp::Shl, int, shift);
+    let quotient = b.binary(BinaryOp::Or, high, frac_done);
+    let signs = b.binary(BinaryOp::Xor, x, y);
+    let negative = b.compare(CompareOp::Lt, signs, zero);
+    let negated = b.unary(UnaryOp::Neg, quotient);
+    let wrapped = b.select(negative, negated, quotient);
+    let limit = b.iconst(1 << (31 - frac_bits));
+    let overflow = b.compare(CompareOp::UGe, int, limit);
+    let sign = b.iconst(31);
+    let sign = b.binary(BinaryOp::Shr, signs, sign);
+    let max = b.iconst(format.max());
+    let extreme = b.binary(BinaryOp::Xor, sign, max);
+    let saturated = b.select(overflow, extreme, wrapped);
+    b.ret(&[wrapped, saturated]);
+    b.finish();
+    func
+}
+
+struct Lowering<'a> {
+    func: &'a mut Function,
+    formats: &'a Formats,
+    format: Fixed,
+    callees: &'a mut Callees,
+    /// Instructions of the block being rewritten
+    out: Vec<Inst>,
+    /// Results of removed instructions and the values replacing them
+    aliases: BTreeMap<Value, Value>,
+}
+
+impl Lowering<'_> {
+    fn run(&mut self) {
+        for i in 0..self.func.layout.len() {
+            let block = self.func.layout[i];
+            for inst in core::mem::take(&mut self.func.block_mut(block).insts) {
+                if self.formats.insts.get(&inst) == Some(&Numeric::Fixed(self.format)) {
+                    self.inst(inst);
+                } else {
+                    self.out.push(inst);
+                }
+            }
+            self.func.block_mut(block).insts = core::mem::take(&mut self.out);
+        }
+        self.func.replace_uses(&self.aliases);
+    }
+
+    fn is_float(&self, value: Value) -> bool {
+        self.formats.values.contains_key(&value)
+    }
+
+    fn inst(&mut self, inst: Inst) {
+        let kind = self.func.inst(inst).kind.clone();
+        let results = self.func.inst(inst).results.clone();
+        let lowered = match kind {
+            InstKind::Const(Constant::F32(x)) => self.iconst(self.format.from_f32(x)),
+            InstKind::Unary(UnaryOp::Neg, x) if self.is_float(x) => self.neg(x),
+            InstKind::Binary(op, x, y) if self.is_float(x) => match op {
+                BinaryOp::Add => self.add(x, y),
+                BinaryOp::Sub => self.sub(x, y),
+                BinaryOp::Mul => self.mul(x, y),
+                BinaryOp::Div => self.div(x, y),
+                _ => unreachable!("`{}` of f32", op.name()),
+            },
+            InstKind::Convert(op, x) => self.convert(op, x),
+            InstKind::Builtin(builtin, args)
+                if args.iter().chain(&results).any(|&v| self.is_float(v)) =>
+            {
+                self.builtin(builtin, &args)
+            }
+            _ => {
+                self.out.push(inst);
+                return;
+            }
+        };
+        self.aliases.insert(results[0], lowered);
+        self.func.detach_results(inst);
+    }
+
+    /// Append an instruction with one result of type `ty`
+    fn emit(&mut self, kind: InstKind, ty: Type) -> Value {
+        let inst = self.func.new_inst(kind, &[ty]);
+        self.out.push(inst);
+        self.func.inst(inst).results[0]
+    }
+
+    fn iconst(&mut self, x: i32) -> Value {
+        self.emit(InstKind::Const(Constant::I32(x)), Type::I32)
+    }
+
+    fn binary(&mut self, op: BinaryOp, x: Value, y: Value) -> Value {
+        self.emit(InstKind::Binary(op, x, y), Type::I32)
+    }
+
+    /// `op` of `x` and a constant
+    fn binary_imm(&mut self, op: BinaryOp, x: Value, y: i32) -> Value {
+        let y = self.iconst(y);
+        self.binary(op, x, y)
+    }
+
+    fn compare(&mut self, op: CompareOp, x: Value, y: Value) -> Value {
+        self.emit(InstKind::Compare(op, x, y), Type::BOOL)
+    }
+
+    fn compare_imm(&mut self, op: CompareOp, x: Value, y: i32) -> Value {
+        let y = self.iconst(y);
+        self.compare(op, x, y)
+    }
+
+    fn select(&mut self, cond: Value, x: Value, y: Value) -> Value {
+        self.emit(InstKind::Select(cond, x, y), Type::I32)
+    }
+
+    fn is_word(&self) -> bool {
+        self.format.bits() == 32
+    }
+
+    fn saturates(&self) -> bool {
+        self.format.overflow == Overflow::Saturate
+    }
+
+    /// The largest value if `x` is positive or zero, else the smallest
+    fn extreme(&mut self, x: Value) -> Value {
+        let sign = self.binary_imm(BinaryOp::Shr, x, 31);
+        self.binary_imm(BinaryOp::Xor, sign, self.format.max())
+    }
+
+    /// `x` clamped to `lo..=hi`
+    fn clamp(&mut self, x: Value, lo: i32, hi: i32) -> Value {
+        let (lo, hi) = (self.iconst(lo), self.iconst(hi));
+        let above = self.compare(CompareOp::Gt, x, hi);
+        let below = self.compare(CompareOp::Lt, x, lo);
+        let x = self.select(below, lo, x);
+        self.select(above, hi, x)
+    }
+
+    /// Bring a result computed in 32 bits into a narrower format
+    fn fit(&mut self, x: Value) -> Value {
+        if self.is_word() {
+            return x;
+        }
+        if self.saturates() {
+            return self.clamp(x, self.format.min(), self.format.max());
+        }
+        let unused = 32 - self.format.bits() as i32;
+        let x = self.binary_imm(BinaryOp::Shl, x, unused);
+        self.binary_imm(BinaryOp::Shr, x, unused)
+    }
+
+    /// `sum` in place of a 32-bit sum or difference that overflowed, which
+    /// the sign of `flags` tells
+    fn saturate_sum(&mut self, x: Value, sum: Value, flags: Value) -> Value {
+        let overflow = self.compare_imm(CompareOp::Lt, flags, 0);
+        let extreme = self.extreme(x);
+        self.select(overflow, extreme, sum)
+    }
+
+    fn add(&mut self, x: Value, y: Value) -> Value {
+        let sum = self.binary(BinaryOp::Add, x, y);
+        if !self.is_word() {
+            return self.fit(sum);
+        }
+        if !self.saturates() {
+            return sum;
+        }
+        // Overflow gives a sum of the other sign than both operands
+        let a = self.binary(BinaryOp::Xor, x, sum);
+        let b = self.binary(BinaryOp::Xor, y, sum);
+        let flags = self.binary(BinaryOp::And, a, b);
+        self.saturate_sum(x, sum, flags)
+    }
+
+    fn sub(&mut self, x: Value, y: Value) -> Value {
+        let diff = self.binary(BinaryOp::Sub, x, y);
+        if !self.is_word() {
+            return self.fit(diff);
+        }
+        if !self.saturates() {
+            return diff;
+        }
+        // Overflow needs operands of different signs, and gives a difference
+        // of the other sign than `x`
+        let a = self.binary(BinaryOp::Xor, x, y);
+        let b = self.binary(BinaryOp::Xor, x, diff);
+        let flags = self.binary(BinaryOp::And, a, b);
+        self.saturate_sum(x, diff, flags)
+    }
+
+    fn neg(&mut self, x: Value) -> Value {
+        let neg = self.emit(InstKind::Unary(UnaryOp::Neg, x), Type::I32);
+        if !self.is_word() {
+            return self.fit(neg);
+        }
+        if !self.saturates() {
+            return neg;
+        }
+        let min = self.compare_imm(CompareOp::Eq, x, self.format.min());
+        let max = self.iconst(self.format.max());
+        self.select(min, max, neg)
+    }
+
+    fn mul(&mut self, x: Value, y: Value) -> Value {
+        let frac_bits = self.format.frac_bits as i32;
+        let low = self.binary(BinaryOp::Mul, x, y);
+        if !self.is_word() {
+            // Narrow operands cannot overflow a word
+            let product = self.binary_imm(BinaryOp::Shr, low, frac_bits);
+            return self.fit(product);
+        }
+        let high = self.emit(InstKind::Binary(BinaryOp::MulH, x, y), Type::I32);
+        let top = self.binary_imm(BinaryOp::Shl, high, 32 - frac_bits);
+        let bottom = self.binary_imm(BinaryOp::UShr, low, frac_bits);
+        let product = self.binary(BinaryOp::Or, top, bottom);
+        if !self.saturates() {
+            return product;
+        }
+        // The product fits if the bits above it are copies of its sign
+        let above = self.binary_imm(BinaryOp::Shr, high, frac_bits - 1);
+        let sign = self.binary_imm(BinaryOp::Shr, product, 31);
+        let overflow = self.compare(CompareOp::Ne, above, sign);
+        let extreme = self.extreme(high);
+        self.select(overflow, extreme, product)
+    }
+
+    fn div(&mut self, x: Value, y: Value) -> Value {
+        if self.is_word() {
+            let format = self.format;
+            let division = self
+                .callees
+                .find_or_add(format!("lp.{}.div", format), |name| division(name, format));
+            let inst = self
+                .func
+                .new_inst(InstKind::Call(division, vec![x, y]), &[Type::I32; 2]);
+            self.out.push(inst);
+            let quotients = &self.func.inst(inst).results;
+            return quotients[self.saturates() as usize];
+        }
+        // Narrow dividends still fit a word once scaled
+        let scaled = self.binary_imm(BinaryOp::Shl, x, self.format.frac_bits as i32);
+        let quotient = self.binary(BinaryOp::Div, scaled, y);
+        let by_zero = self.compare_imm(CompareOp::Eq, y, 0);
+        let extreme = self.extreme(x);
+        let quotient = self.select(by_zero, extreme, quotient);
+        self.fit(quotient)
+    }
+
+    /// Integer part of `x`, rounded toward zero
+    fn integer_part(&mut self, x: Value) -> Value {
+        let frac_bits = self.format.frac_bits as i32;
+        // Negative values are biased by just under one first
+        let sign = self.binary_imm(BinaryOp::Shr, x, 31);
+        let bias = self.binary_imm(BinaryOp::UShr, sign, 32 - frac_bits);
+        let biased = self.binary(BinaryOp::Add, x, bias);
+        self.binary_imm(BinaryOp::Shr, biased, frac_bits)
+    }
+
+    fn convert(&mut self, op: ConvertOp, x: Value) -> Value {
+        let frac_bits = self.format.frac_bits as i32;
+        let max_int = self.format.max() >> frac_bits;
+        let min_int = self.format.min() >> frac_bits;
+        match op {
+            ConvertOp::SIntToFloat if self.saturates() => {
+                let scaled = self.binary_imm(BinaryOp::Shl, x, frac_bits);
+                let above = self.compare_imm(CompareOp::Gt, x, max_int);
+                let below = self.compare_imm(CompareOp::Lt, x, min_int);
+                let (max, min) = (
+                    self.iconst(self.format.max()),
+                    self.iconst(self.format.min()),
+                );
+                let scaled = self.select(below, min, scaled);
+                self.select(above, max, scaled)
+            }
+            ConvertOp::UIntToFloat if self.saturates() => {
+                let scaled = self.binary_imm(BinaryOp::Shl, x, frac_bits);
+                let above = self.compare_imm(CompareOp::UGt, x, max_int);
+                let max = self.iconst(self.format.max());
+                self.select(above, max, scaled)
+            }
+            ConvertOp::SIntToFloat | ConvertOp::UIntToFloat => {
+                let scaled = self.binary_imm(BinaryOp::Shl, x, frac_bits);
+                self.fit(scaled)
+            }
+            ConvertOp::FloatToSInt => self.integer_part(x),
+            ConvertOp::FloatToUInt => {
+                let negative = self.compare_imm(CompareOp::Lt, x, 0);
+                let zero = self.iconst(0);
+                let int = self.binary_imm(BinaryOp::Shr, x, frac_bits);
+                self.select(negative, zero, int)
+            }
+        }
+    }
+
+    fn builtin(&mut self, builtin: Builtin, args: &[Value]) -> Value {
+        use Builtin as B;
+
+        let one = self.format.one();
+        let x = args[0];
+        match builtin {
+            _ if BUILTINS.contains(&builtin) => self.call(builtin, args),
+            B::Abs => {
+                let negative = self.compare_imm(CompareOp::Lt, x, 0);
+                let neg = self.neg(x);
+                self.select(negative, neg, x)
+            }
+            B::Sign => {
+                let positive = self.compare_imm(CompareOp::Gt, x, 0);
+                let negative = self.compare_imm(CompareOp::Lt, x, 0);
+                let (one, minus_one, zero) = (self.iconst(one), self.iconst(-one), self.iconst(0));
+                let sign = self.select(negative, minus_one, zero);
+                self.select(positive, one, sign)
+            }
+            B::Floor => self.binary_imm(BinaryOp::And, x, !(one - 1)),
+            B::Ceil => {
+                let floor = self.binary_imm(BinaryOp::And, x, !(one - 1));
+                let whole = self.compare(CompareOp::Eq, floor, x);
+                let one = self.iconst(one);
+                let next = self.add(floor, one);
+                self.select(whole, floor, next)
+            }
+            B::Fract => self.binary_imm(BinaryOp::And, x, one - 1),
+            B::Trunc => {
+                let int = self.integer_part(x);
+                self.binary_imm(BinaryOp::Shl, int, self.format.frac_bits as i32)
+            }
+            B::Mod => {
+                // Both are scaled alike, so the remainder is exact; it takes
+                // the sign of the divisor as `x - y * floor(x / y)` does
+                let y = args[1];
+                let rem = self.binary(BinaryOp::Rem, x, y);
+                let nonzero = self.compare_imm(CompareOp::Ne, rem, 0);
+                let signs = self.binary(BinaryOp::Xor, rem, y);
+                let differ = self.compare_imm(CompareOp::Lt, signs, 0);
+                let adjust =
+                    self.emit(InstKind::Binary(BinaryOp::And, nonzero, differ), Type::BOOL);
+                let adjusted = self.binary(BinaryOp::Add, rem, y);
+                self.select(adjust, adjusted, rem)
+            }
+            B::Min => self.min(x, args[1]),
+            B::Max => self.max(x, args[1]),
+            B::Clamp => {
+                let max = self.max(x, args[1]);
+                self.min(max, args[2])
+            }
+            B::Mix if self.func.value_type(args[2]).scalar == ScalarType::Bool => {
+                self.select(args[2], args[1], x)
+            }
+            B::Mix => {
+                let one = self.iconst(one);
+                let rest = self.sub(one, args[2]);
+                let a = self.mul(x, rest);
+                let b = self.mul(args[1], args[2]);
+                self.add(a, b)
+            }
+            B::Step => {
+                let below = self.compare(CompareOp::Lt, args[1], x);
+                let (zero, one) = (self.iconst(0), self.iconst(one));
+                self.select(below, zero, one)
+            }
+            B::Radians => {
+                let scale = self.iconst(RADIANS_PER_DEGREE);
+                self.emit(InstKind::Binary(BinaryOp::MulH, x, scale), Type::I32)
+            }
+            B::Degrees => {
+                let scale = self.iconst(self.format.from_f32(180.0 / core::f32::consts::PI));
+                self.mul(x, scale)
+            }
+            B::IsNan | B::IsInf => self.emit(InstKind::Const(Constant::Bool(false)), Type::BOOL),
+            // The bits of a fixed-point value are its encoding
+            B::FloatBitsToInt | B::FloatBitsToUint | B::IntBitsToFloat | B::UintBitsToFloat => x,
+            B::LessThan => self.compare(CompareOp::Lt, x, args[1]),
+            B::LessThanEqual => self.compare(CompareOp::Le, x, args[1]),
+            B::GreaterThan => self.compare(CompareOp::Gt, x, args[1]),
+            B::GreaterThanEqual => self.compare(CompareOp::Ge, x, args[1]),
+            B::Equal => self.compare(CompareOp::Eq, x, args[1]),
+            B::NotEqual => self.compare(CompareOp::Ne, x, args[1]),
+            _ => unreachable!("`{}` of float after scalarisation", builtin.name()),
+        }
+    }
+
+    fn min(&mut self, x: Value, y: Value) -> Value {
+        let less = self.compare(CompareOp::Lt, y, x);
+        self.select(less, y, x)
+    }
+
+    fn max(&mut self, x: Value, y: Value) -> Value {
+        let greater = self.compare(CompareOp::Gt, y, x);
+        self.select(greater, y, x)
+    }
+
+    /// Call the runtime routine for `builtin`, passing vectors lane by lane
+    fn call(&mut self, builtin: Builtin, args: &[Value]) -> Value {
+        let mut scalars = Vec::new();
+        for &arg in args {
+            let ty = self.func.value_type(arg);
+            if !ty.is_vector() {
+                scalars.push(arg);
+                continue;
+            }
+            for lane in 0..ty.lanes {
+                scalars.push(self.emit(InstKind::Extract(arg, lane), Type::I32));
+            }
+        }
+        let name = symbol(self.format, builtin, scalars.len());
+        let routine = self.callees.routine(name, scalars.len(), Type::I32);
+        let inst = self
+            .func
+            .new_inst(InstKind::Call(routine, scalars), &[Type::I32]);
+        self.out.push(inst);
+        self.func.inst(inst).results[0]
+    }
+}
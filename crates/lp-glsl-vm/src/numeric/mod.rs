@@ -0,0 +1,315 @@
+//! Numeric formats for GLSL `float`
+//!
+//! The IR computes `float` in IEEE 754 single precision, which guests
+//! without floating point hardware can only emulate. [`lower`] rewrites a
+//! module to compute it in another [`Numeric`] format instead, such as
+//! [`Fixed`] point in integer registers, or IEEE floats computed by integer
+//! routines. Hosts exchanging values with lowered code, such as uniforms and
+//! output colours, convert them with [`Numeric::encode`] and
+//! [`Numeric::decode`].
+//!
+//! The format may depend on the precision qualifiers of the shader, as
+//! given by [`Precisions`], so that `lowp` colour math runs in a narrow
+//! fixed-point format while `highp` coordinates keep more bits.
+
+mod fixed;
+mod precision;
+mod soft_float;
+
+use alloc::{collections::BTreeSet, string::String, vec, vec::Vec};
+use core::fmt;
+
+use crate::{
+    ir::{FuncRef, Function, Module, ScalarType, Signature, Type, Value},
+    opt::{OptLevel, Pass, PassManager},
+    sema::hir::Precision,
+};
+
+/// Representation of `float` values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Numeric {
+    /// IEEE 754 single precision, as in the IR
+    #[default]
+    Float,
+    Fixed(Fixed),
+    /// IEEE 754 single precision held in integer registers, every operation
+    /// calling a [`soft_float`](crate::runtime::soft_float) routine: slower
+    /// than fixed point, but with the range and results of `f32`
+    SoftFloat,
+}
+
+impl Numeric {
+    /// Bits of `x` in this format, as stored in a 32-bit word
+    pub fn encode(self, x: f32) -> u32 {
+        match self {
+            Numeric::Float | Numeric::SoftFloat => x.to_bits(),
+            Numeric::Fixed(fixed) => fixed.from_f32(x) as u32,
+        }
+    }
+
+    /// Value of the bits of a word in this format
+    pub fn decode(self, bits: u32) -> f32 {
+        match self {
+            Numeric::Float | Numeric::SoftFloat => f32::from_bits(bits),
+            Numeric::Fixed(fixed) => fixed.to_f32(bits as i32),
+        }
+    }
+}
+
+impl fmt::Display for Numeric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Numeric::Float => write!(f, "f32"),
+            Numeric::Fixed(fixed) => write!(f, "{}", fixed),
+            Numeric::SoftFloat => write!(f, "soft f32"),
+        }
+    }
+}
+
+/// Format of `float` at each precision qualifier
+///
+/// Values without a qualifier are `highp`, and so is every value stored in
+/// memory, passed to or returned from a function, or exchanged with the
+/// host: only the arithmetic on qualified variables runs in the `lowp` and
+/// `mediump` formats, converted to and from `high` where needed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Precisions {
+    pub low: Numeric,
+    pub medium: Numeric,
+    pub high: Numeric,
+}
+
+impl Precisions {
+    /// The same format at every precision
+    pub fn uniform(numeric: Numeric) -> Precisions {
+        Precisions {
+            low: numeric,
+            medium: numeric,
+            high: numeric,
+        }
+    }
+
+    pub fn format(self, precision: Precision) -> Numeric {
+        match precision {
+            Precision::Low => self.low,
+            Precision::Medium => self.medium,
+            Precision::High => self.high,
+        }
+    }
+
+    /// Every distinct format, highest precision first
+    fn formats(self) -> Vec<Numeric> {
+        let mut formats = vec![self.high];
+        for numeric in [self.medium, self.low] {
+            if !formats.contains(&numeric) {
+                formats.push(numeric);
+            }
+        }
+        formats
+    }
+}
+
+impl From<Numeric> for Precisions {
+    fn from(numeric: Numeric) -> Precisions {
+        Precisions::uniform(numeric)
+    }
+}
+
+/// What fixed-point arithmetic does with a result outside its range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Keep the low bits of the result, as integer arithmetic does: cheaper,
+    /// but a value past the largest one comes back as a large negative one
+    Wrap,
+    /// Clamp the result to the largest or smallest value of the format
+    Saturate,
+}
+
+/// Signed fixed-point format with `int_bits` integer bits, the sign bit
+/// included, and `frac_bits` fractional bits
+///
+/// Values are held in 32-bit words as integers scaled by `2^frac_bits`,
+/// sign-extended when the format is narrower. The format has 32 bits, or
+/// at most 16 so that products fit in a word.
+///
+/// Addition, subtraction, negation, multiplication, division and the
+/// conversions from integers follow `overflow`. Products are rounded
+/// toward negative infinity and quotients toward zero. Division by zero
+/// gives the largest or smallest value, with the sign of the dividend,
+/// whatever `overflow` says. Constants and the results of builtins always
+/// saturate, and builtins given an argument outside their domain return
+/// their value at the nearest point of it, so `sqrt` of a negative number
+/// is 0 and `log` of 0 is the smallest value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fixed {
+    pub int_bits: u8,
+    pub frac_bits: u8,
+    pub overflow: Overflow,
+}
+
+impl Fixed {
+    /// 16 integer and 16 fractional bits, saturating so that a colour
+    /// pushed past white stays white
+    pub const Q16_16: Fixed = Fixed::new(16, 16, Overflow::Saturate);
+
+    /// Panics if the format is not 32 bits wide or at most 16, or has no
+    /// integer or fractional bits
+    pub const fn new(int_bits: u8, frac_bits: u8, overflow: Overflow) -> Fixed {
+        let bits = int_bits as u32 + frac_bits as u32;
+        assert!(int_bits > 0 && frac_bits > 0, "empty fixed-point part");
+        assert!(bits == 32 || bits <= 16, "unsupported fixed-point width");
+        Fixed {
+            int_bits,
+            frac_bits,
+            overflow,
+        }
+    }
+
+    pub const fn with_overflow(self, overflow: Overflow) -> Fixed {
+        Fixed { overflow, ..self }
+    }
+
+    pub fn bits(self) -> u32 {
+        self.int_bits as u32 + self.frac_bits as u32
+    }
+
+    /// Encoding of 1.0
+    pub fn one(self) -> i32 {
+        1 << self.frac_bits
+    }
+
+    /// Largest encoding
+    pub fn max(self) -> i32 {
+        (((1u64 << (self.bits() - 1)) - 1) as u32) as i32
+    }
+
+    /// Smallest encoding
+    pub fn min(self) -> i32 {
+        !self.max()
+    }
+
+    /// Encoding of the value nearest to `x`, saturating, with NaN as 0
+    pub fn from_f32(self, x: f32) -> i32 {
+        let scaled = x as f64 * self.one() as f64;
+        // Rounds half away from zero; `as` saturates and maps NaN to 0
+        let rounded = if scaled < 0.0 {
+            scaled - 0.5
+        } else {
+            scaled + 0.5
+        } as i64;
+        rounded.clamp(self.min() as i64, self.max() as i64) as i32
+    }
+
+    pub fn to_f32(self, x: i32) -> f32 {
+        (x as f64 / self.one() as f64) as f32
+    }
+
+    /// Difference between consecutive values
+    pub fn epsilon(self) -> f32 {
+        self.to_f32(1)
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "q{}.{}", self.int_bits, self.frac_bits)
+    }
+}
+
+/// Rewrite a module from the IR translation of a shader to compute `float`
+/// in the format of each precision
+///
+/// Vectors are split into scalars first, as the routines of the other
+/// formats only take scalars.
+pub fn lower(module: &mut Module, precisions: impl Into<Precisions>) {
+    let precisions = precisions.into();
+    if precisions == Precisions::uniform(Numeric::Float) {
+        return;
+    }
+    let scalarize = PassManager {
+        passes: vec![Pass::Scalarize, Pass::Dce],
+        ..PassManager::for_level(OptLevel::O0)
+    };
+    scalarize.run(module);
+
+    let mut callees = Callees {
+        first: module.functions.len(),
+        added: Vec::new(),
+    };
+    for func in &mut module.functions {
+        let mut floats = BTreeSet::new();
+        if !func.is_declaration() {
+            let mut formats = precision::assign(func, precisions);
+            precision::convert(func, &mut formats, &mut callees);
+            for numeric in precisions.formats() {
+                match numeric {
+                    Numeric::Float => {}
+                    Numeric::Fixed(format) => fixed::lower(func, format, &formats, &mut callees),
+                    Numeric::SoftFloat => soft_float::lower(func, &formats, &mut callees),
+                }
+            }
+            floats = formats
+                .values
+                .into_iter()
+                .filter(|&(_, numeric)| numeric == Numeric::Float)
+                .map(|(value, _)| value)
+                .collect();
+        }
+        retype(func, precisions.high, &floats);
+    }
+    module.functions.extend(callees.added);
+}
+
+/// Turn every `f32` into an `i32`, in the signature unless the `highp`
+/// format is `f32`, and in the values but `floats`
+fn retype(func: &mut Function, high: Numeric, floats: &BTreeSet<Value>) {
+    let integer = |ty: &mut Type| {
+        if ty.scalar == ScalarType::F32 {
+            ty.scalar = ScalarType::I32;
+        }
+    };
+    if high != Numeric::Float {
+        let signature = &mut func.signature;
+        signature
+            .params
+            .iter_mut()
+            .chain(&mut signature.returns)
+            .for_each(integer);
+    }
+    for (i, value) in func.values.iter_mut().enumerate() {
+        if !floats.contains(&Value(i as u32)) {
+            integer(&mut value.ty);
+        }
+    }
+}
+
+/// Functions added to the module for the lowered code to call
+struct Callees {
+    /// Reference of the first added function
+    first: usize,
+    added: Vec<Function>,
+}
+
+impl Callees {
+    fn find_or_add(&mut self, name: String, make: impl FnOnce(String) -> Function) -> FuncRef {
+        let i = match self.added.iter().position(|f| f.name == name) {
+            Some(i) => i,
+            None => {
+                self.added.push(make(name));
+                self.added.len() - 1
+            }
+        };
+        FuncRef((self.first + i) as u32)
+    }
+
+    /// Declaration of the runtime routine `name`
+    fn routine(&mut self, name: String, params: usize, returns: Type) -> FuncRef {
+        self.find_or_add(name, |name| {
+            let signature = Signature {
+                params: vec![Type::I32; params],
+                returns: vec![returns],
+            };
+            Function::new(name, signature)
+        })
+    }
+}
@@ -0,0 +1,409 @@
+//! Formats of the `float` values of a function lowered at several precisions
+//!
+//! The IR marks every `float` read from or written to a variable with a
+//! precision qualifier with a `lowp`, `mediump` or `highp` marker. Each
+//! `f32` value takes the precision of its marker, and an operation the
+//! highest precision of its operands, constants aside as they take the
+//! precision of their use. Block parameters take the highest precision of
+//! their arguments. Loads, call results and the parameters of the function
+//! are `highp`, as is any value left without a precision.
+//!
+//! Where an operand has another format than its use needs, [`assign`]
+//! inserts a marker converting it, or a copy of the constant in the right
+//! format. Stores, calls and returns need the `highp` format, and branch
+//! arguments the format of their parameter. Once every value has its
+//! format, [`convert`] replaces the markers with conversions.
+
+use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+
+use super::{Callees, Fixed, Numeric, Precisions};
+use crate::{
+    ir::{
+        BinaryOp, Block, Builtin, CompareOp, Constant, Function, Inst, InstKind, ScalarType, Type,
+        UnaryOp, Value, ValueDef,
+    },
+    runtime::fixed::{from_f32_symbol, to_f32_symbol},
+    sema::hir::Precision,
+};
+
+/// Format of each `float` value and instruction of a function
+pub(super) struct Formats {
+    /// Format of every `f32` value
+    pub values: BTreeMap<Value, Numeric>,
+    /// Format every instruction computing on `f32` values is lowered to,
+    /// markers aside
+    pub insts: BTreeMap<Inst, Numeric>,
+}
+
+/// Give every `float` value of `func` a format, inserting the conversions
+/// between formats as markers
+pub(super) fn assign(func: &mut Function, precisions: Precisions) -> Formats {
+    let mut known = infer(func);
+    for i in 0..func.layout.len() {
+        let block = func.layout[i];
+        insert_conversions(func, block, precisions, &mut known);
+    }
+
+    let precision = |v: &Value| known.get(v).copied().unwrap_or(Precision::High);
+    let values: BTreeMap<Value, Numeric> = (0..func.values.len() as u32)
+        .map(Value)
+        .filter(|&v| is_float(func, v))
+        .map(|v| (v, precisions.format(precision(&v))))
+        .collect();
+    // An instruction computes in the format of its float result, or else
+    // in that of its operands
+    let mut insts = BTreeMap::new();
+    for &block in &func.layout {
+        for &inst in &func.block(block).insts {
+            let data = func.inst(inst);
+            let floats = data.kind.operands().into_iter().chain(data.results.clone());
+            if is_marker(&data.kind) || !floats.into_iter().any(|v| is_float(func, v)) {
+                continue;
+            }
+            let format = match data.results.iter().find_map(|r| values.get(r)) {
+                Some(&format) => format,
+                None => {
+                    let own = own_precision(func, inst, &|v| known.get(&v).copied());
+                    precisions.format(own.unwrap_or(Precision::High))
+                }
+            };
+            insts.insert(inst, format);
+        }
+    }
+    Formats { values, insts }
+}
+
+fn is_float(func: &Function, value: Value) -> bool {
+    func.value_type(value).scalar == ScalarType::F32
+}
+
+fn is_marker(kind: &InstKind) -> bool {
+    matches!(kind, InstKind::Unary(op, _) if op.precision().is_some())
+}
+
+fn is_constant(func: &Function, value: Value) -> bool {
+    match func.value(value).def {
+        ValueDef::Result(inst, _) => matches!(func.inst(inst).kind, InstKind::Const(_)),
+        _ => false,
+    }
+}
+
+/// Precision an instruction computes in, given the precision of values
+/// known so far, or `None` while it depends on nothing known
+fn own_precision(
+    func: &Function,
+    inst: Inst,
+    known: &dyn Fn(Value) -> Option<Precision>,
+) -> Option<Precision> {
+    match &func.inst(inst).kind {
+        InstKind::Unary(op, _) if op.precision().is_some() => op.precision(),
+        InstKind::Const(_) => None,
+        InstKind::Load(..) | InstKind::Call(..) => Some(Precision::High),
+        kind => kind
+            .operands()
+            .into_iter()
+            .filter(|&v| is_float(func, v))
+            .filter_map(known)
+            .max(),
+    }
+}
+
+/// Precision of every `f32` value but constants, as far as it is known
+fn infer(func: &Function) -> BTreeMap<Value, Precision> {
+    let mut known = BTreeMap::new();
+    if let Some(entry) = func.entry() {
+        for &param in &func.block(entry).params {
+            known.insert(param, Precision::High);
+        }
+    }
+    let raise = |known: &mut BTreeMap<Value, Precision>, value: Value, precision| {
+        let old = known.get(&value).copied();
+        if old.is_none_or(|old| old < precision) {
+            known.insert(value, precision);
+            return true;
+        }
+        false
+    };
+    loop {
+        let mut changed = false;
+        for &block in &func.layout {
+            for &inst in &func.block(block).insts {
+                let data = func.inst(inst);
+                let own = own_precision(func, inst, &|v| known.get(&v).copied());
+                if let Some(precision) = own {
+                    for &result in &data.results {
+                        if is_float(func, result) {
+                            changed |= raise(&mut known, result, precision);
+                        }
+                    }
+                }
+                for call in data.kind.block_calls() {
+                    let params = &func.block(call.block).params;
+                    for (arg, &param) in call.args.iter().zip(params) {
+                        if let Some(&precision) = known.get(arg) {
+                            changed |= raise(&mut known, param, precision);
+                        }
+                    }
+                }
+            }
+        }
+        if !changed {
+            return known;
+        }
+    }
+}
+
+/// Precision each operand of `inst` must have, in the order of
+/// [`InstKind::operands`], `None` for any
+fn required(
+    func: &Function,
+    inst: Inst,
+    known: &BTreeMap<Value, Precision>,
+) -> Vec<Option<Precision>> {
+    let kind = &func.inst(inst).kind;
+    let operands = kind.operands();
+    let high = Some(Precision::High);
+    let param = |block: Block, i: usize| {
+        let param = func.block(block).params[i];
+        Some(known.get(&param).copied().unwrap_or(Precision::High))
+    };
+    match kind {
+        InstKind::Unary(op, _) if op.precision().is_some() => vec![None],
+        InstKind::Load(..) => vec![None],
+        InstKind::Store(..) => vec![high, None],
+        InstKind::Call(..) | InstKind::Return(_) => vec![high; operands.len()],
+        InstKind::Jump(call) => (0..call.args.len()).map(|i| param(call.block, i)).collect(),
+        InstKind::Branch(_, then, otherwise) => {
+            let then_args = (0..then.args.len()).map(|i| param(then.block, i));
+            let else_args = (0..otherwise.args.len()).map(|i| param(otherwise.block, i));
+            core::iter::once(None)
+                .chain(then_args)
+                .chain(else_args)
+                .collect()
+        }
+        _ => {
+            let known = |v| known.get(&v).copied();
+            let own = own_precision(func, inst, &known).or(high);
+            vec![own; operands.len()]
+        }
+    }
+}
+
+/// Give the `f32` operands of the instructions of `block` the precision
+/// their use needs when it has another format
+fn insert_conversions(
+    func: &mut Function,
+    block: Block,
+    precisions: Precisions,
+    known: &mut BTreeMap<Value, Precision>,
+) {
+    // Converted values, reused for later uses in the block
+    let mut converted: BTreeMap<(Value, Precision), Value> = BTreeMap::new();
+    let mut out = Vec::new();
+    for inst in core::mem::take(&mut func.block_mut(block).insts) {
+        let required = required(func, inst, known);
+        let mut operands = Vec::new();
+        for (operand, required) in func.inst(inst).kind.operands().into_iter().zip(required) {
+            let Some(required) = required.filter(|_| is_float(func, operand)) else {
+                operands.push(operand);
+                continue;
+            };
+            let precision = known.get(&operand).copied().unwrap_or(Precision::High);
+            if precisions.format(precision) == precisions.format(required) {
+                operands.push(operand);
+                continue;
+            }
+            if let Some(&value) = converted.get(&(operand, required)) {
+                operands.push(value);
+                continue;
+            }
+            let ty = func.value_type(operand);
+            let kind = match func.value(operand).def {
+                ValueDef::Result(def, _) if is_constant(func, operand) => {
+                    func.inst(def).kind.clone()
+                }
+                _ => InstKind::Unary(UnaryOp::marker(required), operand),
+            };
+            let conversion = func.new_inst(kind, &[ty]);
+            out.push(conversion);
+            let value = func.inst(conversion).results[0];
+            known.insert(value, required);
+            converted.insert((operand, required), value);
+            operands.push(value);
+        }
+        let mut operands = operands.into_iter();
+        func.inst_mut(inst)
+            .kind
+            .map_operands(|_| operands.next().unwrap());
+        out.push(inst);
+    }
+    func.block_mut(block).insts = out;
+}
+
+/// Replace every marker with a conversion from the format of its operand
+/// to that of its result
+///
+/// Constants are lowered in the format of the result instead.
+/// Conversions between fixed-point formats shift and saturate. Those
+/// between fixed point and floats call the `from_f32` and `to_f32` routines
+/// of [`runtime::fixed`](crate::runtime::fixed) on the bits of the float.
+pub(super) fn convert(func: &mut Function, formats: &mut Formats, callees: &mut Callees) {
+    let mut conversion = Conversion {
+        func,
+        callees,
+        out: Vec::new(),
+        aliases: BTreeMap::new(),
+    };
+    conversion.run(formats);
+}
+
+struct Conversion<'a> {
+    func: &'a mut Function,
+    callees: &'a mut Callees,
+    /// Instructions of the block being rewritten
+    out: Vec<Inst>,
+    /// Results of removed markers and the values replacing them
+    aliases: BTreeMap<Value, Value>,
+}
+
+impl Conversion<'_> {
+    fn run(&mut self, formats: &mut Formats) {
+        for i in 0..self.func.layout.len() {
+            let block = self.func.layout[i];
+            for inst in core::mem::take(&mut self.func.block_mut(block).insts) {
+                let kind = &self.func.inst(inst).kind;
+                let (true, &InstKind::Unary(_, x)) = (is_marker(kind), kind) else {
+                    self.out.push(inst);
+                    continue;
+                };
+                let result = self.func.inst(inst).results[0];
+                let converted = self.convert_lanes(x, result, formats);
+                self.aliases.insert(result, converted);
+                self.func.detach_results(inst);
+            }
+            self.func.block_mut(block).insts = core::mem::take(&mut self.out);
+        }
+        self.func.replace_uses(&self.aliases);
+    }
+
+    fn emit(&mut self, kind: InstKind, ty: Type) -> Value {
+        let inst = self.func.new_inst(kind, &[ty]);
+        self.out.push(inst);
+        self.func.inst(inst).results[0]
+    }
+
+    fn iconst(&mut self, x: i32) -> Value {
+        self.emit(InstKind::Const(Constant::I32(x)), Type::I32)
+    }
+
+    fn binary_imm(&mut self, op: BinaryOp, x: Value, y: i32) -> Value {
+        let y = self.iconst(y);
+        self.emit(InstKind::Binary(op, x, y), Type::I32)
+    }
+
+    /// `x` clamped to `lo..=hi`
+    fn clamp(&mut self, x: Value, lo: i32, hi: i32) -> Value {
+        let (lo, hi) = (self.iconst(lo), self.iconst(hi));
+        let above = self.emit(InstKind::Compare(CompareOp::Gt, x, hi), Type::BOOL);
+        let below = self.emit(InstKind::Compare(CompareOp::Lt, x, lo), Type::BOOL);
+        let x = self.emit(InstKind::Select(below, lo, x), Type::I32);
+        self.emit(InstKind::Select(above, hi, x), Type::I32)
+    }
+
+    fn call(&mut self, name: String, x: Value) -> Value {
+        let routine = self.callees.routine(name, 1, Type::I32);
+        self.emit(InstKind::Call(routine, vec![x]), Type::I32)
+    }
+
+    fn bit_cast(&mut self, builtin: Builtin, x: Value, ty: Type) -> Value {
+        self.emit(InstKind::Builtin(builtin, vec![x]), ty)
+    }
+
+    /// `x` converted to the format of `result`, lane by lane if it is a
+    /// vector crossing a function boundary
+    fn convert_lanes(&mut self, x: Value, result: Value, formats: &mut Formats) -> Value {
+        let (from, to) = (formats.values[&x], formats.values[&result]);
+        let ty = self.func.value_type(x);
+        if let (ValueDef::Result(def, _), false) = (self.func.value(x).def, from == to) {
+            // A constant is lowered in the new format instead
+            if let InstKind::Const(constant) = self.func.inst(def).kind.clone() {
+                let copy = self.func.new_inst(InstKind::Const(constant), &[ty]);
+                self.out.push(copy);
+                formats.insts.insert(copy, to);
+                let converted = self.func.inst(copy).results[0];
+                formats.values.insert(converted, to);
+                return converted;
+            }
+        }
+        if !ty.is_vector() || from == to {
+            let converted = self.convert(x, from, to);
+            formats.values.insert(converted, to);
+            return converted;
+        }
+        let lanes = (0..ty.lanes)
+            .map(|i| {
+                let lane = self.emit(InstKind::Extract(x, i), ty.lane_type());
+                formats.values.insert(lane, from);
+                let converted = self.convert(lane, from, to);
+                formats.values.insert(converted, to);
+                converted
+            })
+            .collect();
+        let scalar = match to {
+            Numeric::Float => ScalarType::F32,
+            _ => ScalarType::I32,
+        };
+        let converted = self.emit(InstKind::Vector(lanes), ty.with_scalar(scalar));
+        formats.values.insert(converted, to);
+        converted
+    }
+
+    /// `x` in format `from` converted to `to`
+    fn convert(&mut self, x: Value, from: Numeric, to: Numeric) -> Value {
+        use Numeric::{Fixed as Q, Float, SoftFloat as Soft};
+
+        match (from, to) {
+            _ if from == to => x,
+            (Q(from), Q(to)) => self.rescale(x, from, to),
+            (Q(from), Soft) => self.call(to_f32_symbol(from), x),
+            (Q(from), Float) => {
+                let bits = self.call(to_f32_symbol(from), x);
+                self.bit_cast(Builtin::IntBitsToFloat, bits, Type::F32)
+            }
+            (Soft, Q(to)) => self.call(from_f32_symbol(to), x),
+            (Float, Q(to)) => {
+                let bits = self.bit_cast(Builtin::FloatBitsToInt, x, Type::I32);
+                self.call(from_f32_symbol(to), bits)
+            }
+            (Soft, Float) => self.bit_cast(Builtin::IntBitsToFloat, x, Type::F32),
+            (Float, Soft) => self.bit_cast(Builtin::FloatBitsToInt, x, Type::I32),
+            (Soft, Soft) | (Float, Float) => x,
+        }
+    }
+
+    /// Fixed-point `x` shifted to the fractional bits of `to`, saturating
+    /// to its range
+    fn rescale(&mut self, x: Value, from: Fixed, to: Fixed) -> Value {
+        let shift = to.frac_bits as i32 - from.frac_bits as i32;
+        if shift >= 0 {
+            // Clamp before shifting left so that nothing overflows
+            let (lo, hi) = (to.min() >> shift, to.max() >> shift);
+            let x = if lo > from.min() || hi < from.max() {
+                self.clamp(x, lo, hi)
+            } else {
+                x
+            };
+            if shift == 0 {
+                return x;
+            }
+            self.binary_imm(BinaryOp::Shl, x, shift)
+        } else {
+            let x = self.binary_imm(BinaryOp::Shr, x, -shift);
+            if from.min() >> -shift < to.min() || from.max() >> -shift > to.max() {
+                self.clamp(x, to.min(), to.max())
+            } else {
+                x
+            }
+        }
+    }
+}
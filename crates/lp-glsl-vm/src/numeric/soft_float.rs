@@ -0,0 +1,194 @@
+//! Lowering of `float` to soft float
+//!
+//! Once vectors are split into scalars, every `f32` value becomes an `i32`
+//! holding its bits, and float instructions become calls to the routines of
+//! [`runtime::soft_float`](crate::runtime::soft_float), declared in the
+//! module under their symbol. Constants, negation, `abs`, the tests for NaN
+//! and infinity and the bit casts only need integer instructions on the
+//! bits. Selects, loads and stores are unchanged.
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+use super::{precision::Formats, Callees, Numeric};
+use crate::{
+    ir::{
+        BinaryOp, Builtin, CompareOp, Constant, Function, Inst, InstKind, ScalarType, Type,
+        UnaryOp, Value,
+    },
+    runtime::soft_float::{builtin_symbol, symbol},
+};
+
+const SIGN: i32 = i32::MIN;
+const INFINITY: i32 = 0x7f80_0000;
+
+pub(super) fn lower(func: &mut Function, formats: &Formats, callees: &mut Callees) {
+    let mut lowering = Lowering {
+        func,
+        formats,
+        callees,
+        out: Vec::new(),
+        aliases: BTreeMap::new(),
+    };
+    lowering.run();
+}
+
+struct Lowering<'a> {
+    func: &'a mut Function,
+    formats: &'a Formats,
+    callees: &'a mut Callees,
+    /// Instructions of the block being rewritten
+    out: Vec<Inst>,
+    /// Results of removed instructions and the values replacing them
+    aliases: BTreeMap<Value, Value>,
+}
+
+impl Lowering<'_> {
+    fn run(&mut self) {
+        for i in 0..self.func.layout.len() {
+            let block = self.func.layout[i];
+            for inst in core::mem::take(&mut self.func.block_mut(block).insts) {
+                if self.formats.insts.get(&inst) == Some(&Numeric::SoftFloat) {
+                    self.inst(inst);
+                } else {
+                    self.out.push(inst);
+                }
+            }
+            self.func.block_mut(block).insts = core::mem::take(&mut self.out);
+        }
+        self.func.replace_uses(&self.aliases);
+    }
+
+    fn is_float(&self, value: Value) -> bool {
+        self.formats.values.contains_key(&value)
+    }
+
+    fn inst(&mut self, inst: Inst) {
+        let kind = self.func.inst(inst).kind.clone();
+        let results = self.func.inst(inst).results.clone();
+        let lowered = match kind {
+            InstKind::Const(Constant::F32(x)) => self.iconst(x.to_bits() as i32),
+            InstKind::Unary(UnaryOp::Neg, x) if self.is_float(x) => {
+                self.binary_imm(BinaryOp::Xor, x, SIGN)
+            }
+            InstKind::Binary(op, x, y) if self.is_float(x) => {
+                self.call(symbol(op.name()), &[x, y], Type::I32)
+            }
+            InstKind::Compare(op, x, y) if self.is_float(x) => self.compare(op, x, y),
+            InstKind::Convert(op, x) => self.call(symbol(op.name()), &[x], Type::I32),
+            InstKind::Builtin(builtin, args)
+                if args.iter().chain(&results).any(|&v| self.is_float(v)) =>
+            {
+                self.builtin(builtin, &args)
+            }
+            _ => {
+                self.out.push(inst);
+                return;
+            }
+        };
+        self.aliases.insert(results[0], lowered);
+        self.func.detach_results(inst);
+    }
+
+    /// Append an instruction with one result of type `ty`
+    fn emit(&mut self, kind: InstKind, ty: Type) -> Value {
+        let inst = self.func.new_inst(kind, &[ty]);
+        self.out.push(inst);
+        self.func.inst(inst).results[0]
+    }
+
+    fn iconst(&mut self, x: i32) -> Value {
+        self.emit(InstKind::Const(Constant::I32(x)), Type::I32)
+    }
+
+    /// `op` of `x` and a constant
+    fn binary_imm(&mut self, op: BinaryOp, x: Value, y: i32) -> Value {
+        let y = self.iconst(y);
+        self.emit(InstKind::Binary(op, x, y), Type::I32)
+    }
+
+    fn select(&mut self, cond: Value, x: Value, y: Value) -> Value {
+        self.emit(InstKind::Select(cond, x, y), Type::I32)
+    }
+
+    /// Call the routine `name` with one result of type `returns`
+    fn call(&mut self, name: String, args: &[Value], returns: Type) -> Value {
+        let routine = self.callees.routine(name, args.len(), returns);
+        self.emit(InstKind::Call(routine, args.to_vec()), returns)
+    }
+
+    /// Float comparison, false if either operand is NaN except for `ne`
+    fn compare(&mut self, op: CompareOp, x: Value, y: Value) -> Value {
+        self.call(symbol(op.name()), &[x, y], Type::BOOL)
+    }
+
+    /// Magnitude of `x` as an integer, which orders like the float
+    fn magnitude(&mut self, x: Value) -> Value {
+        self.binary_imm(BinaryOp::And, x, !SIGN)
+    }
+
+    fn builtin(&mut self, builtin: Builtin, args: &[Value]) -> Value {
+        use Builtin as B;
+
+        let x = args[0];
+        match builtin {
+            B::Abs => self.magnitude(x),
+            B::Min => {
+                let less = self.compare(CompareOp::Lt, args[1], x);
+                self.select(less, args[1], x)
+            }
+            B::Max => {
+                let greater = self.compare(CompareOp::Gt, args[1], x);
+                self.select(greater, args[1], x)
+            }
+            B::Clamp => {
+                let max = self.builtin(B::Max, &args[..2]);
+                self.builtin(B::Min, &[max, args[2]])
+            }
+            B::Mix if self.func.value_type(args[2]).scalar == ScalarType::Bool => {
+                self.select(args[2], args[1], x)
+            }
+            B::IsNan => {
+                let magnitude = self.magnitude(x);
+                let infinity = self.iconst(INFINITY);
+                self.emit(
+                    InstKind::Compare(CompareOp::UGt, magnitude, infinity),
+                    Type::BOOL,
+                )
+            }
+            B::IsInf => {
+                let magnitude = self.magnitude(x);
+                let infinity = self.iconst(INFINITY);
+                self.emit(
+                    InstKind::Compare(CompareOp::Eq, magnitude, infinity),
+                    Type::BOOL,
+                )
+            }
+            // The bits of a soft float are those of the `f32`
+            B::FloatBitsToInt | B::FloatBitsToUint | B::IntBitsToFloat | B::UintBitsToFloat => x,
+            B::LessThan => self.compare(CompareOp::Lt, x, args[1]),
+            B::LessThanEqual => self.compare(CompareOp::Le, x, args[1]),
+            B::GreaterThan => self.compare(CompareOp::Gt, x, args[1]),
+            B::GreaterThanEqual => self.compare(CompareOp::Ge, x, args[1]),
+            B::Equal => self.compare(CompareOp::Eq, x, args[1]),
+            B::NotEqual => self.compare(CompareOp::Ne, x, args[1]),
+            _ => self.routine(builtin, args),
+        }
+    }
+
+    /// Call the runtime routine for `builtin`, passing vectors lane by lane
+    fn routine(&mut self, builtin: Builtin, args: &[Value]) -> Value {
+        let mut scalars = Vec::new();
+        for &arg in args {
+            let ty = self.func.value_type(arg);
+            if !ty.is_vector() {
+                scalars.push(arg);
+                continue;
+            }
+            for lane in 0..ty.lanes {
+                scalars.push(self.emit(InstKind::Extract(arg, lane), Type::I32));
+            }
+        }
+        let name = builtin_symbol(builtin, scalars.len());
+        self.call(name, &scalars, Type::I32)
+    }
+}
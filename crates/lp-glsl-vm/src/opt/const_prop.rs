@@ -0,0 +1,222 @@
+//! Constant propagation
+//!
+//! Walking the reachable blocks in reverse postorder, instructions whose
+//! operands are all known are evaluated as the interpreter would and become
+//! constants, branches on a known condition become jumps, and selects on a
+//! known condition are replaced by the chosen value. A block parameter that
+//! receives the same constant from every branch becomes that constant.
+//! Integer operations with an identity operand, such as `x + 0`, are
+//! replaced by the other operand. The walk is repeated until nothing
+//! changes, as folding a branch can make more parameters constant.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec,
+    vec::Vec,
+};
+
+use super::remove_params;
+use crate::ir::{
+    interpret::{eval, Bits, Operands},
+    BinaryOp, Block, BlockCall, Constant, Dominators, Function, InstKind, ScalarType, Type, Value,
+};
+
+pub(super) fn run(func: &mut Function) -> usize {
+    let mut changes = 0;
+    loop {
+        let changed = propagate(func);
+        if changed == 0 {
+            return changes;
+        }
+        changes += changed;
+    }
+}
+
+/// Values of a function known to be constant
+struct Known<'f> {
+    func: &'f Function,
+    bits: &'f BTreeMap<Value, Bits>,
+}
+
+impl Operands for Known<'_> {
+    fn ty(&self, value: Value) -> Type {
+        self.func.value_type(value)
+    }
+
+    fn bits(&self, value: Value) -> Bits {
+        self.bits[&value]
+    }
+}
+
+fn propagate(func: &mut Function) -> usize {
+    let dominators = Dominators::new(func);
+    let mut known: BTreeMap<Value, Bits> = BTreeMap::new();
+    // Values replaced by another, and the now unused definitions to remove
+    let mut aliases: BTreeMap<Value, Value> = BTreeMap::new();
+    let mut dead_params: BTreeMap<Block, Vec<bool>> = BTreeMap::new();
+    let mut dead_insts = BTreeSet::new();
+    let mut changes = 0;
+    let resolve = |aliases: &BTreeMap<Value, Value>, mut value: Value| {
+        while let Some(&to) = aliases.get(&value) {
+            value = to;
+        }
+        value
+    };
+
+    for &block in dominators.rpo() {
+        if Some(block) != func.entry() {
+            for (index, param) in func.block(block).params.clone().into_iter().enumerate() {
+                let Some(bits) = incoming_constant(func, &dominators, &known, block, index) else {
+                    continue;
+                };
+                let ty = func.value_type(param);
+                if ty.is_vector() {
+                    known.insert(param, bits);
+                    continue;
+                }
+                let inst = func.new_inst(InstKind::Const(constant(ty.scalar, bits[0])), &[ty]);
+                func.block_mut(block).insts.insert(0, inst);
+                let value = func.inst(inst).results[0];
+                known.insert(value, bits);
+                known.insert(param, bits);
+                aliases.insert(param, value);
+                dead_params
+                    .entry(block)
+                    .or_insert_with(|| vec![true; func.block(block).params.len()])[index] = false;
+                changes += 1;
+            }
+        }
+
+        for i in 0..func.block(block).insts.len() {
+            let inst = func.block(block).insts[i];
+            let mut kind = func.inst(inst).kind.clone();
+            kind.map_operands(|v| resolve(&aliases, v));
+            let result = func.inst(inst).results.first().copied();
+
+            match &kind {
+                InstKind::Branch(cond, then, otherwise) if known.contains_key(cond) => {
+                    let taken = if known[cond][0] != 0 { then } else { otherwise };
+                    kind = InstKind::Jump(BlockCall::clone(taken));
+                    changes += 1;
+                }
+                InstKind::Select(cond, a, b)
+                    if known.contains_key(cond) && !func.value_type(*cond).is_vector() =>
+                {
+                    let chosen = if known[cond][0] != 0 { *a } else { *b };
+                    if let Some(&bits) = known.get(&chosen) {
+                        known.insert(result.unwrap(), bits);
+                    }
+                    aliases.insert(result.unwrap(), chosen);
+                    dead_insts.insert(inst);
+                    changes += 1;
+                }
+                InstKind::Const(_) => {
+                    let ty = func.value_type(result.unwrap());
+                    let operands = Known { func, bits: &known };
+                    let bits = eval(&operands, &kind, ty).unwrap();
+                    known.insert(result.unwrap(), bits);
+                }
+                InstKind::StackAddr(_) | InstKind::GlobalAddr(_) => {}
+                _ if kind.is_pure() && kind.operands().iter().all(|v| known.contains_key(v)) => {
+                    let result = result.unwrap();
+                    let ty = func.value_type(result);
+                    let operands = Known { func, bits: &known };
+                    if let Some(bits) = eval(&operands, &kind, ty) {
+                        known.insert(result, bits);
+                        if !ty.is_vector() {
+                            kind = InstKind::Const(constant(ty.scalar, bits[0]));
+                            changes += 1;
+                        }
+                    }
+                }
+                InstKind::Binary(op, a, b) => {
+                    let ty = func.value_type(*a);
+                    if let Some(same) = identity(*op, ty, (*a, known.get(a)), (*b, known.get(b))) {
+                        aliases.insert(result.unwrap(), same);
+                        dead_insts.insert(inst);
+                        changes += 1;
+                    }
+                }
+                _ => {}
+            }
+            func.inst_mut(inst).kind = kind;
+        }
+    }
+    func.replace_uses(&aliases);
+    for (block, keep) in dead_params {
+        remove_params(func, block, &keep);
+    }
+    for &block in dominators.rpo() {
+        func.block_mut(block)
+            .insts
+            .retain(|inst| !dead_insts.contains(inst));
+    }
+    for inst in dead_insts {
+        func.detach_results(inst);
+    }
+    changes
+}
+
+/// The constant passed for parameter `index` of `block` by every reachable
+/// branch already walked, if they all pass the same one
+fn incoming_constant(
+    func: &Function,
+    dominators: &Dominators,
+    known: &BTreeMap<Value, Bits>,
+    block: Block,
+    index: usize,
+) -> Option<Bits> {
+    let param = func.block(block).params[index];
+    let mut incoming = None;
+    for &source in dominators.rpo() {
+        let Some(terminator) = func.terminator(source) else {
+            continue;
+        };
+        for call in func.inst(terminator).kind.block_calls() {
+            if call.block != block || call.args[index] == param {
+                continue;
+            }
+            let bits = *known.get(&call.args[index])?;
+            if incoming.is_some_and(|b| b != bits) {
+                return None;
+            }
+            incoming = Some(bits);
+        }
+    }
+    incoming
+}
+
+/// The operand `a op b` always equals, if the other one is an identity of
+/// `op`
+fn identity(
+    op: BinaryOp,
+    ty: Type,
+    (a, a_bits): (Value, Option<&Bits>),
+    (b, b_bits): (Value, Option<&Bits>),
+) -> Option<Value> {
+    use BinaryOp::*;
+
+    let is = |bits: Option<&Bits>, lane: u32| {
+        bits.is_some_and(|bits| bits[..ty.lanes as usize].iter().all(|&l| l == lane))
+    };
+    match (ty.scalar, op) {
+        (ScalarType::F32, _) => None,
+        (_, Add | Or | Xor) if is(a_bits, 0) => Some(b),
+        (_, Add | Sub | Or | Xor | Shl | Shr | UShr) if is(b_bits, 0) => Some(a),
+        (ScalarType::I32, Mul) if is(a_bits, 1) => Some(b),
+        (ScalarType::I32, Mul | Div | UDiv) if is(b_bits, 1) => Some(a),
+        (ScalarType::I32, And) if is(a_bits, u32::MAX) => Some(b),
+        (ScalarType::I32, And) if is(b_bits, u32::MAX) => Some(a),
+        (ScalarType::Bool, And) if is(a_bits, 1) => Some(b),
+        (ScalarType::Bool, And) if is(b_bits, 1) => Some(a),
+        _ => None,
+    }
+}
+
+fn constant(scalar: ScalarType, bits: u32) -> Constant {
+    match scalar {
+        ScalarType::Bool => Constant::Bool(bits != 0),
+        ScalarType::I32 => Constant::I32(bits as i32),
+        ScalarType::F32 => Constant::F32(f32::from_bits(bits)),
+    }
+}
@@ -0,0 +1,137 @@
+//! Common subexpression elimination
+//!
+//! The dominator tree is walked from the entry block, remembering each pure
+//! instruction in scope. An instruction identical to one that dominates it
+//! is removed and its result replaced by the earlier one. Operands of
+//! commutative integer operations are ordered first, so `a + b` matches
+//! `b + a`; float operations are left alone, as which NaN they return
+//! depends on the order.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec,
+    vec::Vec,
+};
+
+use crate::ir::{
+    BinaryOp, Block, CompareOp, Constant, Dominators, Function, Inst, InstKind, ScalarType, Value,
+};
+
+pub(super) fn run(func: &mut Function) -> usize {
+    let dominators = Dominators::new(func);
+    let children = dominators.children();
+    let entry = func.entry().expect("function with a body");
+
+    let mut available: BTreeMap<Vec<u32>, Value> = BTreeMap::new();
+    let mut aliases: BTreeMap<Value, Value> = BTreeMap::new();
+    let mut removed: BTreeSet<Inst> = BTreeSet::new();
+    // Preorder walk of the dominator tree, with the keys each block added
+    // so they go out of scope once its subtree is done
+    let mut stack: Vec<(Block, bool)> = vec![(entry, false)];
+    let mut scopes: Vec<Vec<Vec<u32>>> = Vec::new();
+    while let Some((block, done)) = stack.pop() {
+        if done {
+            for key in scopes.pop().unwrap() {
+                available.remove(&key);
+            }
+            continue;
+        }
+        let mut added = Vec::new();
+        for &inst in &func.block(block).insts {
+            let data = func.inst(inst);
+            let Some(&result) = data.results.first() else {
+                continue;
+            };
+            let Some(key) = key(func, &data.kind, result, &aliases) else {
+                continue;
+            };
+            match available.get(&key) {
+                Some(&earlier) => {
+                    aliases.insert(result, earlier);
+                    removed.insert(inst);
+                }
+                None => {
+                    available.insert(key.clone(), result);
+                    added.push(key);
+                }
+            }
+        }
+        scopes.push(added);
+        stack.push((block, true));
+        for &child in children.get(&block).into_iter().flatten().rev() {
+            stack.push((child, false));
+        }
+    }
+
+    func.replace_uses(&aliases);
+    for i in 0..func.layout.len() {
+        let block = func.layout[i];
+        func.block_mut(block)
+            .insts
+            .retain(|inst| !removed.contains(inst));
+    }
+    for &inst in &removed {
+        func.detach_results(inst);
+    }
+    removed.len()
+}
+
+/// Key identifying what a pure instruction computes, or `None` for other
+/// instructions
+fn key(
+    func: &Function,
+    kind: &InstKind,
+    result: Value,
+    aliases: &BTreeMap<Value, Value>,
+) -> Option<Vec<u32>> {
+    if !kind.is_pure() {
+        return None;
+    }
+    let resolve = |mut value: Value| {
+        while let Some(&to) = aliases.get(&value) {
+            value = to;
+        }
+        value.0
+    };
+    let operands = kind.operands();
+    let commutes = operands
+        .first()
+        .is_some_and(|&v| func.value_type(v).scalar != ScalarType::F32);
+    let mut operands: Vec<u32> = operands.into_iter().map(resolve).collect();
+    let (tag, detail) = match *kind {
+        InstKind::Const(constant) => match constant {
+            Constant::Bool(b) => (0, b as u32),
+            Constant::I32(i) => (1, i as u32),
+            Constant::F32(f) => (2, f.to_bits()),
+        },
+        InstKind::Unary(op, _) => (3, op as u32),
+        InstKind::Binary(op, ..) => {
+            use BinaryOp::*;
+            if commutes && matches!(op, Add | Mul | MulH | And | Or | Xor) {
+                operands.sort_unstable();
+            }
+            (4, op as u32)
+        }
+        InstKind::Compare(op, ..) => {
+            if commutes && matches!(op, CompareOp::Eq | CompareOp::Ne) {
+                operands.sort_unstable();
+            }
+            (5, op as u32)
+        }
+        InstKind::Convert(op, _) => (6, op as u32),
+        InstKind::Select(..) => (7, 0),
+        InstKind::Splat(_) => (8, 0),
+        InstKind::Vector(_) => (9, 0),
+        InstKind::Extract(_, lane) => (10, lane as u32),
+        InstKind::Insert(_, lane, _) => (11, lane as u32),
+        InstKind::Builtin(builtin, _) => (12, builtin as u32),
+        InstKind::StackAddr(slot) => (13, slot.0),
+        InstKind::GlobalAddr(global) => (14, global.0),
+        _ => unreachable!("impure instruction"),
+    };
+    // Splats of different widths have the same operand
+    let ty = func.value_type(result);
+    let mut key = vec![tag, detail, ty.scalar as u32, ty.lanes as u32];
+    key.extend(operands);
+    Some(key)
+}
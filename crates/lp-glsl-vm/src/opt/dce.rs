@@ -0,0 +1,159 @@
+//! Dead code elimination
+//!
+//! Blocks the entry block cannot reach are removed, and a block that is
+//! only ever entered by a jump from one other block is merged into it.
+//! Then values are marked live from the instructions with effects: the
+//! operands of a live instruction are live, and so are the arguments
+//! passed for a live block parameter. Everything else is removed, including
+//! parameters no branch needs to pass any more.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+
+use super::{predecessors, remove_params};
+use crate::ir::{Block, Dominators, Function, InstKind, Value, ValueDef};
+
+pub(super) fn run(func: &mut Function) -> usize {
+    remove_unreachable(func) + merge_blocks(func) + remove_dead(func)
+}
+
+/// Remove the blocks the entry block cannot reach
+pub(super) fn remove_unreachable(func: &mut Function) -> usize {
+    let dominators = Dominators::new(func);
+    let (reachable, unreachable): (Vec<Block>, Vec<Block>) = func
+        .layout
+        .iter()
+        .partition(|&&block| dominators.is_reachable(block));
+    let mut removed = 0;
+    for block in unreachable {
+        for i in 0..func.block(block).insts.len() {
+            let inst = func.block(block).insts[i];
+            func.detach_results(inst);
+            removed += 1;
+        }
+        for &param in &func.block(block).params.clone() {
+            func.values[param.0 as usize].def = ValueDef::Detached;
+        }
+    }
+    func.layout = reachable;
+    removed
+}
+
+/// Merge each block ending in a jump into its target, when nothing else
+/// branches there
+fn merge_blocks(func: &mut Function) -> usize {
+    let entry = func.entry().expect("function with a body");
+    let mut merged = 0;
+    let mut predecessors = predecessors(func);
+    let mut i = 0;
+    while i < func.layout.len() {
+        let block = func.layout[i];
+        let Some(jump) = func.terminator(block) else {
+            i += 1;
+            continue;
+        };
+        let InstKind::Jump(call) = &func.inst(jump).kind else {
+            i += 1;
+            continue;
+        };
+        let target = call.block;
+        if target == entry || target == block || predecessors[&target].len() != 1 {
+            i += 1;
+            continue;
+        }
+
+        // The parameters of the target become the arguments of the jump
+        let args = call.args.clone();
+        let params = core::mem::take(&mut func.block_mut(target).params);
+        let aliases: BTreeMap<Value, Value> = params.iter().copied().zip(args).collect();
+        for &param in &params {
+            func.values[param.0 as usize].def = ValueDef::Detached;
+        }
+        func.block_mut(block).insts.pop();
+        func.detach_results(jump);
+        let insts = core::mem::take(&mut func.block_mut(target).insts);
+        func.block_mut(block).insts.extend(insts);
+        func.layout.retain(|&b| b != target);
+        func.replace_uses(&aliases);
+        for successor in func.successors(block) {
+            for predecessor in predecessors.get_mut(&successor).into_iter().flatten() {
+                if *predecessor == target {
+                    *predecessor = block;
+                }
+            }
+        }
+        merged += 1;
+        // Stay on the block, which may now jump to another block to merge
+    }
+    merged
+}
+
+fn remove_dead(func: &mut Function) -> usize {
+    let entry = func.entry().expect("function with a body");
+    let mut live: BTreeSet<Value> = func.block(entry).params.iter().copied().collect();
+    let mut worklist: Vec<Value> = Vec::new();
+    for &block in &func.layout {
+        for &inst in &func.block(block).insts {
+            match &func.inst(inst).kind {
+                // Branch arguments are only live if their parameters are
+                InstKind::Jump(_) => {}
+                InstKind::Branch(cond, ..) => worklist.push(*cond),
+                kind if kind.has_side_effects() => worklist.extend(kind.operands()),
+                _ => {}
+            }
+        }
+    }
+
+    while let Some(value) = worklist.pop() {
+        if !live.insert(value) {
+            continue;
+        }
+        match func.value(value).def {
+            ValueDef::Result(inst, _) => worklist.extend(func.inst(inst).kind.operands()),
+            ValueDef::Param(target, index) => {
+                for &block in &func.layout {
+                    let Some(terminator) = func.terminator(block) else {
+                        continue;
+                    };
+                    for call in func.inst(terminator).kind.block_calls() {
+                        if call.block == target {
+                            worklist.push(call.args[index as usize]);
+                        }
+                    }
+                }
+            }
+            ValueDef::Detached => unreachable!("use of a detached value"),
+        }
+    }
+
+    let mut removed = 0;
+    for i in 0..func.layout.len() {
+        let block = func.layout[i];
+        let insts = core::mem::take(&mut func.block_mut(block).insts);
+        let mut kept = Vec::with_capacity(insts.len());
+        for inst in insts {
+            let data = func.inst(inst);
+            if data.kind.has_side_effects() || data.results.iter().any(|r| live.contains(r)) {
+                kept.push(inst);
+            } else {
+                func.detach_results(inst);
+                removed += 1;
+            }
+        }
+        func.block_mut(block).insts = kept;
+    }
+
+    for i in 1..func.layout.len() {
+        let block = func.layout[i];
+        let keep: Vec<bool> = func
+            .block(block)
+            .params
+            .iter()
+            .map(|p| live.contains(p))
+            .collect();
+        removed += remove_params(func, block, &keep);
+    }
+    removed
+}
@@ -0,0 +1,220 @@
+//! Function inlining
+//!
+//! A call is replaced by a copy of the body of its callee when the callee
+//! has at most `threshold` instructions or is only called from that one
+//! place, and is not recursive. The block of the call is split after it:
+//! the call becomes a jump to the copied entry block, and returns become
+//! jumps to the rest of the block, whose parameters take the place of the
+//! call results. Once done, functions `main` no longer calls are removed.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+
+use crate::{
+    ir::{
+        Block, BlockCall, FuncRef, Function, Inst, InstData, InstKind, Module, Slot, Value,
+        ValueDef,
+    },
+    sema::hir::MAIN,
+};
+
+pub(super) fn run(module: &mut Module, threshold: usize) -> usize {
+    let recursive = recursive(module);
+    let mut inlined = 0;
+    for caller in 0..module.functions.len() {
+        let caller = FuncRef(caller as u32);
+        while let Some((block, index, callee)) = next_call(module, caller, |module, callee| {
+            let function = module.function(callee);
+            !function.is_declaration()
+                && !recursive.contains(&callee)
+                && (function.inst_count() <= threshold || call_count(module, callee) == 1)
+        }) {
+            let callee = module.function(callee).clone();
+            inline(module.function_mut(caller), block, index, &callee);
+            inlined += 1;
+        }
+    }
+    remove_uncalled(module);
+    inlined
+}
+
+/// First call in `caller` to a function `eligible` accepts, as its block,
+/// its position in the block and the callee
+fn next_call(
+    module: &Module,
+    caller: FuncRef,
+    eligible: impl Fn(&Module, FuncRef) -> bool,
+) -> Option<(Block, usize, FuncRef)> {
+    let func = module.function(caller);
+    for &block in &func.layout {
+        for (index, &inst) in func.block(block).insts.iter().enumerate() {
+            if let InstKind::Call(callee, _) = func.inst(inst).kind {
+                if eligible(module, callee) {
+                    return Some((block, index, callee));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Functions called by each function
+fn calls(module: &Module) -> BTreeMap<FuncRef, Vec<FuncRef>> {
+    let mut calls: BTreeMap<FuncRef, Vec<FuncRef>> = BTreeMap::new();
+    for (i, func) in module.functions.iter().enumerate() {
+        for &block in &func.layout {
+            for &inst in &func.block(block).insts {
+                if let InstKind::Call(callee, _) = func.inst(inst).kind {
+                    calls.entry(FuncRef(i as u32)).or_default().push(callee);
+                }
+            }
+        }
+    }
+    calls
+}
+
+/// Functions reachable from `roots` through calls, roots included
+fn reachable(calls: &BTreeMap<FuncRef, Vec<FuncRef>>, roots: &[FuncRef]) -> BTreeSet<FuncRef> {
+    let mut reachable = BTreeSet::new();
+    let mut worklist = roots.to_vec();
+    while let Some(func) = worklist.pop() {
+        if reachable.insert(func) {
+            worklist.extend(calls.get(&func).into_iter().flatten());
+        }
+    }
+    reachable
+}
+
+/// Functions that may call themselves
+fn recursive(module: &Module) -> BTreeSet<FuncRef> {
+    let calls = calls(module);
+    (0..module.functions.len() as u32)
+        .map(FuncRef)
+        .filter(|&func| {
+            let callees = calls.get(&func).map_or(&[][..], Vec::as_slice);
+            reachable(&calls, callees).contains(&func)
+        })
+        .collect()
+}
+
+/// Number of calls to `callee` from the functions that remain after
+/// inlining
+fn call_count(module: &Module, callee: FuncRef) -> usize {
+    let calls = calls(module);
+    let live = match module.find_function(MAIN) {
+        Some(main) => reachable(&calls, &[main]),
+        None => (0..module.functions.len() as u32).map(FuncRef).collect(),
+    };
+    live.iter()
+        .flat_map(|caller| calls.get(caller).into_iter().flatten())
+        .filter(|&&c| c == callee)
+        .count()
+}
+
+/// Replace the call at `index` in `block` of `func` by the body of `callee`
+fn inline(func: &mut Function, block: Block, index: usize, callee: &Function) {
+    let call = func.block(block).insts[index];
+    let InstKind::Call(_, args) = func.inst(call).kind.clone() else {
+        unreachable!("inlining a call");
+    };
+
+    // The rest of the block, entered with the results of the call
+    let rest = func.new_block();
+    let tail = func.block_mut(block).insts.split_off(index + 1);
+    func.block_mut(rest).insts = tail;
+    for (i, result) in func.inst(call).results.clone().into_iter().enumerate() {
+        func.values[result.0 as usize].def = ValueDef::Param(rest, i as u32);
+        func.block_mut(rest).params.push(result);
+    }
+
+    let values: Vec<Value> = callee
+        .values
+        .iter()
+        .map(|data| func.new_value(data.ty, ValueDef::Detached))
+        .collect();
+    let slots: Vec<Slot> = callee
+        .slots
+        .iter()
+        .map(|slot| func.new_slot(slot.size, slot.align))
+        .collect();
+    let blocks: BTreeMap<Block, Block> = callee
+        .layout
+        .iter()
+        .map(|&b| (b, func.new_block()))
+        .collect();
+
+    for &old in &callee.layout {
+        let new = blocks[&old];
+        for (i, param) in callee.block(old).params.iter().enumerate() {
+            let param = values[param.0 as usize];
+            func.values[param.0 as usize].def = ValueDef::Param(new, i as u32);
+            func.block_mut(new).params.push(param);
+        }
+        for &inst in &callee.block(old).insts {
+            let data = callee.inst(inst);
+            let mut kind = data.kind.clone();
+            kind.map_operands(|v| values[v.0 as usize]);
+            for target in kind.block_calls_mut() {
+                target.block = blocks[&target.block];
+            }
+            match &mut kind {
+                InstKind::Return(returned) => {
+                    kind = InstKind::Jump(BlockCall {
+                        block: rest,
+                        args: core::mem::take(returned),
+                    });
+                }
+                InstKind::StackAddr(slot) => *slot = slots[slot.0 as usize],
+                _ => {}
+            }
+            let copy = Inst(func.insts.len() as u32);
+            let results: Vec<Value> = data.results.iter().map(|r| values[r.0 as usize]).collect();
+            for (i, &result) in results.iter().enumerate() {
+                func.values[result.0 as usize].def = ValueDef::Result(copy, i as u32);
+            }
+            func.insts.push(InstData { kind, results });
+            func.block_mut(new).insts.push(copy);
+        }
+    }
+
+    let entry = blocks[&callee.entry().expect("inlining a function with a body")];
+    func.inst_mut(call).kind = InstKind::Jump(BlockCall { block: entry, args });
+    func.inst_mut(call).results.clear();
+    let position = func.layout.iter().position(|&b| b == block).unwrap() + 1;
+    let copied = callee.layout.iter().map(|b| blocks[b]).chain([rest]);
+    func.layout.splice(position..position, copied);
+}
+
+/// Remove the functions `main` does not call, directly or not, if the
+/// module has a `main`
+fn remove_uncalled(module: &mut Module) {
+    let Some(main) = module.find_function(MAIN) else {
+        return;
+    };
+    let live = reachable(&calls(module), &[main]);
+    if live.len() == module.functions.len() {
+        return;
+    }
+    let renumbered: BTreeMap<FuncRef, FuncRef> = live
+        .iter()
+        .enumerate()
+        .map(|(i, &old)| (old, FuncRef(i as u32)))
+        .collect();
+    let functions = core::mem::take(&mut module.functions);
+    module.functions = functions
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| live.contains(&FuncRef(*i as u32)))
+        .map(|(_, func)| func)
+        .collect();
+    for func in &mut module.functions {
+        for inst in &mut func.insts {
+            if let InstKind::Call(callee, _) = &mut inst.kind {
+                // Calls left in removed code may name removed functions
+                *callee = renumbered.get(callee).copied().unwrap_or(*callee);
+            }
+        }
+    }
+}
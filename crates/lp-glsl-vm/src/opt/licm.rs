@@ -0,0 +1,160 @@
+//! Loop-invariant code motion
+//!
+//! Natural loops are found from their back edges, branches to a block that
+//! dominates the branching one. Each loop is given a preheader, a block
+//! that only jumps to the loop header and that every branch from outside
+//! the loop goes through. Pure instructions whose operands are all defined
+//! outside the loop, or by instructions already hoisted, are then moved to
+//! the end of the preheader, inner loops first so that their invariants can
+//! move on out of the enclosing loops. Pure instructions cannot trap, so
+//! running them when the loop body would not have is harmless.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+
+use super::predecessors;
+use crate::ir::{Block, BlockCall, Dominators, Function, Inst, InstKind, ValueDef};
+
+pub(super) fn run(func: &mut Function) -> usize {
+    for (header, body) in loops(func) {
+        if preheader(func, header, &body).is_none() {
+            add_preheader(func, header, &body);
+        }
+    }
+
+    let mut hoisted = 0;
+    let mut block_of: BTreeMap<Inst, Block> = BTreeMap::new();
+    for &block in &func.layout {
+        for &inst in &func.block(block).insts {
+            block_of.insert(inst, block);
+        }
+    }
+    let dominators = Dominators::new(func);
+    for (header, body) in loops(func) {
+        let Some(preheader) = preheader(func, header, &body) else {
+            continue;
+        };
+        for &block in dominators.rpo() {
+            if !body.contains(&block) {
+                continue;
+            }
+            let insts = func.block(block).insts.clone();
+            for inst in insts {
+                let kind = &func.inst(inst).kind;
+                let invariant = kind.is_pure()
+                    && kind.operands().iter().all(|&v| match func.value(v).def {
+                        ValueDef::Param(b, _) => !body.contains(&b),
+                        ValueDef::Result(def, _) => !body.contains(&block_of[&def]),
+                        ValueDef::Detached => unreachable!("use of a detached value"),
+                    });
+                if !invariant {
+                    continue;
+                }
+                func.block_mut(block).insts.retain(|&i| i != inst);
+                let insts = &mut func.block_mut(preheader).insts;
+                insts.insert(insts.len() - 1, inst);
+                block_of.insert(inst, preheader);
+                hoisted += 1;
+            }
+        }
+    }
+    hoisted
+}
+
+/// Natural loops by header, with the blocks of their body including the
+/// header, innermost first
+fn loops(func: &Function) -> Vec<(Block, BTreeSet<Block>)> {
+    let dominators = Dominators::new(func);
+    let predecessors = predecessors(func);
+    let mut loops: BTreeMap<Block, BTreeSet<Block>> = BTreeMap::new();
+    for &block in dominators.rpo() {
+        for header in func.successors(block) {
+            if !dominators.dominates(header, block) {
+                continue;
+            }
+            // Everything that reaches the back edge without going through
+            // the header
+            let body = loops
+                .entry(header)
+                .or_insert_with(|| BTreeSet::from([header]));
+            let mut worklist = Vec::from([block]);
+            while let Some(b) = worklist.pop() {
+                if body.insert(b) {
+                    let preds = predecessors.get(&b).into_iter().flatten();
+                    worklist.extend(preds.filter(|&&p| dominators.is_reachable(p)));
+                }
+            }
+        }
+    }
+    let mut loops: Vec<(Block, BTreeSet<Block>)> = loops.into_iter().collect();
+    loops.sort_by_key(|(_, body)| body.len());
+    loops
+}
+
+/// Branches to the header of a loop from outside of it, as the branching
+/// block and the position of the target among its targets
+fn entries(func: &Function, header: Block, body: &BTreeSet<Block>) -> Vec<(Block, usize)> {
+    let mut entries = Vec::new();
+    for &block in &func.layout {
+        if body.contains(&block) {
+            continue;
+        }
+        for (i, successor) in func.successors(block).into_iter().enumerate() {
+            if successor == header {
+                entries.push((block, i));
+            }
+        }
+    }
+    entries
+}
+
+/// The preheader of a loop, if it already has one
+fn preheader(func: &Function, header: Block, body: &BTreeSet<Block>) -> Option<Block> {
+    let dominators = Dominators::new(func);
+    let entries: Vec<Block> = entries(func, header, body)
+        .into_iter()
+        .map(|(block, _)| block)
+        .filter(|&block| dominators.is_reachable(block))
+        .collect();
+    let [block] = entries[..] else {
+        return None;
+    };
+    let jump = func.terminator(block)?;
+    matches!(func.inst(jump).kind, InstKind::Jump(_)).then_some(block)
+}
+
+/// Route every branch from outside a loop to its header through a new
+/// block placed before the header
+fn add_preheader(func: &mut Function, header: Block, body: &BTreeSet<Block>) {
+    if func.entry() == Some(header) {
+        return;
+    }
+    let entries = entries(func, header, body);
+    let preheader = func.new_block();
+    let args = func
+        .block(header)
+        .params
+        .clone()
+        .into_iter()
+        .map(|param| {
+            let ty = func.value_type(param);
+            func.append_block_param(preheader, ty)
+        })
+        .collect();
+    let jump = func.new_inst(
+        InstKind::Jump(BlockCall {
+            block: header,
+            args,
+        }),
+        &[],
+    );
+    func.block_mut(preheader).insts.push(jump);
+    for (block, i) in entries {
+        let terminator = func.terminator(block).unwrap();
+        func.inst_mut(terminator).kind.block_calls_mut()[i].block = preheader;
+    }
+    let position = func.layout.iter().position(|&b| b == header).unwrap();
+    func.layout.insert(position, preheader);
+}
@@ -0,0 +1,276 @@
+//! Optimisation of the IR
+//!
+//! A [`PassManager`] runs a list of [`Pass`]es over a module in order and
+//! reports what each one did. Every pass keeps the module valid for
+//! [`ir::verify`] and the behaviour of its functions unchanged, down to the
+//! bits of floating-point results: passes only fold operations the way the
+//! [`Interpreter`](ir::interpret::Interpreter) evaluates them, and never
+//! reassociate.
+//!
+//! [`OptLevel`] gives the usual presets. `-O1` runs every pass, `-Os` runs
+//! the same passes but only inlines functions that are tiny or called once,
+//! so that inlining never grows the code much. Both scalarise vectors early,
+//! as the guest has no vector instructions, which lets the later passes
+//! work on each lane separately.
+
+mod const_prop;
+mod cse;
+mod dce;
+mod inline;
+mod licm;
+mod scalarize;
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::fmt;
+
+use crate::ir::{self, Block, Function, ValueDef};
+
+/// Inlining threshold of `-O1`, in instructions of the callee
+const O1_INLINE_THRESHOLD: usize = 64;
+
+/// Inlining threshold of `-Os`, about the cost of the call itself
+const OS_INLINE_THRESHOLD: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    /// Fold instructions and branches whose operands are constants
+    ConstProp,
+    /// Remove unreachable blocks, unused instructions and block parameters,
+    /// and merge straight-line blocks
+    Dce,
+    /// Replace pure instructions by an identical one that dominates them
+    Cse,
+    /// Inline calls to small functions and to functions called only once,
+    /// then remove the functions `main` no longer calls
+    Inline,
+    /// Hoist pure instructions out of the loops they do not depend on
+    Licm,
+    /// Split vector values and operations into one scalar per lane
+    Scalarize,
+}
+
+impl Pass {
+    pub const ALL: &'static [Pass] = &[
+        Pass::ConstProp,
+        Pass::Dce,
+        Pass::Cse,
+        Pass::Inline,
+        Pass::Licm,
+        Pass::Scalarize,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::ConstProp => "const-prop",
+            Pass::Dce => "dce",
+            Pass::Cse => "cse",
+            Pass::Inline => "inline",
+            Pass::Licm => "licm",
+            Pass::Scalarize => "scalarize",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Pass> {
+        Pass::ALL.iter().copied().find(|pass| pass.name() == name)
+    }
+}
+
+/// Optimisation preset
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OptLevel {
+    /// No optimisation
+    #[default]
+    O0,
+    /// Optimise for speed
+    O1,
+    /// Optimise for size
+    Os,
+}
+
+impl OptLevel {
+    /// Level from its command line flag, such as `-O1`
+    pub fn from_name(name: &str) -> Option<OptLevel> {
+        match name {
+            "-O0" => Some(OptLevel::O0),
+            "-O1" => Some(OptLevel::O1),
+            "-Os" => Some(OptLevel::Os),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OptLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OptLevel::O0 => "-O0",
+            OptLevel::O1 => "-O1",
+            OptLevel::Os => "-Os",
+        })
+    }
+}
+
+/// Passes to run, in order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PassManager {
+    pub passes: Vec<Pass>,
+    /// Largest callee, in instructions, that [`Pass::Inline`] inlines at
+    /// every call site; functions called once are inlined whatever their size
+    pub inline_threshold: usize,
+}
+
+impl PassManager {
+    pub fn for_level(level: OptLevel) -> PassManager {
+        use Pass::*;
+
+        let (passes, inline_threshold) = match level {
+            OptLevel::O0 => (Vec::new(), 0),
+            OptLevel::O1 => (
+                vec![
+                    Inline, Scalarize, ConstProp, Dce, Cse, Licm, ConstProp, Cse, Dce,
+                ],
+                O1_INLINE_THRESHOLD,
+            ),
+            OptLevel::Os => (
+                vec![
+                    Inline, Scalarize, ConstProp, Dce, Cse, Licm, ConstProp, Cse, Dce,
+                ],
+                OS_INLINE_THRESHOLD,
+            ),
+        };
+        PassManager {
+            passes,
+            inline_threshold,
+        }
+    }
+
+    /// Run the passes over `module`
+    pub fn run(&self, module: &mut ir::Module) -> Stats {
+        let mut stats = Stats::default();
+        for &pass in &self.passes {
+            let insts_before = inst_count(module);
+            let changes = match pass {
+                Pass::Inline => inline::run(module, self.inline_threshold),
+                _ => {
+                    let per_function = match pass {
+                        Pass::ConstProp => const_prop::run,
+                        Pass::Dce => dce::run,
+                        Pass::Cse => cse::run,
+                        Pass::Licm => licm::run,
+                        Pass::Scalarize => scalarize::run,
+                        Pass::Inline => unreachable!(),
+                    };
+                    module
+                        .functions
+                        .iter_mut()
+                        .filter(|f| !f.is_declaration())
+                        .map(per_function)
+                        .sum()
+                }
+            };
+            stats.passes.push(PassStats {
+                pass,
+                changes,
+                insts_before,
+                insts_after: inst_count(module),
+            });
+        }
+        stats
+    }
+}
+
+/// What one run of a pass did
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PassStats {
+    pub pass: Pass,
+    /// Number of rewrites, such as instructions removed or hoisted or calls
+    /// inlined
+    pub changes: usize,
+    /// Instructions in the module before the pass
+    pub insts_before: usize,
+    pub insts_after: usize,
+}
+
+/// Report of a [`PassManager::run`], printed as a table with a line per pass
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub passes: Vec<PassStats>,
+}
+
+impl Stats {
+    /// Changes made by every run of `pass`
+    pub fn total(&self, pass: Pass) -> usize {
+        self.passes
+            .iter()
+            .filter(|s| s.pass == pass)
+            .map(|s| s.changes)
+            .sum()
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12}{:>8}{:>8}{:>8}",
+            "pass", "changes", "before", "after"
+        )?;
+        for s in &self.passes {
+            writeln!(
+                f,
+                "{:<12}{:>8}{:>8}{:>8}",
+                s.pass.name(),
+                s.changes,
+                s.insts_before,
+                s.insts_after
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn inst_count(module: &ir::Module) -> usize {
+    module.functions.iter().map(Function::inst_count).sum()
+}
+
+/// Predecessors of each block of the body, once per branch to it
+fn predecessors(func: &Function) -> BTreeMap<Block, Vec<Block>> {
+    let mut predecessors: BTreeMap<Block, Vec<Block>> = BTreeMap::new();
+    for &block in &func.layout {
+        for successor in func.successors(block) {
+            predecessors.entry(successor).or_default().push(block);
+        }
+    }
+    predecessors
+}
+
+/// Remove the parameters of `block` not marked in `keep`, along with the
+/// arguments branches pass for them, returning how many were removed
+fn remove_params(func: &mut Function, block: Block, keep: &[bool]) -> usize {
+    if keep.iter().all(|&k| k) {
+        return 0;
+    }
+    for i in 0..func.layout.len() {
+        let Some(terminator) = func.terminator(func.layout[i]) else {
+            continue;
+        };
+        for call in func.inst_mut(terminator).kind.block_calls_mut() {
+            if call.block == block {
+                let mut keep = keep.iter();
+                call.args.retain(|_| *keep.next().unwrap());
+            }
+        }
+    }
+    let params = core::mem::take(&mut func.block_mut(block).params);
+    let mut removed = 0;
+    for (param, &keep) in params.into_iter().zip(keep) {
+        if keep {
+            let index = func.block(block).params.len() as u32;
+            func.values[param.0 as usize].def = ValueDef::Param(block, index);
+            func.block_mut(block).params.push(param);
+        } else {
+            func.values[param.0 as usize].def = ValueDef::Detached;
+            removed += 1;
+        }
+    }
+    removed
+}
@@ -0,0 +1,425 @@
+//! Vector scalarisation
+//!
+//! Every vector value is split into one scalar value per lane, and every
+//! lane-wise instruction into one instruction per lane: `splat`, `vector`,
+//! `insert` and `extract` only rename lanes and disappear, loads and stores
+//! are done a lane at a time, block parameters take one parameter per lane,
+//! and component-wise builtins are called per lane. `dot`, `length`,
+//! `distance`, `normalize`, `cross`, `faceforward`, `reflect`, `refract`,
+//! `any` and `all` are expanded into scalar arithmetic that rounds exactly
+//! as the builtins do. Once split,
+//! lanes nobody reads are ordinary dead instructions for [`dce`](super::dce).
+//!
+//! Vectors remain where they cross a function boundary, as parameters,
+//! return values and call arguments, and as arguments of builtins with no
+//! scalar expansion, such as the noise functions: they are rebuilt from
+//! their lanes there, and the lanes of a vector result are extracted.
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+use super::dce;
+use crate::{
+    ir::{
+        BinaryOp, Builtin, CompareOp, Constant, Dominators, Function, Inst, InstKind, Type,
+        UnaryOp, Value, ValueDef,
+    },
+    runtime::Routine,
+};
+
+pub(super) fn run(func: &mut Function) -> usize {
+    dce::remove_unreachable(func);
+    let mut scalarizer = Scalarizer {
+        lanes: BTreeMap::new(),
+        aliases: BTreeMap::new(),
+        out: Vec::new(),
+        split: 0,
+    };
+
+    for i in 1..func.layout.len() {
+        let block = func.layout[i];
+        let params = core::mem::take(&mut func.block_mut(block).params);
+        for param in params {
+            let ty = func.value_type(param);
+            if !ty.is_vector() {
+                let index = func.block(block).params.len() as u32;
+                func.values[param.0 as usize].def = ValueDef::Param(block, index);
+                func.block_mut(block).params.push(param);
+                continue;
+            }
+            let lanes = (0..ty.lanes)
+                .map(|_| func.append_block_param(block, ty.lane_type()))
+                .collect();
+            func.values[param.0 as usize].def = ValueDef::Detached;
+            scalarizer.lanes.insert(param, lanes);
+            scalarizer.split += 1;
+        }
+    }
+
+    let entry = func.entry().expect("function with a body");
+    let dominators = Dominators::new(func);
+    for &block in dominators.rpo() {
+        if block == entry {
+            for param in func.block(entry).params.clone() {
+                if func.value_type(param).is_vector() {
+                    scalarizer.extract_lanes(func, param);
+                }
+            }
+        }
+        for inst in core::mem::take(&mut func.block_mut(block).insts) {
+            scalarizer.inst(func, inst);
+        }
+        func.block_mut(block).insts = core::mem::take(&mut scalarizer.out);
+    }
+    func.replace_uses(&scalarizer.aliases);
+    scalarizer.split
+}
+
+struct Scalarizer {
+    /// Scalar values of the lanes of each vector value
+    lanes: BTreeMap<Value, Vec<Value>>,
+    /// Scalar results replaced by an existing value
+    aliases: BTreeMap<Value, Value>,
+    /// Instructions of the block being rewritten
+    out: Vec<Inst>,
+    /// Vector instructions and parameters split
+    split: usize,
+}
+
+impl Scalarizer {
+    fn inst(&mut self, func: &mut Function, inst: Inst) {
+        let mut kind = func.inst(inst).kind.clone();
+        let results = func.inst(inst).results.clone();
+        let vector_result = results.first().filter(|&&r| func.value_type(r).is_vector());
+        let vector_operand = kind.operands().iter().any(|&v| self.lanes.contains_key(&v));
+        let expands = matches!(kind, InstKind::Builtin(builtin, _) if has_expansion(builtin));
+        if vector_result.is_none() && !vector_operand && !expands {
+            self.out.push(inst);
+            return;
+        }
+        self.split += 1;
+
+        let lane_kinds: Vec<InstKind> = match &mut kind {
+            InstKind::Jump(_) | InstKind::Branch(..) => {
+                for call in kind.block_calls_mut() {
+                    call.args = call
+                        .args
+                        .iter()
+                        .flat_map(|arg| self.lanes.get(arg).cloned().unwrap_or(vec![*arg]))
+                        .collect();
+                }
+                func.inst_mut(inst).kind = kind;
+                self.out.push(inst);
+                return;
+            }
+            InstKind::Return(values) | InstKind::Call(_, values) => {
+                for value in values.iter_mut() {
+                    *value = self.gather(func, *value);
+                }
+                func.inst_mut(inst).kind = kind;
+                self.out.push(inst);
+                for result in results {
+                    if func.value_type(result).is_vector() {
+                        self.extract_lanes(func, result);
+                    }
+                }
+                return;
+            }
+            InstKind::Store(value, addr, offset) => {
+                for (i, lane) in self.lanes[value].clone().into_iter().enumerate() {
+                    let store = InstKind::Store(lane, *addr, *offset + 4 * i as i32);
+                    let store = func.new_inst(store, &[]);
+                    self.out.push(store);
+                }
+                func.detach_results(inst);
+                return;
+            }
+            InstKind::Builtin(builtin, args) if !is_component_wise(*builtin, args.len()) => {
+                let (builtin, args) = (*builtin, args.clone());
+                match self.expand(func, builtin, &args) {
+                    Some(lanes) => self.define(func, results[0], lanes),
+                    None => {
+                        let args = args.iter().map(|&a| self.gather(func, a)).collect();
+                        func.inst_mut(inst).kind = InstKind::Builtin(builtin, args);
+                        self.out.push(inst);
+                        if func.value_type(results[0]).is_vector() {
+                            self.extract_lanes(func, results[0]);
+                        }
+                        return;
+                    }
+                }
+                func.detach_results(inst);
+                return;
+            }
+            InstKind::Splat(value) => vec![InstKind::Splat(*value); lanes(func, results[0])],
+            InstKind::Vector(values) => values.iter().map(|&v| InstKind::Splat(v)).collect(),
+            InstKind::Extract(vector, lane) => {
+                let lane = self.lanes[vector][*lane as usize];
+                self.aliases.insert(results[0], lane);
+                func.detach_results(inst);
+                return;
+            }
+            InstKind::Insert(vector, lane, value) => {
+                let mut lanes = self.lanes[vector].clone();
+                lanes[*lane as usize] = *value;
+                self.lanes.insert(results[0], lanes);
+                func.detach_results(inst);
+                return;
+            }
+            InstKind::Load(addr, offset) => (0..lanes(func, results[0]))
+                .map(|i| InstKind::Load(*addr, *offset + 4 * i as i32))
+                .collect(),
+            _ => {
+                let n = lanes(func, results[0]);
+                (0..n)
+                    .map(|i| {
+                        let mut lane = kind.clone();
+                        lane.map_operands(|v| self.lane(v, i));
+                        lane
+                    })
+                    .collect()
+            }
+        };
+
+        // Splats of a scalar stand for the scalar itself
+        let ty = func.value_type(results[0]).lane_type();
+        let lanes = lane_kinds
+            .into_iter()
+            .map(|kind| match kind {
+                InstKind::Splat(value) => value,
+                kind => self.emit(func, kind, ty),
+            })
+            .collect();
+        self.lanes.insert(results[0], lanes);
+        func.detach_results(inst);
+    }
+
+    /// Lane `i` of `value`, or the value itself if it is a scalar
+    fn lane(&self, value: Value, i: usize) -> Value {
+        self.lanes.get(&value).map_or(value, |lanes| lanes[i])
+    }
+
+    /// Append an instruction with one result of type `ty`
+    fn emit(&mut self, func: &mut Function, kind: InstKind, ty: Type) -> Value {
+        let inst = func.new_inst(kind, &[ty]);
+        self.out.push(inst);
+        func.inst(inst).results[0]
+    }
+
+    /// Make `result` stand for `lanes`, whether it is a vector or a scalar
+    fn define(&mut self, func: &Function, result: Value, lanes: Vec<Value>) {
+        if func.value_type(result).is_vector() {
+            self.lanes.insert(result, lanes);
+        } else {
+            self.aliases.insert(result, lanes[0]);
+        }
+    }
+
+    /// Split a vector the scalarizer did not define into its lanes
+    fn extract_lanes(&mut self, func: &mut Function, vector: Value) {
+        let ty = func.value_type(vector);
+        let lanes = (0..ty.lanes)
+            .map(|i| self.emit(func, InstKind::Extract(vector, i), ty.lane_type()))
+            .collect();
+        self.lanes.insert(vector, lanes);
+    }
+
+    /// A vector value rebuilt from its lanes, for uses that need a vector
+    fn gather(&mut self, func: &mut Function, value: Value) -> Value {
+        match self.lanes.get(&value) {
+            Some(lanes) => {
+                let kind = InstKind::Vector(lanes.clone());
+                let ty = func.value_type(value);
+                self.emit(func, kind, ty)
+            }
+            None => value,
+        }
+    }
+
+    /// Lanes of the result of a builtin that works on whole vectors, from
+    /// scalar instructions, if it has an expansion
+    ///
+    /// Scalar arguments count as vectors of one lane, so `dot(x, y)` of
+    /// floats becomes a multiplication too.
+    fn expand(
+        &mut self,
+        func: &mut Function,
+        builtin: Builtin,
+        args: &[Value],
+    ) -> Option<Vec<Value>> {
+        let ty = func.value_type(args[0]).lane_type();
+        let split = |s: &Self, i: usize| s.lanes.get(&args[i]).cloned().unwrap_or(vec![args[i]]);
+        let binary = |s: &mut Self, func: &mut Function, op, a, b| {
+            let ty = func.value_type(a);
+            s.emit(func, InstKind::Binary(op, a, b), ty)
+        };
+        // Fold of the values in order, as `Iterator::sum` adds them
+        let sum = |s: &mut Self, func: &mut Function, values: Vec<Value>, op| {
+            values
+                .into_iter()
+                .reduce(|acc, v| binary(s, func, op, acc, v))
+                .unwrap()
+        };
+        let dot = |s: &mut Self, func: &mut Function, a: &[Value], b: &[Value]| {
+            let products = a
+                .iter()
+                .zip(b)
+                .map(|(&x, &y)| binary(s, func, BinaryOp::Mul, x, y))
+                .collect();
+            sum(s, func, products, BinaryOp::Add)
+        };
+
+        Some(match builtin {
+            Builtin::Dot => {
+                let (a, b) = (split(self, 0), split(self, 1));
+                vec![dot(self, func, &a, &b)]
+            }
+            Builtin::Length => {
+                let a = split(self, 0);
+                let d = dot(self, func, &a, &a);
+                vec![self.emit(func, InstKind::Builtin(Builtin::Sqrt, vec![d]), ty)]
+            }
+            Builtin::Distance => {
+                let (a, b) = (split(self, 0), split(self, 1));
+                let squares = a
+                    .iter()
+                    .zip(&b)
+                    .map(|(&x, &y)| {
+                        let d = binary(self, func, BinaryOp::Sub, x, y);
+                        binary(self, func, BinaryOp::Mul, d, d)
+                    })
+                    .collect();
+                let d = sum(self, func, squares, BinaryOp::Add);
+                vec![self.emit(func, InstKind::Builtin(Builtin::Sqrt, vec![d]), ty)]
+            }
+            Builtin::Normalize => {
+                let a = split(self, 0);
+                let d = dot(self, func, &a, &a);
+                let scale = InstKind::Builtin(Builtin::InverseSqrt, vec![d]);
+                let scale = self.emit(func, scale, ty);
+                a.iter()
+                    .map(|&x| binary(self, func, BinaryOp::Mul, x, scale))
+                    .collect()
+            }
+            Builtin::Cross => {
+                let (a, b) = (split(self, 0), split(self, 1));
+                [(1, 2), (2, 0), (0, 1)]
+                    .into_iter()
+                    .map(|(i, j)| {
+                        let x = binary(self, func, BinaryOp::Mul, a[i], b[j]);
+                        let y = binary(self, func, BinaryOp::Mul, b[i], a[j]);
+                        binary(self, func, BinaryOp::Sub, x, y)
+                    })
+                    .collect()
+            }
+            Builtin::FaceForward => {
+                let (n, i, nref) = (split(self, 0), split(self, 1), split(self, 2));
+                let d = dot(self, func, &nref, &i);
+                let zero = self.emit(func, InstKind::Const(Constant::F32(0.0)), ty);
+                let flip = InstKind::Compare(CompareOp::Ge, d, zero);
+                let flip = self.emit(func, flip, Type::BOOL);
+                n.iter()
+                    .map(|&x| {
+                        let neg = self.emit(func, InstKind::Unary(UnaryOp::Neg, x), ty);
+                        self.emit(func, InstKind::Select(flip, neg, x), ty)
+                    })
+                    .collect()
+            }
+            Builtin::Reflect => {
+                let (i, n) = (split(self, 0), split(self, 1));
+                let d = dot(self, func, &n, &i);
+                let two = self.emit(func, InstKind::Const(Constant::F32(2.0)), ty);
+                let d = binary(self, func, BinaryOp::Mul, two, d);
+                i.iter()
+                    .zip(&n)
+                    .map(|(&x, &y)| {
+                        let y = binary(self, func, BinaryOp::Mul, d, y);
+                        binary(self, func, BinaryOp::Sub, x, y)
+                    })
+                    .collect()
+            }
+            Builtin::Refract => {
+                let (i, n, eta) = (split(self, 0), split(self, 1), args[2]);
+                let d = dot(self, func, &n, &i);
+                let one = self.emit(func, InstKind::Const(Constant::F32(1.0)), ty);
+                let zero = self.emit(func, InstKind::Const(Constant::F32(0.0)), ty);
+                let dd = binary(self, func, BinaryOp::Mul, d, d);
+                let dd = binary(self, func, BinaryOp::Sub, one, dd);
+                let ee = binary(self, func, BinaryOp::Mul, eta, eta);
+                let k = binary(self, func, BinaryOp::Mul, ee, dd);
+                let k = binary(self, func, BinaryOp::Sub, one, k);
+                // Total internal reflection gives zero
+                let reflected =
+                    self.emit(func, InstKind::Compare(CompareOp::Lt, k, zero), Type::BOOL);
+                let root = self.emit(func, InstKind::Builtin(Builtin::Sqrt, vec![k]), ty);
+                let scale = binary(self, func, BinaryOp::Mul, eta, d);
+                let scale = binary(self, func, BinaryOp::Add, scale, root);
+                i.iter()
+                    .zip(&n)
+                    .map(|(&x, &y)| {
+                        let x = binary(self, func, BinaryOp::Mul, eta, x);
+                        let y = binary(self, func, BinaryOp::Mul, scale, y);
+                        let v = binary(self, func, BinaryOp::Sub, x, y);
+                        self.emit(func, InstKind::Select(reflected, zero, v), ty)
+                    })
+                    .collect()
+            }
+            Builtin::Any => vec![sum(self, func, split(self, 0), BinaryOp::Or)],
+            Builtin::All => vec![sum(self, func, split(self, 0), BinaryOp::And)],
+            _ => return None,
+        })
+    }
+}
+
+/// Lane count of the type of `value`
+fn lanes(func: &Function, value: Value) -> usize {
+    func.value_type(value).lanes as usize
+}
+
+/// Whether [`Scalarizer::expand`] replaces the builtin by scalar arithmetic
+fn has_expansion(builtin: Builtin) -> bool {
+    use Builtin as B;
+
+    matches!(
+        builtin,
+        B::Dot
+            | B::Length
+            | B::Distance
+            | B::Normalize
+            | B::Cross
+            | B::FaceForward
+            | B::Reflect
+            | B::Refract
+            | B::Any
+            | B::All
+    )
+}
+
+/// Whether each lane of the result of the builtin only depends on the same
+/// lane of the arguments
+fn is_component_wise(builtin: Builtin, arity: usize) -> bool {
+    use Builtin as B;
+
+    Routine::of(builtin, arity).is_some()
+        || matches!(
+            builtin,
+            B::Abs
+                | B::Sign
+                | B::Min
+                | B::Max
+                | B::Clamp
+                | B::Mix
+                | B::IsNan
+                | B::IsInf
+                | B::FloatBitsToInt
+                | B::FloatBitsToUint
+                | B::IntBitsToFloat
+                | B::UintBitsToFloat
+                | B::LessThan
+                | B::LessThanEqual
+                | B::GreaterThan
+                | B::GreaterThanEqual
+                | B::Equal
+                | B::NotEqual
+                | B::Not
+        )
+}
@@ -0,0 +1,141 @@
+//! Evaluation of `#if` and `#elif` expressions
+//!
+//! Operands are integers; `defined` has already been replaced and macros
+//! expanded by the time an expression reaches [`evaluate`].
+
+use alloc::{format, string::String, vec::Vec};
+
+use super::lexer::{Token, TokenKind};
+
+/// Evaluate a controlling expression
+pub(super) fn evaluate(tokens: &[Token]) -> Result<i64, String> {
+    let tokens: Vec<&Token> = tokens
+        .iter()
+        .filter(|t| t.kind != TokenKind::Space)
+        .collect();
+    if tokens.is_empty() {
+        return Err("expected an expression".into());
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let value = parser.binary(0)?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(value),
+        Some(t) => Err(format!(
+            "unexpected `{}` in preprocessor expression",
+            t.text
+        )),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<&'a Token>,
+    pos: usize,
+}
+
+/// Binding strength of a binary operator, higher binds tighter
+fn precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        _ => return None,
+    })
+}
+
+impl Parser<'_> {
+    fn binary(&mut self, min: u8) -> Result<i64, String> {
+        let mut lhs = self.unary()?;
+        while let Some(token) = self.tokens.get(self.pos) {
+            let Some(prec) = precedence(&token.text).filter(|p| *p > min) else {
+                break;
+            };
+            if token.kind != TokenKind::Punct {
+                break;
+            }
+            let op = token.text.as_str();
+            self.pos += 1;
+            let rhs = self.binary(prec)?;
+            lhs = apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        let Some(token) = self.tokens.get(self.pos) else {
+            return Err("unexpected end of preprocessor expression".into());
+        };
+        self.pos += 1;
+        match token.kind {
+            TokenKind::Number => parse_integer(&token.text),
+            TokenKind::Ident => Err(format!(
+                "undefined identifier `{}` in preprocessor expression",
+                token.text
+            )),
+            _ => match token.text.as_str() {
+                "+" => self.unary(),
+                "-" => Ok(self.unary()?.wrapping_neg()),
+                "~" => Ok(!self.unary()?),
+                "!" => Ok((self.unary()? == 0) as i64),
+                "(" => {
+                    let value = self.binary(0)?;
+                    match self.tokens.get(self.pos) {
+                        Some(t) if t.is_punct(")") => {
+                            self.pos += 1;
+                            Ok(value)
+                        }
+                        _ => Err("expected `)` in preprocessor expression".into()),
+                    }
+                }
+                other => Err(format!("unexpected `{}` in preprocessor expression", other)),
+            },
+        }
+    }
+}
+
+fn apply(op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+    Ok(match op {
+        "||" => (lhs != 0 || rhs != 0) as i64,
+        "&&" => (lhs != 0 && rhs != 0) as i64,
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "&" => lhs & rhs,
+        "==" => (lhs == rhs) as i64,
+        "!=" => (lhs != rhs) as i64,
+        "<" => (lhs < rhs) as i64,
+        ">" => (lhs > rhs) as i64,
+        "<=" => (lhs <= rhs) as i64,
+        ">=" => (lhs >= rhs) as i64,
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" if rhs == 0 => return Err("division by zero in preprocessor expression".into()),
+        "/" => lhs.wrapping_div(rhs),
+        "%" => lhs.wrapping_rem(rhs),
+        _ => unreachable!("not a binary operator: {}", op),
+    })
+}
+
+/// Parse a decimal, octal or hexadecimal integer with an optional `u` suffix
+fn parse_integer(text: &str) -> Result<i64, String> {
+    let digits = text.trim_end_matches(['u', 'U']);
+    let parsed = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    };
+    parsed.map_err(|_| format!("invalid integer `{}` in preprocessor expression", text))
+}
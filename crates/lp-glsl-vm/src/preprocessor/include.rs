@@ -0,0 +1,134 @@
+//! Resolution of `#include` directives
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// Contents of an included file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolvedInclude {
+    /// Canonical name, used in diagnostics and to resolve nested includes
+    pub name: String,
+    pub text: String,
+}
+
+/// Looks up the files named by `#include`
+pub trait IncludeResolver {
+    /// Resolve `path` as written in the directive, included from file `from`
+    fn resolve(&self, path: &str, from: &str) -> Result<ResolvedInclude, String>;
+}
+
+/// Resolver that rejects every `#include`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoIncludes;
+
+impl IncludeResolver for NoIncludes {
+    fn resolve(&self, _path: &str, _from: &str) -> Result<ResolvedInclude, String> {
+        Err("#include is not available here".into())
+    }
+}
+
+/// Resolver over an in-memory set of named files
+///
+/// Paths are looked up relative to the including file first, then as given.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryIncludes {
+    files: BTreeMap<String, String>,
+}
+
+impl MemoryIncludes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, text: impl Into<String>) {
+        self.files.insert(normalize(&name.into()), text.into());
+    }
+
+    pub fn with(mut self, name: impl Into<String>, text: impl Into<String>) -> Self {
+        self.insert(name, text);
+        self
+    }
+}
+
+impl IncludeResolver for MemoryIncludes {
+    fn resolve(&self, path: &str, from: &str) -> Result<ResolvedInclude, String> {
+        [join(from, path), normalize(path)]
+            .into_iter()
+            .find_map(|name| {
+                let text = self.files.get(&name)?.clone();
+                Some(ResolvedInclude { name, text })
+            })
+            .ok_or_else(|| "file not found".to_string())
+    }
+}
+
+/// Resolver that reads files from disk
+///
+/// Paths are looked up relative to the including file, then in each search
+/// directory in order.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default)]
+pub struct FileIncludes {
+    search_paths: Vec<std::path::PathBuf>,
+}
+
+#[cfg(feature = "std")]
+impl FileIncludes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_search_path(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.search_paths.push(path.into());
+        self
+    }
+}
+
+#[cfg(feature = "std")]
+impl IncludeResolver for FileIncludes {
+    fn resolve(&self, path: &str, from: &str) -> Result<ResolvedInclude, String> {
+        let relative = std::path::Path::new(&join(from, path)).to_path_buf();
+        let candidates =
+            core::iter::once(relative).chain(self.search_paths.iter().map(|dir| dir.join(path)));
+        for candidate in candidates {
+            if candidate.is_file() {
+                let text = std::fs::read_to_string(&candidate).map_err(|e| e.to_string())?;
+                return Ok(ResolvedInclude {
+                    name: candidate.display().to_string(),
+                    text,
+                });
+            }
+        }
+        Err("file not found".into())
+    }
+}
+
+/// Join `path` onto the directory containing `from`
+fn join(from: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        return normalize(path);
+    }
+    match from.rfind('/') {
+        Some(slash) => normalize(&alloc::format!("{}/{}", &from[..slash], path)),
+        None => normalize(path),
+    }
+}
+
+/// Remove `.` segments and resolve `..` segments where possible
+fn normalize(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "." => {}
+            "" if !segments.is_empty() => {}
+            ".." if segments.last().is_some_and(|s| *s != ".." && !s.is_empty()) => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
@@ -0,0 +1,185 @@
+use alloc::{string::String, vec::Vec};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum TokenKind {
+    Ident,
+    Number,
+    Punct,
+    Space,
+}
+
+/// Preprocessing token
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    /// Macros that must not expand this token again
+    pub hide: Vec<String>,
+}
+
+impl Token {
+    pub fn new(kind: TokenKind, text: impl Into<String>) -> Self {
+        Self {
+            kind,
+            text: text.into(),
+            hide: Vec::new(),
+        }
+    }
+
+    pub fn space() -> Self {
+        Self::new(TokenKind::Space, " ")
+    }
+
+    pub fn is_punct(&self, text: &str) -> bool {
+        self.kind == TokenKind::Punct && self.text == text
+    }
+}
+
+/// A line after comment removal and line splicing
+pub(super) struct LogicalLine {
+    /// 1-based physical line the logical line starts on
+    pub line: usize,
+    pub text: String,
+}
+
+/// Split source text into logical lines
+///
+/// Comments are replaced by a single space and backslash-newline pairs are
+/// removed, so a logical line may span several physical lines.
+pub(super) fn logical_lines(text: &str) -> Vec<LogicalLine> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut start_line = 1;
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some('\n')) => {
+                chars.next();
+                line += 1;
+            }
+            '\\' if matches!(chars.peek(), Some('\r')) => {
+                chars.next();
+                if matches!(chars.peek(), Some('\n')) {
+                    chars.next();
+                }
+                line += 1;
+            }
+            '/' if matches!(chars.peek(), Some('/')) => {
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            '/' if matches!(chars.peek(), Some('*')) => {
+                chars.next();
+                let mut prev = '\0';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+                current.push(' ');
+            }
+            '\n' => {
+                lines.push(LogicalLine {
+                    line: start_line,
+                    text: core::mem::take(&mut current),
+                });
+                line += 1;
+                start_line = line;
+            }
+            '\r' => {}
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        lines.push(LogicalLine {
+            line: start_line,
+            text: current,
+        });
+    }
+    lines
+}
+
+const PUNCTUATORS: [&str; 23] = [
+    "<<=", ">>=", "##", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "^^", "+=",
+    "-=", "*=", "/=", "%=", "&=", "|=", "^=", "..",
+];
+
+/// Split a logical line into preprocessing tokens
+///
+/// Runs of whitespace become a single space token that keeps its original
+/// text, so lines without macro expansions are reproduced exactly.
+pub(super) fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        let kind = if c.is_ascii_whitespace() {
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            tokens.push(Token::new(TokenKind::Space, &text[start..i]));
+            continue;
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            TokenKind::Ident
+        } else if c.is_ascii_digit()
+            || (c == b'.' && bytes.get(i + 1).is_some_and(|b| b.is_ascii_digit()))
+        {
+            // pp-number: digits, letters, dots and signed exponents
+            i += 1;
+            while i < bytes.len() {
+                let b = bytes[i];
+                let exponent_sign = (b == b'+' || b == b'-') && matches!(bytes[i - 1], b'e' | b'E');
+                if !(exponent_sign || b.is_ascii_alphanumeric() || b == b'_' || b == b'.') {
+                    break;
+                }
+                i += 1;
+            }
+            TokenKind::Number
+        } else {
+            let len = PUNCTUATORS
+                .iter()
+                .find(|p| text[i..].starts_with(*p))
+                .map_or_else(
+                    || text[i..].chars().next().map_or(1, char::len_utf8),
+                    |p| p.len(),
+                );
+            i += len;
+            TokenKind::Punct
+        };
+        tokens.push(Token::new(kind, &text[start..i]));
+    }
+    tokens
+}
+
+/// Concatenate token texts
+pub(super) fn join(tokens: &[Token]) -> String {
+    tokens.iter().map(|t| t.text.as_str()).collect()
+}
+
+/// Tokens with leading and trailing whitespace removed
+pub(super) fn trim(tokens: &[Token]) -> &[Token] {
+    let start = tokens
+        .iter()
+        .position(|t| t.kind != TokenKind::Space)
+        .unwrap_or(tokens.len());
+    let end = tokens
+        .iter()
+        .rposition(|t| t.kind != TokenKind::Space)
+        .map_or(start, |i| i + 1);
+    &tokens[start..end]
+}
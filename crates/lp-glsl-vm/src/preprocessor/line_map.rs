@@ -0,0 +1,77 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::diagnostic::{SourceId, SourceMap, Span};
+
+/// Original location of a line of preprocessed output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineOrigin {
+    pub source: SourceId,
+    /// 1-based line in the original file
+    pub line: usize,
+}
+
+/// Maps each line of preprocessed output back to the file it came from
+#[derive(Clone, Debug, Default)]
+pub struct LineMap {
+    lines: Vec<LineOrigin>,
+}
+
+impl LineMap {
+    pub(super) fn push(&mut self, origin: LineOrigin) {
+        self.lines.push(origin);
+    }
+
+    /// Origin of a 1-based output line
+    pub fn origin(&self, line: usize) -> Option<LineOrigin> {
+        self.lines.get(line.checked_sub(1)?).copied()
+    }
+
+    /// Map a byte range of the preprocessed `output` back to its original source
+    ///
+    /// Lines that came through unchanged map exactly. On lines altered by
+    /// macro expansion the range's text is searched for in the original
+    /// line, falling back to the whole line.
+    pub fn map_range(
+        &self,
+        output: &str,
+        sources: &SourceMap,
+        range: Range<usize>,
+    ) -> Option<Span> {
+        let before = output.get(..range.start)?;
+        let out_line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let out_line = output[out_line_start..].split('\n').next().unwrap_or("");
+        let origin = self.origin(before.matches('\n').count() + 1)?;
+
+        let file = sources.get(origin.source);
+        let original = file.line(origin.line);
+        let base = file.line_start(origin.line)?;
+        let len = range.end.saturating_sub(range.start);
+        if original == out_line {
+            let start = base + range.start - out_line_start;
+            return Some(Span::new(origin.source, start, start + len));
+        }
+        let needle = output.get(range)?;
+        match find_token(original, needle) {
+            Some(at) => Some(Span::new(origin.source, base + at, base + at + len)),
+            None => sources.line_span(origin.source, origin.line),
+        }
+    }
+}
+
+/// First occurrence of `needle` in `line` that is not part of a longer identifier
+fn find_token(line: &str, needle: &str) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    let is_ident = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+    line.match_indices(needle).map(|(at, _)| at).find(|&at| {
+        let before = line[..at].chars().next_back();
+        let after = line[at + needle.len()..].chars().next();
+        let first = needle.chars().next();
+        let last = needle.chars().next_back();
+        let joins_before = is_ident(first) && is_ident(before);
+        let joins_after = is_ident(last) && is_ident(after);
+        !joins_before && !joins_after
+    })
+}
@@ -0,0 +1,230 @@
+//! Macro expansion
+//!
+//! Uses hide sets: every token produced by expanding macro `M` remembers
+//! `M`, so a macro never expands inside its own expansion while the result
+//! is still rescanned together with the rest of the line.
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use super::lexer::{tokenize, trim, Token, TokenKind};
+use crate::diagnostic::SourceId;
+
+/// A `#define`d macro
+#[derive(Clone, Debug)]
+pub(super) struct Macro {
+    /// Parameter names for function-like macros
+    pub params: Option<Vec<String>>,
+    /// Replacement list with whitespace collapsed to single spaces
+    pub body: Vec<Token>,
+    /// Directive that defined the macro, if it came from source
+    pub defined_at: Option<(SourceId, usize)>,
+}
+
+impl Macro {
+    /// Whether two definitions are the same, as required for redefinition
+    pub fn same_definition(&self, other: &Macro) -> bool {
+        let texts = |m: &Macro| m.body.iter().map(|t| t.text.clone()).collect::<Vec<_>>();
+        self.params == other.params && texts(self) == texts(other)
+    }
+}
+
+pub(super) enum ExpandError {
+    /// A function-like macro invocation is missing its closing parenthesis
+    Incomplete(String),
+    Message(String),
+}
+
+/// Expands macros in a line of tokens
+pub(super) struct Expander<'a> {
+    pub macros: &'a BTreeMap<String, Macro>,
+    /// Value of `__LINE__`
+    pub line: i64,
+    /// Value of `__FILE__`
+    pub file: u32,
+    /// Value of `__VERSION__`
+    pub version: u32,
+}
+
+impl Expander<'_> {
+    pub fn expand(&self, tokens: Vec<Token>) -> Result<Vec<Token>, ExpandError> {
+        let mut input: VecDeque<Token> = tokens.into();
+        let mut out = Vec::new();
+
+        while let Some(token) = input.pop_front() {
+            if token.kind != TokenKind::Ident || token.hide.contains(&token.text) {
+                out.push(token);
+                continue;
+            }
+            let value = match token.text.as_str() {
+                "__LINE__" => Some(self.line.to_string()),
+                "__FILE__" => Some(self.file.to_string()),
+                "__VERSION__" => Some(self.version.to_string()),
+                _ => None,
+            };
+            if let Some(value) = value {
+                out.push(Token::new(TokenKind::Number, value));
+                continue;
+            }
+            let Some(definition) = self.macros.get(&token.text) else {
+                out.push(token);
+                continue;
+            };
+
+            let mut hide = token.hide.clone();
+            hide.push(token.text.clone());
+            let replacement = match &definition.params {
+                None => self.substitute(definition, &[], &hide)?,
+                Some(params) => {
+                    // Without a following `(` the name is not an invocation
+                    let open = input.iter().position(|t| t.kind != TokenKind::Space);
+                    if !open.is_some_and(|i| input[i].is_punct("(")) {
+                        out.push(token);
+                        continue;
+                    }
+                    input.drain(..=open.unwrap_or(0));
+                    let args = collect_args(&mut input, &token.text)?;
+                    let arity_ok = args.len() == params.len()
+                        || (params.is_empty() && args.len() == 1 && trim(&args[0]).is_empty());
+                    if !arity_ok {
+                        return Err(ExpandError::Message(format!(
+                            "macro `{}` takes {} argument{} but {} were given",
+                            token.text,
+                            params.len(),
+                            if params.len() == 1 { "" } else { "s" },
+                            args.len()
+                        )));
+                    }
+                    self.substitute(definition, &args, &hide)?
+                }
+            };
+
+            // Rescan the replacement together with the rest of the line,
+            // padded so it cannot fuse with neighbouring tokens
+            input.push_front(Token::space());
+            for token in replacement.into_iter().rev() {
+                input.push_front(token);
+            }
+            input.push_front(Token::space());
+        }
+        Ok(out)
+    }
+
+    /// Replace parameters in a macro body and apply `##`
+    fn substitute(
+        &self,
+        definition: &Macro,
+        args: &[Vec<Token>],
+        hide: &[String],
+    ) -> Result<Vec<Token>, ExpandError> {
+        let params = definition.params.as_deref().unwrap_or(&[]);
+        let body = &definition.body;
+        let neighbour_is_paste = |i: usize, step: isize| {
+            let mut j = i as isize + step;
+            while j >= 0 && (j as usize) < body.len() {
+                let t = &body[j as usize];
+                if t.kind != TokenKind::Space {
+                    return t.is_punct("##");
+                }
+                j += step;
+            }
+            false
+        };
+
+        let mut result = Vec::new();
+        for (i, token) in body.iter().enumerate() {
+            let param = (token.kind == TokenKind::Ident)
+                .then(|| params.iter().position(|p| *p == token.text))
+                .flatten();
+            match param {
+                Some(p) if neighbour_is_paste(i, -1) || neighbour_is_paste(i, 1) => {
+                    result.extend(trim(&args[p]).iter().cloned());
+                }
+                Some(p) => match self.expand(args[p].clone()) {
+                    Ok(tokens) => result.extend(tokens),
+                    Err(ExpandError::Incomplete(name)) => {
+                        return Err(ExpandError::Message(format!(
+                            "unterminated invocation of macro `{}` in macro argument",
+                            name
+                        )))
+                    }
+                    Err(e) => return Err(e),
+                },
+                None => result.push(token.clone()),
+            }
+        }
+
+        let mut result = paste(result)?;
+        for token in &mut result {
+            for name in hide {
+                if !token.hide.contains(name) {
+                    token.hide.push(name.clone());
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Collect the comma separated arguments of an invocation, consuming the closing `)`
+fn collect_args(input: &mut VecDeque<Token>, name: &str) -> Result<Vec<Vec<Token>>, ExpandError> {
+    let mut args = Vec::new();
+    let mut current = Vec::new();
+    let mut depth = 0;
+    loop {
+        let Some(token) = input.pop_front() else {
+            return Err(ExpandError::Incomplete(name.into()));
+        };
+        if token.is_punct("(") {
+            depth += 1;
+        } else if token.is_punct(")") {
+            if depth == 0 {
+                args.push(current);
+                return Ok(args);
+            }
+            depth -= 1;
+        } else if token.is_punct(",") && depth == 0 {
+            args.push(core::mem::take(&mut current));
+            continue;
+        }
+        current.push(token);
+    }
+}
+
+/// Apply the `##` operator by concatenating its operands
+fn paste(tokens: Vec<Token>) -> Result<Vec<Token>, ExpandError> {
+    let mut out: Vec<Token> = Vec::new();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        if !token.is_punct("##") {
+            out.push(token);
+            continue;
+        }
+        while out.last().is_some_and(|t| t.kind == TokenKind::Space) {
+            out.pop();
+        }
+        while tokens.peek().is_some_and(|t| t.kind == TokenKind::Space) {
+            tokens.next();
+        }
+        let (Some(left), Some(right)) = (out.pop(), tokens.next()) else {
+            return Err(ExpandError::Message(
+                "`##` must appear between two tokens".into(),
+            ));
+        };
+        let mut joined = tokenize(&format!("{}{}", left.text, right.text));
+        if joined.len() != 1 {
+            return Err(ExpandError::Message(format!(
+                "pasting `{}` and `{}` does not give a valid token",
+                left.text, right.text
+            )));
+        }
+        let mut token = joined.remove(0);
+        token.hide = left.hide;
+        out.push(token);
+    }
+    Ok(out)
+}
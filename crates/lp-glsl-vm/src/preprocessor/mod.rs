@@ -0,0 +1,595 @@
+//! GLSL preprocessor
+//!
+//! Runs before parsing. Supports object-like and function-like macros with
+//! `##`, conditional compilation with `#if`/`#ifdef`/`#ifndef`/`#elif`/
+//! `#else`, `#include` through an [`IncludeResolver`], `#line`, `#error`,
+//! `#pragma once` and the predefined `__LINE__`, `__FILE__`, `__VERSION__`
+//! and `GL_ES` macros. `#version` and `#extension` lines are passed through
+//! to the parser.
+//!
+//! Every output line is recorded in a [`LineMap`], so positions in the
+//! preprocessed text can be mapped back to the file and line they came from.
+
+mod expr;
+mod include;
+mod lexer;
+mod line_map;
+mod macros;
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+#[cfg(feature = "std")]
+pub use include::FileIncludes;
+pub use include::{IncludeResolver, MemoryIncludes, NoIncludes, ResolvedInclude};
+use lexer::{join, logical_lines, tokenize, trim, LogicalLine, Token, TokenKind};
+pub use line_map::{LineMap, LineOrigin};
+use macros::{ExpandError, Expander, Macro};
+
+use crate::diagnostic::{Diagnostic, SourceId, SourceMap};
+
+/// Deepest chain of nested `#include`s before giving up
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// `__VERSION__` when the shader has no `#version` directive, the desktop
+/// version analysis assumes as [`Version::DEFAULT`](crate::sema::Version::DEFAULT)
+const DEFAULT_VERSION: u32 = 110;
+
+/// Output of the preprocessor
+#[derive(Clone, Debug)]
+pub struct Preprocessed {
+    /// Text to hand to the parser
+    pub text: String,
+    /// Origin of every line of `text`
+    pub line_map: LineMap,
+    /// Number from the `#version` directive, if any
+    pub version: Option<u32>,
+}
+
+/// An open `#if` group
+struct Conditional {
+    /// Whether lines in the current branch are kept
+    active: bool,
+    /// Whether any branch of the group has been taken
+    taken: bool,
+    /// Whether the enclosing group is active
+    parent_active: bool,
+    seen_else: bool,
+    line: usize,
+}
+
+/// Per-file state while processing
+struct FileState {
+    id: SourceId,
+    conditionals: Vec<Conditional>,
+    /// Adjustment applied to physical line numbers by `#line`
+    line_offset: i64,
+    /// Value of `__FILE__`
+    file_number: u32,
+}
+
+impl FileState {
+    fn active(&self) -> bool {
+        self.conditionals.last().is_none_or(|c| c.active)
+    }
+}
+
+/// Preprocesses one shader and the files it includes
+pub struct Preprocessor<'a> {
+    resolver: &'a dyn IncludeResolver,
+    macros: BTreeMap<String, Macro>,
+    /// Files that contained `#pragma once`
+    once: BTreeSet<String>,
+    depth: usize,
+    version: Option<u32>,
+    /// `__VERSION__` when the shader has no `#version` directive
+    default_version: u32,
+    /// Whether `GL_ES` was defined for the default version rather than by
+    /// the host, so that a desktop `#version` removes it
+    default_gl_es: bool,
+    output: String,
+    line_map: LineMap,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a> Preprocessor<'a> {
+    pub fn new(resolver: &'a dyn IncludeResolver) -> Self {
+        Self {
+            resolver,
+            macros: BTreeMap::new(),
+            once: BTreeSet::new(),
+            depth: 0,
+            version: None,
+            default_version: DEFAULT_VERSION,
+            default_gl_es: false,
+            output: String::new(),
+            line_map: LineMap::default(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Predefine an object-like macro, like `#define name value`
+    pub fn define(&mut self, name: &str, value: &str) {
+        let definition = Macro {
+            params: None,
+            body: normalize_body(trim(&tokenize(value))),
+            defined_at: None,
+        };
+        self.macros.insert(name.into(), definition);
+        if name == "GL_ES" {
+            self.default_gl_es = false;
+        }
+    }
+
+    /// Version assumed when the shader has no `#version` directive
+    ///
+    /// This sets `__VERSION__`, and defines `GL_ES` for an ES version.
+    pub fn default_version(&mut self, number: u32, es: bool) {
+        self.default_version = number;
+        if es && !self.macros.contains_key("GL_ES") {
+            self.define("GL_ES", "1");
+            self.default_gl_es = true;
+        }
+    }
+
+    /// Preprocess `root`, adding included files to `sources`
+    pub fn run(
+        mut self,
+        sources: &mut SourceMap,
+        root: SourceId,
+    ) -> Result<Preprocessed, Vec<Diagnostic>> {
+        self.process(sources, root);
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        Ok(Preprocessed {
+            text: self.output,
+            line_map: self.line_map,
+            version: self.version,
+        })
+    }
+
+    fn process(&mut self, sources: &mut SourceMap, id: SourceId) {
+        let text = sources.get(id).text.clone();
+        let mut file = FileState {
+            id,
+            conditionals: Vec::new(),
+            line_offset: 0,
+            file_number: id.0,
+        };
+        // Lines of a function-like macro invocation whose arguments continue
+        // on the next line
+        let mut pending: Option<(usize, Vec<Token>)> = None;
+
+        for line in logical_lines(&text) {
+            if line.text.trim_start().starts_with('#') {
+                if let Some((start, tokens)) = pending.take() {
+                    self.expand_line(sources, &file, start, tokens, true);
+                }
+                self.directive(sources, &mut file, &line);
+                continue;
+            }
+            if !file.active() {
+                continue;
+            }
+            let (start, mut tokens) = pending.take().unwrap_or((line.line, Vec::new()));
+            if !tokens.is_empty() {
+                tokens.push(Token::space());
+            }
+            tokens.extend(tokenize(&line.text));
+            pending = self
+                .expand_line(sources, &file, start, tokens, false)
+                .map(|tokens| (start, tokens));
+        }
+        if let Some((start, tokens)) = pending {
+            self.expand_line(sources, &file, start, tokens, true);
+        }
+        for conditional in &file.conditionals {
+            self.error(sources, id, conditional.line, "unterminated `#if`");
+        }
+    }
+
+    /// Expand macros in a line and emit it
+    ///
+    /// Returns the tokens back if a macro invocation continues past the end
+    /// of the line and `last` is false.
+    fn expand_line(
+        &mut self,
+        sources: &SourceMap,
+        file: &FileState,
+        line: usize,
+        tokens: Vec<Token>,
+        last: bool,
+    ) -> Option<Vec<Token>> {
+        let expander = self.expander(file, line);
+        match expander.expand(tokens.clone()) {
+            Ok(expanded) => self.emit(file.id, line, join(&expanded).trim_end()),
+            Err(ExpandError::Incomplete(_)) if !last => return Some(tokens),
+            Err(ExpandError::Incomplete(name)) => {
+                let message = format!("unterminated invocation of macro `{}`", name);
+                self.error(sources, file.id, line, message);
+            }
+            Err(ExpandError::Message(message)) => self.error(sources, file.id, line, message),
+        }
+        None
+    }
+
+    fn expander(&self, file: &FileState, line: usize) -> Expander<'_> {
+        Expander {
+            macros: &self.macros,
+            line: line as i64 + file.line_offset,
+            file: file.file_number,
+            version: self.version.unwrap_or(self.default_version),
+        }
+    }
+
+    fn emit(&mut self, source: SourceId, line: usize, text: &str) {
+        self.output.push_str(text);
+        self.output.push('\n');
+        self.line_map.push(LineOrigin { source, line });
+    }
+
+    fn error(
+        &mut self,
+        sources: &SourceMap,
+        id: SourceId,
+        line: usize,
+        message: impl Into<String>,
+    ) {
+        let mut diagnostic = Diagnostic::error(message);
+        if let Some(span) = sources.line_span(id, line) {
+            diagnostic = diagnostic.with_primary(span, "");
+        }
+        self.errors.push(diagnostic);
+    }
+
+    fn directive(&mut self, sources: &mut SourceMap, file: &mut FileState, line: &LogicalLine) {
+        let text = line.text.trim_start()[1..].trim_start();
+        let name_len = text
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(text.len());
+        let (name, rest) = text.split_at(name_len);
+        let rest = rest.trim();
+
+        match name {
+            "if" | "ifdef" | "ifndef" => {
+                let parent_active = file.active();
+                let active = parent_active && self.condition(sources, file, line, name, rest);
+                file.conditionals.push(Conditional {
+                    active,
+                    taken: active,
+                    parent_active,
+                    seen_else: false,
+                    line: line.line,
+                });
+            }
+            "elif" | "else" => {
+                let Some(top) = file.conditionals.last() else {
+                    let message = format!("`#{}` without `#if`", name);
+                    return self.error(sources, file.id, line.line, message);
+                };
+                if top.seen_else {
+                    let message = format!("`#{}` after `#else`", name);
+                    return self.error(sources, file.id, line.line, message);
+                }
+                let enter = top.parent_active && !top.taken;
+                let active =
+                    enter && (name == "else" || self.condition(sources, file, line, name, rest));
+                let top = file.conditionals.last_mut().unwrap();
+                top.active = active;
+                top.taken |= active;
+                top.seen_else = name == "else";
+            }
+            "endif" => {
+                if file.conditionals.pop().is_none() {
+                    self.error(sources, file.id, line.line, "`#endif` without `#if`");
+                }
+            }
+            _ if !file.active() => {}
+            "" => {}
+            "define" => self.define_directive(sources, file, line, rest),
+            "undef" => {
+                let name = rest.split_whitespace().next().unwrap_or("");
+                if is_reserved(name) {
+                    let message = format!("cannot undefine reserved macro `{}`", name);
+                    self.error(sources, file.id, line.line, message);
+                } else {
+                    self.macros.remove(name);
+                }
+            }
+            "include" => self.include(sources, file, line, rest),
+            "line" => self.line_directive(sources, file, line, rest),
+            "error" => self.error(sources, file.id, line.line, format!("#error {}", rest)),
+            "pragma" => {
+                if rest == "once" {
+                    self.once.insert(sources.get(file.id).name.clone());
+                }
+            }
+            "version" => {
+                if !self.output.trim().is_empty() || self.depth > 0 || self.version.is_some() {
+                    let message = "`#version` must come before anything else in the shader";
+                    return self.error(sources, file.id, line.line, message);
+                }
+                let mut words = rest.split_whitespace();
+                let number = words.next().and_then(|n| n.parse::<u32>().ok());
+                let Some(number) = number else {
+                    let message = "expected a version number after `#version`";
+                    return self.error(sources, file.id, line.line, message);
+                };
+                if number == 100 || words.next() == Some("es") {
+                    self.define("GL_ES", "1");
+                } else if self.default_gl_es {
+                    // Defined for an ES default version that this one replaces
+                    self.macros.remove("GL_ES");
+                }
+                self.version = Some(number);
+                self.emit(file.id, line.line, line.text.trim());
+            }
+            "extension" => self.emit(file.id, line.line, line.text.trim()),
+            _ => {
+                let message = format!("unknown preprocessor directive `#{}`", name);
+                self.error(sources, file.id, line.line, message);
+            }
+        }
+    }
+
+    /// Evaluate the condition of an `#if`, `#ifdef`, `#ifndef` or `#elif`
+    fn condition(
+        &mut self,
+        sources: &SourceMap,
+        file: &FileState,
+        line: &LogicalLine,
+        directive: &str,
+        rest: &str,
+    ) -> bool {
+        if directive != "if" && directive != "elif" {
+            let tokens = tokenize(rest);
+            let name = match trim(&tokens) {
+                [name] if name.kind == TokenKind::Ident => name.text.clone(),
+                _ => {
+                    let message = format!("expected a macro name after `#{}`", directive);
+                    self.error(sources, file.id, line.line, message);
+                    return false;
+                }
+            };
+            return self.is_defined(&name) == (directive == "ifdef");
+        }
+
+        let tokens = match self.replace_defined(&tokenize(rest)) {
+            Ok(tokens) => tokens,
+            Err(message) => {
+                self.error(sources, file.id, line.line, message);
+                return false;
+            }
+        };
+        let result = match self.expander(file, line.line).expand(tokens) {
+            Ok(tokens) => expr::evaluate(&tokens),
+            Err(ExpandError::Incomplete(name)) => {
+                Err(format!("unterminated invocation of macro `{}`", name))
+            }
+            Err(ExpandError::Message(message)) => Err(message),
+        };
+        match result {
+            Ok(value) => value != 0,
+            Err(message) => {
+                self.error(sources, file.id, line.line, message);
+                false
+            }
+        }
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.macros.contains_key(name) || matches!(name, "__LINE__" | "__FILE__" | "__VERSION__")
+    }
+
+    /// Replace `defined NAME` and `defined(NAME)` with `1` or `0`
+    fn replace_defined(&self, tokens: &[Token]) -> Result<Vec<Token>, String> {
+        let mut out = Vec::new();
+        let mut iter = tokens.iter().filter(|t| t.kind != TokenKind::Space);
+        while let Some(token) = iter.next() {
+            if token.kind != TokenKind::Ident || token.text != "defined" {
+                out.push(token.clone());
+                out.push(Token::space());
+                continue;
+            }
+            let mut next = iter.next();
+            let parenthesized = next.is_some_and(|t| t.is_punct("("));
+            if parenthesized {
+                next = iter.next();
+            }
+            let name = match next {
+                Some(t) if t.kind == TokenKind::Ident => t.text.as_str(),
+                _ => return Err("expected a macro name after `defined`".into()),
+            };
+            if parenthesized && !iter.next().is_some_and(|t| t.is_punct(")")) {
+                return Err("expected `)` after `defined(`".into());
+            }
+            let value = if self.is_defined(name) { "1" } else { "0" };
+            out.push(Token::new(TokenKind::Number, value));
+            out.push(Token::space());
+        }
+        Ok(out)
+    }
+
+    fn define_directive(
+        &mut self,
+        sources: &SourceMap,
+        file: &FileState,
+        line: &LogicalLine,
+        rest: &str,
+    ) {
+        let tokens = tokenize(rest);
+        let Some(name) = tokens.first().filter(|t| t.kind == TokenKind::Ident) else {
+            return self.error(
+                sources,
+                file.id,
+                line.line,
+                "expected a macro name after `#define`",
+            );
+        };
+        let name = name.text.clone();
+        if is_reserved(&name) {
+            let message = format!("cannot define reserved macro `{}`", name);
+            return self.error(sources, file.id, line.line, message);
+        }
+
+        // A `(` directly after the name starts a parameter list
+        let mut body_start = 1;
+        let mut params = None;
+        if tokens.get(1).is_some_and(|t| t.is_punct("(")) {
+            let close = tokens.iter().position(|t| t.is_punct(")"));
+            let Some(close) = close else {
+                return self.error(
+                    sources,
+                    file.id,
+                    line.line,
+                    "expected `)` in macro parameter list",
+                );
+            };
+            let mut names = Vec::new();
+            for param in join(&tokens[2..close]).split(',') {
+                let param = param.trim();
+                let valid = param.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && param.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if param.is_empty() && names.is_empty() && close == 2 {
+                    break;
+                }
+                if !valid || names.iter().any(|n| n == param) {
+                    let message = format!("invalid macro parameter `{}`", param);
+                    return self.error(sources, file.id, line.line, message);
+                }
+                names.push(param.to_string());
+            }
+            params = Some(names);
+            body_start = close + 1;
+        }
+
+        let definition = Macro {
+            params,
+            body: normalize_body(trim(&tokens[body_start..])),
+            defined_at: Some((file.id, line.line)),
+        };
+        if let Some(previous) = self.macros.get(&name) {
+            if !previous.same_definition(&definition) {
+                let mut diagnostic = Diagnostic::error(format!("macro `{}` redefined", name));
+                if let Some(span) = sources.line_span(file.id, line.line) {
+                    diagnostic = diagnostic.with_primary(span, "redefined here");
+                }
+                if let Some(span) = previous
+                    .defined_at
+                    .and_then(|(id, line)| sources.line_span(id, line))
+                {
+                    diagnostic = diagnostic.with_secondary(span, "previous definition here");
+                }
+                self.errors.push(diagnostic);
+                return;
+            }
+        }
+        self.macros.insert(name, definition);
+    }
+
+    fn include(
+        &mut self,
+        sources: &mut SourceMap,
+        file: &FileState,
+        line: &LogicalLine,
+        rest: &str,
+    ) {
+        let path = match rest.chars().next() {
+            Some('"') => rest[1..]
+                .split('"')
+                .next()
+                .filter(|_| rest[1..].contains('"')),
+            Some('<') => rest[1..].split('>').next().filter(|_| rest.contains('>')),
+            _ => None,
+        };
+        let Some(path) = path.filter(|p| !p.is_empty()) else {
+            return self.error(
+                sources,
+                file.id,
+                line.line,
+                "expected \"file\" or <file> after `#include`",
+            );
+        };
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            let message = format!("`#include` nested more than {} deep", MAX_INCLUDE_DEPTH);
+            return self.error(sources, file.id, line.line, message);
+        }
+
+        let from = sources.get(file.id).name.clone();
+        match self.resolver.resolve(path, &from) {
+            Ok(resolved) => {
+                if self.once.contains(&resolved.name) {
+                    return;
+                }
+                let id = sources.add(resolved.name, resolved.text);
+                self.depth += 1;
+                self.process(sources, id);
+                self.depth -= 1;
+            }
+            Err(reason) => {
+                let mut diagnostic =
+                    Diagnostic::error(format!("cannot include `{}`: {}", path, reason));
+                if let Some(span) = sources.line_span(file.id, line.line) {
+                    diagnostic = diagnostic.with_primary(span, "");
+                }
+                self.errors.push(diagnostic);
+            }
+        }
+    }
+
+    /// `#line N [file]`: renumber the following lines for `__LINE__` and `__FILE__`
+    ///
+    /// The line map keeps pointing at physical lines, so diagnostics still
+    /// show the real source.
+    fn line_directive(
+        &mut self,
+        sources: &SourceMap,
+        file: &mut FileState,
+        line: &LogicalLine,
+        rest: &str,
+    ) {
+        let expanded = self.expander(file, line.line).expand(tokenize(rest));
+        let numbers: Option<Vec<i64>> = match expanded {
+            Ok(tokens) => trim(&tokens)
+                .iter()
+                .filter(|t| t.kind != TokenKind::Space)
+                .map(|t| t.text.parse().ok())
+                .collect(),
+            Err(_) => None,
+        };
+        match numbers.as_deref() {
+            Some([number]) => file.line_offset = number - (line.line as i64 + 1),
+            Some([number, file_number]) if *file_number >= 0 => {
+                file.line_offset = number - (line.line as i64 + 1);
+                file.file_number = *file_number as u32;
+            }
+            _ => self.error(
+                sources,
+                file.id,
+                line.line,
+                "expected a line number after `#line`",
+            ),
+        }
+    }
+}
+
+/// Collapse whitespace in a replacement list so equal definitions compare equal
+fn normalize_body(tokens: &[Token]) -> Vec<Token> {
+    tokens
+        .iter()
+        .map(|t| match t.kind {
+            TokenKind::Space => Token::space(),
+            _ => t.clone(),
+        })
+        .collect()
+}
+
+/// Names a shader may not define or undefine
+fn is_reserved(name: &str) -> bool {
+    name.starts_with("GL_") || matches!(name, "defined" | "__LINE__" | "__FILE__" | "__VERSION__")
+}
@@ -1,14 +1,23 @@
-use std::io::Write;
 use core::num::NonZeroI32;
-use embive::transpiler::transpile_elf;
-use embive::interpreter::memory::{SliceMemory, RAM_OFFSET};
-use embive::interpreter::{Error, Interpreter, State, SYSCALL_ARGS};
+use std::io::Write;
+
+use embive::{
+    interpreter::{
+        memory::{SliceMemory, RAM_OFFSET},
+        Error, Interpreter, State, SYSCALL_ARGS,
+    },
+    transpiler::transpile_elf,
+};
 
 /// RISC-V VM for running embive programs
 pub struct R5Vm {
     code_vec: Vec<u8>,
     ram: Vec<u8>,
     last_result: Option<i32>,
+    /// Words the guest reads with syscall 3
+    input: Vec<u32>,
+    /// Words the guest wrote with syscall 4
+    output: Vec<u32>,
 }
 
 impl R5Vm {
@@ -18,6 +27,8 @@ impl R5Vm {
             code_vec: Vec::new(),
             ram: vec![0u8; ram_size],
             last_result: None,
+            input: Vec::new(),
+            output: Vec::new(),
         }
     }
 
@@ -43,10 +54,11 @@ impl R5Vm {
         if binary_size > code_size {
             let ram_offset_in_combined = code_size;
             let ram_size = (binary_size - ram_offset_in_combined).min(self.ram.len());
-            self.ram[..ram_size]
-                .copy_from_slice(&combined[ram_offset_in_combined..ram_offset_in_combined + ram_size]);
+            self.ram[..ram_size].copy_from_slice(
+                &combined[ram_offset_in_combined..ram_offset_in_combined + ram_size],
+            );
         }
-        
+
         // Ensure the heap region is zero-initialized
         // The .heap section is (NOLOAD) so it won't be in the binary, but we need
         // to ensure the RAM buffer covers it. The RAM is already zero-initialized
@@ -61,18 +73,23 @@ impl R5Vm {
     pub fn run(&mut self) -> Result<(), String> {
         // Capture what we need for syscall handling before creating mutable borrows
         let last_result = &mut self.last_result;
+        let input = &self.input;
+        let output = &mut self.output;
         let code_vec_ptr = self.code_vec.as_ptr();
         let code_vec_len = self.code_vec.len();
         let ram_ptr = self.ram.as_ptr();
         let ram_len = self.ram.len();
-        
+
         // Create memory and interpreter
         let mut memory = SliceMemory::new(&self.code_vec, &mut self.ram);
         let mut interpreter = Interpreter::new(&mut memory, 0);
         interpreter.program_counter = 0;
-        
+
         // Syscall handler - inline the logic from handle_syscall
-        let mut syscall = |nr: i32, args: &[i32; SYSCALL_ARGS], _memory: &mut _| -> Result<Result<i32, NonZeroI32>, Error> {
+        let mut syscall = |nr: i32,
+                           args: &[i32; SYSCALL_ARGS],
+                           _memory: &mut _|
+         -> Result<Result<i32, NonZeroI32>, Error> {
             match nr {
                 0 => {
                     // Syscall 0: Done - store result
@@ -102,7 +119,11 @@ impl R5Vm {
                             if offset + len > ram_len {
                                 return Err(Error::Custom("Address out of bounds in RAM section"));
                             }
-                            core::ptr::copy_nonoverlapping(ram_ptr.add(offset), buf.as_mut_ptr(), len);
+                            core::ptr::copy_nonoverlapping(
+                                ram_ptr.add(offset),
+                                buf.as_mut_ptr(),
+                                len,
+                            );
                         }
                     }
 
@@ -121,6 +142,18 @@ impl R5Vm {
 
                     Ok(Ok(0))
                 }
+                3 => {
+                    // Syscall 3: Read word args[0] of the input
+                    let word = input
+                        .get(args[0] as usize)
+                        .ok_or(Error::Custom("Input word out of range"))?;
+                    Ok(Ok(*word as i32))
+                }
+                4 => {
+                    // Syscall 4: Write args[0] to the output
+                    output.push(args[0] as u32);
+                    Ok(Ok(0))
+                }
                 1000 => {
                     // Syscall 1000: Add two numbers
                     Ok(Ok(args[0] + args[1]))
@@ -131,7 +164,10 @@ impl R5Vm {
 
         // Run the program (exactly like embive examples)
         loop {
-            match interpreter.run().map_err(|e| format!("Interpreter error: {:?}", e))? {
+            match interpreter
+                .run()
+                .map_err(|e| format!("Interpreter error: {:?}", e))?
+            {
                 State::Running => {}
                 State::Called => {
                     interpreter
@@ -151,17 +187,25 @@ impl R5Vm {
         self.last_result
     }
 
+    /// Set the words the guest reads with syscall 3
+    pub fn set_input(&mut self, words: Vec<u32>) {
+        self.input = words;
+    }
+
+    /// Get the words the guest wrote with syscall 4
+    pub fn output(&self) -> &[u32] {
+        &self.output
+    }
+
     /// Handle a syscall from the guest program
     ///
     /// Supported syscalls:
     /// - 0: Done - stores args[0] in last_result
     /// - 2: Write - reads string from memory at args[0] with length args[1] and prints it
+    /// - 3: Read input - returns word args[0] of the input set with `set_input`
+    /// - 4: Write output - appends args[0] to the words returned by `output`
     /// - 1000: Add - returns args[0] + args[1]
-    pub fn handle_syscall(
-        &mut self,
-        nr: i32,
-        args: &[i32; SYSCALL_ARGS],
-    ) -> Result<i32, Error> {
+    pub fn handle_syscall(&mut self, nr: i32, args: &[i32; SYSCALL_ARGS]) -> Result<i32, Error> {
         match nr {
             0 => {
                 // Syscall 0: Done - store result
@@ -193,6 +237,19 @@ impl R5Vm {
 
                 Ok(0)
             }
+            3 => {
+                // Syscall 3: Read word args[0] of the input
+                let word = self
+                    .input
+                    .get(args[0] as usize)
+                    .ok_or(Error::Custom("Input word out of range"))?;
+                Ok(*word as i32)
+            }
+            4 => {
+                // Syscall 4: Write args[0] to the output
+                self.output.push(args[0] as u32);
+                Ok(0)
+            }
             1000 => {
                 // Syscall 1000: Add two numbers
                 Ok(args[0] + args[1])
@@ -242,12 +299,12 @@ impl R5Vm {
         if addr < RAM_OFFSET {
             return Err(Error::Custom("Cannot write to ROM section"));
         }
-        
+
         let offset = (addr - RAM_OFFSET) as usize;
         if offset + data.len() > self.ram.len() {
             return Err(Error::Custom("Address out of bounds in RAM section"));
         }
-        
+
         unsafe {
             core::ptr::copy_nonoverlapping(
                 data.as_ptr(),
@@ -255,7 +312,7 @@ impl R5Vm {
                 data.len(),
             );
         }
-        
+
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,144 @@
+//! Reference rendering of shaders on the host
+//!
+//! [`Reference`] runs the IR of a compiled shader with the
+//! [`Interpreter`] one pixel at a time, giving the colour a correct
+//! compilation for a guest must produce for each pixel. The IR may be
+//! [lowered](crate::numeric::lower) to another numeric format first, to
+//! render what a guest computing in that format produces.
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    ir::{
+        self,
+        interpret::{Exit, Interpreter, Trap},
+    },
+    numeric::{self, Numeric, Precisions},
+    reflect::{Reflection, UniformError},
+    sema::{
+        const_eval::Value,
+        hir::{self, Storage, MAIN},
+    },
+};
+
+/// Name of the builtin output of the classic entry point
+const FRAG_COLOR: &str = "gl_FragColor";
+
+/// What a shader produced for one pixel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fragment {
+    Color([f32; 4]),
+    Discarded,
+}
+
+pub struct Reference {
+    module: ir::Module,
+    reflection: Reflection,
+    /// Contents of the uniform area
+    uniforms: Vec<u8>,
+    /// Global the colour is read from
+    output: String,
+    /// Format of floats in the IR
+    numeric: Numeric,
+}
+
+impl Reference {
+    /// Reference for a compiled module, with every uniform at its default
+    ///
+    /// The colour is read from the first `out` variable, or from
+    /// `gl_FragColor` if the shader declares none.
+    pub fn new(module: &hir::Module) -> Reference {
+        Reference::with_numeric(module, Numeric::Float)
+    }
+
+    /// Reference computing floats in `numeric`, or in the format of each
+    /// precision
+    pub fn with_numeric(module: &hir::Module, numeric: impl Into<Precisions>) -> Reference {
+        let precisions = numeric.into();
+        // Memory and the host only see the `highp` format
+        let numeric = precisions.high;
+        let reflection = Reflection::of(module).with_numeric(numeric);
+        let output = module
+            .globals
+            .iter()
+            .find(|g| g.storage == Storage::Output)
+            .map_or(FRAG_COLOR.into(), |g| g.name.clone());
+        let mut ir = ir::translate(module);
+        numeric::lower(&mut ir, precisions);
+        Reference {
+            module: ir,
+            uniforms: reflection.defaults(),
+            reflection,
+            output,
+            numeric,
+        }
+    }
+
+    /// The IR the reference runs
+    pub fn module(&self) -> &ir::Module {
+        &self.module
+    }
+
+    /// The IR the reference runs, for instance to
+    /// [optimise](crate::opt::PassManager) it before checking that the
+    /// colours are unchanged
+    pub fn module_mut(&mut self) -> &mut ir::Module {
+        &mut self.module
+    }
+
+    pub fn set_uniform(&mut self, name: &str, value: &Value) -> Result<(), UniformError> {
+        let (offset, bytes) = self.reflection.encode(name, value)?;
+        let start = offset as usize;
+        self.uniforms[start..start + bytes.len()].copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    /// Run `main` for the pixel at `frag_coord`, usually the centre of a
+    /// pixel such as `(0.5, 0.5)`
+    ///
+    /// A shader without `main` traps with [`Trap::Undefined`].
+    pub fn shade(&self, frag_coord: [f32; 2]) -> Result<Fragment, Trap> {
+        let mut interpreter = Interpreter::new(&self.module);
+        if let Some(addr) = interpreter.global_address(ir::UNIFORMS) {
+            interpreter.write(addr, &self.uniforms)?;
+        }
+        if let Some(addr) = interpreter.global_address("gl_FragCoord") {
+            let coord = [frag_coord[0], frag_coord[1], 0.0, 1.0];
+            let bytes: Vec<u8> = coord
+                .iter()
+                .flat_map(|&c| self.numeric.encode(c).to_le_bytes())
+                .collect();
+            interpreter.write(addr, &bytes)?;
+        }
+
+        let main = self
+            .module
+            .find_function(MAIN)
+            .ok_or_else(|| Trap::Undefined(MAIN.into()))?;
+        if interpreter.call(main, &[])? == Exit::Discard {
+            return Ok(Fragment::Discarded);
+        }
+
+        let mut color = [0.0, 0.0, 0.0, 1.0];
+        if let Some(addr) = interpreter.global_address(&self.output) {
+            let global = self.module.find_global(&self.output).unwrap();
+            let size = self.module.global(global).size.min(16);
+            let bytes = interpreter.read(addr, size)?;
+            for (c, word) in color.iter_mut().zip(bytes.chunks(4)) {
+                *c = self
+                    .numeric
+                    .decode(u32::from_le_bytes(word.try_into().unwrap()));
+            }
+        }
+        Ok(Fragment::Color(color))
+    }
+
+    /// Shade every pixel of a `width` by `height` image at its centre, row
+    /// by row from `y = 0`
+    pub fn render(&self, width: u32, height: u32) -> Result<Vec<Fragment>, Trap> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| [x as f32 + 0.5, y as f32 + 0.5]))
+            .map(|coord| self.shade(coord))
+            .collect()
+    }
+}
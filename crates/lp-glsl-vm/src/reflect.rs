@@ -0,0 +1,239 @@
+//! Reflection of the uniforms of a compiled shader
+//!
+//! Uniforms are stored together in one uniform area, in declaration order,
+//! each at the next offset aligned for its type as described in
+//! [`layout`](crate::layout). [`Reflection`] lists them with their offsets
+//! within that area, so the host can validate parameter values and encode
+//! them into the little-endian bytes the guest expects, with floats in the
+//! [`Numeric`] format the code was lowered to.
+
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+
+use crate::{
+    layout::{align_to, array_stride, Layout, StructLayout},
+    numeric::Numeric,
+    sema::{
+        const_eval::{ConstEval, Value},
+        hir::{GlobalId, Literal, Module, Storage},
+        ScalarType, Type,
+    },
+};
+
+/// Uniforms of a module and the layout of its uniform area
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reflection {
+    /// Uniforms in declaration order
+    pub uniforms: Vec<Uniform>,
+    /// Size of the uniform area in bytes
+    pub size: u32,
+    /// Alignment of the uniform area in bytes
+    pub align: u32,
+    /// Format floats are encoded in
+    pub numeric: Numeric,
+}
+
+/// A `uniform` declaration
+#[derive(Clone, Debug, PartialEq)]
+pub struct Uniform {
+    pub name: String,
+    pub global: GlobalId,
+    pub ty: Type,
+    /// Value of the initializer, if the declaration has one
+    pub default: Option<Value>,
+    /// Byte offset from the start of the uniform area
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// A uniform value that cannot be stored
+#[derive(Clone, Debug, PartialEq)]
+pub enum UniformError {
+    /// No uniform with this name
+    Unknown(String),
+    /// The value does not have the uniform's type
+    TypeMismatch { name: String, expected: Type },
+}
+
+impl fmt::Display for UniformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UniformError::Unknown(name) => write!(f, "no uniform named `{}`", name),
+            UniformError::TypeMismatch { name, expected } => {
+                write!(
+                    f,
+                    "uniform `{}` expects a value of type `{}`",
+                    name, expected
+                )
+            }
+        }
+    }
+}
+
+impl Reflection {
+    /// Uniforms of `module`, encoding floats as `f32`
+    ///
+    /// For a shader compiled to another format, use
+    /// [`Compiled::reflection`](crate::compiler::Compiled::reflection).
+    pub fn of(module: &Module) -> Reflection {
+        let mut size = 0;
+        let mut align = 1;
+        let eval = ConstEval::new(module);
+        let uniforms = module
+            .globals
+            .iter()
+            .enumerate()
+            .filter(|(_, global)| global.storage == Storage::Uniform)
+            .map(|(i, global)| {
+                let layout = Layout::of(&global.ty);
+                let offset = align_to(size, layout.align);
+                size = offset + layout.size;
+                align = align.max(layout.align);
+                Uniform {
+                    name: global.name.clone(),
+                    global: GlobalId(i as u32),
+                    ty: global.ty.clone(),
+                    // Uniform initializers are folded during analysis
+                    default: global.init.as_ref().map(|init| {
+                        eval.eval(init)
+                            .expect("uniform initializer is not constant")
+                    }),
+                    offset,
+                    size: layout.size,
+                }
+            })
+            .collect();
+        Reflection {
+            uniforms,
+            size: align_to(size, align),
+            align,
+            numeric: Numeric::Float,
+        }
+    }
+
+    /// The same reflection, encoding floats in `numeric`
+    pub fn with_numeric(self, numeric: Numeric) -> Reflection {
+        Reflection { numeric, ..self }
+    }
+
+    pub fn uniform(&self, name: &str) -> Option<&Uniform> {
+        self.uniforms.iter().find(|u| u.name == name)
+    }
+
+    /// Contents of the uniform area holding every default value, with
+    /// uniforms that have no initializer zeroed
+    pub fn defaults(&self) -> Vec<u8> {
+        let mut area = vec![0; self.size as usize];
+        for uniform in &self.uniforms {
+            if let Some(default) = &uniform.default {
+                let start = uniform.offset as usize;
+                encode(
+                    &uniform.ty,
+                    default,
+                    self.numeric,
+                    &mut area[start..start + uniform.size as usize],
+                );
+            }
+        }
+        area
+    }
+
+    /// Offset and encoding of `value` for the uniform called `name`
+    pub fn encode(&self, name: &str, value: &Value) -> Result<(u32, Vec<u8>), UniformError> {
+        let uniform = self
+            .uniform(name)
+            .ok_or_else(|| UniformError::Unknown(name.into()))?;
+        Ok((uniform.offset, uniform.encode_in(value, self.numeric)?))
+    }
+}
+
+impl Uniform {
+    /// Element count of an array uniform
+    pub fn array_len(&self) -> Option<u32> {
+        match self.ty {
+            Type::Array(_, n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Encode `value` as stored in the uniform area with floats as `f32`,
+    /// checking its type
+    ///
+    /// Values are not converted: a `float` uniform rejects an `int`.
+    pub fn encode(&self, value: &Value) -> Result<Vec<u8>, UniformError> {
+        self.encode_in(value, Numeric::Float)
+    }
+
+    /// Encode `value` with floats in `numeric`, checking its type
+    pub fn encode_in(&self, value: &Value, numeric: Numeric) -> Result<Vec<u8>, UniformError> {
+        if !has_type(value, &self.ty) {
+            return Err(UniformError::TypeMismatch {
+                name: self.name.clone(),
+                expected: self.ty.clone(),
+            });
+        }
+        let mut bytes = vec![0; self.size as usize];
+        encode(&self.ty, value, numeric, &mut bytes);
+        Ok(bytes)
+    }
+}
+
+/// Whether `value` has the shape and component types of `ty`
+fn has_type(value: &Value, ty: &Type) -> bool {
+    match (value, ty) {
+        (Value::Components(c), _) => {
+            ty.component_count() == Some(c.len() as u32)
+                && c.iter().all(|l| ty.scalar_type() == Some(scalar_type(l)))
+        }
+        (Value::Aggregate(fields), Type::Struct(st)) => {
+            fields.len() == st.fields.len()
+                && fields
+                    .iter()
+                    .zip(&st.fields)
+                    .all(|(v, f)| has_type(v, &f.ty))
+        }
+        (Value::Aggregate(elements), Type::Array(element, n)) => {
+            elements.len() == *n as usize && elements.iter().all(|v| has_type(v, element))
+        }
+        _ => false,
+    }
+}
+
+fn scalar_type(literal: &Literal) -> ScalarType {
+    match literal {
+        Literal::Bool(_) => ScalarType::Bool,
+        Literal::Int(_) => ScalarType::Int,
+        Literal::UInt(_) => ScalarType::UInt,
+        Literal::Float(_) => ScalarType::Float,
+    }
+}
+
+/// Write `value`, which has type `ty`, into `out`
+fn encode(ty: &Type, value: &Value, numeric: Numeric, out: &mut [u8]) {
+    match (value, ty) {
+        (Value::Components(c), _) => {
+            for (word, literal) in out.chunks_exact_mut(4).zip(c) {
+                let bits = match *literal {
+                    Literal::Bool(b) => b as u32,
+                    Literal::Int(v) => v as u32,
+                    Literal::UInt(v) => v,
+                    Literal::Float(v) => numeric.encode(v),
+                };
+                word.copy_from_slice(&bits.to_le_bytes());
+            }
+        }
+        (Value::Aggregate(fields), Type::Struct(st)) => {
+            let layout = StructLayout::of(st);
+            for ((field, value), offset) in st.fields.iter().zip(fields).zip(layout.offsets) {
+                encode(&field.ty, value, numeric, &mut out[offset as usize..]);
+            }
+        }
+        (Value::Aggregate(elements), Type::Array(element, _)) => {
+            let stride = array_stride(element) as usize;
+            for (i, value) in elements.iter().enumerate() {
+                encode(element, value, numeric, &mut out[i * stride..]);
+            }
+        }
+        _ => unreachable!("value of the wrong shape for `{}`", ty),
+    }
+}
@@ -0,0 +1,358 @@
+//! Fixed-point routines for float builtins
+//!
+//! Code lowered to a [`Fixed`] format calls these for the builtins that
+//! take more than a few integer instructions, passing and returning values
+//! in that format. Each routine is named by its [`symbol`], such as
+//! `lp.q16.16.sin`. They compute in integers with 32 fractional bits and
+//! round the result to nearest, saturating. The noise functions convert to
+//! `f32` and back, so that fixed-point shaders draw the same patterns.
+//! Code mixing formats at several precisions converts values from and to
+//! the bits of an `f32` with routines such as `lp.q8.8.from_f32`.
+
+use alloc::{format, string::String, vec::Vec};
+
+use super::{noise, routine_name};
+use crate::{
+    numeric::{Fixed, Overflow},
+    sema::builtins::Builtin,
+};
+
+/// Intermediate values, integers scaled by 2^32
+type Q = i128;
+
+const ONE: Q = 1 << 32;
+const HALF: Q = ONE / 2;
+const PI: Q = 13_493_037_705;
+const FRAC_PI_2: Q = 6_746_518_852;
+const FRAC_PI_6: Q = 2_248_839_617;
+/// π/2 scaled by 2^64, for range reduction of large arguments
+const FRAC_PI_2_WIDE: Q = 28_976_077_832_308_491_370;
+const FRAC_1_SQRT_3: Q = 2_479_700_525;
+const TAN_FRAC_PI_12: Q = 1_150_833_018;
+const LN_2: Q = 2_977_044_472;
+const LOG2_E: Q = 6_196_328_019;
+/// Far beyond the range of every format, standing for infinity
+const HUGE: Q = 1 << 80;
+
+/// Builtins computed by a routine rather than inline instructions
+pub const BUILTINS: &[Builtin] = &[
+    Builtin::Sin,
+    Builtin::Cos,
+    Builtin::Tan,
+    Builtin::Asin,
+    Builtin::Acos,
+    Builtin::Atan,
+    Builtin::Sinh,
+    Builtin::Cosh,
+    Builtin::Tanh,
+    Builtin::Asinh,
+    Builtin::Acosh,
+    Builtin::Atanh,
+    Builtin::Pow,
+    Builtin::Exp,
+    Builtin::Log,
+    Builtin::Exp2,
+    Builtin::Log2,
+    Builtin::Sqrt,
+    Builtin::InverseSqrt,
+    Builtin::Round,
+    Builtin::RoundEven,
+    Builtin::Smoothstep,
+    Builtin::LpNoise2,
+    Builtin::LpNoise3,
+    Builtin::LpFbm,
+];
+
+/// Name of the routine computing `builtin` of `arity` scalar arguments in
+/// `format`, ending in its [`routine_name`]
+pub fn symbol(format: Fixed, builtin: Builtin, arity: usize) -> String {
+    format!("lp.{}.{}", format, routine_name(builtin, arity))
+}
+
+/// Name of the routine converting the bits of an `f32` to `format`
+pub fn from_f32_symbol(format: Fixed) -> String {
+    format!("lp.{}.from_f32", format)
+}
+
+/// Name of the routine converting a value in `format` to the bits of an
+/// `f32`
+pub fn to_f32_symbol(format: Fixed) -> String {
+    format!("lp.{}.to_f32", format)
+}
+
+/// Run the routine called `name` on the bits of its arguments, or `None`
+/// if no routine has that name
+pub fn call_symbol(name: &str, args: &[u32]) -> Option<u32> {
+    let mut parts = name.strip_prefix("lp.q")?.splitn(3, '.');
+    let int_bits: u8 = parts.next()?.parse().ok()?;
+    let frac_bits: u8 = parts.next()?.parse().ok()?;
+    let name = parts.next()?;
+    let bits = int_bits as u32 + frac_bits as u32;
+    if int_bits == 0 || frac_bits == 0 || !(bits == 32 || bits <= 16) {
+        return None;
+    }
+    let format = Fixed::new(int_bits, frac_bits, Overflow::Saturate);
+    let builtin = match name {
+        "from_f32" => return Some(format.from_f32(f32::from_bits(args[0])) as u32),
+        "to_f32" => return Some(format.to_f32(args[0] as i32).to_bits()),
+        "atan2" => Builtin::Atan,
+        "lp_fbm2" | "lp_fbm3" => Builtin::LpFbm,
+        name => Builtin::from_name(name)?,
+    };
+    let args: Vec<i32> = args.iter().map(|&a| a as i32).collect();
+    call(format, builtin, &args).map(|r| r as u32)
+}
+
+/// Apply `builtin` to arguments in `format`, or `None` if it has no routine
+///
+/// The last argument of `lp_fbm`, the octave count, is an integer.
+pub fn call(format: Fixed, builtin: Builtin, args: &[i32]) -> Option<i32> {
+    use Builtin as B;
+
+    if matches!(builtin, B::LpNoise2 | B::LpNoise3 | B::LpFbm) {
+        let x = |i: usize| format.to_f32(args[i]);
+        let result = match (builtin, args.len()) {
+            (B::LpNoise2, _) => noise::noise2(x(0), x(1)),
+            (B::LpNoise3, _) => noise::noise3(x(0), x(1), x(2)),
+            (_, 3) => noise::fbm2(x(0), x(1), args[2]),
+            (_, 4) => noise::fbm3(x(0), x(1), x(2), args[3]),
+            _ => return None,
+        };
+        return Some(format.from_f32(result));
+    }
+
+    let x = |i: usize| (args[i] as Q) << (32 - format.frac_bits);
+    let result = match builtin {
+        B::Sin => sin_cos(x(0)).0,
+        B::Cos => sin_cos(x(0)).1,
+        B::Tan => {
+            let (s, c) = sin_cos(x(0));
+            quotient(s, c)
+        }
+        B::Asin => {
+            let x = x(0).clamp(-ONE, ONE);
+            atan2(x, sqrt(ONE - mul(x, x)))
+        }
+        B::Acos => {
+            let x = x(0).clamp(-ONE, ONE);
+            atan2(sqrt(ONE - mul(x, x)), x)
+        }
+        B::Atan if args.len() == 1 => atan2(x(0), ONE),
+        B::Atan => atan2(x(0), x(1)),
+        B::Sinh => (exp(x(0)) - exp(-x(0))) / 2,
+        B::Cosh => (exp(x(0)) + exp(-x(0))) / 2,
+        B::Tanh => {
+            let e = exp(2 * x(0).clamp(-32 * ONE, 32 * ONE));
+            quotient(e - ONE, e + ONE)
+        }
+        B::Asinh => {
+            let a = x(0).abs();
+            x(0).signum() * log(a + sqrt(mul(a, a) + ONE))
+        }
+        B::Acosh => {
+            let x = x(0).max(ONE);
+            log(x + sqrt(mul(x, x) - ONE))
+        }
+        B::Atanh => {
+            let x = x(0);
+            if x.abs() >= ONE {
+                x.signum() * HUGE
+            } else {
+                log(quotient(ONE + x, ONE - x)) / 2
+            }
+        }
+        B::Pow => {
+            let (x, y) = (x(0), x(1));
+            match (x > 0, y.signum()) {
+                (_, 0) => ONE,
+                (true, _) => exp2(mul(y, log2(x))),
+                (false, 1) => 0,
+                (false, _) => HUGE,
+            }
+        }
+        B::Exp => exp(x(0)),
+        B::Log => log(x(0)),
+        B::Exp2 => exp2(x(0)),
+        B::Log2 => log2(x(0)),
+        B::Sqrt => sqrt(x(0)),
+        B::InverseSqrt => quotient(ONE, sqrt(x(0))),
+        B::Round => {
+            let x = x(0);
+            x.signum() * ((x.abs() + HALF) & !(ONE - 1))
+        }
+        B::RoundEven => {
+            let x = x(0);
+            let floor = x & !(ONE - 1);
+            let odd = (floor >> 32) & 1 == 1;
+            match x - floor {
+                d if d > HALF || (d == HALF && odd) => floor + ONE,
+                _ => floor,
+            }
+        }
+        B::Smoothstep => {
+            let (edge0, edge1, x) = (x(0), x(1), x(2));
+            if edge0 == edge1 {
+                if x < edge0 {
+                    0
+                } else {
+                    ONE
+                }
+            } else {
+                let t = quotient(x - edge0, edge1 - edge0).clamp(0, ONE);
+                mul(mul(t, t), 3 * ONE - 2 * t)
+            }
+        }
+        _ => return None,
+    };
+    Some(output(format, result))
+}
+
+/// Round to nearest in `format`, saturating
+fn output(format: Fixed, x: Q) -> i32 {
+    let shift = 32 - format.frac_bits;
+    let rounded = (x.clamp(-HUGE, HUGE) + (1 << (shift - 1))) >> shift;
+    rounded.clamp(format.min() as Q, format.max() as Q) as i32
+}
+
+fn mul(a: Q, b: Q) -> Q {
+    (a * b) >> 32
+}
+
+/// `a / b`, infinite for a zero divisor
+fn quotient(a: Q, b: Q) -> Q {
+    match b {
+        0 if a == 0 => 0,
+        0 => a.signum() * HUGE,
+        _ => (a.clamp(-HUGE, HUGE) << 32) / b,
+    }
+}
+
+/// Sine and cosine, from series on the nearest multiple of π/2
+fn sin_cos(x: Q) -> (Q, Q) {
+    let n = (x + FRAC_PI_2 / 2).div_euclid(FRAC_PI_2);
+    let r = ((x << 32) - n * FRAC_PI_2_WIDE) >> 32;
+    let r2 = mul(r, r);
+    let mut s = ONE;
+    for k in [110, 72, 42, 20, 6] {
+        s = ONE - mul(r2, s) / k;
+    }
+    let s = mul(r, s);
+    let mut c = ONE;
+    for k in [132, 90, 56, 30, 12, 2] {
+        c = ONE - mul(r2, c) / k;
+    }
+    match n.rem_euclid(4) {
+        0 => (s, c),
+        1 => (c, -s),
+        2 => (-s, -c),
+        _ => (-c, s),
+    }
+}
+
+/// Arc tangent of `0 <= t <= 1`, from its series near 0 after moving `t`
+/// there with `atan(t) = π/6 + atan((t - 1/√3) / (1 + t/√3))`
+fn atan_unit(t: Q) -> Q {
+    let (t, offset) = if t > TAN_FRAC_PI_12 {
+        let t = quotient(t - FRAC_1_SQRT_3, ONE + mul(t, FRAC_1_SQRT_3));
+        (t, FRAC_PI_6)
+    } else {
+        (t, 0)
+    };
+    let t2 = mul(t, t);
+    let mut p = ONE / 11;
+    for k in [9, 7, 5, 3, 1] {
+        p = ONE / k - mul(t2, p);
+    }
+    offset + mul(t, p)
+}
+
+fn atan2(y: Q, x: Q) -> Q {
+    if x == 0 && y == 0 {
+        return 0;
+    }
+    let (ax, ay) = (x.abs(), y.abs());
+    let mut a = if ay <= ax {
+        atan_unit(quotient(ay, ax))
+    } else {
+        FRAC_PI_2 - atan_unit(quotient(ax, ay))
+    };
+    if x < 0 {
+        a = PI - a;
+    }
+    if y < 0 {
+        a = -a;
+    }
+    a
+}
+
+fn sqrt(x: Q) -> Q {
+    if x <= 0 {
+        return 0;
+    }
+    // Integer square root of x·2^32, a bit at a time
+    let n = (x as u128) << 32;
+    let mut root: u128 = 0;
+    let mut bit: u128 = 1 << ((127 - n.leading_zeros()) & !1);
+    let mut rest = n;
+    while bit != 0 {
+        if rest >= root + bit {
+            rest -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root as Q
+}
+
+/// `2^x`, from the exponential series of the fractional part
+fn exp2(x: Q) -> Q {
+    let n = x >> 32;
+    if n > 40 {
+        return HUGE;
+    }
+    if n < -40 {
+        return 0;
+    }
+    let y = mul(x & (ONE - 1), LN_2);
+    let mut t = ONE;
+    for k in (1..=12).rev() {
+        t = ONE + mul(y, t) / k;
+    }
+    if n >= 0 {
+        t << n
+    } else {
+        t >> -n
+    }
+}
+
+/// `log2(x)`, the fraction found a bit at a time by squaring the mantissa
+fn log2(x: Q) -> Q {
+    if x <= 0 {
+        return -HUGE;
+    }
+    let msb = 127 - x.leading_zeros() as i32;
+    let mut m = if msb >= 32 {
+        x >> (msb - 32)
+    } else {
+        x << (32 - msb)
+    };
+    let mut fraction = 0;
+    for bit in (0..32).rev() {
+        m = mul(m, m);
+        if m >= 2 * ONE {
+            m >>= 1;
+            fraction |= 1 << bit;
+        }
+    }
+    (((msb - 32) as Q) << 32) + fraction
+}
+
+fn exp(x: Q) -> Q {
+    exp2(mul(x, LOG2_E))
+}
+
+fn log(x: Q) -> Q {
+    mul(log2(x), LN_2)
+}
@@ -0,0 +1,591 @@
+//! Floating point builtins for targets without an FPU
+//!
+//! Every routine is written with plain `f32` arithmetic and bit operations
+//! only, so on `riscv32imc`/`riscv32imac` it compiles down to the integer
+//! soft-float primitives and on the host it gives bit-identical results.
+//! Transcendental functions reduce their argument to a small interval and
+//! evaluate a polynomial there.
+//!
+//! Accuracy bounds are stated per function and are checked against `f64`
+//! references by the test suite. They are at least as tight as the bounds
+//! the GLSL ES 3.0 specification requires. Results for arguments where GLSL
+//! leaves a function undefined, such as `log(-1.0)`, are NaN unless stated
+//! otherwise.
+
+use core::f32::consts::{FRAC_PI_2, FRAC_PI_6, LN_2, LOG2_E, PI, SQRT_2};
+
+const SIGN_MASK: u32 = 0x8000_0000;
+
+/// `π/2` split in three parts, the first two with 12 significant bits, so
+/// that multiples `k · part` are exact for `|k| < 2^12`
+const PIO2_HI: f32 = 1.570_312_5;
+const PIO2_MID: f32 = 4.837_513e-4;
+const PIO2_LO: f32 = 7.549_79e-8;
+
+/// `ln 2` split so that `k · LN2_HI` is exact for every exponent `k`
+const LN2_HI: f32 = 0.693_115_23;
+const LN2_LO: f32 = 3.194_618_3e-5;
+
+const SQRT_3: f32 = 1.732_050_8;
+/// `tan(π/12)`, above which `atan` shifts its argument by `π/6`
+const TAN_PI_12: f32 = 0.267_949_2;
+
+/// `2^k` for a normal exponent, `-126 <= k <= 127`
+fn exp2i(k: i32) -> f32 {
+    f32::from_bits(((k + 127) as u32) << 23)
+}
+
+/// `x · 2^k` for any `k`, with a single rounding for subnormal results
+fn scale(mut x: f32, mut k: i32) -> f32 {
+    if k > 127 {
+        x *= exp2i(127);
+        k = (k - 127).min(127);
+    } else if k < -126 {
+        // Scale in two steps so only the last one can round
+        x *= exp2i(-126 + 24);
+        k = (k + 126 - 24).max(-126);
+    }
+    x * exp2i(k)
+}
+
+pub fn abs(x: f32) -> f32 {
+    f32::from_bits(x.to_bits() & !SIGN_MASK)
+}
+
+/// `x` with the sign of `sign`
+pub fn copysign(x: f32, sign: f32) -> f32 {
+    f32::from_bits((x.to_bits() & !SIGN_MASK) | (sign.to_bits() & SIGN_MASK))
+}
+
+/// `1.0`, `0.0` or `-1.0` depending on the sign of `x`
+pub fn sign(x: f32) -> f32 {
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else {
+        x
+    }
+}
+
+/// Exact
+pub fn trunc(x: f32) -> f32 {
+    // Values this large, infinities and NaN have no fractional part
+    if abs(x) >= 8_388_608.0 || x.is_nan() {
+        return x;
+    }
+    copysign(x as i32 as f32, x)
+}
+
+/// Exact
+pub fn floor(x: f32) -> f32 {
+    let t = trunc(x);
+    if t > x {
+        t - 1.0
+    } else {
+        t
+    }
+}
+
+/// Exact
+pub fn ceil(x: f32) -> f32 {
+    let t = trunc(x);
+    if t < x {
+        t + 1.0
+    } else {
+        t
+    }
+}
+
+/// Exact; halfway cases round away from zero
+pub fn round(x: f32) -> f32 {
+    let t = trunc(x);
+    if abs(x - t) >= 0.5 {
+        t + sign(x)
+    } else {
+        t
+    }
+}
+
+/// Exact; halfway cases round to the even neighbour
+pub fn round_even(x: f32) -> f32 {
+    if abs(x) >= 8_388_608.0 || x.is_nan() {
+        return x;
+    }
+    let f = floor(x);
+    let d = x - f;
+    let r = if d > 0.5 || (d == 0.5 && (f as i32) & 1 != 0) {
+        f + 1.0
+    } else {
+        f
+    };
+    copysign(r, x)
+}
+
+/// `x - floor(x)`, exact
+pub fn fract(x: f32) -> f32 {
+    x - floor(x)
+}
+
+/// `x - y · floor(x / y)` as GLSL defines it, with the error of one division
+pub fn modulo(x: f32, y: f32) -> f32 {
+    x - y * floor(x / y)
+}
+
+pub fn min(x: f32, y: f32) -> f32 {
+    if y < x {
+        y
+    } else {
+        x
+    }
+}
+
+pub fn max(x: f32, y: f32) -> f32 {
+    if y > x {
+        y
+    } else {
+        x
+    }
+}
+
+/// `min(max(x, lo), hi)`
+pub fn clamp(x: f32, lo: f32, hi: f32) -> f32 {
+    min(max(x, lo), hi)
+}
+
+/// `x · (1 - a) + y · a`
+pub fn mix(x: f32, y: f32, a: f32) -> f32 {
+    x * (1.0 - a) + y * a
+}
+
+/// `0.0` if `x < edge`, else `1.0`
+pub fn step(edge: f32, x: f32) -> f32 {
+    if x < edge {
+        0.0
+    } else {
+        1.0
+    }
+}
+
+/// Hermite interpolation between `edge0` and `edge1`
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = clamp((x - edge0) / (edge1 - edge0), 0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+pub fn radians(degrees: f32) -> f32 {
+    degrees * (PI / 180.0)
+}
+
+pub fn degrees(radians: f32) -> f32 {
+    radians * (180.0 / PI)
+}
+
+/// Relative error within 1 ulp
+pub fn sqrt(x: f32) -> f32 {
+    if x.is_nan() || x == 0.0 || x == f32::INFINITY {
+        return x;
+    }
+    if x < 0.0 {
+        return f32::NAN;
+    }
+    // Bring subnormals into the normal range, then undo it on the result
+    let (x, rescale) = if x < f32::MIN_POSITIVE {
+        (x * exp2i(24), exp2i(-12))
+    } else {
+        (x, 1.0)
+    };
+    // Halving the exponent gives an estimate within 6%, and each Newton
+    // step squares the relative error
+    let mut y = f32::from_bits((x.to_bits() >> 1) + 0x1fc0_0000);
+    for _ in 0..4 {
+        y = 0.5 * (y + x / y);
+    }
+    y * rescale
+}
+
+/// Relative error within 2 ulp
+pub fn inverse_sqrt(x: f32) -> f32 {
+    1.0 / sqrt(x)
+}
+
+/// Relative error below `2^-22` for results in the normal range
+pub fn exp(x: f32) -> f32 {
+    if x.is_nan() {
+        return x;
+    }
+    if x > 88.722_84 {
+        return f32::INFINITY;
+    }
+    if x < -103.972_08 {
+        return 0.0;
+    }
+    let k = round(x * LOG2_E);
+    let r = (x - k * LN2_HI) - k * LN2_LO;
+    scale(exp_reduced(r), k as i32)
+}
+
+/// `e^r` for `|r| <= ln(2)/2`
+fn exp_reduced(r: f32) -> f32 {
+    1.0 + r
+        * (1.0
+            + r * (1.0 / 2.0
+                + r * (1.0 / 6.0
+                    + r * (1.0 / 24.0
+                        + r * (1.0 / 120.0 + r * (1.0 / 720.0 + r * (1.0 / 5040.0)))))))
+}
+
+/// Relative error below `2^-22` for results in the normal range
+pub fn exp2(x: f32) -> f32 {
+    if x.is_nan() {
+        return x;
+    }
+    if x >= 128.0 {
+        return f32::INFINITY;
+    }
+    if x < -150.0 {
+        return 0.0;
+    }
+    let k = round(x);
+    scale(exp_reduced((x - k) * LN_2), k as i32)
+}
+
+/// Split a positive finite `x` into `(k, m)` with `x = 2^k · m` and
+/// `m` in `[√2/2, √2)`
+fn split_exponent(x: f32) -> (i32, f32) {
+    let (x, bias) = if x < f32::MIN_POSITIVE {
+        (x * exp2i(24), 24)
+    } else {
+        (x, 0)
+    };
+    let bits = x.to_bits();
+    let mut k = (bits >> 23) as i32 - 127 - bias;
+    let mut m = f32::from_bits((bits & 0x007f_ffff) | 0x3f80_0000);
+    if m > SQRT_2 {
+        m *= 0.5;
+        k += 1;
+    }
+    (k, m)
+}
+
+/// `ln(m)` for `m` in `[√2/2, √2)`
+fn log_reduced(m: f32) -> f32 {
+    // ln(m) = 2 atanh(s) with s = (m - 1) / (m + 1), |s| < 0.172
+    let s = (m - 1.0) / (m + 1.0);
+    let s2 = s * s;
+    2.0 * s * (1.0 + s2 * (1.0 / 3.0 + s2 * (1.0 / 5.0 + s2 * (1.0 / 7.0 + s2 * (1.0 / 9.0)))))
+}
+
+/// Handles the arguments of `log` and `log2` outside the positive finite range
+fn log_special(x: f32) -> Option<f32> {
+    if x.is_nan() || x == f32::INFINITY {
+        Some(x)
+    } else if x == 0.0 {
+        Some(f32::NEG_INFINITY)
+    } else if x < 0.0 {
+        Some(f32::NAN)
+    } else {
+        None
+    }
+}
+
+/// Absolute error below `2^-22` for `x` in `[0.5, 2]`, relative error below
+/// `2^-22` elsewhere
+pub fn log(x: f32) -> f32 {
+    if let Some(special) = log_special(x) {
+        return special;
+    }
+    let (k, m) = split_exponent(x);
+    let k = k as f32;
+    k * LN2_HI + (log_reduced(m) + k * LN2_LO)
+}
+
+/// Absolute error below `2^-22` for `x` in `[0.5, 2]`, relative error below
+/// `2^-22` elsewhere
+pub fn log2(x: f32) -> f32 {
+    if let Some(special) = log_special(x) {
+        return special;
+    }
+    let (k, m) = split_exponent(x);
+    k as f32 + log_reduced(m) * LOG2_E
+}
+
+/// `2^(y · log2(x))`
+///
+/// The relative error is below `2^-22 · (2 + |y · log2(x)|)` for results in
+/// the normal range. GLSL leaves `pow` undefined for `x < 0`; here a
+/// negative `x` with an integral `y` gives the mathematical result and any
+/// other negative `x` gives NaN.
+pub fn pow(x: f32, y: f32) -> f32 {
+    if y == 0.0 || x == 1.0 {
+        return 1.0;
+    }
+    if x.is_nan() || y.is_nan() {
+        return f32::NAN;
+    }
+    if x < 0.0 {
+        if trunc(y) != y {
+            return f32::NAN;
+        }
+        let odd = abs(y) < 16_777_216.0 && (y as i32) & 1 != 0;
+        let magnitude = pow(-x, y);
+        return if odd { -magnitude } else { magnitude };
+    }
+    if x == 0.0 {
+        return if y > 0.0 { 0.0 } else { f32::INFINITY };
+    }
+    exp2(y * log2(x))
+}
+
+/// Reduce `x` to `r` in `[-π/4, π/4]` and the quadrant `n` with
+/// `x = n · π/2 + r`
+///
+/// The reduction is accurate for `|x| < 2^12 · π/2`.
+fn reduce_quadrant(x: f32) -> (f32, i32) {
+    let n = round(x * (2.0 / PI));
+    let r = ((x - n * PIO2_HI) - n * PIO2_MID) - n * PIO2_LO;
+    (r, n as i32)
+}
+
+fn sin_reduced(r: f32) -> f32 {
+    let r2 = r * r;
+    r + r * r2 * (-1.0 / 6.0 + r2 * (1.0 / 120.0 + r2 * (-1.0 / 5040.0 + r2 * (1.0 / 362_880.0))))
+}
+
+fn cos_reduced(r: f32) -> f32 {
+    let r2 = r * r;
+    1.0 + r2
+        * (-0.5
+            + r2 * (1.0 / 24.0
+                + r2 * (-1.0 / 720.0 + r2 * (1.0 / 40320.0 + r2 * (-1.0 / 3_628_800.0)))))
+}
+
+/// Absolute error below `2^-21` for `|x| < 6400`; larger arguments lose
+/// precision in the range reduction
+pub fn sin(x: f32) -> f32 {
+    if !x.is_finite() {
+        return f32::NAN;
+    }
+    let (r, n) = reduce_quadrant(x);
+    match n & 3 {
+        0 => sin_reduced(r),
+        1 => cos_reduced(r),
+        2 => -sin_reduced(r),
+        _ => -cos_reduced(r),
+    }
+}
+
+/// Absolute error below `2^-21` for `|x| < 6400`; larger arguments lose
+/// precision in the range reduction
+pub fn cos(x: f32) -> f32 {
+    if !x.is_finite() {
+        return f32::NAN;
+    }
+    let (r, n) = reduce_quadrant(x);
+    match n & 3 {
+        0 => cos_reduced(r),
+        1 => -sin_reduced(r),
+        2 => -cos_reduced(r),
+        _ => sin_reduced(r),
+    }
+}
+
+/// `sin(x) / cos(x)`, with a relative error below `2^-20` away from the poles
+pub fn tan(x: f32) -> f32 {
+    if !x.is_finite() {
+        return f32::NAN;
+    }
+    let (r, n) = reduce_quadrant(x);
+    let (s, c) = (sin_reduced(r), cos_reduced(r));
+    if n & 1 == 0 {
+        s / c
+    } else {
+        -c / s
+    }
+}
+
+/// Absolute error below `2^-22`
+pub fn atan(x: f32) -> f32 {
+    if x.is_nan() {
+        return x;
+    }
+    let a = abs(x);
+    let (a, inverted) = if a > 1.0 { (1.0 / a, true) } else { (a, false) };
+    // atan(a) = π/6 + atan((a√3 - 1) / (a + √3)) brings a below tan(π/12)
+    let (z, offset) = if a > TAN_PI_12 {
+        ((a * SQRT_3 - 1.0) / (a + SQRT_3), FRAC_PI_6)
+    } else {
+        (a, 0.0)
+    };
+    let z2 = z * z;
+    let series = z
+        * (1.0
+            + z2 * (-1.0 / 3.0
+                + z2 * (1.0 / 5.0 + z2 * (-1.0 / 7.0 + z2 * (1.0 / 9.0 + z2 * (-1.0 / 11.0))))));
+    let r = offset + series;
+    copysign(if inverted { FRAC_PI_2 - r } else { r }, x)
+}
+
+/// Angle of the point `(x, y)`, with an absolute error below `2^-21`
+///
+/// The result is in `[-π, π]`; `atan2(0.0, 0.0)` is `0.0`.
+pub fn atan2(y: f32, x: f32) -> f32 {
+    if x.is_nan() || y.is_nan() {
+        return f32::NAN;
+    }
+    if x == 0.0 {
+        return if y == 0.0 {
+            0.0
+        } else {
+            copysign(FRAC_PI_2, y)
+        };
+    }
+    // Dividing the smaller coordinate by the larger keeps the quotient finite
+    if abs(y) > abs(x) {
+        copysign(FRAC_PI_2, y) - atan(x / y)
+    } else if x > 0.0 {
+        atan(y / x)
+    } else if y < 0.0 {
+        atan(y / x) - PI
+    } else {
+        atan(y / x) + PI
+    }
+}
+
+/// Absolute error below `2^-20`
+pub fn asin(x: f32) -> f32 {
+    atan2(x, sqrt((1.0 - x) * (1.0 + x)))
+}
+
+/// Absolute error below `2^-20`
+pub fn acos(x: f32) -> f32 {
+    atan2(sqrt((1.0 - x) * (1.0 + x)), x)
+}
+
+/// Relative error below `2^-20`
+pub fn sinh(x: f32) -> f32 {
+    if abs(x) < 0.5 {
+        let x2 = x * x;
+        return x + x * x2 * (1.0 / 6.0 + x2 * (1.0 / 120.0 + x2 * (1.0 / 5040.0)));
+    }
+    let e = exp(abs(x));
+    copysign(0.5 * e - 0.5 / e, x)
+}
+
+/// Relative error below `2^-20`
+pub fn cosh(x: f32) -> f32 {
+    let e = exp(abs(x));
+    0.5 * e + 0.5 / e
+}
+
+/// Relative error below `2^-20`
+pub fn tanh(x: f32) -> f32 {
+    let a = abs(x);
+    if a < 0.25 {
+        let x2 = x * x;
+        return x + x
+            * x2
+            * (-1.0 / 3.0 + x2 * (2.0 / 15.0 + x2 * (-17.0 / 315.0 + x2 * (62.0 / 2835.0))));
+    }
+    if a > 9.0 {
+        return copysign(1.0, x);
+    }
+    let e = exp(2.0 * a);
+    copysign(1.0 - 2.0 / (e + 1.0), x)
+}
+
+/// Absolute error below `2^-20`
+pub fn asinh(x: f32) -> f32 {
+    let a = abs(x);
+    let r = if a > 1e18 {
+        log(a) + LN_2
+    } else {
+        log(a + sqrt(a * a + 1.0))
+    };
+    copysign(r, x)
+}
+
+/// Absolute error below `2^-20`; NaN for `x < 1`
+pub fn acosh(x: f32) -> f32 {
+    if x > 1e18 {
+        log(x) + LN_2
+    } else {
+        log(x + sqrt((x - 1.0) * (x + 1.0)))
+    }
+}
+
+/// Absolute error below `2^-20` for `|x| < 0.999`; NaN for `|x| > 1`
+pub fn atanh(x: f32) -> f32 {
+    0.5 * log((1.0 + x) / (1.0 - x))
+}
+
+/// Sum of the component products, exact up to one rounding per component
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Euclidean length, with the error of [`sqrt`] on top of [`dot`]
+pub fn length(x: &[f32]) -> f32 {
+    sqrt(dot(x, x))
+}
+
+pub fn distance(a: &[f32], b: &[f32]) -> f32 {
+    let mut sum = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        sum += (x - y) * (x - y);
+    }
+    sqrt(sum)
+}
+
+pub fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - b[1] * a[2],
+        a[2] * b[0] - b[2] * a[0],
+        a[0] * b[1] - b[0] * a[1],
+    ]
+}
+
+/// Scale `x` in place to unit length
+///
+/// The direction of a zero vector is undefined in GLSL; its components
+/// become NaN.
+pub fn normalize(x: &mut [f32]) {
+    let scale = inverse_sqrt(dot(x, x));
+    for v in x {
+        *v *= scale;
+    }
+}
+
+/// Reflect the incident vector `i` in place about the plane with unit normal `n`
+pub fn reflect(i: &mut [f32], n: &[f32]) {
+    let d = 2.0 * dot(n, i);
+    for (v, n) in i.iter_mut().zip(n) {
+        *v -= d * n;
+    }
+}
+
+/// Refract the incident vector `i` in place through the surface with unit
+/// normal `n` and ratio of indices of refraction `eta`
+///
+/// Total internal reflection gives a zero vector.
+pub fn refract(i: &mut [f32], n: &[f32], eta: f32) {
+    let d = dot(n, i);
+    let k = 1.0 - eta * eta * (1.0 - d * d);
+    if k < 0.0 {
+        i.fill(0.0);
+        return;
+    }
+    let scale = eta * d + sqrt(k);
+    for (v, n) in i.iter_mut().zip(n) {
+        *v = eta * *v - scale * n;
+    }
+}
+
+/// Flip the normal `n` in place unless it faces against the incident vector `i`
+pub fn face_forward(n: &mut [f32], i: &[f32], nref: &[f32]) {
+    if dot(nref, i) >= 0.0 {
+        for v in n {
+            *v = -*v;
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Runtime support routines for executing shaders
+//!
+//! The guest targets have no floating point hardware, so builtins that are
+//! not a handful of arithmetic instructions are calls into [`math`]. The same
+//! routines are used on the host, when folding constants at compile time, so
+//! a builtin evaluates to the same bits whichever path executes it.
+//! [`noise`] implements the builtins of the `GL_LP_noise` extension, and
+//! [`fixed`] the builtins of code lowered to fixed point, and [`soft_float`]
+//! the arithmetic of code lowered to integer-only IEEE floats.
+
+pub mod fixed;
+pub mod math;
+pub mod noise;
+pub mod soft_float;
+
+use crate::sema::builtins::Builtin;
+
+/// A component-wise float builtin, applied to one component of each argument
+#[derive(Clone, Copy)]
+pub enum Routine {
+    Unary(fn(f32) -> f32),
+    Binary(fn(f32, f32) -> f32),
+    Ternary(fn(f32, f32, f32) -> f32),
+}
+
+impl Routine {
+    /// The routine implementing `builtin` called with `arity` arguments, if
+    /// it is a component-wise float function
+    pub fn of(builtin: Builtin, arity: usize) -> Option<Routine> {
+        use math::*;
+        use Builtin as B;
+        use Routine::*;
+
+        Some(match (builtin, arity) {
+            (B::Radians, _) => Unary(radians),
+            (B::Degrees, _) => Unary(degrees),
+            (B::Sin, _) => Unary(sin),
+            (B::Cos, _) => Unary(cos),
+            (B::Tan, _) => Unary(tan),
+            (B::Asin, _) => Unary(asin),
+            (B::Acos, _) => Unary(acos),
+            (B::Atan, 1) => Unary(atan),
+            (B::Atan, _) => Binary(atan2),
+            (B::Sinh, _) => Unary(sinh),
+            (B::Cosh, _) => Unary(cosh),
+            (B::Tanh, _) => Unary(tanh),
+            (B::Asinh, _) => Unary(asinh),
+            (B::Acosh, _) => Unary(acosh),
+            (B::Atanh, _) => Unary(atanh),
+            (B::Pow, _) => Binary(pow),
+            (B::Exp, _) => Unary(exp),
+            (B::Log, _) => Unary(log),
+            (B::Exp2, _) => Unary(exp2),
+            (B::Log2, _) => Unary(log2),
+            (B::Sqrt, _) => Unary(sqrt),
+            (B::InverseSqrt, _) => Unary(inverse_sqrt),
+            (B::Floor, _) => Unary(floor),
+            (B::Trunc, _) => Unary(trunc),
+            (B::Round, _) => Unary(round),
+            (B::RoundEven, _) => Unary(round_even),
+            (B::Ceil, _) => Unary(ceil),
+            (B::Fract, _) => Unary(fract),
+            (B::Mod, _) => Binary(modulo),
+            (B::Step, _) => Binary(step),
+            (B::Smoothstep, _) => Ternary(smoothstep),
+            _ => return None,
+        })
+    }
+
+    /// Apply the routine to the first arguments of `args`
+    pub fn call(self, args: &[f32]) -> f32 {
+        match self {
+            Routine::Unary(f) => f(args[0]),
+            Routine::Binary(f) => f(args[0], args[1]),
+            Routine::Ternary(f) => f(args[0], args[1], args[2]),
+        }
+    }
+}
+
+/// Last part of the name of the routine computing `builtin` of `arity`
+/// scalar arguments
+///
+/// This is the name of the builtin, except for the two-argument `atan`,
+/// named `atan2`, and `lp_fbm`, named `lp_fbm2` or `lp_fbm3` after the
+/// dimension of its point.
+pub fn routine_name(builtin: Builtin, arity: usize) -> &'static str {
+    match (builtin, arity) {
+        (Builtin::Atan, 2) => "atan2",
+        (Builtin::LpFbm, 3) => "lp_fbm2",
+        (Builtin::LpFbm, _) => "lp_fbm3",
+        _ => builtin.name(),
+    }
+}
+
+/// Run the runtime routine called `name` on the bits of its scalar
+/// arguments, or `None` if there is no routine with that name
+pub fn call_symbol(name: &str, args: &[u32]) -> Option<u32> {
+    fixed::call_symbol(name, args).or_else(|| soft_float::call_symbol(name, args))
+}
@@ -0,0 +1,218 @@
+//! Noise and hash builtins of the `GL_LP_noise` extension
+//!
+//! Noise is computed in 16.16 fixed point with integer arithmetic only. On
+//! the guest this is much cheaper than soft-float, and since no rounding
+//! mode or float library is involved, every target produces the same bits.
+//! Coordinates are converted to fixed point on entry, which resolves them to
+//! `1/65536` and saturates them to `±32768`.
+
+/// `1.0` in 16.16 fixed point
+const ONE: i32 = 1 << 16;
+
+/// Octave count above which [`fbm2`] and [`fbm3`] stop adding detail
+const MAX_OCTAVES: i32 = 12;
+
+/// Offset added to the coordinates of each successive fbm octave, so the
+/// octaves do not all have a zero crossing at the origin
+const OCTAVE_OFFSET: i32 = 0x0001_3579;
+
+/// Hash of a 32-bit integer with full avalanche
+///
+/// This is the `lowbias32` mixer: two multiplications and three shifts.
+pub fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^ (x >> 16)
+}
+
+pub fn hash2(x: u32, y: u32) -> u32 {
+    hash(x ^ hash(y ^ 0x9e37_79b9))
+}
+
+pub fn hash3(x: u32, y: u32, z: u32) -> u32 {
+    hash(x ^ hash2(y, z))
+}
+
+/// 2D simplex noise in `[-1, 1]`
+pub fn noise2(x: f32, y: f32) -> f32 {
+    from_fixed(simplex2(to_fixed(x), to_fixed(y)))
+}
+
+/// 3D simplex noise in `[-1, 1]`
+pub fn noise3(x: f32, y: f32, z: f32) -> f32 {
+    from_fixed(simplex3(to_fixed(x), to_fixed(y), to_fixed(z)))
+}
+
+/// Fractal sum of `octaves` octaves of [`noise2`], each at twice the
+/// frequency and half the amplitude of the previous one, in `[-1, 1]`
+///
+/// At most 12 octaves are used; fewer than one gives `0.0`.
+pub fn fbm2(x: f32, y: f32, octaves: i32) -> f32 {
+    let (x, y) = (to_fixed(x), to_fixed(y));
+    from_fixed(fbm(octaves, |octave| {
+        let offset = octave.wrapping_mul(OCTAVE_OFFSET);
+        simplex2(
+            x.wrapping_shl(octave as u32).wrapping_add(offset),
+            y.wrapping_shl(octave as u32).wrapping_add(offset),
+        )
+    }))
+}
+
+/// Fractal sum of `octaves` octaves of [`noise3`], as for [`fbm2`]
+pub fn fbm3(x: f32, y: f32, z: f32, octaves: i32) -> f32 {
+    let (x, y, z) = (to_fixed(x), to_fixed(y), to_fixed(z));
+    from_fixed(fbm(octaves, |octave| {
+        let offset = octave.wrapping_mul(OCTAVE_OFFSET);
+        simplex3(
+            x.wrapping_shl(octave as u32).wrapping_add(offset),
+            y.wrapping_shl(octave as u32).wrapping_add(offset),
+            z.wrapping_shl(octave as u32).wrapping_add(offset),
+        )
+    }))
+}
+
+/// Sum `noise(octave) / 2^(octave + 1)`, normalized by the total amplitude
+fn fbm(octaves: i32, noise: impl Fn(i32) -> i32) -> i32 {
+    let octaves = octaves.min(MAX_OCTAVES);
+    if octaves < 1 {
+        return 0;
+    }
+    let total: i32 = (0..octaves)
+        .map(|octave| noise(octave) >> (octave + 1))
+        .sum();
+    // The amplitudes add up to 1 - 2^-octaves
+    let amplitude = ONE - (ONE >> octaves);
+    ((total as i64 * ONE as i64) / amplitude as i64) as i32
+}
+
+fn to_fixed(x: f32) -> i32 {
+    // Scaling by a power of two is exact and the cast saturates
+    (x * ONE as f32) as i32
+}
+
+fn from_fixed(x: i32) -> f32 {
+    x as f32 / ONE as f32
+}
+
+/// Product of two fixed point numbers
+fn mul(a: i32, b: i32) -> i32 {
+    ((a as i64 * b as i64) >> 16) as i32
+}
+
+/// Lattice cell containing the skewed coordinate `x + s`, and its origin
+/// in fixed point
+fn cell(x: i32, s: i64) -> i32 {
+    ((x as i64 + s) >> 16) as i32
+}
+
+/// Falloff of a simplex corner at squared distance `d2` with radius² `r2`,
+/// times the gradient term `g`
+fn corner(r2: i32, d2: i32, g: i32) -> i32 {
+    let t = r2 - d2;
+    if t <= 0 {
+        return 0;
+    }
+    let t2 = mul(t, t);
+    mul(mul(t2, t2), g)
+}
+
+fn simplex2(x: i32, y: i32) -> i32 {
+    /// `(√3 - 1) / 2`, skewing the input to the square lattice
+    const F2: i64 = 23_987;
+    /// `(3 - √3) / 6`, unskewing back
+    const G2: i32 = 13_849;
+
+    let s = ((x as i64 + y as i64) * F2) >> 16;
+    let (i, j) = (cell(x, s), cell(y, s));
+    let t = i.wrapping_add(j).wrapping_mul(G2);
+    // Offsets from the three corners of the containing simplex
+    let x0 = x.wrapping_sub(i.wrapping_shl(16)).wrapping_add(t);
+    let y0 = y.wrapping_sub(j.wrapping_shl(16)).wrapping_add(t);
+    let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+    let (x1, y1) = (x0 - i1 * ONE + G2, y0 - j1 * ONE + G2);
+    let (x2, y2) = (x0 - ONE + 2 * G2, y0 - ONE + 2 * G2);
+
+    let contribution = |dx: i32, dy: i32, di: i32, dj: i32| {
+        let h = hash2(i.wrapping_add(di) as u32, j.wrapping_add(dj) as u32);
+        let g = match h & 7 {
+            0 => dx + dy,
+            1 => dy - dx,
+            2 => dx - dy,
+            3 => -dx - dy,
+            4 => dx,
+            5 => -dx,
+            6 => dy,
+            _ => -dy,
+        };
+        corner(ONE / 2, mul(dx, dx) + mul(dy, dy), g)
+    };
+    let sum =
+        contribution(x0, y0, 0, 0) + contribution(x1, y1, i1, j1) + contribution(x2, y2, 1, 1);
+    (sum * 70).clamp(-ONE, ONE)
+}
+
+fn simplex3(x: i32, y: i32, z: i32) -> i32 {
+    /// `1 / 3`, skewing the input to the cubic lattice
+    const F3: i64 = 21_845;
+    /// `1 / 6`, unskewing back
+    const G3: i32 = 10_923;
+
+    let s = ((x as i64 + y as i64 + z as i64) * F3) >> 16;
+    let (i, j, k) = (cell(x, s), cell(y, s), cell(z, s));
+    let t = i.wrapping_add(j).wrapping_add(k).wrapping_mul(G3);
+    let x0 = x.wrapping_sub(i.wrapping_shl(16)).wrapping_add(t);
+    let y0 = y.wrapping_sub(j.wrapping_shl(16)).wrapping_add(t);
+    let z0 = z.wrapping_sub(k.wrapping_shl(16)).wrapping_add(t);
+    // The simplex is chosen by the order of the offsets along the axes
+    let ((i1, j1, k1), (i2, j2, k2)) = if x0 >= y0 {
+        if y0 >= z0 {
+            ((1, 0, 0), (1, 1, 0))
+        } else if x0 >= z0 {
+            ((1, 0, 0), (1, 0, 1))
+        } else {
+            ((0, 0, 1), (1, 0, 1))
+        }
+    } else if y0 < z0 {
+        ((0, 0, 1), (0, 1, 1))
+    } else if x0 < z0 {
+        ((0, 1, 0), (0, 1, 1))
+    } else {
+        ((0, 1, 0), (1, 1, 0))
+    };
+
+    let contribution = |di: i32, dj: i32, dk: i32, n: i32| {
+        let dx = x0 - di * ONE + n * G3;
+        let dy = y0 - dj * ONE + n * G3;
+        let dz = z0 - dk * ONE + n * G3;
+        let h = hash3(
+            i.wrapping_add(di) as u32,
+            j.wrapping_add(dj) as u32,
+            k.wrapping_add(dk) as u32,
+        );
+        // Gradients towards the midpoints of the 12 cube edges
+        let g = match h % 12 {
+            0 => dx + dy,
+            1 => dy - dx,
+            2 => dx - dy,
+            3 => -dx - dy,
+            4 => dx + dz,
+            5 => dz - dx,
+            6 => dx - dz,
+            7 => -dx - dz,
+            8 => dy + dz,
+            9 => dz - dy,
+            10 => dy - dz,
+            _ => -dy - dz,
+        };
+        let d2 = mul(dx, dx) + mul(dy, dy) + mul(dz, dz);
+        // Radius² of 0.6
+        corner(39_322, d2, g)
+    };
+    let sum = contribution(0, 0, 0, 0)
+        + contribution(i1, j1, k1, 1)
+        + contribution(i2, j2, k2, 2)
+        + contribution(1, 1, 1, 3);
+    (sum * 32).clamp(-ONE, ONE)
+}
@@ -0,0 +1,303 @@
+//! IEEE 754 single precision in integer arithmetic
+//!
+//! Code lowered to [`Numeric::SoftFloat`](crate::numeric::Numeric) holds
+//! `float` values as the bits of an `f32` in integer registers and calls
+//! these routines, named by their [`symbol`] such as `lp.f32.add`, for
+//! everything but moving them around. The basic operations work on the
+//! bits alone and round to nearest, ties to even, so they give the same
+//! bits as host `f32` arithmetic, subnormals included. The one exception
+//! is NaN: every NaN result is the canonical quiet NaN, as on RISC-V.
+//! Conversions to integers saturate and turn NaN into 0, as Rust's `as`
+//! does. Builtins run the [`math`](super::math) and [`noise`] routines,
+//! which are themselves written with these operations in mind.
+
+use alloc::{format, string::String, vec::Vec};
+
+use super::{noise, routine_name, Routine};
+use crate::sema::builtins::Builtin;
+
+const SIGN: u32 = 0x8000_0000;
+const INFINITY: u32 = 0x7f80_0000;
+/// The canonical quiet NaN
+const NAN: u32 = 0x7fc0_0000;
+const MANTISSA: u32 = 0x007f_ffff;
+
+/// Exponent of the least significant bit of a subnormal
+const MIN_EXP: i32 = -149;
+
+/// Name of the routine computing the basic operation or builtin `name`
+pub fn symbol(name: &str) -> String {
+    format!("lp.f32.{}", name)
+}
+
+/// Name of the routine computing `builtin` of `arity` scalar arguments
+pub fn builtin_symbol(builtin: Builtin, arity: usize) -> String {
+    symbol(routine_name(builtin, arity))
+}
+
+/// Run the routine called `name` on the bits of its arguments, or `None`
+/// if no routine has that name
+///
+/// Comparisons return 1 if true and 0 if false.
+pub fn call_symbol(name: &str, args: &[u32]) -> Option<u32> {
+    let name = name.strip_prefix("lp.f32.")?;
+    let arg = |i: usize| args[i];
+    Some(match name {
+        "add" => add(arg(0), arg(1)),
+        "sub" => sub(arg(0), arg(1)),
+        "mul" => mul(arg(0), arg(1)),
+        "div" => div(arg(0), arg(1)),
+        "eq" => eq(arg(0), arg(1)) as u32,
+        "ne" => !eq(arg(0), arg(1)) as u32,
+        "lt" => lt(arg(0), arg(1)) as u32,
+        "le" => le(arg(0), arg(1)) as u32,
+        "gt" => lt(arg(1), arg(0)) as u32,
+        "ge" => le(arg(1), arg(0)) as u32,
+        "sitofp" => from_i32(arg(0) as i32),
+        "uitofp" => from_u32(arg(0)),
+        "fptosi" => to_i32(arg(0)) as u32,
+        "fptoui" => to_u32(arg(0)),
+        "atan2" => return call(Builtin::Atan, args),
+        "lp_fbm2" | "lp_fbm3" => return call(Builtin::LpFbm, args),
+        name => return call(Builtin::from_name(name)?, args),
+    })
+}
+
+/// Apply `builtin` to the bits of its arguments, or `None` if it has no
+/// routine
+///
+/// The last argument of `lp_fbm`, the octave count, is an integer.
+pub fn call(builtin: Builtin, args: &[u32]) -> Option<u32> {
+    let x = |i: usize| f32::from_bits(args[i]);
+    let result = match (builtin, args.len()) {
+        (Builtin::Sign, _) => super::math::sign(x(0)),
+        (Builtin::Mix, _) => super::math::mix(x(0), x(1), x(2)),
+        (Builtin::LpNoise2, _) => noise::noise2(x(0), x(1)),
+        (Builtin::LpNoise3, _) => noise::noise3(x(0), x(1), x(2)),
+        (Builtin::LpFbm, 3) => noise::fbm2(x(0), x(1), args[2] as i32),
+        (Builtin::LpFbm, 4) => noise::fbm3(x(0), x(1), x(2), args[3] as i32),
+        (builtin, arity) => {
+            let args: Vec<f32> = (0..arity).map(x).collect();
+            Routine::of(builtin, arity)?.call(&args)
+        }
+    };
+    Some(result.to_bits())
+}
+
+fn is_nan(x: u32) -> bool {
+    x & !SIGN > INFINITY
+}
+
+fn is_zero(x: u32) -> bool {
+    x & !SIGN == 0
+}
+
+/// Significand and exponent of a finite value, so that its magnitude is
+/// `significand · 2^exponent`
+fn unpack(x: u32) -> (u64, i32) {
+    let biased = ((x >> 23) & 0xff) as i32;
+    let mantissa = (x & MANTISSA) as u64;
+    if biased == 0 {
+        (mantissa, MIN_EXP)
+    } else {
+        (mantissa | 1 << 23, biased - 150)
+    }
+}
+
+/// Significand of a nonzero value shifted to have its top bit at bit 23,
+/// as for normal floats, and the exponent adjusted to match
+fn normalize((significand, exponent): (u64, i32)) -> (u64, i32) {
+    let shift = significand.leading_zeros() as i32 - 40;
+    (significand << shift, exponent - shift)
+}
+
+/// The float nearest to `±significand · 2^exponent`, ties to even
+fn round(negative: bool, significand: u64, exponent: i32) -> u32 {
+    let sign = if negative { SIGN } else { 0 };
+    if significand == 0 {
+        return sign;
+    }
+    let msb = 63 - significand.leading_zeros() as i32;
+    // Exponent of the last bit kept, below which subnormals have no bits
+    let lsb = (msb + exponent - 23).max(MIN_EXP);
+    let shift = lsb - exponent;
+    let mut kept = if shift <= 0 {
+        significand << -shift
+    } else if shift < 64 {
+        let kept = significand >> shift;
+        let rest = significand & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        if rest > half || (rest == half && kept & 1 == 1) {
+            kept + 1
+        } else {
+            kept
+        }
+    } else {
+        // Below half the least subnormal unless just past it
+        (shift == 64 && significand > 1 << 63) as u64
+    };
+    let mut lsb = lsb;
+    if kept == 1 << 24 {
+        kept >>= 1;
+        lsb += 1;
+    }
+    if kept < 1 << 23 {
+        // Subnormal, or zero after rounding
+        return sign | kept as u32;
+    }
+    let biased = lsb + 150;
+    if biased >= 255 {
+        return sign | INFINITY;
+    }
+    sign | (biased as u32) << 23 | (kept as u32 & MANTISSA)
+}
+
+pub fn add(x: u32, y: u32) -> u32 {
+    if is_nan(x) || is_nan(y) {
+        return NAN;
+    }
+    let (x_inf, y_inf) = (x & !SIGN == INFINITY, y & !SIGN == INFINITY);
+    match (x_inf, y_inf) {
+        (true, true) if x != y => return NAN,
+        (true, _) => return x,
+        (_, true) => return y,
+        _ => {}
+    }
+    if is_zero(x) && is_zero(y) {
+        // Only the sum of two negative zeros is negative
+        return x & y;
+    }
+    // Make `x` the larger magnitude
+    let (x, y) = if x & !SIGN >= y & !SIGN {
+        (x, y)
+    } else {
+        (y, x)
+    };
+    let (mut xs, xe) = unpack(x);
+    let (mut ys, mut ye) = unpack(y);
+    // An operand more than 32 bits smaller lies below the rounding bit of
+    // the result, where any tiny nonzero value rounds alike; this keeps the
+    // aligned significands within 56 bits
+    if xe - ye > 32 {
+        ys = (ys != 0) as u64;
+        ye = xe - 32;
+    }
+    xs <<= xe - ye;
+    let negative = x & SIGN != 0;
+    if (x ^ y) & SIGN == 0 {
+        round(negative, xs + ys, ye)
+    } else if xs == ys {
+        0
+    } else {
+        round(negative, xs - ys, ye)
+    }
+}
+
+pub fn sub(x: u32, y: u32) -> u32 {
+    add(x, y ^ SIGN)
+}
+
+pub fn mul(x: u32, y: u32) -> u32 {
+    if is_nan(x) || is_nan(y) {
+        return NAN;
+    }
+    let sign = (x ^ y) & SIGN;
+    let (x_inf, y_inf) = (x & !SIGN == INFINITY, y & !SIGN == INFINITY);
+    if x_inf || y_inf {
+        if is_zero(x) || is_zero(y) {
+            return NAN;
+        }
+        return sign | INFINITY;
+    }
+    let (xs, xe) = unpack(x);
+    let (ys, ye) = unpack(y);
+    round(sign != 0, xs * ys, xe + ye)
+}
+
+pub fn div(x: u32, y: u32) -> u32 {
+    if is_nan(x) || is_nan(y) {
+        return NAN;
+    }
+    let sign = (x ^ y) & SIGN;
+    let (x_inf, y_inf) = (x & !SIGN == INFINITY, y & !SIGN == INFINITY);
+    match (x_inf, y_inf, is_zero(x), is_zero(y)) {
+        (true, true, ..) | (_, _, true, true) => return NAN,
+        (true, ..) | (.., true) => return sign | INFINITY,
+        (_, true, ..) | (_, _, true, _) => return sign,
+        _ => {}
+    }
+    // With both significands of 24 bits, the quotient has 32 or 33 bits,
+    // the remainder folded into the last one
+    let (xs, xe) = normalize(unpack(x));
+    let (ys, ye) = normalize(unpack(y));
+    let dividend = xs << 32;
+    let ys = ys as u32;
+    let quotient = dividend / ys as u64;
+    // The remainder is below 2^24, so it is nonzero exactly when the low
+    // words of the dividend and of `quotient · ys` differ
+    let sticky = (quotient as u32).wrapping_mul(ys) != dividend as u32;
+    round(sign != 0, quotient | sticky as u64, xe - 32 - ye)
+}
+
+pub fn eq(x: u32, y: u32) -> bool {
+    !is_nan(x) && !is_nan(y) && (x == y || is_zero(x | y))
+}
+
+/// Value ordered like the float, both zeros being 0
+fn order(x: u32) -> i64 {
+    let magnitude = (x & !SIGN) as i64;
+    if x & SIGN != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+pub fn lt(x: u32, y: u32) -> bool {
+    !is_nan(x) && !is_nan(y) && order(x) < order(y)
+}
+
+pub fn le(x: u32, y: u32) -> bool {
+    !is_nan(x) && !is_nan(y) && order(x) <= order(y)
+}
+
+pub fn from_i32(x: i32) -> u32 {
+    round(x < 0, x.unsigned_abs() as u64, 0)
+}
+
+pub fn from_u32(x: u32) -> u32 {
+    round(false, x as u64, 0)
+}
+
+/// Magnitude of `x` rounded toward zero, saturating at `limit`
+fn truncate(x: u32, limit: u64) -> u64 {
+    if x & !SIGN >= INFINITY {
+        return limit;
+    }
+    let (significand, exponent) = unpack(x);
+    let magnitude = if exponent >= 0 {
+        // Every float of 2^32 or more is out of range anyway
+        significand << exponent.min(32)
+    } else {
+        significand >> (-exponent).min(63)
+    };
+    magnitude.min(limit)
+}
+
+pub fn to_i32(x: u32) -> i32 {
+    if is_nan(x) {
+        return 0;
+    }
+    if x & SIGN != 0 {
+        -(truncate(x, 1 << 31) as i64) as i32
+    } else {
+        truncate(x, i32::MAX as u64) as i32
+    }
+}
+
+pub fn to_u32(x: u32) -> u32 {
+    if is_nan(x) || x & SIGN != 0 {
+        return 0;
+    }
+    truncate(x, u32::MAX as u64) as u32
+}
@@ -0,0 +1,186 @@
+//! Growable buffer of machine code with labels
+
+use alloc::vec::Vec;
+
+use super::{BranchOffset, Cond, EncodeError, Imm12, ImmOp, Inst, JumpOffset, Reg, UpperImm};
+
+/// Position in a [`CodeBuffer`], which branches may target before it is
+/// bound
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Label(pub(super) u32);
+
+/// Instruction whose offset to a label is patched in when the buffer is
+/// finished
+#[derive(Clone, Copy, Debug)]
+enum Fixup {
+    Branch {
+        cond: Cond,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Jal {
+        rd: Reg,
+    },
+    /// `auipc` and `addi` loading the address of the label
+    Address {
+        rd: Reg,
+    },
+}
+
+/// Machine code being emitted, in little-endian order
+///
+/// Instructions are emitted in their 32-bit form, or in their compressed
+/// form if they have one and the buffer is [`compressed`](Self::compressed).
+/// Branches and jumps to labels always take 32 bits, so that emitting them
+/// does not depend on where the labels are bound.
+#[derive(Clone, Debug, Default)]
+pub struct CodeBuffer {
+    bytes: Vec<u8>,
+    compress: bool,
+    /// Offset each label is bound to
+    labels: Vec<Option<u32>>,
+    fixups: Vec<(u32, Label, Fixup)>,
+}
+
+impl CodeBuffer {
+    pub fn new() -> CodeBuffer {
+        CodeBuffer::default()
+    }
+
+    /// Buffer emitting the compressed form of instructions that have one
+    pub fn compressed() -> CodeBuffer {
+        CodeBuffer {
+            compress: true,
+            ..CodeBuffer::default()
+        }
+    }
+
+    /// Offset of the next instruction from the start of the buffer
+    pub fn offset(&self) -> u32 {
+        self.bytes.len() as u32
+    }
+
+    pub fn emit(&mut self, inst: Inst) {
+        match inst.compress().filter(|_| self.compress) {
+            Some(half) => self.bytes.extend_from_slice(&half.to_le_bytes()),
+            None => self.bytes.extend_from_slice(&inst.encode().to_le_bytes()),
+        }
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() as u32 - 1)
+    }
+
+    /// Bind `label` to the offset of the next instruction
+    pub fn bind(&mut self, label: Label) {
+        let offset = self.offset();
+        let bound = &mut self.labels[label.0 as usize];
+        assert!(bound.is_none(), "label {} bound twice", label.0);
+        *bound = Some(offset);
+    }
+
+    /// Offset `label` is bound to, if it is bound yet
+    pub fn label_offset(&self, label: Label) -> Option<u32> {
+        self.labels[label.0 as usize]
+    }
+
+    /// Branch to `target` if `rs1` and `rs2` satisfy `cond`
+    pub fn branch(&mut self, cond: Cond, rs1: Reg, rs2: Reg, target: Label) {
+        self.fixup(target, Fixup::Branch { cond, rs1, rs2 }, 1);
+    }
+
+    /// Jump to `target`, writing the return address to `rd`
+    pub fn jal(&mut self, rd: Reg, target: Label) {
+        self.fixup(target, Fixup::Jal { rd }, 1);
+    }
+
+    pub fn jump(&mut self, target: Label) {
+        self.jal(Reg::Zero, target);
+    }
+
+    /// Load the address of `target` into `rd`, wherever it is bound
+    pub fn load_address(&mut self, rd: Reg, target: Label) {
+        self.fixup(target, Fixup::Address { rd }, 2);
+    }
+
+    /// Load `value` into `rd` with `addi`, or `lui` then `addi` if it does
+    /// not fit 12 bits
+    pub fn load_immediate(&mut self, rd: Reg, value: i32) {
+        let (upper, lower) = split(value);
+        if upper != 0 {
+            self.emit(Inst::Lui {
+                rd,
+                imm: UpperImm::new(upper).unwrap(),
+            });
+        }
+        if upper == 0 || lower != 0 {
+            self.emit(Inst::OpImm {
+                op: ImmOp::Add,
+                rd,
+                rs1: if upper == 0 { Reg::Zero } else { rd },
+                imm: Imm12::new(lower).unwrap(),
+            });
+        }
+    }
+
+    /// Record a fixup and reserve `words` words for its instructions
+    fn fixup(&mut self, target: Label, fixup: Fixup, words: usize) {
+        self.fixups.push((self.offset(), target, fixup));
+        self.bytes.resize(self.bytes.len() + 4 * words, 0);
+    }
+
+    /// Patch every branch and jump, and return the code
+    pub fn finish(mut self) -> Result<Vec<u8>, EncodeError> {
+        for &(at, target, fixup) in &self.fixups {
+            let target = self.labels[target.0 as usize].ok_or(EncodeError::Unbound(target))?;
+            let offset = target.wrapping_sub(at) as i32;
+            let insts = match fixup {
+                Fixup::Branch { cond, rs1, rs2 } => [
+                    Some(Inst::Branch {
+                        cond,
+                        rs1,
+                        rs2,
+                        offset: BranchOffset::new(offset)?,
+                    }),
+                    None,
+                ],
+                Fixup::Jal { rd } => [
+                    Some(Inst::Jal {
+                        rd,
+                        offset: JumpOffset::new(offset)?,
+                    }),
+                    None,
+                ],
+                Fixup::Address { rd } => {
+                    let (upper, lower) = split(offset);
+                    [
+                        Some(Inst::Auipc {
+                            rd,
+                            imm: UpperImm::new(upper)?,
+                        }),
+                        Some(Inst::OpImm {
+                            op: ImmOp::Add,
+                            rd,
+                            rs1: rd,
+                            imm: Imm12::new(lower)?,
+                        }),
+                    ]
+                }
+            };
+            for (i, inst) in insts.into_iter().flatten().enumerate() {
+                let start = at as usize + 4 * i;
+                self.bytes[start..start + 4].copy_from_slice(&inst.encode().to_le_bytes());
+            }
+        }
+        Ok(self.bytes)
+    }
+}
+
+/// Upper 20 bits and signed lower 12 bits adding up to `value`, the lower
+/// bits being sign-extended by `addi`
+fn split(value: i32) -> (u32, i32) {
+    let lower = value << 20 >> 20;
+    let upper = (value.wrapping_sub(lower) as u32) >> 12;
+    (upper, lower)
+}
@@ -0,0 +1,277 @@
+//! 16-bit encodings from the C extension
+//!
+//! An instruction has a compressed form when its registers and immediate
+//! fit the shorter fields. Which form is chosen for an instruction that has
+//! several, such as `addi sp, sp, 16`, follows the GNU and LLVM assemblers.
+
+use super::{AluOp, Cond, ImmOp, Inst, LoadWidth, Reg, ShiftOp, StoreWidth};
+
+/// Bits `hi..=lo` of `value`, moved to bit `at` of an instruction
+fn field(value: u32, hi: u32, lo: u32, at: u32) -> u16 {
+    ((value >> lo & ((1 << (hi - lo + 1)) - 1)) << at) as u16
+}
+
+/// Instruction of the CI format: a register and a 6-bit immediate
+fn ci(funct3: u16, rd: Reg, imm: i32, op: u16) -> u16 {
+    let imm = imm as u32;
+    funct3 << 13 | field(imm, 5, 5, 12) | (rd.number() as u16) << 7 | field(imm, 4, 0, 2) | op
+}
+
+/// Instruction of the CR format: two full registers
+fn cr(funct4: u16, rd: Reg, rs2: Reg) -> u16 {
+    funct4 << 12 | (rd.number() as u16) << 7 | (rs2.number() as u16) << 2 | 0b10
+}
+
+fn fits6(imm: i32) -> bool {
+    (-32..32).contains(&imm)
+}
+
+impl Inst {
+    /// 16-bit encoding of the instruction, if it has a compressed form
+    pub fn compress(self) -> Option<u16> {
+        let nonzero = |reg: Reg| reg != Reg::Zero;
+        Some(match self {
+            // c.nop
+            Inst::OpImm {
+                op: ImmOp::Add,
+                rd: Reg::Zero,
+                rs1: Reg::Zero,
+                imm,
+            } if imm.value() == 0 => 0x0001,
+            Inst::OpImm {
+                op: ImmOp::Add,
+                rd,
+                rs1,
+                imm,
+            } if nonzero(rd) => {
+                let imm = imm.value();
+                if imm == 0 && nonzero(rs1) {
+                    // c.mv
+                    cr(0b1000, rd, rs1)
+                } else if rs1 == Reg::Zero && fits6(imm) {
+                    // c.li
+                    ci(0b010, rd, imm, 0b01)
+                } else if rd == rs1 && fits6(imm) {
+                    // c.addi
+                    ci(0b000, rd, imm, 0b01)
+                } else if rd == Reg::Sp
+                    && rs1 == Reg::Sp
+                    && imm % 16 == 0
+                    && imm != 0
+                    && (-512..512).contains(&imm)
+                {
+                    // c.addi16sp
+                    let imm = imm as u32;
+                    0b011 << 13
+                        | field(imm, 9, 9, 12)
+                        | (Reg::Sp.number() as u16) << 7
+                        | field(imm, 4, 4, 6)
+                        | field(imm, 6, 6, 5)
+                        | field(imm, 8, 7, 3)
+                        | field(imm, 5, 5, 2)
+                        | 0b01
+                } else if rs1 == Reg::Sp && imm > 0 && imm < 1024 && imm % 4 == 0 {
+                    // c.addi4spn
+                    let rd = rd.compact()?;
+                    let imm = imm as u32;
+                    field(imm, 5, 4, 11)
+                        | field(imm, 9, 6, 7)
+                        | field(imm, 2, 2, 6)
+                        | field(imm, 3, 3, 5)
+                        | rd << 2
+                } else {
+                    return None;
+                }
+            }
+            // c.andi
+            Inst::OpImm {
+                op: ImmOp::And,
+                rd,
+                rs1,
+                imm,
+            } if rd == rs1 && fits6(imm.value()) => {
+                let imm = imm.value() as u32;
+                0b100 << 13
+                    | field(imm, 5, 5, 12)
+                    | 0b10 << 10
+                    | rd.compact()? << 7
+                    | field(imm, 4, 0, 2)
+                    | 0b01
+            }
+            // c.slli
+            Inst::Shift {
+                op: ShiftOp::Sll,
+                rd,
+                rs1,
+                shamt,
+            } if rd == rs1 && nonzero(rd) && shamt.value() != 0 => {
+                ci(0b000, rd, shamt.value() as i32, 0b10)
+            }
+            // c.srli and c.srai
+            Inst::Shift { op, rd, rs1, shamt } if rd == rs1 && shamt.value() != 0 => {
+                let funct2 = if op == ShiftOp::Sra { 0b01 } else { 0b00 };
+                0b100 << 13
+                    | funct2 << 10
+                    | rd.compact()? << 7
+                    | field(shamt.value(), 4, 0, 2)
+                    | 0b01
+            }
+            Inst::Op {
+                op: AluOp::Add,
+                rd,
+                rs1,
+                rs2,
+            } if nonzero(rd) => {
+                if rs1 == Reg::Zero && nonzero(rs2) {
+                    // c.mv
+                    cr(0b1000, rd, rs2)
+                } else if rs2 == Reg::Zero && nonzero(rs1) {
+                    cr(0b1000, rd, rs1)
+                } else if rd == rs1 && nonzero(rs2) {
+                    // c.add
+                    cr(0b1001, rd, rs2)
+                } else if rd == rs2 && nonzero(rs1) {
+                    cr(0b1001, rd, rs1)
+                } else {
+                    return None;
+                }
+            }
+            // c.sub, c.xor, c.or and c.and
+            Inst::Op { op, rd, rs1, rs2 } => {
+                let (funct2, commutes) = match op {
+                    AluOp::Sub => (0b00, false),
+                    AluOp::Xor => (0b01, true),
+                    AluOp::Or => (0b10, true),
+                    AluOp::And => (0b11, true),
+                    _ => return None,
+                };
+                let rs2 = if rd == rs1 {
+                    rs2
+                } else if rd == rs2 && commutes {
+                    rs1
+                } else {
+                    return None;
+                };
+                0b10_0011 << 10 | rd.compact()? << 7 | funct2 << 5 | rs2.compact()? << 2 | 0b01
+            }
+            // c.lui, whose immediate is a sign-extended 6-bit value
+            Inst::Lui { rd, imm } if nonzero(rd) && rd != Reg::Sp => {
+                let imm = imm.value();
+                if imm == 0 || (imm > 31 && imm < 0xf_ffe0) {
+                    return None;
+                }
+                ci(0b011, rd, imm as i32, 0b01)
+            }
+            Inst::Load {
+                width: LoadWidth::Word,
+                rd,
+                rs1,
+                offset,
+            } => {
+                let offset = offset.value();
+                if offset < 0 || offset % 4 != 0 {
+                    return None;
+                }
+                let offset = offset as u32;
+                if rs1 == Reg::Sp && nonzero(rd) && offset < 256 {
+                    // c.lwsp
+                    0b010 << 13
+                        | field(offset, 5, 5, 12)
+                        | (rd.number() as u16) << 7
+                        | field(offset, 4, 2, 4)
+                        | field(offset, 7, 6, 2)
+                        | 0b10
+                } else if offset < 128 {
+                    // c.lw
+                    0b010 << 13
+                        | field(offset, 5, 3, 10)
+                        | rs1.compact()? << 7
+                        | field(offset, 2, 2, 6)
+                        | field(offset, 6, 6, 5)
+                        | rd.compact()? << 2
+                } else {
+                    return None;
+                }
+            }
+            Inst::Store {
+                width: StoreWidth::Word,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let offset = offset.value();
+                if offset < 0 || offset % 4 != 0 {
+                    return None;
+                }
+                let offset = offset as u32;
+                if rs1 == Reg::Sp && offset < 256 {
+                    // c.swsp
+                    0b110 << 13
+                        | field(offset, 5, 2, 9)
+                        | field(offset, 7, 6, 7)
+                        | (rs2.number() as u16) << 2
+                        | 0b10
+                } else if offset < 128 {
+                    // c.sw
+                    0b110 << 13
+                        | field(offset, 5, 3, 10)
+                        | rs1.compact()? << 7
+                        | field(offset, 2, 2, 6)
+                        | field(offset, 6, 6, 5)
+                        | rs2.compact()? << 2
+                } else {
+                    return None;
+                }
+            }
+            // c.j and c.jal
+            Inst::Jal { rd, offset } if (-2048..2048).contains(&offset.value()) => {
+                let funct3 = match rd {
+                    Reg::Zero => 0b101,
+                    Reg::Ra => 0b001,
+                    _ => return None,
+                };
+                let offset = offset.value() as u32;
+                funct3 << 13
+                    | field(offset, 11, 11, 12)
+                    | field(offset, 4, 4, 11)
+                    | field(offset, 9, 8, 9)
+                    | field(offset, 10, 10, 8)
+                    | field(offset, 6, 6, 7)
+                    | field(offset, 7, 7, 6)
+                    | field(offset, 3, 1, 3)
+                    | field(offset, 5, 5, 2)
+                    | 0b01
+            }
+            // c.jr and c.jalr
+            Inst::Jalr { rd, rs1, offset } if nonzero(rs1) && offset.value() == 0 => match rd {
+                Reg::Zero => cr(0b1000, rs1, Reg::Zero),
+                Reg::Ra => cr(0b1001, rs1, Reg::Zero),
+                _ => return None,
+            },
+            // c.beqz and c.bnez
+            Inst::Branch {
+                cond,
+                rs1,
+                rs2: Reg::Zero,
+                offset,
+            } if (-256..256).contains(&offset.value()) => {
+                let funct3 = match cond {
+                    Cond::Eq => 0b110,
+                    Cond::Ne => 0b111,
+                    _ => return None,
+                };
+                let offset = offset.value() as u32;
+                funct3 << 13
+                    | field(offset, 8, 8, 12)
+                    | field(offset, 4, 3, 10)
+                    | rs1.compact()? << 7
+                    | field(offset, 7, 6, 5)
+                    | field(offset, 2, 1, 3)
+                    | field(offset, 5, 5, 2)
+                    | 0b01
+            }
+            Inst::Ebreak => cr(0b1001, Reg::Zero, Reg::Zero),
+            _ => return None,
+        })
+    }
+}
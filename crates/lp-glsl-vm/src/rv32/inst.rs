@@ -0,0 +1,529 @@
+//! Instructions, their operands and their 32-bit encodings
+
+use core::fmt;
+
+use super::{EncodeError, Reg};
+
+/// Check that `value` is a multiple of `align` in `min..=max`
+fn check(value: i64, min: i64, max: i64, align: u32) -> Result<(), EncodeError> {
+    if value < min || value > max {
+        return Err(EncodeError::OutOfRange { value, min, max });
+    }
+    if value % align as i64 != 0 {
+        return Err(EncodeError::Misaligned { value, align });
+    }
+    Ok(())
+}
+
+/// Signed 12-bit immediate of arithmetic, loads, stores and `jalr`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Imm12(i16);
+
+impl Imm12 {
+    pub const ZERO: Imm12 = Imm12(0);
+
+    pub fn new(value: i32) -> Result<Imm12, EncodeError> {
+        check(value as i64, -2048, 2047, 1)?;
+        Ok(Imm12(value as i16))
+    }
+
+    pub const fn value(self) -> i32 {
+        self.0 as i32
+    }
+}
+
+/// Upper 20 bits of a word, loaded by `lui` and added to the pc by `auipc`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UpperImm(u32);
+
+impl UpperImm {
+    pub fn new(value: u32) -> Result<UpperImm, EncodeError> {
+        check(value as i64, 0, 0xf_ffff, 1)?;
+        Ok(UpperImm(value))
+    }
+
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+}
+
+/// Unsigned 5-bit immediate: a shift amount, or the operand of `csrr*i`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Uimm5(u8);
+
+impl Uimm5 {
+    pub fn new(value: u32) -> Result<Uimm5, EncodeError> {
+        check(value as i64, 0, 31, 1)?;
+        Ok(Uimm5(value as u8))
+    }
+
+    pub const fn value(self) -> u32 {
+        self.0 as u32
+    }
+}
+
+/// Offset of a conditional branch from its address, even and within 4 KiB
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BranchOffset(i16);
+
+impl BranchOffset {
+    pub fn new(value: i32) -> Result<BranchOffset, EncodeError> {
+        check(value as i64, -4096, 4094, 2)?;
+        Ok(BranchOffset(value as i16))
+    }
+
+    pub const fn value(self) -> i32 {
+        self.0 as i32
+    }
+}
+
+/// Offset of `jal` from its address, even and within 1 MiB
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct JumpOffset(i32);
+
+impl JumpOffset {
+    pub fn new(value: i32) -> Result<JumpOffset, EncodeError> {
+        check(value as i64, -(1 << 20), (1 << 20) - 2, 2)?;
+        Ok(JumpOffset(value))
+    }
+
+    pub const fn value(self) -> i32 {
+        self.0
+    }
+}
+
+/// Address of a control and status register, printed with its name if it
+/// is one of the constants
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Csr(u16);
+
+impl Csr {
+    pub const CYCLE: Csr = Csr(0xc00);
+    pub const CYCLEH: Csr = Csr(0xc80);
+    pub const INSTRET: Csr = Csr(0xc02);
+    pub const INSTRETH: Csr = Csr(0xc82);
+    pub const MCAUSE: Csr = Csr(0x342);
+    pub const MEPC: Csr = Csr(0x341);
+    pub const MHARTID: Csr = Csr(0xf14);
+    pub const MIE: Csr = Csr(0x304);
+    pub const MIP: Csr = Csr(0x344);
+    pub const MSCRATCH: Csr = Csr(0x340);
+    pub const MSTATUS: Csr = Csr(0x300);
+    pub const MTVAL: Csr = Csr(0x343);
+    pub const MTVEC: Csr = Csr(0x305);
+    pub const TIME: Csr = Csr(0xc01);
+    pub const TIMEH: Csr = Csr(0xc81);
+
+    pub fn new(address: u32) -> Result<Csr, EncodeError> {
+        check(address as i64, 0, 0xfff, 1)?;
+        Ok(Csr(address as u16))
+    }
+
+    pub const fn address(self) -> u32 {
+        self.0 as u32
+    }
+
+    pub fn name(self) -> Option<&'static str> {
+        Some(match self {
+            Csr::MSTATUS => "mstatus",
+            Csr::MIE => "mie",
+            Csr::MTVEC => "mtvec",
+            Csr::MSCRATCH => "mscratch",
+            Csr::MEPC => "mepc",
+            Csr::MCAUSE => "mcause",
+            Csr::MTVAL => "mtval",
+            Csr::MIP => "mip",
+            Csr::CYCLE => "cycle",
+            Csr::TIME => "time",
+            Csr::INSTRET => "instret",
+            Csr::CYCLEH => "cycleh",
+            Csr::TIMEH => "timeh",
+            Csr::INSTRETH => "instreth",
+            Csr::MHARTID => "mhartid",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Csr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+/// `funct3` field of an instruction, in place
+const fn f3(funct3: u32) -> u32 {
+    funct3 << 12
+}
+
+/// `funct7` and `funct3` fields of an instruction, in place
+const fn f7(funct7: u32, funct3: u32) -> u32 {
+    funct7 << 25 | funct3 << 12
+}
+
+macro_rules! ops {
+    (
+        $(#[$meta:meta])*
+        $op:ident {
+            $($(#[$variant_meta:meta])* $variant:ident => $name:literal, $funct:expr,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum $op {
+            $($(#[$variant_meta])* $variant,)*
+        }
+
+        impl $op {
+            pub const ALL: &'static [$op] = &[$($op::$variant,)*];
+
+            /// Mnemonic of the instruction
+            pub fn name(self) -> &'static str {
+                match self {
+                    $($op::$variant => $name,)*
+                }
+            }
+
+            /// Function fields telling the instruction from the others
+            /// with its opcode, in place in its word
+            fn funct(self) -> u32 {
+                match self {
+                    $($op::$variant => $funct,)*
+                }
+            }
+        }
+    };
+}
+
+ops! {
+    /// Operation on two registers, from RV32I or the M extension
+    ///
+    /// Shifts use the low 5 bits of `rs2`. `mulh`, `mulhsu` and `mulhu`
+    /// give the high word of the 64-bit product of operands taken as
+    /// signed or unsigned.
+    AluOp {
+        Add => "add", f7(0x00, 0),
+        Sub => "sub", f7(0x20, 0),
+        Sll => "sll", f7(0x00, 1),
+        Slt => "slt", f7(0x00, 2),
+        Sltu => "sltu", f7(0x00, 3),
+        Xor => "xor", f7(0x00, 4),
+        Srl => "srl", f7(0x00, 5),
+        Sra => "sra", f7(0x20, 5),
+        Or => "or", f7(0x00, 6),
+        And => "and", f7(0x00, 7),
+        Mul => "mul", f7(0x01, 0),
+        Mulh => "mulh", f7(0x01, 1),
+        Mulhsu => "mulhsu", f7(0x01, 2),
+        Mulhu => "mulhu", f7(0x01, 3),
+        Div => "div", f7(0x01, 4),
+        Divu => "divu", f7(0x01, 5),
+        Rem => "rem", f7(0x01, 6),
+        Remu => "remu", f7(0x01, 7),
+    }
+}
+
+ops! {
+    /// Operation on a register and a 12-bit immediate
+    ImmOp {
+        Add => "addi", f3(0),
+        Slt => "slti", f3(2),
+        Sltu => "sltiu", f3(3),
+        Xor => "xori", f3(4),
+        Or => "ori", f3(6),
+        And => "andi", f3(7),
+    }
+}
+
+ops! {
+    /// Shift by an immediate amount
+    ShiftOp {
+        Sll => "slli", f7(0x00, 1),
+        Srl => "srli", f7(0x00, 5),
+        Sra => "srai", f7(0x20, 5),
+    }
+}
+
+ops! {
+    /// Condition of a branch comparing two registers
+    Cond {
+        Eq => "beq", f3(0),
+        Ne => "bne", f3(1),
+        Lt => "blt", f3(4),
+        Ge => "bge", f3(5),
+        Ltu => "bltu", f3(6),
+        Geu => "bgeu", f3(7),
+    }
+}
+
+ops! {
+    /// Width of a load, and whether it extends the sign of the value
+    LoadWidth {
+        Byte => "lb", f3(0),
+        Half => "lh", f3(1),
+        Word => "lw", f3(2),
+        ByteUnsigned => "lbu", f3(4),
+        HalfUnsigned => "lhu", f3(5),
+    }
+}
+
+ops! {
+    /// Width of a store
+    StoreWidth {
+        Byte => "sb", f3(0),
+        Half => "sh", f3(1),
+        Word => "sw", f3(2),
+    }
+}
+
+ops! {
+    /// Atomic update of a CSR, which writes its old value to `rd`
+    ///
+    /// The names are those of the register forms; the immediate forms take
+    /// a trailing `i`.
+    CsrOp {
+        /// Write the operand
+        Write => "csrrw", f3(1),
+        /// Set the bits set in the operand
+        Set => "csrrs", f3(2),
+        /// Clear the bits set in the operand
+        Clear => "csrrc", f3(3),
+    }
+}
+
+const LUI: u32 = 0x37;
+const AUIPC: u32 = 0x17;
+const JAL: u32 = 0x6f;
+const JALR: u32 = 0x67;
+const BRANCH: u32 = 0x63;
+const LOAD: u32 = 0x03;
+const STORE: u32 = 0x23;
+const OP_IMM: u32 = 0x13;
+const OP: u32 = 0x33;
+const MISC_MEM: u32 = 0x0f;
+const SYSTEM: u32 = 0x73;
+
+/// Instruction of RV32I, the M extension or the Zicsr extension
+///
+/// Offsets of branches and jumps are relative to the address of the
+/// instruction. Stores write `rs2` to the address `rs1 + offset`. The
+/// instruction displays in assembly syntax, without pseudo-instructions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Inst {
+    Lui {
+        rd: Reg,
+        imm: UpperImm,
+    },
+    Auipc {
+        rd: Reg,
+        imm: UpperImm,
+    },
+    Jal {
+        rd: Reg,
+        offset: JumpOffset,
+    },
+    Jalr {
+        rd: Reg,
+        rs1: Reg,
+        offset: Imm12,
+    },
+    Branch {
+        cond: Cond,
+        rs1: Reg,
+        rs2: Reg,
+        offset: BranchOffset,
+    },
+    Load {
+        width: LoadWidth,
+        rd: Reg,
+        rs1: Reg,
+        offset: Imm12,
+    },
+    Store {
+        width: StoreWidth,
+        rs1: Reg,
+        rs2: Reg,
+        offset: Imm12,
+    },
+    OpImm {
+        op: ImmOp,
+        rd: Reg,
+        rs1: Reg,
+        imm: Imm12,
+    },
+    Shift {
+        op: ShiftOp,
+        rd: Reg,
+        rs1: Reg,
+        shamt: Uimm5,
+    },
+    Op {
+        op: AluOp,
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    /// Order all memory and I/O accesses
+    Fence,
+    Ecall,
+    Ebreak,
+    Csr {
+        op: CsrOp,
+        rd: Reg,
+        csr: Csr,
+        rs1: Reg,
+    },
+    CsrImm {
+        op: CsrOp,
+        rd: Reg,
+        csr: Csr,
+        uimm: Uimm5,
+    },
+}
+
+impl Inst {
+    pub const NOP: Inst = Inst::OpImm {
+        op: ImmOp::Add,
+        rd: Reg::Zero,
+        rs1: Reg::Zero,
+        imm: Imm12::ZERO,
+    };
+    /// Return to the address in `ra`
+    pub const RET: Inst = Inst::Jalr {
+        rd: Reg::Zero,
+        rs1: Reg::Ra,
+        offset: Imm12::ZERO,
+    };
+
+    /// Copy `rs` to `rd`
+    pub const fn mv(rd: Reg, rs: Reg) -> Inst {
+        Inst::OpImm {
+            op: ImmOp::Add,
+            rd,
+            rs1: rs,
+            imm: Imm12::ZERO,
+        }
+    }
+
+    /// 32-bit encoding
+    pub fn encode(self) -> u32 {
+        match self {
+            Inst::Lui { rd, imm } => u_type(LUI, rd, imm),
+            Inst::Auipc { rd, imm } => u_type(AUIPC, rd, imm),
+            Inst::Jal { rd, offset } => {
+                let imm = offset.value() as u32;
+                let imm = (imm >> 20 & 1) << 31
+                    | (imm >> 1 & 0x3ff) << 21
+                    | (imm >> 11 & 1) << 20
+                    | (imm >> 12 & 0xff) << 12;
+                imm | rd.bits() << 7 | JAL
+            }
+            Inst::Jalr { rd, rs1, offset } => i_type(JALR, 0, rd, rs1, offset.value()),
+            Inst::Branch {
+                cond,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let imm = offset.value() as u32;
+                let imm = (imm >> 12 & 1) << 31
+                    | (imm >> 5 & 0x3f) << 25
+                    | (imm >> 1 & 0xf) << 8
+                    | (imm >> 11 & 1) << 7;
+                imm | rs2.bits() << 20 | rs1.bits() << 15 | cond.funct() | BRANCH
+            }
+            Inst::Load {
+                width,
+                rd,
+                rs1,
+                offset,
+            } => i_type(LOAD, width.funct(), rd, rs1, offset.value()),
+            Inst::Store {
+                width,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let imm = offset.value() as u32;
+                let imm = (imm >> 5 & 0x7f) << 25 | (imm & 0x1f) << 7;
+                imm | rs2.bits() << 20 | rs1.bits() << 15 | width.funct() | STORE
+            }
+            Inst::OpImm { op, rd, rs1, imm } => i_type(OP_IMM, op.funct(), rd, rs1, imm.value()),
+            Inst::Shift { op, rd, rs1, shamt } => {
+                shamt.value() << 20 | rs1.bits() << 15 | op.funct() | rd.bits() << 7 | OP_IMM
+            }
+            Inst::Op { op, rd, rs1, rs2 } => {
+                rs2.bits() << 20 | rs1.bits() << 15 | op.funct() | rd.bits() << 7 | OP
+            }
+            // Predecessor and successor sets are both `iorw`
+            Inst::Fence => 0xff << 20 | MISC_MEM,
+            Inst::Ecall => SYSTEM,
+            Inst::Ebreak => 1 << 20 | SYSTEM,
+            Inst::Csr { op, rd, csr, rs1 } => {
+                csr.address() << 20 | rs1.bits() << 15 | op.funct() | rd.bits() << 7 | SYSTEM
+            }
+            Inst::CsrImm { op, rd, csr, uimm } => {
+                let funct = op.funct() | f3(4);
+                csr.address() << 20 | uimm.value() << 15 | funct | rd.bits() << 7 | SYSTEM
+            }
+        }
+    }
+}
+
+fn u_type(opcode: u32, rd: Reg, imm: UpperImm) -> u32 {
+    imm.value() << 12 | rd.bits() << 7 | opcode
+}
+
+fn i_type(opcode: u32, funct: u32, rd: Reg, rs1: Reg, imm: i32) -> u32 {
+    (imm as u32) << 20 | rs1.bits() << 15 | funct | rd.bits() << 7 | opcode
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Inst::Lui { rd, imm } => write!(f, "lui {}, {:#x}", rd, imm.value()),
+            Inst::Auipc { rd, imm } => write!(f, "auipc {}, {:#x}", rd, imm.value()),
+            Inst::Jal { rd, offset } => write!(f, "jal {}, {}", rd, offset.value()),
+            Inst::Jalr { rd, rs1, offset } => {
+                write!(f, "jalr {}, {}({})", rd, offset.value(), rs1)
+            }
+            Inst::Branch {
+                cond,
+                rs1,
+                rs2,
+                offset,
+            } => write!(f, "{} {}, {}, {}", cond.name(), rs1, rs2, offset.value()),
+            Inst::Load {
+                width,
+                rd,
+                rs1,
+                offset,
+            } => write!(f, "{} {}, {}({})", width.name(), rd, offset.value(), rs1),
+            Inst::Store {
+                width,
+                rs1,
+                rs2,
+                offset,
+            } => write!(f, "{} {}, {}({})", width.name(), rs2, offset.value(), rs1),
+            Inst::OpImm { op, rd, rs1, imm } => {
+                write!(f, "{} {}, {}, {}", op.name(), rd, rs1, imm.value())
+            }
+            Inst::Shift { op, rd, rs1, shamt } => {
+                write!(f, "{} {}, {}, {}", op.name(), rd, rs1, shamt.value())
+            }
+            Inst::Op { op, rd, rs1, rs2 } => write!(f, "{} {}, {}, {}", op.name(), rd, rs1, rs2),
+            Inst::Fence => f.write_str("fence iorw, iorw"),
+            Inst::Ecall => f.write_str("ecall"),
+            Inst::Ebreak => f.write_str("ebreak"),
+            Inst::Csr { op, rd, csr, rs1 } => {
+                write!(f, "{} {}, {}, {}", op.name(), rd, csr, rs1)
+            }
+            Inst::CsrImm { op, rd, csr, uimm } => {
+                write!(f, "{}i {}, {}, {}", op.name(), rd, csr, uimm.value())
+            }
+        }
+    }
+}
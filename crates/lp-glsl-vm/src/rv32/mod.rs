@@ -0,0 +1,161 @@
+//! Machine code for RV32IMC with Zicsr
+//!
+//! [`Inst`] is an instruction of RV32I, the M extension or the Zicsr
+//! extension. Its operands are typed: registers are [`Reg`]s and each
+//! immediate has a type checking on construction that the value fits the
+//! field it is encoded in, so every `Inst` has an encoding.
+//! [`Inst::encode`] gives the 32-bit word, and [`Inst::compress`] the
+//! 16-bit form from the C extension, for instructions having one.
+//!
+//! [`CodeBuffer`] collects encoded instructions. Branches and jumps may
+//! target [`Label`]s bound before or after them, and are patched when the
+//! buffer is finished.
+
+mod buffer;
+mod compress;
+mod inst;
+
+use core::fmt;
+
+pub use buffer::{CodeBuffer, Label};
+pub use inst::{
+    AluOp, BranchOffset, Cond, Csr, CsrOp, Imm12, ImmOp, Inst, JumpOffset, LoadWidth, ShiftOp,
+    StoreWidth, Uimm5, UpperImm,
+};
+
+/// Integer register `x0` to `x31`, named and printed by its ABI name
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Reg {
+    Zero,
+    Ra,
+    Sp,
+    Gp,
+    Tp,
+    T0,
+    T1,
+    T2,
+    S0,
+    S1,
+    A0,
+    A1,
+    A2,
+    A3,
+    A4,
+    A5,
+    A6,
+    A7,
+    S2,
+    S3,
+    S4,
+    S5,
+    S6,
+    S7,
+    S8,
+    S9,
+    S10,
+    S11,
+    T3,
+    T4,
+    T5,
+    T6,
+}
+
+impl Reg {
+    pub const ALL: [Reg; 32] = [
+        Reg::Zero,
+        Reg::Ra,
+        Reg::Sp,
+        Reg::Gp,
+        Reg::Tp,
+        Reg::T0,
+        Reg::T1,
+        Reg::T2,
+        Reg::S0,
+        Reg::S1,
+        Reg::A0,
+        Reg::A1,
+        Reg::A2,
+        Reg::A3,
+        Reg::A4,
+        Reg::A5,
+        Reg::A6,
+        Reg::A7,
+        Reg::S2,
+        Reg::S3,
+        Reg::S4,
+        Reg::S5,
+        Reg::S6,
+        Reg::S7,
+        Reg::S8,
+        Reg::S9,
+        Reg::S10,
+        Reg::S11,
+        Reg::T3,
+        Reg::T4,
+        Reg::T5,
+        Reg::T6,
+    ];
+
+    /// Register `x{number}`, if there is one
+    pub fn new(number: u8) -> Option<Reg> {
+        Reg::ALL.get(number as usize).copied()
+    }
+
+    pub const fn number(self) -> u8 {
+        self as u8
+    }
+
+    /// ABI name, such as `a0` for `x10`
+    pub fn name(self) -> &'static str {
+        const NAMES: [&str; 32] = [
+            "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3",
+            "a4", "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11",
+            "t3", "t4", "t5", "t6",
+        ];
+        NAMES[self as usize]
+    }
+
+    /// Field of the register in the 5-bit register fields
+    fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Field of the register in the 3-bit register fields of compressed
+    /// instructions, which only reach `s0`, `s1` and `a0` to `a5`
+    fn compact(self) -> Option<u16> {
+        let number = self.number();
+        (8..16).contains(&number).then(|| (number - 8) as u16)
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// An operand that cannot be encoded, or a label that was never bound
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The value does not fit the field of the immediate
+    OutOfRange { value: i64, min: i64, max: i64 },
+    /// The value is not a multiple of the alignment the field requires
+    Misaligned { value: i64, align: u32 },
+    /// A branch or jump targets a label that was never bound
+    Unbound(Label),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::OutOfRange { value, min, max } => {
+                write!(f, "immediate {} out of range {}..={}", value, min, max)
+            }
+            EncodeError::Misaligned { value, align } => {
+                write!(f, "immediate {} is not a multiple of {}", value, align)
+            }
+            EncodeError::Unbound(label) => write!(f, "label {} is never bound", label.0),
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! Builtin functions and their overloads
+//!
+//! Generic signatures from the GLSL specification (`genType`, `genIType`,
+//! ...) are expanded into one concrete [`Signature`] per component count.
+
+use alloc::{vec, vec::Vec};
+
+use super::types::{ScalarType, Type};
+
+macro_rules! builtins {
+    ($($variant:ident => $name:literal,)*) => {
+        /// Builtin function
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum Builtin {
+            $($variant,)*
+        }
+
+        impl Builtin {
+            pub const ALL: &'static [Builtin] = &[$(Builtin::$variant,)*];
+
+            /// GLSL name of the function
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Builtin::$variant => $name,)*
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<Builtin> {
+                match name {
+                    $($name => Some(Builtin::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+builtins! {
+    Radians => "radians",
+    Degrees => "degrees",
+    Sin => "sin",
+    Cos => "cos",
+    Tan => "tan",
+    Asin => "asin",
+    Acos => "acos",
+    Atan => "atan",
+    Sinh => "sinh",
+    Cosh => "cosh",
+    Tanh => "tanh",
+    Asinh => "asinh",
+    Acosh => "acosh",
+    Atanh => "atanh",
+    Pow => "pow",
+    Exp => "exp",
+    Log => "log",
+    Exp2 => "exp2",
+    Log2 => "log2",
+    Sqrt => "sqrt",
+    InverseSqrt => "inversesqrt",
+    Abs => "abs",
+    Sign => "sign",
+    Floor => "floor",
+    Trunc => "trunc",
+    Round => "round",
+    RoundEven => "roundEven",
+    Ceil => "ceil",
+    Fract => "fract",
+    Mod => "mod",
+    Min => "min",
+    Max => "max",
+    Clamp => "clamp",
+    Mix => "mix",
+    Step => "step",
+    Smoothstep => "smoothstep",
+    IsNan => "isnan",
+    IsInf => "isinf",
+    FloatBitsToInt => "floatBitsToInt",
+    FloatBitsToUint => "floatBitsToUint",
+    IntBitsToFloat => "intBitsToFloat",
+    UintBitsToFloat => "uintBitsToFloat",
+    Length => "length",
+    Distance => "distance",
+    Dot => "dot",
+    Cross => "cross",
+    Normalize => "normalize",
+    FaceForward => "faceforward",
+    Reflect => "reflect",
+    Refract => "refract",
+    MatrixCompMult => "matrixCompMult",
+    OuterProduct => "outerProduct",
+    Transpose => "transpose",
+    LessThan => "lessThan",
+    LessThanEqual => "lessThanEqual",
+    GreaterThan => "greaterThan",
+    GreaterThanEqual => "greaterThanEqual",
+    Equal => "equal",
+    NotEqual => "notEqual",
+    Any => "any",
+    All => "all",
+    Not => "not",
+    LpNoise2 => "lp_noise2",
+    LpNoise3 => "lp_noise3",
+    LpFbm => "lp_fbm",
+    LpHash => "lp_hash",
+}
+
+/// Extension that makes the `lp_*` noise and hash builtins available
+pub const NOISE_EXTENSION: &str = "GL_LP_noise";
+
+/// One concrete overload of a builtin
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub return_type: Type,
+    pub params: Vec<Type>,
+}
+
+impl Builtin {
+    /// Extension a shader must enable to call the builtin, if any
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Builtin::LpNoise2 | Builtin::LpNoise3 | Builtin::LpFbm | Builtin::LpHash => {
+                Some(NOISE_EXTENSION)
+            }
+            _ => None,
+        }
+    }
+
+    /// Every overload of the builtin
+    pub fn signatures(self) -> Vec<Signature> {
+        use Builtin as B;
+        use ScalarType::{Bool, Float, Int, UInt};
+
+        let mut out = Vec::new();
+        let mut add = |return_type: Type, params: Vec<Type>| {
+            out.push(Signature {
+                return_type,
+                params,
+            })
+        };
+        let float = Type::FLOAT;
+
+        match self {
+            B::Radians
+            | B::Degrees
+            | B::Sin
+            | B::Cos
+            | B::Tan
+            | B::Asin
+            | B::Acos
+            | B::Sinh
+            | B::Cosh
+            | B::Tanh
+            | B::Asinh
+            | B::Acosh
+            | B::Atanh
+            | B::Exp
+            | B::Log
+            | B::Exp2
+            | B::Log2
+            | B::Sqrt
+            | B::InverseSqrt
+            | B::Floor
+            | B::Trunc
+            | B::Round
+            | B::RoundEven
+            | B::Ceil
+            | B::Fract
+            | B::Normalize => {
+                for t in gen(Float) {
+                    add(t.clone(), vec![t]);
+                }
+            }
+            B::Atan => {
+                for t in gen(Float) {
+                    add(t.clone(), vec![t.clone()]);
+                    add(t.clone(), vec![t.clone(), t]);
+                }
+            }
+            B::Pow | B::Reflect => {
+                for t in gen(Float) {
+                    add(t.clone(), vec![t.clone(), t]);
+                }
+            }
+            B::Abs | B::Sign => {
+                for t in gen(Float).chain(gen(Int)) {
+                    add(t.clone(), vec![t]);
+                }
+            }
+            B::Mod => {
+                for t in gen(Float) {
+                    add(t.clone(), vec![t.clone(), t.clone()]);
+                    if t.is_vector() {
+                        add(t.clone(), vec![t, float.clone()]);
+                    }
+                }
+            }
+            B::Min | B::Max => {
+                for s in [Float, Int, UInt] {
+                    for t in gen(s) {
+                        add(t.clone(), vec![t.clone(), t.clone()]);
+                        if t.is_vector() {
+                            add(t.clone(), vec![t, Type::Scalar(s)]);
+                        }
+                    }
+                }
+            }
+            B::Clamp => {
+                for s in [Float, Int, UInt] {
+                    for t in gen(s) {
+                        add(t.clone(), vec![t.clone(), t.clone(), t.clone()]);
+                        if t.is_vector() {
+                            add(t.clone(), vec![t, Type::Scalar(s), Type::Scalar(s)]);
+                        }
+                    }
+                }
+            }
+            B::Mix => {
+                for t in gen(Float) {
+                    add(t.clone(), vec![t.clone(), t.clone(), t.clone()]);
+                    if t.is_vector() {
+                        add(t.clone(), vec![t.clone(), t.clone(), float.clone()]);
+                    }
+                    add(t.clone(), vec![t.clone(), t.clone(), same_size(&t, Bool)]);
+                }
+            }
+            B::Step => {
+                for t in gen(Float) {
+                    add(t.clone(), vec![t.clone(), t.clone()]);
+                    if t.is_vector() {
+                        add(t.clone(), vec![float.clone(), t]);
+                    }
+                }
+            }
+            B::Smoothstep => {
+                for t in gen(Float) {
+                    add(t.clone(), vec![t.clone(), t.clone(), t.clone()]);
+                    if t.is_vector() {
+                        add(t.clone(), vec![float.clone(), float.clone(), t]);
+                    }
+                }
+            }
+            B::IsNan | B::IsInf => {
+                for t in gen(Float) {
+                    add(same_size(&t, Bool), vec![t]);
+                }
+            }
+            B::FloatBitsToInt | B::FloatBitsToUint => {
+                let to = if self == B::FloatBitsToInt { Int } else { UInt };
+                for t in gen(Float) {
+                    add(same_size(&t, to), vec![t]);
+                }
+            }
+            B::IntBitsToFloat | B::UintBitsToFloat => {
+                let from = if self == B::IntBitsToFloat { Int } else { UInt };
+                for t in gen(Float) {
+                    add(t.clone(), vec![same_size(&t, from)]);
+                }
+            }
+            B::Length => {
+                for t in gen(Float) {
+                    add(float.clone(), vec![t]);
+                }
+            }
+            B::Distance | B::Dot => {
+                for t in gen(Float) {
+                    add(float.clone(), vec![t.clone(), t]);
+                }
+            }
+            B::Cross => {
+                let vec3 = Type::Vector(Float, 3);
+                add(vec3.clone(), vec![vec3.clone(), vec3]);
+            }
+            B::FaceForward => {
+                for t in gen(Float) {
+                    add(t.clone(), vec![t.clone(), t.clone(), t]);
+                }
+            }
+            B::Refract => {
+                for t in gen(Float) {
+                    add(t.clone(), vec![t.clone(), t, float.clone()]);
+                }
+            }
+            B::MatrixCompMult => {
+                for n in 2..=4 {
+                    add(Type::Matrix(n), vec![Type::Matrix(n), Type::Matrix(n)]);
+                }
+            }
+            B::OuterProduct => {
+                for n in 2..=4 {
+                    let column = Type::Vector(Float, n);
+                    add(Type::Matrix(n), vec![column.clone(), column]);
+                }
+            }
+            B::Transpose => {
+                for n in 2..=4 {
+                    add(Type::Matrix(n), vec![Type::Matrix(n)]);
+                }
+            }
+            B::LessThan | B::LessThanEqual | B::GreaterThan | B::GreaterThanEqual => {
+                for s in [Float, Int, UInt] {
+                    for n in 2..=4 {
+                        let t = Type::Vector(s, n);
+                        add(Type::Vector(Bool, n), vec![t.clone(), t]);
+                    }
+                }
+            }
+            B::Equal | B::NotEqual => {
+                for s in [Float, Int, UInt, Bool] {
+                    for n in 2..=4 {
+                        let t = Type::Vector(s, n);
+                        add(Type::Vector(Bool, n), vec![t.clone(), t]);
+                    }
+                }
+            }
+            B::Any | B::All => {
+                for n in 2..=4 {
+                    add(Type::BOOL, vec![Type::Vector(Bool, n)]);
+                }
+            }
+            B::Not => {
+                for n in 2..=4 {
+                    let t = Type::Vector(Bool, n);
+                    add(t.clone(), vec![t]);
+                }
+            }
+            B::LpNoise2 => add(float.clone(), vec![Type::Vector(Float, 2)]),
+            B::LpNoise3 => add(float.clone(), vec![Type::Vector(Float, 3)]),
+            B::LpFbm => {
+                for n in 2..=3 {
+                    add(float.clone(), vec![Type::Vector(Float, n), Type::INT]);
+                }
+            }
+            B::LpHash => {
+                for s in [UInt, Int] {
+                    for n in 1..=3 {
+                        add(Type::UINT, vec![Type::vector(s, n)]);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// The scalar and vector types with components of type `scalar`
+fn gen(scalar: ScalarType) -> impl Iterator<Item = Type> {
+    (1..=4).map(move |n| Type::vector(scalar, n))
+}
+
+/// Type with the shape of `ty` and components of type `scalar`
+fn same_size(ty: &Type, scalar: ScalarType) -> Type {
+    Type::vector(scalar, ty.component_count().unwrap_or(1) as u8)
+}
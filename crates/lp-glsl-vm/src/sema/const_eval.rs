@@ -0,0 +1,655 @@
+//! Evaluation of constant expressions
+//!
+//! GLSL requires constant expressions for array sizes, case labels and the
+//! initializers of `const` and plain global variables. [`ConstEval`] folds a
+//! typed expression built from literals, `const` variables, operators,
+//! constructors, swizzles, field and index selection and builtin function
+//! calls into a [`Value`]. Float builtins are evaluated with the same
+//! [`runtime::math`](crate::runtime::math) routines the compiled shader calls.
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+use super::{
+    builtins::Builtin,
+    hir::{BinaryOp, Expr, ExprKind, Literal, LocalId, Module, Storage, UnaryOp, VarRef},
+    types::{ScalarType, Type},
+};
+use crate::runtime::{math, noise, Routine};
+
+/// Value of a constant expression
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// Components of a scalar or vector
+    Components(Vec<Literal>),
+    /// Fields of a struct or elements of an array
+    Aggregate(Vec<Value>),
+}
+
+impl Value {
+    /// The value as an integer, if it is an `int` or `uint` scalar
+    pub fn as_int(&self) -> Option<i64> {
+        match self.as_scalar()? {
+            Literal::Int(v) => Some(*v as i64),
+            Literal::UInt(v) => Some(*v as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_scalar(&self) -> Option<&Literal> {
+        match self {
+            Value::Components(c) if c.len() == 1 => c.first(),
+            _ => None,
+        }
+    }
+
+    /// Build an expression of type `ty` holding this value
+    ///
+    /// Scalars become literals, everything else a constructor of literals.
+    pub fn to_expr(&self, ty: &Type) -> Expr {
+        match (self, ty) {
+            (Value::Components(c), Type::Scalar(_)) => {
+                Expr::new(ExprKind::Literal(c[0].clone()), ty.clone())
+            }
+            (Value::Components(c), _) => {
+                let args = c
+                    .iter()
+                    .map(|l| Expr::new(ExprKind::Literal(l.clone()), literal_type(l)))
+                    .collect();
+                Expr::new(ExprKind::Construct(args), ty.clone())
+            }
+            (Value::Aggregate(items), _) => {
+                let args = items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| {
+                        let item_ty = match ty {
+                            Type::Struct(st) => &st.fields[i].ty,
+                            Type::Array(element, _) => element,
+                            _ => unreachable!("aggregate value of type `{}`", ty),
+                        };
+                        item.to_expr(item_ty)
+                    })
+                    .collect();
+                Expr::new(ExprKind::Construct(args), ty.clone())
+            }
+        }
+    }
+}
+
+/// Evaluator for constant expressions of one module
+///
+/// Global `const` variables are read from their initializers in the module.
+/// Values of `const` locals are supplied with [`ConstEval::with_locals`].
+pub struct ConstEval<'a> {
+    module: &'a Module,
+    locals: Option<&'a BTreeMap<LocalId, Value>>,
+}
+
+impl<'a> ConstEval<'a> {
+    pub fn new(module: &'a Module) -> Self {
+        Self {
+            module,
+            locals: None,
+        }
+    }
+
+    pub fn with_locals(module: &'a Module, locals: &'a BTreeMap<LocalId, Value>) -> Self {
+        Self {
+            module,
+            locals: Some(locals),
+        }
+    }
+
+    /// Value of `expr`, or `None` if it is not a constant expression
+    ///
+    /// Operations whose result is undefined, such as integer division by
+    /// zero or out-of-range indexing, are not constant either.
+    pub fn eval(&self, expr: &Expr) -> Option<Value> {
+        match &expr.kind {
+            ExprKind::Literal(l) => Some(Value::Components(vec![l.clone()])),
+            ExprKind::Var(VarRef::Global(id)) => {
+                let global = self.module.global(*id);
+                match (global.storage, &global.init) {
+                    (Storage::Const, Some(init)) => self.eval(init),
+                    _ => None,
+                }
+            }
+            ExprKind::Var(VarRef::Local(id)) => self.locals?.get(id).cloned(),
+            ExprKind::Unary(op, operand) => {
+                let operand = components(self.eval(operand)?)?;
+                operand
+                    .into_iter()
+                    .map(|l| unary(*op, l))
+                    .collect::<Option<_>>()
+                    .map(Value::Components)
+            }
+            ExprKind::Binary(BinaryOp::Mul, lhs, rhs) if is_linear_product(&lhs.ty, &rhs.ty) => {
+                let a = floats(&components(self.eval(lhs)?)?)?;
+                let b = floats(&components(self.eval(rhs)?)?)?;
+                Some(Value::Components(float_literals(linear_product(
+                    &lhs.ty, &a, &rhs.ty, &b,
+                ))))
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                binary(*op, self.eval(lhs)?, self.eval(rhs)?, &expr.ty)
+            }
+            ExprKind::Ternary(cond, then, otherwise) => {
+                let cond = self.eval(cond)?;
+                let (then, otherwise) = (self.eval(then)?, self.eval(otherwise)?);
+                match cond.as_scalar()? {
+                    Literal::Bool(true) => Some(then),
+                    Literal::Bool(false) => Some(otherwise),
+                    _ => None,
+                }
+            }
+            ExprKind::Builtin(builtin, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg).and_then(components))
+                    .collect::<Option<Vec<_>>>()?;
+                builtin_call(*builtin, &args, &expr.ty).map(Value::Components)
+            }
+            ExprKind::Construct(args) => {
+                let values = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Option<Vec<_>>>()?;
+                match (&expr.ty, args.as_slice()) {
+                    (Type::Matrix(n), [arg]) if arg.ty.is_matrix() || arg.ty.is_scalar() => {
+                        let value = floats(&components(values.into_iter().next()?)?)?;
+                        Some(Value::Components(float_literals(resize_matrix(
+                            &arg.ty, &value, *n,
+                        ))))
+                    }
+                    _ => construct(values, &expr.ty),
+                }
+            }
+            ExprKind::Swizzle(base, indices) => {
+                let base = components(self.eval(base)?)?;
+                Some(Value::Components(
+                    indices.iter().map(|&i| base[i as usize].clone()).collect(),
+                ))
+            }
+            ExprKind::Field(base, index) => match self.eval(base)? {
+                Value::Aggregate(mut fields) => Some(fields.swap_remove(*index as usize)),
+                Value::Components(_) => None,
+            },
+            ExprKind::Index(base, index) => {
+                let index = usize::try_from(self.eval(index)?.as_int()?).ok()?;
+                if let Type::Matrix(n) = base.ty {
+                    let n = n as usize;
+                    let columns = components(self.eval(base)?)?;
+                    return columns
+                        .get(index * n..(index + 1) * n)
+                        .map(|column| Value::Components(column.to_vec()));
+                }
+                match self.eval(base)? {
+                    Value::Aggregate(items) => items.into_iter().nth(index),
+                    Value::Components(c) => {
+                        c.get(index).map(|l| Value::Components(vec![l.clone()]))
+                    }
+                }
+            }
+            // Function calls, assignments and the comma operator are never constant
+            ExprKind::Call(..) | ExprKind::Assign(..) | ExprKind::Comma(..) => None,
+        }
+    }
+
+    /// Replace `expr` with its value if it is constant
+    pub fn fold(&self, expr: Expr) -> Expr {
+        match self.eval(&expr) {
+            Some(value) => value.to_expr(&expr.ty),
+            None => expr,
+        }
+    }
+}
+
+fn components(value: Value) -> Option<Vec<Literal>> {
+    match value {
+        Value::Components(c) => Some(c),
+        Value::Aggregate(_) => None,
+    }
+}
+
+fn literal_type(l: &Literal) -> Type {
+    Type::Scalar(match l {
+        Literal::Bool(_) => ScalarType::Bool,
+        Literal::Int(_) => ScalarType::Int,
+        Literal::UInt(_) => ScalarType::UInt,
+        Literal::Float(_) => ScalarType::Float,
+    })
+}
+
+/// Convert a scalar as a constructor such as `int(2.5)` would
+fn convert(l: &Literal, to: ScalarType) -> Literal {
+    match (l, to) {
+        (Literal::Bool(v), ScalarType::Bool) => Literal::Bool(*v),
+        (Literal::Bool(v), ScalarType::Int) => Literal::Int(*v as i32),
+        (Literal::Bool(v), ScalarType::UInt) => Literal::UInt(*v as u32),
+        (Literal::Bool(v), ScalarType::Float) => Literal::Float(*v as u8 as f32),
+        (Literal::Int(v), ScalarType::Bool) => Literal::Bool(*v != 0),
+        (Literal::Int(v), ScalarType::Int) => Literal::Int(*v),
+        (Literal::Int(v), ScalarType::UInt) => Literal::UInt(*v as u32),
+        (Literal::Int(v), ScalarType::Float) => Literal::Float(*v as f32),
+        (Literal::UInt(v), ScalarType::Bool) => Literal::Bool(*v != 0),
+        (Literal::UInt(v), ScalarType::Int) => Literal::Int(*v as i32),
+        (Literal::UInt(v), ScalarType::UInt) => Literal::UInt(*v),
+        (Literal::UInt(v), ScalarType::Float) => Literal::Float(*v as f32),
+        (Literal::Float(v), ScalarType::Bool) => Literal::Bool(*v != 0.0),
+        (Literal::Float(v), ScalarType::Int) => Literal::Int(*v as i32),
+        (Literal::Float(v), ScalarType::UInt) => Literal::UInt(*v as u32),
+        (Literal::Float(v), ScalarType::Float) => Literal::Float(*v),
+    }
+}
+
+fn construct(args: Vec<Value>, ty: &Type) -> Option<Value> {
+    match ty {
+        Type::Struct(_) | Type::Array(..) => Some(Value::Aggregate(args)),
+        Type::Scalar(_) | Type::Vector(..) | Type::Matrix(_) => {
+            let s = ty.scalar_type()?;
+            let n = ty.component_count()? as usize;
+            let mut flat = Vec::new();
+            for arg in args {
+                flat.extend(components(arg)?);
+            }
+            // A single scalar fills every component
+            if flat.len() == 1 {
+                flat = vec![flat[0].clone(); n];
+            }
+            Some(Value::Components(
+                flat.iter().take(n).map(|l| convert(l, s)).collect(),
+            ))
+        }
+        Type::Void => None,
+    }
+}
+
+fn unary(op: UnaryOp, l: Literal) -> Option<Literal> {
+    Some(match (op, l) {
+        (UnaryOp::Neg, Literal::Int(v)) => Literal::Int(v.wrapping_neg()),
+        (UnaryOp::Neg, Literal::UInt(v)) => Literal::UInt(v.wrapping_neg()),
+        (UnaryOp::Neg, Literal::Float(v)) => Literal::Float(-v),
+        (UnaryOp::Not, Literal::Bool(v)) => Literal::Bool(!v),
+        (UnaryOp::BitNot, Literal::Int(v)) => Literal::Int(!v),
+        (UnaryOp::BitNot, Literal::UInt(v)) => Literal::UInt(!v),
+        // Increments and decrements modify their operand
+        _ => return None,
+    })
+}
+
+fn binary(op: BinaryOp, lhs: Value, rhs: Value, ty: &Type) -> Option<Value> {
+    use BinaryOp as B;
+
+    if matches!(op, B::Eq | B::Ne) {
+        return Some(Value::Components(vec![Literal::Bool(
+            (lhs == rhs) == (op == B::Eq),
+        )]));
+    }
+    let (lhs, rhs) = (components(lhs)?, components(rhs)?);
+    if op.is_comparison() {
+        return compare(op, &lhs[0], &rhs[0]).map(|b| Value::Components(vec![Literal::Bool(b)]));
+    }
+    let n = ty.component_count()? as usize;
+    (0..n)
+        .map(|i| {
+            let (a, b) = (broadcast(&lhs, i), broadcast(&rhs, i));
+            match op {
+                B::Add => arithmetic(a, b, i32::wrapping_add, u32::wrapping_add, |x, y| x + y),
+                B::Sub => arithmetic(a, b, i32::wrapping_sub, u32::wrapping_sub, |x, y| x - y),
+                B::Mul => arithmetic(a, b, i32::wrapping_mul, u32::wrapping_mul, |x, y| x * y),
+                // Integer division by zero is undefined
+                B::Div if matches!(b, Literal::Int(0) | Literal::UInt(0)) => None,
+                B::Div => arithmetic(a, b, i32::wrapping_div, u32::wrapping_div, |x, y| x / y),
+                B::Mod => match (a, b) {
+                    (Literal::Int(x), Literal::Int(y)) => x.checked_rem(*y).map(Literal::Int),
+                    (Literal::UInt(x), Literal::UInt(y)) => x.checked_rem(*y).map(Literal::UInt),
+                    _ => None,
+                },
+                B::And | B::Or | B::Xor => match (a, b) {
+                    (Literal::Bool(x), Literal::Bool(y)) => Some(Literal::Bool(match op {
+                        B::And => *x && *y,
+                        B::Or => *x || *y,
+                        _ => x != y,
+                    })),
+                    _ => None,
+                },
+                B::BitAnd | B::BitOr | B::BitXor => {
+                    let f = match op {
+                        B::BitAnd => |x, y| x & y,
+                        B::BitOr => |x, y| x | y,
+                        _ => |x, y| x ^ y,
+                    };
+                    match (a, b) {
+                        (Literal::Int(x), Literal::Int(y)) => {
+                            Some(Literal::Int(f(*x as u32, *y as u32) as i32))
+                        }
+                        (Literal::UInt(x), Literal::UInt(y)) => Some(Literal::UInt(f(*x, *y))),
+                        _ => None,
+                    }
+                }
+                B::Shl | B::Shr => {
+                    let amount = match b {
+                        Literal::Int(v) => u32::try_from(*v).ok()?,
+                        Literal::UInt(v) => *v,
+                        _ => return None,
+                    };
+                    // Shifting by the bit width or more is undefined
+                    match (a, op) {
+                        (Literal::Int(x), B::Shl) => x.checked_shl(amount).map(Literal::Int),
+                        (Literal::Int(x), _) => x.checked_shr(amount).map(Literal::Int),
+                        (Literal::UInt(x), B::Shl) => x.checked_shl(amount).map(Literal::UInt),
+                        (Literal::UInt(x), _) => x.checked_shr(amount).map(Literal::UInt),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            }
+        })
+        .collect::<Option<_>>()
+        .map(Value::Components)
+}
+
+/// Component `i` of an operand, repeating a scalar operand
+fn broadcast(c: &[Literal], i: usize) -> &Literal {
+    if c.len() == 1 {
+        &c[0]
+    } else {
+        &c[i]
+    }
+}
+
+/// Apply an arithmetic operator to two scalars of the same type
+fn arithmetic(
+    a: &Literal,
+    b: &Literal,
+    int: fn(i32, i32) -> i32,
+    uint: fn(u32, u32) -> u32,
+    float: fn(f32, f32) -> f32,
+) -> Option<Literal> {
+    match (a, b) {
+        (Literal::Int(x), Literal::Int(y)) => Some(Literal::Int(int(*x, *y))),
+        (Literal::UInt(x), Literal::UInt(y)) => Some(Literal::UInt(uint(*x, *y))),
+        (Literal::Float(x), Literal::Float(y)) => Some(Literal::Float(float(*x, *y))),
+        _ => None,
+    }
+}
+
+/// Apply a comparison operator other than `==` and `!=` to two scalars
+fn compare(op: BinaryOp, a: &Literal, b: &Literal) -> Option<bool> {
+    fn apply<T: PartialOrd>(op: BinaryOp, x: T, y: T) -> bool {
+        match op {
+            BinaryOp::Lt => x < y,
+            BinaryOp::Gt => x > y,
+            BinaryOp::Le => x <= y,
+            _ => x >= y,
+        }
+    }
+    match (a, b) {
+        (Literal::Int(x), Literal::Int(y)) => Some(apply(op, x, y)),
+        (Literal::UInt(x), Literal::UInt(y)) => Some(apply(op, x, y)),
+        (Literal::Float(x), Literal::Float(y)) => Some(apply(op, x, y)),
+        _ => None,
+    }
+}
+
+/// Whether `lhs * rhs` is a matrix product rather than component-wise
+fn is_linear_product(lhs: &Type, rhs: &Type) -> bool {
+    (lhs.is_matrix() || rhs.is_matrix()) && !lhs.is_scalar() && !rhs.is_scalar()
+}
+
+/// Product of column-major matrices, treating a vector on the left as a
+/// row and a vector on the right as a column
+fn linear_product(lhs_ty: &Type, lhs: &[f32], rhs_ty: &Type, rhs: &[f32]) -> Vec<f32> {
+    let inner = match (lhs_ty, rhs_ty) {
+        (Type::Matrix(n), _) | (_, Type::Matrix(n)) => *n as usize,
+        _ => unreachable!("product without a matrix"),
+    };
+    let rows = if lhs_ty.is_matrix() { inner } else { 1 };
+    let columns = rhs.len() / inner;
+    let mut out = Vec::with_capacity(rows * columns);
+    for j in 0..columns {
+        for i in 0..rows {
+            out.push(
+                (0..inner)
+                    .map(|k| lhs[k * rows + i] * rhs[j * inner + k])
+                    .sum(),
+            );
+        }
+    }
+    out
+}
+
+/// Matrix of size `n` built from a scalar or from a matrix of type `from`
+///
+/// A scalar fills the diagonal. A matrix is copied into the upper left
+/// corner, with the rest taken from the identity matrix.
+fn resize_matrix(from: &Type, value: &[f32], n: u8) -> Vec<f32> {
+    let n = n as usize;
+    let (source, k) = match from {
+        Type::Matrix(k) => (Some(value), *k as usize),
+        _ => (None, 0),
+    };
+    let mut out = Vec::with_capacity(n * n);
+    for j in 0..n {
+        for i in 0..n {
+            out.push(match source {
+                Some(m) if j < k && i < k => m[j * k + i],
+                None if i == j => value[0],
+                _ if i == j => 1.0,
+                _ => 0.0,
+            });
+        }
+    }
+    out
+}
+
+fn float_literals(values: impl IntoIterator<Item = f32>) -> Vec<Literal> {
+    values.into_iter().map(Literal::Float).collect()
+}
+
+fn float(l: &Literal) -> Option<f32> {
+    match l {
+        Literal::Float(v) => Some(*v),
+        _ => None,
+    }
+}
+
+fn floats(c: &[Literal]) -> Option<Vec<f32>> {
+    c.iter().map(float).collect()
+}
+
+/// Evaluate a builtin function call on constant arguments
+///
+/// The IR interpreter evaluates builtins with it too, so both agree.
+///
+/// Arguments have been checked against the builtin's signatures, so scalar
+/// arguments only appear where the signature repeats them across components.
+pub(crate) fn builtin_call(
+    builtin: Builtin,
+    args: &[Vec<Literal>],
+    ty: &Type,
+) -> Option<Vec<Literal>> {
+    use Builtin as B;
+
+    let n = ty.component_count()? as usize;
+    // Component-wise float function of the arguments
+    let map = |f: &dyn Fn(&[f32]) -> f32| -> Option<Vec<Literal>> {
+        (0..n)
+            .map(|i| {
+                let x = args
+                    .iter()
+                    .map(|arg| float(broadcast(arg, i)))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(Literal::Float(f(&x)))
+            })
+            .collect()
+    };
+    // Component-wise function of the arguments of any scalar type
+    let map_any = |f: &dyn Fn(&[&Literal]) -> Option<Literal>| -> Option<Vec<Literal>> {
+        (0..n)
+            .map(|i| f(&args.iter().map(|arg| broadcast(arg, i)).collect::<Vec<_>>()))
+            .collect()
+    };
+
+    if let Some(routine) = Routine::of(builtin, args.len()) {
+        return map(&|x| routine.call(x));
+    }
+    match builtin {
+        B::Abs => map_any(&|x| match x[0] {
+            Literal::Int(v) => Some(Literal::Int(v.wrapping_abs())),
+            Literal::Float(v) => Some(Literal::Float(math::abs(*v))),
+            _ => None,
+        }),
+        B::Sign => map_any(&|x| match x[0] {
+            Literal::Int(v) => Some(Literal::Int(v.signum())),
+            Literal::Float(v) => Some(Literal::Float(math::sign(*v))),
+            _ => None,
+        }),
+        B::Min | B::Max | B::Clamp => map_any(&|x| {
+            let min = |a: &Literal, b: &Literal| {
+                Some(if compare(BinaryOp::Lt, b, a)? {
+                    b.clone()
+                } else {
+                    a.clone()
+                })
+            };
+            let max = |a: &Literal, b: &Literal| {
+                Some(if compare(BinaryOp::Gt, b, a)? {
+                    b.clone()
+                } else {
+                    a.clone()
+                })
+            };
+            match builtin {
+                B::Min => min(x[0], x[1]),
+                B::Max => max(x[0], x[1]),
+                _ => min(&max(x[0], x[1])?, x[2]),
+            }
+        }),
+        B::Mix => map_any(&|x| match (x[0], x[1], x[2]) {
+            (a, b, Literal::Bool(select)) => Some(if *select { b.clone() } else { a.clone() }),
+            (a, b, t) => Some(Literal::Float(math::mix(float(a)?, float(b)?, float(t)?))),
+        }),
+        B::IsNan => map_any(&|x| Some(Literal::Bool(float(x[0])?.is_nan()))),
+        B::IsInf => map_any(&|x| Some(Literal::Bool(float(x[0])?.is_infinite()))),
+        B::FloatBitsToInt => map_any(&|x| Some(Literal::Int(float(x[0])?.to_bits() as i32))),
+        B::FloatBitsToUint => map_any(&|x| Some(Literal::UInt(float(x[0])?.to_bits()))),
+        B::IntBitsToFloat | B::UintBitsToFloat => map_any(&|x| match x[0] {
+            Literal::Int(v) => Some(Literal::Float(f32::from_bits(*v as u32))),
+            Literal::UInt(v) => Some(Literal::Float(f32::from_bits(*v))),
+            _ => None,
+        }),
+        B::Length => Some(vec![Literal::Float(math::length(&floats(&args[0])?))]),
+        B::Distance => {
+            let (a, b) = (floats(&args[0])?, floats(&args[1])?);
+            Some(vec![Literal::Float(math::distance(&a, &b))])
+        }
+        B::Dot => {
+            let (a, b) = (floats(&args[0])?, floats(&args[1])?);
+            Some(vec![Literal::Float(math::dot(&a, &b))])
+        }
+        B::Cross => {
+            let (a, b) = (floats(&args[0])?, floats(&args[1])?);
+            let c = math::cross(a.try_into().ok()?, b.try_into().ok()?);
+            Some(float_literals(c))
+        }
+        B::Normalize => {
+            let mut x = floats(&args[0])?;
+            math::normalize(&mut x);
+            Some(float_literals(x))
+        }
+        B::FaceForward => {
+            let (mut n_, i, nref) = (floats(&args[0])?, floats(&args[1])?, floats(&args[2])?);
+            math::face_forward(&mut n_, &i, &nref);
+            Some(float_literals(n_))
+        }
+        B::Reflect => {
+            let (mut i, n_) = (floats(&args[0])?, floats(&args[1])?);
+            math::reflect(&mut i, &n_);
+            Some(float_literals(i))
+        }
+        B::Refract => {
+            let (mut i, n_) = (floats(&args[0])?, floats(&args[1])?);
+            math::refract(&mut i, &n_, float(&args[2][0])?);
+            Some(float_literals(i))
+        }
+        B::MatrixCompMult => map(&|x| x[0] * x[1]),
+        B::OuterProduct => {
+            let (c, r) = (floats(&args[0])?, floats(&args[1])?);
+            Some(float_literals(
+                r.iter().flat_map(|y| c.iter().map(move |x| x * y)),
+            ))
+        }
+        B::Transpose => {
+            let m = floats(&args[0])?;
+            let Type::Matrix(size) = *ty else {
+                return None;
+            };
+            let size = size as usize;
+            Some(float_literals(
+                (0..m.len()).map(|k| m[(k % size) * size + k / size]),
+            ))
+        }
+        B::LessThan | B::LessThanEqual | B::GreaterThan | B::GreaterThanEqual => {
+            let op = match builtin {
+                B::LessThan => BinaryOp::Lt,
+                B::LessThanEqual => BinaryOp::Le,
+                B::GreaterThan => BinaryOp::Gt,
+                _ => BinaryOp::Ge,
+            };
+            map_any(&|x| compare(op, x[0], x[1]).map(Literal::Bool))
+        }
+        B::Equal => map_any(&|x| Some(Literal::Bool(x[0] == x[1]))),
+        B::NotEqual => map_any(&|x| Some(Literal::Bool(x[0] != x[1]))),
+        B::Any | B::All => {
+            let mut values = args[0].iter().map(|l| matches!(l, Literal::Bool(true)));
+            let result = if builtin == B::Any {
+                values.any(|v| v)
+            } else {
+                values.all(|v| v)
+            };
+            Some(vec![Literal::Bool(result)])
+        }
+        B::Not => map_any(&|x| match x[0] {
+            Literal::Bool(v) => Some(Literal::Bool(!v)),
+            _ => None,
+        }),
+        B::LpNoise2 => {
+            let p = floats(&args[0])?;
+            Some(vec![Literal::Float(noise::noise2(p[0], p[1]))])
+        }
+        B::LpNoise3 => {
+            let p = floats(&args[0])?;
+            Some(vec![Literal::Float(noise::noise3(p[0], p[1], p[2]))])
+        }
+        B::LpFbm => {
+            let p = floats(&args[0])?;
+            let Literal::Int(octaves) = args[1][0] else {
+                return None;
+            };
+            Some(vec![Literal::Float(match p[..] {
+                [x, y] => noise::fbm2(x, y, octaves),
+                [x, y, z] => noise::fbm3(x, y, z, octaves),
+                _ => return None,
+            })])
+        }
+        B::LpHash => {
+            let bits = args[0]
+                .iter()
+                .map(|l| match l {
+                    Literal::Int(v) => Some(*v as u32),
+                    Literal::UInt(v) => Some(*v),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some(vec![Literal::UInt(match bits[..] {
+                [x] => noise::hash(x),
+                [x, y] => noise::hash2(x, y),
+                [x, y, z] => noise::hash3(x, y, z),
+                _ => return None,
+            })])
+        }
+        // Handled by their runtime routine above
+        _ => None,
+    }
+}
@@ -0,0 +1,315 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use core::fmt;
+
+use super::{builtins::Builtin, types::Type, version::Feature, Version};
+
+/// Error found while analyzing a translation unit
+#[derive(Clone, Debug, PartialEq)]
+pub enum SemaError {
+    UndeclaredVariable(String),
+    UndeclaredFunction(String),
+    UnknownType(String),
+    UnsupportedType(String),
+    Redefinition(String),
+    /// Parameter qualified differently than in an earlier declaration of
+    /// the function, counting parameters from 0
+    QualifierMismatch {
+        name: String,
+        param: usize,
+    },
+    TypeMismatch {
+        expected: Type,
+        found: Type,
+        context: &'static str,
+    },
+    /// Operator applied to operand types it does not accept
+    InvalidOperands {
+        op: &'static str,
+        lhs: Type,
+        rhs: Option<Type>,
+    },
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    /// No function signature accepts the argument types
+    NoMatchingOverload {
+        name: String,
+        args: String,
+        /// Signatures that were considered
+        candidates: Vec<String>,
+    },
+    /// Several signatures accept the argument types and none is the best match
+    AmbiguousCall {
+        name: String,
+        args: String,
+        candidates: Vec<String>,
+    },
+    /// Constructor arguments do not provide the right number of components
+    ConstructorComponents {
+        ty: Type,
+        found: u32,
+    },
+    InvalidSwizzle {
+        field: String,
+        ty: Type,
+    },
+    IndexOutOfRange {
+        index: i64,
+        size: u32,
+    },
+    /// Array length that is not a positive constant integer
+    InvalidArraySize,
+    NotAnLValue(&'static str),
+    AssignToReadOnly(String),
+    MissingInitializer(String),
+    /// Initializer of a `const` or global variable that is not a constant expression
+    NonConstantInitializer(String),
+    /// Expression that must be constant, such as a case label
+    NotConstant(&'static str),
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    CaseOutsideSwitch,
+    /// Feature that the targeted GLSL version does not have
+    UnavailableFeature {
+        feature: Feature,
+        version: Version,
+    },
+    /// Entry point function declared with the wrong signature
+    EntryPointSignature(String),
+    /// Functions that call each other in a cycle, listed in call order
+    Recursion(Vec<String>),
+    /// `#extension` requiring an extension the compiler does not implement
+    UnsupportedExtension(String),
+    /// Anything the analyzer cannot handle yet, with a description
+    Unsupported(String),
+}
+
+impl fmt::Display for SemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SemaError::UndeclaredVariable(name) => {
+                write!(f, "use of undeclared variable `{}`", name)
+            }
+            SemaError::UndeclaredFunction(name) => {
+                write!(f, "call to undeclared function `{}`", name)
+            }
+            SemaError::UnknownType(name) => write!(f, "unknown type `{}`", name),
+            SemaError::UnsupportedType(name) => write!(f, "type `{}` is not supported", name),
+            SemaError::Redefinition(name) => write!(f, "redefinition of `{}`", name),
+            SemaError::QualifierMismatch { name, param } => write!(
+                f,
+                "parameter {} of `{}` is qualified differently than in its earlier declaration",
+                param + 1,
+                name
+            ),
+            SemaError::TypeMismatch {
+                expected,
+                found,
+                context,
+            } => write!(
+                f,
+                "type mismatch in {}: expected `{}`, found `{}`",
+                context, expected, found
+            ),
+            SemaError::InvalidOperands { op, lhs, rhs: None } => {
+                write!(f, "operator `{}` cannot be applied to `{}`", op, lhs)
+            }
+            SemaError::InvalidOperands {
+                op,
+                lhs,
+                rhs: Some(rhs),
+            } => write!(
+                f,
+                "operator `{}` cannot be applied to `{}` and `{}`",
+                op, lhs, rhs
+            ),
+            SemaError::ArityMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` takes {} argument{} but {} were supplied",
+                name,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found
+            ),
+            SemaError::NoMatchingOverload { name, args, .. } => {
+                write!(f, "no matching overload for `{}({})`", name, args)
+            }
+            SemaError::AmbiguousCall { name, args, .. } => {
+                write!(f, "call to `{}({})` is ambiguous", name, args)
+            }
+            SemaError::ConstructorComponents { ty, found } => write!(
+                f,
+                "constructor for `{}` needs {} components, found {}",
+                ty,
+                ty.component_count().unwrap_or(0),
+                found
+            ),
+            SemaError::InvalidSwizzle { field, ty } => {
+                write!(f, "invalid field selection `.{}` on `{}`", field, ty)
+            }
+            SemaError::IndexOutOfRange { index, size } => {
+                write!(f, "index {} is out of range for size {}", index, size)
+            }
+            SemaError::InvalidArraySize => {
+                f.write_str("array size must be a positive constant integer")
+            }
+            SemaError::NotAnLValue(what) => write!(f, "{} is not assignable", what),
+            SemaError::AssignToReadOnly(name) => write!(f, "cannot assign to read-only `{}`", name),
+            SemaError::MissingInitializer(name) => {
+                write!(f, "const variable `{}` requires an initializer", name)
+            }
+            SemaError::NonConstantInitializer(name) => {
+                write!(f, "initializer of `{}` is not a constant expression", name)
+            }
+            SemaError::NotConstant(what) => write!(f, "{} must be a constant expression", what),
+            SemaError::BreakOutsideLoop => f.write_str("`break` outside of a loop or switch"),
+            SemaError::ContinueOutsideLoop => f.write_str("`continue` outside of a loop"),
+            SemaError::CaseOutsideSwitch => f.write_str("case label outside of a switch"),
+            SemaError::UnavailableFeature { feature, version } => {
+                let es = if version.es { "ES " } else { "" };
+                write!(
+                    f,
+                    "{} is not available in GLSL {}{}",
+                    feature, es, version.number
+                )
+            }
+            SemaError::EntryPointSignature(name) => {
+                write!(f, "entry point `{}` has the wrong signature", name)
+            }
+            SemaError::Recursion(cycle) => write!(f, "function `{}` is recursive", cycle[0]),
+            SemaError::UnsupportedExtension(name) => {
+                write!(f, "extension `{}` is not supported", name)
+            }
+            SemaError::Unsupported(what) => write!(f, "{} is not supported", what),
+        }
+    }
+}
+
+impl SemaError {
+    /// Source token the error is about, used to point at it in diagnostics
+    pub fn subject(&self) -> Option<&str> {
+        match self {
+            SemaError::UndeclaredVariable(name)
+            | SemaError::UndeclaredFunction(name)
+            | SemaError::UnknownType(name)
+            | SemaError::Redefinition(name)
+            | SemaError::AssignToReadOnly(name)
+            | SemaError::MissingInitializer(name)
+            | SemaError::NonConstantInitializer(name)
+            | SemaError::UnsupportedExtension(name)
+            | SemaError::EntryPointSignature(name)
+            | SemaError::ArityMismatch { name, .. }
+            | SemaError::QualifierMismatch { name, .. }
+            | SemaError::NoMatchingOverload { name, .. }
+            | SemaError::AmbiguousCall { name, .. } => Some(name),
+            SemaError::InvalidSwizzle { field, .. } => Some(field),
+            SemaError::UnavailableFeature { feature, .. } => match feature {
+                Feature::IntegerOperator(op) => Some(op),
+                Feature::Switch => Some("switch"),
+                Feature::WhileLoop => Some("while"),
+                Feature::GeneralForLoop => Some("for"),
+                Feature::UnsignedIntegers => None,
+            },
+            SemaError::BreakOutsideLoop => Some("break"),
+            SemaError::ContinueOutsideLoop => Some("continue"),
+            SemaError::CaseOutsideSwitch => Some("case"),
+            _ => None,
+        }
+    }
+
+    /// Short message shown under the offending source
+    pub fn label(&self) -> String {
+        match self {
+            SemaError::UndeclaredVariable(_) => "not found in this scope".into(),
+            SemaError::UndeclaredFunction(_) => "no function with this name".into(),
+            SemaError::TypeMismatch {
+                expected, found, ..
+            } => format!("expected `{}`, found `{}`", expected, found),
+            SemaError::ArityMismatch { expected, .. } => format!(
+                "expected {} argument{}",
+                expected,
+                if *expected == 1 { "" } else { "s" }
+            ),
+            SemaError::AssignToReadOnly(_) => "cannot be assigned".into(),
+            SemaError::Redefinition(_) => "already defined".into(),
+            SemaError::QualifierMismatch { .. } => "qualifiers differ from the declaration".into(),
+            SemaError::UnsupportedExtension(_) => "unknown extension".into(),
+            SemaError::EntryPointSignature(_) => "declared here".into(),
+            SemaError::Recursion(cycle) if cycle.len() == 1 => "calls itself".into(),
+            SemaError::Recursion(_) => "part of a call cycle".into(),
+            SemaError::UnavailableFeature { version, .. } => {
+                format!("not available in version {}", version)
+            }
+            SemaError::NonConstantInitializer(_) => "not a constant expression".into(),
+            SemaError::NoMatchingOverload { .. } => "no overload accepts these arguments".into(),
+            SemaError::AmbiguousCall { .. } => "matches more than one overload".into(),
+            _ => String::new(),
+        }
+    }
+
+    /// Extra lines shown after the snippet, such as overload candidates
+    pub fn notes(&self) -> Vec<String> {
+        /// Candidates listed before the rest are summarized
+        const MAX_CANDIDATES: usize = 8;
+
+        if let SemaError::UnavailableFeature { feature, .. } = self {
+            let mut notes = vec![feature.requirement()];
+            if *feature == Feature::GeneralForLoop {
+                notes.push(
+                    "GLSL ES 1.00 loops must initialize one `int` or `float` index to a constant, \
+                     compare it with a constant, step it by a constant and leave it unmodified in \
+                     the body"
+                        .into(),
+                );
+            }
+            return notes;
+        }
+        if let SemaError::EntryPointSignature(_) = self {
+            return vec!["expected `void mainImage(out vec4 fragColor, in vec2 fragCoord)`".into()];
+        }
+        if let SemaError::Recursion(cycle) = self {
+            let mut path = String::new();
+            for name in cycle.iter().chain(&cycle[..1]) {
+                if !path.is_empty() {
+                    path.push_str(" -> ");
+                }
+                path.push_str(name);
+            }
+            return vec![
+                format!("call cycle: {}", path),
+                "GLSL does not allow recursion".into(),
+            ];
+        }
+        if let SemaError::UndeclaredFunction(name) = self {
+            return Builtin::from_name(name)
+                .and_then(Builtin::extension)
+                .map(|extension| format!("enable it with `#extension {} : enable`", extension))
+                .into_iter()
+                .collect();
+        }
+
+        let (SemaError::NoMatchingOverload { candidates, .. }
+        | SemaError::AmbiguousCall { candidates, .. }) = self
+        else {
+            return Vec::new();
+        };
+        let mut notes: Vec<String> = candidates
+            .iter()
+            .take(MAX_CANDIDATES)
+            .map(|c| format!("candidate: {}", c))
+            .collect();
+        if candidates.len() > MAX_CANDIDATES {
+            notes.push(format!(
+                "and {} more candidates",
+                candidates.len() - MAX_CANDIDATES
+            ));
+        }
+        notes
+    }
+}
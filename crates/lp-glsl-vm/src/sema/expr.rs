@@ -0,0 +1,876 @@
+use alloc::{boxed::Box, format, string::String, vec, vec::Vec};
+
+use glsl::syntax::{self, ArraySpecifierDimension, AssignmentOp, FunIdentifier};
+
+use super::{
+    builtins::Builtin,
+    hir::{BinaryOp, Expr, ExprKind, FunctionId, Literal, ParamQualifier, UnaryOp, VarRef},
+    Analyzer, Feature, Result, ScalarType, SemaError, Type,
+};
+
+/// Function or builtin a call may resolve to
+#[derive(Clone, Copy)]
+enum Callee {
+    Function(FunctionId),
+    Builtin(Builtin),
+}
+
+/// One overload considered during call resolution
+struct Candidate {
+    callee: Callee,
+    return_type: Type,
+    params: Vec<(Type, ParamQualifier)>,
+}
+
+impl Candidate {
+    /// Signature as written in GLSL, for diagnostics
+    fn describe(&self, name: &str) -> String {
+        let params = self
+            .params
+            .iter()
+            .map(|(ty, qualifier)| match qualifier {
+                ParamQualifier::In => format!("{}", ty),
+                ParamQualifier::Out => format!("out {}", ty),
+                ParamQualifier::InOut => format!("inout {}", ty),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{} {}({})", self.return_type, name, params)
+    }
+}
+
+impl Analyzer {
+    /// Analyze an expression and infer its type
+    pub(super) fn expr(&mut self, expr: &syntax::Expr) -> Result<Expr> {
+        match expr {
+            syntax::Expr::Variable(ident) => self.variable(&ident.0),
+            syntax::Expr::IntConst(v) => {
+                Ok(Expr::new(ExprKind::Literal(Literal::Int(*v)), Type::INT))
+            }
+            syntax::Expr::UIntConst(v) => {
+                self.require(Feature::UnsignedIntegers)?;
+                Ok(Expr::new(ExprKind::Literal(Literal::UInt(*v)), Type::UINT))
+            }
+            syntax::Expr::BoolConst(v) => {
+                Ok(Expr::new(ExprKind::Literal(Literal::Bool(*v)), Type::BOOL))
+            }
+            syntax::Expr::FloatConst(v) => Ok(Expr::new(
+                ExprKind::Literal(Literal::Float(*v)),
+                Type::FLOAT,
+            )),
+            syntax::Expr::DoubleConst(_) => Err(SemaError::UnsupportedType("double".into())),
+            syntax::Expr::Unary(op, operand) => self.unary(op, operand),
+            syntax::Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+                self.binary(binary_op(op), lhs, rhs)
+            }
+            syntax::Expr::Ternary(cond, a, b) => {
+                let cond = self.expr(cond)?;
+                let cond = self.coerce(cond, &Type::BOOL, "ternary condition")?;
+                let a = self.expr(a)?;
+                let b = self.expr(b)?;
+                let (a, b) = self.unify(a, b);
+                let b = self.coerce(b, &a.ty, "ternary branches")?;
+                let ty = a.ty.clone();
+                Ok(Expr::new(
+                    ExprKind::Ternary(Box::new(cond), Box::new(a), Box::new(b)),
+                    ty,
+                ))
+            }
+            syntax::Expr::Assignment(lhs, op, rhs) => {
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+                self.assignment(assignment_op(op), lhs, rhs)
+            }
+            syntax::Expr::Bracket(base, spec) => {
+                let base = self.expr(base)?;
+                let index = match spec.dimensions.0.as_slice() {
+                    [ArraySpecifierDimension::ExplicitlySized(index)] => self.expr(index)?,
+                    _ => return Err(SemaError::Unsupported("multi-dimensional indexing".into())),
+                };
+                self.index(base, index)
+            }
+            syntax::Expr::FunCall(fun, args) => {
+                let args = args
+                    .iter()
+                    .map(|a| self.expr(a))
+                    .collect::<Result<Vec<_>>>()?;
+                match fun {
+                    FunIdentifier::Identifier(name) => self.call(&name.0, args),
+                    FunIdentifier::Expr(callee) => match &**callee {
+                        // Array constructor such as `float[3](...)`
+                        syntax::Expr::Bracket(element, spec) => {
+                            let syntax::Expr::Variable(name) = &**element else {
+                                return Err(SemaError::Unsupported(
+                                    "call through expression".into(),
+                                ));
+                            };
+                            let element = self
+                                .type_by_name(&name.0)
+                                .ok_or_else(|| SemaError::UnknownType(name.0.clone()))?;
+                            let ty = self.array_type(element, spec)?;
+                            self.require_type(&ty)?;
+                            self.construct_array(ty, args)
+                        }
+                        syntax::Expr::Dot(base, method) if method.0 == "length" => {
+                            let base = self.expr(base)?;
+                            self.length(base, args)
+                        }
+                        _ => Err(SemaError::Unsupported("call through expression".into())),
+                    },
+                }
+            }
+            syntax::Expr::Dot(base, field) => {
+                let base = self.expr(base)?;
+                self.field(base, &field.0)
+            }
+            syntax::Expr::PostInc(operand) => self.inc_dec(UnaryOp::PostInc, operand),
+            syntax::Expr::PostDec(operand) => self.inc_dec(UnaryOp::PostDec, operand),
+            syntax::Expr::Comma(a, b) => {
+                let a = self.expr(a)?;
+                let b = self.expr(b)?;
+                let ty = b.ty.clone();
+                Ok(Expr::new(ExprKind::Comma(Box::new(a), Box::new(b)), ty))
+            }
+        }
+    }
+
+    /// Check that `expr` has type `ty`, converting it implicitly if the version allows
+    pub(super) fn coerce(&mut self, expr: Expr, ty: &Type, context: &'static str) -> Result<Expr> {
+        if &expr.ty == ty {
+            Ok(expr)
+        } else if self.version.converts_type(&expr.ty, ty) {
+            Ok(convert(expr, ty))
+        } else {
+            Err(SemaError::TypeMismatch {
+                expected: ty.clone(),
+                found: expr.ty,
+                context,
+            })
+        }
+    }
+
+    fn variable(&mut self, name: &str) -> Result<Expr> {
+        let var = self
+            .scopes
+            .lookup(name)
+            .or_else(|| self.shadertoy_uniform(name))
+            .ok_or_else(|| SemaError::UndeclaredVariable(name.into()))?;
+        let ty = self.var_type(var);
+        Ok(Expr::new(ExprKind::Var(var), ty))
+    }
+
+    fn var_type(&self, var: VarRef) -> Type {
+        match var {
+            VarRef::Local(id) => {
+                let state = self.current.as_ref().expect("local outside of function");
+                state.locals[id.0 as usize].ty.clone()
+            }
+            VarRef::Global(id) => self.module.global(id).ty.clone(),
+        }
+    }
+
+    fn unary(&mut self, op: &syntax::UnaryOp, operand: &syntax::Expr) -> Result<Expr> {
+        let (op, name) = match op {
+            syntax::UnaryOp::Inc => return self.inc_dec(UnaryOp::PreInc, operand),
+            syntax::UnaryOp::Dec => return self.inc_dec(UnaryOp::PreDec, operand),
+            syntax::UnaryOp::Add => {
+                let operand = self.expr(operand)?;
+                return match operand.ty.scalar_type() {
+                    Some(s) if s.is_numeric() => Ok(operand),
+                    _ => Err(invalid_unary("+", operand.ty)),
+                };
+            }
+            syntax::UnaryOp::Minus => (UnaryOp::Neg, "-"),
+            syntax::UnaryOp::Not => (UnaryOp::Not, "!"),
+            syntax::UnaryOp::Complement => {
+                self.require(Feature::IntegerOperator("~"))?;
+                (UnaryOp::BitNot, "~")
+            }
+        };
+        let operand = self.expr(operand)?;
+        let valid = match (op, operand.ty.scalar_type()) {
+            (UnaryOp::Neg, Some(s)) => s.is_numeric(),
+            (UnaryOp::Not, _) => operand.ty == Type::BOOL,
+            (UnaryOp::BitNot, Some(s)) => s.is_integer(),
+            _ => false,
+        };
+        if !valid {
+            return Err(invalid_unary(name, operand.ty));
+        }
+        let ty = operand.ty.clone();
+        Ok(Expr::new(ExprKind::Unary(op, Box::new(operand)), ty))
+    }
+
+    fn inc_dec(&mut self, op: UnaryOp, operand: &syntax::Expr) -> Result<Expr> {
+        let operand = self.expr(operand)?;
+        match operand.ty.scalar_type() {
+            Some(s) if s.is_numeric() => {}
+            _ => {
+                let name = if matches!(op, UnaryOp::PreInc | UnaryOp::PostInc) {
+                    "++"
+                } else {
+                    "--"
+                };
+                return Err(invalid_unary(name, operand.ty));
+            }
+        }
+        self.check_lvalue(&operand)?;
+        let ty = operand.ty.clone();
+        Ok(Expr::new(ExprKind::Unary(op, Box::new(operand)), ty))
+    }
+
+    /// Implicitly convert one operand so both have the same component type
+    ///
+    /// Operands are returned unchanged when neither converts to the other.
+    fn unify(&self, lhs: Expr, rhs: Expr) -> (Expr, Expr) {
+        let (Some(ls), Some(rs)) = (lhs.ty.scalar_type(), rhs.ty.scalar_type()) else {
+            return (lhs, rhs);
+        };
+        if ls == rs {
+            (lhs, rhs)
+        } else if self.version.converts(ls, rs) {
+            let ty = lhs.ty.with_scalar(rs);
+            (convert(lhs, &ty), rhs)
+        } else if self.version.converts(rs, ls) {
+            let ty = rhs.ty.with_scalar(ls);
+            (lhs, convert(rhs, &ty))
+        } else {
+            (lhs, rhs)
+        }
+    }
+
+    pub(super) fn binary(&mut self, op: BinaryOp, lhs: Expr, rhs: Expr) -> Result<Expr> {
+        self.require_operator(op)?;
+        // Shift operands keep their own types
+        let (lhs, rhs) = match op {
+            BinaryOp::Shl | BinaryOp::Shr => (lhs, rhs),
+            _ => self.unify(lhs, rhs),
+        };
+        let ty =
+            binary_result_type(op, &lhs.ty, &rhs.ty).ok_or_else(|| SemaError::InvalidOperands {
+                op: binary_op_name(op),
+                lhs: lhs.ty.clone(),
+                rhs: Some(rhs.ty.clone()),
+            })?;
+        Ok(Expr::new(
+            ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+            ty,
+        ))
+    }
+
+    fn assignment(&mut self, op: Option<BinaryOp>, lhs: Expr, rhs: Expr) -> Result<Expr> {
+        if let Some(op) = op {
+            self.require_operator(op)?;
+        }
+        self.check_lvalue(&lhs)?;
+        let rhs = match op {
+            None => self.coerce(rhs, &lhs.ty, "assignment")?,
+            Some(op) => {
+                // Only the right-hand side may be converted
+                let rhs = match (lhs.ty.scalar_type(), rhs.ty.scalar_type()) {
+                    (Some(ls), Some(rs))
+                        if ls != rs
+                            && self.version.converts(rs, ls)
+                            && !matches!(op, BinaryOp::Shl | BinaryOp::Shr) =>
+                    {
+                        let ty = rhs.ty.with_scalar(ls);
+                        convert(rhs, &ty)
+                    }
+                    _ => rhs,
+                };
+                let result = binary_result_type(op, &lhs.ty, &rhs.ty);
+                if result.as_ref() != Some(&lhs.ty) {
+                    return Err(SemaError::InvalidOperands {
+                        op: binary_op_name(op),
+                        lhs: lhs.ty,
+                        rhs: Some(rhs.ty),
+                    });
+                }
+                rhs
+            }
+        };
+        let ty = lhs.ty.clone();
+        Ok(Expr::new(
+            ExprKind::Assign(op, Box::new(lhs), Box::new(rhs)),
+            ty,
+        ))
+    }
+
+    /// Check that the targeted version provides the operator `op`
+    fn require_operator(&self, op: BinaryOp) -> Result<()> {
+        match op {
+            BinaryOp::Mod
+            | BinaryOp::BitAnd
+            | BinaryOp::BitOr
+            | BinaryOp::BitXor
+            | BinaryOp::Shl
+            | BinaryOp::Shr => self.require(Feature::IntegerOperator(binary_op_name(op))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that an expression can be assigned to
+    pub(super) fn check_lvalue(&self, expr: &Expr) -> Result<()> {
+        match &expr.kind {
+            ExprKind::Var(VarRef::Local(id)) => {
+                let local = &self
+                    .current
+                    .as_ref()
+                    .expect("local outside of function")
+                    .locals[id.0 as usize];
+                if local.is_const {
+                    return Err(SemaError::AssignToReadOnly(local.name.clone()));
+                }
+                Ok(())
+            }
+            ExprKind::Var(VarRef::Global(id)) => {
+                let global = self.module.global(*id);
+                if !global.writable {
+                    return Err(SemaError::AssignToReadOnly(global.name.clone()));
+                }
+                Ok(())
+            }
+            ExprKind::Index(base, _) | ExprKind::Field(base, _) => self.check_lvalue(base),
+            ExprKind::Swizzle(base, components) => {
+                // A write mask may select each component at most once
+                if components
+                    .iter()
+                    .enumerate()
+                    .any(|(i, c)| components[..i].contains(c))
+                {
+                    return Err(SemaError::NotAnLValue("swizzle with repeated components"));
+                }
+                self.check_lvalue(base)
+            }
+            _ => Err(SemaError::NotAnLValue("expression")),
+        }
+    }
+
+    fn index(&mut self, base: Expr, index: Expr) -> Result<Expr> {
+        if !matches!(index.ty, Type::Scalar(s) if s.is_integer()) {
+            return Err(SemaError::TypeMismatch {
+                expected: Type::INT,
+                found: index.ty,
+                context: "index",
+            });
+        }
+        let (element, size) = match &base.ty {
+            Type::Vector(s, n) => (Type::Scalar(*s), *n as u32),
+            Type::Matrix(n) => (Type::Vector(ScalarType::Float, *n), *n as u32),
+            Type::Array(element, n) => ((**element).clone(), *n),
+            other => {
+                return Err(SemaError::InvalidOperands {
+                    op: "[]",
+                    lhs: other.clone(),
+                    rhs: None,
+                })
+            }
+        };
+        if let Some(i) = self.constant(&index).and_then(|value| value.as_int()) {
+            if i < 0 || i >= size as i64 {
+                return Err(SemaError::IndexOutOfRange { index: i, size });
+            }
+        }
+        Ok(Expr::new(
+            ExprKind::Index(Box::new(base), Box::new(index)),
+            element,
+        ))
+    }
+
+    fn field(&mut self, base: Expr, field: &str) -> Result<Expr> {
+        let invalid = || SemaError::InvalidSwizzle {
+            field: field.into(),
+            ty: base.ty.clone(),
+        };
+        let (scalar, size) = match &base.ty {
+            Type::Vector(s, n) => (*s, *n),
+            Type::Struct(st) => {
+                let (index, ty) = st.field(field).ok_or_else(invalid)?;
+                let ty = ty.clone();
+                return Ok(Expr::new(ExprKind::Field(Box::new(base), index), ty));
+            }
+            _ => return Err(invalid()),
+        };
+        let components = parse_swizzle(field).ok_or_else(invalid)?;
+        if components.iter().any(|c| *c >= size) {
+            return Err(invalid());
+        }
+        let ty = Type::vector(scalar, components.len() as u8);
+        // Chained swizzles such as `v.zyx.xy` select from the innermost base
+        if let ExprKind::Swizzle(inner, selected) = base.kind {
+            let components = components.iter().map(|&c| selected[c as usize]).collect();
+            return Ok(Expr::new(ExprKind::Swizzle(inner, components), ty));
+        }
+        Ok(Expr::new(ExprKind::Swizzle(Box::new(base), components), ty))
+    }
+
+    fn call(&mut self, name: &str, args: Vec<Expr>) -> Result<Expr> {
+        match self.type_by_name(name) {
+            Some(Type::Struct(st)) => return self.construct_struct(Type::Struct(st), args),
+            Some(ty) => {
+                self.require_type(&ty)?;
+                return self.construct(ty, args);
+            }
+            None => {}
+        }
+        let candidates = self.candidates(name)?;
+        let chosen = &candidates[self.resolve_overload(name, &candidates, &args)?];
+        for ((_, qualifier), arg) in chosen.params.iter().zip(&args) {
+            if *qualifier != ParamQualifier::In {
+                self.check_lvalue(arg)?;
+            }
+        }
+        let args = args
+            .into_iter()
+            .zip(&chosen.params)
+            .map(
+                |(arg, (ty, _))| {
+                    if arg.ty == *ty {
+                        arg
+                    } else {
+                        convert(arg, ty)
+                    }
+                },
+            )
+            .collect();
+        let kind = match chosen.callee {
+            Callee::Function(id) => ExprKind::Call(id, args),
+            Callee::Builtin(builtin) => ExprKind::Builtin(builtin, args),
+        };
+        Ok(Expr::new(kind, chosen.return_type.clone()))
+    }
+
+    /// Every overload visible under `name`
+    fn candidates(&self, name: &str) -> Result<Vec<Candidate>> {
+        if let Some(builtin) = self.builtin(name) {
+            return Ok(builtin
+                .signatures()
+                .into_iter()
+                .map(|sig| Candidate {
+                    callee: Callee::Builtin(builtin),
+                    return_type: sig.return_type,
+                    params: sig
+                        .params
+                        .into_iter()
+                        .map(|ty| (ty, ParamQualifier::In))
+                        .collect(),
+                })
+                .collect());
+        }
+        let ids = self
+            .functions
+            .get(name)
+            .ok_or_else(|| SemaError::UndeclaredFunction(name.into()))?;
+        Ok(ids
+            .iter()
+            .map(|id| {
+                let f = self.module.function(*id);
+                Candidate {
+                    callee: Callee::Function(*id),
+                    return_type: f.return_type.clone(),
+                    params: f
+                        .params
+                        .iter()
+                        .map(|p| (p.ty.clone(), p.qualifier))
+                        .collect(),
+                }
+            })
+            .collect())
+    }
+
+    /// Pick the overload that best matches the argument types
+    ///
+    /// Follows the GLSL 4.60 rules: a candidate is viable if every argument
+    /// matches exactly or converts implicitly, and the best viable candidate
+    /// needs no conversion where another does and is never worse.
+    fn resolve_overload(
+        &self,
+        name: &str,
+        candidates: &[Candidate],
+        args: &[Expr],
+    ) -> Result<usize> {
+        let viable: Vec<(usize, Vec<u8>)> = candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| Some((i, self.conversion_costs(c, args)?)))
+            .collect();
+        let best = viable.iter().find(|(i, costs)| {
+            viable
+                .iter()
+                .all(|(j, other)| i == j || is_better_match(costs, other))
+        });
+        if let Some((i, _)) = best {
+            return Ok(*i);
+        }
+
+        if viable.is_empty() {
+            if let [only] = candidates {
+                if only.params.len() != args.len() {
+                    return Err(SemaError::ArityMismatch {
+                        name: name.into(),
+                        expected: only.params.len(),
+                        found: args.len(),
+                    });
+                }
+            }
+            return Err(SemaError::NoMatchingOverload {
+                name: name.into(),
+                args: type_list(args),
+                candidates: candidates.iter().map(|c| c.describe(name)).collect(),
+            });
+        }
+        Err(SemaError::AmbiguousCall {
+            name: name.into(),
+            args: type_list(args),
+            candidates: viable
+                .iter()
+                .map(|(i, _)| candidates[*i].describe(name))
+                .collect(),
+        })
+    }
+
+    /// Per-argument cost of calling `candidate`: 0 for an exact match, 1 for
+    /// an implicit conversion, or `None` if an argument does not fit
+    ///
+    /// `out` and `inout` arguments must match exactly.
+    fn conversion_costs(&self, candidate: &Candidate, args: &[Expr]) -> Option<Vec<u8>> {
+        if candidate.params.len() != args.len() {
+            return None;
+        }
+        candidate
+            .params
+            .iter()
+            .zip(args)
+            .map(|((ty, qualifier), arg)| {
+                if arg.ty == *ty {
+                    Some(0)
+                } else if *qualifier == ParamQualifier::In
+                    && self.version.converts_type(&arg.ty, ty)
+                {
+                    Some(1)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Analyze a struct constructor, which takes one argument per field
+    fn construct_struct(&mut self, ty: Type, args: Vec<Expr>) -> Result<Expr> {
+        let Type::Struct(st) = &ty else {
+            unreachable!("not a struct type: {}", ty);
+        };
+        if args.len() != st.fields.len() {
+            return Err(SemaError::ArityMismatch {
+                name: st.name.clone(),
+                expected: st.fields.len(),
+                found: args.len(),
+            });
+        }
+        let args = args
+            .into_iter()
+            .zip(&st.fields)
+            .map(|(arg, field)| self.coerce(arg, &field.ty, "constructor argument"))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Expr::new(ExprKind::Construct(args), ty))
+    }
+
+    /// Analyze an array constructor; an unsized one takes its length from the arguments
+    fn construct_array(&mut self, ty: Type, args: Vec<Expr>) -> Result<Expr> {
+        let Type::Array(element, length) = ty else {
+            unreachable!("not an array type: {}", ty);
+        };
+        let length = if length == 0 {
+            args.len() as u32
+        } else {
+            length
+        };
+        let ty = Type::Array(element.clone(), length);
+        if args.len() != length as usize || length == 0 {
+            return Err(SemaError::ArityMismatch {
+                name: format!("{}", ty),
+                expected: length as usize,
+                found: args.len(),
+            });
+        }
+        let args = args
+            .into_iter()
+            .map(|arg| self.coerce(arg, &element, "constructor argument"))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Expr::new(ExprKind::Construct(args), ty))
+    }
+
+    /// `.length()` of an array, vector or matrix, which is a constant
+    fn length(&mut self, base: Expr, args: Vec<Expr>) -> Result<Expr> {
+        if !args.is_empty() {
+            return Err(SemaError::ArityMismatch {
+                name: "length".into(),
+                expected: 0,
+                found: args.len(),
+            });
+        }
+        let length = match &base.ty {
+            Type::Array(_, n) => *n,
+            Type::Vector(_, n) | Type::Matrix(n) => *n as u32,
+            other => {
+                return Err(SemaError::InvalidOperands {
+                    op: ".length()",
+                    lhs: other.clone(),
+                    rhs: None,
+                })
+            }
+        };
+        Ok(Expr::new(
+            ExprKind::Literal(Literal::Int(length as i32)),
+            Type::INT,
+        ))
+    }
+
+    /// Analyze a constructor call such as `vec3(1.0)` or `vec4(v.xy, 0.0, 1.0)`
+    fn construct(&mut self, ty: Type, args: Vec<Expr>) -> Result<Expr> {
+        if args.is_empty() {
+            return Err(SemaError::ConstructorComponents { ty, found: 0 });
+        }
+        for arg in &args {
+            if arg.ty.component_count().is_none() {
+                return Err(SemaError::TypeMismatch {
+                    expected: ty,
+                    found: arg.ty.clone(),
+                    context: "constructor argument",
+                });
+            }
+        }
+        let needed = ty.component_count().unwrap_or(0);
+        match &ty {
+            Type::Scalar(_) => {
+                if args.len() != 1 {
+                    return Err(SemaError::ArityMismatch {
+                        name: format!("{}", ty),
+                        expected: 1,
+                        found: args.len(),
+                    });
+                }
+            }
+            // A single scalar fills every component, or the diagonal of a matrix
+            _ if args.len() == 1 && args[0].ty.is_scalar() => {}
+            // Matrices convert to any other size
+            Type::Matrix(_) if args.len() == 1 && args[0].ty.is_matrix() => {}
+            Type::Matrix(_) if args.iter().any(|a| a.ty.is_matrix()) => {
+                let found = args.into_iter().find(|a| a.ty.is_matrix()).unwrap().ty;
+                return Err(SemaError::TypeMismatch {
+                    expected: ty,
+                    found,
+                    context: "constructor argument",
+                });
+            }
+            _ => {
+                let mut total = 0;
+                for (i, arg) in args.iter().enumerate() {
+                    // Every argument must contribute at least one component
+                    if total >= needed {
+                        return Err(SemaError::ConstructorComponents {
+                            ty,
+                            found: total
+                                + args[i..]
+                                    .iter()
+                                    .filter_map(|a| a.ty.component_count())
+                                    .sum::<u32>(),
+                        });
+                    }
+                    total += arg.ty.component_count().unwrap_or(0);
+                }
+                if total < needed {
+                    return Err(SemaError::ConstructorComponents { ty, found: total });
+                }
+            }
+        }
+        Ok(Expr::new(ExprKind::Construct(args), ty))
+    }
+}
+
+/// Whether an overload with conversion costs `a` is a better match than one with `b`
+fn is_better_match(a: &[u8], b: &[u8]) -> bool {
+    a.iter().zip(b).all(|(x, y)| x <= y) && a != b
+}
+
+/// Wrap `expr` in an implicit conversion to `ty`
+fn convert(expr: Expr, ty: &Type) -> Expr {
+    Expr::new(ExprKind::Construct(vec![expr]), ty.clone())
+}
+
+/// Parse swizzle letters into component indices
+///
+/// All letters must come from one of the `xyzw`, `rgba` or `stpq` sets.
+pub(super) fn parse_swizzle(field: &str) -> Option<Vec<u8>> {
+    const SETS: [&[u8; 4]; 3] = [b"xyzw", b"rgba", b"stpq"];
+    let bytes = field.as_bytes();
+    if bytes.is_empty() || bytes.len() > 4 {
+        return None;
+    }
+    let set = SETS.iter().find(|set| set.contains(&bytes[0]))?;
+    bytes
+        .iter()
+        .map(|b| set.iter().position(|c| c == b).map(|i| i as u8))
+        .collect()
+}
+
+/// Result type of a binary operator, or `None` if the operands are invalid
+pub(super) fn binary_result_type(op: BinaryOp, lhs: &Type, rhs: &Type) -> Option<Type> {
+    if matches!(op, BinaryOp::Eq | BinaryOp::Ne) {
+        return (lhs == rhs && *lhs != Type::Void).then_some(Type::BOOL);
+    }
+    if lhs.is_matrix() || rhs.is_matrix() {
+        return matrix_result_type(op, lhs, rhs);
+    }
+    let (ls, rs) = (lhs.scalar_type()?, rhs.scalar_type()?);
+    match op {
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => {
+            if !ls.is_numeric() || ls != rs {
+                return None;
+            }
+            componentwise(lhs, rhs)
+        }
+        BinaryOp::Mod | BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor => {
+            if !ls.is_integer() || ls != rs {
+                return None;
+            }
+            componentwise(lhs, rhs)
+        }
+        BinaryOp::Shl | BinaryOp::Shr => {
+            if !ls.is_integer() || !rs.is_integer() {
+                return None;
+            }
+            match (lhs, rhs) {
+                (_, Type::Scalar(_)) => Some(lhs.clone()),
+                (Type::Vector(_, a), Type::Vector(_, b)) if a == b => Some(lhs.clone()),
+                _ => None,
+            }
+        }
+        BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge => {
+            (lhs == rhs && lhs.is_scalar() && ls.is_numeric()).then_some(Type::BOOL)
+        }
+        BinaryOp::Eq | BinaryOp::Ne => unreachable!("handled above"),
+        BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => {
+            (*lhs == Type::BOOL && *rhs == Type::BOOL).then_some(Type::BOOL)
+        }
+    }
+}
+
+/// Result type of an arithmetic operator with a matrix operand
+///
+/// `*` is the linear algebraic product when both operands are matrices or
+/// one is a vector; every other combination is component-wise, with a
+/// `float` operand applying to every component.
+fn matrix_result_type(op: BinaryOp, lhs: &Type, rhs: &Type) -> Option<Type> {
+    if !matches!(
+        op,
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div
+    ) {
+        return None;
+    }
+    match (lhs, rhs) {
+        (Type::Matrix(a), Type::Matrix(b)) if a == b => Some(lhs.clone()),
+        (Type::Matrix(_), &Type::FLOAT) => Some(lhs.clone()),
+        (&Type::FLOAT, Type::Matrix(_)) => Some(rhs.clone()),
+        (Type::Matrix(n), Type::Vector(ScalarType::Float, m))
+        | (Type::Vector(ScalarType::Float, m), Type::Matrix(n))
+            if n == m && op == BinaryOp::Mul =>
+        {
+            Some(Type::Vector(ScalarType::Float, *n))
+        }
+        _ => None,
+    }
+}
+
+/// Result of a component-wise operation on two operands with the same scalar type
+fn componentwise(lhs: &Type, rhs: &Type) -> Option<Type> {
+    match (lhs, rhs) {
+        _ if lhs == rhs => Some(lhs.clone()),
+        (Type::Scalar(_), Type::Vector(..)) => Some(rhs.clone()),
+        (Type::Vector(..), Type::Scalar(_)) => Some(lhs.clone()),
+        _ => None,
+    }
+}
+
+fn invalid_unary(op: &'static str, ty: Type) -> SemaError {
+    SemaError::InvalidOperands {
+        op,
+        lhs: ty,
+        rhs: None,
+    }
+}
+
+/// Comma separated list of argument types for diagnostics
+pub(super) fn type_list(args: &[Expr]) -> String {
+    args.iter()
+        .map(|a| format!("{}", a.ty))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn binary_op(op: &syntax::BinaryOp) -> BinaryOp {
+    use syntax::BinaryOp as B;
+    match op {
+        B::Or => BinaryOp::Or,
+        B::Xor => BinaryOp::Xor,
+        B::And => BinaryOp::And,
+        B::BitOr => BinaryOp::BitOr,
+        B::BitXor => BinaryOp::BitXor,
+        B::BitAnd => BinaryOp::BitAnd,
+        B::Equal => BinaryOp::Eq,
+        B::NonEqual => BinaryOp::Ne,
+        B::Lt => BinaryOp::Lt,
+        B::Gt => BinaryOp::Gt,
+        B::Lte => BinaryOp::Le,
+        B::Gte => BinaryOp::Ge,
+        B::LShift => BinaryOp::Shl,
+        B::RShift => BinaryOp::Shr,
+        B::Add => BinaryOp::Add,
+        B::Sub => BinaryOp::Sub,
+        B::Mult => BinaryOp::Mul,
+        B::Div => BinaryOp::Div,
+        B::Mod => BinaryOp::Mod,
+    }
+}
+
+fn assignment_op(op: &AssignmentOp) -> Option<BinaryOp> {
+    Some(match op {
+        AssignmentOp::Equal => return None,
+        AssignmentOp::Mult => BinaryOp::Mul,
+        AssignmentOp::Div => BinaryOp::Div,
+        AssignmentOp::Mod => BinaryOp::Mod,
+        AssignmentOp::Add => BinaryOp::Add,
+        AssignmentOp::Sub => BinaryOp::Sub,
+        AssignmentOp::LShift => BinaryOp::Shl,
+        AssignmentOp::RShift => BinaryOp::Shr,
+        AssignmentOp::And => BinaryOp::BitAnd,
+        AssignmentOp::Xor => BinaryOp::BitXor,
+        AssignmentOp::Or => BinaryOp::BitOr,
+    })
+}
+
+pub(super) fn binary_op_name(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Mod => "%",
+        BinaryOp::Lt => "<",
+        BinaryOp::Gt => ">",
+        BinaryOp::Le => "<=",
+        BinaryOp::Ge => ">=",
+        BinaryOp::Eq => "==",
+        BinaryOp::Ne => "!=",
+        BinaryOp::And => "&&",
+        BinaryOp::Or => "||",
+        BinaryOp::Xor => "^^",
+        BinaryOp::BitAnd => "&",
+        BinaryOp::BitOr => "|",
+        BinaryOp::BitXor => "^",
+        BinaryOp::Shl => "<<",
+        BinaryOp::Shr => ">>",
+    }
+}
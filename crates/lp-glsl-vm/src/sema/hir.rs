@@ -0,0 +1,489 @@
+//! Typed high-level IR produced by semantic analysis
+//!
+//! Every expression carries its resolved type and every name has been
+//! resolved to a local, global or function id.
+
+use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+
+use super::{
+    builtins::Builtin,
+    types::{StructType, Type},
+};
+
+/// Name of the classic entry point, which writes `gl_FragColor`
+pub const MAIN: &str = "main";
+
+/// Name of the Shadertoy entry point,
+/// `void mainImage(out vec4 fragColor, in vec2 fragCoord)`
+///
+/// It is the entry point of shaders that do not define [`MAIN`]; lowering
+/// adds a `main` that calls it.
+pub const MAIN_IMAGE: &str = "mainImage";
+
+/// Index into [`Module::globals`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GlobalId(pub u32);
+
+/// Index into [`Module::functions`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FunctionId(pub u32);
+
+/// Index into [`Function::locals`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LocalId(pub u32);
+
+/// A fully analyzed translation unit
+#[derive(Clone, Debug, Default)]
+pub struct Module {
+    /// Struct types in declaration order
+    pub structs: Vec<Rc<StructType>>,
+    pub globals: Vec<Global>,
+    pub functions: Vec<Function>,
+}
+
+impl Module {
+    pub fn global(&self, id: GlobalId) -> &Global {
+        &self.globals[id.0 as usize]
+    }
+
+    pub fn function(&self, id: FunctionId) -> &Function {
+        &self.functions[id.0 as usize]
+    }
+
+    /// Find the defined function with the given name, if there is exactly one
+    pub fn find_function(&self, name: &str) -> Option<FunctionId> {
+        let mut found = self
+            .functions
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.name == name && f.body.is_some());
+        match (found.next(), found.next()) {
+            (Some((i, _)), None) => Some(FunctionId(i as u32)),
+            _ => None,
+        }
+    }
+}
+
+/// Storage class of a global variable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Storage {
+    /// Plain global, private to one shader invocation
+    Private,
+    Const,
+    Uniform,
+    /// Shader input (`in`, `varying`, `attribute`)
+    Input,
+    /// Shader output (`out`)
+    Output,
+    /// Variable provided by the implementation, such as `gl_FragCoord`
+    Builtin,
+}
+
+#[derive(Clone, Debug)]
+pub struct Global {
+    pub name: String,
+    pub ty: Type,
+    pub storage: Storage,
+    pub init: Option<Expr>,
+    /// Whether the shader may assign to this variable
+    pub writable: bool,
+    pub precision: Option<Precision>,
+}
+
+/// Precision qualifier of a float variable, from lowest to highest
+///
+/// A variable declared without one takes the default set by the innermost
+/// `precision` statement for `float`, if any.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Precision {
+    Low,
+    Medium,
+    High,
+}
+
+impl Precision {
+    pub const ALL: [Precision; 3] = [Precision::Low, Precision::Medium, Precision::High];
+
+    /// GLSL spelling of the qualifier
+    pub fn name(self) -> &'static str {
+        match self {
+            Precision::Low => "lowp",
+            Precision::Medium => "mediump",
+            Precision::High => "highp",
+        }
+    }
+}
+
+/// Direction of a function parameter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamQualifier {
+    In,
+    Out,
+    InOut,
+}
+
+#[derive(Clone, Debug)]
+pub struct Param {
+    pub name: Option<String>,
+    pub ty: Type,
+    pub qualifier: ParamQualifier,
+    /// Local slot holding the parameter inside the body
+    pub local: LocalId,
+    pub precision: Option<Precision>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Local {
+    pub name: String,
+    pub ty: Type,
+    pub is_const: bool,
+    pub precision: Option<Precision>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub name: String,
+    pub return_type: Type,
+    pub return_precision: Option<Precision>,
+    pub params: Vec<Param>,
+    pub locals: Vec<Local>,
+    /// `None` for a prototype that was never defined
+    pub body: Option<Block>,
+}
+
+impl Function {
+    pub fn local(&self, id: LocalId) -> &Local {
+        &self.locals[id.0 as usize]
+    }
+}
+
+pub type Block = Vec<Stmt>;
+
+#[derive(Clone, Debug)]
+pub enum Stmt {
+    Expr(Expr),
+    /// Local declaration with an optional initializer
+    Decl(LocalId, Option<Expr>),
+    Block(Block),
+    If {
+        cond: Expr,
+        then_branch: Block,
+        else_branch: Option<Block>,
+    },
+    /// `while`, `for` and `do`/`while` loops
+    ///
+    /// `cond` is checked before the body unless `test_first` is false, and
+    /// `step` runs after every iteration including ones ended by `continue`.
+    Loop {
+        cond: Option<Expr>,
+        step: Option<Expr>,
+        body: Block,
+        test_first: bool,
+    },
+    Switch {
+        selector: Expr,
+        cases: Vec<SwitchCase>,
+    },
+    Break,
+    Continue,
+    Return(Option<Expr>),
+    Discard,
+}
+
+/// A group of statements following one or more case labels
+///
+/// `labels` holds `None` for `default`. Falling through to the next case
+/// happens when `body` does not end with a jump.
+#[derive(Clone, Debug)]
+pub struct SwitchCase {
+    pub labels: Vec<Option<i64>>,
+    pub body: Block,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Literal {
+    Bool(bool),
+    Int(i32),
+    UInt(u32),
+    Float(f32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+    PreInc,
+    PreDec,
+    PostInc,
+    PostDec,
+}
+
+impl UnaryOp {
+    /// Whether this is `++` or `--`, which assign to their operand
+    pub fn is_inc_dec(self) -> bool {
+        matches!(
+            self,
+            UnaryOp::PreInc | UnaryOp::PreDec | UnaryOp::PostInc | UnaryOp::PostDec
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+    Xor,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+impl BinaryOp {
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge | BinaryOp::Eq | BinaryOp::Ne
+        )
+    }
+
+    pub fn is_logical(self) -> bool {
+        matches!(self, BinaryOp::And | BinaryOp::Or | BinaryOp::Xor)
+    }
+}
+
+/// Resolved variable reference
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VarRef {
+    Local(LocalId),
+    Global(GlobalId),
+}
+
+#[derive(Clone, Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub ty: Type,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, ty: Type) -> Self {
+        Self { kind, ty }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ExprKind {
+    Literal(Literal),
+    Var(VarRef),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// Assignment, with the operator for compound forms such as `+=`
+    Assign(Option<BinaryOp>, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    Call(FunctionId, Vec<Expr>),
+    /// Call to a builtin function
+    Builtin(Builtin, Vec<Expr>),
+    /// Constructor call for the expression's type
+    ///
+    /// Implicit conversions are represented as single-argument constructors.
+    Construct(Vec<Expr>),
+    /// Component selection, each entry is a component index
+    ///
+    /// Selects from a scalar or vector base, never from another swizzle.
+    /// After lowering, every swizzle selects a single component.
+    Swizzle(Box<Expr>, Vec<u8>),
+    /// Struct member access by field index
+    Field(Box<Expr>, u32),
+    Index(Box<Expr>, Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+}
+
+/// Traversal of the statements and expressions of a block
+///
+/// Each method visits the children of its node by default, in source
+/// order, through the matching `walk_` function. A pass overrides the
+/// nodes it handles and calls the `walk_` function itself to continue into
+/// their children, so only the walk functions list every kind of node.
+pub trait Visitor {
+    fn visit_block(&mut self, block: &Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, block: &Block) {
+    for stmt in block {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Expr(expr) | Stmt::Decl(_, Some(expr)) | Stmt::Return(Some(expr)) => {
+            visitor.visit_expr(expr)
+        }
+        Stmt::Block(block) => visitor.visit_block(block),
+        Stmt::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr(cond);
+            visitor.visit_block(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_block(else_branch);
+            }
+        }
+        Stmt::Loop {
+            cond, step, body, ..
+        } => {
+            if let Some(cond) = cond {
+                visitor.visit_expr(cond);
+            }
+            if let Some(step) = step {
+                visitor.visit_expr(step);
+            }
+            visitor.visit_block(body);
+        }
+        Stmt::Switch { selector, cases } => {
+            visitor.visit_expr(selector);
+            for case in cases {
+                visitor.visit_block(&case.body);
+            }
+        }
+        Stmt::Decl(_, None) | Stmt::Break | Stmt::Continue | Stmt::Return(None) | Stmt::Discard => {
+        }
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Var(_) => {}
+        ExprKind::Unary(_, operand)
+        | ExprKind::Swizzle(operand, _)
+        | ExprKind::Field(operand, _) => visitor.visit_expr(operand),
+        ExprKind::Binary(_, lhs, rhs)
+        | ExprKind::Assign(_, lhs, rhs)
+        | ExprKind::Index(lhs, rhs)
+        | ExprKind::Comma(lhs, rhs) => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        ExprKind::Ternary(cond, then, otherwise) => {
+            visitor.visit_expr(cond);
+            visitor.visit_expr(then);
+            visitor.visit_expr(otherwise);
+        }
+        ExprKind::Call(_, args) | ExprKind::Builtin(_, args) | ExprKind::Construct(args) => {
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+    }
+}
+
+/// [`Visitor`] that may modify the nodes it visits
+pub trait VisitorMut {
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut Block) {
+    for stmt in block {
+        visitor.visit_stmt_mut(stmt);
+    }
+}
+
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Expr(expr) | Stmt::Decl(_, Some(expr)) | Stmt::Return(Some(expr)) => {
+            visitor.visit_expr_mut(expr)
+        }
+        Stmt::Block(block) => visitor.visit_block_mut(block),
+        Stmt::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr_mut(cond);
+            visitor.visit_block_mut(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_block_mut(else_branch);
+            }
+        }
+        Stmt::Loop {
+            cond, step, body, ..
+        } => {
+            if let Some(cond) = cond {
+                visitor.visit_expr_mut(cond);
+            }
+            if let Some(step) = step {
+                visitor.visit_expr_mut(step);
+            }
+            visitor.visit_block_mut(body);
+        }
+        Stmt::Switch { selector, cases } => {
+            visitor.visit_expr_mut(selector);
+            for case in cases {
+                visitor.visit_block_mut(&mut case.body);
+            }
+        }
+        Stmt::Decl(_, None) | Stmt::Break | Stmt::Continue | Stmt::Return(None) | Stmt::Discard => {
+        }
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Literal(_) | ExprKind::Var(_) => {}
+        ExprKind::Unary(_, operand)
+        | ExprKind::Swizzle(operand, _)
+        | ExprKind::Field(operand, _) => visitor.visit_expr_mut(operand),
+        ExprKind::Binary(_, lhs, rhs)
+        | ExprKind::Assign(_, lhs, rhs)
+        | ExprKind::Index(lhs, rhs)
+        | ExprKind::Comma(lhs, rhs) => {
+            visitor.visit_expr_mut(lhs);
+            visitor.visit_expr_mut(rhs);
+        }
+        ExprKind::Ternary(cond, then, otherwise) => {
+            visitor.visit_expr_mut(cond);
+            visitor.visit_expr_mut(then);
+            visitor.visit_expr_mut(otherwise);
+        }
+        ExprKind::Call(_, args) | ExprKind::Builtin(_, args) | ExprKind::Construct(args) => {
+            for arg in args {
+                visitor.visit_expr_mut(arg);
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Recovery of source positions for semantic errors
+//!
+//! The glsl AST carries no spans, so errors are located by searching the
+//! shader text: first for the enclosing function definition, then for the
+//! leading token of each statement up to the one being analyzed, each
+//! after the end of the one before, then for the token the error is about.
+
+use alloc::string::String;
+use core::ops::Range;
+
+use glsl::syntax::{Declaration, Expr, FunIdentifier, JumpStatement, SimpleStatement};
+
+use super::LocatedError;
+use crate::diagnostic::{Diagnostic, Span};
+
+impl LocatedError {
+    /// Convert to a diagnostic, pointing at `span` if the error was located
+    pub fn to_diagnostic(&self, span: Option<Span>) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(alloc::format!("{}", self.error));
+        if let Some(span) = span {
+            diagnostic = diagnostic.with_primary(span, self.error.label());
+        }
+        for note in self.error.notes() {
+            diagnostic = diagnostic.with_note(note);
+        }
+        diagnostic
+    }
+}
+
+/// Find the byte range of the token an error is about in the analyzed text
+pub fn locate(error: &LocatedError, text: &str) -> Option<Range<usize>> {
+    let mut from = 0;
+    let mut best = None;
+    // Where the statement being analyzed may start
+    let mut next = 0;
+    if let Some(function) = &error.function {
+        let at = find_function(text, function)?;
+        from = at;
+        best = Some(at..at + function.len());
+        next = text[at..].find('{').map_or(at, |brace| at + brace + 1);
+        for token in &error.preceding {
+            match find_statement(text, token, next) {
+                Some(at) => next = statement_end(text, token, at),
+                None => break,
+            }
+        }
+    }
+    if let Some(anchor) = &error.anchor {
+        // Anchors outside statements, such as a function name, come earlier
+        let found = find_statement(text, anchor, next).or_else(|| find_word(text, anchor, from));
+        if let Some(at) = found {
+            from = at;
+            best = Some(at..at + anchor.len());
+        }
+    }
+    if let Some(subject) = error.error.subject() {
+        if let Some(at) = find_word(text, subject, from) {
+            best = Some(at..at + subject.len());
+        }
+    }
+    best
+}
+
+/// Find the keyword starting the loop numbered `ordinal` in `function`
+///
+/// Loops are numbered in source order, as in
+/// [`loops::classify`](crate::analysis::loops::classify).
+pub fn locate_loop(text: &str, function: &str, ordinal: usize) -> Option<Range<usize>> {
+    let mut from = find_function(text, function)?;
+    let mut seen = 0;
+    loop {
+        let (at, keyword) = ["for", "while", "do"]
+            .into_iter()
+            .filter_map(|k| find_word(text, k, from).map(|at| (at, k)))
+            .min()?;
+        from = at + keyword.len();
+        // The `while` closing a `do` loop belongs to a loop already counted
+        if keyword == "while" && closes_do_loop(&text[from..]) {
+            continue;
+        }
+        if seen == ordinal {
+            return Some(at..from);
+        }
+        seen += 1;
+    }
+}
+
+/// Offset of the statement whose leading token is `token` at or after `from`
+fn find_statement(text: &str, token: &str, mut from: usize) -> Option<usize> {
+    loop {
+        let at = find_word(text, token, from)?;
+        from = at + token.len();
+        if token != "while" || !closes_do_loop(&text[from..]) {
+            return Some(at);
+        }
+    }
+}
+
+/// Offset just past the statement whose leading token `token` is at `at`
+///
+/// The statements nested in one with a body come after it, so that ends
+/// with its keyword and parenthesized header. Any other statement ends with
+/// its `;`.
+fn statement_end(text: &str, token: &str, at: usize) -> usize {
+    let header = match token {
+        "if" | "for" | "while" | "switch" => true,
+        "do" => return at + token.len(),
+        _ => false,
+    };
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut i = at + token.len();
+    while i < bytes.len() {
+        let comment = comment_len(text, i);
+        if comment > 0 {
+            i += comment;
+            continue;
+        }
+        match bytes[i] {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' if depth > 0 => {
+                depth -= 1;
+                if header && depth == 0 {
+                    return i + 1;
+                }
+            }
+            b';' if !header && depth == 0 => return i + 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    text.len()
+}
+
+/// Length of the comment starting at `at`, 0 if none does
+fn comment_len(text: &str, at: usize) -> usize {
+    let rest = &text.as_bytes()[at..];
+    if rest.starts_with(b"//") {
+        rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len())
+    } else if rest.starts_with(b"/*") {
+        rest[2..]
+            .windows(2)
+            .position(|w| w == b"*/")
+            .map_or(rest.len(), |end| end + 4)
+    } else {
+        0
+    }
+}
+
+/// Whether the text after a `while` is a condition followed by `;`
+fn closes_do_loop(rest: &str) -> bool {
+    let mut depth = 0;
+    for (i, c) in rest.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 1 => return rest[i + 1..].trim_start().starts_with(';'),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Offset of the name in the definition of function `name`
+fn find_function(text: &str, name: &str) -> Option<usize> {
+    let mut from = 0;
+    while let Some(at) = find_word(text, name, from) {
+        from = at + name.len();
+        let rest = text[from..].trim_start();
+        if !rest.starts_with('(') {
+            continue;
+        }
+        // Skip the parameter list and require a body
+        let close = rest.find(')')?;
+        if rest[close + 1..].trim_start().starts_with('{') {
+            return Some(at);
+        }
+    }
+    None
+}
+
+/// Offset of the next occurrence of identifier `word` at or after `from`, outside comments
+fn find_word(text: &str, word: &str, from: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    let mut i = 0;
+    while i < bytes.len() {
+        let comment = comment_len(text, i);
+        if comment > 0 {
+            i += comment;
+            continue;
+        }
+        if !is_ident(bytes[i]) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && is_ident(bytes[i]) {
+            i += 1;
+        }
+        if start >= from && &text[start..i] == word {
+            return Some(start);
+        }
+    }
+    None
+}
+
+/// Leading token of a statement: a declared name, keyword or leftmost identifier
+pub(super) fn statement_anchor(statement: &SimpleStatement) -> Option<String> {
+    match statement {
+        SimpleStatement::Declaration(decl) => declaration_anchor(decl),
+        SimpleStatement::Expression(expr) => expr.as_ref().and_then(expr_anchor),
+        SimpleStatement::Selection(_) => Some("if".into()),
+        SimpleStatement::Switch(_) => Some("switch".into()),
+        SimpleStatement::CaseLabel(_) => None,
+        SimpleStatement::Iteration(iteration) => Some(
+            match iteration {
+                glsl::syntax::IterationStatement::While(..) => "while",
+                glsl::syntax::IterationStatement::DoWhile(..) => "do",
+                glsl::syntax::IterationStatement::For(..) => "for",
+            }
+            .into(),
+        ),
+        SimpleStatement::Jump(jump) => Some(
+            match jump {
+                JumpStatement::Continue => "continue",
+                JumpStatement::Break => "break",
+                JumpStatement::Return(_) => "return",
+                JumpStatement::Discard => "discard",
+            }
+            .into(),
+        ),
+    }
+}
+
+pub(super) fn declaration_anchor(decl: &Declaration) -> Option<String> {
+    match decl {
+        Declaration::FunctionPrototype(proto) => Some(proto.name.0.clone()),
+        Declaration::InitDeclaratorList(list) => list.head.name.as_ref().map(|n| n.0.clone()),
+        Declaration::Block(block) => Some(block.name.0.clone()),
+        Declaration::Precision(..) => Some("precision".into()),
+        Declaration::Global(_, names) => names.first().map(|n| n.0.clone()),
+    }
+}
+
+fn expr_anchor(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Variable(ident) => Some(ident.0.clone()),
+        Expr::FunCall(FunIdentifier::Identifier(ident), _) => Some(ident.0.clone()),
+        Expr::FunCall(FunIdentifier::Expr(e), _)
+        | Expr::Unary(_, e)
+        | Expr::Binary(_, e, _)
+        | Expr::Ternary(e, ..)
+        | Expr::Assignment(e, ..)
+        | Expr::Bracket(e, _)
+        | Expr::Dot(e, _)
+        | Expr::PostInc(e)
+        | Expr::PostDec(e)
+        | Expr::Comma(e, _) => expr_anchor(e),
+        _ => None,
+    }
+}
@@ -0,0 +1,894 @@
+//! Semantic analysis of parsed GLSL
+//!
+//! [`analyze`] walks a [`TranslationUnit`], resolves every identifier,
+//! infers the type of every expression and produces a typed [`hir::Module`].
+//! Analysis continues past errors so that all problems in a shader are
+//! reported at once.
+
+pub mod builtins;
+pub mod const_eval;
+mod error;
+mod expr;
+pub mod hir;
+mod locate;
+mod scope;
+mod stmt;
+pub mod types;
+mod version;
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    format,
+    rc::Rc,
+    string::String,
+    vec,
+    vec::Vec,
+};
+
+use const_eval::{ConstEval, Value};
+pub use error::SemaError;
+use glsl::syntax::{
+    ArraySpecifier, ArraySpecifierDimension, Declaration, ExternalDeclaration, FunctionDefinition,
+    FunctionParameterDeclaration, FunctionPrototype, InitDeclaratorList, Initializer,
+    PrecisionQualifier, Preprocessor, PreprocessorExtension, PreprocessorExtensionBehavior,
+    PreprocessorExtensionName, SingleDeclaration, StorageQualifier, StructSpecifier,
+    TranslationUnit, TypeQualifier, TypeQualifierSpec, TypeSpecifier, TypeSpecifierNonArray,
+};
+use hir::{
+    Function, FunctionId, Global, GlobalId, Local, LocalId, Module, Param, ParamQualifier,
+    Precision, Storage, VarRef,
+};
+pub use locate::{locate, locate_loop};
+use scope::Scopes;
+pub use types::{Field, ScalarType, StructType, Type};
+pub use version::{Feature, Version};
+
+use crate::analysis::call_graph::CallGraph;
+
+type Result<T> = core::result::Result<T, SemaError>;
+
+/// Uniforms of the Shadertoy convention, available without a declaration
+const SHADERTOY_UNIFORMS: &[(&str, Type)] = &[
+    ("iResolution", Type::Vector(ScalarType::Float, 3)),
+    ("iTime", Type::FLOAT),
+    ("iTimeDelta", Type::FLOAT),
+    ("iFrame", Type::INT),
+    ("iMouse", Type::Vector(ScalarType::Float, 4)),
+];
+
+/// Analyze a parsed translation unit
+///
+/// Returns the typed module, or every error found in the shader.
+pub fn analyze(tu: &TranslationUnit) -> core::result::Result<Module, Vec<SemaError>> {
+    analyze_located(tu).map_err(|errors| errors.into_iter().map(|e| e.error).collect())
+}
+
+/// Like [`analyze`], but keeps the context needed to locate each error in the source
+pub fn analyze_located(tu: &TranslationUnit) -> core::result::Result<Module, Vec<LocatedError>> {
+    analyze_with_version(tu, None)
+}
+
+/// Like [`analyze_located`], targeting `version` unless the shader has a
+/// `#version` directive
+///
+/// Features the targeted version lacks are rejected. A shader with neither
+/// a directive nor a default version may use every feature the compiler
+/// implements.
+pub fn analyze_with_version(
+    tu: &TranslationUnit,
+    version: Option<Version>,
+) -> core::result::Result<Module, Vec<LocatedError>> {
+    let mut analyzer = Analyzer::new();
+    if let Some(version) = version {
+        analyzer.version = version;
+        analyzer.gated = true;
+    }
+    for decl in &tu.0 .0 {
+        analyzer.external_declaration(decl);
+    }
+    analyzer.check_entry_point();
+    analyzer.check_recursion();
+    if analyzer.errors.is_empty() {
+        Ok(analyzer.module)
+    } else {
+        Err(analyzer.errors)
+    }
+}
+
+/// A semantic error with the context it was found in
+///
+/// The parser does not record source positions, so this is what
+/// [`to_diagnostic`] uses to find the error in the shader text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocatedError {
+    pub error: SemaError,
+    /// Function whose body contains the error
+    pub function: Option<String>,
+    /// Leading token of the statement or declaration being analyzed
+    pub anchor: Option<String>,
+    /// Leading tokens of the statements before it in the function, in
+    /// source order
+    pub preceding: Vec<String>,
+}
+
+/// What a `break` statement would leave
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BreakTarget {
+    Loop,
+    Switch,
+}
+
+/// State for the function body currently being analyzed
+struct FunctionState {
+    name: String,
+    return_type: Type,
+    locals: Vec<Local>,
+    /// Values of the `const` locals declared so far
+    const_values: BTreeMap<LocalId, Value>,
+    breakable: Vec<BreakTarget>,
+    /// Leading tokens of the statements analyzed so far, in source order
+    statements: Vec<String>,
+    /// Number of those before the statement being analyzed
+    preceding: usize,
+}
+
+struct Analyzer {
+    module: Module,
+    errors: Vec<LocatedError>,
+    scopes: Scopes,
+    functions: BTreeMap<String, Vec<FunctionId>>,
+    struct_types: BTreeMap<String, Rc<StructType>>,
+    current: Option<FunctionState>,
+    /// Leading token of the statement being analyzed, for locating errors
+    anchor: Option<String>,
+    /// Target version, from the `#version` directive
+    version: Version,
+    /// Whether a version was selected, so features it lacks are rejected
+    gated: bool,
+    /// Extensions enabled with `#extension`
+    extensions: BTreeSet<String>,
+}
+
+impl Analyzer {
+    fn new() -> Self {
+        let mut analyzer = Self {
+            module: Module::default(),
+            errors: Vec::new(),
+            scopes: Scopes::default(),
+            functions: BTreeMap::new(),
+            struct_types: BTreeMap::new(),
+            current: None,
+            anchor: None,
+            version: Version::DEFAULT,
+            gated: false,
+            extensions: BTreeSet::new(),
+        };
+        // Global scope stays open for the whole translation unit
+        analyzer.scopes.push();
+        analyzer.declare_builtin_globals();
+        analyzer
+    }
+
+    fn declare_builtin_globals(&mut self) {
+        let vec4 = Type::Vector(ScalarType::Float, 4);
+        self.add_global(Global {
+            name: "gl_FragCoord".into(),
+            ty: vec4.clone(),
+            storage: Storage::Builtin,
+            init: None,
+            writable: false,
+            precision: None,
+        });
+        self.add_global(Global {
+            name: "gl_FragColor".into(),
+            ty: vec4,
+            storage: Storage::Builtin,
+            init: None,
+            writable: true,
+            precision: None,
+        });
+    }
+
+    /// Declare the Shadertoy uniform called `name` on its first use
+    ///
+    /// They are declared lazily so that shaders which do not use them, or
+    /// declare them themselves, are unaffected.
+    fn shadertoy_uniform(&mut self, name: &str) -> Option<VarRef> {
+        let (_, ty) = SHADERTOY_UNIFORMS.iter().find(|(n, _)| *n == name)?;
+        let id = GlobalId(self.module.globals.len() as u32);
+        self.module.globals.push(Global {
+            name: name.into(),
+            ty: ty.clone(),
+            storage: Storage::Uniform,
+            init: None,
+            writable: false,
+            precision: None,
+        });
+        self.scopes.declare_global(name, VarRef::Global(id));
+        Some(VarRef::Global(id))
+    }
+
+    /// Check the signature of `mainImage` when it is the entry point
+    fn check_entry_point(&mut self) {
+        if self.module.find_function(hir::MAIN).is_some() {
+            return;
+        }
+        let Some(id) = self.module.find_function(hir::MAIN_IMAGE) else {
+            return;
+        };
+        let function = self.module.function(id);
+        let signature: Vec<_> = function
+            .params
+            .iter()
+            .map(|p| (p.qualifier, &p.ty))
+            .collect();
+        let vec4 = Type::Vector(ScalarType::Float, 4);
+        let vec2 = Type::Vector(ScalarType::Float, 2);
+        if function.return_type != Type::Void
+            || signature != [(ParamQualifier::Out, &vec4), (ParamQualifier::In, &vec2)]
+        {
+            self.anchor = Some(hir::MAIN_IMAGE.into());
+            self.error(SemaError::EntryPointSignature(hir::MAIN_IMAGE.into()));
+        }
+    }
+
+    /// Reject every cycle of calls, pointing at the first function in each
+    fn check_recursion(&mut self) {
+        for cycle in CallGraph::new(&self.module).cycles() {
+            let names: Vec<String> = cycle
+                .iter()
+                .map(|&id| self.module.function(id).name.clone())
+                .collect();
+            self.errors.push(LocatedError {
+                function: Some(names[0].clone()),
+                anchor: None,
+                preceding: Vec::new(),
+                error: SemaError::Recursion(names),
+            });
+        }
+    }
+
+    /// Check that the targeted version provides `feature`
+    fn require(&self, feature: Feature) -> Result<()> {
+        if self.gated && !self.version.supports(feature) {
+            return Err(SemaError::UnavailableFeature {
+                feature,
+                version: self.version,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check that the targeted version provides the components of `ty`
+    fn require_type(&self, ty: &Type) -> Result<()> {
+        match ty {
+            Type::Array(element, _) => self.require_type(element),
+            _ if ty.scalar_type() == Some(ScalarType::UInt) => {
+                self.require(Feature::UnsignedIntegers)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn error(&mut self, error: SemaError) {
+        self.errors.push(LocatedError {
+            error,
+            function: self.current.as_ref().map(|f| f.name.clone()),
+            anchor: self.anchor.clone(),
+            preceding: self
+                .current
+                .as_ref()
+                .map_or(Vec::new(), |f| f.statements[..f.preceding].to_vec()),
+        });
+    }
+
+    fn add_global(&mut self, global: Global) -> GlobalId {
+        let id = GlobalId(self.module.globals.len() as u32);
+        if !self.scopes.declare(&global.name, VarRef::Global(id)) {
+            self.error(SemaError::Redefinition(global.name.clone()));
+        }
+        self.module.globals.push(global);
+        id
+    }
+
+    /// Add a local to the current function and declare it in the innermost scope
+    fn add_local(
+        &mut self,
+        name: &str,
+        ty: Type,
+        is_const: bool,
+        precision: Option<Precision>,
+    ) -> Result<LocalId> {
+        let state = self.current.as_mut().expect("local outside of function");
+        let id = LocalId(state.locals.len() as u32);
+        state.locals.push(Local {
+            name: name.into(),
+            ty,
+            is_const,
+            precision,
+        });
+        if !self.scopes.declare(name, VarRef::Local(id)) {
+            return Err(SemaError::Redefinition(name.into()));
+        }
+        Ok(id)
+    }
+
+    fn external_declaration(&mut self, decl: &ExternalDeclaration) {
+        match decl {
+            ExternalDeclaration::Preprocessor(Preprocessor::Version(version)) => {
+                self.version = Version::from_directive(version);
+                self.gated = true;
+            }
+            ExternalDeclaration::Preprocessor(Preprocessor::Extension(extension)) => {
+                if let Err(e) = self.extension(extension) {
+                    self.error(e);
+                }
+            }
+            // Other directives have been handled by the preprocessor
+            ExternalDeclaration::Preprocessor(_) => {}
+            ExternalDeclaration::FunctionDefinition(def) => {
+                self.anchor = Some(def.prototype.name.0.clone());
+                self.function_definition(def)
+            }
+            ExternalDeclaration::Declaration(decl) => {
+                self.anchor = locate::declaration_anchor(decl);
+                if let Err(e) = self.global_declaration(decl) {
+                    self.error(e);
+                }
+            }
+        }
+    }
+
+    fn extension(&mut self, extension: &PreprocessorExtension) -> Result<()> {
+        let name = match &extension.name {
+            PreprocessorExtensionName::Specific(name) => name,
+            PreprocessorExtensionName::All => {
+                if extension.behavior == Some(PreprocessorExtensionBehavior::Disable) {
+                    self.extensions.clear();
+                }
+                return Ok(());
+            }
+        };
+        let supported = name == builtins::NOISE_EXTENSION;
+        match extension.behavior {
+            Some(PreprocessorExtensionBehavior::Disable) => {
+                self.extensions.remove(name);
+            }
+            _ if supported => {
+                self.extensions.insert(name.clone());
+            }
+            Some(PreprocessorExtensionBehavior::Require) => {
+                return Err(SemaError::UnsupportedExtension(name.clone()));
+            }
+            // Enabling an unknown extension only warrants a warning
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The builtin called `name`, if it exists and its extension is enabled
+    fn builtin(&self, name: &str) -> Option<builtins::Builtin> {
+        builtins::Builtin::from_name(name).filter(|builtin| {
+            builtin
+                .extension()
+                .is_none_or(|extension| self.extensions.contains(extension))
+        })
+    }
+
+    /// Precision of a variable of type `ty` declared with `qualifier`
+    ///
+    /// Only float variables have one; precision qualifiers on integers are
+    /// accepted but have no effect, as integers are always 32 bits.
+    fn precision(&self, qualifier: &Option<TypeQualifier>, ty: &Type) -> Option<Precision> {
+        let mut element = ty;
+        while let Type::Array(inner, _) = element {
+            element = inner;
+        }
+        if element.scalar_type() != Some(ScalarType::Float) {
+            return None;
+        }
+        let explicit = qualifier.as_ref().and_then(|q| {
+            q.qualifiers.0.iter().find_map(|spec| match spec {
+                TypeQualifierSpec::Precision(p) => Some(precision(p)),
+                _ => None,
+            })
+        });
+        explicit.or_else(|| self.scopes.float_precision())
+    }
+
+    /// Handle a `precision` statement, which only affects `float`
+    fn default_precision(&mut self, qualifier: &PrecisionQualifier, ty: &TypeSpecifier) {
+        if ty.ty == TypeSpecifierNonArray::Float && ty.array_specifier.is_none() {
+            self.scopes.set_float_precision(precision(qualifier));
+        }
+    }
+
+    fn global_declaration(&mut self, decl: &Declaration) -> Result<()> {
+        match decl {
+            Declaration::FunctionPrototype(proto) => {
+                self.declare_function(proto)?;
+                Ok(())
+            }
+            Declaration::InitDeclaratorList(list) => self.global_variables(list),
+            Declaration::Precision(qualifier, ty) => {
+                self.default_precision(qualifier, ty);
+                Ok(())
+            }
+            Declaration::Block(_) => Err(SemaError::Unsupported("interface block".into())),
+            // Qualifier-only redeclarations such as `invariant gl_Position;`
+            Declaration::Global(..) => Ok(()),
+        }
+    }
+
+    fn global_variables(&mut self, list: &InitDeclaratorList) -> Result<()> {
+        let head = &list.head;
+        let storage = match storage_qualifier(&head.ty.qualifier) {
+            None => Storage::Private,
+            Some(StorageQualifier::Const) => Storage::Const,
+            Some(StorageQualifier::Uniform) => Storage::Uniform,
+            Some(
+                StorageQualifier::In | StorageQualifier::Varying | StorageQualifier::Attribute,
+            ) => Storage::Input,
+            Some(StorageQualifier::Out) => Storage::Output,
+            Some(other) => {
+                return Err(SemaError::Unsupported(format!(
+                    "storage qualifier `{:?}`",
+                    other
+                )))
+            }
+        };
+        let base = self.resolve_type(&head.ty.ty)?;
+        let declarators = declarators(head, list);
+        if declarators.is_empty() {
+            return Ok(());
+        }
+        for (name, array, init) in declarators {
+            self.anchor = Some(name.into());
+            let ty = match self.declarator_type(&base, array) {
+                Ok(ty) => ty,
+                Err(e) => {
+                    self.error(e);
+                    continue;
+                }
+            };
+            let init = match init {
+                Some(init) => match self.initializer(init, &ty) {
+                    // Global initializers are evaluated before the shader runs,
+                    // and uniform ones are the defaults reported by reflection
+                    Ok(expr)
+                        if matches!(
+                            storage,
+                            Storage::Const | Storage::Private | Storage::Uniform
+                        ) =>
+                    {
+                        match self.constant(&expr) {
+                            Some(value) => Some(value.to_expr(&expr.ty)),
+                            None => {
+                                self.error(SemaError::NonConstantInitializer(name.into()));
+                                None
+                            }
+                        }
+                    }
+                    Ok(expr) => Some(expr),
+                    Err(e) => {
+                        self.error(e);
+                        None
+                    }
+                },
+                None if storage == Storage::Const => {
+                    self.error(SemaError::MissingInitializer(name.into()));
+                    None
+                }
+                None => None,
+            };
+            let ty = match sized_type(ty, init.as_ref()) {
+                Ok(ty) => ty,
+                Err(e) => {
+                    self.error(e);
+                    continue;
+                }
+            };
+            let writable = matches!(storage, Storage::Private | Storage::Output);
+            let precision = self.precision(&head.ty.qualifier, &ty);
+            self.add_global(Global {
+                name: name.into(),
+                ty,
+                storage,
+                init,
+                writable,
+                precision,
+            });
+        }
+        Ok(())
+    }
+
+    /// Type of one declarator, adding its array specifier to the base type
+    fn declarator_type(&mut self, base: &Type, array: Option<&ArraySpecifier>) -> Result<Type> {
+        match array {
+            Some(spec) => self.array_type(base.clone(), spec),
+            None => Ok(base.clone()),
+        }
+    }
+
+    /// Analyze an initializer for a variable of type `ty`
+    ///
+    /// An unsized array type accepts an array of any length.
+    fn initializer(&mut self, init: &Initializer, ty: &Type) -> Result<hir::Expr> {
+        match init {
+            Initializer::Simple(expr) => {
+                let expr = self.expr(expr)?;
+                match (ty, &expr.ty) {
+                    (Type::Array(element, 0), Type::Array(found, _)) if element == found => {
+                        Ok(expr)
+                    }
+                    _ => self.coerce(expr, ty, "initializer"),
+                }
+            }
+            Initializer::List(items) => {
+                let (ty, item_types) = match ty {
+                    Type::Array(element, 0) => (
+                        Type::Array(element.clone(), items.0.len() as u32),
+                        vec![(**element).clone(); items.0.len()],
+                    ),
+                    Type::Array(element, n) => (ty.clone(), vec![(**element).clone(); *n as usize]),
+                    Type::Struct(st) => {
+                        (ty.clone(), st.fields.iter().map(|f| f.ty.clone()).collect())
+                    }
+                    Type::Vector(s, n) => (ty.clone(), vec![Type::Scalar(*s); *n as usize]),
+                    _ => {
+                        return Err(SemaError::Unsupported(format!(
+                            "initializer list for `{}`",
+                            ty
+                        )))
+                    }
+                };
+                if items.0.len() != item_types.len() {
+                    return Err(SemaError::ArityMismatch {
+                        name: format!("{}", ty),
+                        expected: item_types.len(),
+                        found: items.0.len(),
+                    });
+                }
+                let items = items
+                    .0
+                    .iter()
+                    .zip(&item_types)
+                    .map(|(item, item_ty)| self.initializer(item, item_ty))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(hir::Expr::new(hir::ExprKind::Construct(items), ty))
+            }
+        }
+    }
+
+    /// Resolve a parsed type specifier
+    ///
+    /// Unsized arrays get length 0 until an initializer gives them a size.
+    fn resolve_type(&mut self, spec: &TypeSpecifier) -> Result<Type> {
+        let base = match &spec.ty {
+            TypeSpecifierNonArray::TypeName(name) => self
+                .struct_types
+                .get(&name.0)
+                .map(|st| Type::Struct(st.clone()))
+                .ok_or_else(|| SemaError::UnknownType(name.0.clone()))?,
+            TypeSpecifierNonArray::Struct(st) => self.declare_struct(st)?,
+            other => Type::from_syntax(other)
+                .ok_or_else(|| SemaError::UnsupportedType(syntax_type_name(other)))?,
+        };
+        self.require_type(&base)?;
+        match &spec.array_specifier {
+            Some(array) => self.array_type(base, array),
+            None => Ok(base),
+        }
+    }
+
+    /// Resolve a struct or array type by name, as used in constructors
+    pub(super) fn type_by_name(&self, name: &str) -> Option<Type> {
+        Type::from_name(name).or_else(|| {
+            self.struct_types
+                .get(name)
+                .map(|st| Type::Struct(st.clone()))
+        })
+    }
+
+    /// Array of `element` with the length given by `spec`
+    pub(super) fn array_type(&mut self, element: Type, spec: &ArraySpecifier) -> Result<Type> {
+        if matches!(element, Type::Array(..)) {
+            return Err(SemaError::Unsupported("arrays of arrays".into()));
+        }
+        if element == Type::Void {
+            return Err(SemaError::UnsupportedType("void[]".into()));
+        }
+        let length = match spec.dimensions.0.as_slice() {
+            [ArraySpecifierDimension::Unsized] => 0,
+            [ArraySpecifierDimension::ExplicitlySized(size)] => {
+                let size = self.expr(size)?;
+                self.array_length(&size)?
+            }
+            _ => return Err(SemaError::Unsupported("arrays of arrays".into())),
+        };
+        Ok(Type::Array(Box::new(element), length))
+    }
+
+    /// Value of an array size expression, which must be a positive constant
+    fn array_length(&self, size: &hir::Expr) -> Result<u32> {
+        self.constant(size)
+            .and_then(|value| value.as_int())
+            .filter(|&n| n > 0)
+            .and_then(|n| u32::try_from(n).ok())
+            .ok_or(SemaError::InvalidArraySize)
+    }
+
+    /// Value of `expr` if it is a constant expression
+    pub(super) fn constant(&self, expr: &hir::Expr) -> Option<Value> {
+        match &self.current {
+            Some(state) => ConstEval::with_locals(&self.module, &state.const_values).eval(expr),
+            None => ConstEval::new(&self.module).eval(expr),
+        }
+    }
+
+    /// Declare a struct type and return it
+    fn declare_struct(&mut self, spec: &StructSpecifier) -> Result<Type> {
+        let name = match &spec.name {
+            Some(name) => name.0.clone(),
+            None => return Err(SemaError::Unsupported("anonymous structs".into())),
+        };
+        let mut fields: Vec<Field> = Vec::new();
+        for field in &spec.fields.0 {
+            let base = self.resolve_type(&field.ty)?;
+            for ident in &field.identifiers.0 {
+                let ty = self.declarator_type(&base, ident.array_spec.as_ref())?;
+                let ty = sized_type(ty, None)?;
+                if ty == Type::Void {
+                    return Err(SemaError::UnsupportedType("void".into()));
+                }
+                if fields.iter().any(|f| f.name == ident.ident.0) {
+                    return Err(SemaError::Redefinition(ident.ident.0.clone()));
+                }
+                fields.push(Field {
+                    name: ident.ident.0.clone(),
+                    ty,
+                });
+            }
+        }
+        if self.type_by_name(&name).is_some() {
+            return Err(SemaError::Redefinition(name));
+        }
+        let st = Rc::new(StructType {
+            name: name.clone(),
+            fields,
+        });
+        self.struct_types.insert(name, st.clone());
+        self.module.structs.push(st.clone());
+        Ok(Type::Struct(st))
+    }
+
+    /// Collect the return type and parameters of a prototype
+    ///
+    /// Parameters occupy the first locals of the function, in order.
+    fn signature(
+        &mut self,
+        proto: &FunctionPrototype,
+    ) -> Result<(Type, Option<Precision>, Vec<Param>)> {
+        let return_type = self.resolve_type(&proto.ty.ty)?;
+        let return_precision = self.precision(&proto.ty.qualifier, &return_type);
+        let mut params = Vec::new();
+        for param in &proto.parameters {
+            let (qualifier, name, ty, array) = match param {
+                FunctionParameterDeclaration::Named(q, decl) => (
+                    q,
+                    Some(decl.ident.ident.0.clone()),
+                    &decl.ty,
+                    decl.ident.array_spec.as_ref(),
+                ),
+                FunctionParameterDeclaration::Unnamed(q, ty) => (q, None, ty, None),
+            };
+            let ty = self.resolve_type(ty)?;
+            let ty = sized_type(self.declarator_type(&ty, array)?, None)?;
+            if ty == Type::Void {
+                return Err(SemaError::TypeMismatch {
+                    expected: Type::FLOAT,
+                    found: Type::Void,
+                    context: "parameter declaration",
+                });
+            }
+            let precision = self.precision(qualifier, &ty);
+            let qualifier = match storage_qualifier(qualifier) {
+                Some(StorageQualifier::Out) => ParamQualifier::Out,
+                Some(StorageQualifier::InOut) => ParamQualifier::InOut,
+                _ => ParamQualifier::In,
+            };
+            params.push(Param {
+                name,
+                ty,
+                qualifier,
+                local: LocalId(params.len() as u32),
+                precision,
+            });
+        }
+        Ok((return_type, return_precision, params))
+    }
+
+    /// Declare a function, reusing an earlier prototype with the same parameter types
+    fn declare_function(&mut self, proto: &FunctionPrototype) -> Result<FunctionId> {
+        let name = proto.name.0.as_str();
+        // Type names and builtin functions cannot be redeclared or overloaded
+        if self.type_by_name(name).is_some() || self.builtin(name).is_some() {
+            return Err(SemaError::Redefinition(name.into()));
+        }
+        let (return_type, return_precision, params) = self.signature(proto)?;
+        let existing = self.functions.get(name).and_then(|ids| {
+            ids.iter().copied().find(|id| {
+                let f = self.module.function(*id);
+                f.params.len() == params.len()
+                    && f.params.iter().zip(&params).all(|(a, b)| a.ty == b.ty)
+            })
+        });
+        if let Some(id) = existing {
+            let f = self.module.function(id);
+            if f.return_type != return_type {
+                return Err(SemaError::TypeMismatch {
+                    expected: f.return_type.clone(),
+                    found: return_type,
+                    context: "function redeclaration",
+                });
+            }
+            // Prototype and definition must agree on `in`, `out` and `inout`
+            if let Some(param) = f
+                .params
+                .iter()
+                .zip(&params)
+                .position(|(a, b)| a.qualifier != b.qualifier)
+            {
+                return Err(SemaError::QualifierMismatch {
+                    name: name.into(),
+                    param,
+                });
+            }
+            return Ok(id);
+        }
+
+        let id = FunctionId(self.module.functions.len() as u32);
+        let locals = params
+            .iter()
+            .map(|p| Local {
+                name: p.name.clone().unwrap_or_default(),
+                ty: p.ty.clone(),
+                is_const: false,
+                precision: p.precision,
+            })
+            .collect();
+        self.module.functions.push(Function {
+            name: name.into(),
+            return_type,
+            return_precision,
+            params,
+            locals,
+            body: None,
+        });
+        self.functions.entry(name.into()).or_default().push(id);
+        Ok(id)
+    }
+
+    fn function_definition(&mut self, def: &FunctionDefinition) {
+        let id = match self.declare_function(&def.prototype) {
+            Ok(id) => id,
+            Err(e) => return self.error(e),
+        };
+        let function = &mut self.module.functions[id.0 as usize];
+        if function.body.is_some() {
+            let name = function.name.clone();
+            return self.error(SemaError::Redefinition(name));
+        }
+        // Parameter names come from the definition, not an earlier prototype
+        for (param, decl) in function.params.iter_mut().zip(&def.prototype.parameters) {
+            param.name = match decl {
+                FunctionParameterDeclaration::Named(_, d) => Some(d.ident.ident.0.clone()),
+                FunctionParameterDeclaration::Unnamed(..) => None,
+            };
+            function.locals[param.local.0 as usize].name = param.name.clone().unwrap_or_default();
+        }
+        let function = &self.module.functions[id.0 as usize];
+
+        self.current = Some(FunctionState {
+            name: function.name.clone(),
+            return_type: function.return_type.clone(),
+            locals: function.locals.clone(),
+            const_values: BTreeMap::new(),
+            breakable: Vec::new(),
+            statements: Vec::new(),
+            preceding: 0,
+        });
+        self.scopes.push();
+        for param in function.params.clone() {
+            if let Some(name) = &param.name {
+                if !self.scopes.declare(name, VarRef::Local(param.local)) {
+                    self.error(SemaError::Redefinition(name.clone()));
+                }
+            }
+        }
+        let body = self.statements(&def.statement.statement_list);
+        self.scopes.pop();
+
+        let state = self.current.take().expect("function state");
+        let function = &mut self.module.functions[id.0 as usize];
+        function.locals = state.locals;
+        function.body = Some(body);
+    }
+}
+
+/// A declared name with its array specifier and initializer
+type Declarator<'a> = (&'a str, Option<&'a ArraySpecifier>, Option<&'a Initializer>);
+
+/// Flatten the declarators of a declaration list
+fn declarators<'a>(
+    head: &'a SingleDeclaration,
+    list: &'a InitDeclaratorList,
+) -> Vec<Declarator<'a>> {
+    let mut out = Vec::new();
+    if let Some(name) = &head.name {
+        out.push((
+            name.0.as_str(),
+            head.array_specifier.as_ref(),
+            head.initializer.as_ref(),
+        ));
+    }
+    for decl in &list.tail {
+        out.push((
+            decl.ident.ident.0.as_str(),
+            decl.ident.array_spec.as_ref(),
+            decl.initializer.as_ref(),
+        ));
+    }
+    out
+}
+
+/// Final type of a declaration, sizing an unsized array from its initializer
+fn sized_type(ty: Type, init: Option<&hir::Expr>) -> Result<Type> {
+    match (&ty, init) {
+        (Type::Array(_, 0), Some(init)) => Ok(init.ty.clone()),
+        (Type::Array(_, 0), None) => Err(SemaError::InvalidArraySize),
+        _ => Ok(ty),
+    }
+}
+
+/// First storage qualifier in a qualifier list
+fn storage_qualifier(qualifier: &Option<TypeQualifier>) -> Option<StorageQualifier> {
+    qualifier.as_ref().and_then(|q| {
+        q.qualifiers.0.iter().find_map(|spec| match spec {
+            TypeQualifierSpec::Storage(s) => Some(s.clone()),
+            _ => None,
+        })
+    })
+}
+
+fn precision(qualifier: &PrecisionQualifier) -> Precision {
+    match qualifier {
+        PrecisionQualifier::Low => Precision::Low,
+        PrecisionQualifier::Medium => Precision::Medium,
+        PrecisionQualifier::High => Precision::High,
+    }
+}
+
+/// GLSL spelling of a parsed type that has no semantic equivalent
+fn syntax_type_name(ty: &TypeSpecifierNonArray) -> String {
+    use TypeSpecifierNonArray as T;
+    match ty {
+        T::Mat2 => "mat2".into(),
+        T::Mat3 => "mat3".into(),
+        T::Mat4 => "mat4".into(),
+        T::Mat23 => "mat2x3".into(),
+        T::Mat24 => "mat2x4".into(),
+        T::Mat32 => "mat3x2".into(),
+        T::Mat34 => "mat3x4".into(),
+        T::Mat42 => "mat4x2".into(),
+        T::Mat43 => "mat4x3".into(),
+        other => {
+            let mut name = format!("{:?}", other);
+            if let Some(first) = name.get_mut(..1) {
+                first.make_ascii_lowercase();
+            }
+            name
+        }
+    }
+}
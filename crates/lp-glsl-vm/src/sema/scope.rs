@@ -0,0 +1,69 @@
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+use super::hir::{Precision, VarRef};
+
+/// Names declared in one lexical scope
+#[derive(Default)]
+struct Scope {
+    names: BTreeMap<String, VarRef>,
+    /// Default precision of `float` set by a `precision` statement
+    float_precision: Option<Precision>,
+}
+
+/// Stack of lexical scopes mapping names to variables
+#[derive(Default)]
+pub struct Scopes {
+    stack: Vec<Scope>,
+}
+
+impl Scopes {
+    pub fn push(&mut self) {
+        self.stack.push(Scope::default());
+    }
+
+    pub fn pop(&mut self) {
+        self.stack.pop();
+    }
+
+    /// Declare a name in the innermost scope
+    ///
+    /// Returns false if the name is already declared in that scope.
+    pub fn declare(&mut self, name: &str, var: VarRef) -> bool {
+        let scope = self.stack.last_mut().expect("no open scope");
+        if scope.names.contains_key(name) {
+            return false;
+        }
+        scope.names.insert(name.into(), var);
+        true
+    }
+
+    /// Declare a name in the outermost, global scope
+    pub fn declare_global(&mut self, name: &str, var: VarRef) -> bool {
+        let scope = self.stack.first_mut().expect("no open scope");
+        if scope.names.contains_key(name) {
+            return false;
+        }
+        scope.names.insert(name.into(), var);
+        true
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<VarRef> {
+        self.stack
+            .iter()
+            .rev()
+            .find_map(|s| s.names.get(name).copied())
+    }
+
+    /// Set the default precision of `float` until the innermost scope ends
+    pub fn set_float_precision(&mut self, precision: Precision) {
+        self.stack
+            .last_mut()
+            .expect("no open scope")
+            .float_precision = Some(precision);
+    }
+
+    /// Default precision of `float` in the innermost scope that sets one
+    pub fn float_precision(&self) -> Option<Precision> {
+        self.stack.iter().rev().find_map(|s| s.float_precision)
+    }
+}
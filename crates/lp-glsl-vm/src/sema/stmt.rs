@@ -0,0 +1,426 @@
+use alloc::{format, vec, vec::Vec};
+
+use glsl::syntax::{
+    self, CaseLabel, Condition, Declaration, ForInitStatement, IterationStatement, JumpStatement,
+    SelectionRestStatement, SimpleStatement, Statement, StorageQualifier,
+};
+
+use super::{
+    hir::{BinaryOp, Block, Expr, ExprKind, Stmt, SwitchCase, VarRef},
+    locate, storage_qualifier, Analyzer, BreakTarget, Feature, Result, ScalarType, SemaError, Type,
+};
+use crate::analysis::loops;
+
+impl Analyzer {
+    /// Analyze a statement list, recording errors and continuing with the next statement
+    pub(super) fn statements(&mut self, statements: &[Statement]) -> Block {
+        let mut block = Vec::new();
+        for statement in statements {
+            block.extend(self.statement(statement));
+        }
+        block
+    }
+
+    /// Analyze a statement in its own scope, as for loop and branch bodies
+    fn scoped_block(&mut self, statement: &Statement) -> Block {
+        self.scopes.push();
+        let block = match statement {
+            Statement::Compound(compound) => self.statements(&compound.statement_list),
+            simple => self.statements(core::slice::from_ref(simple)),
+        };
+        self.scopes.pop();
+        block
+    }
+
+    /// Analyze one statement, which may expand to several HIR statements,
+    /// recording its errors
+    fn statement(&mut self, statement: &Statement) -> Vec<Stmt> {
+        let simple = match statement {
+            Statement::Compound(compound) => {
+                self.scopes.push();
+                let block = self.statements(&compound.statement_list);
+                self.scopes.pop();
+                return vec![Stmt::Block(block)];
+            }
+            Statement::Simple(simple) => simple,
+        };
+        // Errors are located after the statements before this one, and those
+        // nested in it come after it
+        let state = self
+            .current
+            .as_mut()
+            .expect("statement outside of function");
+        let outer = (self.anchor.take(), state.preceding);
+        state.preceding = state.statements.len();
+        self.anchor = locate::statement_anchor(simple);
+        state.statements.extend(self.anchor.clone());
+        let stmts = self.simple_statement(simple).unwrap_or_else(|e| {
+            self.error(e);
+            Vec::new()
+        });
+        let state = self
+            .current
+            .as_mut()
+            .expect("statement outside of function");
+        (self.anchor, state.preceding) = outer;
+        stmts
+    }
+
+    fn simple_statement(&mut self, simple: &SimpleStatement) -> Result<Vec<Stmt>> {
+        match simple {
+            SimpleStatement::Declaration(decl) => self.local_declaration(decl),
+            SimpleStatement::Expression(None) => Ok(Vec::new()),
+            SimpleStatement::Expression(Some(expr)) => Ok(vec![Stmt::Expr(self.expr(expr)?)]),
+            SimpleStatement::Selection(selection) => {
+                let cond = self.condition(&selection.cond)?;
+                let (then_branch, else_branch) = match &selection.rest {
+                    SelectionRestStatement::Statement(then) => (self.scoped_block(then), None),
+                    SelectionRestStatement::Else(then, otherwise) => {
+                        (self.scoped_block(then), Some(self.scoped_block(otherwise)))
+                    }
+                };
+                Ok(vec![Stmt::If {
+                    cond,
+                    then_branch,
+                    else_branch,
+                }])
+            }
+            SimpleStatement::Switch(switch) => self.switch(switch),
+            SimpleStatement::CaseLabel(_) => Err(SemaError::CaseOutsideSwitch),
+            SimpleStatement::Iteration(iteration) => self.iteration(iteration),
+            SimpleStatement::Jump(jump) => self.jump(jump).map(|s| vec![s]),
+        }
+    }
+
+    fn condition(&mut self, cond: &syntax::Expr) -> Result<Expr> {
+        let cond = self.expr(cond)?;
+        self.coerce(cond, &Type::BOOL, "condition")
+    }
+
+    fn loop_condition(&mut self, cond: &Condition) -> Result<Expr> {
+        match cond {
+            Condition::Expr(expr) => self.condition(expr),
+            Condition::Assignment(..) => {
+                Err(SemaError::Unsupported("declarations in conditions".into()))
+            }
+        }
+    }
+
+    fn local_declaration(&mut self, decl: &Declaration) -> Result<Vec<Stmt>> {
+        let list = match decl {
+            Declaration::InitDeclaratorList(list) => list,
+            Declaration::Precision(qualifier, ty) => {
+                self.default_precision(qualifier, ty);
+                return Ok(Vec::new());
+            }
+            Declaration::FunctionPrototype(_) => {
+                return Err(SemaError::Unsupported("local function declarations".into()))
+            }
+            Declaration::Block(_) | Declaration::Global(..) => {
+                return Err(SemaError::Unsupported(
+                    "block declarations inside functions".into(),
+                ))
+            }
+        };
+        let is_const = match storage_qualifier(&list.head.ty.qualifier) {
+            None => false,
+            Some(StorageQualifier::Const) => true,
+            Some(other) => {
+                return Err(SemaError::Unsupported(format!(
+                    "storage qualifier `{:?}` on a local",
+                    other
+                )))
+            }
+        };
+        let ty = self.resolve_type(&list.head.ty.ty)?;
+        let mut stmts = Vec::new();
+        for (name, array, init) in super::declarators(&list.head, list) {
+            let ty = self.declarator_type(&ty, array)?;
+            // The initializer is analyzed before the name comes into scope. If
+            // it is in error, the local is still declared with its type, so
+            // that later uses do not report it undeclared
+            let init = match init {
+                Some(init) => match self.initializer(init, &ty) {
+                    Ok(init) => Some(init),
+                    Err(e) => {
+                        self.error(e);
+                        None
+                    }
+                },
+                None if is_const => return Err(SemaError::MissingInitializer(name.into())),
+                None => None,
+            };
+            let ty = super::sized_type(ty, init.as_ref())?;
+            // The values of const locals are kept for later constant expressions
+            let value = match &init {
+                Some(init) if is_const => Some(
+                    self.constant(init)
+                        .ok_or_else(|| SemaError::NonConstantInitializer(name.into()))?,
+                ),
+                _ => None,
+            };
+            let precision = self.precision(&list.head.ty.qualifier, &ty);
+            let id = self.add_local(name, ty, is_const, precision)?;
+            let init = match value {
+                Some(value) => {
+                    let init = init.map(|init| value.to_expr(&init.ty));
+                    self.current
+                        .as_mut()
+                        .expect("local outside of function")
+                        .const_values
+                        .insert(id, value);
+                    init
+                }
+                None => init,
+            };
+            stmts.push(Stmt::Decl(id, init));
+        }
+        Ok(stmts)
+    }
+
+    fn iteration(&mut self, iteration: &IterationStatement) -> Result<Vec<Stmt>> {
+        match iteration {
+            IterationStatement::While(cond, body) => {
+                self.require(Feature::WhileLoop)?;
+                self.scopes.push();
+                let cond = self.loop_condition(cond);
+                let body = self.loop_body(body);
+                self.scopes.pop();
+                Ok(vec![Stmt::Loop {
+                    cond: Some(cond?),
+                    step: None,
+                    body,
+                    test_first: true,
+                }])
+            }
+            IterationStatement::DoWhile(body, cond) => {
+                self.require(Feature::WhileLoop)?;
+                let body = self.loop_body(body);
+                let cond = self.condition(cond)?;
+                Ok(vec![Stmt::Loop {
+                    cond: Some(cond),
+                    step: None,
+                    body,
+                    test_first: false,
+                }])
+            }
+            IterationStatement::For(init, rest, body) => {
+                // The whole for statement gets a scope for its init declaration
+                self.scopes.push();
+                let result = self.for_loop(init, rest, body);
+                self.scopes.pop();
+                result.map(|block| vec![Stmt::Block(block)])
+            }
+        }
+    }
+
+    fn for_loop(
+        &mut self,
+        init: &ForInitStatement,
+        rest: &syntax::ForRestStatement,
+        body: &Statement,
+    ) -> Result<Block> {
+        let mut block = match init {
+            ForInitStatement::Expression(None) => Vec::new(),
+            ForInitStatement::Expression(Some(expr)) => vec![Stmt::Expr(self.expr(expr)?)],
+            ForInitStatement::Declaration(decl) => self.local_declaration(decl)?,
+        };
+        let cond = rest
+            .condition
+            .as_ref()
+            .map(|c| self.loop_condition(c))
+            .transpose()?;
+        let step = rest.post_expr.as_ref().map(|e| self.expr(e)).transpose()?;
+        let body = self.loop_body(body);
+        if !self.is_restricted_for_loop(&block, cond.as_ref(), step.as_ref(), &body) {
+            self.require(Feature::GeneralForLoop)?;
+        }
+        block.push(Stmt::Loop {
+            cond,
+            step,
+            body,
+            test_first: true,
+        });
+        Ok(block)
+    }
+
+    /// Whether a `for` loop has the form GLSL ES 1.00 requires
+    ///
+    /// The loop declares one `int` or `float` index with a constant
+    /// initializer, compares it with a constant, steps it with `++`, `--`,
+    /// `+=` or `-=` by a constant, and does not otherwise modify it.
+    fn is_restricted_for_loop(
+        &self,
+        init: &[Stmt],
+        cond: Option<&Expr>,
+        step: Option<&Expr>,
+        body: &[Stmt],
+    ) -> bool {
+        let [Stmt::Decl(index, Some(value))] = init else {
+            return false;
+        };
+        let is_index =
+            |expr: &Expr| matches!(expr.kind, ExprKind::Var(VarRef::Local(id)) if id == *index);
+        let is_constant = |expr: &Expr| self.constant(expr).is_some();
+        let cond_ok = match cond.map(|c| &c.kind) {
+            Some(ExprKind::Binary(op, lhs, rhs)) => {
+                op.is_comparison() && is_index(lhs) && is_constant(rhs)
+            }
+            _ => false,
+        };
+        let step_ok = match step.map(|s| &s.kind) {
+            Some(ExprKind::Unary(op, operand)) => op.is_inc_dec() && is_index(operand),
+            Some(ExprKind::Assign(Some(BinaryOp::Add | BinaryOp::Sub), lhs, rhs)) => {
+                is_index(lhs) && is_constant(rhs)
+            }
+            _ => false,
+        };
+        matches!(value.ty, Type::Scalar(ScalarType::Int | ScalarType::Float))
+            && is_constant(value)
+            && cond_ok
+            && step_ok
+            && !loops::block_modifies(&self.module, body, *index)
+    }
+
+    fn loop_body(&mut self, body: &Statement) -> Block {
+        self.breakable_push(BreakTarget::Loop);
+        let block = self.scoped_block(body);
+        self.breakable_pop();
+        block
+    }
+
+    fn breakable_push(&mut self, target: BreakTarget) {
+        self.current
+            .as_mut()
+            .expect("statement outside of function")
+            .breakable
+            .push(target);
+    }
+
+    fn breakable_pop(&mut self) {
+        self.current
+            .as_mut()
+            .expect("statement outside of function")
+            .breakable
+            .pop();
+    }
+
+    fn switch(&mut self, switch: &syntax::SwitchStatement) -> Result<Vec<Stmt>> {
+        self.require(Feature::Switch)?;
+        let selector = self.expr(&switch.head)?;
+        if !matches!(selector.ty, Type::Scalar(s) if s.is_integer()) {
+            return Err(SemaError::TypeMismatch {
+                expected: Type::INT,
+                found: selector.ty,
+                context: "switch selector",
+            });
+        }
+
+        self.breakable_push(BreakTarget::Switch);
+        self.scopes.push();
+        let cases = self.switch_cases(&switch.body, &selector.ty);
+        self.scopes.pop();
+        self.breakable_pop();
+
+        Ok(vec![Stmt::Switch {
+            selector,
+            cases: cases?,
+        }])
+    }
+
+    fn switch_cases(&mut self, body: &[Statement], selector: &Type) -> Result<Vec<SwitchCase>> {
+        let mut cases: Vec<SwitchCase> = Vec::new();
+        let mut seen = Vec::new();
+        for statement in body {
+            let label = match statement {
+                Statement::Simple(simple) => match &**simple {
+                    SimpleStatement::CaseLabel(label) => Some(label),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let Some(label) = label else {
+                let Some(case) = cases.last_mut() else {
+                    return Err(SemaError::Unsupported(
+                        "statements before the first case label".into(),
+                    ));
+                };
+                case.body.extend(self.statement(statement));
+                continue;
+            };
+            let value = match label {
+                CaseLabel::Case(expr) => Some(self.case_value(expr, selector)?),
+                CaseLabel::Def => None,
+            };
+            if seen.contains(&value) {
+                return Err(SemaError::Redefinition(match value {
+                    Some(v) => format!("case {}", v),
+                    None => "default".into(),
+                }));
+            }
+            seen.push(value);
+            // Consecutive labels share one body
+            match cases.last_mut() {
+                Some(case) if case.body.is_empty() => case.labels.push(value),
+                _ => cases.push(SwitchCase {
+                    labels: vec![value],
+                    body: Vec::new(),
+                }),
+            }
+        }
+        Ok(cases)
+    }
+
+    fn case_value(&mut self, expr: &syntax::Expr, selector: &Type) -> Result<i64> {
+        let expr = self.expr(expr)?;
+        // Case labels must have the selector's type exactly
+        if expr.ty != *selector {
+            return Err(SemaError::TypeMismatch {
+                expected: selector.clone(),
+                found: expr.ty,
+                context: "case label",
+            });
+        }
+        self.constant(&expr)
+            .and_then(|value| value.as_int())
+            .ok_or(SemaError::NotConstant("case label"))
+    }
+
+    fn jump(&mut self, jump: &JumpStatement) -> Result<Stmt> {
+        let state = self
+            .current
+            .as_ref()
+            .expect("statement outside of function");
+        match jump {
+            JumpStatement::Break => {
+                if state.breakable.is_empty() {
+                    return Err(SemaError::BreakOutsideLoop);
+                }
+                Ok(Stmt::Break)
+            }
+            JumpStatement::Continue => {
+                if !state.breakable.contains(&BreakTarget::Loop) {
+                    return Err(SemaError::ContinueOutsideLoop);
+                }
+                Ok(Stmt::Continue)
+            }
+            JumpStatement::Discard => Ok(Stmt::Discard),
+            JumpStatement::Return(value) => {
+                let return_type = state.return_type.clone();
+                match value {
+                    None if return_type == Type::Void => Ok(Stmt::Return(None)),
+                    None => Err(SemaError::TypeMismatch {
+                        expected: return_type,
+                        found: Type::Void,
+                        context: "return",
+                    }),
+                    Some(value) => {
+                        let value = self.expr(value)?;
+                        let value = self.coerce(value, &return_type, "return")?;
+                        Ok(Stmt::Return(Some(value)))
+                    }
+                }
+            }
+        }
+    }
+}
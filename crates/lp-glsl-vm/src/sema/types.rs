@@ -0,0 +1,232 @@
+use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+use core::fmt;
+
+use glsl::syntax::TypeSpecifierNonArray;
+
+/// Scalar component type of a GLSL value
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ScalarType {
+    Bool,
+    Int,
+    UInt,
+    Float,
+}
+
+impl ScalarType {
+    /// Whether arithmetic operators apply to this scalar type
+    pub fn is_numeric(self) -> bool {
+        !matches!(self, ScalarType::Bool)
+    }
+
+    /// Whether bitwise and shift operators apply to this scalar type
+    pub fn is_integer(self) -> bool {
+        matches!(self, ScalarType::Int | ScalarType::UInt)
+    }
+
+    fn vector_prefix(self) -> &'static str {
+        match self {
+            ScalarType::Bool => "b",
+            ScalarType::Int => "i",
+            ScalarType::UInt => "u",
+            ScalarType::Float => "",
+        }
+    }
+}
+
+impl fmt::Display for ScalarType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScalarType::Bool => "bool",
+            ScalarType::Int => "int",
+            ScalarType::UInt => "uint",
+            ScalarType::Float => "float",
+        })
+    }
+}
+
+/// Type of a GLSL value after semantic analysis
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Type {
+    Void,
+    Scalar(ScalarType),
+    /// Vector with 2 to 4 components
+    Vector(ScalarType, u8),
+    /// Square `float` matrix with 2 to 4 columns, stored column-major
+    Matrix(u8),
+    /// User-defined struct
+    Struct(Rc<StructType>),
+    /// Fixed-size array with its element count
+    Array(Box<Type>, u32),
+}
+
+/// A user-defined struct
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StructType {
+    pub name: String,
+    pub fields: Vec<Field>,
+}
+
+impl StructType {
+    /// Index and type of the field called `name`
+    pub fn field(&self, name: &str) -> Option<(u32, &Type)> {
+        self.fields
+            .iter()
+            .enumerate()
+            .find(|(_, f)| f.name == name)
+            .map(|(i, f)| (i as u32, &f.ty))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Field {
+    pub name: String,
+    pub ty: Type,
+}
+
+impl Type {
+    pub const BOOL: Type = Type::Scalar(ScalarType::Bool);
+    pub const FLOAT: Type = Type::Scalar(ScalarType::Float);
+    pub const INT: Type = Type::Scalar(ScalarType::Int);
+    pub const UINT: Type = Type::Scalar(ScalarType::UInt);
+
+    /// Build a scalar (`size == 1`) or vector type
+    pub fn vector(scalar: ScalarType, size: u8) -> Type {
+        if size == 1 {
+            Type::Scalar(scalar)
+        } else {
+            Type::Vector(scalar, size)
+        }
+    }
+
+    /// Map a parsed type specifier to a semantic type, if it is supported
+    pub fn from_syntax(ty: &TypeSpecifierNonArray) -> Option<Type> {
+        use ScalarType::*;
+        use TypeSpecifierNonArray as T;
+
+        Some(match ty {
+            T::Void => Type::Void,
+            T::Bool => Type::Scalar(Bool),
+            T::Int => Type::Scalar(Int),
+            T::UInt => Type::Scalar(UInt),
+            T::Float => Type::Scalar(Float),
+            T::Vec2 => Type::Vector(Float, 2),
+            T::Vec3 => Type::Vector(Float, 3),
+            T::Vec4 => Type::Vector(Float, 4),
+            T::BVec2 => Type::Vector(Bool, 2),
+            T::BVec3 => Type::Vector(Bool, 3),
+            T::BVec4 => Type::Vector(Bool, 4),
+            T::IVec2 => Type::Vector(Int, 2),
+            T::IVec3 => Type::Vector(Int, 3),
+            T::IVec4 => Type::Vector(Int, 4),
+            T::UVec2 => Type::Vector(UInt, 2),
+            T::UVec3 => Type::Vector(UInt, 3),
+            T::UVec4 => Type::Vector(UInt, 4),
+            T::Mat2 => Type::Matrix(2),
+            T::Mat3 => Type::Matrix(3),
+            T::Mat4 => Type::Matrix(4),
+            _ => return None,
+        })
+    }
+
+    /// Look up a builtin type by its GLSL keyword, as used in constructor calls
+    pub fn from_name(name: &str) -> Option<Type> {
+        use ScalarType::*;
+
+        let (prefix, size) = match name {
+            "bool" => return Some(Type::BOOL),
+            "int" => return Some(Type::INT),
+            "uint" => return Some(Type::UINT),
+            "float" => return Some(Type::FLOAT),
+            "mat2" | "mat2x2" => return Some(Type::Matrix(2)),
+            "mat3" | "mat3x3" => return Some(Type::Matrix(3)),
+            "mat4" | "mat4x4" => return Some(Type::Matrix(4)),
+            _ if name.len() >= 4 => name.split_at(name.len() - 1),
+            _ => return None,
+        };
+        let size = match size {
+            "2" => 2,
+            "3" => 3,
+            "4" => 4,
+            _ => return None,
+        };
+        let scalar = match prefix {
+            "vec" => Float,
+            "bvec" => Bool,
+            "ivec" => Int,
+            "uvec" => UInt,
+            _ => return None,
+        };
+        Some(Type::Vector(scalar, size))
+    }
+
+    /// Type with the same shape and components of type `scalar`
+    pub fn with_scalar(&self, scalar: ScalarType) -> Type {
+        match self {
+            Type::Scalar(_) => Type::Scalar(scalar),
+            Type::Vector(_, n) => Type::Vector(scalar, *n),
+            other => other.clone(),
+        }
+    }
+
+    /// Scalar component type of scalars, vectors and matrices
+    pub fn scalar_type(&self) -> Option<ScalarType> {
+        match self {
+            Type::Scalar(s) | Type::Vector(s, _) => Some(*s),
+            Type::Matrix(_) => Some(ScalarType::Float),
+            _ => None,
+        }
+    }
+
+    /// Number of scalar components in a scalar, vector or matrix
+    pub fn component_count(&self) -> Option<u32> {
+        match self {
+            Type::Scalar(_) => Some(1),
+            Type::Vector(_, n) => Some(*n as u32),
+            Type::Matrix(n) => Some(*n as u32 * *n as u32),
+            _ => None,
+        }
+    }
+
+    /// Type of one column of a matrix
+    pub fn column_type(&self) -> Option<Type> {
+        match self {
+            Type::Matrix(n) => Some(Type::Vector(ScalarType::Float, *n)),
+            _ => None,
+        }
+    }
+
+    /// Whether this is a struct or array, which live in memory rather than registers
+    pub fn is_aggregate(&self) -> bool {
+        matches!(self, Type::Struct(_) | Type::Array(..))
+    }
+
+    pub fn is_scalar(&self) -> bool {
+        matches!(self, Type::Scalar(_))
+    }
+
+    pub fn is_vector(&self) -> bool {
+        matches!(self, Type::Vector(..))
+    }
+
+    pub fn is_matrix(&self) -> bool {
+        matches!(self, Type::Matrix(_))
+    }
+
+    /// Whether values of this type can be built with a constructor call
+    pub fn is_constructible(&self) -> bool {
+        !matches!(self, Type::Void)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Void => f.write_str("void"),
+            Type::Scalar(s) => write!(f, "{}", s),
+            Type::Vector(s, n) => write!(f, "{}vec{}", s.vector_prefix(), n),
+            Type::Matrix(n) => write!(f, "mat{}", n),
+            Type::Struct(s) => f.write_str(&s.name),
+            Type::Array(element, n) => write!(f, "{}[{}]", element, n),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use alloc::{format, string::String};
+use core::fmt;
+
+use glsl::syntax::{PreprocessorVersion, PreprocessorVersionProfile};
+
+use super::types::{ScalarType, Type};
+
+/// GLSL language version a shader targets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Version {
+    /// Version number as written in `#version`, such as `300` or `450`
+    pub number: u16,
+    /// Whether this is GLSL ES
+    pub es: bool,
+}
+
+impl Version {
+    /// Version of a shader without a `#version` directive
+    pub const DEFAULT: Version = Version {
+        number: 110,
+        es: false,
+    };
+
+    pub fn from_directive(directive: &PreprocessorVersion) -> Self {
+        Self {
+            number: directive.version,
+            // Version 100 only exists as GLSL ES
+            es: directive.version == 100
+                || matches!(directive.profile, Some(PreprocessorVersionProfile::ES)),
+        }
+    }
+
+    /// Whether a value of scalar type `from` implicitly converts to `to`
+    ///
+    /// GLSL ES has no implicit conversions. Desktop GLSL allows int and uint
+    /// to float from 1.20 and int to uint from 4.00.
+    pub fn converts(self, from: ScalarType, to: ScalarType) -> bool {
+        if from == to {
+            return true;
+        }
+        if self.es {
+            return false;
+        }
+        match (from, to) {
+            (ScalarType::Int | ScalarType::UInt, ScalarType::Float) => self.number >= 120,
+            (ScalarType::Int, ScalarType::UInt) => self.number >= 400,
+            _ => false,
+        }
+    }
+
+    /// Whether a value of type `from` implicitly converts to `to`
+    ///
+    /// Conversions apply component-wise between types of the same shape.
+    pub fn converts_type(self, from: &Type, to: &Type) -> bool {
+        match (from, to) {
+            (Type::Scalar(a), Type::Scalar(b)) => self.converts(*a, *b),
+            (Type::Vector(a, n), Type::Vector(b, m)) => n == m && self.converts(*a, *b),
+            _ => from == to,
+        }
+    }
+
+    /// Whether shaders targeting this version may use `feature`
+    pub fn supports(self, feature: Feature) -> bool {
+        let (desktop, es) = feature.minimum();
+        self.number >= if self.es { es } else { desktop }
+    }
+}
+
+impl Default for Version {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.number)?;
+        if self.es && self.number != 100 {
+            f.write_str(" es")?;
+        }
+        Ok(())
+    }
+}
+
+/// Language feature that only some versions provide
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// `uint`, `uvec*` and `u`-suffixed literals
+    UnsignedIntegers,
+    /// `%` or a bitwise operator, by its spelling
+    IntegerOperator(&'static str),
+    Switch,
+    /// `while` and `do`-`while` loops
+    WhileLoop,
+    /// `for` loop not of the restricted form GLSL ES 1.00 requires
+    GeneralForLoop,
+}
+
+impl Feature {
+    /// First desktop and first ES version providing the feature
+    fn minimum(self) -> (u16, u16) {
+        match self {
+            Feature::UnsignedIntegers | Feature::IntegerOperator(_) | Feature::Switch => (130, 300),
+            Feature::WhileLoop | Feature::GeneralForLoop => (110, 300),
+        }
+    }
+
+    /// Versions that provide the feature, for diagnostics
+    pub fn requirement(self) -> String {
+        match self.minimum() {
+            (110, es) => format!("requires desktop GLSL or `#version {} es`", es),
+            (desktop, es) => format!("requires `#version {}` or `#version {} es`", desktop, es),
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Feature::UnsignedIntegers => f.write_str("the `uint` type"),
+            Feature::IntegerOperator(op) => write!(f, "operator `{}`", op),
+            Feature::Switch => f.write_str("the `switch` statement"),
+            Feature::WhileLoop => f.write_str("the `while` loop"),
+            Feature::GeneralForLoop => f.write_str("this form of `for` loop"),
+        }
+    }
+}
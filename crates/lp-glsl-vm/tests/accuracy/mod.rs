@@ -0,0 +1,348 @@
+//! Accuracy of float arithmetic and builtins in a numeric format
+//!
+//! [`measure`] builds a function applying one operation to its parameters,
+//! [lowers](lp_glsl_vm::numeric::lower) it to a [`Numeric`] format and runs
+//! it over inputs sampled from the ranges of a [`Case`]. Each result is
+//! compared with the exact value the case computes in `f64` from the inputs
+//! as the format holds them, and the [`Report`] keeps the largest absolute
+//! and ULP errors with the inputs giving them, for [`Bounds`] to check.
+//!
+//! The function runs in the guest: [`guest`] hands it to the `accuracy`
+//! program of embive-program, built for riscv32imac, which executes it in
+//! an `R5Vm` with the runtime routines compiled for the guest. [`host`]
+//! runs it with the interpreter on the host instead.
+
+use std::{fmt, ops::RangeInclusive, path::Path, process::Command, sync::OnceLock};
+
+use lp_glsl_vm::{
+    ir::{
+        interpret::{Exit, Interpreter},
+        parse, BinaryOp, Builtin, Function, FunctionBuilder, Module, Signature, Type,
+    },
+    numeric::{self, Numeric},
+    r5vm::R5Vm,
+};
+
+/// Stack of the interpreter, which a single operation barely uses
+const STACK_SIZE: u32 = 4096;
+const STEP_LIMIT: u64 = 1_000_000;
+
+/// RAM of the VM running the guest program
+const GUEST_RAM_SIZE: usize = 4 * 1024 * 1024;
+
+/// Runs the function `@f` of a module on the arguments of each sample and
+/// returns its results
+pub type Run = fn(&Module, &[Vec<u32>]) -> Vec<u32>;
+
+/// Operation whose accuracy is measured
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Binary(BinaryOp),
+    /// Builtin called with `arity` float arguments
+    Builtin(Builtin, usize),
+}
+
+impl Operation {
+    pub fn arity(self) -> usize {
+        match self {
+            Operation::Binary(_) => 2,
+            Operation::Builtin(_, arity) => arity,
+        }
+    }
+}
+
+/// An operation, the ranges its inputs are sampled from and its exact value
+#[derive(Clone, Debug)]
+pub struct Case {
+    pub name: &'static str,
+    pub operation: Operation,
+    /// Range of each input
+    pub inputs: Vec<RangeInclusive<f32>>,
+    /// Exact result for the inputs, computed on the host
+    pub exact: fn(&[f64]) -> f64,
+}
+
+impl Case {
+    pub fn new(
+        name: &'static str,
+        operation: Operation,
+        inputs: &[RangeInclusive<f32>],
+        exact: fn(&[f64]) -> f64,
+    ) -> Case {
+        assert_eq!(inputs.len(), operation.arity(), "one range per input");
+        Case {
+            name,
+            operation,
+            inputs: inputs.to_vec(),
+            exact,
+        }
+    }
+
+    /// Inputs of sample `i` of `count`
+    ///
+    /// The first samples are the corners of the ranges, and the others
+    /// follow an additive recurrence with irrational steps, which covers
+    /// the ranges evenly in every dimension.
+    fn sample(&self, i: usize, count: usize) -> Vec<f32> {
+        const STEPS: [f64; 3] = [
+            0.618_033_988_749_894_9,
+            0.414_213_562_373_095,
+            0.732_050_807_568_877_2,
+        ];
+        let corners = 1 << self.inputs.len();
+        self.inputs
+            .iter()
+            .enumerate()
+            .map(|(dim, range)| {
+                let t = if i < corners.min(count) {
+                    (i >> dim & 1) as f64
+                } else {
+                    (0.5 + i as f64 * STEPS[dim % STEPS.len()]).fract()
+                };
+                let (lo, hi) = (*range.start() as f64, *range.end() as f64);
+                (lo + (hi - lo) * t) as f32
+            })
+            .collect()
+    }
+}
+
+/// Largest errors of an operation over the samples of a [`Case`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub name: &'static str,
+    pub numeric: Numeric,
+    pub samples: usize,
+    /// Largest absolute difference from the exact value
+    pub max_abs: f64,
+    /// Inputs giving `max_abs`, as the format holds them
+    pub worst_abs: Vec<f64>,
+    /// Largest error in units in the last place: the distance in `f32`
+    /// values from the exact value rounded to `f32`, or the absolute error
+    /// in steps of a fixed-point format, whose last place is the same
+    /// whatever the magnitude
+    pub max_ulp: f64,
+    /// Inputs giving `max_ulp`
+    pub worst_ulp: Vec<f64>,
+}
+
+/// Errors a [`Report`] must stay within
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub abs: f64,
+    pub ulp: f64,
+}
+
+impl Report {
+    /// Describe every bound the errors exceed, or `Ok` if none
+    pub fn check(&self, bounds: Bounds) -> Result<(), String> {
+        let mut errors = Vec::new();
+        if self.max_abs > bounds.abs {
+            errors.push(format!(
+                "absolute error {:e} past {:e} at {:?}",
+                self.max_abs, bounds.abs, self.worst_abs
+            ));
+        }
+        if self.max_ulp > bounds.ulp {
+            errors.push(format!(
+                "{} ulp past {} at {:?}",
+                self.max_ulp, bounds.ulp, self.worst_ulp
+            ));
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "{} in {}: {}",
+            self.name,
+            self.numeric,
+            errors.join(", ")
+        ))
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in {}: max abs {:e} at {:?}, max {} ulp at {:?}, {} samples",
+            self.name,
+            self.numeric,
+            self.max_abs,
+            self.worst_abs,
+            self.max_ulp,
+            self.worst_ulp,
+            self.samples
+        )
+    }
+}
+
+/// Run `case` with `run` on `samples` inputs in `numeric` and report its
+/// errors
+///
+/// A NaN result counts as exact if the exact value is NaN too, and as an
+/// infinite error otherwise.
+pub fn measure(case: &Case, numeric: Numeric, samples: usize, run: Run) -> Report {
+    let mut module = Module::default();
+    module.add_function(function(case.operation));
+    numeric::lower(&mut module, numeric);
+    let args: Vec<Vec<u32>> = (0..samples)
+        .map(|i| {
+            case.sample(i, samples)
+                .into_iter()
+                .map(|x| numeric.encode(x))
+                .collect()
+        })
+        .collect();
+    let results = run(&module, &args);
+    assert_eq!(results.len(), samples, "results of {}", case.name);
+
+    let mut report = Report {
+        name: case.name,
+        numeric,
+        samples,
+        max_abs: 0.0,
+        worst_abs: Vec::new(),
+        max_ulp: 0.0,
+        worst_ulp: Vec::new(),
+    };
+    for (bits, result) in args.iter().zip(results) {
+        let inputs: Vec<f64> = bits.iter().map(|&b| decode(numeric, b)).collect();
+        let (abs, ulp) = error(numeric, result, (case.exact)(&inputs));
+        if abs > report.max_abs || report.worst_abs.is_empty() {
+            report.max_abs = abs;
+            report.worst_abs = inputs.clone();
+        }
+        if ulp > report.max_ulp || report.worst_ulp.is_empty() {
+            report.max_ulp = ulp;
+            report.worst_ulp = inputs;
+        }
+    }
+    report
+}
+
+/// Run `@f` with the interpreter on the host, on the module parsed back from
+/// its text as the guest gets it
+pub fn host(module: &Module, args: &[Vec<u32>]) -> Vec<u32> {
+    let module = parse(&module.to_string()).expect("printed IR does not parse");
+    let func = module.find_function("f").expect("no function @f");
+    let mut interpreter = Interpreter::with_limits(&module, STACK_SIZE, STEP_LIMIT);
+    args.iter()
+        .map(|args| {
+            let args: Vec<[u32; 4]> = args.iter().map(|&a| [a, 0, 0, 0]).collect();
+            match interpreter.call(func, &args) {
+                Ok(Exit::Return(results)) => results[0][0],
+                result => panic!("@f of {:?}: {:?}", args, result),
+            }
+        })
+        .collect()
+}
+
+/// Run `@f` in the guest program, in an [`R5Vm`]
+pub fn guest(module: &Module, args: &[Vec<u32>]) -> Vec<u32> {
+    let text = module.to_string();
+    let mut input = vec![text.len() as u32];
+    input.extend(text.as_bytes().chunks(4).map(|chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        u32::from_le_bytes(word)
+    }));
+    input.push(args.first().map_or(0, Vec::len) as u32);
+    input.push(args.len() as u32);
+    input.extend(args.iter().flatten());
+
+    let mut vm = R5Vm::new(GUEST_RAM_SIZE);
+    vm.load(guest_program())
+        .expect("loading the guest program failed");
+    vm.set_input(input);
+    vm.run().expect("running the guest program failed");
+    vm.output().to_vec()
+}
+
+/// ELF of the guest program, built on first use
+fn guest_program() -> &'static [u8] {
+    static ELF: OnceLock<Vec<u8>> = OnceLock::new();
+    ELF.get_or_init(|| {
+        let workspace_root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .parent()
+            .and_then(Path::parent)
+            .expect("no workspace root");
+        let target = "riscv32imac-unknown-none-elf";
+        let output = Command::new("cargo")
+            .args(["build", "--release", "--package", "embive-program"])
+            .args(["--bin", "accuracy", "--target", target])
+            .current_dir(workspace_root)
+            .output()
+            .expect("running cargo failed");
+        assert!(
+            output.status.success(),
+            "building the guest program failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let path = workspace_root.join(format!("target/{}/release/accuracy", target));
+        std::fs::read(&path).unwrap_or_else(|e| panic!("reading {:?} failed: {}", path, e))
+    })
+}
+
+/// Function `@f` applying `operation` to its `f32` parameters
+fn function(operation: Operation) -> Function {
+    let signature = Signature {
+        params: vec![Type::F32; operation.arity()],
+        returns: vec![Type::F32],
+    };
+    let mut func = Function::new("f", signature);
+    let mut b = FunctionBuilder::new(&mut func);
+    let entry = b.create_entry_block();
+    b.switch_to_block(entry);
+    let params = b.block_params(entry).to_vec();
+    let result = match operation {
+        Operation::Binary(op) => b.binary(op, params[0], params[1]),
+        Operation::Builtin(builtin, _) => b.builtin(builtin, &params, Type::F32),
+    };
+    b.ret(&[result]);
+    b.finish();
+    func
+}
+
+/// Exact value of the bits of a word in `numeric`
+///
+/// Unlike [`Numeric::decode`], this keeps every bit of 32-bit fixed-point
+/// formats, which `f32` cannot hold.
+fn decode(numeric: Numeric, bits: u32) -> f64 {
+    match numeric {
+        Numeric::Fixed(fixed) => bits as i32 as f64 / fixed.one() as f64,
+        Numeric::Float | Numeric::SoftFloat => f32::from_bits(bits) as f64,
+    }
+}
+
+/// Absolute and ULP error of the bits of `result` in `numeric` from `exact`
+fn error(numeric: Numeric, result: u32, exact: f64) -> (f64, f64) {
+    let value = decode(numeric, result);
+    if value.is_nan() || exact.is_nan() {
+        if value.is_nan() && exact.is_nan() {
+            return (0.0, 0.0);
+        }
+        return (f64::INFINITY, f64::INFINITY);
+    }
+    let abs = if value == exact {
+        0.0
+    } else {
+        (value - exact).abs()
+    };
+    let ulp = match numeric {
+        Numeric::Fixed(fixed) => abs / fixed.epsilon() as f64,
+        Numeric::Float | Numeric::SoftFloat => {
+            order(value as f32).abs_diff(order(exact as f32)) as f64
+        }
+    };
+    (abs, ulp)
+}
+
+/// Position of `x` among the floats, both zeros being 0
+fn order(x: f32) -> i64 {
+    let magnitude = (x.to_bits() & 0x7fff_ffff) as i64;
+    if x.is_sign_negative() {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
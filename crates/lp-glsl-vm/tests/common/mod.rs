@@ -0,0 +1,59 @@
+//! Helpers shared by the integration tests
+//!
+//! Each test file compiles this module on its own and uses only some of it.
+#![allow(dead_code)]
+
+use glsl::{parser::Parse, syntax::TranslationUnit};
+use lp_glsl_vm::sema::{
+    analyze,
+    const_eval::{ConstEval, Value},
+    hir::{Expr, Literal, Module, Stmt},
+    SemaError,
+};
+
+pub fn analyze_source(source: &str) -> Result<Module, Vec<SemaError>> {
+    let tu = TranslationUnit::parse(source).expect("GLSL parsing failed");
+    analyze(&tu)
+}
+
+/// Folded value of the global called `name`
+pub fn global_value(module: &Module, name: &str) -> Value {
+    let global = module.globals.iter().find(|g| g.name == name).unwrap();
+    ConstEval::new(module)
+        .eval(global.init.as_ref().unwrap())
+        .unwrap()
+}
+
+/// Components of a value made of floats
+pub fn floats(value: &Value) -> Vec<f32> {
+    let Value::Components(c) = value else {
+        panic!("expected components, found {:?}", value);
+    };
+    c.iter()
+        .map(|l| match l {
+            Literal::Float(v) => *v,
+            other => panic!("expected a float, found {:?}", other),
+        })
+        .collect()
+}
+
+pub fn assert_close(found: &[f32], expected: &[f32]) {
+    assert_eq!(found.len(), expected.len());
+    for (f, e) in found.iter().zip(expected) {
+        assert!((f - e).abs() < 1e-5, "{:?} != {:?}", found, expected);
+    }
+}
+
+/// Top-level expressions of the statements in `main`
+pub fn main_exprs(module: &Module) -> Vec<&Expr> {
+    let main = module.function(module.find_function("main").unwrap());
+    main.body
+        .as_ref()
+        .unwrap()
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::Expr(expr) | Stmt::Decl(_, Some(expr)) => Some(expr),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,280 @@
+mod accuracy;
+
+use accuracy::{guest, host, measure, Bounds, Case, Operation};
+use lp_glsl_vm::{
+    ir::{BinaryOp, Builtin},
+    numeric::{Fixed, Numeric, Overflow},
+};
+
+const SAMPLES: usize = 2000;
+
+const Q16: Numeric = Numeric::Fixed(Fixed::Q16_16);
+const Q8: Numeric = Numeric::Fixed(Fixed::new(8, 8, Overflow::Saturate));
+
+fn unary(builtin: Builtin) -> Operation {
+    Operation::Builtin(builtin, 1)
+}
+
+fn binary(builtin: Builtin) -> Operation {
+    Operation::Builtin(builtin, 2)
+}
+
+/// Every case, with ranges whose results fit Q16.16
+fn cases() -> Vec<Case> {
+    use BinaryOp as Op;
+    use Builtin as B;
+
+    vec![
+        Case::new(
+            "add",
+            Operation::Binary(Op::Add),
+            &[-1e3..=1e3, -1e3..=1e3],
+            |x| x[0] + x[1],
+        ),
+        Case::new(
+            "sub",
+            Operation::Binary(Op::Sub),
+            &[-1e3..=1e3, -1e3..=1e3],
+            |x| x[0] - x[1],
+        ),
+        Case::new(
+            "mul",
+            Operation::Binary(Op::Mul),
+            &[-100.0..=100.0, -100.0..=100.0],
+            |x| x[0] * x[1],
+        ),
+        Case::new(
+            "div",
+            Operation::Binary(Op::Div),
+            &[-100.0..=100.0, 0.5..=100.0],
+            |x| x[0] / x[1],
+        ),
+        Case::new("sin", unary(B::Sin), &[-100.0..=100.0], |x| x[0].sin()),
+        Case::new("cos", unary(B::Cos), &[-100.0..=100.0], |x| x[0].cos()),
+        Case::new("tan", unary(B::Tan), &[-1.5..=1.5], |x| x[0].tan()),
+        Case::new("asin", unary(B::Asin), &[-1.0..=1.0], |x| x[0].asin()),
+        Case::new("acos", unary(B::Acos), &[-1.0..=1.0], |x| x[0].acos()),
+        Case::new("atan", unary(B::Atan), &[-100.0..=100.0], |x| x[0].atan()),
+        Case::new(
+            "atan2",
+            binary(B::Atan),
+            &[-10.0..=10.0, -10.0..=10.0],
+            |x| x[0].atan2(x[1]),
+        ),
+        Case::new("sinh", unary(B::Sinh), &[-8.0..=8.0], |x| x[0].sinh()),
+        Case::new("cosh", unary(B::Cosh), &[-8.0..=8.0], |x| x[0].cosh()),
+        Case::new("tanh", unary(B::Tanh), &[-8.0..=8.0], |x| x[0].tanh()),
+        Case::new("asinh", unary(B::Asinh), &[-100.0..=100.0], |x| {
+            x[0].asinh()
+        }),
+        Case::new("acosh", unary(B::Acosh), &[1.0..=100.0], |x| x[0].acosh()),
+        Case::new("atanh", unary(B::Atanh), &[-0.99..=0.99], |x| x[0].atanh()),
+        Case::new("pow", binary(B::Pow), &[0.1..=8.0, -3.0..=3.0], |x| {
+            x[0].powf(x[1])
+        }),
+        Case::new("exp", unary(B::Exp), &[-8.0..=8.0], |x| x[0].exp()),
+        Case::new("log", unary(B::Log), &[1e-2..=1e3], |x| x[0].ln()),
+        Case::new("exp2", unary(B::Exp2), &[-12.0..=12.0], |x| x[0].exp2()),
+        Case::new("log2", unary(B::Log2), &[1e-2..=1e3], |x| x[0].log2()),
+        Case::new("sqrt", unary(B::Sqrt), &[0.0..=1e4], |x| x[0].sqrt()),
+        Case::new("inversesqrt", unary(B::InverseSqrt), &[1e-2..=1e4], |x| {
+            1.0 / x[0].sqrt()
+        }),
+    ]
+}
+
+/// Cases narrow enough for the results to fit Q8.8
+fn narrow_cases() -> Vec<Case> {
+    use BinaryOp as Op;
+    use Builtin as B;
+
+    vec![
+        Case::new(
+            "add",
+            Operation::Binary(Op::Add),
+            &[-50.0..=50.0, -50.0..=50.0],
+            |x| x[0] + x[1],
+        ),
+        Case::new(
+            "mul",
+            Operation::Binary(Op::Mul),
+            &[-10.0..=10.0, -10.0..=10.0],
+            |x| x[0] * x[1],
+        ),
+        Case::new(
+            "div",
+            Operation::Binary(Op::Div),
+            &[-10.0..=10.0, 0.5..=10.0],
+            |x| x[0] / x[1],
+        ),
+        Case::new("sin", unary(B::Sin), &[-10.0..=10.0], |x| x[0].sin()),
+        Case::new(
+            "atan2",
+            binary(B::Atan),
+            &[-10.0..=10.0, -10.0..=10.0],
+            |x| x[0].atan2(x[1]),
+        ),
+        Case::new("exp", unary(B::Exp), &[-4.0..=4.0], |x| x[0].exp()),
+        Case::new("log", unary(B::Log), &[0.1..=100.0], |x| x[0].ln()),
+        Case::new("sqrt", unary(B::Sqrt), &[0.0..=100.0], |x| x[0].sqrt()),
+    ]
+}
+
+/// Largest absolute and ULP errors allowed for each case
+///
+/// In `f32`, the absolute bounds follow the magnitude of the results, and
+/// `asinh` and `atanh` lose accuracy relative to tiny results.
+const F32_BOUNDS: &[(&str, f64, f64)] = &[
+    ("add", 1e-4, 0.0),
+    ("sub", 1e-4, 0.0),
+    ("mul", 1e-3, 0.0),
+    ("div", 1e-5, 0.0),
+    ("sin", 1e-7, 1.0),
+    ("cos", 1e-7, 1.0),
+    ("tan", 2e-6, 2.0),
+    ("asin", 2e-7, 3.0),
+    ("acos", 3e-7, 2.0),
+    ("atan", 2e-7, 1.0),
+    ("atan2", 3e-7, 2.0),
+    ("sinh", 2e-4, 2.0),
+    ("cosh", 2e-4, 1.0),
+    ("tanh", 1e-7, 3.0),
+    ("asinh", 4e-7, 20.0),
+    ("acosh", 4e-7, 1.0),
+    ("atanh", 2e-7, 600.0),
+    ("pow", 2e-4, 6.0),
+    ("exp", 2e-4, 1.0),
+    ("log", 3e-7, 1.0),
+    ("exp2", 2e-4, 1.0),
+    ("log2", 6e-7, 1.0),
+    ("sqrt", 6e-6, 1.0),
+    ("inversesqrt", 1e-6, 2.0),
+];
+
+/// Fixed-point builtins round to nearest, within half a step and what the
+/// `f64` reference lacks, while products and quotients round down
+const Q16_BOUNDS: &[(&str, f64, f64)] = &[
+    ("add", 0.0, 0.0),
+    ("sub", 0.0, 0.0),
+    ("mul", 1.53e-5, 1.0),
+    ("div", 1.53e-5, 1.0),
+    ("sin", 8.5e-6, 0.55),
+    ("cos", 8.5e-6, 0.55),
+    ("tan", 8.5e-6, 0.55),
+    ("asin", 8.5e-6, 0.55),
+    ("acos", 8.5e-6, 0.55),
+    ("atan", 8.5e-6, 0.55),
+    ("atan2", 8.5e-6, 0.55),
+    ("sinh", 8.5e-6, 0.55),
+    ("cosh", 8.5e-6, 0.55),
+    ("tanh", 8.5e-6, 0.55),
+    ("asinh", 8.5e-6, 0.55),
+    ("acosh", 8.5e-6, 0.55),
+    ("atanh", 8.5e-6, 0.55),
+    ("pow", 8.5e-6, 0.55),
+    ("exp", 8.5e-6, 0.55),
+    ("log", 8.5e-6, 0.55),
+    ("exp2", 8.5e-6, 0.55),
+    ("log2", 8.5e-6, 0.55),
+    ("sqrt", 8.5e-6, 0.55),
+    ("inversesqrt", 8.5e-6, 0.55),
+];
+
+const Q8_BOUNDS: &[(&str, f64, f64)] = &[
+    ("add", 0.0, 0.0),
+    ("mul", 3.91e-3, 1.0),
+    ("div", 3.91e-3, 1.0),
+    ("sin", 1.96e-3, 0.5),
+    ("atan2", 1.96e-3, 0.5),
+    ("exp", 1.96e-3, 0.5),
+    ("log", 1.96e-3, 0.5),
+    ("sqrt", 1.96e-3, 0.5),
+];
+
+/// Measure every case in `numeric` in the guest and check it against its
+/// bounds, panicking with every regression
+fn check(numeric: Numeric, cases: &[Case], bounds: &[(&str, f64, f64)]) {
+    let mut regressions = Vec::new();
+    for case in cases {
+        let report = measure(case, numeric, SAMPLES, guest);
+        println!("{}", report);
+        let &(_, abs, ulp) = bounds
+            .iter()
+            .find(|(name, ..)| *name == case.name)
+            .unwrap_or_else(|| panic!("no bounds for {}", case.name));
+        if let Err(regression) = report.check(Bounds { abs, ulp }) {
+            regressions.push(regression);
+        }
+    }
+    assert!(regressions.is_empty(), "{}", regressions.join("\n"));
+}
+
+#[test]
+fn test_f32_within_bounds() {
+    check(Numeric::Float, &cases(), F32_BOUNDS);
+}
+
+#[test]
+fn test_soft_float_matches_f32() {
+    for case in cases() {
+        let float = measure(&case, Numeric::Float, SAMPLES, guest);
+        let soft = measure(&case, Numeric::SoftFloat, SAMPLES, guest);
+        assert_eq!(
+            (soft.max_abs, soft.max_ulp, &soft.worst_abs, &soft.worst_ulp),
+            (
+                float.max_abs,
+                float.max_ulp,
+                &float.worst_abs,
+                &float.worst_ulp
+            ),
+            "{}",
+            case.name
+        );
+    }
+}
+
+#[test]
+fn test_q16_within_bounds() {
+    check(Q16, &cases(), Q16_BOUNDS);
+}
+
+#[test]
+fn test_q8_within_bounds() {
+    check(Q8, &narrow_cases(), Q8_BOUNDS);
+}
+
+#[test]
+fn test_regression_reported() {
+    let case = Case::new("sqrt", unary(Builtin::Sqrt), &[0.0..=1e4], |x| x[0].sqrt());
+    let report = measure(&case, Q16, 100, host);
+    assert_eq!(report.samples, 100);
+    assert_eq!(report.worst_abs.len(), 1);
+    assert!(report.max_abs > 0.0 && report.max_abs < Fixed::Q16_16.epsilon() as f64);
+    assert!(report.to_string().starts_with("sqrt in q16.16: max abs "));
+
+    let tight = Bounds {
+        abs: 1e-9,
+        ulp: 0.0,
+    };
+    let message = report.check(tight).unwrap_err();
+    assert!(
+        message.starts_with("sqrt in q16.16: absolute error "),
+        "{}",
+        message
+    );
+    assert!(message.contains(&format!("at {:?}", report.worst_abs)));
+    assert!(message.contains(" ulp past 0 at "), "{}", message);
+    let loose = Bounds {
+        abs: report.max_abs,
+        ulp: report.max_ulp,
+    };
+    assert_eq!(report.check(loose), Ok(()));
+
+    // An exact NaN where the result is a number is an infinite error
+    let case = Case::new("nan", unary(Builtin::Sqrt), &[1.0..=2.0], |_| f64::NAN);
+    let report = measure(&case, Numeric::Float, 4, host);
+    assert_eq!(
+        (report.max_abs, report.max_ulp),
+        (f64::INFINITY, f64::INFINITY)
+    );
+}
@@ -0,0 +1,176 @@
+mod common;
+
+use common::analyze_source;
+use lp_glsl_vm::{
+    analysis::call_graph::CallGraph,
+    compiler::{compile, compile_with, CompileOptions},
+    preprocessor::NoIncludes,
+    sema::SemaError,
+};
+
+#[test]
+fn test_direct_recursion() {
+    let errors = analyze_source(
+        r#"
+        int fact(int n) { return n <= 1 ? 1 : n * fact(n - 1); }
+        void main() { int x = fact(5); }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(errors, vec![SemaError::Recursion(vec!["fact".into()])]);
+    assert_eq!(errors[0].to_string(), "function `fact` is recursive");
+    assert_eq!(errors[0].label(), "calls itself");
+    assert_eq!(
+        errors[0].notes(),
+        vec![
+            "call cycle: fact -> fact".to_string(),
+            "GLSL does not allow recursion".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn test_indirect_recursion() {
+    let source = r#"
+        float odd(int n);
+        float helper(int n);
+        float even(int n) { return n == 0 ? 1.0 : odd(n - 1); }
+        float odd(int n) { return n == 0 ? 0.0 : helper(n); }
+        float helper(int n) { return even(n - 1); }
+        void main() { float x = even(4); }
+    "#;
+    let errors = analyze_source(source).unwrap_err();
+    // The cycle starts at the function declared first
+    assert_eq!(
+        errors,
+        vec![SemaError::Recursion(vec![
+            "odd".into(),
+            "helper".into(),
+            "even".into()
+        ])]
+    );
+    assert_eq!(errors[0].label(), "part of a call cycle");
+    assert_eq!(
+        errors[0].notes()[0],
+        "call cycle: odd -> helper -> even -> odd"
+    );
+
+    // Each cycle is reported once, at the definition of its first function
+    let error = compile("shader.glsl", source).unwrap_err();
+    assert_eq!(error.diagnostics.len(), 1);
+    let rendered = error.render();
+    assert!(
+        rendered.contains("float odd(int n) { return"),
+        "{}",
+        rendered
+    );
+    assert!(rendered.contains("part of a call cycle"), "{}", rendered);
+    assert!(
+        rendered.contains("call cycle: odd -> helper -> even -> odd"),
+        "{}",
+        rendered
+    );
+}
+
+#[test]
+fn test_separate_cycles() {
+    let errors = analyze_source(
+        r#"
+        int b(int x);
+        int a(int x) { return b(x); }
+        int b(int x) { return a(x); }
+        int c(int x) { return c(x) + a(x); }
+        void main() { int x = c(1); }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors,
+        vec![
+            SemaError::Recursion(vec!["b".into(), "a".into()]),
+            SemaError::Recursion(vec!["c".into()]),
+        ]
+    );
+}
+
+#[test]
+fn test_depth_and_stack_size() {
+    let module = analyze_source(
+        r#"
+        float leaf(float x) { return x * 2.0; }
+        float big(float x) {
+            float a[8];
+            a[0] = leaf(x);
+            return a[0];
+        }
+        float mid(float x) { return leaf(x) + big(x); }
+        void main() {
+            float b[2];
+            b[0] = mid(1.0) + leaf(2.0);
+        }
+    "#,
+    )
+    .unwrap();
+    let graph = CallGraph::new(&module);
+    let id = |name| module.find_function(name).unwrap();
+    assert!(graph.cycles().is_empty());
+    assert_eq!(
+        graph.callees(id("mid")).iter().copied().collect::<Vec<_>>(),
+        vec![id("leaf"), id("big")]
+    );
+    assert!(graph.callees(id("leaf")).is_empty());
+
+    // 52 bytes of saved registers, 4 for the spilled `x`
+    assert_eq!(graph.frame_size(id("leaf")), 64);
+    // 32 more for the array
+    assert_eq!(graph.frame_size(id("big")), 96);
+    // The array's slot is padded to 16 bytes
+    assert_eq!(graph.frame_size(id("main")), 80);
+
+    // main -> mid -> big -> leaf
+    assert_eq!(graph.max_depth(id("main")), Some(4));
+    assert_eq!(graph.max_depth(id("leaf")), Some(1));
+    assert_eq!(graph.max_stack_size(id("main")), Some(304));
+    assert_eq!(graph.max_stack_size(id("mid")), Some(224));
+}
+
+#[test]
+fn test_frame_counts_register_locals() {
+    let module = analyze_source(
+        r#"
+        void main() {
+            vec4 a = vec4(1.0);
+            mat2 m = mat2(2.0);
+            gl_FragColor = a * m[0].x;
+        }
+    "#,
+    )
+    .unwrap();
+    let graph = CallGraph::new(&module);
+    let main = module.find_function("main").unwrap();
+    // 52 bytes of saved registers, 16 for `a` and 16 for `m`
+    assert_eq!(graph.frame_size(main), 96);
+    assert_eq!(graph.max_stack_size(main), Some(96));
+}
+
+#[test]
+fn test_compiled_stack_size() {
+    let compiled = compile_with(
+        "shader.glsl",
+        "vec4 f() { vec4 v[3]; return v[0]; }\nvoid main() { gl_FragColor = f(); }",
+        &CompileOptions::default(),
+        &NoIncludes,
+    )
+    .unwrap();
+    // `f` holds the array and saved registers, `main` only saved registers
+    assert_eq!(compiled.stack_size, Some(112 + 64));
+
+    let compiled = compile_with(
+        "shader.glsl",
+        "float f(float x) { return x; }",
+        &CompileOptions::default(),
+        &NoIncludes,
+    )
+    .unwrap();
+    assert_eq!(compiled.stack_size, None);
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use common::{analyze_source, assert_close, floats, global_value};
+use lp_glsl_vm::sema::{
+    const_eval::{ConstEval, Value},
+    hir::{BinaryOp, Expr, ExprKind, Literal, LocalId, Stmt},
+    ScalarType, SemaError, Type,
+};
+
+#[test]
+fn test_fold_globals() {
+    let module = analyze_source(
+        r#"
+        const float TAU = 6.2831853;
+        const float HALF_TAU = TAU / 2.0;
+        const vec3 TINT = vec3(sin(HALF_TAU / 2.0), 0.5, 1.0).zyx;
+        const int COUNT = 3 * 4 + (7 >> 1);
+        const ivec2 SIZE = ivec2(vec2(2.7, -1.5));
+        const float LEN = length(vec2(3.0, 4.0)) + dot(TINT, vec3(1.0));
+        const bool ORDERED = COUNT > 10 && all(lessThan(vec2(0.0), vec2(1.0)));
+        float scale = smoothstep(0.0, 1.0, 0.5);
+        void main() {}
+    "#,
+    )
+    .unwrap();
+
+    assert_close(
+        &floats(&global_value(&module, "HALF_TAU")),
+        &[std::f32::consts::PI],
+    );
+    assert_close(&floats(&global_value(&module, "TINT")), &[1.0, 0.5, 1.0]);
+    assert_eq!(global_value(&module, "COUNT").as_int(), Some(15));
+    assert_eq!(
+        global_value(&module, "SIZE"),
+        Value::Components(vec![Literal::Int(2), Literal::Int(-1)])
+    );
+    assert_close(&floats(&global_value(&module, "LEN")), &[7.5]);
+    assert_eq!(
+        global_value(&module, "ORDERED").as_scalar(),
+        Some(&Literal::Bool(true))
+    );
+    assert_close(&floats(&global_value(&module, "scale")), &[0.5]);
+
+    // Initializers are replaced by their folded values
+    let tint = module.globals.iter().find(|g| g.name == "TINT").unwrap();
+    let ExprKind::Construct(args) = &tint.init.as_ref().unwrap().kind else {
+        panic!("expected a constructor");
+    };
+    assert!(args
+        .iter()
+        .all(|arg| matches!(arg.kind, ExprKind::Literal(Literal::Float(_)))));
+}
+
+#[test]
+fn test_constants_feed_sizes_and_labels() {
+    let module = analyze_source(
+        r#"
+        struct Light { vec3 dir; float power; };
+        const Light SUN = Light(normalize(vec3(0.0, 2.0, 0.0)), 1.5);
+        const int N = int(SUN.power * 2.0) + 1;
+
+        void main() {
+            const int M = N * 2;
+            float weights[M + 1];
+            int mode = 1;
+            switch (mode) {
+            case N - 3:
+                break;
+            case -M:
+                break;
+            }
+            float w = weights[M];
+        }
+    "#,
+    )
+    .unwrap();
+    let main = module.function(module.find_function("main").unwrap());
+    assert_eq!(
+        main.local(LocalId(1)).ty,
+        Type::Array(Box::new(Type::FLOAT), 9)
+    );
+    let body = main.body.as_ref().unwrap();
+    let Stmt::Decl(_, Some(init)) = &body[0] else {
+        panic!("expected the declaration of M");
+    };
+    assert!(matches!(init.kind, ExprKind::Literal(Literal::Int(8))));
+    let Stmt::Switch { cases, .. } = &body[3] else {
+        panic!("expected a switch");
+    };
+    assert_eq!(cases[0].labels, vec![Some(1)]);
+    assert_eq!(cases[1].labels, vec![Some(-8)]);
+
+    let sun = module.globals.iter().find(|g| g.name == "SUN").unwrap();
+    assert!(matches!(sun.ty, Type::Struct(_)));
+    let Value::Aggregate(fields) = global_value(&module, "SUN") else {
+        panic!("expected an aggregate");
+    };
+    assert_close(&floats(&fields[0]), &[0.0, 1.0, 0.0]);
+}
+
+#[test]
+fn test_non_constant_errors() {
+    let errors = analyze_source(
+        r#"
+        uniform float time;
+        const float PHASE = time * 2.0;
+        float speed = time;
+        int helper() { return 2; }
+        void main() {
+            int n = 4;
+            const int K = n;
+            float a[helper()];
+            switch (n) {
+            case n:
+                break;
+            }
+            vec2 v = vec2(1.0);
+            float x = v[1 + 1];
+        }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors,
+        vec![
+            SemaError::NonConstantInitializer("PHASE".into()),
+            SemaError::NonConstantInitializer("speed".into()),
+            SemaError::NonConstantInitializer("K".into()),
+            SemaError::InvalidArraySize,
+            SemaError::NotConstant("case label"),
+            SemaError::IndexOutOfRange { index: 2, size: 2 },
+        ]
+    );
+}
+
+#[test]
+fn test_undefined_operations_are_not_constant() {
+    let module = analyze_source("void main() { int z = 0; }").unwrap();
+    let eval = ConstEval::new(&module);
+    let int = |v| Expr::new(ExprKind::Literal(Literal::Int(v)), Type::INT);
+    let binary = |op, a, b| {
+        Expr::new(
+            ExprKind::Binary(op, Box::new(int(a)), Box::new(int(b))),
+            Type::Scalar(ScalarType::Int),
+        )
+    };
+    assert_eq!(eval.eval(&binary(BinaryOp::Div, 1, 0)), None);
+    assert_eq!(eval.eval(&binary(BinaryOp::Shl, 1, 32)), None);
+    assert_eq!(
+        eval.eval(&binary(BinaryOp::Add, i32::MAX, 1)),
+        Some(Value::Components(vec![Literal::Int(i32::MIN)]))
+    );
+}
@@ -0,0 +1,108 @@
+use lp_glsl_vm::{
+    compiler::compile,
+    diagnostic::{Diagnostic, SourceMap, Span},
+};
+
+#[test]
+fn test_render_primary_and_secondary_labels() {
+    let mut sources = SourceMap::new();
+    let id = sources.add(
+        "palette.glsl",
+        "float hue = 0.5;\nvoid main() {\n\tfloat hue = 1.0;\n}\n",
+    );
+
+    let diagnostic = Diagnostic::warning("declaration of `hue` shadows a global")
+        .with_primary(Span::new(id, 38, 41), "shadows the global")
+        .with_secondary(Span::new(id, 6, 9), "global declared here")
+        .with_note("rename the local to avoid confusion");
+
+    let expected = "\
+warning: declaration of `hue` shadows a global
+ --> palette.glsl:3:8
+  |
+3 |     float hue = 1.0;
+  |           ^^^ shadows the global
+1 | float hue = 0.5;
+  |       --- global declared here
+  |
+  = note: rename the local to avoid confusion
+";
+    assert_eq!(diagnostic.render(&sources), expected);
+}
+
+#[test]
+fn test_render_without_span() {
+    let sources = SourceMap::new();
+    let diagnostic =
+        Diagnostic::error("shader has no entry point").with_note("define `void main()`");
+    assert_eq!(
+        diagnostic.render(&sources),
+        "error: shader has no entry point\n = note: define `void main()`\n"
+    );
+}
+
+#[test]
+fn test_compile_error_points_at_undeclared_variable() {
+    let source = "\
+void main() {
+    vec3 color = vec3(0.5);
+    gl_FragColor = vec4(colr, 1.0);
+}
+";
+    let error = compile("effect.glsl", source).unwrap_err();
+    let expected = "\
+error: use of undeclared variable `colr`
+ --> effect.glsl:3:25
+  |
+3 |     gl_FragColor = vec4(colr, 1.0);
+  |                         ^^^^ not found in this scope
+";
+    assert_eq!(error.render(), expected);
+}
+
+#[test]
+fn test_compile_error_points_at_declaration() {
+    let source = "\
+float brightness(vec3 c) {
+    return dot(c, c);
+}
+
+void main() {
+    vec3 c = vec3(1.0);
+    vec3 bad = vec2(1.0);
+}
+";
+    let error = compile("effect.glsl", source).unwrap_err();
+    let rendered = error.render();
+    assert_eq!(error.diagnostics.len(), 1, "{}", rendered);
+    assert!(
+        rendered.contains(
+            "7 |     vec3 bad = vec2(1.0);\n  |          ^^^ expected `vec3`, found `vec2`"
+        ),
+        "{}",
+        rendered
+    );
+}
+
+#[test]
+fn test_compile_error_points_at_statement() {
+    let source = "\
+void main() {
+    vec4 c = vec4(0.0);
+    c.xx = vec2(1.0);
+    if (c.x > 0.0) {
+        c.x = c.w;
+    }
+    c.yy = vec2(c.x);
+}
+";
+    let error = compile("effect.glsl", source).unwrap_err();
+    let rendered = error.render();
+    assert_eq!(error.diagnostics.len(), 2, "{}", rendered);
+    for snippet in [
+        "3 |     c.xx = vec2(1.0);\n  |     ^",
+        "7 |     c.yy = vec2(c.x);\n  |     ^",
+    ] {
+        assert!(rendered.contains(snippet), "{}", rendered);
+    }
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use common::analyze_source;
+use lp_glsl_vm::{
+    compiler::compile,
+    reflect::Reflection,
+    sema::{
+        hir::{ExprKind, Stmt, Storage, VarRef, MAIN, MAIN_IMAGE},
+        ScalarType, SemaError, Type,
+    },
+};
+
+const SHADERTOY: &str = r#"
+    void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+        vec2 uv = fragCoord / iResolution.xy;
+        float pulse = 0.5 + 0.5 * sin(iTime + float(iFrame) * 0.01);
+        if (iMouse.z > 0.0) {
+            uv = iMouse.xy / iResolution.xy;
+        }
+        fragColor = vec4(uv, pulse, 1.0);
+    }
+"#;
+
+#[test]
+fn test_main_image_entry_point() {
+    let module = compile("shadertoy.glsl", SHADERTOY).unwrap();
+    let main = module.function(module.find_function(MAIN).unwrap());
+    assert_eq!(main.return_type, Type::Void);
+    assert!(main.params.is_empty());
+
+    // main calls mainImage with gl_FragColor and the pixel position
+    let body = main.body.as_ref().unwrap();
+    assert_eq!(body.len(), 1);
+    let Stmt::Expr(call) = &body[0] else {
+        panic!("expected a call, found {:?}", body[0]);
+    };
+    let ExprKind::Call(id, args) = &call.kind else {
+        panic!("expected a call, found {:?}", call);
+    };
+    assert_eq!(*id, module.find_function(MAIN_IMAGE).unwrap());
+    assert_eq!(args.len(), 2);
+    let ExprKind::Var(VarRef::Global(frag_color)) = &args[0].kind else {
+        panic!("expected gl_FragColor, found {:?}", args[0]);
+    };
+    assert_eq!(module.global(*frag_color).name, "gl_FragColor");
+    assert_eq!(args[1].ty, Type::Vector(ScalarType::Float, 2));
+}
+
+#[test]
+fn test_shadertoy_uniforms() {
+    let module = compile("shadertoy.glsl", SHADERTOY).unwrap();
+    let reflection = Reflection::of(&module);
+    let uniforms: Vec<_> = reflection
+        .uniforms
+        .iter()
+        .map(|u| (u.name.as_str(), u.ty.clone()))
+        .collect();
+    assert_eq!(
+        uniforms,
+        vec![
+            ("iResolution", Type::Vector(ScalarType::Float, 3)),
+            ("iTime", Type::FLOAT),
+            ("iFrame", Type::INT),
+            ("iMouse", Type::Vector(ScalarType::Float, 4)),
+        ]
+    );
+    assert!(module
+        .globals
+        .iter()
+        .filter(|g| g.name.starts_with('i'))
+        .all(|g| g.storage == Storage::Uniform && !g.writable));
+
+    // Unused uniforms are not declared, and explicit declarations are kept
+    let module = compile(
+        "shader.glsl",
+        r#"
+        uniform float iTime;
+        void main() {
+            gl_FragColor = vec4(iTime);
+        }
+    "#,
+    )
+    .unwrap();
+    let names: Vec<_> = Reflection::of(&module)
+        .uniforms
+        .into_iter()
+        .map(|u| u.name)
+        .collect();
+    assert_eq!(names, vec!["iTime"]);
+
+    let errors = analyze_source(
+        r#"
+        void main() {
+            iTime = 1.0;
+        }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(errors, vec![SemaError::AssignToReadOnly("iTime".into())]);
+}
+
+#[test]
+fn test_classic_main_is_kept() {
+    let module = compile(
+        "shader.glsl",
+        r#"
+        void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+            fragColor = vec4(fragCoord, 0.0, 1.0);
+        }
+        void main() {
+            mainImage(gl_FragColor, gl_FragCoord.xy * 0.5);
+        }
+    "#,
+    )
+    .unwrap();
+    let mains = module.functions.iter().filter(|f| f.name == MAIN).count();
+    assert_eq!(mains, 1);
+}
+
+#[test]
+fn test_main_image_signature() {
+    let errors = analyze_source(
+        r#"
+        void mainImage(out vec4 fragColor, vec3 fragCoord) {
+            fragColor = vec4(fragCoord, 1.0);
+        }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors,
+        vec![SemaError::EntryPointSignature("mainImage".into())]
+    );
+
+    let error = compile(
+        "shader.glsl",
+        "vec4 mainImage(vec2 fragCoord) { return vec4(fragCoord, 0.0, 1.0); }",
+    )
+    .unwrap_err();
+    let rendered = error.render();
+    assert!(
+        rendered.contains("entry point `mainImage` has the wrong signature"),
+        "{}",
+        rendered
+    );
+    assert!(
+        rendered.contains("void mainImage(out vec4 fragColor, in vec2 fragCoord)"),
+        "{}",
+        rendered
+    );
+}
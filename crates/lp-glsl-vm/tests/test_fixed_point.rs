@@ -0,0 +1,388 @@
+use lp_glsl_vm::{
+    compiler::{compile, compile_with, CompileOptions},
+    ir::{
+        self,
+        interpret::{Bits, Exit, Interpreter},
+    },
+    numeric::{self, Fixed, Numeric, Overflow},
+    preprocessor::NoIncludes,
+    reference::{Fragment, Reference},
+    runtime::{fixed, math},
+    sema::builtins::Builtin,
+};
+
+const Q16: Fixed = Fixed::Q16_16;
+const Q16_WRAP: Fixed = Fixed::Q16_16.with_overflow(Overflow::Wrap);
+const Q8: Fixed = Fixed::new(8, 8, Overflow::Saturate);
+const Q8_WRAP: Fixed = Fixed::new(8, 8, Overflow::Wrap);
+
+/// Lower the module in `text` to `format` and call its function `@f` with
+/// `args`, returning the raw bits of the result
+fn run(text: &str, format: Fixed, args: &[f32]) -> i32 {
+    let mut module = ir::parse(text).unwrap_or_else(|e| panic!("{}", e));
+    numeric::lower(&mut module, Numeric::Fixed(format));
+    if let Err(errors) = ir::verify(&module) {
+        panic!("{}\n{:#?}", module, errors);
+    }
+    let f = module.find_function("f").unwrap();
+    let args: Vec<Bits> = args
+        .iter()
+        .map(|&x| [format.from_f32(x) as u32, 0, 0, 0])
+        .collect();
+    match Interpreter::new(&module).call(f, &args).unwrap() {
+        Exit::Return(results) => results[0][0] as i32,
+        Exit::Discard => panic!("discarded"),
+    }
+}
+
+fn binary(op: &str) -> String {
+    format!(
+        "\
+function @f(f32, f32) -> f32 {{
+block0(v0: f32, v1: f32):
+    v2: f32 = {} v0, v1
+    return v2
+}}
+",
+        op
+    )
+}
+
+#[test]
+fn test_encoding() {
+    assert_eq!(Q16.from_f32(1.5), 0x1_8000);
+    assert_eq!(Q16.from_f32(-0.25), -0x4000);
+    assert_eq!(Q16.from_f32(1e6), i32::MAX);
+    assert_eq!(Q16.from_f32(-1e6), i32::MIN);
+    assert_eq!(Q16.from_f32(f32::NAN), 0);
+    assert_eq!(Q16.to_f32(0x2_4000), 2.25);
+    assert_eq!(Q8.from_f32(1000.0), 0x7fff);
+    assert_eq!(Q8.from_f32(-1.0 / 512.0), -1);
+    assert_eq!(Q8.max(), 32767);
+    assert_eq!(Q8.min(), -32768);
+    assert_eq!(Q16.to_string(), "q16.16");
+
+    let numeric = Numeric::Fixed(Q16);
+    assert_eq!(numeric.encode(3.0), 0x3_0000);
+    assert_eq!(numeric.decode(0xffff_8000), -0.5);
+    assert_eq!(Numeric::Float.encode(3.0), 3.0f32.to_bits());
+}
+
+#[test]
+fn test_lowered_ir() {
+    let text = "\
+function @f(f32, f32) -> f32 {
+block0(v0: f32, v1: f32):
+    v2: f32 = mul v0, v1
+    v3: f32 = div v2, v1
+    v4: f32 = neg v3
+    return v4
+}
+";
+    let mut module = ir::parse(text).unwrap();
+    numeric::lower(&mut module, Numeric::Fixed(Q16));
+    ir::verify(&module).unwrap();
+    let printed = module.to_string();
+    assert!(!printed.contains("f32"), "{}", printed);
+    assert!(printed.contains("mulh v0, v1"), "{}", printed);
+    assert!(printed.contains("call @lp.q16.16.div("), "{}", printed);
+    assert!(printed.contains("function @lp.q16.16.div(i32, i32) -> i32, i32"));
+    // The printed module parses back and computes the same
+    let reparsed = ir::parse(&printed).unwrap();
+    assert_eq!(reparsed.to_string(), printed);
+}
+
+#[test]
+fn test_arithmetic() {
+    let raw = |x: f32| Q16.from_f32(x);
+    assert_eq!(run(&binary("add"), Q16, &[1.5, -2.25]), raw(-0.75));
+    assert_eq!(run(&binary("sub"), Q16, &[1.5, -2.25]), raw(3.75));
+    assert_eq!(run(&binary("mul"), Q16, &[1.5, 2.25]), raw(3.375));
+    assert_eq!(run(&binary("mul"), Q16, &[-3.0, 0.5]), raw(-1.5));
+    // Products round toward negative infinity
+    assert_eq!(run(&binary("mul"), Q16, &[1.0 / 65536.0, 0.5]), 0);
+    assert_eq!(run(&binary("mul"), Q16, &[-1.0 / 65536.0, 0.5]), -1);
+    assert_eq!(run(&binary("mul"), Q16, &[181.0, 181.0]), raw(32761.0));
+
+    // Quotients round toward zero, by the short and the long path
+    assert_eq!(run(&binary("div"), Q16, &[1.0, 3.0]), 0x5555);
+    assert_eq!(run(&binary("div"), Q16, &[-1.0, 3.0]), -0x5555);
+    assert_eq!(run(&binary("div"), Q16, &[-7.5, 2.0]), raw(-3.75));
+    assert_eq!(run(&binary("div"), Q16, &[1.0, 0.25]), raw(4.0));
+    assert_eq!(
+        run(&binary("div"), Q16, &[1000.0, 300.0]),
+        (1000 << 16) / 300
+    );
+    assert_eq!(
+        run(&binary("div"), Q16, &[-123.5, 0.125 + 9.0 / 65536.0]),
+        ((-123.5 * 65536.0) as i64 * 65536 / (8192 + 9)) as i32
+    );
+    assert_eq!(run(&binary("div"), Q16, &[0.0, -5.0]), 0);
+}
+
+#[test]
+fn test_overflow() {
+    // 30000 * 4 and 30000 + 30000 do not fit below 32768
+    let product = ((30000i64 << 16) * 4) as i32;
+    assert_eq!(run(&binary("mul"), Q16, &[30000.0, 4.0]), i32::MAX);
+    assert_eq!(run(&binary("mul"), Q16, &[30000.0, -4.0]), i32::MIN);
+    assert_eq!(run(&binary("mul"), Q16_WRAP, &[30000.0, 4.0]), product);
+    assert_eq!(run(&binary("add"), Q16, &[30000.0, 30000.0]), i32::MAX);
+    assert_eq!(run(&binary("sub"), Q16, &[-30000.0, 30000.0]), i32::MIN);
+    assert_eq!(
+        run(&binary("add"), Q16_WRAP, &[30000.0, 30000.0]),
+        Q16.from_f32(60000.0 - 65536.0)
+    );
+    assert_eq!(run(&binary("div"), Q16, &[20000.0, 0.5]), i32::MAX);
+    assert_eq!(run(&binary("div"), Q16, &[20000.0, -0.5]), i32::MIN);
+    assert_eq!(
+        run(&binary("div"), Q16_WRAP, &[20000.0, 0.5]),
+        Q16.from_f32(40000.0 - 65536.0)
+    );
+
+    // Division by zero saturates in both modes
+    for format in [Q16, Q16_WRAP, Q8, Q8_WRAP] {
+        assert_eq!(run(&binary("div"), format, &[1.0, 0.0]), format.max());
+        assert_eq!(run(&binary("div"), format, &[-1.0, 0.0]), format.min());
+    }
+
+    let neg = "\
+function @f(f32) -> f32 {
+block0(v0: f32):
+    v1: f32 = neg v0
+    return v1
+}
+";
+    let min = Q16.to_f32(i32::MIN);
+    assert_eq!(run(neg, Q16, &[min]), i32::MAX);
+    assert_eq!(run(neg, Q16_WRAP, &[min]), i32::MIN);
+}
+
+#[test]
+fn test_narrow_format() {
+    let raw = |x: f32| Q8.from_f32(x);
+    assert_eq!(run(&binary("mul"), Q8, &[1.5, -2.25]), raw(-3.375));
+    assert_eq!(run(&binary("div"), Q8, &[1.0, 3.0]), 0x55);
+    assert_eq!(run(&binary("add"), Q8, &[100.0, 100.0]), Q8.max());
+    assert_eq!(run(&binary("mul"), Q8, &[-100.0, 2.0]), Q8.min());
+    // Wrapped results are sign-extended from 16 bits
+    assert_eq!(run(&binary("add"), Q8_WRAP, &[100.0, 100.0]), raw(-56.0));
+    assert_eq!(run(&binary("mul"), Q8_WRAP, &[100.0, 2.0]), raw(-56.0));
+    assert_eq!(run(&binary("div"), Q8_WRAP, &[100.0, 0.5]), raw(-56.0));
+}
+
+#[test]
+fn test_conversions() {
+    let convert = |op: &str, from: &str, to: &str| {
+        format!(
+            "\
+function @f({from}) -> {to} {{
+block0(v0: {from}):
+    v1: {to} = {op} v0
+    return v1
+}}
+"
+        )
+    };
+    let to_int = convert("fptosi", "f32", "i32");
+    assert_eq!(run(&to_int, Q16, &[2.75]), 2);
+    assert_eq!(run(&to_int, Q16, &[-2.5]), -2);
+    assert_eq!(run(&to_int, Q16, &[-3.0]), -3);
+    assert_eq!(run(&to_int, Q8, &[-0.5]), 0);
+    let to_uint = convert("fptoui", "f32", "i32");
+    assert_eq!(run(&to_uint, Q16, &[7.9]), 7);
+    assert_eq!(run(&to_uint, Q16, &[-1.0]), 0);
+
+    // Integers are passed through `run` as floats in the format, so call
+    // the conversions directly
+    let from_int = |format: Fixed, op: &str, x: i32| {
+        let mut module = ir::parse(&convert(op, "i32", "f32")).unwrap();
+        numeric::lower(&mut module, Numeric::Fixed(format));
+        let f = module.find_function("f").unwrap();
+        match Interpreter::new(&module).call(f, &[[x as u32, 0, 0, 0]]) {
+            Ok(Exit::Return(results)) => results[0][0] as i32,
+            exit => panic!("{:?}", exit),
+        }
+    };
+    assert_eq!(from_int(Q16, "sitofp", -7), Q16.from_f32(-7.0));
+    assert_eq!(from_int(Q16, "sitofp", 40000), i32::MAX);
+    assert_eq!(from_int(Q16, "sitofp", -40000), i32::MIN);
+    assert_eq!(from_int(Q16_WRAP, "sitofp", 40000), (40000 - 65536) << 16);
+    assert_eq!(from_int(Q16, "uitofp", -1), i32::MAX);
+    assert_eq!(from_int(Q8, "sitofp", 200), Q8.max());
+    assert_eq!(from_int(Q8_WRAP, "sitofp", 200), -56 << 8);
+}
+
+/// Largest error of the routine for `builtin` over `inputs`, relative to
+/// the float result where it is above 1
+/// Float function a routine approximates
+type Exact = fn(&[f32]) -> f32;
+
+fn routine_error(builtin: Builtin, inputs: &[&[f32]], expected: impl Fn(&[f32]) -> f32) -> f32 {
+    let mut worst: f32 = 0.0;
+    for args in inputs {
+        let raw: Vec<i32> = args.iter().map(|&x| Q16.from_f32(x)).collect();
+        let exact: Vec<f32> = raw.iter().map(|&x| Q16.to_f32(x)).collect();
+        let result = Q16.to_f32(fixed::call(Q16, builtin, &raw).unwrap());
+        let expected = expected(&exact).clamp(Q16.to_f32(i32::MIN), Q16.to_f32(i32::MAX));
+        let error = (result - expected).abs() / expected.abs().max(1.0);
+        worst = worst.max(error);
+    }
+    worst
+}
+
+#[test]
+fn test_routines() {
+    let grid: Vec<[f32; 1]> = (-200..=200).map(|i| [i as f32 * 0.173]).collect();
+    let grid: Vec<&[f32]> = grid.iter().map(|a| &a[..]).collect();
+    let unit: Vec<[f32; 1]> = (-50..=50).map(|i| [i as f32 * 0.0199]).collect();
+    let unit: Vec<&[f32]> = unit.iter().map(|a| &a[..]).collect();
+    let positive: Vec<[f32; 1]> = (1..=300).map(|i| [i as f32 * i as f32 * 0.01]).collect();
+    let positive: Vec<&[f32]> = positive.iter().map(|a| &a[..]).collect();
+    let pairs: Vec<[f32; 2]> = (-12..=12)
+        .flat_map(|i| (-12..=12).map(move |j| [i as f32 * 0.7, j as f32 * 0.45]))
+        .collect();
+    let pairs: Vec<&[f32]> = pairs.iter().map(|a| &a[..]).collect();
+
+    let bound = 4.0 / 65536.0;
+    let checks: [(Builtin, &[&[f32]], Exact); 16] = [
+        (Builtin::Sin, &grid, |x| math::sin(x[0])),
+        (Builtin::Cos, &grid, |x| math::cos(x[0])),
+        (Builtin::Asin, &unit, |x| math::asin(x[0])),
+        (Builtin::Acos, &unit, |x| math::acos(x[0])),
+        (Builtin::Atan, &grid, |x| math::atan(x[0])),
+        (Builtin::Atan, &pairs, |x| math::atan2(x[0], x[1])),
+        (Builtin::Tanh, &grid, |x| math::tanh(x[0])),
+        (Builtin::Asinh, &grid, |x| math::asinh(x[0])),
+        (Builtin::Exp, &grid, |x| math::exp(x[0])),
+        (Builtin::Exp2, &grid, |x| math::exp2(x[0])),
+        (Builtin::Log, &positive, |x| math::log(x[0])),
+        (Builtin::Log2, &positive, |x| math::log2(x[0])),
+        (Builtin::Sqrt, &positive, |x| math::sqrt(x[0])),
+        (Builtin::InverseSqrt, &positive, |x| {
+            math::inverse_sqrt(x[0])
+        }),
+        (Builtin::Round, &grid, |x| math::round(x[0])),
+        (Builtin::RoundEven, &grid, |x| math::round_even(x[0])),
+    ];
+    for (builtin, inputs, expected) in checks {
+        let error = routine_error(builtin, inputs, expected);
+        assert!(error <= bound, "{}: {}", builtin.name(), error);
+    }
+    let pow: Vec<[f32; 2]> = (1..=20)
+        .flat_map(|i| (-8..=8).map(move |j| [i as f32 * 0.3, j as f32 * 0.5]))
+        .collect();
+    let pow: Vec<&[f32]> = pow.iter().map(|a| &a[..]).collect();
+    let error = routine_error(Builtin::Pow, &pow, |x| math::pow(x[0], x[1]));
+    assert!(error <= 16.0 / 65536.0, "pow: {}", error);
+
+    // Outside their domain, routines take the nearest point of it
+    let call = |builtin, x: f32| Q16.to_f32(fixed::call(Q16, builtin, &[Q16.from_f32(x)]).unwrap());
+    assert_eq!(call(Builtin::Sqrt, -4.0), 0.0);
+    assert_eq!(call(Builtin::Log, 0.0), Q16.to_f32(i32::MIN));
+    assert_eq!(call(Builtin::InverseSqrt, 0.0), Q16.to_f32(i32::MAX));
+    assert_eq!(call(Builtin::Asin, 2.0), call(Builtin::Asin, 1.0));
+    assert_eq!(call(Builtin::Exp, 20.0), Q16.to_f32(i32::MAX));
+
+    // Symbols name the format and the builtin
+    assert_eq!(fixed::symbol(Q16, Builtin::Sin, 1), "lp.q16.16.sin");
+    assert_eq!(fixed::symbol(Q8, Builtin::Atan, 2), "lp.q8.8.atan2");
+    let one = Q16.from_f32(1.0) as u32;
+    assert_eq!(
+        fixed::call_symbol("lp.q16.16.atan2", &[one, one]),
+        fixed::call(Q16, Builtin::Atan, &[one as i32, one as i32]).map(|r| r as u32)
+    );
+    assert_eq!(fixed::call_symbol("lp.q16.16.nope", &[one]), None);
+}
+
+const SHADERS: &[&str] = &[
+    r#"
+    uniform float time = 0.7;
+    uniform vec2 resolution = vec2(8.0, 6.0);
+    void main() {
+        vec2 uv = gl_FragCoord.xy / resolution;
+        vec2 p = uv * 2.0 - 1.0;
+        float d = length(p);
+        float ring = smoothstep(0.3, 0.35, d) - smoothstep(0.6, 0.65, d);
+        float angle = atan(p.y, p.x);
+        vec3 col = 0.5 + 0.5 * cos(time + angle + vec3(0.0, 2.0, 4.0));
+        col *= ring + 0.2 * sin(d * 12.0 - time * 3.0);
+        gl_FragColor = vec4(clamp(col, 0.0, 1.0), 1.0);
+    }
+    "#,
+    r#"
+    vec3 shade(vec3 n, vec3 light) {
+        float diffuse = max(dot(n, light), 0.0);
+        vec3 r = reflect(-light, n);
+        float specular = pow(max(r.z, 0.0), 8.0);
+        return vec3(0.1, 0.2, 0.5) * diffuse + vec3(specular);
+    }
+    void main() {
+        vec2 p = (gl_FragCoord.xy - vec2(4.0, 3.0)) / 3.0;
+        float h = 1.0 - dot(p, p);
+        if (h < 0.0) {
+            gl_FragColor = vec4(mod(gl_FragCoord.x, 2.0) * 0.25, fract(p.y), 0.0, 1.0);
+            return;
+        }
+        vec3 n = normalize(vec3(p, sqrt(h)));
+        vec3 light = normalize(vec3(0.5, 0.8, 1.0));
+        gl_FragColor = vec4(shade(n, light), 1.0);
+    }
+    "#,
+    r#"
+    #extension GL_LP_noise : enable
+    void main() {
+        vec2 p = gl_FragCoord.xy * 0.37;
+        float n = lp_fbm(p, 3) * 0.5 + 0.5;
+        float steps = floor(n * 4.0) / 4.0;
+        int bands = int(n * 10.0);
+        gl_FragColor = vec4(n, steps, float(bands) / 10.0, abs(sign(p.x - 1.0)));
+    }
+    "#,
+];
+
+#[test]
+fn test_render_close_to_float() {
+    for (i, source) in SHADERS.iter().enumerate() {
+        let module = compile("test.glsl", source).unwrap_or_else(|e| panic!("{}", e.render()));
+        let expected = Reference::new(&module).render(8, 6).unwrap();
+        let reference = Reference::with_numeric(&module, Numeric::Fixed(Q16));
+        if let Err(errors) = ir::verify(reference.module()) {
+            panic!("{}\n{:#?}", reference.module(), errors);
+        }
+        assert!(!reference.module().to_string().contains("f32"));
+        let image = reference.render(8, 6).unwrap();
+        for (pixel, (fixed, float)) in image.iter().zip(&expected).enumerate() {
+            let (Fragment::Color(fixed), Fragment::Color(float)) = (fixed, float) else {
+                assert_eq!(fixed, float);
+                continue;
+            };
+            for (a, b) in fixed.iter().zip(float) {
+                assert!(
+                    (a - b).abs() < 2e-3,
+                    "shader {} pixel {}: {:?} instead of {:?}",
+                    i,
+                    pixel,
+                    fixed,
+                    float
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_compile_option() {
+    let options = CompileOptions {
+        numeric: Numeric::Fixed(Q16),
+        ..CompileOptions::default()
+    };
+    let compiled = compile_with("test.glsl", SHADERS[0], &options, &NoIncludes).unwrap();
+    assert_eq!(compiled.precisions.high, Numeric::Fixed(Q16));
+    let module = compiled.ir();
+    ir::verify(&module).unwrap();
+    let printed = module.to_string();
+    assert!(!printed.contains("f32"));
+    assert!(printed.contains("call @lp.q16.16.cos("));
+    assert!(printed.contains("function @lp.q16.16.smoothstep(i32, i32, i32) -> i32\n"));
+}
@@ -0,0 +1,296 @@
+use lp_glsl_vm::{
+    compiler::compile,
+    ir::{self, BinaryOp, CompareOp, Function, FunctionBuilder, InstKind, Module, Signature, Type},
+};
+
+/// `sum(n)`: the total of 0..n, accumulated in a loop
+fn sum_module() -> Module {
+    let mut module = Module::default();
+    let mut func = Function::new(
+        "sum",
+        Signature {
+            params: vec![Type::I32],
+            returns: vec![Type::I32],
+        },
+    );
+    let mut b = FunctionBuilder::new(&mut func);
+    let i = b.declare_var(Type::I32);
+    let total = b.declare_var(Type::I32);
+    let entry = b.create_entry_block();
+    let header = b.create_block();
+    let body = b.create_block();
+    let exit = b.create_block();
+
+    b.switch_to_block(entry);
+    b.seal_block(entry);
+    let n = b.block_params(entry)[0];
+    let zero = b.iconst(0);
+    b.def_var(i, zero);
+    b.def_var(total, zero);
+    b.jump(header, &[]);
+
+    b.switch_to_block(header);
+    let iv = b.use_var(i);
+    let cond = b.compare(CompareOp::Lt, iv, n);
+    b.brif(cond, body, &[], exit, &[]);
+
+    b.switch_to_block(body);
+    b.seal_block(body);
+    let iv = b.use_var(i);
+    let tv = b.use_var(total);
+    let tv = b.binary(BinaryOp::Add, tv, iv);
+    b.def_var(total, tv);
+    let one = b.iconst(1);
+    let iv = b.binary(BinaryOp::Add, iv, one);
+    b.def_var(i, iv);
+    b.jump(header, &[]);
+    b.seal_block(header);
+
+    b.switch_to_block(exit);
+    b.seal_block(exit);
+    let tv = b.use_var(total);
+    b.ret(&[tv]);
+    b.finish();
+
+    module.add_function(func);
+    module
+}
+
+#[test]
+fn test_builder_loop_variables() {
+    let module = sum_module();
+    assert_eq!(ir::verify(&module), Ok(()));
+    assert_eq!(
+        module.to_string(),
+        "\
+function @sum(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = const 0
+    jump block1(v1, v1)
+block1(v2: i32, v4: i32):
+    v3: bool = lt v2, v0
+    brif v3, block2, block3
+block2:
+    v5: i32 = add v4, v2
+    v6: i32 = const 1
+    v7: i32 = add v2, v6
+    jump block1(v7, v5)
+block3:
+    return v4
+}
+"
+    );
+}
+
+#[test]
+fn test_builder_removes_trivial_params() {
+    // A variable only read in a loop needs no parameter on the header
+    let mut func = Function::new(
+        "f",
+        Signature {
+            params: vec![Type::F32],
+            returns: vec![Type::F32],
+        },
+    );
+    let mut b = FunctionBuilder::new(&mut func);
+    let x = b.declare_var(Type::F32);
+    let entry = b.create_entry_block();
+    let header = b.create_block();
+    let exit = b.create_block();
+    b.switch_to_block(entry);
+    let param = b.block_params(entry)[0];
+    b.def_var(x, param);
+    b.jump(header, &[]);
+    b.switch_to_block(header);
+    let cond = b.bconst(false);
+    b.brif(cond, header, &[], exit, &[]);
+    b.switch_to_block(exit);
+    let value = b.use_var(x);
+    b.ret(&[value]);
+    b.finish();
+
+    assert!(func.block(header).params.is_empty());
+    let ret = func.terminator(exit).unwrap();
+    assert_eq!(func.inst(ret).kind, InstKind::Return(vec![param]));
+}
+
+#[test]
+fn test_verify_errors() {
+    let mut module = sum_module();
+    let func = &mut module.functions[0];
+    let entry = func.entry().unwrap();
+    // Make `add v4, v2` add a bool
+    let body = func.layout[2];
+    let add = func.block(body).insts[0];
+    func.inst_mut(add).kind = InstKind::Binary(BinaryOp::Add, ir::Value(4), ir::Value(3));
+    // Drop the return
+    let exit = func.layout[3];
+    func.block_mut(exit).insts.clear();
+    // Use a value of the body in the entry block
+    let jump = func.terminator(entry).unwrap();
+    func.inst_mut(jump).kind.map_operands(|_| ir::Value(7));
+
+    let errors: Vec<String> = ir::verify(&module)
+        .unwrap_err()
+        .iter()
+        .map(|e| e.to_string())
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "@sum, block3: empty block".to_string(),
+            "@sum, block0: v7 is used where it is not always defined".to_string(),
+            "@sum, block0: v7 is used where it is not always defined".to_string(),
+            "@sum, block2: operands of `add` have different types i32 and bool".to_string(),
+        ]
+    );
+}
+
+fn translate(source: &str) -> Module {
+    let module = compile("test.glsl", source).unwrap_or_else(|e| panic!("{}", e.render()));
+    let ir = ir::translate(&module);
+    if let Err(errors) = ir::verify(&ir) {
+        panic!("{}\n{:#?}", ir, errors);
+    }
+    ir
+}
+
+#[test]
+fn test_translate_function() {
+    let module = translate(
+        r#"
+        vec2 scale(vec2 v, float s) { return v * s; }
+        void main() { gl_FragColor = vec4(scale(vec2(1.0), 2.0), 0.0, 1.0); }
+    "#,
+    );
+    assert_eq!(
+        module.to_string(),
+        "\
+global @gl_FragCoord: size 16, align 4
+global @gl_FragColor: size 16, align 4
+
+function @scale(f32x2, f32) -> f32x2 {
+block0(v0: f32x2, v1: f32):
+    v2: f32x2 = splat v1
+    v3: f32x2 = mul v0, v2
+    return v3
+}
+
+function @main() {
+block0:
+    v0: i32 = global_addr @gl_FragColor
+    v1: f32 = const 1.0
+    v2: f32x2 = splat v1
+    v3: f32 = const 2.0
+    v4: f32x2 = call @scale(v2, v3)
+    v5: f32 = extract v4, 0
+    v6: f32 = extract v4, 1
+    v7: f32 = const 0.0
+    v8: f32 = const 1.0
+    v9: f32x4 = vector v5, v6, v7, v8
+    store v9, v0
+    return
+}
+"
+    );
+}
+
+#[test]
+fn test_translate_memory_and_parameters() {
+    let module = translate(
+        r#"
+        uniform float time;
+        uniform vec2 resolution;
+        struct Light { vec3 color; float power[2]; };
+        float g = 2.0;
+
+        Light make(float p) { Light l; l.color = vec3(p); l.power[1] = p; return l; }
+        void split(in vec4 v, out vec2 a, inout float b, out Light l) {
+            a = v.xy; b += v.z; l = make(b);
+        }
+        mat2 rotate(float a) { return mat2(cos(a), sin(a), -sin(a), cos(a)); }
+
+        void main() {
+            vec2 a; float b = time; Light l;
+            split(vec4(1.0), a, b, l);
+            mat2 m = rotate(b) * rotate(g);
+            vec2 p = m * (gl_FragCoord.xy / resolution);
+            float k = l.power[int(p.x) & 1];
+            gl_FragColor = vec4(l.color * k, l == make(1.0) ? 1.0 : 0.0);
+        }
+    "#,
+    );
+    let globals: Vec<&str> = module.globals.iter().map(|g| g.name.as_str()).collect();
+    assert_eq!(
+        globals,
+        vec![ir::UNIFORMS, "gl_FragCoord", "gl_FragColor", "g"]
+    );
+    let split = module.function(module.find_function("split").unwrap());
+    assert_eq!(
+        split.signature.params,
+        vec![Type::vector(ir::ScalarType::F32, 4), Type::F32, Type::I32]
+    );
+    assert_eq!(
+        split.signature.returns,
+        vec![Type::vector(ir::ScalarType::F32, 2), Type::F32]
+    );
+    let make = module.function(module.find_function("make").unwrap());
+    assert_eq!(make.signature.params, vec![Type::I32, Type::F32]);
+    assert!(make.signature.returns.is_empty());
+}
+
+#[test]
+fn test_translate_control_flow() {
+    let module = translate(
+        r#"
+        int pick(int n) {
+            int r = 0;
+            switch (n) {
+                case 0: r = 1;
+                case 1: r += 2; break;
+                case 2: case 3: return 7;
+                default: r = -1;
+            }
+            return r;
+        }
+        float f(float x) {
+            float s = 0.0;
+            for (int i = 0; i < 8; i++) {
+                if (i == 2) continue;
+                if (s > 4.0 || x < 0.0) break;
+                s += x;
+            }
+            int j = 0;
+            do { j++; } while (j < 3 && s > 0.0);
+            while (true) { if (s > 1.0) { s -= 1.0; } else break; }
+            return s > 2.0 ? s : float(j);
+        }
+        void main() {
+            ivec2 v = ivec2(1, 2);
+            int i = pick(int(gl_FragCoord.x));
+            v[i & 1] += 3;
+            v.y++;
+            uint u = uint(i) / 3u;
+            if (gl_FragCoord.y < 0.0) discard;
+            gl_FragColor = vec4(f(float(v.x)), float(u >> 1u), vec2(v) == vec2(1.0) ? 1.0 : 0.0, 1.0);
+        }
+    "#,
+    );
+    assert_eq!(module.functions.len(), 3);
+}
+
+#[test]
+fn test_translate_overloads() {
+    let module = translate(
+        r#"
+        float twice(float x) { return 2.0 * x; }
+        vec2 twice(vec2 x) { return 2.0 * x; }
+        float later(float x);
+        void main() { gl_FragColor = vec4(twice(vec2(later(1.0))), twice(1.0), 1.0); }
+        float later(float x) { return x + 1.0; }
+    "#,
+    );
+    let names: Vec<&str> = module.functions.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, vec!["twice", "twice.1", "later", "main"]);
+    assert!(module.functions.iter().all(|f| !f.is_declaration()));
+}
@@ -0,0 +1,135 @@
+use std::{fs, path::Path};
+
+use lp_glsl_vm::{
+    compiler::compile,
+    ir::{self, Module, ParseError},
+};
+
+/// Parse `text`, check that it prints back unchanged and verifies
+fn round_trip(text: &str) -> Module {
+    let module = ir::parse(text).unwrap_or_else(|e| panic!("{}", e));
+    assert_eq!(module.to_string(), text);
+    if let Err(errors) = ir::verify(&module) {
+        panic!("{:#?}", errors);
+    }
+    module
+}
+
+#[test]
+fn test_round_trip_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ir");
+    let mut count = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "lpir") {
+            let text = fs::read_to_string(&path).unwrap();
+            println!("{}", path.display());
+            round_trip(&text);
+            count += 1;
+        }
+    }
+    assert!(count >= 2);
+}
+
+#[test]
+fn test_round_trip_translated() {
+    let sources = [
+        r#"
+        uniform float time;
+        struct Light { vec3 color; float power[2]; };
+        Light make(float p) { Light l; l.color = vec3(p); l.power[1] = p; return l; }
+        void split(vec4 v, out vec2 a, inout float b) { a = v.xy; b += v.z; }
+        void main() {
+            vec2 a; float b = time;
+            split(vec4(1.0, -0.5, 1e-7, 0.0), a, b);
+            Light l = make(b);
+            mat2 m = mat2(a, a.yx);
+            gl_FragColor = vec4(l.color * l.power[int(b) & 1], (m * a).x);
+        }
+    "#,
+        r#"
+        int pick(int n) {
+            switch (n) { case 0: return 1; case 1: case 2: n *= 3; default: break; }
+            return n;
+        }
+        void main() {
+            float s = 0.0;
+            for (int i = 0; i < 4 && s < 2.0; i++) { s += float(pick(i)); }
+            if (s > 3.0) discard;
+            gl_FragColor = vec4(s > 1.0 ? s : -s);
+        }
+    "#,
+    ];
+    for source in sources {
+        let module = compile("test.glsl", source).unwrap();
+        round_trip(&ir::translate(&module).to_string());
+    }
+}
+
+#[test]
+fn test_comments_and_forward_references() {
+    let module = ir::parse(
+        "\
+; Calls a function declared later
+function @main() -> f32 {
+block0:
+    v0: f32 = call @one()  ; forward reference
+    return v0
+}
+
+function @one() -> f32 {
+block0:
+    v0: f32 = const 1.0
+    return v0
+}
+",
+    )
+    .unwrap();
+    assert_eq!(ir::verify(&module), Ok(()));
+    assert_eq!(module.functions.len(), 2);
+    assert!(module
+        .to_string()
+        .starts_with("function @main() -> f32 {\n"));
+}
+
+#[test]
+fn test_parse_errors() {
+    let error = |text: &str| ir::parse(text).unwrap_err();
+
+    assert_eq!(
+        error("function @f() {\nblock0:\n    v0: i32 = frob v1\n}\n"),
+        ParseError {
+            line: 3,
+            message: "unknown instruction `frob`".into(),
+        }
+    );
+    assert_eq!(
+        error("function @f() {\nblock0:\n    v0: i32 = const 1.5\n    return\n}\n").to_string(),
+        "line 3: `1.5` is not a constant of type i32"
+    );
+    assert_eq!(
+        error("function @f() {\nblock0:\n    v0: i32 = add v1, v1\n    return\n}\n").to_string(),
+        "line 3: v1 is not defined"
+    );
+    assert_eq!(
+        error("function @f() {\nblock0:\n    v0: i32 = call @g()\n}\n").to_string(),
+        "line 3: unknown function `@g`"
+    );
+    assert_eq!(
+        error("function @f() {\nblock0:\n    return\n").to_string(),
+        "line 1: function body is not closed"
+    );
+    assert_eq!(
+        error("global @g: size 4, align 4\nglobal @g: size 4, align 4\n").to_string(),
+        "line 2: global `@g` is declared twice"
+    );
+    assert_eq!(
+        error("function @f(f32x5)\n").to_string(),
+        "line 1: unknown type `f32x5`"
+    );
+    assert_eq!(
+        error("function @f() {\nblock0:\n    v0: f32 = const 1.0\n    v0: f32 = const 2.0\n}\n")
+            .to_string(),
+        "line 4: v0 is defined twice"
+    );
+}
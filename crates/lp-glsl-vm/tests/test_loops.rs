@@ -0,0 +1,194 @@
+use lp_glsl_vm::{
+    analysis::loops::{classify, Termination},
+    compiler::{compile, compile_with, CompileOptions},
+    preprocessor::NoIncludes,
+    sema::hir::{ExprKind, Literal, Stmt},
+};
+
+/// Termination of each loop in `main`, with `body` as its body
+fn terminations(body: &str) -> Vec<Termination> {
+    let source = format!(
+        "uniform int n;\nuniform float t;\nvoid bump(inout int i) {{ i++; }}\nvoid main() \
+         {{\n{}\n}}\n",
+        body
+    );
+    let module = compile("shader.glsl", &source).unwrap();
+    let main = module.find_function("main").unwrap();
+    classify(&module)
+        .into_iter()
+        .filter(|l| l.function == main)
+        .map(|l| l.termination)
+        .collect()
+}
+
+#[test]
+fn test_bounded_loops() {
+    use Termination::Bounded;
+
+    assert_eq!(
+        terminations("for (int i = 0; i < 10; i++) {}"),
+        vec![Bounded(10)]
+    );
+    assert_eq!(
+        terminations("for (int i = 0; i <= 10; i += 3) {}"),
+        vec![Bounded(4)]
+    );
+    assert_eq!(
+        terminations("for (int i = 10; i > 0; --i) {}"),
+        vec![Bounded(10)]
+    );
+    assert_eq!(
+        terminations("for (int i = 5; i >= 5; i -= 1) {}"),
+        vec![Bounded(1)]
+    );
+    assert_eq!(
+        terminations("for (int i = 0; i != 12; i += 4) {}"),
+        vec![Bounded(3)]
+    );
+    assert_eq!(
+        terminations("for (int i = 3; i < 0; i++) {}"),
+        vec![Bounded(0)]
+    );
+    assert_eq!(
+        terminations("const int N = 8; for (int i = 0; i < N * 2; i++) {}"),
+        vec![Bounded(16)]
+    );
+    assert_eq!(
+        terminations("for (float x = 0.0; x < 1.0; x += 0.25) {}"),
+        vec![Bounded(4)]
+    );
+    assert_eq!(terminations("while (false) {}"), vec![Bounded(0)]);
+    assert_eq!(terminations("do {} while (false);"), vec![Bounded(1)]);
+    // Nested loops are numbered in source order
+    assert_eq!(
+        terminations(
+            "for (int i = 0; i < 2; i++) { while (true) {} } for (int j = 0; j < 3; j++) {}"
+        ),
+        vec![Bounded(2), Termination::Unbounded, Bounded(3)]
+    );
+}
+
+#[test]
+fn test_unbounded_loops() {
+    let unbounded = [
+        "while (true) {}",
+        "for (;;) {}",
+        "do {} while (n > 0);",
+        "int i = 0; while (i < 10) { i++; }",
+        "for (int i = 0; i < n; i++) {}",
+        "for (int i = 0; i < 10; i += n) {}",
+        "for (int i = 0; i < 10; i++) { i--; }",
+        "for (int i = 0; i < 10; i++) { bump(i); }",
+        "for (int i = 0; i != 10; i += 3) {}",
+        "for (int i = 0; i < 10; i--) {}",
+        "for (int i = 0; i <= 2147483647; i++) {}",
+        // The index stops changing once the step is below its precision
+        "for (float x = 16777216.0; x < 16777220.0; x += 1.0) {}",
+        "for (float x = 0.0; x < t; x += 0.5) {}",
+    ];
+    for body in unbounded {
+        assert_eq!(terminations(body), vec![Termination::Unbounded], "{}", body);
+    }
+}
+
+#[test]
+fn test_loop_cap() {
+    let source = "\
+uniform int n;
+void main() {
+    int total = 0;
+    for (int i = 0; i < 4; i++) {
+        total += i;
+    }
+    while (total < n) {
+        total *= 2;
+    }
+}
+";
+    let options = CompileOptions {
+        loop_cap: Some(1000),
+        ..Default::default()
+    };
+    let compiled = compile_with("shader.glsl", source, &options, &NoIncludes).unwrap();
+    let expected = "\
+warning: loop may not terminate
+ --> shader.glsl:7:5
+  |
+7 |     while (total < n) {
+  |     ^^^^^ capped at 1000 iterations
+  |
+  = note: bound the loop with a constant to avoid the cap, as in GLSL ES 1.00 Appendix A
+";
+    assert_eq!(compiled.render_warnings(), expected);
+
+    // The capped loop counts its iterations and breaks at the cap
+    let module = &compiled.module;
+    let main = module.function(module.find_function("main").unwrap());
+    let body = main.body.as_ref().unwrap();
+    let Some(Stmt::Block(capped)) = body.last() else {
+        panic!(
+            "expected the capped loop in a block, found {:?}",
+            body.last()
+        );
+    };
+    let [Stmt::Decl(_, Some(zero)), Stmt::Loop { body, .. }] = capped.as_slice() else {
+        panic!("expected a counter and a loop, found {:?}", capped);
+    };
+    assert!(matches!(zero.kind, ExprKind::Literal(Literal::UInt(0))));
+    let Stmt::If {
+        cond, then_branch, ..
+    } = &body[0]
+    else {
+        panic!("expected the cap check, found {:?}", body[0]);
+    };
+    let ExprKind::Binary(_, _, limit) = &cond.kind else {
+        panic!("expected a comparison, found {:?}", cond);
+    };
+    assert!(matches!(limit.kind, ExprKind::Literal(Literal::UInt(1000))));
+    assert!(matches!(then_branch.as_slice(), [Stmt::Break]));
+    assert_eq!(body.len(), 3);
+
+    // Bounded loops are untouched, and nothing is capped without the option
+    assert!(matches!(
+        classify(module)[0].termination,
+        Termination::Bounded(4)
+    ));
+    let compiled = compile_with("shader.glsl", source, &Default::default(), &NoIncludes).unwrap();
+    assert!(compiled.warnings.is_empty());
+    assert_eq!(
+        classify(&compiled.module)[1].termination,
+        Termination::Unbounded
+    );
+}
+
+#[test]
+fn test_do_while_cap_location() {
+    let source = "\
+void main() {
+    int i = 0;
+    do {
+        i++;
+    } while (i > 0);
+    for (;;) {
+        break;
+    }
+}
+";
+    let options = CompileOptions {
+        loop_cap: Some(16),
+        ..Default::default()
+    };
+    let compiled = compile_with("shader.glsl", source, &options, &NoIncludes).unwrap();
+    let rendered = compiled.render_warnings();
+    assert_eq!(compiled.warnings.len(), 2);
+    assert!(
+        rendered.contains("3 |     do {\n  |     ^^ capped at 16 iterations"),
+        "{}",
+        rendered
+    );
+    assert!(
+        rendered.contains("6 |     for (;;) {\n  |     ^^^ capped"),
+        "{}",
+        rendered
+    );
+}
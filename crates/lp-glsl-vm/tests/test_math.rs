@@ -0,0 +1,162 @@
+use lp_glsl_vm::{
+    runtime::{math, Routine},
+    sema::builtins::Builtin,
+};
+
+/// `count` evenly spaced samples of `[lo, hi]`
+fn samples(lo: f32, hi: f32, count: usize) -> impl Iterator<Item = f32> {
+    (0..=count).map(move |i| lo + (hi - lo) * (i as f32 / count as f32))
+}
+
+/// Largest absolute error of `f` against the `f64` reference over `xs`
+fn max_abs_error(
+    xs: impl Iterator<Item = f32>,
+    f: fn(f32) -> f32,
+    reference: fn(f64) -> f64,
+) -> f64 {
+    xs.map(|x| (f(x) as f64 - reference(x as f64)).abs())
+        .fold(0.0, f64::max)
+}
+
+/// Largest relative error of `f` against the `f64` reference over `xs`
+fn max_rel_error(
+    xs: impl Iterator<Item = f32>,
+    f: fn(f32) -> f32,
+    reference: fn(f64) -> f64,
+) -> f64 {
+    xs.map(|x| {
+        let expected = reference(x as f64);
+        ((f(x) as f64 - expected) / expected).abs()
+    })
+    .fold(0.0, f64::max)
+}
+
+fn exp2i(k: i32) -> f64 {
+    2f64.powi(k)
+}
+
+#[test]
+fn test_trigonometric_accuracy() {
+    let bound = exp2i(-21);
+    assert!(max_abs_error(samples(-6400.0, 6400.0, 200_000), math::sin, f64::sin) < bound);
+    assert!(max_abs_error(samples(-6400.0, 6400.0, 200_000), math::cos, f64::cos) < bound);
+    assert!(max_abs_error(samples(-10.0, 10.0, 100_000), math::atan, f64::atan) < exp2i(-22));
+    assert!(max_abs_error(samples(-1.0, 1.0, 100_000), math::asin, f64::asin) < exp2i(-20));
+    assert!(max_abs_error(samples(-1.0, 1.0, 100_000), math::acos, f64::acos) < exp2i(-20));
+    assert!(max_rel_error(samples(-1.4, 1.4, 100_000), math::tan, f64::tan) < exp2i(-20));
+
+    let mut worst: f64 = 0.0;
+    for y in samples(-5.0, 5.0, 200) {
+        for x in samples(-5.0, 5.0, 200) {
+            let error = (math::atan2(y, x) as f64 - (y as f64).atan2(x as f64)).abs();
+            // Points on the negative x axis may land on either side of the cut
+            if y != 0.0 {
+                worst = worst.max(error);
+            }
+        }
+    }
+    assert!(worst < exp2i(-21), "{}", worst);
+    assert_eq!(math::atan2(0.0, 0.0), 0.0);
+    assert!(math::sin(f32::INFINITY).is_nan());
+}
+
+#[test]
+fn test_exponential_accuracy() {
+    let bound = exp2i(-22);
+    assert!(max_rel_error(samples(-87.0, 88.0, 100_000), math::exp, f64::exp) < bound);
+    assert!(max_rel_error(samples(-126.0, 127.0, 100_000), math::exp2, f64::exp2) < bound);
+    assert!(max_abs_error(samples(0.5, 2.0, 100_000), math::log, f64::ln) < bound);
+    assert!(max_abs_error(samples(0.5, 2.0, 100_000), math::log2, f64::log2) < bound);
+    assert!(max_rel_error(samples(2.0, 1e30, 100_000), math::log, f64::ln) < bound);
+    assert!(max_rel_error(samples(2.0, 1e30, 100_000), math::log2, f64::log2) < bound);
+    assert!(max_rel_error(samples(1e-30, 0.5, 100_000), math::log2, f64::log2) < bound);
+    assert!(max_rel_error(samples(1e-20, 1e20, 100_000), math::sqrt, f64::sqrt) < exp2i(-23));
+    assert!(
+        max_rel_error(samples(1e-20, 1e20, 100_000), math::inverse_sqrt, |x| 1.0
+            / x.sqrt())
+            < exp2i(-22)
+    );
+
+    for x in samples(0.01, 100.0, 300) {
+        for y in samples(-8.0, 8.0, 300) {
+            let expected = (x as f64).powf(y as f64);
+            let error = ((math::pow(x, y) as f64 - expected) / expected).abs();
+            let bound = exp2i(-22) * (2.0 + (y as f64 * (x as f64).log2()).abs());
+            assert!(error < bound, "pow({}, {}): {} > {}", x, y, error, bound);
+        }
+    }
+    assert_eq!(math::pow(-2.0, 3.0), -8.0);
+    assert!(math::pow(-2.0, 0.5).is_nan());
+    assert_eq!(math::exp(100.0), f32::INFINITY);
+    assert_eq!(math::exp(-200.0), 0.0);
+    assert_eq!(math::log(0.0), f32::NEG_INFINITY);
+    assert!(math::log(-1.0).is_nan());
+    assert_eq!(math::sqrt(1e-40), 1e-40f32.sqrt());
+}
+
+#[test]
+fn test_hyperbolic_accuracy() {
+    let bound = exp2i(-20);
+    assert!(max_rel_error(samples(-10.0, 10.0, 100_001), math::sinh, f64::sinh) < bound);
+    assert!(max_rel_error(samples(-10.0, 10.0, 100_000), math::cosh, f64::cosh) < bound);
+    assert!(max_rel_error(samples(-10.0, 10.0, 100_001), math::tanh, f64::tanh) < bound);
+    assert!(max_abs_error(samples(-100.0, 100.0, 100_000), math::asinh, f64::asinh) < bound);
+    assert!(max_abs_error(samples(1.0, 100.0, 100_000), math::acosh, f64::acosh) < bound);
+    assert!(max_abs_error(samples(-0.999, 0.999, 100_000), math::atanh, f64::atanh) < bound);
+}
+
+#[test]
+fn test_exact_functions() {
+    for x in samples(-20.0, 20.0, 1_000).chain([0.5, -0.5, 1.5, -2.5, 1e10, -0.0]) {
+        assert_eq!(math::floor(x), x.floor(), "floor({})", x);
+        assert_eq!(math::ceil(x), x.ceil(), "ceil({})", x);
+        assert_eq!(math::trunc(x), x.trunc(), "trunc({})", x);
+        assert_eq!(math::round(x), x.round(), "round({})", x);
+        assert_eq!(math::round_even(x), x.round_ties_even(), "roundEven({})", x);
+        assert_eq!(math::fract(x), x - x.floor(), "fract({})", x);
+    }
+    assert_eq!(math::modulo(-1.5, 1.0), 0.5);
+    assert_eq!(math::step(0.5, 0.5), 1.0);
+    assert_eq!(math::smoothstep(0.0, 2.0, 1.0), 0.5);
+    assert_eq!(math::clamp(3.0, 0.0, 1.0), 1.0);
+    assert_eq!(math::mix(2.0, 4.0, 0.25), 2.5);
+}
+
+#[test]
+fn test_geometric_functions() {
+    let a = [3.0, 4.0, 12.0];
+    assert_eq!(math::dot(&a, &a), 169.0);
+    assert_eq!(math::length(&a), 13.0);
+    assert_eq!(math::distance(&a, &[3.0, 0.0, 9.0]), 5.0);
+    assert_eq!(
+        math::cross([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        [0.0, 0.0, 1.0]
+    );
+
+    let mut n = [3.0, 4.0];
+    math::normalize(&mut n);
+    assert!((n[0] - 0.6).abs() < 1e-7 && (n[1] - 0.8).abs() < 1e-7);
+
+    let mut i = [1.0, -1.0];
+    math::reflect(&mut i, &[0.0, 1.0]);
+    assert_eq!(i, [1.0, 1.0]);
+
+    // Grazing incidence from the denser medium is totally reflected
+    let mut i = [1.0, -0.01];
+    math::refract(&mut i, &[0.0, 1.0], 1.5);
+    assert_eq!(i, [0.0, 0.0]);
+}
+
+#[test]
+fn test_routines_match_functions() {
+    let sin = Routine::of(Builtin::Sin, 1).unwrap();
+    assert_eq!(sin.call(&[0.5]), math::sin(0.5));
+    let atan2 = Routine::of(Builtin::Atan, 2).unwrap();
+    assert_eq!(atan2.call(&[1.0, -1.0]), math::atan2(1.0, -1.0));
+    let smoothstep = Routine::of(Builtin::Smoothstep, 3).unwrap();
+    assert_eq!(
+        smoothstep.call(&[0.0, 1.0, 0.25]),
+        math::smoothstep(0.0, 1.0, 0.25)
+    );
+    assert!(Routine::of(Builtin::Length, 1).is_none());
+}
@@ -0,0 +1,240 @@
+mod common;
+
+use common::{analyze_source, assert_close, floats, global_value, main_exprs};
+use lp_glsl_vm::{
+    compiler::compile,
+    layout::Layout,
+    sema::{
+        builtins::Builtin,
+        hir::{Expr, ExprKind, Stmt},
+        ScalarType, SemaError, Type,
+    },
+};
+
+/// Whether an expression still operates on whole matrices rather than columns
+fn has_matrix_operation(expr: &Expr) -> bool {
+    let children: Vec<&Expr> = match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Var(_) => vec![],
+        ExprKind::Unary(_, e) | ExprKind::Field(e, _) | ExprKind::Swizzle(e, _) => vec![e],
+        ExprKind::Binary(_, a, b) | ExprKind::Assign(Some(_), a, b)
+            if a.ty.is_matrix() || b.ty.is_matrix() =>
+        {
+            return true
+        }
+        ExprKind::Binary(_, a, b)
+        | ExprKind::Assign(_, a, b)
+        | ExprKind::Index(a, b)
+        | ExprKind::Comma(a, b) => vec![a, b],
+        ExprKind::Ternary(a, b, c) => vec![a, b, c],
+        ExprKind::Builtin(
+            Builtin::MatrixCompMult | Builtin::Transpose | Builtin::OuterProduct,
+            _,
+        ) => return true,
+        ExprKind::Construct(args) if expr.ty.is_matrix() => {
+            if args
+                .iter()
+                .any(|arg| arg.ty != expr.ty.column_type().unwrap())
+            {
+                return true;
+            }
+            args.iter().collect()
+        }
+        ExprKind::Call(_, args) | ExprKind::Builtin(_, args) | ExprKind::Construct(args) => {
+            args.iter().collect()
+        }
+    };
+    children.into_iter().any(has_matrix_operation)
+}
+
+#[test]
+fn test_matrix_typing() {
+    let module = analyze_source(
+        r#"
+        mat2 rot(float a) {
+            float c = cos(a), s = sin(a);
+            return mat2(c, s, -s, c);
+        }
+        void main() {
+            mat2 m = rot(0.5);
+            vec2 a = m * vec2(1.0, 0.0);
+            vec2 b = vec2(1.0, 0.0) * m;
+            mat2 c = m * m;
+            vec2 d = m[1];
+            float e = m[1][0];
+            mat2 f = matrixCompMult(m, transpose(m)) * 2.0;
+            mat3 g = outerProduct(vec3(1.0), vec3(2.0));
+            mat4 h = mat4(1.0);
+            mat3 i = mat3(h);
+        }
+    "#,
+    )
+    .unwrap();
+    let types: Vec<_> = main_exprs(&module).iter().map(|e| e.ty.clone()).collect();
+    let vec2 = Type::Vector(ScalarType::Float, 2);
+    assert_eq!(
+        types,
+        vec![
+            Type::Matrix(2),
+            vec2.clone(),
+            vec2.clone(),
+            Type::Matrix(2),
+            vec2,
+            Type::FLOAT,
+            Type::Matrix(2),
+            Type::Matrix(3),
+            Type::Matrix(4),
+            Type::Matrix(3),
+        ]
+    );
+}
+
+#[test]
+fn test_matrix_errors() {
+    let errors = analyze_source(
+        r#"
+        void main() {
+            mat2 m = mat2(1.0);
+            vec3 v = m * vec3(1.0);
+            mat3 n = m * mat3(1.0);
+            mat2 k = mat2(m, 1.0);
+        }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(errors.len(), 3);
+    assert_eq!(
+        errors[0],
+        SemaError::InvalidOperands {
+            op: "*",
+            lhs: Type::Matrix(2),
+            rhs: Some(Type::Vector(ScalarType::Float, 3)),
+        }
+    );
+    assert_eq!(
+        errors[1],
+        SemaError::InvalidOperands {
+            op: "*",
+            lhs: Type::Matrix(2),
+            rhs: Some(Type::Matrix(3)),
+        }
+    );
+    assert!(matches!(errors[2], SemaError::TypeMismatch { .. }));
+}
+
+#[test]
+fn test_matrix_constant_folding() {
+    let module = analyze_source(
+        r#"
+        const mat2 A = mat2(1.0, 2.0, 3.0, 4.0);
+        const mat2 B = mat2(vec2(5.0, 6.0), vec2(7.0, 8.0));
+        const mat2 R = mat2(cos(0.5), sin(0.5), -sin(0.5), cos(0.5));
+        const vec2 RX = R * vec2(1.0, 0.0);
+        const vec2 ROW = vec2(1.0, 1.0) * A;
+        const mat2 AB = A * B;
+        const vec2 COLUMN = A[1];
+        const mat2 T = transpose(A);
+        const mat2 CM = matrixCompMult(A, B);
+        const mat2 OP = outerProduct(vec2(1.0, 2.0), vec2(3.0, 4.0));
+        const mat3 DIAG = mat3(2.0);
+        const mat3 GROWN = mat3(A);
+        const mat2 SCALED = A * 2.0 - 1.0;
+    "#,
+    )
+    .unwrap();
+    assert_close(
+        &floats(&global_value(&module, "RX")),
+        &[0.5f32.cos(), 0.5f32.sin()],
+    );
+    assert_close(&floats(&global_value(&module, "ROW")), &[3.0, 7.0]);
+    assert_close(
+        &floats(&global_value(&module, "AB")),
+        &[23.0, 34.0, 31.0, 46.0],
+    );
+    assert_close(&floats(&global_value(&module, "COLUMN")), &[3.0, 4.0]);
+    assert_close(&floats(&global_value(&module, "T")), &[1.0, 3.0, 2.0, 4.0]);
+    assert_close(
+        &floats(&global_value(&module, "CM")),
+        &[5.0, 12.0, 21.0, 32.0],
+    );
+    assert_close(&floats(&global_value(&module, "OP")), &[3.0, 6.0, 4.0, 8.0]);
+    assert_close(
+        &floats(&global_value(&module, "DIAG")),
+        &[2.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 2.0],
+    );
+    assert_close(
+        &floats(&global_value(&module, "GROWN")),
+        &[1.0, 2.0, 0.0, 3.0, 4.0, 0.0, 0.0, 0.0, 1.0],
+    );
+    assert_close(
+        &floats(&global_value(&module, "SCALED")),
+        &[1.0, 3.0, 5.0, 7.0],
+    );
+}
+
+#[test]
+fn test_matrix_lowering() {
+    let module = compile(
+        "shader.glsl",
+        r#"
+        mat2 rot(float a) {
+            return mat2(cos(a), sin(a), -sin(a), cos(a));
+        }
+        void main() {
+            mat2 m = rot(0.5);
+            mat3 big = mat3(m);
+            vec2 a = m * vec2(1.0, 0.0);
+            vec2 b = vec2(1.0, 0.0) * m;
+            m *= rot(0.25);
+            a *= m;
+            bool same = m == -transpose(m);
+            mat2 f = matrixCompMult(m, m) + outerProduct(a, b) / 2.0;
+            m++;
+            m[1] = vec2(0.0);
+        }
+    "#,
+    )
+    .unwrap();
+    for function in &module.functions {
+        let mut exprs = Vec::new();
+        collect_exprs(function.body.as_ref().unwrap(), &mut exprs);
+        for expr in exprs {
+            assert!(!has_matrix_operation(expr), "{:?}", expr);
+        }
+    }
+
+    // m * v is a sum of scaled columns
+    let exprs = main_exprs(&module);
+    let ExprKind::Binary(_, lhs, rhs) = &exprs[2].kind else {
+        panic!("expected a sum, found {:?}", exprs[2]);
+    };
+    assert_eq!(lhs.ty, Type::Vector(ScalarType::Float, 2));
+    assert_eq!(rhs.ty, Type::Vector(ScalarType::Float, 2));
+
+    // v * m is a vector of dot products
+    let ExprKind::Construct(args) = &exprs[3].kind else {
+        panic!("expected a constructor, found {:?}", exprs[3]);
+    };
+    assert!(args
+        .iter()
+        .all(|arg| matches!(arg.kind, ExprKind::Builtin(Builtin::Dot, _))));
+}
+
+fn collect_exprs<'a>(block: &'a [Stmt], out: &mut Vec<&'a Expr>) {
+    for stmt in block {
+        match stmt {
+            Stmt::Expr(expr) | Stmt::Decl(_, Some(expr)) | Stmt::Return(Some(expr)) => {
+                out.push(expr)
+            }
+            Stmt::Block(block) => collect_exprs(block, out),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_matrix_layout() {
+    assert_eq!(Layout::of(&Type::Matrix(2)).size, 16);
+    assert_eq!(Layout::of(&Type::Matrix(3)).size, 36);
+    assert_eq!(Layout::of(&Type::Matrix(4)).size, 64);
+    assert_eq!(Layout::of(&Type::Matrix(3)).align, 4);
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use common::{analyze_source, global_value};
+use lp_glsl_vm::{
+    runtime::noise,
+    sema::{
+        hir::{Literal, Module},
+        SemaError,
+    },
+};
+
+/// Folded value of the scalar global called `name`
+fn global_scalar(module: &Module, name: &str) -> Literal {
+    global_value(module, name)
+        .as_scalar()
+        .cloned()
+        .expect("expected a scalar")
+}
+
+/// `count` evenly spaced samples of the square `[lo, hi]²`
+fn grid(lo: f32, hi: f32, count: usize) -> impl Iterator<Item = (f32, f32)> {
+    let step = (hi - lo) / count as f32;
+    (0..count)
+        .flat_map(move |i| (0..count).map(move |j| (lo + i as f32 * step, lo + j as f32 * step)))
+}
+
+#[test]
+fn test_extension_gating() {
+    let source = r#"
+        void main() {
+            float n = lp_noise2(vec2(0.5));
+        }
+    "#;
+    let errors = analyze_source(source).unwrap_err();
+    assert_eq!(
+        errors,
+        vec![SemaError::UndeclaredFunction("lp_noise2".into())]
+    );
+    assert_eq!(
+        errors[0].notes(),
+        vec!["enable it with `#extension GL_LP_noise : enable`".to_string()]
+    );
+
+    // Without the extension the names are free for user functions
+    analyze_source(
+        r#"
+        uint lp_hash(uint x) { return x * 3u; }
+        void main() {
+            uint h = lp_hash(1u);
+        }
+    "#,
+    )
+    .unwrap();
+
+    analyze_source(
+        r#"
+        #extension GL_LP_noise : enable
+        void main() {
+            float a = lp_noise2(vec2(0.5));
+            float b = lp_noise3(vec3(0.5));
+            float c = lp_fbm(vec2(0.5), 4) + lp_fbm(vec3(0.5), 4);
+            uint d = lp_hash(1u) ^ lp_hash(uvec2(1u, 2u)) ^ lp_hash(ivec3(1));
+        }
+    "#,
+    )
+    .unwrap();
+
+    let errors = analyze_source(
+        r#"
+        #extension GL_LP_noise : enable
+        #extension GL_LP_noise : disable
+        void main() {
+            uint h = lp_hash(1u);
+        }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors,
+        vec![SemaError::UndeclaredFunction("lp_hash".into())]
+    );
+
+    let errors = analyze_source(
+        r#"
+        #extension GL_FOO_bar : require
+        void main() {}
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors,
+        vec![SemaError::UnsupportedExtension("GL_FOO_bar".into())]
+    );
+    analyze_source("#extension GL_FOO_bar : enable\nvoid main() {}").unwrap();
+}
+
+#[test]
+fn test_noise_range_and_continuity() {
+    let mut sum_squares = 0.0;
+    let mut count = 0;
+    for (x, y) in grid(-7.0, 9.0, 300) {
+        let n = noise::noise2(x, y);
+        assert!((-1.0..=1.0).contains(&n), "noise2({}, {}) = {}", x, y, n);
+        assert!(
+            (noise::noise2(x + 1e-3, y) - n).abs() < 0.02,
+            "noise2({}, {})",
+            x,
+            y
+        );
+        sum_squares += n * n;
+        count += 1;
+
+        let n = noise::noise3(x, y, x - y);
+        assert!((-1.0..=1.0).contains(&n), "noise3({}, {}) = {}", x, y, n);
+        assert!((noise::noise3(x, y, x - y + 1e-3) - n).abs() < 0.02);
+
+        let f = noise::fbm2(x, y, 6);
+        assert!((-1.0..=1.0).contains(&f), "fbm2({}, {}) = {}", x, y, f);
+    }
+    // The noise is not degenerate
+    let rms = (sum_squares / count as f32).sqrt();
+    assert!(rms > 0.3, "{}", rms);
+}
+
+#[test]
+fn test_fbm_octaves() {
+    assert_eq!(noise::fbm2(1.25, -3.5, 0), 0.0);
+    assert_eq!(noise::fbm3(1.25, -3.5, 0.5, -4), 0.0);
+    // A single octave is plain noise
+    assert_eq!(noise::fbm2(1.25, -3.5, 1), noise::noise2(1.25, -3.5));
+    assert_eq!(
+        noise::fbm3(1.25, -3.5, 0.5, 1),
+        noise::noise3(1.25, -3.5, 0.5)
+    );
+    // Octaves past the limit add nothing
+    assert_eq!(noise::fbm2(1.25, -3.5, 12), noise::fbm2(1.25, -3.5, 1000));
+    assert!(noise::fbm2(1000.0, 1000.0, 12).is_finite());
+}
+
+#[test]
+fn test_hash() {
+    assert_eq!(noise::hash(0), 0);
+    // Neighbouring inputs give unrelated outputs
+    let hashes: std::collections::BTreeSet<u32> = (0..10_000).map(noise::hash).collect();
+    assert_eq!(hashes.len(), 10_000);
+    assert_ne!(noise::hash2(1, 2), noise::hash2(2, 1));
+    assert_ne!(noise::hash3(1, 2, 3), noise::hash3(3, 2, 1));
+    let ones: u32 = (0..1000).map(|x| noise::hash(x).count_ones()).sum();
+    assert!((15_000..17_000).contains(&ones), "{}", ones);
+}
+
+#[test]
+fn test_deterministic_values() {
+    // Pinned so that any change to the integer pipeline, which must produce
+    // the same bits on every target, shows up here
+    assert_eq!(noise::hash(1), 0x6889_90c0);
+    assert_eq!(noise::hash2(1, 2), 0x2300_69ce);
+    assert_eq!(noise::hash3(1, 2, 3), 0x68e9_7049);
+    assert_eq!(noise::noise2(1.25, -3.5).to_bits(), 0xbeb7_c000);
+    assert_eq!(noise::noise3(0.5, 1.5, -2.25).to_bits(), 0x3f24_2000);
+}
+
+#[test]
+fn test_noise_constant_folding() {
+    let module = analyze_source(
+        r#"
+        #extension GL_LP_noise : enable
+        const uint H = lp_hash(7u);
+        const uint H2 = lp_hash(ivec2(1, 2));
+        const float N = lp_noise2(vec2(1.25, -3.5));
+        const float N3 = lp_noise3(vec3(0.5, 1.5, -2.25));
+        const float F = lp_fbm(vec2(1.25, -3.5), 4);
+    "#,
+    )
+    .unwrap();
+    assert_eq!(global_scalar(&module, "H"), Literal::UInt(noise::hash(7)));
+    assert_eq!(
+        global_scalar(&module, "H2"),
+        Literal::UInt(noise::hash2(1, 2))
+    );
+    assert_eq!(
+        global_scalar(&module, "N"),
+        Literal::Float(noise::noise2(1.25, -3.5))
+    );
+    assert_eq!(
+        global_scalar(&module, "N3"),
+        Literal::Float(noise::noise3(0.5, 1.5, -2.25))
+    );
+    assert_eq!(
+        global_scalar(&module, "F"),
+        Literal::Float(noise::fbm2(1.25, -3.5, 4))
+    );
+}
@@ -0,0 +1,604 @@
+use lp_glsl_vm::{
+    compiler::compile,
+    ir::{
+        self,
+        interpret::{Bits, Exit, Interpreter},
+        Module, ScalarType,
+    },
+    opt::{OptLevel, Pass, PassManager},
+    reference::Reference,
+};
+
+/// Run `passes` over the module in `text`, check that it still verifies and
+/// that its last function returns the same for each of `inputs`, and print
+/// it
+fn optimize(text: &str, passes: &[Pass], inputs: &[i32]) -> String {
+    let before = ir::parse(text).unwrap_or_else(|e| panic!("{}", e));
+    let mut after = before.clone();
+    let manager = PassManager {
+        passes: passes.to_vec(),
+        ..PassManager::for_level(OptLevel::O1)
+    };
+    manager.run(&mut after);
+    if let Err(errors) = ir::verify(&after) {
+        panic!("{}\n{:#?}", after, errors);
+    }
+    for &input in inputs {
+        assert_eq!(call(&after, input), call(&before, input), "f({})", input);
+    }
+    after.to_string()
+}
+
+/// Call the last function with arguments derived from `input`, different
+/// for each parameter and lane
+fn call(module: &Module, input: i32) -> Exit {
+    let f = ir::FuncRef(module.functions.len() as u32 - 1);
+    let args: Vec<Bits> = (module.function(f).signature.params.iter().enumerate())
+        .map(|(i, ty)| {
+            let mut bits = [0; 4];
+            for (lane, bits) in bits.iter_mut().enumerate().take(ty.lanes as usize) {
+                let n = input + (i * 4 + lane) as i32 * 3 % 7;
+                *bits = match ty.scalar {
+                    ScalarType::Bool => n as u32 & 1,
+                    ScalarType::I32 => n as u32,
+                    ScalarType::F32 => (n as f32 * 0.37 - 0.5).to_bits(),
+                };
+            }
+            bits
+        })
+        .collect();
+    Interpreter::new(module).call(f, &args).unwrap()
+}
+
+#[test]
+fn test_const_prop() {
+    let text = "\
+function @f(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = const 2
+    v2: i32 = const 3
+    v3: i32 = mul v1, v2
+    v4: bool = lt v3, v2
+    brif v4, block1, block2(v1)
+block1:
+    jump block2(v3)
+block2(v5: i32):
+    v6: i32 = const 0
+    v7: i32 = add v0, v6
+    v8: i32 = mul v7, v5
+    return v8
+}
+";
+    let optimized = optimize(text, &[Pass::ConstProp, Pass::Dce], &[-3, 0, 7]);
+    assert_eq!(
+        optimized,
+        "\
+function @f(i32) -> i32 {
+block0(v0: i32):
+    v9: i32 = const 2
+    v8: i32 = mul v0, v9
+    return v8
+}
+"
+    );
+}
+
+#[test]
+fn test_dce() {
+    // The loop computes a product nobody reads and an unused load
+    let text = "\
+global @g: size 4, align 4
+
+function @f(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = const 0
+    v2: i32 = const 1
+    jump block1(v1, v1, v2)
+block1(v3: i32, v4: i32, v5: i32):
+    v6: bool = lt v3, v0
+    brif v6, block2, block3
+block2:
+    v7: i32 = add v4, v3
+    v8: i32 = mul v5, v3
+    v9: i32 = add v3, v2
+    jump block1(v9, v7, v8)
+block3:
+    v10: i32 = global_addr @g
+    v11: i32 = load v10+0
+    jump block4
+block4:
+    return v4
+}
+";
+    let optimized = optimize(text, &[Pass::Dce], &[-1, 0, 5]);
+    assert_eq!(
+        optimized,
+        "\
+global @g: size 4, align 4
+
+function @f(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = const 0
+    v2: i32 = const 1
+    jump block1(v1, v1)
+block1(v3: i32, v4: i32):
+    v6: bool = lt v3, v0
+    brif v6, block2, block3
+block2:
+    v7: i32 = add v4, v3
+    v9: i32 = add v3, v2
+    jump block1(v9, v7)
+block3:
+    return v4
+}
+"
+    );
+}
+
+#[test]
+fn test_cse() {
+    // Only the sum in block1, dominated by the first one, is redundant
+    let text = "\
+function @f(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = const 3
+    v2: i32 = add v0, v1
+    v3: bool = lt v0, v1
+    brif v3, block1, block2
+block1:
+    v4: i32 = add v1, v0
+    v5: i32 = const 3
+    v6: i32 = mul v4, v5
+    jump block3(v6)
+block2:
+    v7: i32 = sub v0, v1
+    jump block3(v7)
+block3(v8: i32):
+    v9: i32 = sub v0, v1
+    v10: i32 = add v8, v9
+    v11: i32 = add v10, v2
+    return v11
+}
+";
+    let optimized = optimize(text, &[Pass::Cse], &[0, 3, 10]);
+    assert_eq!(
+        optimized,
+        "\
+function @f(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = const 3
+    v2: i32 = add v0, v1
+    v3: bool = lt v0, v1
+    brif v3, block1, block2
+block1:
+    v6: i32 = mul v2, v1
+    jump block3(v6)
+block2:
+    v7: i32 = sub v0, v1
+    jump block3(v7)
+block3(v8: i32):
+    v9: i32 = sub v0, v1
+    v10: i32 = add v8, v9
+    v11: i32 = add v10, v2
+    return v11
+}
+"
+    );
+}
+
+#[test]
+fn test_licm() {
+    // The loop is entered from a branch, so it needs a preheader
+    let text = "\
+function @f(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = const 0
+    v2: bool = lt v0, v1
+    brif v2, block3(v1), block1(v1, v1)
+block1(v3: i32, v4: i32):
+    v5: bool = lt v3, v0
+    brif v5, block2, block3(v4)
+block2:
+    v6: i32 = mul v0, v0
+    v7: i32 = const 1
+    v8: i32 = add v6, v3
+    v9: i32 = add v4, v8
+    v10: i32 = add v3, v7
+    jump block1(v10, v9)
+block3(v11: i32):
+    return v11
+}
+";
+    let optimized = optimize(text, &[Pass::Licm], &[-2, 0, 4]);
+    assert_eq!(
+        optimized,
+        "\
+function @f(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = const 0
+    v2: bool = lt v0, v1
+    brif v2, block3(v1), block4(v1, v1)
+block4(v12: i32, v13: i32):
+    v6: i32 = mul v0, v0
+    v7: i32 = const 1
+    jump block1(v12, v13)
+block1(v3: i32, v4: i32):
+    v5: bool = lt v3, v0
+    brif v5, block2, block3(v4)
+block2:
+    v8: i32 = add v6, v3
+    v9: i32 = add v4, v8
+    v10: i32 = add v3, v7
+    jump block1(v10, v9)
+block3(v11: i32):
+    return v11
+}
+"
+    );
+}
+
+#[test]
+fn test_inline() {
+    let text = "\
+function @square(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = mul v0, v0
+    return v1
+}
+
+function @f(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = call @square(v0)
+    v2: i32 = call @square(v1)
+    return v2
+}
+
+function @main() -> i32 {
+block0:
+    v0: i32 = const 2
+    v1: i32 = call @f(v0)
+    return v1
+}
+";
+    let optimized = optimize(text, &[Pass::Inline, Pass::Dce], &[0]);
+    // `@f` is called once, and `@square` is small enough for every call, so
+    // both are inlined and removed
+    assert_eq!(
+        optimized,
+        "\
+function @main() -> i32 {
+block0:
+    v0: i32 = const 2
+    v6: i32 = mul v0, v0
+    v8: i32 = mul v6, v6
+    return v8
+}
+"
+    );
+}
+
+#[test]
+fn test_inline_keeps_recursion() {
+    let text = "\
+function @f(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = const 0
+    v2: bool = le v0, v1
+    brif v2, block1, block2
+block1:
+    return v1
+block2:
+    v3: i32 = const 1
+    v4: i32 = sub v0, v3
+    v5: i32 = call @f(v4)
+    v6: i32 = add v5, v0
+    return v6
+}
+";
+    let optimized = optimize(text, &[Pass::Inline], &[0, 4]);
+    assert_eq!(optimized, ir::parse(text).unwrap().to_string());
+}
+
+#[test]
+fn test_scalarize() {
+    // Only the lane that is read survives
+    let text = "\
+function @f(f32x3, f32) -> f32 {
+block0(v0: f32x3, v1: f32):
+    v2: f32x3 = splat v1
+    v3: f32x3 = mul v0, v2
+    v4: f32 = extract v3, 2
+    return v4
+}
+";
+    let optimized = optimize(text, &[Pass::Scalarize, Pass::Dce], &[-2, 0, 5]);
+    assert_eq!(
+        optimized,
+        "\
+function @f(f32x3, f32) -> f32 {
+block0(v0: f32x3, v1: f32):
+    v7: f32 = extract v0, 2
+    v10: f32 = mul v7, v1
+    return v10
+}
+"
+    );
+}
+
+#[test]
+fn test_scalarize_block_params() {
+    let text = "\
+global @g: size 8, align 4
+
+function @f(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = const 0
+    v2: i32x2 = splat v1
+    jump block1(v1, v2)
+block1(v3: i32, v4: i32x2):
+    v5: bool = lt v3, v0
+    brif v5, block2, block3
+block2:
+    v6: i32 = const 1
+    v7: i32x2 = vector v3, v6
+    v8: i32x2 = add v4, v7
+    v9: i32 = add v3, v6
+    jump block1(v9, v8)
+block3:
+    v10: i32 = global_addr @g
+    store v4, v10
+    v11: i32x2 = load v10
+    v12: i32x2 = insert v11, 0, v0
+    v13: boolx2 = eq v12, v11
+    v14: bool = builtin all(v13)
+    v15: i32 = extract v11, 1
+    v16: i32 = select v14, v15, v3
+    return v16
+}
+";
+    let optimized = optimize(text, &[Pass::Scalarize], &[-1, 0, 4]);
+    assert_eq!(
+        optimized,
+        "\
+global @g: size 8, align 4
+
+function @f(i32) -> i32 {
+block0(v0: i32):
+    v1: i32 = const 0
+    jump block1(v1, v1, v1)
+block1(v3: i32, v17: i32, v18: i32):
+    v5: bool = lt v3, v0
+    brif v5, block2, block3
+block2:
+    v6: i32 = const 1
+    v24: i32 = add v17, v3
+    v25: i32 = add v18, v6
+    v9: i32 = add v3, v6
+    jump block1(v9, v24, v25)
+block3:
+    v10: i32 = global_addr @g
+    store v17, v10
+    store v18, v10+4
+    v19: i32 = load v10
+    v20: i32 = load v10+4
+    v21: bool = eq v0, v19
+    v22: bool = eq v20, v20
+    v23: bool = and v21, v22
+    v16: i32 = select v23, v20, v3
+    return v16
+}
+"
+    );
+}
+
+#[test]
+fn test_scalarize_geometry() {
+    let text = "\
+function @f(f32x3, f32x3, f32x2) -> f32x4 {
+block0(v0: f32x3, v1: f32x3, v2: f32x2):
+    v3: f32 = builtin dot(v0, v1)
+    v4: f32 = builtin length(v0)
+    v5: f32 = builtin distance(v0, v1)
+    v6: f32x3 = builtin normalize(v1)
+    v7: f32x3 = builtin cross(v0, v6)
+    v8: f32x3 = builtin mix(v0, v7, v3)
+    v9: f32x3 = builtin clamp(v8, v4, v5)
+    v10: f32 = builtin lp_noise2(v2)
+    v11: f32 = extract v9, 1
+    v12: f32 = add v11, v10
+    v13: f32 = extract v7, 2
+    v14: f32 = extract v6, 0
+    v15: f32x4 = vector v3, v12, v13, v14
+    return v15
+}
+";
+    let optimized = optimize(text, &[Pass::Scalarize, Pass::Dce], &[-3, 0, 1, 8]);
+    // Vectors are only left for the parameters, the noise and the result
+    let vector_insts: Vec<&str> = optimized
+        .lines()
+        .filter(|line| line.starts_with("    ") && line.contains(": f32x"))
+        .collect();
+    assert_eq!(
+        vector_insts,
+        [
+            "    v68: f32x2 = vector v22, v23",
+            "    v69: f32x4 = vector v28, v12, v61, v50",
+        ],
+        "{}",
+        optimized
+    );
+}
+
+#[test]
+fn test_scalarize_reflection() {
+    // Scalar arguments are expanded as vectors of one lane
+    let text = "\
+function @f(f32x3, f32x3, f32, f32) -> f32x4 {
+block0(v0: f32x3, v1: f32x3, v2: f32, v3: f32):
+    v4: f32x3 = builtin normalize(v1)
+    v5: f32x3 = builtin refract(v0, v4, v2)
+    v6: f32x3 = builtin reflect(v5, v4)
+    v7: f32x3 = builtin faceforward(v6, v0, v1)
+    v8: f32 = builtin reflect(v2, v3)
+    v9: f32 = builtin refract(v3, v2, v3)
+    v10: f32 = builtin faceforward(v2, v3, v9)
+    v11: f32 = extract v7, 0
+    v12: f32 = extract v7, 2
+    v13: f32x4 = vector v11, v12, v8, v10
+    return v13
+}
+";
+    let optimized = optimize(text, &[Pass::Scalarize, Pass::Dce], &[-3, 0, 1, 2, 8]);
+    assert!(!optimized.contains("builtin reflect"), "{}", optimized);
+    assert!(!optimized.contains("builtin refract"), "{}", optimized);
+    assert!(!optimized.contains("builtin faceforward"), "{}", optimized);
+}
+
+const SHADERS: &[&str] = &[
+    r#"
+    uniform float time;
+    float wave(float x) { return sin(x * 3.0 + time) * 0.5 + 0.5; }
+    vec3 palette(float t) { return vec3(t, t * t, 1.0 - t); }
+    void main() {
+        vec2 uv = gl_FragCoord.xy / vec2(4.0, 3.0);
+        float sum = 0.0;
+        for (int i = 0; i < 4; i++) {
+            float scale = time * 2.0 + 1.0;
+            sum += wave(uv.x * scale + float(i)) * wave(uv.y) * 0.25;
+        }
+        gl_FragColor = vec4(palette(sum), 1.0);
+    }
+    "#,
+    r#"
+    struct Ray { vec3 origin; vec3 dir; };
+    float hit(Ray r, float radius) {
+        float b = dot(r.origin, r.dir);
+        float c = dot(r.origin, r.origin) - radius * radius;
+        float h = b * b - c;
+        if (h < 0.0) return -1.0;
+        return -b - sqrt(h);
+    }
+    void main() {
+        Ray r;
+        r.origin = vec3(0.0, 0.0, -3.0);
+        r.dir = normalize(vec3(gl_FragCoord.xy / 2.0 - 1.0, 1.0));
+        float t = hit(r, 1.0);
+        if (t < 0.0) discard;
+        int n = int(t * 10.0);
+        int k = n / 3 + n % 3;
+        gl_FragColor = vec4(vec3(t / 4.0), float(k) / 10.0);
+    }
+    "#,
+    r#"
+    int collatz(int n) {
+        int steps = 0;
+        while (n != 1 && steps < 100) {
+            n = (n % 2 == 0) ? n / 2 : 3 * n + 1;
+            steps++;
+        }
+        return steps;
+    }
+    void main() {
+        int x = int(gl_FragCoord.x) + 4 * int(gl_FragCoord.y) + 1;
+        float c = 0.0;
+        for (int i = 0; i < 3; i++) {
+            for (int j = 0; j < 2; j++) {
+                c += float(i * j) * 0.01 + 0.1;
+            }
+        }
+        gl_FragColor = vec4(float(collatz(x)) / 20.0, c, 0.0, 1.0);
+    }
+    "#,
+];
+
+#[test]
+fn test_presets_keep_colours() {
+    for source in SHADERS {
+        let module = compile("test.glsl", source).unwrap_or_else(|e| panic!("{}", e.render()));
+        let expected = Reference::new(&module).render(4, 3).unwrap();
+        for level in [OptLevel::O0, OptLevel::O1, OptLevel::Os] {
+            let mut reference = Reference::new(&module);
+            let before = reference.module().clone();
+            let stats = PassManager::for_level(level).run(reference.module_mut());
+            let after = reference.module();
+            if let Err(errors) = ir::verify(after) {
+                panic!("{} {}\n{:#?}", level, after, errors);
+            }
+            assert_eq!(reference.render(4, 3).unwrap(), expected, "{}", level);
+
+            // Operations the guest runs, a vector operation being one per lane
+            let cost = |module: &Module| -> usize {
+                let mut cost = 0;
+                for func in &module.functions {
+                    for &block in &func.layout {
+                        for &inst in &func.block(block).insts {
+                            let lanes = func.inst(inst).results.first();
+                            cost += lanes.map_or(1, |&v| func.value_type(v).lanes as usize);
+                        }
+                    }
+                }
+                cost
+            };
+            match level {
+                OptLevel::O0 => {
+                    assert_eq!(after, &before);
+                    assert!(stats.passes.is_empty());
+                }
+                _ => assert!(cost(after) < cost(&before), "{}\n{}", level, stats),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_os_inlines_less() {
+    let module = compile("test.glsl", SHADERS[0]).unwrap();
+    let mut o1 = ir::translate(&module);
+    let mut os = o1.clone();
+    PassManager::for_level(OptLevel::O1).run(&mut o1);
+    PassManager::for_level(OptLevel::Os).run(&mut os);
+    let calls = |module: &Module| module.to_string().matches(" call ").count();
+    // `palette` is called once and inlined by both; `wave` is called twice
+    // and only inlined by `-O1`
+    assert_eq!(calls(&o1), 0);
+    assert_eq!(calls(&os), 2);
+    assert!(os.find_function("wave").is_some());
+    assert!(os.find_function("palette").is_none());
+}
+
+#[test]
+fn test_stats() {
+    let module = compile("test.glsl", SHADERS[2]).unwrap();
+    let mut module = ir::translate(&module);
+    let stats = PassManager::for_level(OptLevel::O1).run(&mut module);
+    let names: Vec<&str> = stats.passes.iter().map(|s| s.pass.name()).collect();
+    assert_eq!(
+        names,
+        [
+            "inline",
+            "scalarize",
+            "const-prop",
+            "dce",
+            "cse",
+            "licm",
+            "const-prop",
+            "cse",
+            "dce"
+        ]
+    );
+    assert_eq!(stats.total(Pass::Inline), 1);
+    assert!(stats.total(Pass::Licm) > 0);
+    for pair in stats.passes.windows(2) {
+        assert_eq!(pair[0].insts_after, pair[1].insts_before);
+    }
+
+    let report = stats.to_string();
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines.len(), 10);
+    assert_eq!(lines[0], "pass         changes  before   after");
+    assert!(lines[1].starts_with("inline             1"), "{}", report);
+
+    assert_eq!(Pass::from_name("licm"), Some(Pass::Licm));
+    assert_eq!(OptLevel::from_name("-Os"), Some(OptLevel::Os));
+    assert_eq!(OptLevel::from_name("-O3"), None);
+}
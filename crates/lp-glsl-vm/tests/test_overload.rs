@@ -0,0 +1,174 @@
+mod common;
+
+use common::analyze_source;
+use lp_glsl_vm::{
+    compiler::compile,
+    sema::{
+        builtins::Builtin,
+        hir::{Expr, ExprKind, Module, Stmt},
+        ScalarType, SemaError, Type,
+    },
+};
+
+/// Initializer of the `index`th statement of `main`, which must be a declaration
+fn main_init(module: &Module, index: usize) -> &Expr {
+    let main = module.function(module.find_function("main").unwrap());
+    match &main.body.as_ref().unwrap()[index] {
+        Stmt::Decl(_, Some(init)) => init,
+        other => panic!("expected an initialized declaration, found {:?}", other),
+    }
+}
+
+#[test]
+fn test_user_overloads_resolve_by_argument_type() {
+    let module = analyze_source(
+        r#"
+        float hash(vec2 p) { return p.x; }
+        float hash(vec3 p) { return p.y; }
+        void main() {
+            float a = hash(vec3(1.0));
+            float b = hash(vec2(1.0));
+        }
+    "#,
+    )
+    .unwrap();
+    let ExprKind::Call(a, _) = &main_init(&module, 0).kind else {
+        panic!("expected a call");
+    };
+    let ExprKind::Call(b, _) = &main_init(&module, 1).kind else {
+        panic!("expected a call");
+    };
+    assert_eq!(
+        module.function(*a).params[0].ty,
+        Type::Vector(ScalarType::Float, 3)
+    );
+    assert_eq!(
+        module.function(*b).params[0].ty,
+        Type::Vector(ScalarType::Float, 2)
+    );
+}
+
+#[test]
+fn test_builtin_call() {
+    let module = analyze_source(
+        r#"
+        void main() {
+            vec3 c = max(vec3(0.5), 0.25);
+            float d = dot(c, c);
+        }
+    "#,
+    )
+    .unwrap();
+    let init = main_init(&module, 0);
+    assert!(matches!(init.kind, ExprKind::Builtin(Builtin::Max, _)));
+    assert_eq!(init.ty, Type::Vector(ScalarType::Float, 3));
+    assert!(matches!(
+        main_init(&module, 1).kind,
+        ExprKind::Builtin(Builtin::Dot, _)
+    ));
+}
+
+#[test]
+fn test_implicit_int_to_float_conversion() {
+    let module = analyze_source(
+        r#"
+        #version 330
+        float scale(float x) { return x * 2; }
+        void main() {
+            float a = scale(3);
+            vec2 b = ivec2(1, 2) + vec2(0.5);
+        }
+    "#,
+    )
+    .unwrap();
+    let ExprKind::Call(_, args) = &main_init(&module, 0).kind else {
+        panic!("expected a call");
+    };
+    assert_eq!(args[0].ty, Type::FLOAT);
+    assert!(matches!(args[0].kind, ExprKind::Construct(_)));
+    assert_eq!(main_init(&module, 1).ty, Type::Vector(ScalarType::Float, 2));
+}
+
+#[test]
+fn test_no_implicit_conversion_in_es() {
+    let errors = analyze_source(
+        r#"
+        #version 300 es
+        float scale(float x) { return x; }
+        void main() { float a = scale(3); }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors,
+        vec![SemaError::NoMatchingOverload {
+            name: "scale".into(),
+            args: "int".into(),
+            candidates: vec!["float scale(float)".into()],
+        }]
+    );
+}
+
+#[test]
+fn test_exact_match_beats_conversion() {
+    let module = analyze_source(
+        r#"
+        #version 330
+        int pick(int x) { return x; }
+        float pick(float x) { return x; }
+        void main() { int a = pick(1); }
+    "#,
+    )
+    .unwrap();
+    assert_eq!(main_init(&module, 0).ty, Type::INT);
+}
+
+#[test]
+fn test_ambiguous_call() {
+    let errors = analyze_source(
+        r#"
+        #version 330
+        void g(float a, int b) {}
+        void g(int a, float b) {}
+        void main() { g(1, 1); }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors,
+        vec![SemaError::AmbiguousCall {
+            name: "g".into(),
+            args: "int, int".into(),
+            candidates: vec!["void g(float, int)".into(), "void g(int, float)".into()],
+        }]
+    );
+}
+
+#[test]
+fn test_builtins_cannot_be_redefined() {
+    let errors = analyze_source("float sin(float x) { return x; }").unwrap_err();
+    assert_eq!(errors, vec![SemaError::Redefinition("sin".into())]);
+}
+
+#[test]
+fn test_diagnostic_lists_candidates() {
+    let source = "\
+float hash(vec2 p) { return p.x; }
+float hash(vec3 p) { return p.y; }
+void main() {
+    float h = hash(1.0);
+}
+";
+    let error = compile("noise.glsl", source).unwrap_err();
+    let expected = "\
+error: no matching overload for `hash(float)`
+ --> noise.glsl:4:15
+  |
+4 |     float h = hash(1.0);
+  |               ^^^^ no overload accepts these arguments
+  |
+  = note: candidate: float hash(vec2)
+  = note: candidate: float hash(vec3)
+";
+    assert_eq!(error.render(), expected);
+}
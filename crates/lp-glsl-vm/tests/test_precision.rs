@@ -0,0 +1,300 @@
+use lp_glsl_vm::{
+    compiler::{compile, compile_with, CompileOptions},
+    ir::{
+        self,
+        interpret::{Exit, Interpreter},
+    },
+    numeric::{self, Fixed, Numeric, Overflow, Precisions},
+    preprocessor::NoIncludes,
+    reference::{Fragment, Reference},
+    runtime::fixed,
+    sema::hir::{Module, Precision},
+};
+
+const Q16: Numeric = Numeric::Fixed(Fixed::Q16_16);
+const Q8: Numeric = Numeric::Fixed(Fixed::new(8, 8, Overflow::Saturate));
+
+fn compile_source(source: &str) -> Module {
+    compile("test.glsl", source).unwrap_or_else(|e| panic!("{}", e.render()))
+}
+
+const SHADER: &str = r#"
+    precision highp float;
+    uniform float time = 0.4;
+
+    lowp vec3 palette(lowp float t) {
+        return 0.5 + 0.5 * cos(6.28318 * (t + vec3(0.0, 0.33, 0.67)));
+    }
+
+    void main() {
+        vec2 uv = gl_FragCoord.xy / vec2(8.0, 6.0);
+        float d = length(uv - 0.5) * 3.0 + time;
+        lowp vec3 col = palette(d);
+        lowp float glow = 0.0;
+        for (int i = 0; i < 3; i++) {
+            glow += 0.1 * col.g;
+        }
+        col = col * 0.8 + glow;
+        gl_FragColor = vec4(col, 1.0);
+    }
+"#;
+
+#[test]
+fn test_sema_records_precision() {
+    let module = compile_source(
+        r#"
+        precision mediump float;
+        uniform float time;
+        lowp vec3 tint;
+        int count;
+
+        highp float f(lowp float x, float y, int n) {
+            float a = x;
+            {
+                precision lowp float;
+                vec2 b = vec2(a);
+                highp float c[2];
+                int k;
+            }
+            float e = y;
+            return a + e;
+        }
+
+        void main() {
+            gl_FragColor = vec4(f(time, 1.0, 2));
+        }
+    "#,
+    );
+    let global = |name: &str| {
+        module
+            .globals
+            .iter()
+            .find(|g| g.name == name)
+            .unwrap()
+            .precision
+    };
+    assert_eq!(global("time"), Some(Precision::Medium));
+    assert_eq!(global("tint"), Some(Precision::Low));
+    assert_eq!(global("count"), None);
+    assert_eq!(global("gl_FragCoord"), None);
+
+    let f = module.function(module.find_function("f").unwrap());
+    assert_eq!(f.return_precision, Some(Precision::High));
+    let params: Vec<_> = f.params.iter().map(|p| p.precision).collect();
+    assert_eq!(
+        params,
+        [Some(Precision::Low), Some(Precision::Medium), None]
+    );
+    let local = |name: &str| f.locals.iter().find(|l| l.name == name).unwrap().precision;
+    assert_eq!(local("a"), Some(Precision::Medium));
+    assert_eq!(local("b"), Some(Precision::Low));
+    assert_eq!(local("c"), Some(Precision::High));
+    assert_eq!(local("k"), None);
+    // The default ends with the block that sets it
+    assert_eq!(local("e"), Some(Precision::Medium));
+    assert_eq!(Precision::Low.name(), "lowp");
+}
+
+#[test]
+fn test_markers_in_ir() {
+    let module = compile_source(SHADER);
+    let ir = ir::translate(&module);
+    ir::verify(&ir).unwrap();
+    let printed = ir.to_string();
+    assert!(printed.contains(" = lowp v"), "{}", printed);
+    assert!(printed.contains(" = highp v"), "{}", printed);
+    assert_eq!(ir::parse(&printed).unwrap().to_string(), printed);
+
+    // Markers are the identity in `f32`
+    let plain = compile_source(
+        &SHADER
+            .replace("lowp ", "")
+            .replace("precision highp float;", ""),
+    );
+    let expected = Reference::new(&plain).render(8, 6).unwrap();
+    assert_eq!(Reference::new(&module).render(8, 6).unwrap(), expected);
+}
+
+/// Square of `x` computed in `lowp`, returned in `highp`
+const SQUARE: &str = "\
+function @f(f32) -> f32 {
+block0(v0: f32):
+    v1: f32 = lowp v0
+    v2: f32 = const 0.5
+    v3: f32 = mul v1, v1
+    v4: f32 = add v3, v2
+    v5: f32 = highp v4
+    return v5
+}
+";
+
+#[test]
+fn test_conversions() {
+    let q8 = Fixed::new(8, 8, Overflow::Saturate);
+    let q16 = Fixed::Q16_16;
+    let cases = [
+        Precisions {
+            low: Q8,
+            ..Q16.into()
+        },
+        Precisions {
+            low: Q8,
+            ..Numeric::Float.into()
+        },
+        Precisions {
+            low: Q8,
+            ..Numeric::SoftFloat.into()
+        },
+        Precisions {
+            low: Numeric::Float,
+            ..Q16.into()
+        },
+        Precisions {
+            low: Numeric::SoftFloat,
+            ..Q8.into()
+        },
+    ];
+    for precisions in cases {
+        let mut module = ir::parse(SQUARE).unwrap();
+        numeric::lower(&mut module, precisions);
+        if let Err(errors) = ir::verify(&module) {
+            panic!("{}\n{:#?}", module, errors);
+        }
+        let f = module.find_function("f").unwrap();
+        // Past the range of Q8.8 the square saturates, whichever format
+        // it goes through
+        let largest = if precisions.low == Q8 || precisions.high == Q8 {
+            q8.to_f32(q8.max())
+        } else {
+            q16.to_f32(q16.max())
+        };
+        for (x, expected) in [(1.5f32, 2.75f32), (-0.25, 0.5625), (200.0, largest)] {
+            let arg = [precisions.high.encode(x), 0, 0, 0];
+            let Exit::Return(results) = Interpreter::new(&module).call(f, &[arg]).unwrap() else {
+                panic!("discarded");
+            };
+            let result = precisions.high.decode(results[0][0]);
+            assert_eq!(result, expected, "{:?} {}\n{}", precisions, x, module);
+        }
+    }
+
+    let mut module = ir::parse(SQUARE).unwrap();
+    numeric::lower(
+        &mut module,
+        Precisions {
+            low: Q8,
+            ..Numeric::Float.into()
+        },
+    );
+    let printed = module.to_string();
+    assert!(printed.contains("call @lp.q8.8.from_f32("), "{}", printed);
+    assert!(printed.contains("function @lp.q8.8.to_f32(i32) -> i32\n"));
+    assert!(printed.contains("function @f(f32) -> f32 {"));
+
+    let bits = |x: f32| x.to_bits();
+    assert_eq!(
+        fixed::call_symbol("lp.q8.8.from_f32", &[bits(1.5)]),
+        Some(384)
+    );
+    assert_eq!(
+        fixed::call_symbol("lp.q8.8.to_f32", &[q8.max() as u32]),
+        Some(bits(q8.to_f32(q8.max())))
+    );
+}
+
+#[test]
+fn test_render_mixed_precision() {
+    let module = compile_source(SHADER);
+    let expected = Reference::new(&module).render(8, 6).unwrap();
+    let uniform = Reference::with_numeric(&module, Q16).render(8, 6).unwrap();
+    // Rounding `t` to 1/256 moves the argument of `cos` by up to 2π/256
+    let cases = [
+        (
+            Precisions {
+                low: Q8,
+                ..Q16.into()
+            },
+            5e-2,
+        ),
+        (
+            Precisions {
+                low: Q8,
+                ..Numeric::SoftFloat.into()
+            },
+            5e-2,
+        ),
+        (
+            Precisions {
+                low: Q8,
+                ..Numeric::Float.into()
+            },
+            5e-2,
+        ),
+        (
+            Precisions {
+                low: Numeric::SoftFloat,
+                ..Q16.into()
+            },
+            2e-3,
+        ),
+    ];
+    for (precisions, tolerance) in cases {
+        let reference = Reference::with_numeric(&module, precisions);
+        if let Err(errors) = ir::verify(reference.module()) {
+            panic!("{}\n{:#?}", reference.module(), errors);
+        }
+        let image = reference.render(8, 6).unwrap();
+        if precisions.low == Q8 {
+            assert_ne!(image, uniform, "{:?}", precisions);
+        }
+        for (pixel, (found, float)) in image.iter().zip(&expected).enumerate() {
+            let (Fragment::Color(found), Fragment::Color(float)) = (found, float) else {
+                panic!("discarded");
+            };
+            for (a, b) in found.iter().zip(float) {
+                assert!(
+                    (a - b).abs() < tolerance,
+                    "{:?} pixel {}: {:?} instead of {:?}",
+                    precisions,
+                    pixel,
+                    found,
+                    float
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_compile_options() {
+    let options = CompileOptions {
+        numeric: Numeric::SoftFloat,
+        mediump: Some(Q16),
+        ..CompileOptions::default()
+    };
+    assert_eq!(
+        options.precisions(),
+        Precisions {
+            low: Q16,
+            medium: Q16,
+            high: Numeric::SoftFloat
+        }
+    );
+    let options = CompileOptions {
+        numeric: Q16,
+        lowp: Some(Q8),
+        ..CompileOptions::default()
+    };
+    let compiled = compile_with("test.glsl", SHADER, &options, &NoIncludes).unwrap();
+    assert_eq!(compiled.precisions.medium, Q16);
+    assert_eq!(compiled.precisions.low, Q8);
+    let module = compiled.ir();
+    ir::verify(&module).unwrap();
+    let printed = module.to_string();
+    assert!(!printed.contains("f32"), "{}", printed);
+    assert!(printed.contains("call @lp.q8.8.cos("), "{}", printed);
+    assert_eq!(
+        CompileOptions::default().precisions(),
+        Precisions::uniform(Numeric::Float)
+    );
+}
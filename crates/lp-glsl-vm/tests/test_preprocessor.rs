@@ -0,0 +1,231 @@
+use lp_glsl_vm::{
+    compiler::{compile, compile_with, CompileOptions},
+    diagnostic::SourceMap,
+    preprocessor::{MemoryIncludes, NoIncludes, Preprocessed, Preprocessor},
+};
+
+fn preprocess(source: &str) -> Preprocessed {
+    let mut sources = SourceMap::new();
+    let id = sources.add("shader.glsl", source);
+    Preprocessor::new(&NoIncludes)
+        .run(&mut sources, id)
+        .unwrap_or_else(|errors| panic!("{:?}", errors))
+}
+
+fn lines(text: &str) -> Vec<&str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+#[test]
+fn test_object_and_function_macros() {
+    let out = preprocess(
+        "\
+#define SCALE 2.0
+#define MIX(a, b) ((a) * SCALE + (b))
+#define NAME(n) value_ ## n
+float NAME(1) = MIX(1.0, SCALE);
+",
+    );
+    assert_eq!(
+        lines(&out.text)[0].replace(' ', ""),
+        "floatvalue_1=((1.0)*2.0+(2.0));"
+    );
+}
+
+#[test]
+fn test_recursive_macro_is_not_reexpanded() {
+    let out = preprocess("#define x x + 1\nint y = x;\n");
+    assert_eq!(lines(&out.text)[0].replace(' ', ""), "inty=x+1;");
+}
+
+#[test]
+fn test_invocation_spanning_lines() {
+    let out = preprocess("#define ADD(a, b) a + b\nint v = ADD(1,\n    2);\nint w;\n");
+    assert_eq!(lines(&out.text)[0].replace(' ', ""), "intv=1+2;");
+    assert_eq!(out.line_map.origin(1).unwrap().line, 2);
+    assert_eq!(out.line_map.origin(2).unwrap().line, 4);
+}
+
+#[test]
+fn test_conditionals() {
+    let out = preprocess(
+        "\
+#define QUALITY 2
+#if QUALITY > 1 && defined(QUALITY)
+int high;
+#elif QUALITY == 1
+int medium;
+#else
+int low;
+#endif
+#ifdef MISSING
+int missing;
+#endif
+#ifndef MISSING
+int present;
+#endif
+#if 0
+#error not reached
+#endif
+",
+    );
+    assert_eq!(lines(&out.text), ["int high;", "int present;"]);
+}
+
+#[test]
+fn test_line_and_file() {
+    let out = preprocess("\n\nint a = __LINE__;\n#line 100\nint b = __LINE__ + __FILE__;\n");
+    assert_eq!(
+        lines(&out.text)
+            .iter()
+            .map(|l| l.replace(' ', ""))
+            .collect::<Vec<_>>(),
+        ["inta=3;", "intb=100+0;"]
+    );
+}
+
+#[test]
+fn test_version_passthrough_and_gl_es() {
+    let out = preprocess("#version 300 es\n#ifdef GL_ES\nprecision mediump float;\n#endif\n");
+    assert_eq!(out.version, Some(300));
+    assert_eq!(
+        lines(&out.text),
+        ["#version 300 es", "precision mediump float;"]
+    );
+}
+
+#[test]
+fn test_default_version_is_desktop() {
+    let out = preprocess("int v = __VERSION__;\n#ifdef GL_ES\nint es;\n#endif\n");
+    assert_eq!(out.version, None);
+    assert_eq!(lines(&out.text), ["int v = 110;"]);
+}
+
+#[test]
+fn test_desktop_version_keeps_host_gl_es() {
+    let keeps_gl_es = |preprocessor: Preprocessor| {
+        let mut sources = SourceMap::new();
+        let id = sources.add(
+            "shader.glsl",
+            "#version 330\n#ifdef GL_ES\nint es;\n#endif\n",
+        );
+        let out = preprocessor.run(&mut sources, id).unwrap();
+        lines(&out.text).contains(&"int es;")
+    };
+
+    // Defined for an ES default, which the directive replaces
+    let mut preprocessor = Preprocessor::new(&NoIncludes);
+    preprocessor.default_version(100, true);
+    assert!(!keeps_gl_es(preprocessor));
+
+    // Defined by the host
+    let mut preprocessor = Preprocessor::new(&NoIncludes);
+    preprocessor.define("GL_ES", "1");
+    preprocessor.default_version(100, true);
+    assert!(keeps_gl_es(preprocessor));
+}
+
+#[test]
+fn test_include_with_memory_resolver() {
+    let includes = MemoryIncludes::new()
+        .with(
+            "lib/common.glsl",
+            "#pragma once\n#include \"consts.glsl\"\nfloat twice(float x) { return x * TWO; }\n",
+        )
+        .with("lib/consts.glsl", "#define TWO 2.0\n");
+    let mut sources = SourceMap::new();
+    let id = sources.add(
+        "main.glsl",
+        "#include \"lib/common.glsl\"\n#include \"lib/common.glsl\"\nvoid main() {}\n",
+    );
+    let out = Preprocessor::new(&includes).run(&mut sources, id).unwrap();
+    assert_eq!(
+        lines(&out.text)
+            .iter()
+            .map(|l| l.replace(' ', ""))
+            .collect::<Vec<_>>(),
+        ["floattwice(floatx){returnx*2.0;}", "voidmain(){}"]
+    );
+
+    let origin = out.line_map.origin(1).unwrap();
+    assert_eq!(sources.get(origin.source).name, "lib/common.glsl");
+    assert_eq!(origin.line, 3);
+}
+
+#[test]
+fn test_preprocessor_errors() {
+    let error = compile(
+        "shader.glsl",
+        "#define A 1\n#define A 2\n#include \"missing.glsl\"\n#if 1\n",
+    )
+    .unwrap_err();
+    let rendered = error.render();
+    assert_eq!(error.diagnostics.len(), 3, "{}", rendered);
+    assert!(
+        rendered.contains("error: macro `A` redefined"),
+        "{}",
+        rendered
+    );
+    assert!(
+        rendered.contains("previous definition here"),
+        "{}",
+        rendered
+    );
+    assert!(
+        rendered.contains("cannot include `missing.glsl`"),
+        "{}",
+        rendered
+    );
+    assert!(rendered.contains("unterminated `#if`"), "{}", rendered);
+}
+
+#[test]
+fn test_diagnostics_point_into_included_file() {
+    let includes = MemoryIncludes::new().with(
+        "util.glsl",
+        "vec3 tint(vec3 c) {\n    return c * strength;\n}\n",
+    );
+    let source = "\
+#include \"util.glsl\"
+void main() {
+    gl_FragColor = vec4(tint(vec3(1.0)), 1.0);
+}
+";
+    let error =
+        compile_with("main.glsl", source, &CompileOptions::default(), &includes).unwrap_err();
+    let expected = "\
+error: use of undeclared variable `strength`
+ --> util.glsl:2:16
+  |
+2 |     return c * strength;
+  |                ^^^^^^^^ not found in this scope
+";
+    assert_eq!(error.render(), expected);
+}
+
+#[test]
+fn test_defines_from_options() {
+    let options = CompileOptions {
+        defines: vec![("STEPS".into(), "4".into())],
+        ..Default::default()
+    };
+    let source = "\
+void main() {
+    int n = STEPS;
+#if STEPS > 2
+    float x = vec2(1.0);
+#endif
+}
+";
+    let error = compile_with("main.glsl", source, &options, &NoIncludes).unwrap_err();
+    assert!(
+        error.render().contains(
+            "4 |     float x = vec2(1.0);\n  |           ^ expected `float`, found `vec2`"
+        ),
+        "{}",
+        error.render()
+    );
+}
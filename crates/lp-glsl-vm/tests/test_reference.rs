@@ -0,0 +1,246 @@
+use lp_glsl_vm::{
+    compiler::compile,
+    ir::{
+        self,
+        interpret::{Exit, Interpreter, Trap},
+    },
+    reference::{Fragment, Reference},
+    runtime::{math, noise},
+    sema::{const_eval::Value, hir::Literal},
+};
+
+fn reference(source: &str) -> Reference {
+    let module = compile("test.glsl", source).unwrap_or_else(|e| panic!("{}", e.render()));
+    Reference::new(&module)
+}
+
+fn floats(values: &[f32]) -> Value {
+    Value::Components(values.iter().map(|&v| Literal::Float(v)).collect())
+}
+
+fn color(fragment: Fragment) -> [f32; 4] {
+    match fragment {
+        Fragment::Color(color) => color,
+        Fragment::Discarded => panic!("fragment was discarded"),
+    }
+}
+
+#[test]
+fn test_gradient_with_uniforms() {
+    let mut shader = reference(
+        r#"
+        uniform vec2 resolution;
+        uniform float blue = 0.5;
+        void main() { gl_FragColor = vec4(gl_FragCoord.xy / resolution, blue, 1.0); }
+    "#,
+    );
+    shader
+        .set_uniform("resolution", &floats(&[4.0, 2.0]))
+        .unwrap();
+    let image = shader.render(4, 2).unwrap();
+    assert_eq!(image.len(), 8);
+    assert_eq!(image[0], Fragment::Color([0.125, 0.25, 0.5, 1.0]));
+    assert_eq!(image[7], Fragment::Color([0.875, 0.75, 0.5, 1.0]));
+
+    shader.set_uniform("blue", &floats(&[0.0])).unwrap();
+    assert_eq!(
+        shader.shade([2.0, 1.0]).unwrap(),
+        Fragment::Color([0.5, 0.5, 0.0, 1.0])
+    );
+}
+
+#[test]
+fn test_functions_memory_and_control_flow() {
+    let shader = reference(
+        r#"
+        struct P { vec2 pos; float w[3]; };
+        float weight(P p, int i) { return p.w[i]; }
+        void split(vec2 v, out float a, inout float b) { a = v.x * 2.0; b += v.y; }
+        int pick(int n) {
+            switch (n) {
+                case 0: return 10;
+                case 1: n = 5;
+                case 2: n += 1; break;
+                default: n = -1;
+            }
+            return n;
+        }
+        void main() {
+            P p = P(gl_FragCoord.xy, float[3](1.0, 2.0, 3.0));
+            float s = 0.0;
+            for (int i = 0; i < 3; i++) {
+                if (i == 1 && p.pos.y > 1.0) continue;
+                s += weight(p, i);
+            }
+            float a;
+            float b = 1.0;
+            split(p.pos, a, b);
+            mat2 m = mat2(1.0, 2.0, 3.0, 4.0);
+            vec2 r = m * vec2(1.0);
+            gl_FragColor = vec4(s, a + b, r.y, float(pick(int(gl_FragCoord.x))));
+        }
+    "#,
+    );
+    let image = shader.render(4, 2).unwrap();
+    let colors: Vec<[f32; 4]> = image.into_iter().map(color).collect();
+    assert_eq!(
+        colors,
+        vec![
+            [6.0, 2.5, 6.0, 10.0],
+            [6.0, 4.5, 6.0, 6.0],
+            [6.0, 6.5, 6.0, 3.0],
+            [6.0, 8.5, 6.0, -1.0],
+            [4.0, 3.5, 6.0, 10.0],
+            [4.0, 5.5, 6.0, 6.0],
+            [4.0, 7.5, 6.0, 3.0],
+            [4.0, 9.5, 6.0, -1.0],
+        ]
+    );
+}
+
+#[test]
+fn test_discard() {
+    let shader = reference(
+        r#"
+        void hide() { discard; }
+        void main() {
+            if (gl_FragCoord.x > 1.0) hide();
+            gl_FragColor = vec4(1.0);
+        }
+    "#,
+    );
+    assert_eq!(
+        shader.render(2, 1).unwrap(),
+        vec![Fragment::Color([1.0; 4]), Fragment::Discarded]
+    );
+}
+
+#[test]
+fn test_builtins_match_runtime() {
+    let shader = reference(
+        r#"
+        #extension GL_LP_noise : enable
+        void main() {
+            vec2 p = gl_FragCoord.xy * 0.37;
+            uint u = min(4000000000u, 5u);
+            bvec2 lt = lessThan(uvec2(3000000000u, 1u), uvec2(2u));
+            gl_FragColor = vec4(lp_noise2(p), sin(p.x), float(u), lt.x || !lt.y ? 1.0 : 0.0);
+        }
+    "#,
+    );
+    for (x, y) in [(0.5, 0.5), (3.5, 7.5), (10.5, 2.5)] {
+        let (px, py) = (x * 0.37, y * 0.37);
+        assert_eq!(
+            color(shader.shade([x, y]).unwrap()),
+            [noise::noise2(px, py), math::sin(px), 5.0, 0.0]
+        );
+    }
+}
+
+#[test]
+fn test_shadertoy_entry_point() {
+    let mut shader = reference(
+        r#"
+        void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+            fragColor = vec4(fragCoord / iResolution.xy, iTime, 1.0);
+        }
+    "#,
+    );
+    shader
+        .set_uniform("iResolution", &floats(&[8.0, 4.0, 1.0]))
+        .unwrap();
+    shader.set_uniform("iTime", &floats(&[2.0])).unwrap();
+    assert_eq!(
+        shader.shade([4.0, 1.0]).unwrap(),
+        Fragment::Color([0.5, 0.25, 2.0, 1.0])
+    );
+}
+
+#[test]
+fn test_output_variable() {
+    let shader = reference(
+        r#"
+        #version 300 es
+        precision mediump float;
+        out vec4 color;
+        void main() { color = vec4(gl_FragCoord.x, 0.0, 0.0, 1.0); }
+    "#,
+    );
+    assert_eq!(
+        shader.shade([3.0, 0.0]).unwrap(),
+        Fragment::Color([3.0, 0.0, 0.0, 1.0])
+    );
+}
+
+#[test]
+fn test_integer_semantics() {
+    let module = ir::parse(
+        "\
+function @div(i32, i32) -> i32, i32, i32 {
+block0(v0: i32, v1: i32):
+    v2: i32 = div v0, v1
+    v3: i32 = rem v0, v1
+    v4: i32 = shl v0, v1
+    return v2, v3, v4
+}
+",
+    )
+    .unwrap();
+    let mut interpreter = Interpreter::new(&module);
+    let div = module.find_function("div").unwrap();
+    let call = |interpreter: &mut Interpreter, a: i32, b: i32| match interpreter
+        .call(div, &[[a as u32, 0, 0, 0], [b as u32, 0, 0, 0]])
+        .unwrap()
+    {
+        Exit::Return(values) => values.iter().map(|v| v[0] as i32).collect::<Vec<_>>(),
+        Exit::Discard => unreachable!(),
+    };
+    assert_eq!(call(&mut interpreter, 7, 0), vec![-1, 7, 7]);
+    assert_eq!(call(&mut interpreter, i32::MIN, -1), vec![i32::MIN, 0, 0]);
+    assert_eq!(call(&mut interpreter, -7, 33), vec![0, -7, -14]);
+}
+
+#[test]
+fn test_traps() {
+    let module = ir::parse(
+        "\
+function @spin() {
+block0:
+    jump block0
+}
+
+function @null() -> f32 {
+block0:
+    v0: i32 = const 0
+    v1: f32 = load v0
+    return v1
+}
+
+function @missing()
+",
+    )
+    .unwrap();
+    let mut interpreter = Interpreter::with_limits(&module, 1024, 1000);
+    let call = |interpreter: &mut Interpreter, name| {
+        interpreter.call(module.find_function(name).unwrap(), &[])
+    };
+    assert_eq!(
+        call(&mut interpreter, "null"),
+        Err(Trap::OutOfBounds { addr: 0, size: 4 })
+    );
+    assert_eq!(
+        call(&mut interpreter, "missing").unwrap_err().to_string(),
+        "call to undefined function `@missing`"
+    );
+    assert_eq!(call(&mut interpreter, "spin"), Err(Trap::StepLimit));
+}
+
+#[test]
+fn test_missing_main() {
+    let shader = reference("float half(float x) { return x * 0.5; }");
+    assert_eq!(
+        shader.shade([0.5, 0.5]),
+        Err(Trap::Undefined("main".into()))
+    );
+    assert!(shader.render(2, 2).is_err());
+}
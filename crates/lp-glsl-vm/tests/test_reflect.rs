@@ -0,0 +1,195 @@
+use lp_glsl_vm::{
+    compiler::{compile, compile_with, CompileOptions},
+    ir::{self, interpret::Interpreter},
+    numeric::{Fixed, Numeric},
+    preprocessor::NoIncludes,
+    reflect::{Reflection, UniformError},
+    sema::{const_eval::Value, hir::Literal, ScalarType, Type},
+};
+
+fn floats(values: &[f32]) -> Value {
+    Value::Components(values.iter().map(|v| Literal::Float(*v)).collect())
+}
+
+fn words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+        .collect()
+}
+
+const SOURCE: &str = r#"
+    struct Light {
+        vec3 color;
+        float intensity;
+        bool enabled;
+    };
+    const float SCALE = 2.0;
+    uniform float time;
+    uniform float speed = SCALE * 0.25;
+    uniform vec2 resolution;
+    uniform int palette[3] = int[3](4, 5, 6);
+    float phase = 0.0;
+    uniform Light light = Light(vec3(1.0, 0.5, 0.0), 3.0, true);
+    uniform mat2 rotation;
+
+    void main() {
+        phase = time * speed + resolution.x + float(palette[1]) + rotation[0][0];
+    }
+"#;
+
+#[test]
+fn test_uniform_reflection() {
+    let module = compile("shader.glsl", SOURCE).unwrap();
+    let reflection = Reflection::of(&module);
+    let summary: Vec<_> = reflection
+        .uniforms
+        .iter()
+        .map(|u| (u.name.as_str(), u.offset, u.size))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("time", 0, 4),
+            ("speed", 4, 4),
+            ("resolution", 8, 8),
+            ("palette", 16, 12),
+            ("light", 28, 20),
+            ("rotation", 48, 16),
+        ]
+    );
+    assert_eq!(reflection.size, 64);
+    assert_eq!(reflection.align, 4);
+
+    let time = reflection.uniform("time").unwrap();
+    assert_eq!(time.ty, Type::FLOAT);
+    assert_eq!(time.default, None);
+    assert_eq!(time.array_len(), None);
+    assert_eq!(module.global(time.global).name, "time");
+
+    let palette = reflection.uniform("palette").unwrap();
+    assert_eq!(
+        palette.ty,
+        Type::Array(Box::new(Type::Scalar(ScalarType::Int)), 3)
+    );
+    assert_eq!(palette.array_len(), Some(3));
+    assert_eq!(
+        reflection.uniform("speed").unwrap().default,
+        Some(floats(&[0.5]))
+    );
+    assert!(reflection.uniform("phase").is_none());
+}
+
+#[test]
+fn test_uniform_defaults() {
+    let reflection = Reflection::of(&compile("shader.glsl", SOURCE).unwrap());
+    let area = words(&reflection.defaults());
+    assert_eq!(area.len(), 16);
+    assert_eq!(area[0], 0);
+    assert_eq!(area[1], 0.5f32.to_bits());
+    assert_eq!(&area[4..7], &[4, 5, 6]);
+    assert_eq!(
+        &area[7..12],
+        &[1.0f32.to_bits(), 0.5f32.to_bits(), 0, 3.0f32.to_bits(), 1]
+    );
+    assert!(area[12..].iter().all(|w| *w == 0));
+}
+
+#[test]
+fn test_uniform_encoding() {
+    let reflection = Reflection::of(&compile("shader.glsl", SOURCE).unwrap());
+
+    let (offset, bytes) = reflection
+        .encode("resolution", &floats(&[320.0, 240.0]))
+        .unwrap();
+    assert_eq!(offset, 8);
+    assert_eq!(words(&bytes), vec![320f32.to_bits(), 240f32.to_bits()]);
+
+    let light = Value::Aggregate(vec![
+        floats(&[0.0, 0.0, 1.0]),
+        floats(&[0.5]),
+        Value::Components(vec![Literal::Bool(false)]),
+    ]);
+    let (offset, bytes) = reflection.encode("light", &light).unwrap();
+    assert_eq!(offset, 28);
+    assert_eq!(
+        words(&bytes),
+        vec![0, 0, 1f32.to_bits(), 0.5f32.to_bits(), 0]
+    );
+
+    assert_eq!(
+        reflection.encode("nope", &floats(&[1.0])),
+        Err(UniformError::Unknown("nope".into()))
+    );
+    // Values are checked against the declared type without conversion
+    let mismatch = reflection
+        .encode("time", &Value::Components(vec![Literal::Int(1)]))
+        .unwrap_err();
+    assert_eq!(
+        mismatch,
+        UniformError::TypeMismatch {
+            name: "time".into(),
+            expected: Type::FLOAT
+        }
+    );
+    assert_eq!(
+        mismatch.to_string(),
+        "uniform `time` expects a value of type `float`"
+    );
+    assert!(reflection
+        .encode("palette", &Value::Components(vec![Literal::Int(1); 3]))
+        .is_err());
+    assert!(reflection.encode("rotation", &floats(&[1.0; 4])).is_ok());
+}
+
+#[test]
+fn test_compiled_reflection_in_fixed_point() {
+    let options = CompileOptions {
+        numeric: Numeric::Fixed(Fixed::Q16_16),
+        ..CompileOptions::default()
+    };
+    let compiled = compile_with("shader.glsl", SOURCE, &options, &NoIncludes).unwrap();
+    let reflection = compiled.reflection();
+    assert_eq!(reflection.numeric, Numeric::Fixed(Fixed::Q16_16));
+
+    let (offset, bytes) = reflection.encode("time", &floats(&[1.5])).unwrap();
+    assert_eq!(offset, 0);
+    assert_eq!(words(&bytes), vec![0x1_8000]);
+    // Integers are unaffected by the float format
+    assert_eq!(
+        &words(&reflection.defaults())[1..7],
+        &[0x8000, 0, 0, 4, 5, 6]
+    );
+
+    // The lowered code reads a uniform set through the reflection
+    let source = "uniform float gain; void main() { gl_FragColor = vec4(gain * 0.5); }";
+    let compiled = compile_with("shader.glsl", source, &options, &NoIncludes).unwrap();
+    let (offset, bytes) = compiled
+        .reflection()
+        .encode("gain", &floats(&[3.0]))
+        .unwrap();
+    let module = compiled.ir();
+    let mut interpreter = Interpreter::new(&module);
+    let uniforms = interpreter.global_address(ir::UNIFORMS).unwrap();
+    interpreter.write(uniforms + offset, &bytes).unwrap();
+    interpreter
+        .call(module.find_function("main").unwrap(), &[])
+        .unwrap();
+    let color = interpreter.global_address("gl_FragColor").unwrap();
+    assert_eq!(words(interpreter.read(color, 4).unwrap()), vec![0x1_8000]);
+}
+
+#[test]
+fn test_non_constant_uniform_initializer() {
+    let error = compile(
+        "shader.glsl",
+        r#"
+        uniform float a;
+        uniform float b = a * 2.0;
+        void main() {}
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(error.diagnostics.len(), 1);
+    assert!(error.render().contains("b"), "{}", error.render());
+}
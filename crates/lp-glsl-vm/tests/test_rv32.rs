@@ -0,0 +1,574 @@
+use lp_glsl_vm::rv32::{
+    AluOp, BranchOffset, CodeBuffer, Cond, Csr, CsrOp, EncodeError, Imm12, ImmOp, Inst, JumpOffset,
+    LoadWidth, Reg, ShiftOp, StoreWidth, Uimm5, UpperImm,
+};
+
+const A0: Reg = Reg::A0;
+const A1: Reg = Reg::A1;
+const ZERO: Reg = Reg::Zero;
+
+fn imm(value: i32) -> Imm12 {
+    Imm12::new(value).unwrap()
+}
+
+fn addi(rd: Reg, rs1: Reg, value: i32) -> Inst {
+    Inst::OpImm {
+        op: ImmOp::Add,
+        rd,
+        rs1,
+        imm: imm(value),
+    }
+}
+
+fn words(insts: &[Inst]) -> Vec<u8> {
+    insts
+        .iter()
+        .flat_map(|i| i.encode().to_le_bytes())
+        .collect()
+}
+
+/// Instructions with their text, encoding and compressed encoding, as
+/// given by the LLVM assembler
+fn cases() -> Vec<(Inst, &'static str, u32, Option<u16>)> {
+    vec![
+        (
+            Inst::Op {
+                op: AluOp::Add,
+                rd: A0,
+                rs1: A0,
+                rs2: A1,
+            },
+            "add a0, a0, a1",
+            0x00b50533,
+            Some(0x952e),
+        ),
+        (Inst::RET, "jalr zero, 0(ra)", 0x00008067, Some(0x8082)),
+        (Inst::NOP, "addi zero, zero, 0", 0x00000013, Some(0x0001)),
+        (
+            addi(Reg::Sp, Reg::Sp, -16),
+            "addi sp, sp, -16",
+            0xff010113,
+            Some(0x1141),
+        ),
+        (
+            addi(Reg::Sp, Reg::Sp, 496),
+            "addi sp, sp, 496",
+            0x1f010113,
+            Some(0x617d),
+        ),
+        (
+            addi(Reg::S0, Reg::Sp, 16),
+            "addi s0, sp, 16",
+            0x01010413,
+            Some(0x0800),
+        ),
+        (
+            addi(A0, ZERO, -32),
+            "addi a0, zero, -32",
+            0xfe000513,
+            Some(0x5501),
+        ),
+        (
+            Inst::OpImm {
+                op: ImmOp::Sltu,
+                rd: A0,
+                rs1: A1,
+                imm: imm(-1),
+            },
+            "sltiu a0, a1, -1",
+            0xfff5b513,
+            None,
+        ),
+        (
+            Inst::Shift {
+                op: ShiftOp::Sra,
+                rd: A0,
+                rs1: A0,
+                shamt: Uimm5::new(7).unwrap(),
+            },
+            "srai a0, a0, 7",
+            0x40755513,
+            Some(0x851d),
+        ),
+        (
+            Inst::Shift {
+                op: ShiftOp::Sll,
+                rd: Reg::Ra,
+                rs1: Reg::Ra,
+                shamt: Uimm5::new(31).unwrap(),
+            },
+            "slli ra, ra, 31",
+            0x01f09093,
+            Some(0x00fe),
+        ),
+        (
+            Inst::Op {
+                op: AluOp::And,
+                rd: Reg::S0,
+                rs1: Reg::S1,
+                rs2: Reg::S0,
+            },
+            "and s0, s1, s0",
+            0x0084f433,
+            Some(0x8c65),
+        ),
+        (
+            Inst::Op {
+                op: AluOp::Sub,
+                rd: A0,
+                rs1: Reg::A5,
+                rs2: A0,
+            },
+            "sub a0, a5, a0",
+            0x40a78533,
+            None,
+        ),
+        (
+            Inst::Op {
+                op: AluOp::Sra,
+                rd: A0,
+                rs1: A1,
+                rs2: Reg::T6,
+            },
+            "sra a0, a1, t6",
+            0x41f5d533,
+            None,
+        ),
+        (
+            Inst::Op {
+                op: AluOp::Mulhsu,
+                rd: Reg::T6,
+                rs1: Reg::A6,
+                rs2: Reg::S1,
+            },
+            "mulhsu t6, a6, s1",
+            0x02982fb3,
+            None,
+        ),
+        (
+            Inst::Op {
+                op: AluOp::Divu,
+                rd: A0,
+                rs1: A0,
+                rs2: A1,
+            },
+            "divu a0, a0, a1",
+            0x02b55533,
+            None,
+        ),
+        (
+            Inst::Lui {
+                rd: A0,
+                imm: UpperImm::new(0xfffff).unwrap(),
+            },
+            "lui a0, 0xfffff",
+            0xfffff537,
+            Some(0x757d),
+        ),
+        (
+            Inst::Lui {
+                rd: A0,
+                imm: UpperImm::new(0x20).unwrap(),
+            },
+            "lui a0, 0x20",
+            0x00020537,
+            None,
+        ),
+        (
+            Inst::Auipc {
+                rd: Reg::Ra,
+                imm: UpperImm::new(0x12345).unwrap(),
+            },
+            "auipc ra, 0x12345",
+            0x12345097,
+            None,
+        ),
+        (
+            Inst::Load {
+                width: LoadWidth::Word,
+                rd: A0,
+                rs1: Reg::Sp,
+                offset: imm(4),
+            },
+            "lw a0, 4(sp)",
+            0x00412503,
+            Some(0x4512),
+        ),
+        (
+            Inst::Load {
+                width: LoadWidth::Word,
+                rd: A0,
+                rs1: Reg::S1,
+                offset: imm(124),
+            },
+            "lw a0, 124(s1)",
+            0x07c4a503,
+            Some(0x5ce8),
+        ),
+        (
+            Inst::Load {
+                width: LoadWidth::HalfUnsigned,
+                rd: Reg::S1,
+                rs1: Reg::T6,
+                offset: imm(-2048),
+            },
+            "lhu s1, -2048(t6)",
+            0x800fd483,
+            None,
+        ),
+        (
+            Inst::Store {
+                width: StoreWidth::Word,
+                rs1: Reg::Sp,
+                rs2: Reg::Ra,
+                offset: imm(252),
+            },
+            "sw ra, 252(sp)",
+            0x0e112e23,
+            Some(0xdf86),
+        ),
+        (
+            Inst::Store {
+                width: StoreWidth::Word,
+                rs1: A0,
+                rs2: Reg::A5,
+                offset: imm(64),
+            },
+            "sw a5, 64(a0)",
+            0x04f52023,
+            Some(0xc13c),
+        ),
+        (
+            Inst::Store {
+                width: StoreWidth::Byte,
+                rs1: Reg::Sp,
+                rs2: A0,
+                offset: imm(2047),
+            },
+            "sb a0, 2047(sp)",
+            0x7ea10fa3,
+            None,
+        ),
+        (
+            Inst::Branch {
+                cond: Cond::Eq,
+                rs1: Reg::S1,
+                rs2: ZERO,
+                offset: BranchOffset::new(-256).unwrap(),
+            },
+            "beq s1, zero, -256",
+            0xf00480e3,
+            Some(0xd081),
+        ),
+        (
+            Inst::Branch {
+                cond: Cond::Geu,
+                rs1: Reg::A6,
+                rs2: Reg::S0,
+                offset: BranchOffset::new(2046).unwrap(),
+            },
+            "bgeu a6, s0, 2046",
+            0x7e887f63,
+            None,
+        ),
+        (
+            Inst::Jal {
+                rd: Reg::Ra,
+                offset: JumpOffset::new(2046).unwrap(),
+            },
+            "jal ra, 2046",
+            0x7fe000ef,
+            Some(0x2ffd),
+        ),
+        (
+            Inst::Jal {
+                rd: ZERO,
+                offset: JumpOffset::new(-2048).unwrap(),
+            },
+            "jal zero, -2048",
+            0x801ff06f,
+            Some(0xb001),
+        ),
+        (
+            Inst::Jal {
+                rd: Reg::Ra,
+                offset: JumpOffset::new(-(1 << 20)).unwrap(),
+            },
+            "jal ra, -1048576",
+            0x800000ef,
+            None,
+        ),
+        (
+            Inst::Jalr {
+                rd: Reg::Ra,
+                rs1: Reg::A6,
+                offset: Imm12::ZERO,
+            },
+            "jalr ra, 0(a6)",
+            0x000800e7,
+            Some(0x9802),
+        ),
+        (Inst::Fence, "fence iorw, iorw", 0x0ff0000f, None),
+        (Inst::Ecall, "ecall", 0x00000073, None),
+        (Inst::Ebreak, "ebreak", 0x00100073, Some(0x9002)),
+        (
+            Inst::Csr {
+                op: CsrOp::Set,
+                rd: A0,
+                csr: Csr::CYCLE,
+                rs1: ZERO,
+            },
+            "csrrs a0, cycle, zero",
+            0xc0002573,
+            None,
+        ),
+        (
+            Inst::Csr {
+                op: CsrOp::Clear,
+                rd: Reg::S0,
+                csr: Csr::new(0x7c0).unwrap(),
+                rs1: A0,
+            },
+            "csrrc s0, 0x7c0, a0",
+            0x7c053473,
+            None,
+        ),
+        (
+            Inst::CsrImm {
+                op: CsrOp::Write,
+                rd: ZERO,
+                csr: Csr::MSTATUS,
+                uimm: Uimm5::new(8).unwrap(),
+            },
+            "csrrwi zero, mstatus, 8",
+            0x30045073,
+            None,
+        ),
+    ]
+}
+
+#[test]
+fn test_encode() {
+    for (inst, text, word, half) in cases() {
+        assert_eq!(inst.to_string(), text);
+        assert_eq!(inst.encode(), word, "{}: {:08x}", text, inst.encode());
+        assert_eq!(inst.compress(), half, "{}", text);
+    }
+    assert_eq!(Inst::mv(A0, Reg::Sp), addi(A0, Reg::Sp, 0));
+    assert_eq!(Reg::new(31), Some(Reg::T6));
+    assert_eq!(Reg::new(32), None);
+    assert_eq!(Reg::S11.to_string(), "s11");
+}
+
+#[test]
+fn test_immediate_ranges() {
+    assert_eq!(Imm12::new(-2048).unwrap().value(), -2048);
+    assert_eq!(
+        Imm12::new(2048),
+        Err(EncodeError::OutOfRange {
+            value: 2048,
+            min: -2048,
+            max: 2047
+        })
+    );
+    assert!(UpperImm::new(0xf_ffff).is_ok());
+    assert!(UpperImm::new(0x10_0000).is_err());
+    assert!(Uimm5::new(32).is_err());
+    assert!(Csr::new(0x1000).is_err());
+    assert!(BranchOffset::new(-4096).is_ok());
+    assert!(BranchOffset::new(4096).is_err());
+    assert_eq!(
+        BranchOffset::new(3),
+        Err(EncodeError::Misaligned { value: 3, align: 2 })
+    );
+    assert!(JumpOffset::new((1 << 20) - 2).is_ok());
+    assert!(JumpOffset::new(1 << 20).is_err());
+    assert_eq!(
+        Imm12::new(4096).unwrap_err().to_string(),
+        "immediate 4096 out of range -2048..=2047"
+    );
+    assert_eq!(
+        JumpOffset::new(-7).unwrap_err().to_string(),
+        "immediate -7 is not a multiple of 2"
+    );
+}
+
+#[test]
+fn test_labels() {
+    // Count a0 down to zero, then load the address of the data after the
+    // code into a1
+    let mut code = CodeBuffer::new();
+    let top = code.new_label();
+    let done = code.new_label();
+    let data = code.new_label();
+    code.bind(top);
+    code.branch(Cond::Eq, A0, ZERO, done);
+    code.emit(addi(A0, A0, -1));
+    code.jump(top);
+    code.bind(done);
+    code.load_address(A1, data);
+    code.emit(Inst::RET);
+    code.bind(data);
+    assert_eq!(code.label_offset(done), Some(12));
+    assert_eq!(code.offset(), 24);
+
+    let expected = words(&[
+        Inst::Branch {
+            cond: Cond::Eq,
+            rs1: A0,
+            rs2: ZERO,
+            offset: BranchOffset::new(12).unwrap(),
+        },
+        addi(A0, A0, -1),
+        Inst::Jal {
+            rd: ZERO,
+            offset: JumpOffset::new(-8).unwrap(),
+        },
+        Inst::Auipc {
+            rd: A1,
+            imm: UpperImm::new(0).unwrap(),
+        },
+        addi(A1, A1, 12),
+        Inst::RET,
+    ]);
+    assert_eq!(code.finish().unwrap(), expected);
+
+    // Addresses past 2 KiB take the upper bits from `auipc`, rounded so
+    // that `addi` adds a negative offset
+    let mut code = CodeBuffer::new();
+    let far = code.new_label();
+    code.load_address(A0, far);
+    code.jal(Reg::Ra, far);
+    for _ in 0..(0x800 - 12) / 4 {
+        code.emit(Inst::NOP);
+    }
+    code.bind(far);
+    let bytes = code.finish().unwrap();
+    let expected = words(&[
+        Inst::Auipc {
+            rd: A0,
+            imm: UpperImm::new(1).unwrap(),
+        },
+        addi(A0, A0, -2048),
+        Inst::Jal {
+            rd: Reg::Ra,
+            offset: JumpOffset::new(0x7f8).unwrap(),
+        },
+    ]);
+    assert_eq!(bytes[..12], expected[..]);
+}
+
+#[test]
+fn test_label_errors() {
+    let mut code = CodeBuffer::new();
+    let nowhere = code.new_label();
+    code.jump(nowhere);
+    assert_eq!(code.finish(), Err(EncodeError::Unbound(nowhere)));
+    assert_eq!(
+        EncodeError::Unbound(nowhere).to_string(),
+        "label 0 is never bound"
+    );
+
+    let mut code = CodeBuffer::new();
+    let far = code.new_label();
+    code.branch(Cond::Ne, A0, A1, far);
+    for _ in 0..1024 {
+        code.emit(Inst::NOP);
+    }
+    code.bind(far);
+    assert_eq!(
+        code.finish(),
+        Err(EncodeError::OutOfRange {
+            value: 4100,
+            min: -4096,
+            max: 4094
+        })
+    );
+}
+
+#[test]
+fn test_compressed_buffer() {
+    // The function of the JIT experiment: a0 + a1
+    let add = Inst::Op {
+        op: AluOp::Add,
+        rd: A0,
+        rs1: A0,
+        rs2: A1,
+    };
+    let mut code = CodeBuffer::new();
+    code.emit(add);
+    code.emit(Inst::RET);
+    assert_eq!(
+        code.finish().unwrap(),
+        [0x33, 0x05, 0xb5, 0x00, 0x67, 0x80, 0x00, 0x00]
+    );
+
+    // Labels after compressed instructions may be at odd halfwords, and
+    // branches to them keep their 32-bit form
+    let mut code = CodeBuffer::compressed();
+    let done = code.new_label();
+    code.branch(Cond::Eq, A0, ZERO, done);
+    code.emit(add);
+    code.emit(Inst::Fence);
+    code.bind(done);
+    code.emit(Inst::RET);
+    assert_eq!(code.label_offset(done), Some(10));
+    let branch = Inst::Branch {
+        cond: Cond::Eq,
+        rs1: A0,
+        rs2: ZERO,
+        offset: BranchOffset::new(10).unwrap(),
+    };
+    let mut expected = words(&[branch]);
+    expected.extend([0x2e, 0x95]);
+    expected.extend(words(&[Inst::Fence]));
+    expected.extend([0x82, 0x80]);
+    assert_eq!(code.finish().unwrap(), expected);
+}
+
+#[test]
+fn test_load_immediate() {
+    for value in [
+        0,
+        1,
+        -1,
+        2047,
+        -2048,
+        2048,
+        -2049,
+        0x1000,
+        0x7ff,
+        0x800,
+        0x1234_5678,
+        0x7fff_f800,
+        0x7fff_ffff,
+        i32::MIN,
+        -0x1234_5678,
+    ] {
+        let mut code = CodeBuffer::new();
+        code.load_immediate(A0, value);
+        let bytes = code.finish().unwrap();
+        let expected_len = if (-2048..2048).contains(&value) || value & 0xfff == 0 {
+            4
+        } else {
+            8
+        };
+        assert_eq!(bytes.len(), expected_len, "{:#x}", value);
+
+        // Run the `lui` and `addi` writing a0
+        let mut a0 = 0i32;
+        for word in bytes.chunks_exact(4) {
+            let word = u32::from_le_bytes(word.try_into().unwrap());
+            assert_eq!(word >> 7 & 0x1f, 10, "{:08x}", word);
+            match word & 0x7f {
+                0x37 => a0 = (word & 0xffff_f000) as i32,
+                0x13 => {
+                    let rs1 = if word >> 15 & 0x1f == 0 { 0 } else { a0 };
+                    a0 = rs1.wrapping_add(word as i32 >> 20);
+                }
+                _ => panic!("{:08x}", word),
+            }
+        }
+        assert_eq!(a0, value, "{:#x}", value);
+    }
+}
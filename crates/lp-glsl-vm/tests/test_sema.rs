@@ -0,0 +1,192 @@
+mod common;
+
+use common::analyze_source;
+use lp_glsl_vm::sema::{
+    hir::{ExprKind, Stmt},
+    ScalarType, SemaError, Type,
+};
+
+#[test]
+fn test_analyze_fragment_shader() {
+    let module = analyze_source(
+        r#"
+        uniform float time;
+
+        float wave(float x, float speed) {
+            return x * speed + time;
+        }
+
+        void main() {
+            vec2 uv = gl_FragCoord.xy;
+            float v = wave(uv.x, 2.0);
+            gl_FragColor = vec4(vec3(v), 1.0);
+        }
+    "#,
+    )
+    .expect("analysis failed");
+
+    let main = module.find_function("main").expect("main not found");
+    let main = module.function(main);
+    assert_eq!(main.return_type, Type::Void);
+    assert_eq!(main.locals.len(), 2);
+    assert_eq!(main.locals[0].ty, Type::Vector(ScalarType::Float, 2));
+
+    // `v`'s initializer is a call to `wave` returning float
+    let Some(Stmt::Decl(_, Some(init))) = main.body.as_ref().unwrap().get(1) else {
+        panic!("expected a declaration");
+    };
+    assert!(matches!(init.kind, ExprKind::Call(..)));
+    assert_eq!(init.ty, Type::FLOAT);
+}
+
+#[test]
+fn test_undeclared_variable() {
+    let errors = analyze_source("void main() { gl_FragColor = vec4(missing); }").unwrap_err();
+    assert_eq!(
+        errors,
+        vec![SemaError::UndeclaredVariable("missing".into())]
+    );
+}
+
+#[test]
+fn test_type_mismatch() {
+    let errors = analyze_source("void main() { vec3 c = vec2(1.0); }").unwrap_err();
+    assert_eq!(
+        errors,
+        vec![SemaError::TypeMismatch {
+            expected: Type::Vector(ScalarType::Float, 3),
+            found: Type::Vector(ScalarType::Float, 2),
+            context: "initializer",
+        }]
+    );
+}
+
+#[test]
+fn test_prototype_qualifier_mismatch() {
+    let errors = analyze_source(
+        r#"
+        void f(float a, out float x);
+        void f(float a, float x) { }
+        void main() { }
+    "#,
+    )
+    .unwrap_err();
+    let error = SemaError::QualifierMismatch {
+        name: "f".into(),
+        param: 1,
+    };
+    assert_eq!(errors, vec![error.clone()]);
+    assert_eq!(
+        error.to_string(),
+        "parameter 2 of `f` is qualified differently than in its earlier declaration"
+    );
+
+    analyze_source(
+        r#"
+        void f(inout float x);
+        void f(inout float x) { x += 1.0; }
+        void main() { float y = 0.0; f(y); }
+    "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_bad_arity() {
+    let errors = analyze_source(
+        r#"
+        float twice(float x) { return x * 2.0; }
+        void main() { float y = twice(1.0, 2.0); }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors,
+        vec![SemaError::ArityMismatch {
+            name: "twice".into(),
+            expected: 1,
+            found: 2,
+        }]
+    );
+}
+
+#[test]
+fn test_constructor_components() {
+    let errors = analyze_source("void main() { vec4 c = vec4(1.0, 2.0); }").unwrap_err();
+    assert_eq!(
+        errors,
+        vec![SemaError::ConstructorComponents {
+            ty: Type::Vector(ScalarType::Float, 4),
+            found: 2,
+        }]
+    );
+}
+
+#[test]
+fn test_errors_are_collected() {
+    let errors = analyze_source(
+        r#"
+        void main() {
+            float a = b;
+            int i = 1.0;
+            break;
+            gl_FragCoord = vec4(0.0);
+        }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(errors.len(), 4, "{:?}", errors);
+    assert_eq!(errors[2], SemaError::BreakOutsideLoop);
+    assert_eq!(
+        errors[3],
+        SemaError::AssignToReadOnly("gl_FragCoord".into())
+    );
+}
+
+#[test]
+fn test_local_with_bad_initializer_is_declared() {
+    let errors = analyze_source(
+        r#"
+        float g(float x) { return x; }
+        void main() {
+            float a = g(true);
+            gl_FragColor = vec4(a);
+        }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(
+        matches!(errors[0], SemaError::NoMatchingOverload { .. }),
+        "{:?}",
+        errors
+    );
+}
+
+#[test]
+fn test_scoping() {
+    let errors = analyze_source(
+        r#"
+        void main() {
+            for (int i = 0; i < 4; i++) {
+                float x = float(i);
+            }
+            float y = x;
+        }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(errors, vec![SemaError::UndeclaredVariable("x".into())]);
+}
+
+#[test]
+fn test_invalid_swizzle() {
+    let errors = analyze_source("void main() { vec2 v = vec2(1.0); float z = v.z; }").unwrap_err();
+    assert_eq!(
+        errors,
+        vec![SemaError::InvalidSwizzle {
+            field: "z".into(),
+            ty: Type::Vector(ScalarType::Float, 2),
+        }]
+    );
+}
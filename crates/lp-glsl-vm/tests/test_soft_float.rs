@@ -0,0 +1,261 @@
+use lp_glsl_vm::{
+    compiler::{compile, compile_with, CompileOptions},
+    ir::{
+        self,
+        interpret::{Exit, Interpreter},
+    },
+    numeric::{self, Numeric},
+    preprocessor::NoIncludes,
+    reference::{Fragment, Reference},
+    runtime::{call_symbol, soft_float},
+};
+
+/// Bits worth testing on their own, then pseudo-random ones
+fn operands() -> Vec<u32> {
+    let mut values = vec![
+        0,
+        0x8000_0000,
+        1,
+        0x8000_0001,
+        0x007f_ffff,
+        0x0080_0000,
+        0x0080_0001,
+        0x3f80_0000,
+        0xbf80_0000,
+        0x3f80_0001,
+        0x3fff_ffff,
+        0x4b00_0000,
+        0x4f00_0000,
+        0xcf00_0000,
+        0x4f80_0000,
+        0x7f7f_ffff,
+        0xff7f_ffff,
+        0x7f80_0000,
+        0xff80_0000,
+        0x7fc0_0000,
+        0x7f80_0001,
+        0.1f32.to_bits(),
+        (-3.0e-39f32).to_bits(),
+    ];
+    // xorshift32, biased toward exponents near each other now and then
+    let mut state = 0x2545_f491u32;
+    for i in 0..3000 {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        values.push(match i % 3 {
+            0 => state,
+            1 => (state & 0x87ff_ffff) | 0x3800_0000,
+            _ => state & 0x80ff_ffff,
+        });
+    }
+    values
+}
+
+fn same(result: u32, expected: f32) -> bool {
+    let result = f32::from_bits(result);
+    result.to_bits() == expected.to_bits() || (result.is_nan() && expected.is_nan())
+}
+
+type SoftOp = fn(u32, u32) -> u32;
+/// Host arithmetic a routine must match
+type HostOp = fn(f32, f32) -> f32;
+
+#[test]
+fn test_arithmetic_bit_exact() {
+    let values = operands();
+    let ops: [(&str, SoftOp, HostOp); 4] = [
+        ("add", soft_float::add, |x, y| x + y),
+        ("sub", soft_float::sub, |x, y| x - y),
+        ("mul", soft_float::mul, |x, y| x * y),
+        ("div", soft_float::div, |x, y| x / y),
+    ];
+    for (name, op, host) in ops {
+        // Every special value against every operand, then neighbours in
+        // the pseudo-random part
+        let pairs = values[..23]
+            .iter()
+            .flat_map(|&x| values.iter().map(move |&y| (x, y)))
+            .chain(values.windows(2).map(|w| (w[0], w[1])));
+        for (x, y) in pairs {
+            for (x, y) in [(x, y), (y, x)] {
+                let expected = host(f32::from_bits(x), f32::from_bits(y));
+                let result = op(x, y);
+                assert!(
+                    same(result, expected),
+                    "{} {:#010x} {:#010x}: {:#010x} instead of {:#010x}",
+                    name,
+                    x,
+                    y,
+                    result,
+                    expected.to_bits()
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_comparisons_and_conversions() {
+    let values = operands();
+    for &x in &values {
+        let fx = f32::from_bits(x);
+        for &y in &values[..23] {
+            let fy = f32::from_bits(y);
+            assert_eq!(soft_float::eq(x, y), fx == fy, "{} == {}", fx, fy);
+            assert_eq!(soft_float::lt(x, y), fx < fy, "{} < {}", fx, fy);
+            assert_eq!(soft_float::le(x, y), fx <= fy, "{} <= {}", fx, fy);
+        }
+        assert_eq!(soft_float::to_i32(x), fx as i32, "{}", fx);
+        assert_eq!(soft_float::to_u32(x), fx as u32, "{}", fx);
+        assert_eq!(soft_float::from_i32(x as i32), (x as i32 as f32).to_bits());
+        assert_eq!(soft_float::from_u32(x), (x as f32).to_bits());
+    }
+    for x in [i32::MIN, i32::MAX, 16_777_217, -16_777_219, 0] {
+        assert_eq!(soft_float::from_i32(x), (x as f32).to_bits());
+    }
+}
+
+#[test]
+fn test_symbols() {
+    let bits = |x: f32| x.to_bits();
+    assert_eq!(
+        call_symbol("lp.f32.mul", &[bits(1.5), bits(-4.0)]),
+        Some(bits(-6.0))
+    );
+    assert_eq!(call_symbol("lp.f32.gt", &[bits(2.0), bits(1.0)]), Some(1));
+    assert_eq!(
+        call_symbol("lp.f32.ne", &[bits(f32::NAN), bits(f32::NAN)]),
+        Some(1)
+    );
+    assert_eq!(
+        call_symbol("lp.f32.fptosi", &[bits(-7.9)]),
+        Some(-7i32 as u32)
+    );
+    assert_eq!(
+        call_symbol("lp.f32.atan2", &[bits(1.0), bits(-1.0)]),
+        Some(bits(lp_glsl_vm::runtime::math::atan2(1.0, -1.0)))
+    );
+    assert_eq!(call_symbol("lp.f32.nope", &[0]), None);
+}
+
+#[test]
+fn test_lowered_ir() {
+    let text = "\
+function @f(f32, f32) -> f32 {
+block0(v0: f32, v1: f32):
+    v2: f32 = mul v0, v1
+    v3: bool = lt v2, v1
+    v4: f32 = neg v2
+    v5: f32 = select v3, v4, v1
+    v6: f32 = builtin sqrt(v5)
+    return v6
+}
+";
+    let mut module = ir::parse(text).unwrap();
+    numeric::lower(&mut module, Numeric::SoftFloat);
+    ir::verify(&module).unwrap();
+    let printed = module.to_string();
+    assert!(!printed.contains("f32 ="), "{}", printed);
+    assert!(printed.contains("call @lp.f32.mul(v0, v1)"), "{}", printed);
+    assert!(printed.contains("function @lp.f32.lt(i32, i32) -> bool\n"));
+    assert!(printed.contains("function @lp.f32.sqrt(i32) -> i32\n"));
+    let reparsed = ir::parse(&printed).unwrap();
+    assert_eq!(reparsed.to_string(), printed);
+
+    let f = module.find_function("f").unwrap();
+    for (x, y) in [(3.0f32, 0.5f32), (-2.0, 1.0e30), (1.0e-20, 1.0e-20)] {
+        let args = [[x.to_bits(), 0, 0, 0], [y.to_bits(), 0, 0, 0]];
+        let Exit::Return(results) = Interpreter::new(&module).call(f, &args).unwrap() else {
+            panic!("discarded");
+        };
+        let product = x * y;
+        let selected = if product < y { -product } else { y };
+        let expected = lp_glsl_vm::runtime::math::sqrt(selected);
+        assert!(same(results[0][0], expected), "{} {}", x, y);
+    }
+}
+
+const SHADERS: &[&str] = &[
+    // Deep zoom, far past the range and resolution of Q16.16
+    r#"
+    uniform vec2 center = vec2(-0.10109636, 0.95628651);
+    uniform float zoom = 3.0e-6;
+    void main() {
+        vec2 c = center + (gl_FragCoord.xy - vec2(4.0, 3.0)) * zoom;
+        vec2 z = vec2(0.0);
+        float n = 0.0;
+        for (int i = 0; i < 64; i++) {
+            z = vec2(z.x * z.x - z.y * z.y, 2.0 * z.x * z.y) + c;
+            if (dot(z, z) > 1.0e4) {
+                break;
+            }
+            n += 1.0;
+        }
+        float t = n / 64.0;
+        gl_FragColor = vec4(t, fract(t * 7.0), log2(dot(z, z) + 1.0) * 0.01, 1.0);
+    }
+    "#,
+    r#"
+    uniform float time = 0.7;
+    void main() {
+        vec2 p = gl_FragCoord.xy / vec2(8.0, 6.0) * 2.0 - 1.0;
+        vec3 n = normalize(vec3(p, 1.0));
+        vec3 r = refract(n, vec3(0.0, 0.0, -1.0), 0.75);
+        float a = atan(p.y, p.x) + sin(time * 3.0);
+        vec3 col = 0.5 + 0.5 * cos(a + vec3(0.0, 2.0, 4.0));
+        col = mix(col, abs(r), smoothstep(0.2, 0.8, length(p)));
+        if (isnan(col.x) || col.y > 100.0) {
+            discard;
+        }
+        gl_FragColor = vec4(clamp(col, 0.0, 1.0), uint(gl_FragCoord.x) % 2u == 0u ? 1.0 : 0.5);
+    }
+    "#,
+];
+
+#[test]
+fn test_render_matches_float() {
+    for (i, source) in SHADERS.iter().enumerate() {
+        let module = compile("test.glsl", source).unwrap_or_else(|e| panic!("{}", e.render()));
+        let expected = Reference::new(&module).render(8, 6).unwrap();
+        let reference = Reference::with_numeric(&module, Numeric::SoftFloat);
+        if let Err(errors) = ir::verify(reference.module()) {
+            panic!("{}\n{:#?}", reference.module(), errors);
+        }
+        assert!(!reference.module().to_string().contains("f32 ="));
+        let image = reference.render(8, 6).unwrap();
+        for (pixel, (soft, float)) in image.iter().zip(&expected).enumerate() {
+            let (Fragment::Color(soft), Fragment::Color(float)) = (soft, float) else {
+                assert_eq!(soft, float);
+                continue;
+            };
+            let bits = |c: &[f32; 4]| c.map(f32::to_bits);
+            assert_eq!(
+                bits(soft),
+                bits(float),
+                "shader {} pixel {}: {:?} instead of {:?}",
+                i,
+                pixel,
+                soft,
+                float
+            );
+        }
+    }
+}
+
+#[test]
+fn test_compile_option() {
+    let options = CompileOptions {
+        numeric: Numeric::SoftFloat,
+        ..CompileOptions::default()
+    };
+    let compiled = compile_with("test.glsl", SHADERS[0], &options, &NoIncludes).unwrap();
+    assert_eq!(compiled.precisions.high, Numeric::SoftFloat);
+    let module = compiled.ir();
+    ir::verify(&module).unwrap();
+    let printed = module.to_string();
+    assert!(printed.contains("call @lp.f32.add("));
+    assert!(printed.contains("function @lp.f32.log2(i32) -> i32\n"));
+    assert_eq!(Numeric::SoftFloat.encode(-2.5), (-2.5f32).to_bits());
+    assert_eq!(Numeric::SoftFloat.decode(0x4020_0000), 2.5);
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use common::analyze_source;
+use lp_glsl_vm::{
+    layout::{array_stride, FrameLayout, Layout, StructLayout},
+    sema::{
+        hir::{ExprKind, LocalId, Stmt},
+        ScalarType, SemaError, Type,
+    },
+};
+
+const PALETTE: &str = r#"
+    struct Stop {
+        vec3 color;
+        float pos;
+    };
+
+    const int STOPS = 4;
+
+    vec3 sample_palette(Stop stops[STOPS], float t) {
+        vec3 color = stops[0].color;
+        for (int i = 1; i < stops.length(); i++) {
+            if (t >= stops[i].pos) {
+                color = stops[i].color;
+            }
+        }
+        return color;
+    }
+
+    void main() {
+        Stop stops[STOPS];
+        stops[0] = Stop(vec3(0.0), 0.0);
+        stops[1].color = vec3(1.0, 0.5, 0.0);
+        stops[1].pos = 0.5;
+        float weights[] = float[](0.25, 0.5, 0.25);
+        Stop last = stops[STOPS - 1];
+        bool same = last == stops[0];
+        gl_FragColor = vec4(sample_palette(stops, weights[1]), 1.0);
+    }
+"#;
+
+#[test]
+fn test_struct_and_array_types() {
+    let module = analyze_source(PALETTE).unwrap();
+    assert_eq!(module.structs.len(), 1);
+    let stop = Type::Struct(module.structs[0].clone());
+    assert_eq!(format!("{}", stop), "Stop");
+
+    let main = module.function(module.find_function("main").unwrap());
+    assert_eq!(
+        main.local(LocalId(0)).ty,
+        Type::Array(Box::new(stop.clone()), 4)
+    );
+    // Unsized arrays take their length from the initializer
+    assert_eq!(
+        main.local(LocalId(1)).ty,
+        Type::Array(Box::new(Type::FLOAT), 3)
+    );
+    assert_eq!(main.local(LocalId(2)).ty, stop);
+
+    // `stops[1].color = ...` assigns through a field of an array element
+    let Stmt::Expr(assign) = &main.body.as_ref().unwrap()[2] else {
+        panic!("expected an expression statement");
+    };
+    let ExprKind::Assign(None, lhs, _) = &assign.kind else {
+        panic!("expected an assignment");
+    };
+    assert!(matches!(lhs.kind, ExprKind::Field(_, 0)));
+    assert_eq!(lhs.ty, Type::Vector(ScalarType::Float, 3));
+
+    let sample = module.function(module.find_function("sample_palette").unwrap());
+    assert_eq!(sample.params[0].ty, Type::Array(Box::new(stop), 4));
+}
+
+#[test]
+fn test_struct_errors() {
+    let errors = analyze_source(
+        r#"
+        struct Particle { vec2 pos; vec2 vel; };
+        void main() {
+            Particle p = Particle(vec2(0.0));
+            Particle q = Particle(vec2(0.0), vec2(1.0));
+            float m = q.mass;
+            float a[3];
+            float b = a[3];
+            int n = 2;
+            float c[n];
+        }
+    "#,
+    )
+    .unwrap_err();
+    let particle = analyze_source("struct Particle { vec2 pos; vec2 vel; };")
+        .unwrap()
+        .structs[0]
+        .clone();
+    assert_eq!(
+        errors,
+        vec![
+            SemaError::ArityMismatch {
+                name: "Particle".into(),
+                expected: 2,
+                found: 1,
+            },
+            SemaError::InvalidSwizzle {
+                field: "mass".into(),
+                ty: Type::Struct(particle),
+            },
+            SemaError::IndexOutOfRange { index: 3, size: 3 },
+            SemaError::InvalidArraySize,
+        ]
+    );
+}
+
+#[test]
+fn test_layout() {
+    let module = analyze_source(
+        r#"
+        struct Particle {
+            vec3 pos;
+            bool alive;
+            float life[2];
+        };
+        void main() {
+            float t = 0.0;
+            Particle p;
+            Particle ps[3];
+            vec4 c = vec4(t);
+        }
+    "#,
+    )
+    .unwrap();
+    let particle = &module.structs[0];
+    let layout = StructLayout::of(particle);
+    assert_eq!(layout.offsets, vec![0, 12, 16]);
+    assert_eq!(layout.layout, Layout { size: 24, align: 4 });
+
+    let particle = Type::Struct(particle.clone());
+    assert_eq!(array_stride(&particle), 24);
+    assert_eq!(
+        Layout::of(&Type::Array(Box::new(particle), 3)),
+        Layout { size: 72, align: 4 }
+    );
+    assert_eq!(Layout::of(&Type::Vector(ScalarType::Float, 3)).size, 12);
+
+    let main = module.function(module.find_function("main").unwrap());
+    let frame = FrameLayout::new(main);
+    assert_eq!(frame.slots, vec![None, Some(0), Some(24), None]);
+    assert_eq!(frame.size, 96);
+}
@@ -0,0 +1,246 @@
+mod common;
+
+use common::{analyze_source, main_exprs};
+use lp_glsl_vm::{
+    compiler::compile,
+    sema::{
+        hir::{BinaryOp, Expr, ExprKind, VarRef},
+        ScalarType, SemaError, Type,
+    },
+};
+
+/// Flatten a comma chain into its parts
+fn sequence(expr: &Expr) -> Vec<&Expr> {
+    match &expr.kind {
+        ExprKind::Comma(first, rest) => {
+            let mut parts = vec![&**first];
+            parts.extend(sequence(rest));
+            parts
+        }
+        _ => vec![expr],
+    }
+}
+
+/// Every swizzle mask in an expression
+fn swizzles(expr: &Expr, out: &mut Vec<Vec<u8>>) {
+    match &expr.kind {
+        ExprKind::Swizzle(base, mask) => {
+            out.push(mask.clone());
+            swizzles(base, out);
+        }
+        ExprKind::Literal(_) | ExprKind::Var(_) => {}
+        ExprKind::Unary(_, e) | ExprKind::Field(e, _) => swizzles(e, out),
+        ExprKind::Binary(_, a, b)
+        | ExprKind::Assign(_, a, b)
+        | ExprKind::Index(a, b)
+        | ExprKind::Comma(a, b) => {
+            swizzles(a, out);
+            swizzles(b, out);
+        }
+        ExprKind::Ternary(a, b, c) => {
+            swizzles(a, out);
+            swizzles(b, out);
+            swizzles(c, out);
+        }
+        ExprKind::Call(_, args) | ExprKind::Builtin(_, args) | ExprKind::Construct(args) => {
+            for arg in args {
+                swizzles(arg, out);
+            }
+        }
+    }
+}
+
+/// The component stored by a single-component assignment, as (target, source)
+fn component_store(expr: &Expr) -> (u8, u8) {
+    let ExprKind::Assign(None, target, value) = &expr.kind else {
+        panic!("expected an assignment, found {:?}", expr);
+    };
+    match (&target.kind, &value.kind) {
+        (ExprKind::Swizzle(_, t), ExprKind::Swizzle(_, v)) => {
+            assert_eq!((t.len(), v.len()), (1, 1));
+            (t[0], v[0])
+        }
+        other => panic!("expected component stores, found {:?}", other),
+    }
+}
+
+#[test]
+fn test_read_swizzles() {
+    let module = analyze_source(
+        r#"
+        void main() {
+            vec4 col = vec4(1.0, 0.5, 0.25, 1.0);
+            vec3 a = col.bgr;
+            vec2 b = col.st;
+            float c = col.w;
+            vec2 d = col.zyx.xy;
+            vec4 e = col.xxyy;
+        }
+    "#,
+    )
+    .unwrap();
+    let exprs = main_exprs(&module);
+    let masks: Vec<_> = exprs[1..]
+        .iter()
+        .map(|e| match &e.kind {
+            ExprKind::Swizzle(base, mask) => {
+                assert!(matches!(base.kind, ExprKind::Var(_)));
+                mask.clone()
+            }
+            other => panic!("expected a swizzle, found {:?}", other),
+        })
+        .collect();
+    assert_eq!(
+        masks,
+        vec![
+            vec![2, 1, 0],
+            vec![0, 1],
+            vec![3],
+            vec![2, 1],
+            vec![0, 0, 1, 1]
+        ]
+    );
+}
+
+#[test]
+fn test_swizzle_errors() {
+    let errors = analyze_source(
+        r#"
+        void main() {
+            vec4 v = vec4(0.0);
+            v.xx = vec2(1.0);
+            v.rgz = vec3(1.0);
+            vec2 w = v.xy;
+            w.z = 1.0;
+            v.xy.yy = vec2(0.0);
+            vec3(1.0).xy = vec2(0.0);
+        }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors,
+        vec![
+            SemaError::NotAnLValue("swizzle with repeated components"),
+            SemaError::InvalidSwizzle {
+                field: "rgz".into(),
+                ty: Type::Vector(ScalarType::Float, 4),
+            },
+            SemaError::InvalidSwizzle {
+                field: "z".into(),
+                ty: Type::Vector(ScalarType::Float, 2),
+            },
+            SemaError::NotAnLValue("swizzle with repeated components"),
+            SemaError::NotAnLValue("expression"),
+        ]
+    );
+}
+
+#[test]
+fn test_write_mask_lowering() {
+    let module = compile(
+        "shader.glsl",
+        r#"
+        void main() {
+            vec4 col = vec4(1.0);
+            col.rgb = col.bgr * 0.5;
+            col.xy += vec2(2.0);
+            col.zw++;
+        }
+    "#,
+    )
+    .unwrap();
+    let exprs = main_exprs(&module);
+
+    // Every remaining swizzle selects a single component
+    for expr in &exprs {
+        let mut masks = Vec::new();
+        swizzles(expr, &mut masks);
+        assert!(masks.iter().all(|m| m.len() == 1), "{:?}", masks);
+    }
+
+    // col.rgb = ...: store the value once, then each masked component
+    let parts = sequence(exprs[1]);
+    assert_eq!(parts.len(), 5);
+    let ExprKind::Assign(None, tmp, value) = &parts[0].kind else {
+        panic!("expected the value to be stored first");
+    };
+    assert!(matches!(tmp.kind, ExprKind::Var(VarRef::Local(_))));
+    assert!(matches!(value.kind, ExprKind::Binary(BinaryOp::Mul, ..)));
+    let stores: Vec<_> = parts[1..4].iter().map(|e| component_store(e)).collect();
+    assert_eq!(stores, vec![(0, 0), (1, 1), (2, 2)]);
+    assert!(matches!(parts[4].kind, ExprKind::Var(_)));
+
+    // col.xy += ...: the compound operator reads the masked components
+    let parts = sequence(exprs[2]);
+    let ExprKind::Assign(None, _, value) = &parts[0].kind else {
+        panic!("expected the value to be stored first");
+    };
+    let ExprKind::Binary(BinaryOp::Add, lhs, _) = &value.kind else {
+        panic!("expected an addition");
+    };
+    assert!(matches!(&lhs.kind, ExprKind::Construct(args) if args.len() == 2));
+    let stores: Vec<_> = parts[1..3].iter().map(|e| component_store(e)).collect();
+    assert_eq!(stores, vec![(0, 0), (1, 1)]);
+
+    // col.zw++: evaluates to the old value
+    let parts = sequence(exprs[3]);
+    let stores: Vec<_> = parts[2..4].iter().map(|e| component_store(e)).collect();
+    assert_eq!(stores, vec![(2, 0), (3, 1)]);
+    let (ExprKind::Assign(_, old, _), ExprKind::Var(result)) = (&parts[0].kind, &parts[4].kind)
+    else {
+        panic!("expected the old value as the result");
+    };
+    assert!(matches!(&old.kind, ExprKind::Var(v) if v == result));
+}
+
+#[test]
+fn test_masked_places_are_evaluated_once() {
+    let module = compile(
+        "shader.glsl",
+        r#"
+        void split(float v, out vec2 parts) {
+            parts = vec2(floor(v), fract(v));
+        }
+        void main() {
+            vec3 points[4];
+            int i = 0;
+            points[i++].xz = vec2(1.0);
+            vec4 p = vec4(0.0);
+            split(2.5, p.wy);
+        }
+    "#,
+    )
+    .unwrap();
+    let exprs = main_exprs(&module);
+
+    // The index is hoisted into a temporary before the stores
+    let parts = sequence(exprs[1]);
+    let ExprKind::Assign(None, index, increment) = &parts[0].kind else {
+        panic!("expected the index to be stored first");
+    };
+    assert_eq!(index.ty, Type::INT);
+    assert!(matches!(increment.kind, ExprKind::Unary(..)));
+    let ExprKind::Assign(None, target, _) = &parts[2].kind else {
+        panic!("expected a component store");
+    };
+    let ExprKind::Swizzle(element, _) = &target.kind else {
+        panic!("expected a component of the element");
+    };
+    let ExprKind::Index(_, hoisted) = &element.kind else {
+        panic!("expected an indexed element");
+    };
+    assert!(
+        matches!(&hoisted.kind, ExprKind::Var(v) if matches!(index.kind, ExprKind::Var(w) if w == *v))
+    );
+
+    // The out argument goes through a temporary that is copied back
+    let parts = sequence(exprs[3]);
+    assert_eq!(parts.len(), 3);
+    let ExprKind::Call(_, args) = &parts[0].kind else {
+        panic!("expected the call first");
+    };
+    assert!(matches!(args[1].kind, ExprKind::Var(_)));
+    let stores: Vec<_> = parts[1..].iter().map(|e| component_store(e)).collect();
+    assert_eq!(stores, vec![(3, 0), (1, 1)]);
+}
@@ -0,0 +1,210 @@
+mod common;
+
+use common::analyze_source;
+use lp_glsl_vm::{
+    compiler::{compile, compile_with, CompileOptions},
+    preprocessor::NoIncludes,
+    sema::{Feature, SemaError, Version},
+};
+
+/// The features rejected when analyzing `body` as the body of `main`
+fn unavailable(version: &str, body: &str) -> Vec<Feature> {
+    let source = format!(
+        "#version {}\nuniform int n;\nvoid main() {{\n{}\n}}\n",
+        version, body
+    );
+    match analyze_source(&source) {
+        Ok(_) => Vec::new(),
+        Err(errors) => errors
+            .into_iter()
+            .map(|e| match e {
+                SemaError::UnavailableFeature { feature, .. } => feature,
+                other => panic!("unexpected error {:?}", other),
+            })
+            .collect(),
+    }
+}
+
+const ES_100: Version = Version {
+    number: 100,
+    es: true,
+};
+
+#[test]
+fn test_integer_features() {
+    let body = "uint a = 1u; int b = n % 3; int c = n << 1; int d = ~n; int e = n; e &= 7;";
+    assert_eq!(
+        unavailable("100", body),
+        vec![
+            Feature::UnsignedIntegers,
+            Feature::IntegerOperator("%"),
+            Feature::IntegerOperator("<<"),
+            Feature::IntegerOperator("~"),
+            Feature::IntegerOperator("&"),
+        ]
+    );
+    assert_eq!(unavailable("120", "uvec2 v = uvec2(n);").len(), 1);
+    assert_eq!(
+        unavailable("100", "int a = int(uint(n));"),
+        vec![Feature::UnsignedIntegers]
+    );
+    assert!(unavailable("300 es", body).is_empty());
+    assert!(unavailable("130", body).is_empty());
+    // Plain integers are available everywhere
+    assert!(unavailable("100", "int a = n * 2 + 1;").is_empty());
+}
+
+#[test]
+fn test_control_flow_features() {
+    let switch = "switch (n) { case 0: break; default: break; }";
+    assert_eq!(unavailable("100", switch), vec![Feature::Switch]);
+    assert_eq!(unavailable("120", switch), vec![Feature::Switch]);
+    assert!(unavailable("300 es", switch).is_empty());
+
+    let loops = "int i = 0; while (i < n) { i++; } do { i--; } while (i > 0);";
+    assert_eq!(
+        unavailable("100", loops),
+        vec![Feature::WhileLoop, Feature::WhileLoop]
+    );
+    assert!(unavailable("110", loops).is_empty());
+    assert!(unavailable("300 es", loops).is_empty());
+}
+
+#[test]
+fn test_es_100_for_loops() {
+    let allowed = [
+        "for (int i = 0; i < 10; i++) {}",
+        "for (float x = 1.0; x >= 0.0; x -= 0.25) {}",
+        "for (int i = 8; i != 0; --i) { int j = i * 2; }",
+        "const int N = 4; for (int i = 0; i < N * 2; i += 2) { if (i > n) break; }",
+    ];
+    for body in allowed {
+        assert!(unavailable("100", body).is_empty(), "{}", body);
+    }
+
+    let rejected = [
+        // Bound that is not constant
+        "for (int i = 0; i < n; i++) {}",
+        // No loop index
+        "int i = 0; for (; i < 10; i++) {}",
+        // Index that is not int or float
+        "for (bool b = true; b == true; b = false) {}",
+        // Step that is not by a constant
+        "for (int i = 0; i < 10; i += n) {}",
+        // Index modified in the body
+        "for (int i = 0; i < 10; i++) { i = i + 1; }",
+        "for (int i = 0; i < 10; i++) { if (i > 2) { i++; } }",
+    ];
+    for body in rejected {
+        assert_eq!(
+            unavailable("100", body),
+            vec![Feature::GeneralForLoop],
+            "{}",
+            body
+        );
+        assert!(unavailable("300 es", body).is_empty(), "{}", body);
+        assert!(unavailable("110", body).is_empty(), "{}", body);
+    }
+}
+
+#[test]
+fn test_out_argument_modifies_loop_index() {
+    let errors = analyze_source(
+        r#"
+        #version 100
+        void bump(inout int i) { i += 1; }
+        void main() {
+            for (int i = 0; i < 10; i++) { bump(i); }
+        }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors,
+        vec![SemaError::UnavailableFeature {
+            feature: Feature::GeneralForLoop,
+            version: ES_100,
+        }]
+    );
+}
+
+#[test]
+fn test_unversioned_shaders_are_not_gated() {
+    analyze_source(
+        r#"
+        void main() {
+            uint a = 3u << 1;
+            switch (int(a)) { default: break; }
+            while (a > 0u) { a--; }
+        }
+    "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_version_diagnostics() {
+    let error = compile(
+        "shader.glsl",
+        "#version 120\nvoid main() {\n    int n = 2;\n    switch (n) { default: break; }\n}\n",
+    )
+    .unwrap_err();
+    let expected = "\
+error: the `switch` statement is not available in GLSL 120
+ --> shader.glsl:4:5
+  |
+4 |     switch (n) { default: break; }
+  |     ^^^^^^ not available in version 120
+  |
+  = note: requires `#version 130` or `#version 300 es`
+";
+    assert_eq!(error.render(), expected);
+
+    let error = compile(
+        "shader.glsl",
+        "#version 100\nvoid main() {\n    for (int i = 0; i < 4; i++) { i++; }\n}\n",
+    )
+    .unwrap_err();
+    let rendered = error.render();
+    assert!(
+        rendered.starts_with("error: this form of `for` loop is not available in GLSL ES 100"),
+        "{}",
+        rendered
+    );
+    assert!(
+        rendered.contains("requires desktop GLSL or `#version 300 es`"),
+        "{}",
+        rendered
+    );
+}
+
+#[test]
+fn test_default_version_from_options() {
+    let options = CompileOptions {
+        version: Some(ES_100),
+        ..Default::default()
+    };
+    let source = "\
+#ifdef GL_ES
+precision mediump float;
+#endif
+void main() {
+    int n = __VERSION__ % 7;
+}
+";
+    let error = compile_with("shader.glsl", source, &options, &NoIncludes).unwrap_err();
+    assert_eq!(error.diagnostics.len(), 1);
+    assert!(
+        error
+            .render()
+            .starts_with("error: operator `%` is not available in GLSL ES 100"),
+        "{}",
+        error.render()
+    );
+
+    // The directive takes precedence over the default
+    let versioned = format!("#version 330\n{}", source);
+    compile_with("shader.glsl", &versioned, &options, &NoIncludes).unwrap();
+    // Without either, nothing is gated
+    compile("shader.glsl", source).unwrap();
+}